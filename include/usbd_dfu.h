@@ -0,0 +1,74 @@
+/*
+ * C API of usbd-dfu, requires `ffi` feature.
+ *
+ * See `ffi` and `link` module documentation for details and the frame format.
+ * Functions must not be called concurrently.
+ */
+
+#ifndef USBD_DFU_H
+#define USBD_DFU_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Transfer size, wTransferSize. */
+#define USBD_DFU_TRANSFER_SIZE 256
+
+/* Memory layout reported to the host, a single 4 GiB region of 256-byte pages
+ * at address 0, callbacks reject addresses outside of the actual memory. */
+#define USBD_DFU_MEM_INFO_STRING "@Memory/0x00000000/1*256 g"
+
+/* Length of the setup packet at the start of a request frame. */
+#define USBD_DFU_SETUP_LENGTH 8
+
+/* Length of the reply frame header. */
+#define USBD_DFU_REPLY_HEADER_LENGTH 3
+
+/* Callback status: operation succeeded, other values are DFU status codes. */
+#define USBD_DFU_OK 0
+
+/* Memory callbacks, `context` is passed to every callback. */
+struct usbd_dfu_memory_ops {
+    void *context;
+    /* Read at most `length` bytes at `address`, store the number of read bytes in `*read`. */
+    uint8_t (*read)(void *context, uint32_t address, uint8_t *buffer, size_t length, size_t *read);
+    /* Program `length` bytes of `data` at `address`. */
+    uint8_t (*program)(void *context, uint32_t address, const uint8_t *data, size_t length);
+    /* Erase the page that contains `address`. */
+    uint8_t (*erase)(void *context, uint32_t address);
+    /* Erase all memory. */
+    uint8_t (*erase_all)(void *context);
+    /* Finish the download, may not return. */
+    uint8_t (*manifestation)(void *context);
+};
+
+/* Initialize the protocol, returns false if `ops` or any callback is NULL. */
+bool usbd_dfu_init(const struct usbd_dfu_memory_ops *ops);
+
+/* Handle a complete request frame, returns the reply length, 0 if not initialized.
+ * NULL buffers are treated as empty. */
+size_t usbd_dfu_handle(const uint8_t *request, size_t request_length,
+                       uint8_t *reply, size_t reply_length);
+
+/* Process bytes received from a stream, `send` is called with the reply to each frame.
+ * NULL `data` is treated as empty. */
+void usbd_dfu_receive(const uint8_t *data, size_t length,
+                      void (*send)(void *context, const uint8_t *reply, size_t length),
+                      void *context);
+
+/* Drop a partially received frame, and reset the protocol state if `link_lost`. */
+void usbd_dfu_reset(bool link_lost);
+
+/* Returns the current address pointer. */
+uint32_t usbd_dfu_address_pointer(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* USBD_DFU_H */
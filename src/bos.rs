@@ -0,0 +1,110 @@
+//! Binary Object Store descriptor
+//!
+//! Microsoft OS 2.0 descriptors and WebUSB are announced with platform capability
+//! descriptors in the device *Binary Object Store* (BOS). [`BosClass`] is a small
+//! companion [`UsbClass`] that contributes [`PlatformCapability`] entries to the BOS,
+//! it's polled together with [`DfuClass`](crate::DfuClass).
+//!
+//! `usb-device` serves the BOS descriptor only if the device is built with
+//! [`UsbRev::Usb210`](usb_device::device::UsbRev::Usb210):
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usb_device::device::UsbRev;
+//! # use usb_device::prelude::*;
+//! # use usbd_dfu::bos::*;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # const MY_UUID: [u8; 16] = [0; 16];
+//! # const MY_DATA: [u8; 4] = [0; 4];
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: impl DfuMemory) {
+//! const CAPABILITIES: &[PlatformCapability] = &[PlatformCapability::new(MY_UUID, &MY_DATA)];
+//!
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, my_mem);
+//! let mut bos = BosClass::new(CAPABILITIES);
+//!
+//! let mut usb_dev = UsbDeviceBuilder::new(&usb_bus_alloc, UsbVidPid(0x1209, 0x0001))
+//!     .usb_rev(UsbRev::Usb210)
+//!     .build();
+//!
+//! loop {
+//!     usb_dev.poll(&mut [&mut dfu, &mut bos]);
+//! }
+//! # }
+//! ```
+
+use usb_device::bus::UsbBus;
+use usb_device::class_prelude::*;
+use usb_device::descriptor::capability_type;
+
+/// Size of a platform capability descriptor without data, including
+/// *bLength*, *bDescriptorType*, *bDevCapabilityType*, *bReserved* and the UUID.
+pub const PLATFORM_CAPABILITY_HEADER_LENGTH: usize = 20;
+
+/// Maximum size of platform capability data.
+pub const MAX_PLATFORM_CAPABILITY_DATA_LENGTH: usize = 255 - PLATFORM_CAPABILITY_HEADER_LENGTH;
+
+/// Size of a platform capability descriptor body written by [`PlatformCapability::write()`].
+const BODY_LENGTH: usize = 255 - 3;
+
+/// Platform capability descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlatformCapability<'a> {
+    /// *PlatformCapabilityUUID*, in the byte order it's sent on the wire.
+    pub uuid: [u8; 16],
+    /// *CapabilityData*, at most [`MAX_PLATFORM_CAPABILITY_DATA_LENGTH`] bytes.
+    pub data: &'a [u8],
+}
+
+impl<'a> PlatformCapability<'a> {
+    /// Creates a platform capability.
+    pub const fn new(uuid: [u8; 16], data: &'a [u8]) -> Self {
+        Self { uuid, data }
+    }
+
+    /// Write the descriptor body, *bReserved*, the UUID and the data, to `dst`.
+    ///
+    /// Returns the number of written bytes, or `Err` if `dst` is too small
+    /// or the data is longer than [`MAX_PLATFORM_CAPABILITY_DATA_LENGTH`].
+    #[allow(clippy::result_unit_err)]
+    pub fn write(&self, dst: &mut [u8]) -> Result<usize, ()> {
+        if self.data.len() > MAX_PLATFORM_CAPABILITY_DATA_LENGTH {
+            return Err(());
+        }
+        let len = 1 + self.uuid.len() + self.data.len();
+        let dst = dst.get_mut(..len).ok_or(())?;
+        dst[0] = 0;
+        dst[1..17].copy_from_slice(&self.uuid);
+        dst[17..].copy_from_slice(self.data);
+        Ok(len)
+    }
+}
+
+/// [`UsbClass`] that adds platform capabilities to the BOS descriptor.
+///
+/// The class has no interfaces and does not handle any requests.
+pub struct BosClass<'a> {
+    capabilities: &'a [PlatformCapability<'a>],
+}
+
+impl<'a> BosClass<'a> {
+    /// Creates a class that adds `capabilities` to the BOS descriptor.
+    pub fn new(capabilities: &'a [PlatformCapability<'a>]) -> Self {
+        Self { capabilities }
+    }
+
+    /// Returns platform capabilities of the class.
+    pub fn capabilities(&self) -> &'a [PlatformCapability<'a>] {
+        self.capabilities
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for BosClass<'_> {
+    fn get_bos_descriptors(&self, writer: &mut BosWriter) -> usb_device::Result<()> {
+        let mut body = [0; BODY_LENGTH];
+        for cap in self.capabilities {
+            let len = cap.write(&mut body).map_err(|_| UsbError::BufferOverflow)?;
+            writer.capability(capability_type::PLATFORM, &body[..len])?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+//! Aligned buffers
+//!
+//! Flash controllers often program whole words, and DMA engines may require
+//! the source address to be aligned to the transfer width or a cache line.
+//! A `[u8; N]` array is only byte-aligned, so a block received from the host
+//! and stored in it may fault or be rejected when it's programmed directly.
+//!
+//! [`AlignedBuffer`] is a byte array aligned to `ALIGN` bytes, one of `1`,
+//! `2`, `4`, `8`, `16`, `32`, `64`, or `128`:
+//!
+//! ```
+//! use usbd_dfu::buffer::AlignedBuffer;
+//!
+//! let mut buffer = AlignedBuffer::<128, 8>::new();
+//! buffer[..4].copy_from_slice(&[1, 2, 3, 4]);
+//!
+//! assert_eq!(buffer.as_ptr() as usize % 8, 0);
+//! assert_eq!(buffer.len(), 128);
+//! ```
+
+use core::ops::{Deref, DerefMut};
+
+/// Alignment of `N` bytes, see [`Alignment`].
+pub struct Align<const N: usize>;
+
+/// Implemented by [`Align`] for supported alignments.
+pub trait Alignment {
+    /// Zero-sized type with the alignment.
+    type Type: Copy;
+}
+
+macro_rules! alignment {
+    ($($n:literal => $t:ident),*) => {
+        $(
+            #[doc(hidden)]
+            #[derive(Clone, Copy)]
+            #[repr(align($n))]
+            pub struct $t;
+
+            impl Alignment for Align<$n> {
+                type Type = $t;
+            }
+        )*
+    };
+}
+
+alignment!(
+    1 => Align1, 2 => Align2, 4 => Align4, 8 => Align8,
+    16 => Align16, 32 => Align32, 64 => Align64, 128 => Align128
+);
+
+/// Byte array of `N` bytes, the first byte is aligned to `ALIGN` bytes.
+///
+/// Dereferences to `[u8; N]`. The size of the buffer is a multiple of `ALIGN`,
+/// so it may be larger than `N`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct AlignedBuffer<const N: usize, const ALIGN: usize>
+where
+    Align<ALIGN>: Alignment,
+{
+    _align: [<Align<ALIGN> as Alignment>::Type; 0],
+    bytes: [u8; N],
+}
+
+impl<const N: usize, const ALIGN: usize> AlignedBuffer<N, ALIGN>
+where
+    Align<ALIGN>: Alignment,
+{
+    /// Creates a buffer filled with zeros.
+    pub const fn new() -> Self {
+        Self {
+            _align: [],
+            bytes: [0; N],
+        }
+    }
+}
+
+impl<const N: usize, const ALIGN: usize> Default for AlignedBuffer<N, ALIGN>
+where
+    Align<ALIGN>: Alignment,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const ALIGN: usize> Deref for AlignedBuffer<N, ALIGN>
+where
+    Align<ALIGN>: Alignment,
+{
+    type Target = [u8; N];
+
+    fn deref(&self) -> &Self::Target {
+        &self.bytes
+    }
+}
+
+impl<const N: usize, const ALIGN: usize> DerefMut for AlignedBuffer<N, ALIGN>
+where
+    Align<ALIGN>: Alignment,
+{
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.bytes
+    }
+}
@@ -0,0 +1,201 @@
+//! Upload read cache
+//!
+//! Reads from slow external memories, e.g. SPI flash, are dominated by the command
+//! overhead. When the host uploads with a *wLength* shorter than
+//! [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE), or reads the same block again,
+//! the same data is read from the memory several times.
+//!
+//! [`CachedMemory`] wraps [`DfuMemory`] and keeps the last `N` bytes read from it.
+//! On a miss, the cache is filled with `TRANSFER_SIZE` reads starting at the requested
+//! address, so with `N` larger than `TRANSFER_SIZE` the following blocks are prefetched.
+//! The cache is dropped when the memory is programmed or erased.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::cache::CachedMemory;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_spi_flash_mem: impl DfuMemory) {
+//! let mem = CachedMemory::<_, 512>::new(my_spi_flash_mem);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError, ReadOutcome};
+
+/// [`DfuMemory`] adapter that caches `N` bytes of read data.
+///
+/// `N` must be at least [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE).
+pub struct CachedMemory<M: DfuMemory, const N: usize> {
+    mem: M,
+    cache: [u8; N],
+    /// Address of the first cached byte.
+    address: u32,
+    /// Number of cached bytes.
+    len: usize,
+    /// Cached data ends at the end of the memory.
+    end: bool,
+}
+
+impl<M: DfuMemory, const N: usize> CachedMemory<M, N> {
+    /// Wrap `mem`.
+    pub fn new(mem: M) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize,
+                "cache is smaller than TRANSFER_SIZE"
+            )
+        };
+
+        Self {
+            mem,
+            cache: [0; N],
+            address: 0,
+            len: 0,
+            end: false,
+        }
+    }
+
+    /// Returns a reference to the wrapped memory.
+    ///
+    /// The cache is dropped, as the memory may be modified.
+    pub fn memory(&mut self) -> &mut M {
+        self.invalidate();
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    /// Drop cached data.
+    pub fn invalidate(&mut self) {
+        self.len = 0;
+        self.end = false;
+    }
+
+    /// Returns the offset of `address..address + length` in the cache, if it's cached.
+    ///
+    /// A range past the end of the memory is cached up to the end.
+    fn lookup(&self, address: u32, length: usize) -> Option<usize> {
+        let offset = address.checked_sub(self.address)? as usize;
+        let cached = offset <= self.len && (self.end || self.len - offset >= length);
+        cached.then_some(offset)
+    }
+
+    /// Fill the cache starting at `address`, at least `length` bytes must be read.
+    fn fill(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        self.invalidate();
+        self.address = address;
+
+        let chunk = M::TRANSFER_SIZE as usize;
+        while self.len < N {
+            let chunk = chunk.min(N - self.len);
+            let Some(from) = address.checked_add(self.len as u32) else {
+                self.end = true;
+                break;
+            };
+            let block = match self.mem.read_block(from, chunk) {
+                Ok(block) => block,
+                // prefetch failed, the requested data is already cached
+                Err(_) if self.len >= length => break,
+                Err(e) => {
+                    self.invalidate();
+                    return Err(e);
+                }
+            };
+            let data = block.data();
+            let data = &data[..data.len().min(chunk)];
+            self.cache[self.len..self.len + data.len()].copy_from_slice(data);
+            self.len += data.len();
+            if block.is_last() || data.len() < chunk {
+                self.end = true;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns cached data at `address`, up to `length` bytes,
+    /// and `true` if it reaches the end of the memory.
+    fn cached(&mut self, address: u32, length: usize) -> Result<(&[u8], bool), DfuMemoryError> {
+        let offset = match self.lookup(address, length) {
+            Some(offset) => offset,
+            None => {
+                self.fill(address, length)?;
+                0
+            }
+        };
+        let to = (offset + length).min(self.len);
+        Ok((&self.cache[offset..to], self.end && to == self.len))
+    }
+}
+
+impl<M: DfuMemory, const N: usize> DfuMemory for CachedMemory<M, N> {
+    forward_dfu_memory!(
+        mem: M,
+        except read, read_block, program, erase, erase_all, erase_range, manifestation,
+        download_start, usb_reset, select_alt_setting, session_timeout
+    );
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        self.cached(address, length).map(|(data, _)| data)
+    }
+
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        match self.cached(address, length)? {
+            (data, true) => Ok(ReadOutcome::LastChunk(data)),
+            (data, false) => Ok(ReadOutcome::Full(data)),
+        }
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        self.invalidate();
+        self.mem.program(address, length)
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        self.invalidate();
+        self.mem.erase(address)
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        self.invalidate();
+        self.mem.erase_all()
+    }
+
+    fn erase_range(&mut self, address: u32, length: u32) -> Result<(), DfuMemoryError> {
+        self.invalidate();
+        self.mem.erase_range(address, length)
+    }
+
+    fn select_alt_setting(&mut self, alt: u8) {
+        // same addresses may read another region
+        self.invalidate();
+        self.mem.select_alt_setting(alt)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.invalidate();
+        self.mem.manifestation()
+    }
+
+    fn download_start(&mut self) {
+        self.invalidate();
+        self.mem.download_start()
+    }
+
+    fn usb_reset(&mut self) {
+        self.invalidate();
+        self.mem.usb_reset()
+    }
+
+    fn session_timeout(&mut self) {
+        self.invalidate();
+        self.mem.session_timeout()
+    }
+}
@@ -1,36 +1,52 @@
+#[cfg(any(feature = "download", feature = "upload"))]
 use core::cmp::min;
 use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use heapless::Deque;
 use usb_device::{class_prelude::*, control::Request};
 
-const USB_CLASS_APPLICATION_SPECIFIC: u8 = 0xFE;
-const USB_SUBCLASS_DFU: u8 = 0x01;
-
-#[allow(dead_code)]
-const USB_PROTOCOL_RUN_TIME: u8 = 0x01;
-const USB_PROTOCOL_DFU_MODE: u8 = 0x02;
-
-#[allow(dead_code)]
-const DFU_DETACH: u8 = 0x00;
-const DFU_DNLOAD: u8 = 0x01;
-const DFU_UPLOAD: u8 = 0x02;
-const DFU_GETSTATUS: u8 = 0x03;
-const DFU_CLRSTATUS: u8 = 0x04;
-const DFU_GETSTATE: u8 = 0x05;
-const DFU_ABORT: u8 = 0x06;
-
-const DESC_DESCTYPE_DFU: u8 = 0x21;
-
-const HAS_READ_UNPROTECT: bool = false;
-
-#[derive(Clone, Copy, PartialEq, Eq)]
-#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+use crate::consts::*;
+use crate::info::DeviceInfo;
+#[cfg(feature = "upload")]
+use crate::info::MAX_DEVICE_INFO_LENGTH;
+use crate::mem_info::{self, Operations};
+use crate::split::{self, DfuChannel, DfuControl, DfuWorker};
+#[cfg(feature = "stats")]
+use crate::stats::{self, DfuStats};
+use crate::status::{ExtendedStatus, EXTENDED_STATUS_LENGTH};
+use crate::suffix::LMDFU_PREFIX_LENGTH;
+#[cfg(feature = "download")]
+use crate::suffix::{Crc32, LmdfuPrefix};
+#[cfg(any(feature = "download", feature = "upload"))]
+use crate::tlv::MAX_PAYLOAD_LENGTH;
+use crate::tlv::{TlvError, TlvReader, TlvWriter};
+#[cfg(feature = "trace")]
+use crate::trace::{Trace, Transition};
+
+/// Size of `usb-device` control endpoint buffer, the maximum
+/// [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE) of [`DfuClass`].
+///
+/// `256` bytes with `control-buffer-256` feature, which enables the same
+/// feature of `usb-device`, `128` bytes otherwise.
+pub const CONTROL_BUFFER_SIZE: usize = if cfg!(feature = "control-buffer-256") {
+    256
+} else {
+    128
+};
+
+/// Maximum number of download commands that can be queued, see
+/// [`DfuMemory::COMMAND_QUEUE_DEPTH`].
+pub const MAX_COMMAND_QUEUE_DEPTH: usize = 4;
+
+/// DFU state, *bState* of `DFU_GETSTATUS` and `DFU_GETSTATE` replies.
+///
+/// `Display` and `defmt::Format` show the name from DFU specification, e.g. `dfuDNLOAD-SYNC`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
-enum DfuState {
+pub enum DfuState {
     /// Device is running its normal application.
-    #[allow(dead_code)]
     AppIdle = 0,
     /// Device is running its normal application, has received the DFU_DETACH request, and is waiting for a USB reset.
-    #[allow(dead_code)]
     AppDetach = 1,
     /// Device is operating in the DFU mode and is waiting for requests.
     DfuIdle = 2,
@@ -52,10 +68,12 @@ enum DfuState {
     DfuError = 10,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+/// DFU status code, *bStatus* of `DFU_GETSTATUS` reply.
+///
+/// `Display` and `defmt::Format` show the name from DFU specification, e.g. `errVERIFY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
-enum DfuStatusCode {
+pub enum DfuStatusCode {
     /// No error condition is present.
     Ok = 0x00,
     /// File is not targeted for use by this device.
@@ -90,18 +108,165 @@ enum DfuStatusCode {
     ErrStalledPkt = 0x0F,
 }
 
+impl DfuState {
+    /// Name of the state in DFU specification.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            DfuState::AppIdle => "appIDLE",
+            DfuState::AppDetach => "appDETACH",
+            DfuState::DfuIdle => "dfuIDLE",
+            DfuState::DfuDnloadSync => "dfuDNLOAD-SYNC",
+            DfuState::DfuDnBusy => "dfuDNBUSY",
+            DfuState::DfuDnloadIdle => "dfuDNLOAD-IDLE",
+            DfuState::DfuManifestSync => "dfuMANIFEST-SYNC",
+            DfuState::DfuManifest => "dfuMANIFEST",
+            DfuState::DfuManifestWaitReset => "dfuMANIFEST-WAIT-RESET",
+            DfuState::DfuUploadIdle => "dfuUPLOAD-IDLE",
+            DfuState::DfuError => "dfuERROR",
+        }
+    }
+}
+
+impl TryFrom<u8> for DfuState {
+    type Error = u8;
+
+    /// Parse *bState*, returns the value back if it's not a valid state.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => DfuState::AppIdle,
+            1 => DfuState::AppDetach,
+            2 => DfuState::DfuIdle,
+            3 => DfuState::DfuDnloadSync,
+            4 => DfuState::DfuDnBusy,
+            5 => DfuState::DfuDnloadIdle,
+            6 => DfuState::DfuManifestSync,
+            7 => DfuState::DfuManifest,
+            8 => DfuState::DfuManifestWaitReset,
+            9 => DfuState::DfuUploadIdle,
+            10 => DfuState::DfuError,
+            _ => return Err(value),
+        })
+    }
+}
+
+impl core::fmt::Display for DfuState {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(feature = "defmt-03")]
+impl defmt::Format for DfuState {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{=str}", self.name())
+    }
+}
+
+impl DfuStatusCode {
+    /// Name of the status code in DFU specification.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            DfuStatusCode::Ok => "OK",
+            DfuStatusCode::ErrTarget => "errTARGET",
+            DfuStatusCode::ErrFile => "errFILE",
+            DfuStatusCode::ErrWrite => "errWRITE",
+            DfuStatusCode::ErrErase => "errERASE",
+            DfuStatusCode::ErrCheckErased => "errCHECK_ERASED",
+            DfuStatusCode::ErrProg => "errPROG",
+            DfuStatusCode::ErrVerify => "errVERIFY",
+            DfuStatusCode::ErrAddress => "errADDRESS",
+            DfuStatusCode::ErrNotdone => "errNOTDONE",
+            DfuStatusCode::ErrFirmware => "errFIRMWARE",
+            DfuStatusCode::ErrVendor => "errVENDOR",
+            DfuStatusCode::ErrUsbr => "errUSBR",
+            DfuStatusCode::ErrPOR => "errPOR",
+            DfuStatusCode::ErrUnknown => "errUNKNOWN",
+            DfuStatusCode::ErrStalledPkt => "errSTALLEDPKT",
+        }
+    }
+}
+
+impl TryFrom<u8> for DfuStatusCode {
+    type Error = u8;
+
+    /// Parse *bStatus*, returns the value back if it's not a valid status code.
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0x00 => DfuStatusCode::Ok,
+            0x01 => DfuStatusCode::ErrTarget,
+            0x02 => DfuStatusCode::ErrFile,
+            0x03 => DfuStatusCode::ErrWrite,
+            0x04 => DfuStatusCode::ErrErase,
+            0x05 => DfuStatusCode::ErrCheckErased,
+            0x06 => DfuStatusCode::ErrProg,
+            0x07 => DfuStatusCode::ErrVerify,
+            0x08 => DfuStatusCode::ErrAddress,
+            0x09 => DfuStatusCode::ErrNotdone,
+            0x0A => DfuStatusCode::ErrFirmware,
+            0x0B => DfuStatusCode::ErrVendor,
+            0x0C => DfuStatusCode::ErrUsbr,
+            0x0D => DfuStatusCode::ErrPOR,
+            0x0E => DfuStatusCode::ErrUnknown,
+            0x0F => DfuStatusCode::ErrStalledPkt,
+            _ => return Err(value),
+        })
+    }
+}
+
+impl core::fmt::Display for DfuStatusCode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(feature = "defmt-03")]
+impl defmt::Format for DfuStatusCode {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{=str}", self.name())
+    }
+}
+
+#[derive(Clone, Copy)]
 #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
 #[repr(u8)]
-enum DownloadCommand {
-    GetCommands = 0x00,
-    SetAddressPointer = 0x21,
-    Erase = 0x41,
-    ReadUnprotect = 0x92,
+#[cfg_attr(not(feature = "download"), allow(dead_code))]
+pub(crate) enum DownloadCommand {
+    #[cfg_attr(not(feature = "upload"), allow(dead_code))]
+    GetCommands = DFUSE_GET_COMMANDS,
+    SetAddressPointer = DFUSE_SET_ADDRESS_POINTER,
+    Erase = DFUSE_ERASE,
+    ReadUnprotect = DFUSE_READ_UNPROTECT,
+    /// Vendor-specific, expected CRC-32 of the image.
+    SetImageCrc = CMD_SET_IMAGE_CRC,
+    /// Vendor-specific, continue an interrupted download.
+    Resume = CMD_RESUME,
+    /// Vendor-specific, erase an address range.
+    EraseRange = CMD_ERASE_RANGE,
+    /// Vendor-specific, allow the next mass erase.
+    UnlockMassErase = CMD_UNLOCK_MASS_ERASE,
+    /// Vendor-specific, length of the image.
+    SetImageSize = CMD_SET_IMAGE_SIZE,
+    /// Vendor-specific, token that allows downloads.
+    Unlock = CMD_UNLOCK,
+    /// Vendor-specific, set or query the readout protection level.
+    ReadoutProtection = CMD_READOUT_PROTECTION,
+    /// Vendor-specific, query the device metadata.
+    GetDeviceInfo = CMD_GET_DEVICE_INFO,
+    /// Vendor-specific, TLV parameters from the host.
+    SetParameters = CMD_SET_PARAMETERS,
+    /// Vendor-specific, query the TLV capabilities of the device.
+    GetCapabilities = CMD_GET_CAPABILITIES,
+    /// Vendor-specific, compare the downloaded blocks with the memory.
+    Verify = CMD_VERIFY,
 }
 
+/// Maximum length of *Unlock* command token, see [`DfuMemory::UNLOCK_COMMAND`].
+pub const MAX_UNLOCK_TOKEN_LENGTH: usize = 32;
+
 /// Errors that may happen when working with the memory
 /// (reading, erasing, writting). These will be translated
 /// to a corresponding error codes in DFU protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
 #[repr(u8)]
 pub enum DfuMemoryError {
@@ -198,14 +363,58 @@ pub trait DfuMemory {
     /// 48 1K-pages are avaiable for reading, erase, and write operations.
     const MEM_INFO_STRING: &'static str;
 
+    /// If set, DFU interface descriptor references [`MEM_INFO_STRING`](DfuMemory::MEM_INFO_STRING)
+    /// in *iInterface*. Default is `true`.
+    ///
+    /// Plain DFU hosts don't parse the memory map, if it's not needed, set to `false`
+    /// to use *iInterface* `0` and save a string index.
+    const HAS_INTERFACE_STRING: bool = true;
+
+    /// Human-readable name of the DFU interface, e.g. "Firmware Update". Default is `None`.
+    ///
+    /// Some tools, e.g. STM32CubeProgrammer, show the raw [`MEM_INFO_STRING`](DfuMemory::MEM_INFO_STRING)
+    /// as the interface name. If set, DFU interface gets an alternate setting `1` with this name
+    /// in *iInterface*. Both alternate settings access the same memory, DfuSe hosts parse
+    /// the memory map of alternate setting `0`.
+    const INTERFACE_NAME: Option<&'static str> = None;
+
+    /// Alternate settings of the DFU interface with their permissions. Default is empty.
+    ///
+    /// If set, the interface gets one alternate setting for each entry, at most
+    /// [`MAX_ALT_SETTINGS`], with [`AltSetting::name`] in *iInterface*, instead of the
+    /// alternate settings of [`MEM_INFO_STRING`](DfuMemory::MEM_INFO_STRING) and
+    /// [`INTERFACE_NAME`](DfuMemory::INTERFACE_NAME). The name of alternate setting `0`
+    /// is referenced if [`HAS_INTERFACE_STRING`](DfuMemory::HAS_INTERFACE_STRING) is set.
+    /// All alternate settings access the same memory, their memory maps declare
+    /// different regions of it.
+    ///
+    /// DFU functional descriptor is shared by all alternate settings, *bitCanUpload* and
+    /// *bitCanDnload* are set if any of them allows the operation, so the permissions of
+    /// the selected alternate setting are enforced by the request handlers:
+    /// * `DFU_UPLOAD` of a memory block is rejected with `errSTALLEDPKT` without
+    ///   [`AltSetting::upload`],
+    /// * `DFU_DNLOAD` of a data block or a command other than *Set Address Pointer*
+    ///   is rejected with `errSTALLEDPKT` without [`AltSetting::download`].
+    ///
+    /// The name of the selected alternate setting is the memory map used by
+    /// [`ENFORCE_PERMISSIONS`](DfuMemory::ENFORCE_PERMISSIONS),
+    /// [`VALIDATE_ADDRESS_POINTER`](DfuMemory::VALIDATE_ADDRESS_POINTER) and
+    /// [`CLAMP_UPLOAD`](DfuMemory::CLAMP_UPLOAD). `SET_INTERFACE` request is rejected
+    /// unless the state is `dfuIDLE` or `dfuERROR`.
+    const ALT_SETTINGS: &'static [AltSetting] = &[];
+
     /// If set, DFU descriptor will have *bitCanDnload* bit set. Default is `true`.
     ///
     /// Should be set to true if firmware download (host to device) is supported.
+    /// Without `download` feature, `DFU_DNLOAD` requests are not handled at all
+    /// and the bit is always cleared.
     const HAS_DOWNLOAD: bool = true;
 
     /// If set, DFU descriptor will have *bitCanUpload* bit set. Default is `true`.
     ///
     /// Should be set to true if firmware upload (device to host) is supported.
+    /// Without `upload` feature, `DFU_UPLOAD` requests are not handled at all
+    /// and the bit is always cleared.
     const HAS_UPLOAD: bool = true;
 
     /// If set, DFU descriptor will have *bitManifestationTolerant* bit set. Default is `true`.
@@ -213,10 +422,6 @@ pub trait DfuMemory {
     /// See also [`MANIFESTATION_TIME_MS`](DfuMemory::MANIFESTATION_TIME_MS).
     const MANIFESTATION_TOLERANT: bool = true;
 
-    // /// Remove device's flash read protection. This operation should erase
-    // /// memory contents.
-    // const HAS_READ_UNPROTECT : bool = false;
-
     /// Time in milliseconds host must wait before issuing the next command after
     /// block program request.
     ///
@@ -275,10 +480,300 @@ pub trait DfuMemory {
     ///
     /// All DFU transfers use Control endpoint only.
     ///
-    /// **Warning**: must be less or equal of `usb-device`'s control endpoint buffer size (usually `128` bytes),
-    /// otherwise data transfers may fail for no obvious reason.
+    /// **Warning**: must be less or equal of `usb-device`'s control endpoint buffer size,
+    /// [`CONTROL_BUFFER_SIZE`], otherwise data transfers may fail for no obvious reason.
+    /// [`DfuClass::new()`] fails to compile if it's larger.
     const TRANSFER_SIZE: u16 = 128;
 
+    /// Number of `DFU_DNLOAD` commands the device accepts before they are executed. Default is `1`.
+    ///
+    /// With the default value, host must issue `DFU_GETSTATUS` after every command, as
+    /// required by the DFU specification. A larger value allows host to send, for example,
+    /// *Set Address Pointer* and *Erase*, or *Erase* and a data block back-to-back while the
+    /// device is in `dfuDNLOAD-SYNC` state. Queued commands are executed in order after the next
+    /// `DFU_GETSTATUS` request, and the reported *bwPollTimeout* is the sum of their timeouts.
+    ///
+    /// A data block must be the last command in the queue, because its contents are kept in
+    /// the buffer filled by [`store_write_buffer()`](DfuMemory::store_write_buffer).
+    ///
+    /// Values larger than [`MAX_COMMAND_QUEUE_DEPTH`] are treated as `MAX_COMMAND_QUEUE_DEPTH`.
+    const COMMAND_QUEUE_DEPTH: usize = 1;
+
+    /// If set, the first data block of a download may start with an LMDFU prefix, as
+    /// created by TI Tiva and Stellaris tools. Default is `false`.
+    ///
+    /// The prefix sets Address Pointer to the image address, and is not passed
+    /// to [`store_write_buffer()`](DfuMemory::store_write_buffer). The rest of the image
+    /// is programmed right after the prefix address. Download fails with `errNOTDONE`
+    /// if the image is shorter than the length in the prefix, or with `errADDRESS` if
+    /// it is longer.
+    ///
+    /// See [`LmdfuPrefix`](crate::suffix::LmdfuPrefix).
+    const LMDFU_PREFIX: bool = false;
+
+    /// If set, the device accepts a vendor-specific *Set Image CRC* command
+    /// (`0xB1`, followed by 4 bytes of CRC, little-endian) in block 0 during download.
+    /// Default is `false`.
+    ///
+    /// The class calculates CRC-32 (IEEE 802.3, as in zlib) of all data blocks received since
+    /// the start of the download. If the command was received, and the CRC does not match,
+    /// the final zero-length `DFU_DNLOAD` request fails with `errVERIFY`, and
+    /// [`manifestation()`](DfuMemory::manifestation) is not called.
+    ///
+    /// The command is listed in *Get Commands* reply.
+    const IMAGE_CRC_COMMAND: bool = false;
+
+    /// If set, the host can continue an interrupted download. Default is `false`.
+    ///
+    /// `DFU_UPLOAD` request with block number 1 in `dfuIDLE` state returns
+    /// [`resume_point()`](DfuMemory::resume_point) as 4 bytes, little-endian,
+    /// or an empty reply if there is no download to continue.
+    ///
+    /// Vendor-specific *Resume* command (`0xB2`, followed by 4 bytes of address, little-endian)
+    /// in block 0 starts a download that continues the interrupted one. It sets Address Pointer
+    /// to the address, so the rest of the image is downloaded from block 2, and
+    /// [`download_start()`](DfuMemory::download_start) is not called. CRC of *Set Image CRC*
+    /// command can't be checked in a resumed download.
+    ///
+    /// The command is listed in *Get Commands* reply. The resume point is not available
+    /// with [`DfuClass::split()`].
+    const RESUME_COMMAND: bool = false;
+
+    /// If set, the device accepts a vendor-specific *Erase Range* command (`0xB3`, followed
+    /// by 4 bytes of start address and 4 bytes of length, little-endian) in block 0.
+    /// Default is `false`.
+    ///
+    /// The range is erased with a single [`erase_range()`](DfuMemory::erase_range) call,
+    /// instead of a DfuSe *Erase* command for each page. *bwPollTimeout* is
+    /// [`ERASE_TIME_MS`](DfuMemory::ERASE_TIME_MS) for each
+    /// [`ERASE_PAGE_SIZE`](DfuMemory::ERASE_PAGE_SIZE) bytes of the range, but not more
+    /// than [`FULL_ERASE_TIME_MS`](DfuMemory::FULL_ERASE_TIME_MS).
+    ///
+    /// The command is listed in *Get Commands* reply.
+    const ERASE_RANGE_COMMAND: bool = false;
+
+    /// Size of the memory area erased in [`ERASE_TIME_MS`](DfuMemory::ERASE_TIME_MS),
+    /// used to report *bwPollTimeout* of *Erase Range* command. Default is `1024`.
+    const ERASE_PAGE_SIZE: u32 = 1024;
+
+    /// If set, DfuSe *Erase All* command is rejected with `errVENDOR`, unless
+    /// [`mass_erase_allowed()`](DfuMemory::mass_erase_allowed) returns `true`, or
+    /// it follows a vendor-specific *Unlock Mass Erase* command (`0xB4`) in block 0.
+    /// Default is `false`.
+    ///
+    /// The unlock applies to the next *Erase All* command only, and is cleared by
+    /// `DFU_ABORT`, `DFU_CLRSTATUS`, and USB reset.
+    ///
+    /// The command is listed in *Get Commands* reply.
+    const MASS_ERASE_GUARD: bool = false;
+
+    /// If set, downloads are locked until the host sends a vendor-specific *Unlock* command
+    /// (`0xB6`, followed by a token of 1 to [`MAX_UNLOCK_TOKEN_LENGTH`] bytes, e.g. a PIN)
+    /// in block 0, and [`unlock()`](DfuMemory::unlock) accepts the token. Default is `false`.
+    ///
+    /// Until then, every other `DFU_DNLOAD` request is rejected with `errVENDOR`, uploads
+    /// are not affected. A rejected token fails with `errVENDOR` too. The device is locked
+    /// again on USB reset.
+    ///
+    /// The command is listed in *Get Commands* reply.
+    const UNLOCK_COMMAND: bool = false;
+
+    /// If set, the host can query and change the readout protection level of the device,
+    /// like with STM32 system bootloader. Default is `false`.
+    ///
+    /// DfuSe *Read Unprotect* command (`0x92`), and vendor-specific *Readout Protection*
+    /// command (`0xB7`, followed by 1 byte of [`ReadoutProtection`] level) in block 0 call
+    /// [`set_readout_protection()`](DfuMemory::set_readout_protection). The host waits
+    /// [`FULL_ERASE_TIME_MS`](DfuMemory::FULL_ERASE_TIME_MS), as removing the protection
+    /// usually erases the memory.
+    ///
+    /// *Readout Protection* command without the level selects the level returned by
+    /// [`readout_protection()`](DfuMemory::readout_protection) as 1 byte reply of the next
+    /// `DFU_UPLOAD` request with block number 1 in `dfuIDLE` state, instead of the resume
+    /// point of [`RESUME_COMMAND`](DfuMemory::RESUME_COMMAND). The query is not available
+    /// with [`DfuClass::split()`].
+    ///
+    /// Both commands are listed in *Get Commands* reply.
+    const READOUT_PROTECTION_COMMANDS: bool = false;
+
+    /// If set, the device accepts a vendor-specific *Get Device Info* command (`0xB8`)
+    /// in block 0. Default is `false`.
+    ///
+    /// The command selects [`device_info()`](DfuMemory::device_info), encoded as described
+    /// in [`info`](crate::info) module, as the reply of the next `DFU_UPLOAD` request with
+    /// block number 1 in `dfuIDLE` state, like *Readout Protection* query of
+    /// [`READOUT_PROTECTION_COMMANDS`](DfuMemory::READOUT_PROTECTION_COMMANDS).
+    /// The upload is rejected if *wLength* is shorter than the encoded reply.
+    /// It's not available with [`DfuClass::split()`].
+    ///
+    /// The command is listed in *Get Commands* reply.
+    const DEVICE_INFO_COMMAND: bool = false;
+
+    /// If set, the device accepts vendor-specific commands with [`tlv`](crate::tlv) payloads
+    /// in block 0. Default is `false`.
+    ///
+    /// *Set Parameters* command (`0xB9`, followed by TLV records, at most
+    /// [`MAX_PAYLOAD_LENGTH`](crate::tlv::MAX_PAYLOAD_LENGTH) bytes) calls
+    /// [`set_parameters()`](DfuMemory::set_parameters), a payload with a truncated
+    /// record is rejected with `errSTALLEDPKT`.
+    ///
+    /// *Get Capabilities* command (`0xBA`) selects the records written by
+    /// [`capabilities()`](DfuMemory::capabilities) as the reply of the next `DFU_UPLOAD`
+    /// request with block number 1 in `dfuIDLE` state, like
+    /// [`DEVICE_INFO_COMMAND`](DfuMemory::DEVICE_INFO_COMMAND). The query is not available
+    /// with [`DfuClass::split()`].
+    ///
+    /// Both commands are listed in *Get Commands* reply.
+    const TLV_COMMANDS: bool = false;
+
+    /// If set, the device replies to a vendor-specific *Get Extended Status* request
+    /// with [`ExtendedStatus`]: the state, bytes programmed, the expected image length,
+    /// and the last memory error, see [`status`](crate::status) module. Default is `false`.
+    ///
+    /// Host GUIs can show the progress of a download without counting blocks. The image
+    /// length is known with [`IMAGE_SIZE_COMMAND`](DfuMemory::IMAGE_SIZE_COMMAND) or
+    /// [`LMDFU_PREFIX`](DfuMemory::LMDFU_PREFIX).
+    const EXTENDED_STATUS_REQUEST: bool = false;
+
+    /// If set, the class enforces operations of the areas declared in
+    /// [`MEM_INFO_STRING`](DfuMemory::MEM_INFO_STRING). Default is `false`.
+    ///
+    /// Programming a block that is not in writable areas fails with `errWRITE`, and erasing
+    /// a page or a range that is not in erasable areas fails with `errERASE`. `DFU_UPLOAD`
+    /// of a block that overlaps an area that can't be read is rejected with `errADDRESS`.
+    /// Programming and erasing addresses outside of the declared areas fails with `errADDRESS`,
+    /// uploads are not checked, so the memory can return a short block at the end.
+    ///
+    /// *Erase All* command is not checked.
+    const ENFORCE_PERMISSIONS: bool = false;
+
+    /// If set, DfuSe *Set Address Pointer* command fails with `errADDRESS` if the address
+    /// is not in an area declared in [`MEM_INFO_STRING`](DfuMemory::MEM_INFO_STRING),
+    /// and the Address Pointer is not changed. Default is `false`.
+    ///
+    /// The error is reported by the first `DFU_GETSTATUS` request after the command,
+    /// instead of a failed program or erase operation later.
+    const VALIDATE_ADDRESS_POINTER: bool = false;
+
+    /// If set, the Address Pointer is moved forward when the block number of a download
+    /// wraps. Default is `false`.
+    ///
+    /// Data blocks are numbered from 2 to 65535, so a download from one Address Pointer
+    /// is limited to 65534 blocks, about 4 MiB with 64-byte transfers. With this option,
+    /// block 2 right after block 65535 continues the image: the Address Pointer moves
+    /// by 65534 blocks before the block is programmed, so large images can be downloaded
+    /// without *Set Address Pointer* commands. Uploads are not rebased.
+    const REBASE_ON_WRAP: bool = false;
+
+    /// If set, uploads end at the end of the region declared in
+    /// [`MEM_INFO_STRING`](DfuMemory::MEM_INFO_STRING). Default is `false`.
+    ///
+    /// The class limits the length of [`read_block()`](DfuMemory::read_block) calls,
+    /// so memory after the region is never read. The block that ends at the end of
+    /// the region is the last one, and an upload from an address outside of the declared
+    /// areas is handled like a request after the last block, see
+    /// [`UPLOAD_END`](DfuMemory::UPLOAD_END).
+    const CLAMP_UPLOAD: bool = false;
+
+    /// How an upload ends at the end of the memory. Default is [`UploadEnd::ZeroLength`].
+    const UPLOAD_END: UploadEnd = UploadEnd::ZeroLength;
+
+    /// If set, the device accepts a vendor-specific *Set Image Size* command
+    /// (`0xB5`, followed by 4 bytes of length, little-endian) in block 0 before data blocks.
+    /// Default is `false`.
+    ///
+    /// A download with more data fails with `errADDRESS`, and the final zero-length
+    /// `DFU_DNLOAD` request of a shorter one fails with `errNOTDONE`, so
+    /// [`manifestation()`](DfuMemory::manifestation) is not called with an incomplete image.
+    /// [`DfuClass::download_progress()`] returns the received part of the image.
+    ///
+    /// If [`ERASE_RANGE_COMMAND`](DfuMemory::ERASE_RANGE_COMMAND) is set too, the image range
+    /// starting at Address Pointer is erased with [`erase_range()`](DfuMemory::erase_range).
+    ///
+    /// The command is listed in *Get Commands* reply.
+    const IMAGE_SIZE_COMMAND: bool = false;
+
+    /// Minimum number of bytes downloaded before manifestation. Default is `0`.
+    ///
+    /// The final zero-length `DFU_DNLOAD` request of a download with fewer bytes of data,
+    /// e.g. when the host sends it right after the first block, fails with `errNOTDONE`,
+    /// and [`manifestation()`](DfuMemory::manifestation) is not called.
+    const MIN_IMAGE_SIZE: u32 = 0;
+
+    /// How a zero-length `DFU_DNLOAD` request in `dfuIDLE` state, a download without data,
+    /// is handled. Default is [`EmptyDownload::Manifest`].
+    ///
+    /// In `dfuDNLOAD-IDLE` state, the request always starts manifestation.
+    const EMPTY_DOWNLOAD: EmptyDownload = EmptyDownload::Manifest;
+
+    /// How data blocks that repeat the previous block or skip blocks are handled.
+    /// Default is [`BlockSequence::PassThrough`].
+    ///
+    /// Hosts occasionally send a block again after an error on the control pipe,
+    /// so the same data would be programmed twice.
+    const BLOCK_SEQUENCE: BlockSequence = BlockSequence::PassThrough;
+
+    /// If set, the memory is write-once, e.g. OTP. Default is `false`.
+    ///
+    /// Before a block is programmed, the class reads the target range with
+    /// [`read()`](DfuMemory::read), and fails the download with `errWRITE` if it's not
+    /// blank (all bytes are `0xFF`), so programmed data can't be programmed again.
+    /// [`read()`](DfuMemory::read) must not modify the data stored by
+    /// [`store_write_buffer()`](DfuMemory::store_write_buffer).
+    const WRITE_ONCE: bool = false;
+
+    /// If set, blocks that are already in the memory are not programmed again.
+    /// Default is `false`.
+    ///
+    /// Before a block is programmed, the class reads the target range with
+    /// [`read()`](DfuMemory::read) and compares its CRC-32 with the CRC-32 of the block,
+    /// the class doesn't keep a copy of the data. If they match, the bytes are compared
+    /// with [`compare()`](DfuMemory::compare), and only if it returns `Ok(true)`,
+    /// [`program()`](DfuMemory::program) is not called, so downloading a mostly unchanged
+    /// image takes less time and wears the flash less. If the range can't be read or
+    /// compared, the block is programmed.
+    ///
+    /// This is useful if [`program()`](DfuMemory::program) erases pages itself, or the
+    /// host doesn't erase them before a download. [`read()`](DfuMemory::read) must not
+    /// modify the data stored by [`store_write_buffer()`](DfuMemory::store_write_buffer).
+    const SKIP_IDENTICAL: bool = false;
+
+    /// If set, the device accepts a vendor-specific *Verify* command (`0xBC`) in block 0
+    /// at the start of a download. Default is `false`.
+    ///
+    /// The following data blocks of the download are not programmed, the class reads
+    /// the target range with [`read()`](DfuMemory::read), and if its CRC-32 matches the
+    /// CRC-32 of the block, compares the bytes with [`compare()`](DfuMemory::compare),
+    /// which must be implemented. A block that doesn't match, or can't be read, fails the download with `errVERIFY`,
+    /// and [`DfuClass::last_memory_error()`] returns its address and block number.
+    /// Erase commands are acknowledged without erasing, so units can be checked against
+    /// a golden image with an ordinary download tool.
+    ///
+    /// [`download_start()`](DfuMemory::download_start) and
+    /// [`manifestation()`](DfuMemory::manifestation) are not called, the final
+    /// zero-length `DFU_DNLOAD` request returns the device to `dfuIDLE`.
+    ///
+    /// The command is listed in *Get Commands* reply.
+    const VERIFY_COMMAND: bool = false;
+
+    /// If set, every request rejected in `dfuERROR` state sets the status to `errSTALLEDPKT`.
+    /// Default is `false`.
+    ///
+    /// By default, the status of the first error is kept until `DFU_CLRSTATUS`, so the host
+    /// reports the root cause, e.g. `errVERIFY`, instead of the status of a later request
+    /// that is rejected because of the error.
+    const STRICT: bool = false;
+
+    /// Time in milliseconds after which an unfinished download or upload is cancelled
+    /// if the host sends no requests. Default is `0`, the session never times out.
+    ///
+    /// The class has no time source, the application reports elapsed time with
+    /// [`DfuClass::tick()`]. When the host goes away, e.g. the cable is pulled or the host
+    /// tool is killed, pending commands are dropped, [`session_timeout()`](DfuMemory::session_timeout)
+    /// is called, and the device goes to `dfuIDLE` after an upload, or to `dfuERROR`
+    /// with `errNOTDONE` after a download, so the next host sees the incomplete image.
+    const SESSION_TIMEOUT_MS: u32 = 0;
+
     // /// Not supported, implementation would probably need some
     // /// non-trivial locking.
     // const MEMIO_IN_USB_INTERRUPT: bool = true;
@@ -296,7 +791,7 @@ pub trait DfuMemory {
     ///
     /// This function is called from `usb_dev.poll([])` (USB interrupt context).
     ///
-    #[allow(unused_variables)]
+    #[allow(unused_variables, clippy::result_unit_err)]
     fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
         Err(())
     }
@@ -316,6 +811,50 @@ pub trait DfuMemory {
         Err(DfuMemoryError::Unknown)
     }
 
+    /// Read memory like [`read()`](DfuMemory::read), and tell if the block is
+    /// the end of the memory.
+    ///
+    /// Upload ends with the first block shorter than [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE).
+    /// If [`ReadOutcome::LastChunk`] is `TRANSFER_SIZE` bytes long, the next upload
+    /// request gets a zero-length block without calling this function, so memory
+    /// after the end is never read.
+    ///
+    /// By default, calls [`read()`](DfuMemory::read), and the block is the last one
+    /// if it's shorter than `length`.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    ///
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        let data = self.read(address, length)?;
+        if data.len() < length {
+            Ok(ReadOutcome::LastChunk(data))
+        } else {
+            Ok(ReadOutcome::Full(data))
+        }
+    }
+
+    /// Compare `length` bytes of the memory at `address` with the block stored by
+    /// [`store_write_buffer()`](DfuMemory::store_write_buffer), returns `Ok(true)`
+    /// if all bytes are equal.
+    ///
+    /// Used by [`SKIP_IDENTICAL`](DfuMemory::SKIP_IDENTICAL) and
+    /// [`VERIFY_COMMAND`](DfuMemory::VERIFY_COMMAND), after the CRC-32 of the range
+    /// read with [`read()`](DfuMemory::read) matched the block, so a block that only has
+    /// the same CRC-32 is neither skipped nor accepted. By default, returns
+    /// `Err(DfuMemoryError::Unknown)`: every block is programmed, and every verified
+    /// block fails.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    ///
+    #[allow(unused_variables)]
+    fn compare(&mut self, address: u32, length: usize) -> Result<bool, DfuMemoryError> {
+        Err(DfuMemoryError::Unknown)
+    }
+
     /// Trigger block program.
     ///
     /// Implementation must check that address is in a target region and that the
@@ -354,6 +893,129 @@ pub trait DfuMemory {
         Err(DfuMemoryError::Erase)
     }
 
+    /// Trigger erase of `length` bytes starting at `address`,
+    /// see [`ERASE_RANGE_COMMAND`](DfuMemory::ERASE_RANGE_COMMAND).
+    ///
+    /// Implementation must erase all pages that overlap the range,
+    /// and ensure that the range is valid, or return an error.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    // / This function by default is called from USB interrupt context, depending on
+    // / [`MEMIO_IN_USB_INTERRUPT`](DfuMemory::MEMIO_IN_USB_INTERRUPT) value.
+    ///
+    #[allow(unused_variables)]
+    fn erase_range(&mut self, address: u32, length: u32) -> Result<(), DfuMemoryError> {
+        Err(DfuMemoryError::Erase)
+    }
+
+    /// Returns `true` if *Erase All* command is allowed without
+    /// *Unlock Mass Erase* command, see [`MASS_ERASE_GUARD`](DfuMemory::MASS_ERASE_GUARD).
+    /// Default is `false`.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    fn mass_erase_allowed(&mut self) -> bool {
+        false
+    }
+
+    /// Returns `false` if a new download must not start now, e.g. while the application
+    /// uses the memory. Default is `true`.
+    ///
+    /// Called before [`download_start()`](DfuMemory::download_start) and the first erase
+    /// or program operation of a download. If it returns `false`, the operation fails
+    /// with `errVENDOR`, so the host reports an error, and the memory is not modified.
+    /// The host can retry after `DFU_CLRSTATUS`.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    fn may_start_download(&mut self) -> bool {
+        true
+    }
+
+    /// Returns `false` if the memory must not be erased now. Default is `true`.
+    ///
+    /// Called before every [`erase()`](DfuMemory::erase), [`erase_all()`](DfuMemory::erase_all)
+    /// and [`erase_range()`](DfuMemory::erase_range) call. If it returns `false`,
+    /// the operation fails with `errVENDOR`.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    fn may_erase(&mut self) -> bool {
+        true
+    }
+
+    /// Returns `true` if `token` of *Unlock* command allows downloads,
+    /// see [`UNLOCK_COMMAND`](DfuMemory::UNLOCK_COMMAND). Default is `false`.
+    ///
+    /// Compare the token in constant time, so its value can't be guessed from
+    /// the response time.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    #[allow(unused_variables)]
+    fn unlock(&mut self, token: &[u8]) -> bool {
+        false
+    }
+
+    /// Returns the current readout protection level, see
+    /// [`READOUT_PROTECTION_COMMANDS`](DfuMemory::READOUT_PROTECTION_COMMANDS).
+    /// Default is [`ReadoutProtection::Level0`].
+    fn readout_protection(&mut self) -> ReadoutProtection {
+        ReadoutProtection::Level0
+    }
+
+    /// Change the readout protection level, see
+    /// [`READOUT_PROTECTION_COMMANDS`](DfuMemory::READOUT_PROTECTION_COMMANDS).
+    /// Default implementation fails with [`DfuMemoryError::Unknown`].
+    ///
+    /// Return an error to refuse a level, e.g. [`ReadoutProtection::Level2`], which
+    /// can't be undone. The new level usually takes effect after a reset, so this
+    /// function may not return.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    #[allow(unused_variables)]
+    fn set_readout_protection(&mut self, level: ReadoutProtection) -> Result<(), DfuMemoryError> {
+        Err(DfuMemoryError::Unknown)
+    }
+
+    /// Returns the device metadata, see [`DEVICE_INFO_COMMAND`](DfuMemory::DEVICE_INFO_COMMAND).
+    /// Default is [`DeviceInfo::default()`].
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    fn device_info(&mut self) -> DeviceInfo<'_> {
+        DeviceInfo::default()
+    }
+
+    /// Apply parameters of *Set Parameters* command, see
+    /// [`TLV_COMMANDS`](DfuMemory::TLV_COMMANDS). Records are complete, unknown tags
+    /// should be skipped. Default implementation fails with [`DfuMemoryError::Unknown`].
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    #[allow(unused_variables)]
+    fn set_parameters(&mut self, parameters: TlvReader<'_>) -> Result<(), DfuMemoryError> {
+        Err(DfuMemoryError::Unknown)
+    }
+
+    /// Write the reply of *Get Capabilities* command, see
+    /// [`TLV_COMMANDS`](DfuMemory::TLV_COMMANDS). Default is no records.
+    ///
+    /// The request is rejected with `errUNKNOWN` if an error is returned.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    #[allow(unused_variables)]
+    fn capabilities(&mut self, w: &mut TlvWriter<'_>) -> Result<(), TlvError> {
+        Ok(())
+    }
+
+    /// Called before [`manifestation()`](DfuMemory::manifestation) with the Address Pointer.
+    ///
+    /// DfuSe hosts, e.g. dfu-util with `:leave`, send *Set Address Pointer* command with
+    /// the entry point of the new firmware before the zero-length `DFU_DNLOAD` that
+    /// starts manifestation. Keep `address` to jump there after manifestation instead of
+    /// a fixed application address. Without the command, it's the address of the last
+    /// command or [`INITIAL_ADDRESS_POINTER`](DfuMemory::INITIAL_ADDRESS_POINTER).
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    ///
+    #[allow(unused_variables)]
+    fn leave(&mut self, address: u32) {}
+
     /// Finish writing firmware to a persistent storage, and optionally activate it.
     ///
     /// This funciton should return if [`MANIFESTATION_TOLERANT`](DfuMemory::MANIFESTATION_TOLERANT) is `true`.
@@ -369,6 +1031,62 @@ pub trait DfuMemory {
         Err(DfuManifestationError::Unknown)
     }
 
+    /// Returns the version of the downloaded image, e.g. *bcdDevice* from DFU file
+    /// suffix or a version from a custom image header.
+    ///
+    /// Called before [`manifestation()`](DfuMemory::manifestation) if
+    /// [`minimum_image_version()`](DfuMemory::minimum_image_version) returns a value.
+    ///
+    fn image_version(&mut self) -> Option<u32> {
+        None
+    }
+
+    /// Returns the minimum image version the device accepts, to prevent downgrades.
+    ///
+    /// If the version returned by [`image_version()`](DfuMemory::image_version)
+    /// is lower, or is unknown, `manifestation()` is not called and DFU switches
+    /// to ERROR state with `errFILE` status.
+    ///
+    /// Default implementation returns `None`, version is not checked.
+    ///
+    fn minimum_image_version(&mut self) -> Option<u32> {
+        None
+    }
+
+    /// Called when the host starts a new download, before the first erase or program
+    /// operation of this download.
+    ///
+    /// Note that [`store_write_buffer()`](DfuMemory::store_write_buffer) may be called
+    /// for the first data block before this function.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    ///
+    fn download_start(&mut self) {}
+
+    /// Called when the host sends a data block after block `block_num` of `length` bytes,
+    /// that is shorter than [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE).
+    ///
+    /// The host doesn't use *wTransferSize*, and, as block addresses are calculated
+    /// with `TRANSFER_SIZE`, the image is likely corrupted. Use it to report misbehaving
+    /// host tools, see also [`DfuClass::download_block_sizes()`].
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context),
+    /// before the next erase or program operation.
+    ///
+    #[allow(unused_variables)]
+    fn block_size_mismatch(&mut self, block_num: u16, length: u16) {}
+
+    /// End address of the last programmed block of an interrupted download,
+    /// see [`RESUME_COMMAND`](DfuMemory::RESUME_COMMAND). Default is `None`.
+    ///
+    /// [`JournalMemory`](crate::journal::JournalMemory) returns it from the download journal.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    ///
+    fn resume_point(&mut self) -> Option<u32> {
+        None
+    }
+
     /// Called every time when USB is reset.
     ///
     /// After firmware update is done, device should switch to an application
@@ -384,31 +1102,196 @@ pub trait DfuMemory {
     /// This function is called from `usb_dev.poll([])` (USB interrupt context).
     ///
     fn usb_reset(&mut self) {}
+
+    /// Called when the selected alternate setting of [`ALT_SETTINGS`](DfuMemory::ALT_SETTINGS)
+    /// changes to `alt`, with `SET_INTERFACE` request, or to `0` after USB reset. A memory
+    /// with several regions routes the following reads and writes to the region of `alt`,
+    /// see [`staging`](crate::staging) module.
+    ///
+    /// With [`DfuClass::split()`], it's called by [`DfuWorker`](crate::DfuWorker)
+    /// before the next operation.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    #[allow(unused_variables)]
+    fn select_alt_setting(&mut self, alt: u8) {}
+
+    /// Called when a session is cancelled after [`SESSION_TIMEOUT_MS`](DfuMemory::SESSION_TIMEOUT_MS)
+    /// or with [`DfuClass::force_idle()`], to release resources of the unfinished download
+    /// or upload, e.g. a flash lock.
+    ///
+    /// This function is called from [`DfuClass::tick()`] and [`DfuClass::force_idle()`].
+    fn session_timeout(&mut self) {}
 }
 
-impl From<DfuMemoryError> for DfuStatusCode {
-    fn from(e: DfuMemoryError) -> Self {
-        match e {
-            DfuMemoryError::File => DfuStatusCode::ErrFile,
-            DfuMemoryError::Target => DfuStatusCode::ErrTarget,
-            DfuMemoryError::Address => DfuStatusCode::ErrAddress,
-            DfuMemoryError::CheckErased => DfuStatusCode::ErrCheckErased,
-            DfuMemoryError::Erase => DfuStatusCode::ErrErase,
-            DfuMemoryError::Write => DfuStatusCode::ErrWrite,
-            DfuMemoryError::Prog => DfuStatusCode::ErrProg,
-            DfuMemoryError::Verify => DfuStatusCode::ErrVerify,
-            DfuMemoryError::Unknown => DfuStatusCode::ErrUnknown,
-            DfuMemoryError::ErrVendor => DfuStatusCode::ErrVendor,
+/// Block returned by [`DfuMemory::read_block()`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum ReadOutcome<'a> {
+    /// Memory continues after the block.
+    Full(&'a [u8]),
+    /// The block is the end of the memory.
+    LastChunk(&'a [u8]),
+}
+
+impl<'a> ReadOutcome<'a> {
+    /// Returns the block data.
+    pub fn data(&self) -> &'a [u8] {
+        match *self {
+            ReadOutcome::Full(data) | ReadOutcome::LastChunk(data) => data,
         }
     }
+
+    /// Returns `true` if the block is the end of the memory.
+    pub fn is_last(&self) -> bool {
+        matches!(self, ReadOutcome::LastChunk(_))
+    }
 }
 
-impl From<DfuManifestationError> for DfuStatusCode {
-    fn from(e: DfuManifestationError) -> Self {
-        match e {
-            DfuManifestationError::NotDone => DfuStatusCode::ErrNotdone,
-            DfuManifestationError::Firmware => DfuStatusCode::ErrFirmware,
-            DfuManifestationError::Unknown => DfuStatusCode::ErrUnknown,
+/// How an upload ends at the end of the memory, see [`DfuMemory::UPLOAD_END`].
+///
+/// The end is known when [`DfuMemory::read_block()`] returns [`ReadOutcome::LastChunk`],
+/// or from the region end with [`CLAMP_UPLOAD`](DfuMemory::CLAMP_UPLOAD). A block shorter
+/// than *wTransferSize* always ends the upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum UploadEnd {
+    /// A full-length last block is followed by a zero-length block. This is the default.
+    ZeroLength,
+    /// The last block has the remaining bytes of the memory, and ends the upload
+    /// even if it's full-length, the device returns to `dfuIDLE`.
+    LastBlock,
+    /// Like [`ZeroLength`](UploadEnd::ZeroLength), but a request after the last block
+    /// is rejected with `errADDRESS` instead of a zero-length block.
+    Error,
+}
+
+/// How a zero-length `DFU_DNLOAD` request in `dfuIDLE` state is handled,
+/// see [`DfuMemory::EMPTY_DOWNLOAD`].
+///
+/// Host tools differ: DFU 1.1 does not allow the request in `dfuIDLE`, while DfuSe
+/// hosts, e.g. dfu-util with `:leave`, send it after an upload or without a download
+/// to leave DFU mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum EmptyDownload {
+    /// Manifestation starts like after a download. This is the default.
+    Manifest,
+    /// The request is rejected with `errSTALLEDPKT`, as DFU 1.1 requires.
+    Stall,
+    /// DfuSe *Leave DFU*: [`leave()`](DfuMemory::leave) and
+    /// [`manifestation()`](DfuMemory::manifestation) are called without the checks
+    /// of a downloaded image, [`MIN_IMAGE_SIZE`](DfuMemory::MIN_IMAGE_SIZE) and
+    /// [`minimum_image_version()`](DfuMemory::minimum_image_version).
+    Leave,
+}
+
+/// How the block numbers of a download are checked, see [`DfuMemory::BLOCK_SEQUENCE`].
+///
+/// A block is a duplicate if it has the block number and the length of the previous
+/// data block. The first data block of a download, or after DfuSe *Set Address Pointer*
+/// command, can have any block number, and block 2 follows block 65535.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum BlockSequence {
+    /// Every block is programmed at the address of its block number. This is the default.
+    PassThrough,
+    /// Duplicate blocks are acknowledged without programming, other blocks
+    /// are programmed.
+    IgnoreDuplicates,
+    /// Duplicate blocks are acknowledged without programming, and the download
+    /// fails with `errADDRESS` on a block that is not the next one, e.g. after a lost block.
+    Strict,
+}
+
+/// Alternate setting of the DFU interface, see [`DfuMemory::ALT_SETTINGS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct AltSetting {
+    /// *iInterface* string, e.g. the DfuSe memory map of the region.
+    pub name: &'static str,
+    /// Memory blocks can be read with `DFU_UPLOAD`.
+    pub upload: bool,
+    /// Memory can be erased and programmed with `DFU_DNLOAD`.
+    pub download: bool,
+}
+
+/// Maximum number of alternate settings, see [`DfuMemory::ALT_SETTINGS`].
+pub const MAX_ALT_SETTINGS: usize = 8;
+
+/// Readout protection level, see [`DfuMemory::READOUT_PROTECTION_COMMANDS`].
+///
+/// The levels are the ones of STM32 *RDP* option byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ReadoutProtection {
+    /// The memory can be read.
+    Level0 = 0,
+    /// The memory can't be read from outside, going back to level 0 erases it.
+    Level1 = 1,
+    /// The memory can't be read from outside and the debug interface is disabled,
+    /// permanently.
+    Level2 = 2,
+}
+
+impl TryFrom<u8> for ReadoutProtection {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => ReadoutProtection::Level0,
+            1 => ReadoutProtection::Level1,
+            2 => ReadoutProtection::Level2,
+            _ => return Err(value),
+        })
+    }
+}
+
+/// Smallest and largest data block of a download, see [`DfuClass::download_block_sizes()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct BlockSizes {
+    /// Smallest *wLength*.
+    pub min: u16,
+    /// Largest *wLength*.
+    pub max: u16,
+}
+
+/// Failed [`DfuMemory`] call, see [`DfuClass::last_memory_error()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct MemoryErrorDetail {
+    /// Returned error.
+    pub error: DfuMemoryError,
+    /// Memory address, `None` for a full erase.
+    pub address: Option<u32>,
+    /// Block number of `DFU_DNLOAD` or `DFU_UPLOAD` request, `None` for erase.
+    pub block: Option<u16>,
+}
+
+impl From<DfuMemoryError> for DfuStatusCode {
+    fn from(e: DfuMemoryError) -> Self {
+        match e {
+            DfuMemoryError::File => DfuStatusCode::ErrFile,
+            DfuMemoryError::Target => DfuStatusCode::ErrTarget,
+            DfuMemoryError::Address => DfuStatusCode::ErrAddress,
+            DfuMemoryError::CheckErased => DfuStatusCode::ErrCheckErased,
+            DfuMemoryError::Erase => DfuStatusCode::ErrErase,
+            DfuMemoryError::Write => DfuStatusCode::ErrWrite,
+            DfuMemoryError::Prog => DfuStatusCode::ErrProg,
+            DfuMemoryError::Verify => DfuStatusCode::ErrVerify,
+            DfuMemoryError::Unknown => DfuStatusCode::ErrUnknown,
+            DfuMemoryError::ErrVendor => DfuStatusCode::ErrVendor,
+        }
+    }
+}
+
+impl From<DfuManifestationError> for DfuStatusCode {
+    fn from(e: DfuManifestationError) -> Self {
+        match e {
+            DfuManifestationError::NotDone => DfuStatusCode::ErrNotdone,
+            DfuManifestationError::Firmware => DfuStatusCode::ErrFirmware,
+            DfuManifestationError::Unknown => DfuStatusCode::ErrUnknown,
             DfuManifestationError::ErrVendor => DfuStatusCode::ErrVendor,
             DfuManifestationError::File => DfuStatusCode::ErrFile,
             DfuManifestationError::Target => DfuStatusCode::ErrTarget,
@@ -416,79 +1299,2224 @@ impl From<DfuManifestationError> for DfuStatusCode {
     }
 }
 
-/// DFU protocol USB class implementation for usb-device library.
-#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
-pub struct DfuClass<B: UsbBus, M: DfuMemory> {
-    if_num: InterfaceNumber,
-    status: DFUStatus,
-    interface_string: StringIndex,
-    _bus: PhantomData<B>,
-    mem: M,
+/// DFU protocol USB class implementation for usb-device library.
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct DfuClass<B: UsbBus, M: DfuMemory> {
+    if_num: InterfaceNumber,
+    status: DFUStatus,
+    interface_string: Option<StringIndex>,
+    alt_strings: AltStrings,
+    _bus: PhantomData<B>,
+    mem: M,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+// commands are only queued by `DFU_DNLOAD`
+#[cfg_attr(not(feature = "download"), allow(dead_code))]
+enum Command {
+    /// Erase `length` bytes at Address Pointer.
+    EraseImage {
+        length: u32,
+    },
+    /// `unlocked` by *Unlock Mass Erase* command.
+    EraseAll {
+        unlocked: bool,
+    },
+    Erase(u32),
+    EraseRange {
+        address: u32,
+        length: u32,
+    },
+    SetAddressPointer(u32),
+    SetReadoutProtection(ReadoutProtection),
+    /// Token of *Unlock* command is kept in [`DFUStatus`].
+    Unlock,
+    /// Payload is kept in [`DFUStatus`].
+    SetParameters,
+    /// `skip` bytes were removed from the start of the block.
+    /// `rebase` moves Address Pointer first, see [`DfuMemory::REBASE_ON_WRAP`].
+    WriteMemory {
+        block_num: u16,
+        len: u16,
+        skip: u16,
+        rebase: bool,
+        crc: Option<u32>,
+    },
+    /// `image` is `false` for DfuSe *Leave DFU* without a download.
+    LeaveDfu {
+        image: bool,
+    },
+}
+
+/// Number of data blocks before the block number wraps, blocks 2 to 65535,
+/// see [`DfuMemory::REBASE_ON_WRAP`].
+const WRAP_BLOCKS: u16 = u16::MAX - 1;
+
+/// Memory operation, with the final memory address resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub(crate) enum Operation {
+    EraseAll {
+        unlocked: bool,
+    },
+    Erase(u32),
+    EraseRange {
+        address: u32,
+        length: u32,
+    },
+    SetReadoutProtection(ReadoutProtection),
+    Unlock,
+    SetParameters,
+    /// `crc` is CRC-32 of the block, see [`DfuMemory::SKIP_IDENTICAL`].
+    Program {
+        address: u32,
+        len: u16,
+        block_num: u16,
+        crc: Option<u32>,
+    },
+    /// Compare the memory with a block, see [`DfuMemory::VERIFY_COMMAND`].
+    Verify {
+        address: u32,
+        len: u16,
+        block_num: u16,
+        crc: u32,
+    },
+    Manifestation {
+        address: u32,
+        image: bool,
+    },
+}
+
+/// Error of an [`Operation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub(crate) enum OperationError {
+    /// [`DfuMemory`] function failed.
+    Memory(DfuMemoryError),
+    /// Operation was rejected, or manifestation failed.
+    Status(DfuStatusCode),
+}
+
+impl From<DfuMemoryError> for OperationError {
+    fn from(e: DfuMemoryError) -> Self {
+        OperationError::Memory(e)
+    }
+}
+
+impl From<DfuStatusCode> for OperationError {
+    fn from(e: DfuStatusCode) -> Self {
+        OperationError::Status(e)
+    }
+}
+
+impl From<OperationError> for DfuStatusCode {
+    fn from(e: OperationError) -> Self {
+        match e {
+            OperationError::Memory(e) => e.into(),
+            OperationError::Status(e) => e,
+        }
+    }
+}
+
+impl Operation {
+    fn timeout(&self, config: &MemoryConfig) -> u32 {
+        match self {
+            Operation::Program { .. } => config.program_time_ms,
+            Operation::Verify { .. } => 0,
+            Operation::EraseAll { .. } => config.full_erase_time_ms,
+            Operation::Erase(_) => config.erase_time_ms,
+            Operation::EraseRange { length, .. } => config.erase_range_time_ms(*length),
+            Operation::Manifestation { .. } => config.manifestation_time_ms,
+            Operation::SetReadoutProtection(_) => config.full_erase_time_ms,
+            Operation::Unlock | Operation::SetParameters => 0,
+        }
+    }
+
+    /// Call the corresponding [`DfuMemory`] function,
+    /// `payload` is the payload of *Unlock* or *Set Parameters* command.
+    pub(crate) fn execute<M: DfuMemory>(
+        &self,
+        mem: &mut M,
+        payload: &[u8],
+    ) -> Result<(), OperationError> {
+        let erase = matches!(
+            self,
+            Operation::EraseAll { .. } | Operation::Erase(_) | Operation::EraseRange { .. }
+        );
+        if erase && !mem.may_erase() {
+            return Err(DfuStatusCode::ErrVendor.into());
+        }
+
+        match *self {
+            #[cfg(feature = "download")]
+            Operation::EraseAll { unlocked } => {
+                if M::MASS_ERASE_GUARD && !unlocked && !mem.mass_erase_allowed() {
+                    return Err(DfuStatusCode::ErrVendor.into());
+                }
+                mem.erase_all().map_err(|e| e.into())
+            }
+            #[cfg(feature = "download")]
+            Operation::Erase(address) => mem.erase(address).map_err(|e| e.into()),
+            #[cfg(feature = "download")]
+            Operation::EraseRange { address, length } => {
+                mem.erase_range(address, length).map_err(|e| e.into())
+            }
+            #[cfg(feature = "download")]
+            Operation::Program {
+                address, len, crc, ..
+            } => {
+                if crc.is_some_and(|crc| matches_block(mem, address, len as usize, crc)) {
+                    debug!("DFU block at 0x{:08x} is not changed", address);
+                    return Ok(());
+                }
+                if M::WRITE_ONCE {
+                    check_blank(mem, address, len as usize)?;
+                }
+                mem.program(address, len as usize).map_err(|e| e.into())
+            }
+            #[cfg(feature = "download")]
+            Operation::Verify {
+                address, len, crc, ..
+            } => match matches_block(mem, address, len as usize, crc) {
+                true => Ok(()),
+                false => Err(DfuMemoryError::Verify.into()),
+            },
+            Operation::Manifestation { address, image } => {
+                if image {
+                    check_image_version(mem)?;
+                }
+                mem.leave(address);
+                // may not return
+                mem.manifestation()
+                    .map_err(|e| DfuStatusCode::from(e).into())
+            }
+            // not queued without `download` feature
+            #[cfg(not(feature = "download"))]
+            Operation::EraseAll { .. }
+            | Operation::Erase(_)
+            | Operation::EraseRange { .. }
+            | Operation::Program { .. }
+            | Operation::Verify { .. } => Err(DfuStatusCode::ErrStalledPkt.into()),
+            Operation::SetReadoutProtection(level) => {
+                mem.set_readout_protection(level).map_err(|e| e.into())
+            }
+            Operation::Unlock => match mem.unlock(payload) {
+                true => Ok(()),
+                false => Err(DfuStatusCode::ErrVendor.into()),
+            },
+            Operation::SetParameters => mem
+                .set_parameters(TlvReader::new(payload))
+                .map_err(|e| e.into()),
+        }
+    }
+}
+
+/// Reject programmed data, see [`DfuMemory::WRITE_ONCE`].
+#[cfg(feature = "download")]
+fn check_blank<M: DfuMemory>(
+    mem: &mut M,
+    address: u32,
+    length: usize,
+) -> Result<(), OperationError> {
+    let mut checked = 0;
+    while checked < length {
+        let data = mem.read(address.wrapping_add(checked as u32), length - checked)?;
+        if data.is_empty() {
+            return Err(DfuStatusCode::ErrAddress.into());
+        }
+        let data = &data[..data.len().min(length - checked)];
+        if data.iter().any(|&b| b != 0xff) {
+            return Err(DfuStatusCode::ErrWrite.into());
+        }
+        checked += data.len();
+    }
+    Ok(())
+}
+
+/// Returns `true` if `length` bytes at `address` have CRC-32 `crc`.
+#[cfg(feature = "download")]
+fn crc_matches<M: DfuMemory>(mem: &mut M, address: u32, length: usize, crc: u32) -> bool {
+    let mut memory_crc = Crc32::new();
+    let mut checked = 0;
+    while checked < length {
+        match mem.read(address.wrapping_add(checked as u32), length - checked) {
+            Ok(data) if !data.is_empty() => {
+                let data = &data[..data.len().min(length - checked)];
+                memory_crc.update(data);
+                checked += data.len();
+            }
+            _ => return false,
+        }
+    }
+    memory_crc.finalize() == crc
+}
+
+/// Returns `true` if `length` bytes at `address` are equal to the stored block,
+/// see [`DfuMemory::SKIP_IDENTICAL`]. The CRC-32 `crc` of the block filters out
+/// different ranges without [`DfuMemory::compare()`].
+#[cfg(feature = "download")]
+fn matches_block<M: DfuMemory>(mem: &mut M, address: u32, length: usize, crc: u32) -> bool {
+    crc_matches(mem, address, length, crc) && mem.compare(address, length) == Ok(true)
+}
+
+/// Start a new download before its first operation, see [`DfuMemory::may_start_download()`].
+pub(crate) fn start_download<M: DfuMemory>(mem: &mut M) -> Result<(), OperationError> {
+    if !mem.may_start_download() {
+        return Err(DfuStatusCode::ErrVendor.into());
+    }
+    mem.download_start();
+    Ok(())
+}
+
+/// Reject downgrades, see [`DfuMemory::minimum_image_version()`].
+fn check_image_version<M: DfuMemory>(mem: &mut M) -> Result<(), DfuStatusCode> {
+    match mem.minimum_image_version() {
+        Some(minimum) if mem.image_version().is_none_or(|v| v < minimum) => {
+            Err(DfuStatusCode::ErrFile)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Clone)]
+struct CommandQueue(Deque<Command, MAX_COMMAND_QUEUE_DEPTH>);
+
+impl CommandQueue {
+    fn new() -> Self {
+        Self(Deque::new())
+    }
+}
+
+impl Deref for CommandQueue {
+    type Target = Deque<Command, MAX_COMMAND_QUEUE_DEPTH>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for CommandQueue {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[cfg(feature = "defmt-03")]
+impl defmt::Format for CommandQueue {
+    fn format(&self, fmt: defmt::Formatter) {
+        let (a, b) = self.0.as_slices();
+        defmt::write!(fmt, "{}{}", a, b)
+    }
+}
+
+/// Image length announced in LMDFU prefix or with *Set Image Size* command,
+/// and the number of bytes received.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+#[cfg_attr(not(feature = "download"), allow(dead_code))]
+struct ImageProgress {
+    length: u32,
+    received: u32,
+}
+
+/// Reply of `DFU_UPLOAD` request with block number 1.
+#[cfg(feature = "upload")]
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+#[cfg_attr(not(feature = "download"), allow(dead_code))]
+pub(crate) enum Query {
+    /// See [`DfuMemory::resume_point()`].
+    Resume,
+    /// See [`DfuMemory::readout_protection()`].
+    ReadoutProtection,
+    /// See [`DfuMemory::device_info()`].
+    DeviceInfo,
+    /// See [`DfuMemory::capabilities()`].
+    Capabilities,
+}
+
+/// Maximum length of the reply of `DFU_UPLOAD` request with block number 1.
+#[cfg(feature = "upload")]
+const MAX_QUERY_REPLY_LENGTH: usize = match MAX_DEVICE_INFO_LENGTH > MAX_PAYLOAD_LENGTH {
+    true => MAX_DEVICE_INFO_LENGTH,
+    false => MAX_PAYLOAD_LENGTH,
+};
+
+/// Reply of `DFU_UPLOAD` request with block number 1 and its length.
+#[cfg(feature = "upload")]
+pub(crate) type QueryReply = ([u8; MAX_QUERY_REPLY_LENGTH], usize);
+
+/// Returns the reply of `DFU_UPLOAD` request with block number 1,
+/// or `None` if it can't be encoded.
+#[cfg(feature = "upload")]
+pub(crate) fn query_reply<M: DfuMemory>(mem: &mut M, query: Query) -> Option<QueryReply> {
+    let mut reply = [0; MAX_QUERY_REPLY_LENGTH];
+    let len = match query {
+        Query::Resume => match mem.resume_point() {
+            Some(address) => {
+                reply[..4].copy_from_slice(&address.to_le_bytes());
+                4
+            }
+            None => 0,
+        },
+        Query::ReadoutProtection => {
+            reply[0] = mem.readout_protection() as u8;
+            1
+        }
+        Query::DeviceInfo => mem.device_info().encode(&mut reply)?,
+        Query::Capabilities => {
+            let mut w = TlvWriter::new(&mut reply[..MAX_PAYLOAD_LENGTH]);
+            mem.capabilities(&mut w).ok()?;
+            w.len()
+        }
+    };
+    Some((reply, len))
+}
+
+/// Source of `DFU_UPLOAD` data.
+#[cfg(feature = "upload")]
+enum UploadSource {
+    /// Supported DfuSe commands.
+    Commands(&'static [u8]),
+    /// Memory block to read, `last` if it ends at the end of the region.
+    Memory {
+        address: u32,
+        length: usize,
+        last: bool,
+    },
+    /// The previous block was the end of the memory.
+    End,
+}
+
+/// Optional commands, in *Get Commands* reply order.
+#[cfg(feature = "upload")]
+const OPTIONAL_COMMANDS: [DownloadCommand; 12] = [
+    DownloadCommand::ReadUnprotect,
+    DownloadCommand::SetImageCrc,
+    DownloadCommand::Resume,
+    DownloadCommand::EraseRange,
+    DownloadCommand::UnlockMassErase,
+    DownloadCommand::SetImageSize,
+    DownloadCommand::Unlock,
+    DownloadCommand::ReadoutProtection,
+    DownloadCommand::GetDeviceInfo,
+    DownloadCommand::SetParameters,
+    DownloadCommand::GetCapabilities,
+    DownloadCommand::Verify,
+];
+
+/// *Get Commands* reply and its length, `enabled[n]` enables `OPTIONAL_COMMANDS[n]`.
+#[cfg(feature = "upload")]
+type CommandList = ([u8; 3 + OPTIONAL_COMMANDS.len()], usize);
+
+#[cfg(feature = "upload")]
+const fn command_list(enabled: [bool; OPTIONAL_COMMANDS.len()]) -> CommandList {
+    let mut list = [0; 3 + OPTIONAL_COMMANDS.len()];
+    list[0] = DownloadCommand::GetCommands as u8;
+    list[1] = DownloadCommand::SetAddressPointer as u8;
+    list[2] = DownloadCommand::Erase as u8;
+    let mut len = 3;
+    let mut n = 0;
+    while n < OPTIONAL_COMMANDS.len() {
+        if enabled[n] {
+            list[len] = OPTIONAL_COMMANDS[n] as u8;
+            len += 1;
+        }
+        n += 1;
+    }
+    (list, len)
+}
+
+/// *Get Commands* reply of memory `M`, built at compile time,
+/// so only the replies of the used memory types are stored.
+#[cfg(feature = "upload")]
+struct Commands<M>(PhantomData<M>);
+
+#[cfg(feature = "upload")]
+impl<M: DfuMemory> Commands<M> {
+    const LIST: CommandList = command_list([
+        M::READOUT_PROTECTION_COMMANDS,
+        M::IMAGE_CRC_COMMAND,
+        M::RESUME_COMMAND,
+        M::ERASE_RANGE_COMMAND,
+        M::MASS_ERASE_GUARD,
+        M::IMAGE_SIZE_COMMAND,
+        M::UNLOCK_COMMAND,
+        M::READOUT_PROTECTION_COMMANDS,
+        M::DEVICE_INFO_COMMAND,
+        M::TLV_COMMANDS,
+        M::TLV_COMMANDS,
+        M::VERIFY_COMMAND,
+    ]);
+}
+
+/// [`DfuMemory`] constants used by the protocol state machine.
+///
+/// Read once from the memory type, so that [`DFUStatus`] is not generic
+/// and its code is not duplicated for each memory type.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+#[cfg_attr(not(feature = "download"), allow(dead_code))]
+pub(crate) struct MemoryConfig {
+    transfer_size: u16,
+    program_time_ms: u32,
+    erase_time_ms: u32,
+    full_erase_time_ms: u32,
+    manifestation_time_ms: u32,
+    manifestation_tolerant: bool,
+    command_queue_depth: usize,
+    lmdfu_prefix: bool,
+    image_crc_command: bool,
+    resume_command: bool,
+    image_size_command: bool,
+    min_image_size: u32,
+    #[cfg_attr(not(feature = "download"), allow(dead_code))]
+    empty_download: EmptyDownload,
+    #[cfg_attr(not(feature = "download"), allow(dead_code))]
+    block_sequence: BlockSequence,
+    erase_range_command: bool,
+    erase_page_size: u32,
+    mass_erase_guard: bool,
+    unlock_command: bool,
+    readout_protection_commands: bool,
+    device_info_command: bool,
+    tlv_commands: bool,
+    extended_status_request: bool,
+    /// *Get Commands* reply.
+    #[cfg(feature = "upload")]
+    commands: &'static CommandList,
+    mem_info: &'static str,
+    alt_settings: &'static [AltSetting],
+    enforce_permissions: bool,
+    validate_address_pointer: bool,
+    #[cfg_attr(not(feature = "download"), allow(dead_code))]
+    rebase_on_wrap: bool,
+    #[cfg_attr(not(feature = "download"), allow(dead_code))]
+    skip_identical: bool,
+    #[cfg_attr(not(feature = "download"), allow(dead_code))]
+    verify_command: bool,
+    #[cfg_attr(not(feature = "upload"), allow(dead_code))]
+    clamp_upload: bool,
+    #[cfg_attr(not(feature = "upload"), allow(dead_code))]
+    upload_end: UploadEnd,
+    strict: bool,
+    session_timeout_ms: u32,
+}
+
+impl MemoryConfig {
+    pub(crate) const fn new<M: DfuMemory>() -> Self {
+        Self {
+            transfer_size: M::TRANSFER_SIZE,
+            program_time_ms: M::PROGRAM_TIME_MS,
+            erase_time_ms: M::ERASE_TIME_MS,
+            full_erase_time_ms: M::FULL_ERASE_TIME_MS,
+            manifestation_time_ms: M::MANIFESTATION_TIME_MS,
+            manifestation_tolerant: M::MANIFESTATION_TOLERANT,
+            command_queue_depth: M::COMMAND_QUEUE_DEPTH,
+            lmdfu_prefix: M::LMDFU_PREFIX,
+            image_crc_command: M::IMAGE_CRC_COMMAND,
+            resume_command: M::RESUME_COMMAND,
+            image_size_command: M::IMAGE_SIZE_COMMAND,
+            min_image_size: M::MIN_IMAGE_SIZE,
+            empty_download: M::EMPTY_DOWNLOAD,
+            block_sequence: M::BLOCK_SEQUENCE,
+            erase_range_command: M::ERASE_RANGE_COMMAND,
+            erase_page_size: M::ERASE_PAGE_SIZE,
+            mass_erase_guard: M::MASS_ERASE_GUARD,
+            unlock_command: M::UNLOCK_COMMAND,
+            readout_protection_commands: M::READOUT_PROTECTION_COMMANDS,
+            device_info_command: M::DEVICE_INFO_COMMAND,
+            tlv_commands: M::TLV_COMMANDS,
+            extended_status_request: M::EXTENDED_STATUS_REQUEST,
+            #[cfg(feature = "upload")]
+            commands: &Commands::<M>::LIST,
+            mem_info: M::MEM_INFO_STRING,
+            alt_settings: M::ALT_SETTINGS,
+            enforce_permissions: M::ENFORCE_PERMISSIONS,
+            validate_address_pointer: M::VALIDATE_ADDRESS_POINTER,
+            rebase_on_wrap: M::REBASE_ON_WRAP,
+            skip_identical: M::SKIP_IDENTICAL,
+            verify_command: M::VERIFY_COMMAND,
+            clamp_upload: M::CLAMP_UPLOAD,
+            upload_end: M::UPLOAD_END,
+            strict: M::STRICT,
+            session_timeout_ms: M::SESSION_TIMEOUT_MS,
+        }
+    }
+
+    /// Erase time of `length` bytes, see [`DfuMemory::ERASE_RANGE_COMMAND`].
+    fn erase_range_time_ms(&self, length: u32) -> u32 {
+        length
+            .div_ceil(self.erase_page_size.max(1))
+            .saturating_mul(self.erase_time_ms)
+            .min(self.full_erase_time_ms)
+    }
+}
+
+/// Payload of *Unlock* or *Set Parameters* command, its value is not logged.
+#[cfg(feature = "download")]
+#[derive(Clone, Default)]
+struct CommandPayload(heapless::Vec<u8, MAX_PAYLOAD_LENGTH>);
+
+#[cfg(feature = "download")]
+impl CommandPayload {
+    /// Overwrite and drop the payload.
+    fn clear(&mut self) {
+        self.0.fill(0);
+        self.0.clear();
+    }
+}
+
+#[cfg(all(feature = "download", feature = "defmt-03"))]
+impl defmt::Format for CommandPayload {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "CommandPayload({})", self.0.len())
+    }
+}
+
+/// DFU protocol state machine, without access to the memory.
+///
+/// Memory reads and writes are done through the callbacks,
+/// erase, program and manifestation operations are returned by
+/// [`next_operation()`](DFUStatus::next_operation) and their results
+/// are reported back with [`complete()`](DFUStatus::complete).
+#[derive(Clone)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub(crate) struct DFUStatus {
+    config: MemoryConfig,
+    status: DfuStatusCode,
+    poll_timeout: u32,
+    state: DfuState,
+    address_pointer: u32,
+    command: CommandQueue,
+    pending: CommandQueue,
+    in_progress: Option<Operation>,
+    lmdfu: Option<ImageProgress>,
+    /// Image length announced with *Set Image Size* command.
+    image_size: Option<ImageProgress>,
+    image_crc: Option<u32>,
+    #[cfg(feature = "download")]
+    crc: Crc32,
+    download_start: bool,
+    /// Host issued `DFU_GETSTATUS` or `DFU_GETSTATE` request.
+    host_polled: bool,
+    /// Host issued a DfuSe command since the last USB reset.
+    dfuse: bool,
+    /// Time since the last request, see [`DfuMemory::SESSION_TIMEOUT_MS`].
+    idle_ms: u32,
+    /// Selected alternate setting of the DFU interface.
+    alt_setting: u8,
+    /// *Unlock Mass Erase* command was received.
+    #[cfg(feature = "download")]
+    mass_erase_unlocked: bool,
+    /// Downloads are unlocked, see [`DfuMemory::UNLOCK_COMMAND`].
+    #[cfg(feature = "download")]
+    unlocked: bool,
+    /// Payload of the queued *Unlock* or *Set Parameters* command.
+    #[cfg(feature = "download")]
+    payload: CommandPayload,
+    /// The last uploaded block was the end of the memory.
+    #[cfg(feature = "upload")]
+    upload_end: bool,
+    /// Reply of the next `DFU_UPLOAD` request with block number 1.
+    #[cfg(feature = "upload")]
+    query: Query,
+    memory_error: Option<MemoryErrorDetail>,
+    block_sizes: Option<BlockSizes>,
+    /// Block number and length of the last data block, if it was short.
+    #[cfg(feature = "download")]
+    short_block: Option<(u16, u16)>,
+    /// Number of bytes of data received since the start of the download.
+    #[cfg(feature = "download")]
+    image_received: u32,
+    /// Number of bytes programmed since the start of the download.
+    #[cfg(feature = "download")]
+    image_programmed: u32,
+    /// Blocks of the download are compared, see [`DfuMemory::VERIFY_COMMAND`].
+    #[cfg(feature = "download")]
+    verify: bool,
+    /// Block number and length of the last data block since the start of the download
+    /// or *Set Address Pointer* command.
+    #[cfg(feature = "download")]
+    last_block: Option<(u16, u16)>,
+    /// Short block that was followed by another data block.
+    block_size_mismatch: Option<(u16, u16)>,
+    #[cfg(feature = "trace")]
+    trace: Trace,
+    /// Request that is being handled, for the trace.
+    #[cfg(feature = "trace")]
+    request: Option<u8>,
+    #[cfg(feature = "stats")]
+    stats: DfuStats,
+}
+
+impl DFUStatus {
+    pub fn new(config: MemoryConfig, addr: u32) -> Self {
+        Self {
+            config,
+            status: DfuStatusCode::Ok,
+            poll_timeout: 0,
+            state: DfuState::DfuIdle,
+            address_pointer: addr,
+            command: CommandQueue::new(),
+            pending: CommandQueue::new(),
+            in_progress: None,
+            lmdfu: None,
+            image_size: None,
+            image_crc: None,
+            #[cfg(feature = "download")]
+            crc: Crc32::new(),
+            download_start: false,
+            host_polled: false,
+            dfuse: false,
+            idle_ms: 0,
+            alt_setting: 0,
+            #[cfg(feature = "download")]
+            mass_erase_unlocked: false,
+            #[cfg(feature = "download")]
+            unlocked: false,
+            #[cfg(feature = "download")]
+            payload: CommandPayload::default(),
+            #[cfg(feature = "upload")]
+            upload_end: false,
+            #[cfg(feature = "upload")]
+            query: Query::Resume,
+            memory_error: None,
+            block_sizes: None,
+            #[cfg(feature = "download")]
+            short_block: None,
+            #[cfg(feature = "download")]
+            image_received: 0,
+            #[cfg(feature = "download")]
+            image_programmed: 0,
+            #[cfg(feature = "download")]
+            verify: false,
+            #[cfg(feature = "download")]
+            last_block: None,
+            block_size_mismatch: None,
+            #[cfg(feature = "trace")]
+            trace: Trace::new(),
+            #[cfg(feature = "trace")]
+            request: None,
+            #[cfg(feature = "stats")]
+            stats: DfuStats::new(),
+        }
+    }
+
+    fn new_state_ok(&mut self, state: DfuState) {
+        self.new_state_status(state, DfuStatusCode::Ok);
+    }
+
+    fn new_state_status(&mut self, state: DfuState, status: DfuStatusCode) {
+        // the first error is kept until DFU_CLRSTATUS
+        let status = match (self.state, state) {
+            (DfuState::DfuError, DfuState::DfuError) if !self.config.strict => self.status,
+            _ => status,
+        };
+        if state != self.state || status != self.status {
+            debug!("DFU state {} -> {}, status {}", self.state, state, status);
+            #[cfg(feature = "trace")]
+            self.trace.record(Transition {
+                request: self.request,
+                old_state: self.state,
+                new_state: state,
+                status,
+            });
+            #[cfg(feature = "stats")]
+            self.stats.error(status);
+        }
+        self.status = status;
+        self.state = state;
+    }
+
+    fn state(&self) -> DfuState {
+        self.state
+    }
+
+    /// Set *bRequest* of the request that is being handled,
+    /// `None` for USB reset and memory operations.
+    fn begin(&mut self, request: Option<u8>) {
+        if request.is_some() {
+            self.idle_ms = 0;
+        }
+        #[cfg(feature = "trace")]
+        {
+            self.request = request;
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    pub(crate) fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    #[cfg(feature = "trace")]
+    pub(crate) fn clear_trace(&mut self) {
+        self.trace.clear();
+    }
+
+    #[cfg(feature = "stats")]
+    pub(crate) fn stats(&self) -> &DfuStats {
+        &self.stats
+    }
+
+    #[cfg(feature = "stats")]
+    pub(crate) fn reset_stats(&mut self) {
+        self.stats = DfuStats::new();
+    }
+
+    fn clear_commands(&mut self) {
+        self.command.clear();
+        self.pending.clear();
+        self.lmdfu = None;
+        self.image_size = None;
+        self.image_crc = None;
+        self.download_start = false;
+        #[cfg(feature = "download")]
+        {
+            self.mass_erase_unlocked = false;
+            self.payload.clear();
+        }
+    }
+
+    pub(crate) fn address_pointer(&self) -> u32 {
+        self.address_pointer
+    }
+
+    pub(crate) fn set_address_pointer(&mut self, address: u32) -> Result<(), DfuStatusCode> {
+        if self.state() != DfuState::DfuIdle {
+            return Err(DfuStatusCode::ErrNotdone);
+        }
+        if !self.valid_address_pointer(address) {
+            return Err(DfuStatusCode::ErrAddress);
+        }
+        self.address_pointer = address;
+        Ok(())
+    }
+
+    pub(crate) fn last_memory_error(&self) -> Option<MemoryErrorDetail> {
+        self.memory_error
+    }
+
+    pub(crate) fn download_block_sizes(&self) -> Option<BlockSizes> {
+        self.block_sizes
+    }
+
+    pub(crate) fn download_progress(&self) -> Option<u8> {
+        self.image_size
+            .or(self.lmdfu)
+            .map(|p| (p.received as u64 * 100 / p.length.max(1) as u64) as u8)
+    }
+
+    pub(crate) fn extended_status(&self) -> ExtendedStatus {
+        ExtendedStatus {
+            status: self.status,
+            state: self.state,
+            alt_setting: self.alt_setting,
+            #[cfg(feature = "download")]
+            programmed: self.image_programmed,
+            #[cfg(not(feature = "download"))]
+            programmed: 0,
+            total: self.image_size.or(self.lmdfu).map(|p| p.length),
+            last_error: self.memory_error,
+        }
+    }
+
+    /// Returns `true` if `req` is vendor-specific *Get Extended Status* request to the
+    /// interface `if_num`, and it's enabled, see [`DfuMemory::EXTENDED_STATUS_REQUEST`].
+    pub(crate) fn is_extended_status_request(
+        &self,
+        req: &Request,
+        if_num: InterfaceNumber,
+    ) -> bool {
+        self.config.extended_status_request
+            && req.request_type == control::RequestType::Vendor
+            && req.recipient == control::Recipient::Interface
+            && req.request == VENDOR_GET_EXTENDED_STATUS
+            && req.index == u8::from(if_num) as u16
+    }
+
+    /// Handle vendor-specific *Get Extended Status* request, the state is not changed.
+    ///
+    /// Returns `None` if request must be rejected.
+    pub(crate) fn get_extended_status(
+        &self,
+        req: &Request,
+    ) -> Option<[u8; EXTENDED_STATUS_LENGTH]> {
+        let valid = req.value == 0 && req.length as usize >= EXTENDED_STATUS_LENGTH;
+        valid.then(|| self.extended_status().encode())
+    }
+
+    pub(crate) fn alt_setting(&self) -> u8 {
+        self.alt_setting
+    }
+
+    /// Select alternate setting `alt`, returns `false` if a transfer is in progress.
+    pub(crate) fn set_alt_setting(&mut self, alt: u8) -> bool {
+        let idle = matches!(self.state(), DfuState::DfuIdle | DfuState::DfuError);
+        if alt != self.alt_setting && !idle {
+            debug!("DFU alternate setting {} rejected in {}", alt, self.state);
+            return false;
+        }
+        self.alt_setting = alt;
+        true
+    }
+
+    /// Returns the selected entry of [`DfuMemory::ALT_SETTINGS`].
+    fn alt(&self) -> Option<&'static AltSetting> {
+        self.config.alt_settings.get(self.alt_setting as usize)
+    }
+
+    /// Returns the memory map of the selected alternate setting.
+    fn mem_info(&self) -> &'static str {
+        self.alt().map_or(self.config.mem_info, |alt| alt.name)
+    }
+
+    pub(crate) fn set_unexpected_reset_state(&mut self) {
+        self.begin(None);
+        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrPOR);
+    }
+
+    pub(crate) fn set_firmware_corrupted_state(&mut self) {
+        self.begin(None);
+        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrFirmware);
+    }
+
+    pub(crate) fn usb_reset(&mut self) {
+        debug!("DFU USB reset in {}", self.state);
+        self.begin(None);
+        self.dfuse = false;
+        self.alt_setting = 0;
+        // commands of the host before the reset are not executed
+        self.clear_commands();
+        #[cfg(feature = "upload")]
+        {
+            self.query = Query::Resume;
+        }
+        #[cfg(feature = "download")]
+        {
+            self.unlocked = false;
+        }
+        // Try to signal possible error to a host.
+        // Not exactly clear what status should be.
+        match self.state() {
+            DfuState::DfuUploadIdle
+            | DfuState::DfuDnloadIdle
+            | DfuState::DfuDnloadSync
+            | DfuState::DfuDnBusy
+            | DfuState::DfuError
+            | DfuState::DfuManifest
+            | DfuState::DfuManifestSync => {
+                self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrUsbr);
+            }
+            DfuState::DfuIdle
+            | DfuState::AppDetach
+            | DfuState::AppIdle
+            | DfuState::DfuManifestWaitReset => {}
+        }
+    }
+
+    /// Advance the session timer by `elapsed_ms`, see [`DfuMemory::SESSION_TIMEOUT_MS`].
+    ///
+    /// Returns `true` if the session was cancelled, and [`DfuMemory::session_timeout()`]
+    /// must be called.
+    pub(crate) fn tick(&mut self, elapsed_ms: u32) -> bool {
+        let waiting = matches!(
+            self.state(),
+            DfuState::DfuUploadIdle
+                | DfuState::DfuDnloadIdle
+                | DfuState::DfuDnloadSync
+                | DfuState::DfuDnBusy
+                | DfuState::DfuManifestSync
+                | DfuState::DfuManifest
+        );
+        // an operation in progress is not interrupted
+        if self.config.session_timeout_ms == 0 || !waiting || self.in_progress.is_some() {
+            self.idle_ms = 0;
+            return false;
+        }
+
+        self.idle_ms = self.idle_ms.saturating_add(elapsed_ms);
+        if self.idle_ms < self.config.session_timeout_ms {
+            return false;
+        }
+
+        debug!("DFU session timeout in {}", self.state);
+        self.begin(None);
+        self.idle_ms = 0;
+        self.clear_commands();
+        if self.state() == DfuState::DfuUploadIdle {
+            self.new_state_ok(DfuState::DfuIdle);
+        } else {
+            self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrNotdone);
+        }
+        true
+    }
+
+    /// Cancel the session and go to `dfuIDLE`, see [`DfuClass::force_idle()`].
+    ///
+    /// An operation in progress is not interrupted, its result is dropped by
+    /// [`complete()`](DFUStatus::complete). Returns `true` if a session was cancelled,
+    /// and [`DfuMemory::session_timeout()`] must be called.
+    pub(crate) fn force_idle(&mut self) -> bool {
+        let idle = self.state() == DfuState::DfuIdle && !self.is_busy();
+        debug!("DFU forced idle in {}", self.state);
+        self.begin(None);
+        self.idle_ms = 0;
+        self.clear_commands();
+        #[cfg(feature = "upload")]
+        {
+            self.query = Query::Resume;
+        }
+        self.new_state_ok(DfuState::DfuIdle);
+        !idle
+    }
+
+    /// Set error state after a request that is not supported.
+    pub(crate) fn stall(&mut self) {
+        debug!("DFU request stalled in {}", self.state);
+        self.begin(None);
+        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+    }
+
+    /// Returns `false` if request must be rejected.
+    pub(crate) fn clear_status(&mut self) -> bool {
+        debug!("DFU_CLRSTATUS");
+        self.begin(Some(DFU_CLRSTATUS));
+        match self.state() {
+            DfuState::DfuError => {
+                self.clear_commands();
+                self.new_state_ok(DfuState::DfuIdle);
+                true
+            }
+            _ => {
+                self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+                false
+            }
+        }
+    }
+
+    /// Returns `false` if request must be rejected.
+    pub(crate) fn abort(&mut self) -> bool {
+        debug!("DFU_ABORT");
+        self.begin(Some(DFU_ABORT));
+        match self.state() {
+            DfuState::DfuIdle
+            | DfuState::DfuUploadIdle
+            | DfuState::DfuDnloadIdle
+            | DfuState::DfuDnloadSync
+            | DfuState::DfuManifestSync => {
+                self.clear_commands();
+                self.new_state_ok(DfuState::DfuIdle);
+                true
+            }
+            DfuState::AppDetach
+            | DfuState::AppIdle
+            | DfuState::DfuDnBusy
+            | DfuState::DfuManifest
+            | DfuState::DfuManifestWaitReset
+            | DfuState::DfuError => false,
+        }
+    }
+
+    /// Track data block sizes, see [`DfuMemory::block_size_mismatch()`].
+    #[cfg(feature = "download")]
+    fn check_block_size(&mut self, block_num: u16, length: u16) {
+        self.block_sizes = Some(match self.block_sizes {
+            Some(s) => BlockSizes {
+                min: min(s.min, length),
+                max: s.max.max(length),
+            },
+            None => BlockSizes {
+                min: length,
+                max: length,
+            },
+        });
+
+        // the same block can be sent again
+        if let Some(short) = self.short_block.take().filter(|s| s.0 != block_num) {
+            debug!("DFU short block {} of {} bytes", short.0, short.1);
+            self.block_size_mismatch = Some(short);
+        }
+        if length < self.config.transfer_size {
+            self.short_block = Some((block_num, length));
+        }
+    }
+
+    /// Returns `true` if one more download command can be queued
+    /// while the device is in `dfuDNLOAD-SYNC` state.
+    #[cfg(feature = "download")]
+    fn can_queue_command(&self) -> bool {
+        let depth = min(self.config.command_queue_depth, MAX_COMMAND_QUEUE_DEPTH);
+        depth > 1 && self.state() == DfuState::DfuDnloadSync && self.command.len() < depth
+    }
+
+    #[cfg(feature = "download")]
+    fn queue_command(&mut self, command: Command) {
+        debug!("DFU command {:?}", command);
+        // room in the queue is checked by the caller
+        self.command.push_back(command).ok();
+        self.new_state_ok(DfuState::DfuDnloadSync);
+    }
+
+    /// Handle `DFU_DNLOAD` request. `store` is called to save the
+    /// received data block.
+    ///
+    /// Returns `false` if request must be rejected.
+    #[cfg(feature = "download")]
+    pub(crate) fn download(
+        &mut self,
+        req: &Request,
+        data: &[u8],
+        store: &mut dyn FnMut(&[u8]) -> Result<(), ()>,
+    ) -> bool {
+        debug!("DFU_DNLOAD block {} length {}", req.value, req.length);
+        self.begin(Some(DFU_DNLOAD));
+
+        if data.len() != req.length as usize {
+            // truncated data stage, commands below index the data
+            self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+            return false;
+        }
+        let initial_state = self.state();
+        let queued = self.can_queue_command();
+
+        if initial_state != DfuState::DfuIdle && initial_state != DfuState::DfuDnloadIdle && !queued
+        {
+            self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+            return false;
+        }
+
+        let set_address =
+            req.value == 0 && data.first() == Some(&(DownloadCommand::SetAddressPointer as u8));
+        if self.alt().is_some_and(|alt| !alt.download) && !set_address {
+            debug!(
+                "DFU_DNLOAD in read-only alternate setting {}",
+                self.alt_setting
+            );
+            self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+            return false;
+        }
+
+        let unlock = req.value == 0 && data.first() == Some(&(DownloadCommand::Unlock as u8));
+        if self.config.unlock_command && !self.unlocked && !unlock {
+            debug!("DFU_DNLOAD is locked");
+            self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrVendor);
+            return false;
+        }
+
+        if initial_state == DfuState::DfuIdle {
+            // a new download
+            self.image_crc = None;
+            self.image_size = None;
+            self.crc = Crc32::new();
+            self.download_start = !data.is_empty();
+            self.block_sizes = None;
+            self.short_block = None;
+            self.image_received = 0;
+            self.image_programmed = 0;
+            self.last_block = None;
+            self.verify = false;
+            #[cfg(feature = "stats")]
+            stats::add(&mut self.stats.downloads, 1);
+        }
+
+        if data.is_empty() {
+            if !queued && initial_state == DfuState::DfuIdle {
+                match self.config.empty_download {
+                    EmptyDownload::Manifest => {}
+                    EmptyDownload::Stall => {
+                        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+                        return false;
+                    }
+                    EmptyDownload::Leave => {
+                        self.command
+                            .push_back(Command::LeaveDfu { image: false })
+                            .ok();
+                        self.new_state_ok(DfuState::DfuManifestSync);
+                        return true;
+                    }
+                }
+            }
+            if !queued {
+                if let Some(expected) = self.image_crc.take() {
+                    if !self.crc.finalize() != expected {
+                        self.lmdfu = None;
+                        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrVerify);
+                        return true;
+                    }
+                }
+                let lmdfu = self.lmdfu.take();
+                let image_size = self.image_size.take();
+                if [lmdfu, image_size]
+                    .iter()
+                    .flatten()
+                    .any(|p| p.received != p.length)
+                    || self.image_received < self.config.min_image_size
+                {
+                    // image is shorter than announced or than the minimum
+                    self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrNotdone);
+                    return true;
+                }
+                if !self.verify {
+                    self.command
+                        .push_back(Command::LeaveDfu { image: true })
+                        .ok();
+                }
+                self.new_state_ok(DfuState::DfuManifestSync);
+                return true;
+            }
+        } else if req.value > 1 {
+            let block_num = req.value - 2;
+            let write_queued = self
+                .command
+                .iter()
+                .any(|c| matches!(c, Command::WriteMemory { .. }));
+
+            // write buffer is in use until the queued block is programmed
+            if !data.is_empty() && !write_queued {
+                let previous = self.last_block.map(|(b, _)| b);
+                let duplicate = self.last_block == Some((block_num, req.length));
+                let next = previous.is_none_or(|b| (b + 1) % WRAP_BLOCKS == block_num);
+                match self.config.block_sequence {
+                    BlockSequence::PassThrough => {}
+                    _ if duplicate => {
+                        debug!("DFU duplicate block {}", block_num);
+                        self.new_state_ok(DfuState::DfuDnloadSync);
+                        return true;
+                    }
+                    BlockSequence::Strict if !next => {
+                        debug!("DFU block {} after block {:?}", block_num, previous);
+                        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrAddress);
+                        return false;
+                    }
+                    _ => {}
+                }
+
+                self.check_block_size(block_num, req.length);
+
+                let mut data = data;
+                let mut skip = 0;
+                let rebase = self.config.rebase_on_wrap
+                    && previous == Some(WRAP_BLOCKS - 1)
+                    && block_num == 0;
+
+                if self.config.lmdfu_prefix && block_num == 0 && !rebase {
+                    self.lmdfu = None;
+                    if let (Ok(prefix), Some(image)) =
+                        (LmdfuPrefix::try_from(data), data.get(LMDFU_PREFIX_LENGTH..))
+                    {
+                        self.address_pointer = prefix.address;
+                        self.lmdfu = Some(ImageProgress {
+                            length: prefix.length,
+                            received: 0,
+                        });
+                        data = image;
+                        skip = LMDFU_PREFIX_LENGTH as u16;
+                    }
+                }
+
+                for progress in [&mut self.lmdfu, &mut self.image_size]
+                    .into_iter()
+                    .flatten()
+                {
+                    progress.received = progress.received.saturating_add(data.len() as u32);
+                }
+                self.image_received = self.image_received.saturating_add(data.len() as u32);
+                if [self.lmdfu, self.image_size]
+                    .iter()
+                    .flatten()
+                    .any(|p| p.received > p.length)
+                {
+                    // image is longer than announced
+                    self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrAddress);
+                    return false;
+                }
+
+                if data.is_empty() {
+                    // block contained only the prefix
+                    self.new_state_ok(DfuState::DfuDnloadSync);
+                    return true;
+                }
+
+                if self.config.image_crc_command {
+                    self.crc.update(data);
+                }
+
+                // store the whole buffer, chunked operation in not supported
+                match store(data) {
+                    Err(_) => {
+                        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+                        return false;
+                    }
+                    Ok(_) => {
+                        #[cfg(feature = "stats")]
+                        if previous == Some(block_num) {
+                            stats::add(&mut self.stats.retries, 1);
+                        }
+                        self.last_block = Some((block_num, req.length));
+                        self.queue_command(Command::WriteMemory {
+                            block_num,
+                            len: data.len() as u16,
+                            skip,
+                            rebase,
+                            crc: (self.config.skip_identical || self.verify).then(|| {
+                                let mut crc = Crc32::new();
+                                crc.update(data);
+                                crc.finalize()
+                            }),
+                        });
+                        return true;
+                    }
+                }
+            }
+        } else if let (0, Some((&command, args))) = (req.value, data.split_first()) {
+            // 32-bit little-endian argument of a command
+            let arg = <[u8; 4]>::try_from(args).ok().map(u32::from_le_bytes);
+
+            if command == DownloadCommand::SetAddressPointer as u8 {
+                if let Some(addr) = arg {
+                    self.dfuse = true;
+                    self.last_block = None;
+                    self.queue_command(Command::SetAddressPointer(addr));
+                    return true;
+                }
+            } else if command == DownloadCommand::Erase as u8 {
+                if let Some(addr) = arg {
+                    self.dfuse = true;
+                    self.queue_command(Command::Erase(addr));
+                    return true;
+                } else if args.is_empty() {
+                    self.dfuse = true;
+                    let unlocked = core::mem::take(&mut self.mass_erase_unlocked);
+                    self.queue_command(Command::EraseAll { unlocked });
+                    return true;
+                }
+            } else if self.config.image_crc_command && command == DownloadCommand::SetImageCrc as u8
+            {
+                if let (Some(crc), false) = (arg, queued) {
+                    self.image_crc = Some(crc);
+                    self.new_state_ok(DfuState::DfuDnloadSync);
+                    return true;
+                }
+            } else if self.config.image_size_command
+                && command == DownloadCommand::SetImageSize as u8
+            {
+                if let (Some(length @ 1..), false) = (arg, queued) {
+                    self.image_size = Some(ImageProgress {
+                        length,
+                        received: 0,
+                    });
+                    if self.config.erase_range_command {
+                        self.queue_command(Command::EraseImage { length });
+                    } else {
+                        self.new_state_ok(DfuState::DfuDnloadSync);
+                    }
+                    return true;
+                }
+            } else if self.config.resume_command && command == DownloadCommand::Resume as u8 {
+                if let (Some(addr), DfuState::DfuIdle) = (arg, initial_state) {
+                    // continue the interrupted download
+                    self.download_start = false;
+                    self.queue_command(Command::SetAddressPointer(addr));
+                    return true;
+                }
+            } else if self.config.erase_range_command
+                && command == DownloadCommand::EraseRange as u8
+            {
+                if let Ok(range) = <[u8; 8]>::try_from(args) {
+                    let address = u32::from_le_bytes([range[0], range[1], range[2], range[3]]);
+                    let length = u32::from_le_bytes([range[4], range[5], range[6], range[7]]);
+                    if length > 0 && address.checked_add(length - 1).is_some() {
+                        self.queue_command(Command::EraseRange { address, length });
+                        return true;
+                    }
+                }
+            } else if self.config.mass_erase_guard
+                && command == DownloadCommand::UnlockMassErase as u8
+            {
+                if args.is_empty() {
+                    self.mass_erase_unlocked = true;
+                    self.new_state_ok(DfuState::DfuDnloadSync);
+                    return true;
+                }
+            } else if self.config.unlock_command && command == DownloadCommand::Unlock as u8 {
+                let valid = (1..=MAX_UNLOCK_TOKEN_LENGTH).contains(&args.len());
+                // only one payload is queued
+                if valid && !self.unlocked && self.payload.0.is_empty() {
+                    self.payload.0.extend_from_slice(args).ok();
+                    self.queue_command(Command::Unlock);
+                    return true;
+                }
+            } else if self.config.readout_protection_commands
+                && command == DownloadCommand::ReadUnprotect as u8
+            {
+                if args.is_empty() {
+                    self.dfuse = true;
+                    let level = ReadoutProtection::Level0;
+                    self.queue_command(Command::SetReadoutProtection(level));
+                    return true;
+                }
+            } else if self.config.readout_protection_commands
+                && command == DownloadCommand::ReadoutProtection as u8
+            {
+                match *args {
+                    #[cfg(feature = "upload")]
+                    [] if !queued => {
+                        self.query = Query::ReadoutProtection;
+                        self.new_state_ok(DfuState::DfuDnloadSync);
+                        return true;
+                    }
+                    [level] => {
+                        if let Ok(level) = ReadoutProtection::try_from(level) {
+                            self.queue_command(Command::SetReadoutProtection(level));
+                            return true;
+                        }
+                    }
+                    _ => {}
+                }
+            } else if self.config.device_info_command
+                && command == DownloadCommand::GetDeviceInfo as u8
+            {
+                #[cfg(feature = "upload")]
+                if let ([], false) = (args, queued) {
+                    self.query = Query::DeviceInfo;
+                    self.new_state_ok(DfuState::DfuDnloadSync);
+                    return true;
+                }
+            } else if self.config.tlv_commands && command == DownloadCommand::SetParameters as u8 {
+                let valid = args.len() <= MAX_PAYLOAD_LENGTH && TlvReader::new(args).is_valid();
+                if valid && self.payload.0.is_empty() {
+                    self.payload.0.extend_from_slice(args).ok();
+                    self.queue_command(Command::SetParameters);
+                    return true;
+                }
+            } else if self.config.tlv_commands && command == DownloadCommand::GetCapabilities as u8
+            {
+                #[cfg(feature = "upload")]
+                if let ([], false) = (args, queued) {
+                    self.query = Query::Capabilities;
+                    self.new_state_ok(DfuState::DfuDnloadSync);
+                    return true;
+                }
+            } else if self.config.verify_command && command == DownloadCommand::Verify as u8 {
+                // the first command of a download, the memory is not modified
+                if let ([], DfuState::DfuIdle) = (args, initial_state) {
+                    self.verify = true;
+                    self.download_start = false;
+                    self.new_state_ok(DfuState::DfuDnloadSync);
+                    return true;
+                }
+            }
+        }
+
+        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+        false
+    }
+
+    /// Handle `DFU_UPLOAD` request. `read` is called to get memory contents.
+    ///
+    /// Returns `None` if request must be rejected.
+    #[cfg(feature = "upload")]
+    pub(crate) fn upload<'d>(
+        &mut self,
+        req: &Request,
+        read: impl FnOnce(u32, usize) -> Result<ReadOutcome<'d>, DfuMemoryError>,
+    ) -> Option<&'d [u8]> {
+        match self.upload_source(req)? {
+            UploadSource::Commands(commands) => Some(commands),
+            UploadSource::End => Some(&[]),
+            UploadSource::Memory {
+                address,
+                length,
+                last,
+            } => match read(address, length) {
+                Ok(b) => {
+                    let data = &b.data()[..min(b.data().len(), length)];
+                    self.upload_complete(data.len(), b.is_last() || last);
+                    Some(data)
+                }
+                Err(e) => {
+                    self.memory_error = Some(MemoryErrorDetail {
+                        error: e,
+                        address: Some(address),
+                        block: Some(req.value - 2),
+                    });
+                    self.new_state_status(DfuState::DfuError, e.into());
+                    None
+                }
+            },
+        }
+    }
+
+    /// Returns what must be sent to the host for `DFU_UPLOAD` request.
+    ///
+    /// Returns `None` if request must be rejected.
+    #[cfg(feature = "upload")]
+    fn upload_source(&mut self, req: &Request) -> Option<UploadSource> {
+        debug!("DFU_UPLOAD block {} length {}", req.value, req.length);
+        self.begin(Some(DFU_UPLOAD));
+        let initial_state = self.state();
+
+        if initial_state != DfuState::DfuIdle && initial_state != DfuState::DfuUploadIdle {
+            self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+            return None;
+        }
+
+        if req.value == 0 {
+            // Get command
+            self.dfuse = true;
+            let (list, len) = self.config.commands;
+            let commands: &'static [u8] = &list[..*len];
+
+            if req.length as usize >= commands.len() {
+                self.new_state_ok(DfuState::DfuIdle);
+                return Some(UploadSource::Commands(commands));
+            }
+        } else if req.value > 1 && initial_state == DfuState::DfuUploadIdle && self.upload_end {
+            // after the last full-length block
+            return self.upload_past_end();
+        } else if req.value > 1 {
+            // upload command
+            if self.alt().is_some_and(|alt| !alt.upload) {
+                debug!(
+                    "DFU_UPLOAD in write-only alternate setting {}",
+                    self.alt_setting
+                );
+                self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+                return None;
+            }
+            let block_num = req.value - 2;
+            let transfer_size = min(self.config.transfer_size, req.length);
+
+            if let Some(address) = self.block_address(block_num, 0) {
+                let mut length = transfer_size as usize;
+                let mut last = false;
+                if self.config.clamp_upload {
+                    match mem_info::region_end(self.mem_info(), address) {
+                        Some(end) => {
+                            let remaining = end - address as u64;
+                            if remaining <= length as u64 {
+                                length = remaining as usize;
+                                last = true;
+                            }
+                        }
+                        None => {
+                            // not in the memory
+                            return self.upload_past_end();
+                        }
+                    }
+                }
+                let readable = !self.config.enforce_permissions
+                    || mem_info::areas(self.mem_info())
+                        .filter(|a| a.overlaps(address, length as u32))
+                        .all(|a| a.operations.contains(Operations::READ));
+                if !readable {
+                    self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrAddress);
+                    return None;
+                }
+                #[cfg(feature = "stats")]
+                if initial_state == DfuState::DfuIdle {
+                    stats::add(&mut self.stats.uploads, 1);
+                }
+                return Some(UploadSource::Memory {
+                    address,
+                    length,
+                    last,
+                });
+            } else {
+                // overflow
+                self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrAddress);
+                return None;
+            }
+        }
+
+        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+        None
+    }
+
+    /// Update the state after `length` bytes were read for `DFU_UPLOAD` request,
+    /// `last` is `true` if it's the end of the memory.
+    #[cfg(feature = "upload")]
+    fn upload_complete(&mut self, length: usize, last: bool) {
+        let short = length < self.config.transfer_size as usize;
+        let end = short || (last && self.config.upload_end == UploadEnd::LastBlock);
+        self.upload_end = last && !end;
+        #[cfg(feature = "stats")]
+        stats::add(&mut self.stats.bytes_uploaded, length);
+        if end {
+            // short frame or the last block, back to idle
+            self.new_state_ok(DfuState::DfuIdle);
+        } else {
+            self.new_state_ok(DfuState::DfuUploadIdle);
+        }
+    }
+
+    /// Handle `DFU_UPLOAD` request after the end of the memory, see [`DfuMemory::UPLOAD_END`].
+    #[cfg(feature = "upload")]
+    fn upload_past_end(&mut self) -> Option<UploadSource> {
+        if self.config.upload_end == UploadEnd::Error {
+            self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrAddress);
+            return None;
+        }
+        // terminating short frame
+        self.new_state_ok(DfuState::DfuIdle);
+        Some(UploadSource::End)
+    }
+
+    /// Handle `DFU_UPLOAD` request with block number 1,
+    /// `reply` is called to get the selected reply, see [`query_reply()`].
+    ///
+    /// Returns `None` if request must be rejected.
+    #[cfg(feature = "upload")]
+    pub(crate) fn query(
+        &mut self,
+        req: &Request,
+        reply: impl FnOnce(Query) -> Option<QueryReply>,
+    ) -> Option<QueryReply> {
+        self.begin(Some(DFU_UPLOAD));
+        let query = core::mem::replace(&mut self.query, Query::Resume);
+        let enabled = match query {
+            Query::Resume => self.config.resume_command,
+            Query::ReadoutProtection | Query::DeviceInfo | Query::Capabilities => true,
+        };
+        if enabled && self.state() == DfuState::DfuIdle {
+            match reply(query) {
+                Some((data, len)) if len <= req.length as usize => return Some((data, len)),
+                Some(_) => {}
+                None => {
+                    self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrUnknown);
+                    return None;
+                }
+            }
+        }
+
+        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+        None
+    }
+
+    /// Handle `DFU_GETSTATE` request.
+    ///
+    /// Returns `None` if request must be rejected.
+    pub(crate) fn get_state(&mut self, req: &Request) -> Option<u8> {
+        self.begin(Some(DFU_GETSTATE));
+        self.host_polled = true;
+        // return current state, without any state transition
+        if req.length > 0 {
+            Some(self.state() as u8)
+        } else {
+            self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+            None
+        }
+    }
+
+    /// Handle `DFU_GETSTATUS` request.
+    ///
+    /// Returns `None` if request must be rejected.
+    pub(crate) fn get_status(&mut self, req: &Request) -> Option<[u8; 6]> {
+        self.begin(Some(DFU_GETSTATUS));
+        self.host_polled = true;
+        if req.length >= 6 && self.process() {
+            self.poll_timeout = self.expected_timeout();
+            return Some((&*self).into());
+        }
+
+        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
+        None
+    }
+
+    fn expected_timeout(&self) -> u32 {
+        self.in_progress
+            .iter()
+            .map(|op| op.timeout(&self.config))
+            .chain(self.pending.iter().map(|command| match command {
+                // blocks are compared, erase commands are skipped
+                _ if self.verifying() => 0,
+                Command::WriteMemory { .. } => self.config.program_time_ms,
+                Command::EraseAll { .. } | Command::SetReadoutProtection(_) => {
+                    self.config.full_erase_time_ms
+                }
+                Command::Erase(_) => self.config.erase_time_ms,
+                Command::EraseRange { length, .. } | Command::EraseImage { length } => {
+                    self.config.erase_range_time_ms(*length)
+                }
+                Command::LeaveDfu { .. } => self.config.manifestation_time_ms,
+                _ => 0,
+            }))
+            .fold(0, u32::saturating_add)
+    }
+
+    /// Returns the address of `offset` bytes into block `block_num`,
+    /// or `None` on overflow.
+    fn block_address(&self, block_num: u16, offset: u16) -> Option<u32> {
+        (block_num as u32)
+            .checked_mul(self.config.transfer_size as u32)
+            .and_then(|o| o.checked_add(offset as u32))
+            .and_then(|o| self.address_pointer.checked_add(o))
+    }
+
+    /// Returns `true` if there are queued operations, or an operation
+    /// is not completed yet.
+    pub(crate) fn is_busy(&self) -> bool {
+        self.in_progress.is_some() || !self.pending.is_empty()
+    }
+
+    /// Take the next memory operation that must be executed.
+    ///
+    /// Commands that do not need memory access are handled here.
+    /// [`complete()`](DFUStatus::complete) must be called with the result
+    /// before the next operation can be taken.
+    pub(crate) fn next_operation(&mut self) -> Option<Operation> {
+        if self.in_progress.is_some() {
+            return None;
+        }
+        self.begin(None);
+
+        while let Some(command) = self.pending.pop_front() {
+            let op = match command {
+                Command::EraseAll { unlocked } => Operation::EraseAll { unlocked },
+                Command::Erase(b) => Operation::Erase(b),
+                Command::EraseRange { address, length } => {
+                    Operation::EraseRange { address, length }
+                }
+                Command::EraseImage { length } => Operation::EraseRange {
+                    address: self.address_pointer,
+                    length,
+                },
+                Command::LeaveDfu { image } => Operation::Manifestation {
+                    address: self.address_pointer,
+                    image,
+                },
+                Command::SetReadoutProtection(level) => Operation::SetReadoutProtection(level),
+                Command::Unlock => Operation::Unlock,
+                Command::SetParameters => Operation::SetParameters,
+                Command::WriteMemory {
+                    block_num,
+                    len,
+                    skip,
+                    rebase,
+                    crc,
+                } => {
+                    // with LMDFU prefix, image starts after the prefix in the first block
+                    let prefix_len = match self.lmdfu {
+                        Some(_) => LMDFU_PREFIX_LENGTH as u32,
+                        None => 0,
+                    };
+                    let pointer = match rebase {
+                        true => self.block_address(WRAP_BLOCKS, 0),
+                        false => Some(self.address_pointer),
+                    };
+                    if let Some(address) = pointer
+                        .and_then(|p| {
+                            self.address_pointer = p;
+                            self.block_address(block_num, skip)
+                        })
+                        .and_then(|a| a.checked_sub(prefix_len))
+                    {
+                        match crc.filter(|_| self.verifying()) {
+                            Some(crc) => Operation::Verify {
+                                address,
+                                len,
+                                block_num,
+                                crc,
+                            },
+                            None => Operation::Program {
+                                address,
+                                len,
+                                block_num,
+                                crc,
+                            },
+                        }
+                    } else {
+                        // overflow
+                        self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrAddress);
+                        // drop the rest of the queue
+                        self.pending.clear();
+                        return None;
+                    }
+                }
+                Command::SetAddressPointer(p) => {
+                    self.address_pointer = p;
+                    continue;
+                }
+            };
+
+            let erase = matches!(
+                op,
+                Operation::EraseAll { .. } | Operation::Erase(_) | Operation::EraseRange { .. }
+            );
+            if erase && self.verifying() {
+                debug!("DFU operation {:?} is skipped in verify mode", op);
+                continue;
+            }
+
+            if let Err(status) = self.check_permissions(&op) {
+                debug!("DFU operation {:?} is not permitted", op);
+                self.new_state_status(DfuState::DfuError, status);
+                // drop the rest of the queue
+                self.pending.clear();
+                return None;
+            }
+
+            debug!("DFU operation {:?}", op);
+            self.in_progress = Some(op);
+            return Some(op);
+        }
+
+        if self.state() == DfuState::DfuDnBusy {
+            // all queued commands are done
+            self.new_state_ok(DfuState::DfuDnloadSync);
+        }
+
+        None
+    }
+
+    /// Reject an operation on addresses without the required area operations,
+    /// see [`DfuMemory::ENFORCE_PERMISSIONS`].
+    fn check_permissions(&self, op: &Operation) -> Result<(), DfuStatusCode> {
+        if !self.config.enforce_permissions {
+            return Ok(());
+        }
+        let (address, length, required, status) = match *op {
+            Operation::Program { address, len, .. } => (
+                address,
+                len as u32,
+                Operations::WRITE,
+                DfuStatusCode::ErrWrite,
+            ),
+            Operation::Verify { address, len, .. } => (
+                address,
+                len as u32,
+                Operations::READ,
+                DfuStatusCode::ErrVerify,
+            ),
+            Operation::Erase(address) => (address, 1, Operations::ERASE, DfuStatusCode::ErrErase),
+            Operation::EraseRange { address, length } => {
+                (address, length, Operations::ERASE, DfuStatusCode::ErrErase)
+            }
+            _ => return Ok(()),
+        };
+        match mem_info::operations(self.mem_info(), address, length) {
+            Some(operations) if operations.contains(required) => Ok(()),
+            Some(_) => Err(status),
+            None => Err(DfuStatusCode::ErrAddress),
+        }
+    }
+
+    /// Returns `true` if `op` returned by [`next_operation()`](DFUStatus::next_operation)
+    /// is the first access to the image of a new download, and
+    /// [`DfuMemory::download_start()`] must be called before it.
+    ///
+    /// Commands that don't touch the image, like *Unlock*, leave the download start
+    /// to the next operation.
+    pub(crate) fn take_download_start(&mut self, op: &Operation) -> bool {
+        let image = !matches!(
+            op,
+            Operation::Unlock | Operation::SetParameters | Operation::SetReadoutProtection(_)
+        );
+        image && core::mem::take(&mut self.download_start)
+    }
+
+    /// Returns `true` if the host polled the status since the last call.
+    pub(crate) fn take_host_poll(&mut self) -> bool {
+        core::mem::take(&mut self.host_polled)
+    }
+
+    /// Returns `true` if blocks of the download are compared,
+    /// see [`DfuMemory::VERIFY_COMMAND`].
+    fn verifying(&self) -> bool {
+        #[cfg(feature = "download")]
+        return self.verify;
+        #[cfg(not(feature = "download"))]
+        false
+    }
+
+    pub(crate) fn dfuse_used(&self) -> bool {
+        self.dfuse
+    }
+
+    /// Returns the payload of *Unlock* or *Set Parameters* command,
+    /// it's cleared when the command is completed.
+    pub(crate) fn payload(&self) -> &[u8] {
+        #[cfg(feature = "download")]
+        return &self.payload.0;
+        #[cfg(not(feature = "download"))]
+        return &[];
+    }
+
+    /// Returns block number and length of a short block followed by another one,
+    /// [`DfuMemory::block_size_mismatch()`] must be called with them.
+    pub(crate) fn take_block_size_mismatch(&mut self) -> Option<(u16, u16)> {
+        self.block_size_mismatch.take()
+    }
+
+    /// Report the result of an operation returned by [`next_operation()`](DFUStatus::next_operation).
+    pub(crate) fn complete(&mut self, result: Result<(), OperationError>) {
+        let Some(op) = self.in_progress.take() else {
+            return;
+        };
+        self.begin(None);
+
+        #[cfg(feature = "download")]
+        if matches!(op, Operation::Unlock | Operation::SetParameters) {
+            self.payload.clear();
+        }
+
+        if self.state() != DfuState::DfuDnBusy && self.state() != DfuState::DfuManifest {
+            // state was changed while operation was running, e.g. by USB reset
+            return;
+        }
+
+        match result {
+            Err(e) => {
+                debug!("DFU operation {:?} failed: {}", op, DfuStatusCode::from(e));
+                if let OperationError::Memory(error) = e {
+                    let (address, block) = match op {
+                        Operation::Erase(address) | Operation::EraseRange { address, .. } => {
+                            (Some(address), None)
+                        }
+                        Operation::Program {
+                            address, block_num, ..
+                        }
+                        | Operation::Verify {
+                            address, block_num, ..
+                        } => (Some(address), Some(block_num)),
+                        _ => (None, None),
+                    };
+                    self.memory_error = Some(MemoryErrorDetail {
+                        error,
+                        address,
+                        block,
+                    });
+                }
+                self.new_state_status(DfuState::DfuError, e.into());
+                // drop the rest of the queue
+                self.pending.clear();
+            }
+            #[cfg(feature = "download")]
+            Ok(_) if matches!(op, Operation::Unlock) => {
+                self.unlocked = true;
+            }
+            Ok(_) if matches!(op, Operation::Manifestation { .. }) => {
+                if self.config.manifestation_tolerant {
+                    self.new_state_ok(DfuState::DfuManifestSync)
+                } else {
+                    self.new_state_ok(DfuState::DfuManifestWaitReset)
+                }
+            }
+            #[cfg(feature = "download")]
+            Ok(_) if matches!(op, Operation::Program { .. }) => {
+                if let Operation::Program { len, .. } = op {
+                    self.image_programmed = self.image_programmed.saturating_add(len as u32);
+                }
+                #[cfg(feature = "stats")]
+                stats::add(&mut self.stats.blocks_programmed, 1);
+            }
+            #[cfg(feature = "download")]
+            Ok(_) if matches!(op, Operation::Verify { .. }) => {
+                if let Operation::Verify { len, .. } = op {
+                    // progress of the download
+                    self.image_programmed = self.image_programmed.saturating_add(len as u32);
+                }
+            }
+            // state is updated by next_operation() when the queue is empty
+            Ok(_) => {}
+        }
+    }
+
+    /// Returns `false` if a queued *Set Address Pointer* command is outside of
+    /// the memory, see [`DfuMemory::VALIDATE_ADDRESS_POINTER`].
+    fn check_address_pointers(&self) -> bool {
+        self.command.iter().all(|command| match *command {
+            Command::SetAddressPointer(p) => self.valid_address_pointer(p),
+            _ => true,
+        })
+    }
+
+    /// Returns `false` if `address` is outside of the memory and
+    /// [`DfuMemory::VALIDATE_ADDRESS_POINTER`] is set.
+    fn valid_address_pointer(&self, address: u32) -> bool {
+        !self.config.validate_address_pointer
+            || mem_info::operations(self.mem_info(), address, 0).is_some()
+    }
+
+    fn process(&mut self) -> bool {
+        let initial_state = self.state();
+        if initial_state == DfuState::DfuDnloadSync {
+            if !self.check_address_pointers() {
+                self.command.clear();
+                self.new_state_status(DfuState::DfuError, DfuStatusCode::ErrAddress);
+                return true;
+            }
+
+            while let Some(command) = self.command.pop_front() {
+                // both queues have the same capacity
+                self.pending.push_back(command).ok();
+            }
+
+            if self.is_busy() {
+                self.new_state_ok(DfuState::DfuDnBusy);
+            } else {
+                self.new_state_ok(DfuState::DfuDnloadIdle);
+            }
+        } else if initial_state == DfuState::DfuManifestSync {
+            if self.command.is_empty() {
+                if self.config.manifestation_tolerant || self.verifying() {
+                    // Leave manifestation, back to Idle
+                    self.new_state_ok(DfuState::DfuIdle);
+                }
+            } else {
+                // Start manifestation
+                while let Some(command) = self.command.pop_front() {
+                    self.pending.push_back(command).ok();
+                }
+                self.new_state_ok(DfuState::DfuManifest);
+            }
+        } else if initial_state == DfuState::DfuDnBusy {
+            // operation may still be executed in a different context
+            return self.is_busy();
+        }
+
+        true
+    }
+}
+
+impl From<&DFUStatus> for [u8; 6] {
+    fn from(dfu: &DFUStatus) -> Self {
+        [
+            // bStatus
+            dfu.status as u8,
+            // bwPollTimeout
+            (dfu.poll_timeout & 0xff) as u8,
+            ((dfu.poll_timeout >> 8) & 0xff) as u8,
+            ((dfu.poll_timeout >> 16) & 0xff) as u8,
+            // bState
+            dfu.state as u8,
+            // iString: Index of status description in string table.
+            0,
+        ]
+    }
+}
+
+/// Write DFU interface and DFU Functional descriptors.
+#[allow(clippy::identity_op)]
+pub(crate) fn write_descriptors<M: DfuMemory>(
+    writer: &mut DescriptorWriter,
+    if_num: InterfaceNumber,
+    interface_string: Option<StringIndex>,
+    alt_strings: &AltStrings,
+    can_upload: bool,
+) -> usb_device::Result<()> {
+    for alt in 0..alt_setting_count::<M>() {
+        let string = match alt {
+            0 => interface_string,
+            _ => alt_strings[alt - 1],
+        };
+        writer.interface_alt(
+            if_num,
+            alt as u8,
+            USB_CLASS_APPLICATION_SPECIFIC,
+            USB_SUBCLASS_DFU,
+            USB_PROTOCOL_DFU_MODE,
+            string,
+        )?;
+    }
+
+    // DFU Functional descriptor
+    writer.write(
+        DESC_DESCTYPE_DFU,
+        &[
+            // bmAttributes
+            // Bit 7: bitAcceleratedST
+            (if false {0x80} else {0}) |
+                // Bit 4-6: Reserved
+                // Bit 3: bitWillDetach
+                (if true {0x8} else {0}) |
+                // Bit 2: bitManifestationTolerant
+                (if M::MANIFESTATION_TOLERANT {0x4} else {0}) |
+                // Bit 1: bitCanUpload
+                (if can_upload && cfg!(feature = "upload") {0x2} else {0}) |
+                // Bit 0: bitCanDnload
+                (if has_download::<M>() && cfg!(feature = "download") {0x1} else {0}),
+            // wDetachTimeOut
+            (M::DETACH_TIMEOUT & 0xff) as u8,
+            (M::DETACH_TIMEOUT >> 8) as u8,
+            // wTransferSize
+            (M::TRANSFER_SIZE & 0xff) as u8,
+            (M::TRANSFER_SIZE >> 8) as u8,
+            // bcdDFUVersion
+            0x1a,
+            0x01,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Returns the interface string of DFU interface `index`.
+pub(crate) fn get_string<M: DfuMemory>(
+    index: StringIndex,
+    interface_string: Option<StringIndex>,
+    alt_strings: &AltStrings,
+) -> Option<&'static str> {
+    // ASCII strings, answered for any requested language
+    if Some(index) == interface_string {
+        return alt_setting_name::<M>(0);
+    }
+    let alt = alt_strings.iter().position(|s| *s == Some(index))?;
+    alt_setting_name::<M>(alt + 1)
+}
+
+/// Strings of alternate settings `1..`, see [`DfuMemory::ALT_SETTINGS`].
+pub(crate) type AltStrings = [Option<StringIndex>; MAX_ALT_SETTINGS - 1];
+
+/// Allocate the strings of alternate settings `1..`.
+pub(crate) fn alloc_alt_strings<B: UsbBus, M: DfuMemory>(alloc: &UsbBusAllocator<B>) -> AltStrings {
+    const {
+        assert!(
+            M::ALT_SETTINGS.len() <= MAX_ALT_SETTINGS,
+            "ALT_SETTINGS has more than MAX_ALT_SETTINGS entries"
+        )
+    };
+
+    let mut strings = [None; MAX_ALT_SETTINGS - 1];
+    for string in strings.iter_mut().take(alt_setting_count::<M>() - 1) {
+        *string = Some(alloc.string());
+    }
+    strings
+}
+
+/// Returns the number of alternate settings of the DFU interface.
+fn alt_setting_count<M: DfuMemory>() -> usize {
+    match (M::ALT_SETTINGS.len(), M::INTERFACE_NAME) {
+        (0, None) => 1,
+        (0, Some(_)) => 2,
+        (n, _) => n,
+    }
+}
+
+/// Returns *iInterface* string of alternate setting `alt`.
+fn alt_setting_name<M: DfuMemory>(alt: usize) -> Option<&'static str> {
+    match (M::ALT_SETTINGS, alt) {
+        ([], 0) => Some(M::MEM_INFO_STRING),
+        ([], 1) => M::INTERFACE_NAME,
+        (alt_settings, alt) => alt_settings.get(alt).map(|a| a.name),
+    }
+}
+
+/// Returns `true` if `alt_setting` of the DFU interface exists.
+pub(crate) fn has_alt_setting<M: DfuMemory>(alt_setting: u8) -> bool {
+    (alt_setting as usize) < alt_setting_count::<M>()
 }
 
-#[derive(Clone, Copy, PartialEq, Eq)]
-#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
-enum Command {
-    None,
-    EraseAll,
-    Erase(u32),
-    SetAddressPointer(u32),
-    ReadUnprotect,
-    WriteMemory { block_num: u16, len: u16 },
-    LeaveDfu,
+/// Returns `true` if [`DfuMemory::HAS_UPLOAD`] is set,
+/// or any of [`DfuMemory::ALT_SETTINGS`] allows uploads.
+pub(crate) fn has_upload<M: DfuMemory>() -> bool {
+    match M::ALT_SETTINGS {
+        [] => M::HAS_UPLOAD,
+        alt_settings => alt_settings.iter().any(|a| a.upload),
+    }
 }
 
-#[derive(Clone, Copy)]
+/// Returns `true` if [`DfuMemory::HAS_DOWNLOAD`] is set,
+/// or any of [`DfuMemory::ALT_SETTINGS`] allows downloads.
+pub(crate) fn has_download<M: DfuMemory>() -> bool {
+    match M::ALT_SETTINGS {
+        [] => M::HAS_DOWNLOAD,
+        alt_settings => alt_settings.iter().any(|a| a.download),
+    }
+}
+
+/// Returns `true` if control request is a DFU class request for the interface.
+pub(crate) fn is_dfu_request(req: &Request, if_num: InterfaceNumber) -> bool {
+    req.request_type == control::RequestType::Class
+        && req.recipient == control::Recipient::Interface
+        && req.index == u8::from(if_num) as u16
+}
+
+/// State of [`DfuClass`] after it's created, see [`DfuClassBuilder::initial_state()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
-struct DFUStatus {
-    status: DfuStatusCode,
-    poll_timeout: u32,
-    state: DfuState,
-    address_pointer: u32,
-    command: Command,
-    pending: Command,
+pub enum InitialState {
+    /// `dfuIDLE`, the default.
+    #[default]
+    Idle,
+    /// `dfuERROR` with `errPOR`, see [`DfuClass::set_unexpected_reset_state()`].
+    UnexpectedReset,
+    /// `dfuERROR` with `errFIRMWARE`, see [`DfuClass::set_firmware_corrupted_state()`].
+    FirmwareCorrupted,
 }
 
-impl DFUStatus {
-    pub fn new(addr: u32) -> Self {
+/// Builder of [`DfuClass`], see [`DfuClass::builder()`].
+///
+/// Options are applied when the class is created, so they can't race
+/// with the enumeration. Options that are not set come from [`DfuMemory`].
+pub struct DfuClassBuilder<B: UsbBus, M: DfuMemory> {
+    _bus: PhantomData<B>,
+    mem: M,
+    config: MemoryConfig,
+    initial_state: InitialState,
+    if_num: Option<InterfaceNumber>,
+    str_idx: Option<StringIndex>,
+}
+
+impl<B: UsbBus, M: DfuMemory> DfuClassBuilder<B, M> {
+    fn new(mem: M) -> Self {
         Self {
-            status: DfuStatusCode::Ok,
-            poll_timeout: 0,
-            state: DfuState::DfuIdle,
-            address_pointer: addr,
-            command: Command::None,
-            pending: Command::None,
+            _bus: PhantomData,
+            mem,
+            config: MemoryConfig::new::<M>(),
+            initial_state: InitialState::Idle,
+            if_num: None,
+            str_idx: None,
         }
     }
 
-    fn new_state_ok(&mut self, state: DfuState) {
-        self.new_state_status(state, DfuStatusCode::Ok);
+    /// Set the state of the created class. Default is [`InitialState::Idle`].
+    pub fn initial_state(mut self, state: InitialState) -> Self {
+        self.initial_state = state;
+        self
     }
 
-    fn new_state_status(&mut self, state: DfuState, status: DfuStatusCode) {
-        self.status = status;
-        self.state = state;
+    /// Override [`DfuMemory::STRICT`].
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self
     }
 
-    fn state(&self) -> DfuState {
-        self.state
+    /// Override [`DfuMemory::SESSION_TIMEOUT_MS`].
+    pub fn session_timeout_ms(mut self, timeout_ms: u32) -> Self {
+        self.config.session_timeout_ms = timeout_ms;
+        self
     }
-}
 
-impl From<DFUStatus> for [u8; 6] {
-    fn from(dfu: DFUStatus) -> Self {
-        [
-            // bStatus
-            dfu.status as u8,
-            // bwPollTimeout
-            (dfu.poll_timeout & 0xff) as u8,
-            ((dfu.poll_timeout >> 8) & 0xff) as u8,
-            ((dfu.poll_timeout >> 16) & 0xff) as u8,
-            // bState
-            dfu.state as u8,
-            // iString: Index of status description in string table.
-            0,
-        ]
+    /// Use `if_num` allocated by the application, see [`DfuClass::new_with_resources()`].
+    pub fn interface_number(mut self, if_num: InterfaceNumber) -> Self {
+        self.if_num = Some(if_num);
+        self
+    }
+
+    /// Use `str_idx` allocated by the application for the interface string,
+    /// see [`DfuClass::new_with_resources()`].
+    pub fn interface_string(mut self, str_idx: StringIndex) -> Self {
+        self.str_idx = Some(str_idx);
+        self
+    }
+
+    /// Create the [`DfuClass`], resources that were not set are allocated from `alloc`.
+    pub fn build(self, alloc: &UsbBusAllocator<B>) -> DfuClass<B, M> {
+        let if_num = self.if_num.unwrap_or_else(|| alloc.interface());
+        let interface_string =
+            M::HAS_INTERFACE_STRING.then(|| self.str_idx.unwrap_or_else(|| alloc.string()));
+        let mut dfu =
+            DfuClass::from_resources(alloc, self.mem, if_num, interface_string, self.config);
+        match self.initial_state {
+            InitialState::Idle => {}
+            InitialState::UnexpectedReset => dfu.set_unexpected_reset_state(),
+            InitialState::FirmwareCorrupted => dfu.set_firmware_corrupted_state(),
+        }
+        dfu
     }
 }
 
@@ -497,78 +3525,84 @@ impl<B: UsbBus, M: DfuMemory> UsbClass<B> for DfuClass<B, M> {
         &self,
         writer: &mut DescriptorWriter,
     ) -> usb_device::Result<()> {
-        writer.interface_alt(
+        write_descriptors::<M>(
+            writer,
             self.if_num,
-            0,
-            USB_CLASS_APPLICATION_SPECIFIC,
-            USB_SUBCLASS_DFU,
-            USB_PROTOCOL_DFU_MODE,
-            Some(self.interface_string),
-        )?;
+            self.interface_string,
+            &self.alt_strings,
+            has_upload::<M>(),
+        )
+    }
 
-        // DFU Functional descriptor
-        writer.write(
-            DESC_DESCTYPE_DFU,
-            &[
-                // bmAttributes
-                // Bit 7: bitAcceleratedST
-                (if false {0x80} else {0}) |
-                    // Bit 4-6: Reserved
-                    // Bit 3: bitWillDetach
-                    (if true {0x8} else {0}) |
-                    // Bit 2: bitManifestationTolerant
-                    (if M::MANIFESTATION_TOLERANT {0x4} else {0}) |
-                    // Bit 1: bitCanUpload
-                    (if M::HAS_UPLOAD {0x2} else {0}) |
-                    // Bit 0: bitCanDnload
-                    (if M::HAS_DOWNLOAD {0x1} else {0}),
-                // wDetachTimeOut
-                (M::DETACH_TIMEOUT & 0xff) as u8,
-                (M::DETACH_TIMEOUT >> 8) as u8,
-                // wTransferSize
-                (M::TRANSFER_SIZE & 0xff) as u8,
-                (M::TRANSFER_SIZE >> 8) as u8,
-                // bcdDFUVersion
-                0x1a,
-                0x01,
-            ],
-        )?;
+    fn get_string(&self, index: StringIndex, _lang_id: LangID) -> Option<&str> {
+        get_string::<M>(index, self.interface_string, &self.alt_strings)
+    }
 
-        Ok(())
+    fn get_alt_setting(&mut self, interface: InterfaceNumber) -> Option<u8> {
+        (interface == self.if_num).then_some(self.status.alt_setting())
     }
 
-    fn get_string(&self, index: StringIndex, lang_id: LangID) -> Option<&str> {
-        if index == self.interface_string && (lang_id == LangID::EN_US || u16::from(lang_id) == 0) {
-            return Some(M::MEM_INFO_STRING);
+    fn set_alt_setting(&mut self, interface: InterfaceNumber, alternative: u8) -> bool {
+        if interface != self.if_num || !has_alt_setting::<M>(alternative) {
+            return false;
         }
-        None
+        let changed = alternative != self.status.alt_setting();
+        if !self.status.set_alt_setting(alternative) {
+            return false;
+        }
+        if changed {
+            self.mem.select_alt_setting(alternative);
+        }
+        true
     }
 
     // Handle control requests to the host.
     fn control_in(&mut self, xfer: ControlIn<B>) {
         let req = *xfer.request();
 
-        if req.request_type != control::RequestType::Class {
-            return;
-        }
-
-        if req.recipient != control::Recipient::Interface {
+        if self.status.is_extended_status_request(&req, self.if_num) {
+            match self.status.get_extended_status(&req) {
+                Some(v) => xfer.accept_with(&v).ok(),
+                None => xfer.reject().ok(),
+            };
             return;
         }
 
-        if req.index != u8::from(self.if_num) as u16 {
+        if !is_dfu_request(&req, self.if_num) {
             return;
         }
 
         match req.request {
+            #[cfg(feature = "upload")]
+            DFU_UPLOAD if req.value == 1 => {
+                let mem = &mut self.mem;
+                match self.status.query(&req, |query| query_reply(mem, query)) {
+                    Some((data, len)) => xfer.accept_with(&data[..len]).ok(),
+                    None => xfer.reject().ok(),
+                };
+            }
+            #[cfg(feature = "upload")]
             DFU_UPLOAD => {
-                self.upload(xfer, req);
+                let mem = &mut self.mem;
+                match self
+                    .status
+                    .upload(&req, |address, length| mem.read_block(address, length))
+                {
+                    Some(data) => xfer.accept_with(data).ok(),
+                    None => xfer.reject().ok(),
+                };
             }
             DFU_GETSTATUS => {
-                self.get_status(xfer, req);
+                match self.status.get_status(&req) {
+                    Some(v) => xfer.accept_with(&v).ok(),
+                    None => xfer.reject().ok(),
+                };
             }
             DFU_GETSTATE => {
-                self.get_state(xfer, req);
+                match self.status.get_state(&req) {
+                    Some(v) => xfer.accept_with(&[v]).ok(),
+                    None => xfer.reject().ok(),
+                };
             }
             _ => {
                 xfer.reject().ok();
@@ -580,56 +3614,39 @@ impl<B: UsbBus, M: DfuMemory> UsbClass<B> for DfuClass<B, M> {
     fn control_out(&mut self, xfer: ControlOut<B>) {
         let req = *xfer.request();
 
-        if req.request_type != control::RequestType::Class {
-            return;
-        }
-
-        if req.recipient != control::Recipient::Interface {
-            return;
-        }
-
-        if req.index != u8::from(self.if_num) as u16 {
+        if !is_dfu_request(&req, self.if_num) {
             return;
         }
 
-        match req.request {
+        let accepted = match req.request {
             //DFU_DETACH => {},
+            #[cfg(feature = "download")]
             DFU_DNLOAD => {
-                self.download(xfer, req);
-            }
-            DFU_CLRSTATUS => {
-                self.clear_status(xfer);
-            }
-            DFU_ABORT => {
-                self.abort(xfer);
-            }
-            _ => {
-                xfer.reject().ok();
+                let mem = &mut self.mem;
+                self.status
+                    .download(&req, xfer.data(), &mut |data| mem.store_write_buffer(data))
             }
+            DFU_CLRSTATUS => self.status.clear_status(),
+            DFU_ABORT => self.status.abort(),
+            _ => false,
+        };
+
+        if accepted {
+            xfer.accept().ok();
+        } else {
+            xfer.reject().ok();
         }
     }
 
     fn reset(&mut self) {
+        let alt_setting = self.status.alt_setting();
+
         // may not return
         self.mem.usb_reset();
 
-        // Try to signal possible error to a host.
-        // Not exactly clear what status should be.
-        match self.status.state() {
-            DfuState::DfuUploadIdle
-            | DfuState::DfuDnloadIdle
-            | DfuState::DfuDnloadSync
-            | DfuState::DfuDnBusy
-            | DfuState::DfuError
-            | DfuState::DfuManifest
-            | DfuState::DfuManifestSync => {
-                self.status
-                    .new_state_status(DfuState::DfuError, DfuStatusCode::ErrUsbr);
-            }
-            DfuState::DfuIdle
-            | DfuState::AppDetach
-            | DfuState::AppIdle
-            | DfuState::DfuManifestWaitReset => {}
+        self.status.usb_reset();
+        if alt_setting != 0 {
+            self.mem.select_alt_setting(0);
         }
     }
 
@@ -642,10 +3659,75 @@ impl<B: UsbBus, M: DfuMemory> DfuClass<B, M> {
     /// Creates a new [`DfuClass`] with the provided UsbBus and
     /// [`DfuMemory`]
     pub fn new(alloc: &UsbBusAllocator<B>, mem: M) -> Self {
+        let if_num = alloc.interface();
+        let interface_string = M::HAS_INTERFACE_STRING.then(|| alloc.string());
+        Self::from_resources(
+            alloc,
+            mem,
+            if_num,
+            interface_string,
+            MemoryConfig::new::<M>(),
+        )
+    }
+
+    /// Returns a [`DfuClassBuilder`] of a [`DfuClass`] with `mem`, to set options
+    /// before the class is created.
+    ///
+    /// ```no_run
+    /// # use usb_device::bus::{UsbBus, UsbBusAllocator};
+    /// # use usbd_dfu::*;
+    /// # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, mem: impl DfuMemory) {
+    /// let mut dfu = DfuClass::builder(mem)
+    ///     .initial_state(InitialState::FirmwareCorrupted)
+    ///     .strict(true)
+    ///     .build(&usb_bus_alloc);
+    /// # }
+    /// ```
+    pub fn builder(mem: M) -> DfuClassBuilder<B, M> {
+        DfuClassBuilder::new(mem)
+    }
+
+    /// Creates a new [`DfuClass`] with the interface number and the interface string index
+    /// allocated by the application, e.g. to control the order of interfaces in a composite device.
+    ///
+    /// `str_idx` is the index of [`MEM_INFO_STRING`](DfuMemory::MEM_INFO_STRING), it's not used if
+    /// [`HAS_INTERFACE_STRING`](DfuMemory::HAS_INTERFACE_STRING) is `false`. The strings of
+    /// other alternate settings, [`INTERFACE_NAME`](DfuMemory::INTERFACE_NAME) or
+    /// [`ALT_SETTINGS`](DfuMemory::ALT_SETTINGS), are allocated from `alloc`.
+    pub fn new_with_resources(
+        alloc: &UsbBusAllocator<B>,
+        mem: M,
+        if_num: InterfaceNumber,
+        str_idx: StringIndex,
+    ) -> Self {
+        Self::from_resources(
+            alloc,
+            mem,
+            if_num,
+            M::HAS_INTERFACE_STRING.then_some(str_idx),
+            MemoryConfig::new::<M>(),
+        )
+    }
+
+    fn from_resources(
+        alloc: &UsbBusAllocator<B>,
+        mem: M,
+        if_num: InterfaceNumber,
+        interface_string: Option<StringIndex>,
+        config: MemoryConfig,
+    ) -> Self {
+        const {
+            assert!(
+                M::TRANSFER_SIZE as usize <= CONTROL_BUFFER_SIZE,
+                "TRANSFER_SIZE is larger than usb-device control buffer"
+            )
+        };
+
         Self {
-            if_num: alloc.interface(),
-            status: DFUStatus::new(M::INITIAL_ADDRESS_POINTER),
-            interface_string: alloc.string(),
+            if_num,
+            status: DFUStatus::new(config, M::INITIAL_ADDRESS_POINTER),
+            interface_string,
+            alt_strings: alloc_alt_strings::<B, M>(alloc),
             _bus: PhantomData,
             mem,
         }
@@ -661,248 +3743,160 @@ impl<B: UsbBus, M: DfuMemory> DfuClass<B, M> {
     /// set DFU error state to "Device detected unexpected power on reset"
     /// instead of the usual `dfuIdle`.
     pub fn set_unexpected_reset_state(&mut self) {
-        self.status
-            .new_state_status(DfuState::DfuError, DfuStatusCode::ErrPOR);
+        self.status.set_unexpected_reset_state();
     }
 
     /// This function may be called just after [`DfuClass::new()`] to
     /// set DFU error state to "Device’s firmware is corrupt. It cannot return to run-time (non-DFU) operations"
     /// instead of the usual `dfuIdle`.
     pub fn set_firmware_corrupted_state(&mut self) {
-        self.status
-            .new_state_status(DfuState::DfuError, DfuStatusCode::ErrFirmware);
+        self.status.set_firmware_corrupted_state();
     }
 
     /// Return current Address Pointer value.
     pub fn get_address_pointer(&self) -> u32 {
-        self.status.address_pointer
+        self.status.address_pointer()
     }
 
-    fn clear_status(&mut self, xfer: ControlOut<B>) {
-        match self.status.state() {
-            DfuState::DfuError => {
-                self.status.command = Command::None;
-                self.status.pending = Command::None;
-                self.status.new_state_ok(DfuState::DfuIdle);
-                xfer.accept().ok();
-            }
-            _ => {
-                self.status
-                    .new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
-                xfer.reject().ok();
-            }
-        }
+    /// Set Address Pointer, e.g. to the inactive slot before the host starts a download.
+    ///
+    /// The address is checked like the one of DfuSe *Set Address Pointer* command,
+    /// see [`DfuMemory::VALIDATE_ADDRESS_POINTER`], and the pointer is not changed
+    /// on error:
+    /// - `ErrAddress` if the address is outside of the memory,
+    /// - `ErrNotdone` if the state is not `dfuIDLE`, e.g. a download is in progress.
+    pub fn set_address_pointer(&mut self, address: u32) -> Result<(), DfuStatusCode> {
+        self.status.set_address_pointer(address)
     }
 
-    fn abort(&mut self, xfer: ControlOut<B>) {
-        match self.status.state() {
-            DfuState::DfuIdle
-            | DfuState::DfuUploadIdle
-            | DfuState::DfuDnloadIdle
-            | DfuState::DfuDnloadSync
-            | DfuState::DfuManifestSync => {
-                self.status.command = Command::None;
-                self.status.pending = Command::None;
-                self.status.new_state_ok(DfuState::DfuIdle);
-                xfer.accept().ok();
-            }
-            DfuState::AppDetach
-            | DfuState::AppIdle
-            | DfuState::DfuDnBusy
-            | DfuState::DfuManifest
-            | DfuState::DfuManifestWaitReset
-            | DfuState::DfuError => {
-                xfer.reject().ok();
-            }
-        }
+    /// Returns the number of DFU interface, e.g. for *wIndex* of class requests,
+    /// or for descriptors of other functions of a composite device.
+    pub fn get_interface_number(&self) -> InterfaceNumber {
+        self.if_num
     }
 
-    fn download(&mut self, xfer: ControlOut<B>, req: Request) {
-        let initial_state = self.status.state();
+    /// Returns the last failed [`DfuMemory`] call, if any.
+    ///
+    /// The host only gets the status code, this keeps the error
+    /// with its address for device-side logging.
+    pub fn last_memory_error(&self) -> Option<MemoryErrorDetail> {
+        self.status.last_memory_error()
+    }
 
-        if initial_state != DfuState::DfuIdle && initial_state != DfuState::DfuDnloadIdle {
-            self.status
-                .new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
-            xfer.reject().ok();
-            return;
-        }
+    /// Returns the smallest and largest data block of the current or the last download,
+    /// `None` if no data blocks were received.
+    ///
+    /// All blocks but the last one should be [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE)
+    /// bytes long, see [`DfuMemory::block_size_mismatch()`].
+    pub fn download_block_sizes(&self) -> Option<BlockSizes> {
+        self.status.download_block_sizes()
+    }
 
-        if req.length == 0 {
-            self.status.command = Command::LeaveDfu;
-            self.status.new_state_ok(DfuState::DfuManifestSync);
-            xfer.accept().ok();
-            return;
-        }
+    /// Returns the received part of the image in percent, if its length was announced
+    /// with *Set Image Size* command or in LMDFU prefix, see
+    /// [`IMAGE_SIZE_COMMAND`](DfuMemory::IMAGE_SIZE_COMMAND).
+    ///
+    /// The value can drive a progress indicator, it's `None` after the download ends.
+    pub fn download_progress(&self) -> Option<u8> {
+        self.status.download_progress()
+    }
 
-        if req.value > 1 {
-            let data = xfer.data();
-            if !data.is_empty() {
-                // store the whole buffer, chunked operation in not supported
-                match self.mem.store_write_buffer(data) {
-                    Err(_) => {
-                        self.status
-                            .new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
-                        xfer.reject().ok();
-                    }
-                    Ok(_) => {
-                        let block_num = req.value - 2;
-                        self.status.command = Command::WriteMemory {
-                            block_num,
-                            len: data.len() as u16,
-                        };
-                        self.status.new_state_ok(DfuState::DfuDnloadSync);
-                        xfer.accept().ok();
-                    }
-                }
-                return;
-            }
-        } else if req.value == 0 {
-            let data = xfer.data();
-            if req.length >= 1 {
-                let command = data[0];
-
-                if command == DownloadCommand::SetAddressPointer as u8 {
-                    if req.length == 5 {
-                        let addr = (data[1] as u32)
-                            | ((data[2] as u32) << 8)
-                            | ((data[3] as u32) << 16)
-                            | ((data[4] as u32) << 24);
-                        self.status.command = Command::SetAddressPointer(addr);
-                        self.status.new_state_ok(DfuState::DfuDnloadSync);
-                        xfer.accept().ok();
-                        return;
-                    }
-                } else if command == DownloadCommand::Erase as u8 {
-                    if req.length == 5 {
-                        let addr = (data[1] as u32)
-                            | ((data[2] as u32) << 8)
-                            | ((data[3] as u32) << 16)
-                            | ((data[4] as u32) << 24);
-                        self.status.command = Command::Erase(addr);
-                        self.status.new_state_ok(DfuState::DfuDnloadSync);
-                        xfer.accept().ok();
-                        return;
-                    } else if req.length == 1 {
-                        self.status.command = Command::EraseAll;
-                        self.status.new_state_ok(DfuState::DfuDnloadSync);
-                        xfer.accept().ok();
-                        return;
-                    }
-                } else if HAS_READ_UNPROTECT && command == DownloadCommand::ReadUnprotect as u8 {
-                    self.status.command = Command::ReadUnprotect;
-                    self.status.new_state_ok(DfuState::DfuDnloadSync);
-                    xfer.accept().ok();
-                    return;
-                }
-            }
-        }
+    /// Returns the reply of *Get Extended Status* request, see
+    /// [`DfuMemory::EXTENDED_STATUS_REQUEST`].
+    pub fn extended_status(&self) -> ExtendedStatus {
+        self.status.extended_status()
+    }
 
-        self.status
-            .new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
-        xfer.reject().ok();
+    /// Returns `true` if the host issued `DFU_GETSTATUS` or `DFU_GETSTATE` request
+    /// since the last call.
+    ///
+    /// The host polls the status after every command and waits for *bwPollTimeout*
+    /// at most, so when the flag stays clear for longer than that in the middle of
+    /// a download, the host is gone, e.g. the cable was pulled or the host tool was killed,
+    /// and the application can release its resources or restart advertising.
+    pub fn take_host_poll(&mut self) -> bool {
+        self.status.take_host_poll()
     }
 
-    fn upload(&mut self, xfer: ControlIn<B>, req: Request) {
-        let initial_state = self.status.state();
+    /// Returns `true` if the host issued a DfuSe command since the last USB reset:
+    /// *Get Commands*, *Set Address Pointer*, *Erase* or *Read Unprotect*.
+    ///
+    /// DfuSe hosts, e.g. STM32CubeProgrammer or dfu-util with `-s`, erase and position
+    /// the download themselves, while a plain DFU host only sends data blocks, so the
+    /// application can enable its own erase for plain hosts, or log which tool was used.
+    pub fn dfuse_used(&self) -> bool {
+        self.status.dfuse_used()
+    }
 
-        if initial_state != DfuState::DfuIdle && initial_state != DfuState::DfuUploadIdle {
-            self.status
-                .new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
-            xfer.reject().ok();
-            return;
+    /// Report `elapsed_ms` milliseconds since the last call, the time source
+    /// of [`SESSION_TIMEOUT_MS`](DfuMemory::SESSION_TIMEOUT_MS).
+    ///
+    /// Call it periodically, e.g. from a timer interrupt or the main loop, in the same
+    /// context as `usb_dev.poll([])`. Returns `true` if the session timed out.
+    pub fn tick(&mut self, elapsed_ms: u32) -> bool {
+        let timeout = self.status.tick(elapsed_ms);
+        if timeout {
+            self.mem.session_timeout();
         }
+        timeout
+    }
 
-        if req.value == 0 {
-            // Get command
-            let commands = [
-                DownloadCommand::GetCommands as u8,
-                DownloadCommand::SetAddressPointer as u8,
-                DownloadCommand::Erase as u8,
-                // XXX read unprotect
-            ];
-
-            if req.length as usize >= commands.len() {
-                self.status.new_state_ok(DfuState::DfuIdle);
-                xfer.accept_with(&commands).ok();
-                return;
-            }
-        } else if req.value > 1 {
-            // upload command
-            let block_num = req.value - 2;
-            let transfer_size = min(M::TRANSFER_SIZE, req.length);
-
-            if let Some(address) = self
-                .status
-                .address_pointer
-                .checked_add((block_num as u32) * (M::TRANSFER_SIZE as u32))
-            {
-                match self.mem.read(address, transfer_size as usize) {
-                    Ok(b) => {
-                        if b.len() < M::TRANSFER_SIZE as usize {
-                            // short frame, back to idle
-                            self.status.new_state_ok(DfuState::DfuIdle);
-                        } else {
-                            self.status.new_state_ok(DfuState::DfuUploadIdle);
-                        }
-                        xfer.accept_with(b).ok();
-                        return;
-                    }
-                    Err(e) => {
-                        self.status.new_state_status(DfuState::DfuError, e.into());
-                        xfer.reject().ok();
-                        return;
-                    }
-                }
-            } else {
-                // overflow
-                self.status
-                    .new_state_status(DfuState::DfuError, DfuStatusCode::ErrAddress);
-                xfer.reject().ok();
-                return;
-            }
+    /// Cancel the session from device code and go to `dfuIDLE`, e.g. when a supervisor
+    /// decides that the session is stuck, instead of waiting for `DFU_CLRSTATUS` or
+    /// `DFU_ABORT` from the host.
+    ///
+    /// Queued commands are dropped, and [`DfuMemory::session_timeout()`] is called to
+    /// release resources of the unfinished download or upload, unless the device was idle.
+    /// The Address Pointer and the alternate setting are kept.
+    ///
+    /// Call it in the same context as `usb_dev.poll([])`.
+    pub fn force_idle(&mut self) {
+        if self.status.force_idle() {
+            self.mem.session_timeout();
         }
+    }
 
-        self.status
-            .new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
-        xfer.reject().ok();
+    /// Returns the last state transitions, see [`trace`](crate::trace).
+    #[cfg(feature = "trace")]
+    pub fn trace(&self) -> &Trace {
+        self.status.trace()
     }
 
-    fn get_state(&mut self, xfer: ControlIn<B>, req: Request) {
-        // return current state, without any state transition
-        if req.length > 0 {
-            let v = self.status.state() as u8;
-            xfer.accept_with(&[v]).ok();
-        } else {
-            self.status
-                .new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
-            xfer.reject().ok();
-        }
+    /// Forget recorded state transitions.
+    #[cfg(feature = "trace")]
+    pub fn clear_trace(&mut self) {
+        self.status.clear_trace();
     }
 
-    fn get_status(&mut self, xfer: ControlIn<B>, req: Request) {
-        if req.length >= 6 && self.process() {
-            self.status.poll_timeout = self.expected_timeout();
-            let v: [u8; 6] = self.status.into();
-            xfer.accept_with(&v).ok();
-            return;
-        }
+    /// Returns session counters, see [`stats`](crate::stats).
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &DfuStats {
+        self.status.stats()
+    }
 
-        self.status
-            .new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt);
-        xfer.reject().ok();
+    /// Reset session counters to zero.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.status.reset_stats();
     }
 
-    fn expected_timeout(&self) -> u32 {
-        match self.status.pending {
-            Command::WriteMemory {
-                block_num: _,
-                len: _,
-            } => M::PROGRAM_TIME_MS,
-            Command::EraseAll => M::FULL_ERASE_TIME_MS,
-            Command::Erase(_) => M::ERASE_TIME_MS,
-            Command::LeaveDfu => M::MANIFESTATION_TIME_MS,
-            _ => 0,
-        }
+    /// Split the class into a [`DfuControl`] that handles USB requests and
+    /// a [`DfuWorker`] that owns the memory and executes erase, program and
+    /// manifestation operations.
+    ///
+    /// See [`split`](crate::split) module documentation for details.
+    pub fn split<const N: usize>(
+        self,
+        channel: &mut DfuChannel<N>,
+    ) -> (DfuControl<'_, B, M, N>, DfuWorker<'_, M, N>) {
+        split::split(
+            self.if_num,
+            self.interface_string,
+            self.alt_strings,
+            self.status,
+            self.mem,
+            channel,
+        )
     }
 
     // ///
@@ -933,102 +3927,16 @@ impl<B: UsbBus, M: DfuMemory> DfuClass<B, M> {
     // }
 
     fn update_impl(&mut self) {
-        match self.status.pending {
-            Command::EraseAll => match self.mem.erase_all() {
-                Err(e) => self.status.new_state_status(DfuState::DfuError, e.into()),
-                Ok(_) => self.status.new_state_ok(DfuState::DfuDnloadSync),
-            },
-            Command::Erase(b) => match self.mem.erase(b) {
-                Err(e) => self.status.new_state_status(DfuState::DfuError, e.into()),
-                Ok(_) => self.status.new_state_ok(DfuState::DfuDnloadSync),
-            },
-            Command::LeaveDfu => {
-                // may not return
-                let mr = self.mem.manifestation();
-
-                match mr {
-                    Err(e) => self.status.new_state_status(DfuState::DfuError, e.into()),
-                    Ok(_) => {
-                        if M::MANIFESTATION_TOLERANT {
-                            self.status.new_state_ok(DfuState::DfuManifestSync)
-                        } else {
-                            self.status.new_state_ok(DfuState::DfuManifestWaitReset)
-                        }
-                    }
-                }
-            }
-            Command::ReadUnprotect => {
-                // XXX not implemented
-                // self.status.state = DfuState::DfuDnloadSync;
-                self.status
-                    .new_state_status(DfuState::DfuError, DfuStatusCode::ErrStalledPkt)
-            }
-            Command::WriteMemory { block_num, len } => {
-                if let Some(pointer) = self
-                    .status
-                    .address_pointer
-                    .checked_add((block_num as u32) * (M::TRANSFER_SIZE as u32))
-                {
-                    match self.mem.program(pointer, len as usize) {
-                        Err(e) => self.status.new_state_status(DfuState::DfuError, e.into()),
-                        Ok(_) => self.status.new_state_ok(DfuState::DfuDnloadSync),
-                    }
-                } else {
-                    // overflow
-                    self.status
-                        .new_state_status(DfuState::DfuError, DfuStatusCode::ErrAddress);
-                }
-            }
-            Command::SetAddressPointer(p) => {
-                self.status.address_pointer = p;
-                self.status.new_state_ok(DfuState::DfuDnloadSync)
-            }
-            Command::None => {}
-        }
-        self.status.pending = Command::None;
-    }
-
-    fn process(&mut self) -> bool {
-        let initial_state = self.status.state();
-        if initial_state == DfuState::DfuDnloadSync {
-            match self.status.command {
-                Command::WriteMemory {
-                    block_num: _,
-                    len: _,
-                }
-                | Command::SetAddressPointer(_)
-                | Command::ReadUnprotect
-                | Command::EraseAll
-                | Command::Erase(_) => {
-                    self.status.pending = self.status.command;
-                    self.status.command = Command::None;
-                    self.status.new_state_ok(DfuState::DfuDnBusy);
-                }
-                //Command::None => {}
-                _ => {
-                    self.status.new_state_ok(DfuState::DfuDnloadIdle);
-                }
-            }
-        } else if initial_state == DfuState::DfuManifestSync {
-            match self.status.command {
-                Command::None => {
-                    if M::MANIFESTATION_TOLERANT {
-                        // Leave manifestation, back to Idle
-                        self.status.command = Command::None;
-                        self.status.new_state_ok(DfuState::DfuIdle);
-                    }
-                }
-                _ => {
-                    // Start manifestation
-                    self.status.pending = self.status.command;
-                    self.status.command = Command::None;
-                    self.status.new_state_ok(DfuState::DfuManifest);
-                }
+        while let Some(op) = self.status.next_operation() {
+            let start = match self.status.take_download_start(&op) {
+                true => start_download(&mut self.mem),
+                false => Ok(()),
+            };
+            if let Some((block_num, length)) = self.status.take_block_size_mismatch() {
+                self.mem.block_size_mismatch(block_num, length);
             }
-        } else if initial_state == DfuState::DfuDnBusy {
-            return false;
+            let result = start.and_then(|_| op.execute(&mut self.mem, self.status.payload()));
+            self.status.complete(result);
         }
-
-        true
     }
 }
@@ -0,0 +1,472 @@
+//! Protocol conformance checks
+//!
+//! [`run()`] drives [`DfuClass`] with a [`DfuMemory`] implementation through
+//! scripted DFU sessions on the emulated bus of `usbd-class-tester`: erase, download,
+//! upload of the downloaded data, manifestation, abort, and recovery after rejected
+//! requests. Every session gets a new memory, so the checks are independent.
+//!
+//! The checks use the area at [`INITIAL_ADDRESS_POINTER`](DfuMemory::INITIAL_ADDRESS_POINTER),
+//! two blocks of [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE) bytes are programmed.
+//!
+//! Requires `test-helpers` feature, which depends on `std`.
+//!
+//! ```no_run
+//! # use usbd_dfu::DfuMemory;
+//! # struct MyFlash;
+//! # impl MyFlash {
+//! #     fn new() -> Self {
+//! #         MyFlash
+//! #     }
+//! # }
+//! # impl DfuMemory for MyFlash {
+//! #     const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+//! #     const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*1Kg";
+//! #     const PROGRAM_TIME_MS: u32 = 10;
+//! #     const ERASE_TIME_MS: u32 = 20;
+//! #     const FULL_ERASE_TIME_MS: u32 = 320;
+//! # }
+//! #[test]
+//! fn test_conformance() {
+//!     let report = usbd_dfu::conformance::run(|| MyFlash::new());
+//!     assert!(report.passed(), "{report}");
+//! }
+//! # fn main() {}
+//! ```
+
+use core::cell::RefCell;
+use std::format;
+use std::string::String;
+use std::vec::Vec;
+
+use usb_device::bus::UsbBusAllocator;
+use usbd_class_tester::prelude::*;
+
+use crate::class::{DfuClass, DfuMemory, DfuState, DfuStatusCode};
+use crate::consts::{DFUSE_ERASE, DFUSE_GET_COMMANDS, DFUSE_SET_ADDRESS_POINTER};
+use crate::mem_info::{self, Operations};
+use crate::test_helpers::DeviceExt;
+
+/// Number of data blocks of a download.
+const BLOCKS: u16 = 2;
+
+/// Maximum number of `DFU_GETSTATUS` requests while the device is busy.
+const MAX_POLLS: usize = 100;
+
+std::thread_local! {
+    /// Outcome of the last session, `with_usb()` can't return it.
+    static OUTCOME: RefCell<Option<Outcome>> = const { RefCell::new(None) };
+}
+
+/// Result of a conformance check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The device behaved as expected.
+    Pass,
+    /// The device did not behave as expected, with a description.
+    Fail(String),
+    /// The check does not apply to the memory, with a reason.
+    Skip(&'static str),
+}
+
+/// Conformance check and its [`Outcome`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    /// Name of the check.
+    pub name: &'static str,
+    /// Result of the check.
+    pub outcome: Outcome,
+}
+
+/// Results of [`run()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// All checks, in the order they were run.
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Returns `true` if no check failed.
+    pub fn passed(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// Returns failed checks.
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks
+            .iter()
+            .filter(|c| matches!(c.outcome, Outcome::Fail(_)))
+    }
+}
+
+impl core::fmt::Display for Report {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for check in &self.checks {
+            match &check.outcome {
+                Outcome::Pass => writeln!(f, "pass {}", check.name)?,
+                Outcome::Fail(e) => writeln!(f, "FAIL {}: {}", check.name, e)?,
+                Outcome::Skip(reason) => writeln!(f, "skip {}: {}", check.name, reason)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run all checks with memories returned by `mem`.
+pub fn run<M: DfuMemory + 'static, F: FnMut() -> M>(mut mem: F) -> Report {
+    let checks: [(&'static str, Session<M, F>); 7] = [
+        ("get_commands", check_get_commands),
+        ("erase", check_erase),
+        ("download", check_download),
+        ("upload", check_upload),
+        ("manifestation", check_manifestation),
+        ("abort", check_abort),
+        ("error_recovery", check_error_recovery),
+    ];
+
+    let mut report = Report { checks: Vec::new() };
+    for (name, check) in checks {
+        let ctx = Ctx { mem: &mut mem };
+        let outcome = match ctx.with_usb(check) {
+            Ok(()) => OUTCOME
+                .take()
+                .unwrap_or_else(|| Outcome::Fail("session ended without an outcome".into())),
+            Err(e) => Outcome::Fail(format!("emulated device failed: {e:?}")),
+        };
+        report.checks.push(Check { name, outcome });
+    }
+    report
+}
+
+/// Device context that creates [`DfuClass`] with a new memory.
+struct Ctx<'f, F> {
+    mem: &'f mut F,
+}
+
+impl<M: DfuMemory + 'static, F: FnMut() -> M> UsbDeviceCtx for Ctx<'_, F> {
+    type C<'c> = DfuClass<EmulatedUsbBus, M>;
+
+    fn create_class(
+        &mut self,
+        alloc: &UsbBusAllocator<EmulatedUsbBus>,
+    ) -> AnyResult<DfuClass<EmulatedUsbBus, M>> {
+        Ok(DfuClass::new(alloc, (self.mem)()))
+    }
+}
+
+type Dev<'a, 'f, M, F> = Device<'a, DfuClass<EmulatedUsbBus, M>, Ctx<'f, F>>;
+
+/// Check function, a session with a new device.
+type Session<M, F> = for<'a, 'f> fn(DfuClass<EmulatedUsbBus, M>, Dev<'a, 'f, M, F>);
+
+/// Convert a check result to [`Outcome`] of the session, failures include the
+/// last memory error.
+fn finish<M: DfuMemory>(dfu: &DfuClass<EmulatedUsbBus, M>, result: Result<Outcome, String>) {
+    let outcome = match result {
+        Ok(outcome) => outcome,
+        Err(e) => match dfu.last_memory_error() {
+            Some(detail) => Outcome::Fail(format!("{e}, memory error {detail:?}")),
+            None => Outcome::Fail(e),
+        },
+    };
+    OUTCOME.set(Some(outcome));
+}
+
+fn can_download<M: DfuMemory>() -> bool {
+    M::HAS_DOWNLOAD && cfg!(feature = "download")
+}
+
+fn can_upload<M: DfuMemory>() -> bool {
+    M::HAS_UPLOAD && cfg!(feature = "upload")
+}
+
+/// Operations of the area used by the checks, all of them if it's not declared.
+fn operations<M: DfuMemory>() -> Operations {
+    let length = M::TRANSFER_SIZE as u32 * BLOCKS as u32;
+    mem_info::operations(M::MEM_INFO_STRING, M::INITIAL_ADDRESS_POINTER, length)
+        .unwrap_or(Operations::ALL)
+}
+
+/// Data of download block `block_num`.
+fn block_data<M: DfuMemory>(block_num: u16) -> Vec<u8> {
+    (0..M::TRANSFER_SIZE as usize)
+        .map(|i| (i as u8).wrapping_mul(7) ^ (block_num as u8).wrapping_add(0x5a))
+        .collect()
+}
+
+/// Send `DFU_GETSTATUS`, returns the status and the state.
+fn get_status<M: DfuMemory + 'static, F: FnMut() -> M>(
+    dfu: &mut DfuClass<EmulatedUsbBus, M>,
+    dev: &mut Dev<'_, '_, M, F>,
+) -> Result<(DfuStatusCode, DfuState), String> {
+    let reply = dev
+        .get_status(dfu)
+        .map_err(|e| format!("DFU_GETSTATUS failed: {e:?}"))?;
+    if reply.len() != 6 {
+        return Err(format!("DFU_GETSTATUS reply is {} bytes", reply.len()));
+    }
+    let status = DfuStatusCode::try_from(reply[0])
+        .map_err(|s| format!("DFU_GETSTATUS reply has invalid status {s}"))?;
+    let state = DfuState::try_from(reply[4])
+        .map_err(|s| format!("DFU_GETSTATUS reply has invalid state {s}"))?;
+    Ok((status, state))
+}
+
+/// Poll the status until the device is not busy.
+fn wait<M: DfuMemory + 'static, F: FnMut() -> M>(
+    dfu: &mut DfuClass<EmulatedUsbBus, M>,
+    dev: &mut Dev<'_, '_, M, F>,
+) -> Result<(DfuStatusCode, DfuState), String> {
+    for _ in 0..MAX_POLLS {
+        let (status, state) = get_status(dfu, dev)?;
+        let busy = match state {
+            DfuState::DfuDnloadSync | DfuState::DfuDnBusy | DfuState::DfuManifestSync => true,
+            DfuState::DfuManifest => M::MANIFESTATION_TOLERANT,
+            _ => false,
+        };
+        if !busy || status != DfuStatusCode::Ok {
+            return Ok((status, state));
+        }
+    }
+    Err(format!(
+        "device is busy after {MAX_POLLS} DFU_GETSTATUS requests"
+    ))
+}
+
+fn expect(what: &str, got: (DfuStatusCode, DfuState), state: DfuState) -> Result<(), String> {
+    if got == (DfuStatusCode::Ok, state) {
+        return Ok(());
+    }
+    Err(format!(
+        "{what}: expected {state}, got {} with {}",
+        got.1, got.0
+    ))
+}
+
+/// Execute a DfuSe command and wait for it.
+fn command<M: DfuMemory + 'static, F: FnMut() -> M>(
+    dfu: &mut DfuClass<EmulatedUsbBus, M>,
+    dev: &mut Dev<'_, '_, M, F>,
+    what: &str,
+    opcode: u8,
+    address: u32,
+) -> Result<(), String> {
+    let mut data = [opcode, 0, 0, 0, 0];
+    data[1..].copy_from_slice(&address.to_le_bytes());
+    dev.download(dfu, 0, &data)
+        .map_err(|e| format!("{what} is rejected: {e:?}"))?;
+    expect(what, wait(dfu, dev)?, DfuState::DfuDnloadIdle)
+}
+
+/// Erase the area if it's erasable, and download all blocks.
+fn download<M: DfuMemory + 'static, F: FnMut() -> M>(
+    dfu: &mut DfuClass<EmulatedUsbBus, M>,
+    dev: &mut Dev<'_, '_, M, F>,
+) -> Result<(), String> {
+    let address = M::INITIAL_ADDRESS_POINTER;
+    if operations::<M>().contains(Operations::ERASE) {
+        command(dfu, dev, "erase", DFUSE_ERASE, address)?;
+    }
+    command(
+        dfu,
+        dev,
+        "set address pointer",
+        DFUSE_SET_ADDRESS_POINTER,
+        address,
+    )?;
+    for block_num in 2..2 + BLOCKS {
+        let what = format!("block {block_num}");
+        dev.download(dfu, block_num, &block_data::<M>(block_num))
+            .map_err(|e| format!("{what} is rejected: {e:?}"))?;
+        expect(&what, wait(dfu, dev)?, DfuState::DfuDnloadIdle)?;
+    }
+    Ok(())
+}
+
+fn check_get_commands<M: DfuMemory + 'static, F: FnMut() -> M>(
+    mut dfu: DfuClass<EmulatedUsbBus, M>,
+    mut dev: Dev<'_, '_, M, F>,
+) {
+    let result = (|| {
+        if !cfg!(feature = "upload") {
+            return Ok(Outcome::Skip("upload feature is disabled"));
+        }
+        let reply = dev
+            .upload(&mut dfu, 0, M::TRANSFER_SIZE as usize)
+            .map_err(|e| format!("Get Commands is rejected: {e:?}"))?;
+        for opcode in [DFUSE_GET_COMMANDS, DFUSE_SET_ADDRESS_POINTER, DFUSE_ERASE] {
+            if !reply.contains(&opcode) {
+                return Err(format!(
+                    "Get Commands reply {reply:02x?} has no {opcode:#04x}"
+                ));
+            }
+        }
+        expect(
+            "after Get Commands",
+            get_status(&mut dfu, &mut dev)?,
+            DfuState::DfuIdle,
+        )?;
+        Ok(Outcome::Pass)
+    })();
+    finish(&dfu, result);
+}
+
+fn check_erase<M: DfuMemory + 'static, F: FnMut() -> M>(
+    mut dfu: DfuClass<EmulatedUsbBus, M>,
+    mut dev: Dev<'_, '_, M, F>,
+) {
+    let result = (|| {
+        if !can_download::<M>() {
+            return Ok(Outcome::Skip("download is not supported"));
+        }
+        if !operations::<M>().contains(Operations::ERASE) {
+            return Ok(Outcome::Skip("area is not erasable"));
+        }
+        command(
+            &mut dfu,
+            &mut dev,
+            "erase",
+            DFUSE_ERASE,
+            M::INITIAL_ADDRESS_POINTER,
+        )?;
+        if dfu.get_address_pointer() != M::INITIAL_ADDRESS_POINTER {
+            return Err("erase changed the address pointer".into());
+        }
+        Ok(Outcome::Pass)
+    })();
+    finish(&dfu, result);
+}
+
+fn check_download<M: DfuMemory + 'static, F: FnMut() -> M>(
+    mut dfu: DfuClass<EmulatedUsbBus, M>,
+    mut dev: Dev<'_, '_, M, F>,
+) {
+    let result = (|| {
+        if !can_download::<M>() {
+            return Ok(Outcome::Skip("download is not supported"));
+        }
+        download(&mut dfu, &mut dev)?;
+        Ok(Outcome::Pass)
+    })();
+    finish(&dfu, result);
+}
+
+fn check_upload<M: DfuMemory + 'static, F: FnMut() -> M>(
+    mut dfu: DfuClass<EmulatedUsbBus, M>,
+    mut dev: Dev<'_, '_, M, F>,
+) {
+    let result = (|| {
+        if !can_download::<M>() || !can_upload::<M>() {
+            return Ok(Outcome::Skip("download and upload are not supported"));
+        }
+        if !operations::<M>().contains(Operations::READ) {
+            return Ok(Outcome::Skip("area is not readable"));
+        }
+        download(&mut dfu, &mut dev)?;
+        dev.abort(&mut dfu)
+            .map_err(|e| format!("DFU_ABORT is rejected: {e:?}"))?;
+
+        // uploaded data is the downloaded one
+        for block_num in 2..2 + BLOCKS {
+            let data = dev
+                .upload(&mut dfu, block_num, M::TRANSFER_SIZE as usize)
+                .map_err(|e| format!("upload of block {block_num} is rejected: {e:?}"))?;
+            if data != block_data::<M>(block_num) {
+                return Err(format!("block {block_num} data differs after upload"));
+            }
+        }
+        dev.abort(&mut dfu)
+            .map_err(|e| format!("DFU_ABORT is rejected: {e:?}"))?;
+        Ok(Outcome::Pass)
+    })();
+    finish(&dfu, result);
+}
+
+fn check_manifestation<M: DfuMemory + 'static, F: FnMut() -> M>(
+    mut dfu: DfuClass<EmulatedUsbBus, M>,
+    mut dev: Dev<'_, '_, M, F>,
+) {
+    let result = (|| {
+        if !can_download::<M>() {
+            return Ok(Outcome::Skip("download is not supported"));
+        }
+        download(&mut dfu, &mut dev)?;
+        dev.download(&mut dfu, 2 + BLOCKS, &[])
+            .map_err(|e| format!("zero-length DFU_DNLOAD is rejected: {e:?}"))?;
+        let got = wait(&mut dfu, &mut dev)?;
+        if M::MANIFESTATION_TOLERANT {
+            expect("manifestation", got, DfuState::DfuIdle)?;
+        } else if got != (DfuStatusCode::Ok, DfuState::DfuManifest)
+            && got != (DfuStatusCode::Ok, DfuState::DfuManifestWaitReset)
+        {
+            expect("manifestation", got, DfuState::DfuManifestWaitReset)?;
+        }
+        Ok(Outcome::Pass)
+    })();
+    finish(&dfu, result);
+}
+
+fn check_abort<M: DfuMemory + 'static, F: FnMut() -> M>(
+    mut dfu: DfuClass<EmulatedUsbBus, M>,
+    mut dev: Dev<'_, '_, M, F>,
+) {
+    let result = (|| {
+        if !can_download::<M>() {
+            return Ok(Outcome::Skip("download is not supported"));
+        }
+        download(&mut dfu, &mut dev)?;
+        dev.abort(&mut dfu)
+            .map_err(|e| format!("DFU_ABORT is rejected: {e:?}"))?;
+        expect(
+            "after DFU_ABORT",
+            get_status(&mut dfu, &mut dev)?,
+            DfuState::DfuIdle,
+        )?;
+
+        // a new download starts after the abort
+        download(&mut dfu, &mut dev)?;
+        Ok(Outcome::Pass)
+    })();
+    finish(&dfu, result);
+}
+
+fn check_error_recovery<M: DfuMemory + 'static, F: FnMut() -> M>(
+    mut dfu: DfuClass<EmulatedUsbBus, M>,
+    mut dev: Dev<'_, '_, M, F>,
+) {
+    let result = (|| {
+        // unsupported request is rejected and does not change the state
+        if dev.write(&mut dfu, 0x07, 0, 0, 0, &[]).is_ok() {
+            return Err("unsupported request 0x07 is accepted".into());
+        }
+        expect(
+            "after unsupported request",
+            get_status(&mut dfu, &mut dev)?,
+            DfuState::DfuIdle,
+        )?;
+
+        // DFU_GETSTATUS with a short data stage
+        if dev.read(&mut dfu, 0x03, 0, 0, 1).is_ok() {
+            return Err("DFU_GETSTATUS with wLength 1 is accepted".into());
+        }
+        let got = get_status(&mut dfu, &mut dev)?;
+        if got != (DfuStatusCode::ErrStalledPkt, DfuState::DfuError) {
+            return Err(format!(
+                "after rejected DFU_GETSTATUS: expected dfuERROR with errSTALLEDPKT, got {} with {}",
+                got.1, got.0
+            ));
+        }
+        dev.clear_status(&mut dfu)
+            .map_err(|e| format!("DFU_CLRSTATUS is rejected: {e:?}"))?;
+        expect(
+            "after DFU_CLRSTATUS",
+            get_status(&mut dfu, &mut dev)?,
+            DfuState::DfuIdle,
+        )?;
+
+        if can_download::<M>() {
+            download(&mut dfu, &mut dev)?;
+        }
+        Ok(Outcome::Pass)
+    })();
+    finish(&dfu, result);
+}
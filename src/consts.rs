@@ -0,0 +1,86 @@
+//! DFU protocol constants
+//!
+//! Request codes, descriptor values and DfuSe command opcodes used by the class,
+//! for host-side tools and tests that talk to the device:
+//!
+//! ```
+//! use usbd_dfu::consts::DFUSE_SET_ADDRESS_POINTER;
+//!
+//! // data of block 0 of `DFU_DNLOAD`
+//! let mut command = [DFUSE_SET_ADDRESS_POINTER, 0, 0, 0, 0];
+//! command[1..].copy_from_slice(&0x0800_0000u32.to_le_bytes());
+//! assert_eq!(command, [0x21, 0x00, 0x00, 0x00, 0x08]);
+//! ```
+
+/// *bInterfaceClass* of DFU interface.
+pub const USB_CLASS_APPLICATION_SPECIFIC: u8 = 0xFE;
+/// *bInterfaceSubClass* of DFU interface.
+pub const USB_SUBCLASS_DFU: u8 = 0x01;
+/// *bInterfaceProtocol* of DFU interface in run-time mode.
+pub const USB_PROTOCOL_RUN_TIME: u8 = 0x01;
+/// *bInterfaceProtocol* of DFU interface in DFU mode.
+pub const USB_PROTOCOL_DFU_MODE: u8 = 0x02;
+/// *bDescriptorType* of DFU Functional descriptor.
+pub const DESC_DESCTYPE_DFU: u8 = 0x21;
+
+/// `DFU_DETACH` request.
+pub const DFU_DETACH: u8 = 0x00;
+/// `DFU_DNLOAD` request.
+pub const DFU_DNLOAD: u8 = 0x01;
+/// `DFU_UPLOAD` request.
+pub const DFU_UPLOAD: u8 = 0x02;
+/// `DFU_GETSTATUS` request.
+pub const DFU_GETSTATUS: u8 = 0x03;
+/// `DFU_CLRSTATUS` request.
+pub const DFU_CLRSTATUS: u8 = 0x04;
+/// `DFU_GETSTATE` request.
+pub const DFU_GETSTATE: u8 = 0x05;
+/// `DFU_ABORT` request.
+pub const DFU_ABORT: u8 = 0x06;
+
+/// DfuSe *Get Commands* command, the reply is read with `DFU_UPLOAD` of block 0.
+pub const DFUSE_GET_COMMANDS: u8 = 0x00;
+/// DfuSe *Set Address Pointer* command, followed by 4 bytes of address.
+pub const DFUSE_SET_ADDRESS_POINTER: u8 = 0x21;
+/// DfuSe *Erase* command, followed by 4 bytes of page address, or alone for mass erase.
+pub const DFUSE_ERASE: u8 = 0x41;
+/// DfuSe *Read Unprotect* command.
+pub const DFUSE_READ_UNPROTECT: u8 = 0x92;
+
+/// Vendor-specific *Set Image CRC* command, see
+/// [`DfuMemory::IMAGE_CRC_COMMAND`](crate::DfuMemory::IMAGE_CRC_COMMAND).
+pub const CMD_SET_IMAGE_CRC: u8 = 0xB1;
+/// Vendor-specific *Resume* command, see
+/// [`DfuMemory::RESUME_COMMAND`](crate::DfuMemory::RESUME_COMMAND).
+pub const CMD_RESUME: u8 = 0xB2;
+/// Vendor-specific *Erase Range* command, see
+/// [`DfuMemory::ERASE_RANGE_COMMAND`](crate::DfuMemory::ERASE_RANGE_COMMAND).
+pub const CMD_ERASE_RANGE: u8 = 0xB3;
+/// Vendor-specific *Unlock Mass Erase* command, see
+/// [`DfuMemory::MASS_ERASE_GUARD`](crate::DfuMemory::MASS_ERASE_GUARD).
+pub const CMD_UNLOCK_MASS_ERASE: u8 = 0xB4;
+/// Vendor-specific *Set Image Size* command, see
+/// [`DfuMemory::IMAGE_SIZE_COMMAND`](crate::DfuMemory::IMAGE_SIZE_COMMAND).
+pub const CMD_SET_IMAGE_SIZE: u8 = 0xB5;
+/// Vendor-specific *Unlock* command, see
+/// [`DfuMemory::UNLOCK_COMMAND`](crate::DfuMemory::UNLOCK_COMMAND).
+pub const CMD_UNLOCK: u8 = 0xB6;
+/// Vendor-specific *Readout Protection* command, see
+/// [`DfuMemory::READOUT_PROTECTION_COMMANDS`](crate::DfuMemory::READOUT_PROTECTION_COMMANDS).
+pub const CMD_READOUT_PROTECTION: u8 = 0xB7;
+/// Vendor-specific *Get Device Info* command, see
+/// [`DfuMemory::DEVICE_INFO_COMMAND`](crate::DfuMemory::DEVICE_INFO_COMMAND).
+pub const CMD_GET_DEVICE_INFO: u8 = 0xB8;
+/// Vendor-specific *Set Parameters* command, see
+/// [`DfuMemory::TLV_COMMANDS`](crate::DfuMemory::TLV_COMMANDS).
+pub const CMD_SET_PARAMETERS: u8 = 0xB9;
+/// Vendor-specific *Get Capabilities* command, see
+/// [`DfuMemory::TLV_COMMANDS`](crate::DfuMemory::TLV_COMMANDS).
+pub const CMD_GET_CAPABILITIES: u8 = 0xBA;
+/// Vendor-specific *Verify* command, see
+/// [`DfuMemory::VERIFY_COMMAND`](crate::DfuMemory::VERIFY_COMMAND).
+pub const CMD_VERIFY: u8 = 0xBC;
+
+/// *bRequest* of vendor-specific *Get Extended Status* request to the DFU interface, see
+/// [`DfuMemory::EXTENDED_STATUS_REQUEST`](crate::DfuMemory::EXTENDED_STATUS_REQUEST).
+pub const VENDOR_GET_EXTENDED_STATUS: u8 = 0xBB;
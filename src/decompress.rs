@@ -0,0 +1,223 @@
+//! Decompression of downloaded data
+//!
+//! [`DecompressMemory`] wraps [`DfuMemory`] and passes downloaded blocks through
+//! a streaming [`Decompressor`], e.g. heatshrink or LZ4. Decompressed data is
+//! programmed in blocks of [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE) bytes,
+//! one after another, starting at the address of the first downloaded block.
+//! A single downloaded block may produce several programmed blocks, and the last
+//! incomplete block is programmed before manifestation.
+//!
+//! [`PROGRAM_TIME_MS`](DfuMemory::PROGRAM_TIME_MS) of the wrapped memory should
+//! account for the expected compression ratio.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::decompress::*;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # fn example<B: UsbBus>(
+//! #     usb_bus_alloc: UsbBusAllocator<B>,
+//! #     my_mem: impl DfuMemory,
+//! #     my_heatshrink_decoder: impl Decompressor,
+//! # ) {
+//! let mem = DecompressMemory::<_, _, 128>::new(my_mem, my_heatshrink_decoder);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError};
+
+/// Streaming decompressor.
+pub trait Decompressor {
+    /// Start a new stream.
+    fn reset(&mut self);
+
+    /// Decompress a part of `input` to `output`.
+    ///
+    /// Returns the number of consumed input bytes and the number of produced output bytes.
+    /// While `input` is not empty, at least one byte must be consumed or produced.
+    fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(usize, usize), DfuMemoryError>;
+
+    /// Write remaining output at the end of the stream.
+    ///
+    /// Returns the number of produced bytes, `0` when the stream is complete.
+    fn finish(&mut self, output: &mut [u8]) -> Result<usize, DfuMemoryError>;
+}
+
+/// [`DfuMemory`] adapter that decompresses downloaded data with a [`Decompressor`].
+///
+/// `N` is the size of the internal buffers, it must be at least `TRANSFER_SIZE` bytes.
+pub struct DecompressMemory<M: DfuMemory, D: Decompressor, const N: usize> {
+    mem: M,
+    decompressor: D,
+    input: [u8; N],
+    output: [u8; N],
+    output_len: usize,
+    /// Address of the next programmed block, set by the first downloaded block.
+    address: Option<u32>,
+}
+
+impl<M: DfuMemory, D: Decompressor, const N: usize> DecompressMemory<M, D, N> {
+    /// Wrap `mem`, and decompress data with `decompressor`.
+    pub fn new(mem: M, mut decompressor: D) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize,
+                "DecompressMemory buffer is too small"
+            )
+        };
+
+        decompressor.reset();
+        Self {
+            mem,
+            decompressor,
+            input: [0; N],
+            output: [0; N],
+            output_len: 0,
+            address: None,
+        }
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    fn restart(&mut self) {
+        self.decompressor.reset();
+        self.output_len = 0;
+        self.address = None;
+    }
+
+    /// Program decompressed data.
+    fn flush(&mut self) -> Result<(), DfuMemoryError> {
+        let len = self.output_len;
+        let address = self.address.ok_or(DfuMemoryError::Unknown)?;
+        if len == 0 {
+            return Ok(());
+        }
+
+        self.mem
+            .store_write_buffer(&self.output[..len])
+            .map_err(|_| DfuMemoryError::Prog)?;
+        self.mem.program(address, len)?;
+        self.output_len = 0;
+        self.address = Some(
+            address
+                .checked_add(len as u32)
+                .ok_or(DfuMemoryError::Address)?,
+        );
+        Ok(())
+    }
+
+    /// Decompress and program `input[..length]`.
+    fn decompress(&mut self, length: usize) -> Result<(), DfuMemoryError> {
+        let block = M::TRANSFER_SIZE as usize;
+        let mut pos = 0;
+
+        loop {
+            let (consumed, produced) = self.decompressor.decompress(
+                &self.input[pos..length],
+                &mut self.output[self.output_len..block],
+            )?;
+            pos += consumed;
+            self.output_len += produced;
+
+            if self.output_len == block {
+                self.flush()?;
+            } else if consumed == 0 && produced == 0 {
+                break;
+            }
+        }
+
+        if pos != length {
+            // decompressor is stuck
+            return Err(DfuMemoryError::File);
+        }
+        Ok(())
+    }
+
+    /// Program the rest of the stream.
+    fn finish(&mut self) -> Result<(), DfuMemoryError> {
+        let block = M::TRANSFER_SIZE as usize;
+
+        loop {
+            let produced = self
+                .decompressor
+                .finish(&mut self.output[self.output_len..block])?;
+            self.output_len += produced;
+
+            if self.output_len == block || produced == 0 {
+                self.flush()?;
+            }
+            if produced == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<M: DfuMemory, D: Decompressor, const N: usize> DfuMemory for DecompressMemory<M, D, N> {
+    forward_dfu_memory!(
+        mem: M,
+        except RESUME_COMMAND, IMAGE_SIZE_COMMAND, MIN_IMAGE_SIZE, WRITE_ONCE, SKIP_IDENTICAL,
+        VERIFY_COMMAND, store_write_buffer, compare, program, manifestation, download_start,
+        resume_point
+    );
+
+    // the decompressor window of an interrupted download is lost
+    const RESUME_COMMAND: bool = false;
+
+    // the image in the memory is not the downloaded data
+    const IMAGE_SIZE_COMMAND: bool = false;
+    const MIN_IMAGE_SIZE: u32 = 0;
+
+    // the memory holds the decompressed image, not the compressed blocks
+    const VERIFY_COMMAND: bool = false;
+    const WRITE_ONCE: bool = false;
+    const SKIP_IDENTICAL: bool = false;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        // wrapped memory gets decompressed blocks when they are programmed
+        self.input
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        if length > N {
+            return Err(DfuMemoryError::Prog);
+        }
+        if self.address.is_none() {
+            self.address = Some(address);
+        }
+        self.decompress(length)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        if self.address.is_some() {
+            let result = self.finish();
+            self.restart();
+            result.map_err(|e| match e {
+                DfuMemoryError::File => DfuManifestationError::File,
+                _ => DfuManifestationError::Unknown,
+            })?;
+        }
+        self.mem.manifestation()
+    }
+
+    fn download_start(&mut self) {
+        self.restart();
+        self.mem.download_start()
+    }
+}
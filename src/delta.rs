@@ -0,0 +1,358 @@
+//! Delta updates
+//!
+//! [`DeltaMemory`] wraps [`DfuMemory`] and treats downloaded data as a patch
+//! that describes the new image in terms of the old one. The old image is read
+//! with [`DfuMemory::read()`], the new image is programmed in blocks of
+//! [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE) bytes, one after another,
+//! starting at the address of the first downloaded block. The new image must
+//! not overlap the old one.
+//!
+//! ### Patch format
+//!
+//! All numbers are little-endian `u32`. The patch starts with a header:
+//!
+//! | Size | Field                    |
+//! |------|--------------------------|
+//! | 4    | Magic, `DPAT`            |
+//! | 4    | Size of the new image    |
+//!
+//! followed by commands:
+//!
+//! | Code   | Arguments                | Description                                   |
+//! |--------|--------------------------|-----------------------------------------------|
+//! | `0x01` | *offset*, *len*          | Copy *len* bytes of the old image at *offset* |
+//! | `0x02` | *offset*, *len*, data    | Add *len* bytes of data to the old image bytes at *offset*, modulo 256 |
+//! | `0x03` | *len*, data              | Insert *len* bytes of data                    |
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::delta::DeltaMemory;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # const OLD_IMAGE_ADDRESS: u32 = 0x0800_8000;
+//! # const OLD_IMAGE_SIZE: u32 = 96 * 1024;
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: impl DfuMemory) {
+//! let mem = DeltaMemory::<_, 128>::new(my_mem, OLD_IMAGE_ADDRESS, OLD_IMAGE_SIZE);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError};
+
+/// Patch magic.
+pub const PATCH_MAGIC: [u8; 4] = *b"DPAT";
+
+/// Size of the patch header in bytes.
+pub const PATCH_HEADER_LENGTH: usize = 8;
+
+/// Copy bytes of the old image.
+pub const PATCH_COPY: u8 = 0x01;
+
+/// Add data to bytes of the old image.
+pub const PATCH_ADD: u8 = 0x02;
+
+/// Insert data.
+pub const PATCH_INSERT: u8 = 0x03;
+
+/// Maximum size of a header or a command without data.
+const MAX_FIELDS_LENGTH: usize = 9;
+
+#[derive(Clone, Copy)]
+enum State {
+    /// Receiving the header or a command, and the number of received bytes.
+    Fields(usize),
+    Copy {
+        offset: u32,
+        len: u32,
+    },
+    Add {
+        offset: u32,
+        len: u32,
+    },
+    Insert {
+        len: u32,
+    },
+}
+
+/// [`DfuMemory`] adapter that applies downloaded delta patch to the old image.
+///
+/// `N` is the size of the internal buffers, it must be at least `TRANSFER_SIZE` bytes.
+pub struct DeltaMemory<M: DfuMemory, const N: usize> {
+    mem: M,
+    old_address: u32,
+    old_size: u32,
+    input: [u8; N],
+    output: [u8; N],
+    output_len: usize,
+    fields: [u8; MAX_FIELDS_LENGTH],
+    state: State,
+    /// Size of the new image, from the header.
+    new_size: Option<u32>,
+    /// Number of produced bytes of the new image.
+    produced: u32,
+    /// Address of the next programmed block, set by the first downloaded block.
+    address: Option<u32>,
+}
+
+fn u32_at(bytes: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+}
+
+impl<M: DfuMemory, const N: usize> DeltaMemory<M, N> {
+    /// Wrap `mem`, the old image of `old_size` bytes is at `old_address`.
+    pub fn new(mem: M, old_address: u32, old_size: u32) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize,
+                "DeltaMemory buffer is too small"
+            )
+        };
+
+        Self {
+            mem,
+            old_address,
+            old_size,
+            input: [0; N],
+            output: [0; N],
+            output_len: 0,
+            fields: [0; MAX_FIELDS_LENGTH],
+            state: State::Fields(0),
+            new_size: None,
+            produced: 0,
+            address: None,
+        }
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    fn restart(&mut self) {
+        self.output_len = 0;
+        self.state = State::Fields(0);
+        self.new_size = None;
+        self.produced = 0;
+        self.address = None;
+    }
+
+    /// Number of header or command bytes, depending on the first byte.
+    fn fields_length(&self) -> Result<usize, DfuMemoryError> {
+        if self.new_size.is_none() {
+            return Ok(PATCH_HEADER_LENGTH);
+        }
+        match self.fields[0] {
+            PATCH_COPY | PATCH_ADD => Ok(9),
+            PATCH_INSERT => Ok(5),
+            _ => Err(DfuMemoryError::File),
+        }
+    }
+
+    /// Start the next command, header or command bytes are complete.
+    fn parse_fields(&mut self) -> Result<State, DfuMemoryError> {
+        let f = &self.fields;
+        if self.new_size.is_none() {
+            if f[..4] != PATCH_MAGIC {
+                return Err(DfuMemoryError::File);
+            }
+            self.new_size = Some(u32_at(f, 4));
+            return Ok(State::Fields(0));
+        }
+
+        let state = match f[0] {
+            PATCH_COPY => State::Copy {
+                offset: u32_at(f, 1),
+                len: u32_at(f, 5),
+            },
+            PATCH_ADD => State::Add {
+                offset: u32_at(f, 1),
+                len: u32_at(f, 5),
+            },
+            _ => State::Insert { len: u32_at(f, 1) },
+        };
+
+        let len = match state {
+            State::Copy { offset, len } | State::Add { offset, len } => {
+                match offset.checked_add(len) {
+                    Some(end) if end <= self.old_size => len,
+                    _ => return Err(DfuMemoryError::File),
+                }
+            }
+            State::Insert { len } => len,
+            State::Fields(_) => 0,
+        };
+        match self.produced.checked_add(len) {
+            Some(end) if Some(end) <= self.new_size => {}
+            _ => return Err(DfuMemoryError::File),
+        }
+        self.produced += len;
+
+        Ok(state)
+    }
+
+    /// Program the new image data.
+    fn flush(&mut self) -> Result<(), DfuMemoryError> {
+        let len = self.output_len;
+        let address = self.address.ok_or(DfuMemoryError::Unknown)?;
+        if len == 0 {
+            return Ok(());
+        }
+
+        self.mem
+            .store_write_buffer(&self.output[..len])
+            .map_err(|_| DfuMemoryError::Prog)?;
+        self.mem.program(address, len)?;
+        self.output_len = 0;
+        self.address = Some(
+            address
+                .checked_add(len as u32)
+                .ok_or(DfuMemoryError::Address)?,
+        );
+        Ok(())
+    }
+
+    /// Copy `len` bytes of the old image at `offset` to the output.
+    fn read_old(&mut self, offset: u32, len: usize) -> Result<(), DfuMemoryError> {
+        let address = self
+            .old_address
+            .checked_add(offset)
+            .ok_or(DfuMemoryError::Address)?;
+        let data = self.mem.read(address, len)?;
+        if data.len() != len {
+            return Err(DfuMemoryError::Address);
+        }
+        self.output[self.output_len..self.output_len + len].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Apply patch bytes in `input[..length]`.
+    fn apply(&mut self, length: usize) -> Result<(), DfuMemoryError> {
+        let block = M::TRANSFER_SIZE as usize;
+        let mut pos = 0;
+
+        loop {
+            let space = block - self.output_len;
+            let available = length - pos;
+
+            let state = match self.state {
+                State::Fields(_) if available == 0 => return Ok(()),
+                State::Fields(received) => {
+                    self.fields[received] = self.input[pos];
+                    pos += 1;
+                    if received + 1 < self.fields_length()? {
+                        State::Fields(received + 1)
+                    } else {
+                        self.parse_fields()?
+                    }
+                }
+                State::Copy { offset, len } => {
+                    let n = space.min(len as usize);
+                    self.read_old(offset, n)?;
+                    self.output_len += n;
+                    State::Copy {
+                        offset: offset + n as u32,
+                        len: len - n as u32,
+                    }
+                }
+                State::Add { .. } | State::Insert { .. } if available == 0 => return Ok(()),
+                State::Add { offset, len } => {
+                    let n = space.min(available).min(len as usize);
+                    self.read_old(offset, n)?;
+                    let output = &mut self.output[self.output_len..self.output_len + n];
+                    for (o, d) in output.iter_mut().zip(&self.input[pos..pos + n]) {
+                        *o = o.wrapping_add(*d);
+                    }
+                    pos += n;
+                    self.output_len += n;
+                    State::Add {
+                        offset: offset + n as u32,
+                        len: len - n as u32,
+                    }
+                }
+                State::Insert { len } => {
+                    let n = space.min(available).min(len as usize);
+                    self.output[self.output_len..self.output_len + n]
+                        .copy_from_slice(&self.input[pos..pos + n]);
+                    pos += n;
+                    self.output_len += n;
+                    State::Insert {
+                        len: len - n as u32,
+                    }
+                }
+            };
+
+            self.state = match state {
+                State::Copy { len: 0, .. }
+                | State::Add { len: 0, .. }
+                | State::Insert { len: 0 } => State::Fields(0),
+                state => state,
+            };
+
+            if self.output_len == block {
+                self.flush()?;
+            }
+        }
+    }
+}
+
+impl<M: DfuMemory, const N: usize> DfuMemory for DeltaMemory<M, N> {
+    forward_dfu_memory!(
+        mem: M,
+        except RESUME_COMMAND, IMAGE_SIZE_COMMAND, MIN_IMAGE_SIZE, WRITE_ONCE, SKIP_IDENTICAL,
+        VERIFY_COMMAND, store_write_buffer, compare, program, manifestation, download_start,
+        resume_point
+    );
+
+    // the patch decoder state of an interrupted download is lost
+    const RESUME_COMMAND: bool = false;
+
+    // the image in the memory is not the downloaded data
+    const IMAGE_SIZE_COMMAND: bool = false;
+    const MIN_IMAGE_SIZE: u32 = 0;
+
+    // the memory holds the patched image, not the patch
+    const VERIFY_COMMAND: bool = false;
+    const WRITE_ONCE: bool = false;
+    const SKIP_IDENTICAL: bool = false;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        // wrapped memory gets new image blocks when they are programmed
+        self.input
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        if length > N {
+            return Err(DfuMemoryError::Prog);
+        }
+        if self.address.is_none() {
+            self.address = Some(address);
+        }
+        self.apply(length)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        let complete =
+            matches!(self.state, State::Fields(0)) && self.new_size == Some(self.produced);
+        let result = if complete {
+            self.flush().map_err(|_| DfuManifestationError::Unknown)
+        } else {
+            Err(DfuManifestationError::NotDone)
+        };
+        self.restart();
+        result?;
+        self.mem.manifestation()
+    }
+
+    fn download_start(&mut self) {
+        self.restart();
+        self.mem.download_start()
+    }
+}
@@ -0,0 +1,484 @@
+//! DfuSe (`.dfu`) file format
+//!
+//! DfuSe files, created by ST tools or `dfuse-pack.py`, contain one or more
+//! targets (alternate settings), each with a list of image elements,
+//! and a standard DFU [`Suffix`](crate::suffix::Suffix) at the end:
+//!
+//! ```text
+//! Prefix: "DfuSe", bVersion, DFUImageSize, bTargets
+//!   Target prefix: "Target", bAlternateSetting, bTargetNamed, szTargetName, dwTargetSize, dwNbElements
+//!     Element: dwElementAddress, dwElementSize, data...
+//!     ...
+//!   ...
+//! Suffix
+//! ```
+//!
+//! [`Parser`] processes a file received in blocks of any size and returns
+//! element data together with its target address, so it can be used on the device.
+//! [`Prefix`], [`TargetPrefix`], and [`ElementHeader`] can be serialized
+//! with `to_bytes()` to build DfuSe files.
+
+/// Size of the DfuSe prefix in bytes.
+pub const PREFIX_LENGTH: usize = 11;
+/// Size of the target prefix in bytes.
+pub const TARGET_PREFIX_LENGTH: usize = 274;
+/// Size of the image element header in bytes.
+pub const ELEMENT_HEADER_LENGTH: usize = 8;
+/// Maximum size of the target name.
+pub const TARGET_NAME_LENGTH: usize = 255;
+
+const PREFIX_SIGNATURE: &[u8; 5] = b"DfuSe";
+const TARGET_SIGNATURE: &[u8; 6] = b"Target";
+const PREFIX_VERSION: u8 = 0x01;
+
+/// Errors that may happen when parsing a DfuSe file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum DfuSeError {
+    /// Input is shorter than the structure.
+    TooShort,
+    /// File does not start with "DfuSe".
+    InvalidSignature,
+    /// *bVersion* is not supported.
+    UnsupportedVersion(u8),
+    /// Target prefix does not start with "Target".
+    InvalidTargetSignature,
+    /// Element size does not fit into the target size.
+    InvalidSize,
+    /// Data after the last element of the last target.
+    TrailingData,
+}
+
+/// Serialization of [`TargetPrefix::name`].
+#[cfg(feature = "serde")]
+mod target_name {
+    use core::fmt;
+
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    use super::TARGET_NAME_LENGTH;
+
+    pub fn serialize<S: Serializer>(
+        name: &[u8; TARGET_NAME_LENGTH],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        match core::str::from_utf8(&name[..len]) {
+            Ok(s) if name[len..].iter().all(|&b| b == 0) => serializer.serialize_str(s),
+            _ => {
+                let len = name.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+                serializer.serialize_bytes(&name[..len])
+            }
+        }
+    }
+
+    struct NameVisitor;
+
+    impl<'de> Visitor<'de> for NameVisitor {
+        type Value = [u8; TARGET_NAME_LENGTH];
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(
+                f,
+                "a string or bytes up to {} bytes long",
+                TARGET_NAME_LENGTH
+            )
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+            let mut name = [0; TARGET_NAME_LENGTH];
+            name.get_mut(..v.len())
+                .ok_or_else(|| E::invalid_length(v.len(), &self))?
+                .copy_from_slice(v);
+            Ok(name)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            self.visit_bytes(v.as_bytes())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut name = [0; TARGET_NAME_LENGTH];
+            let mut len = 0;
+            while let Some(b) = seq.next_element()? {
+                *name
+                    .get_mut(len)
+                    .ok_or_else(|| de::Error::invalid_length(len + 1, &self))? = b;
+                len += 1;
+            }
+            Ok(name)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u8; TARGET_NAME_LENGTH], D::Error> {
+        deserializer.deserialize_any(NameVisitor)
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// DfuSe file prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Prefix {
+    /// Total file size, excluding the DFU suffix.
+    pub image_size: u32,
+    /// Number of targets in the file.
+    pub targets: u8,
+}
+
+impl Prefix {
+    /// Serialize the prefix.
+    pub fn to_bytes(&self) -> [u8; PREFIX_LENGTH] {
+        let mut bytes = [0; PREFIX_LENGTH];
+        bytes[0..5].copy_from_slice(PREFIX_SIGNATURE);
+        bytes[5] = PREFIX_VERSION;
+        bytes[6..10].copy_from_slice(&self.image_size.to_le_bytes());
+        bytes[10] = self.targets;
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Prefix {
+    type Error = DfuSeError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < PREFIX_LENGTH {
+            return Err(DfuSeError::TooShort);
+        }
+        if &bytes[0..5] != PREFIX_SIGNATURE {
+            return Err(DfuSeError::InvalidSignature);
+        }
+        if bytes[5] != PREFIX_VERSION {
+            return Err(DfuSeError::UnsupportedVersion(bytes[5]));
+        }
+
+        Ok(Self {
+            image_size: u32_at(bytes, 6),
+            targets: bytes[10],
+        })
+    }
+}
+
+/// DfuSe target prefix.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TargetPrefix {
+    /// Alternate setting of the DFU interface this target is for.
+    pub alt_setting: u8,
+    /// Target name, zero-padded. Valid if `named` is `true`.
+    ///
+    /// With `serde` feature, it's serialized as a string up to the first zero
+    /// byte, or as bytes without trailing zeros if it's not valid UTF-8.
+    #[cfg_attr(feature = "serde", serde(with = "target_name"))]
+    pub name: [u8; TARGET_NAME_LENGTH],
+    /// *bTargetNamed*
+    pub named: bool,
+    /// Size of all elements of this target, including element headers.
+    pub size: u32,
+    /// Number of elements.
+    pub elements: u32,
+}
+
+impl TargetPrefix {
+    /// Target name, up to the first zero byte, or `None` if target is unnamed
+    /// or the name is not valid UTF-8.
+    pub fn name(&self) -> Option<&str> {
+        if !self.named {
+            return None;
+        }
+        let len = self
+            .name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).ok()
+    }
+
+    /// Serialize the target prefix.
+    pub fn to_bytes(&self) -> [u8; TARGET_PREFIX_LENGTH] {
+        let mut bytes = [0; TARGET_PREFIX_LENGTH];
+        bytes[0..6].copy_from_slice(TARGET_SIGNATURE);
+        bytes[6] = self.alt_setting;
+        bytes[7..11].copy_from_slice(&(self.named as u32).to_le_bytes());
+        bytes[11..266].copy_from_slice(&self.name);
+        bytes[266..270].copy_from_slice(&self.size.to_le_bytes());
+        bytes[270..274].copy_from_slice(&self.elements.to_le_bytes());
+        bytes
+    }
+}
+
+impl core::fmt::Debug for TargetPrefix {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TargetPrefix")
+            .field("alt_setting", &self.alt_setting)
+            .field("name", &self.name())
+            .field("size", &self.size)
+            .field("elements", &self.elements)
+            .finish()
+    }
+}
+
+impl TryFrom<&[u8]> for TargetPrefix {
+    type Error = DfuSeError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < TARGET_PREFIX_LENGTH {
+            return Err(DfuSeError::TooShort);
+        }
+        if &bytes[0..6] != TARGET_SIGNATURE {
+            return Err(DfuSeError::InvalidTargetSignature);
+        }
+
+        let mut name = [0; TARGET_NAME_LENGTH];
+        name.copy_from_slice(&bytes[11..266]);
+
+        Ok(Self {
+            alt_setting: bytes[6],
+            name,
+            named: u32_at(bytes, 7) != 0,
+            size: u32_at(bytes, 266),
+            elements: u32_at(bytes, 270),
+        })
+    }
+}
+
+/// DfuSe image element header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ElementHeader {
+    /// Memory address of the element data.
+    pub address: u32,
+    /// Size of the element data.
+    pub size: u32,
+}
+
+impl ElementHeader {
+    /// Serialize the element header.
+    pub fn to_bytes(&self) -> [u8; ELEMENT_HEADER_LENGTH] {
+        let mut bytes = [0; ELEMENT_HEADER_LENGTH];
+        bytes[0..4].copy_from_slice(&self.address.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.size.to_le_bytes());
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for ElementHeader {
+    type Error = DfuSeError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < ELEMENT_HEADER_LENGTH {
+            return Err(DfuSeError::TooShort);
+        }
+
+        Ok(Self {
+            address: u32_at(bytes, 0),
+            size: u32_at(bytes, 4),
+        })
+    }
+}
+
+/// Item of a DfuSe file returned by [`Parser::push()`].
+// events are passed to the callback and never stored
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum Event<'d> {
+    /// File prefix.
+    Prefix(Prefix),
+    /// Start of a target.
+    Target(TargetPrefix),
+    /// Start of an element.
+    Element(ElementHeader),
+    /// Part of element data, `address` is the memory address of the first byte.
+    Data {
+        /// Memory address of `data[0]`.
+        address: u32,
+        /// Element data.
+        data: &'d [u8],
+    },
+    /// The last element of the last target is complete.
+    End,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+enum ParserState {
+    Prefix,
+    Target,
+    Element,
+    Data { address: u32, remaining: u32 },
+    Done,
+}
+
+/// Streaming DfuSe file parser.
+///
+/// ```
+/// use usbd_dfu::dfuse::{Event, Parser};
+///
+/// # let file: &[u8] = &[];
+/// let mut parser = Parser::new();
+/// for block in file.chunks(128) {
+///     parser.push(block, |event| {
+///         if let Event::Data { address, data } = event {
+///             // program data at address
+///         }
+///     })?;
+/// }
+/// # Ok::<(), usbd_dfu::dfuse::DfuSeError>(())
+/// ```
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Parser {
+    state: ParserState,
+    header: [u8; TARGET_PREFIX_LENGTH],
+    header_len: usize,
+    targets_left: u8,
+    elements_left: u32,
+    target_left: u32,
+}
+
+impl Parser {
+    /// Creates a parser that expects the start of a file.
+    pub const fn new() -> Self {
+        Self {
+            state: ParserState::Prefix,
+            header: [0; TARGET_PREFIX_LENGTH],
+            header_len: 0,
+            targets_left: 0,
+            elements_left: 0,
+            target_left: 0,
+        }
+    }
+
+    /// Returns `true` if the last element of the last target was parsed.
+    pub fn is_done(&self) -> bool {
+        self.state == ParserState::Done
+    }
+
+    /// Parse the next part of the file, `f` is called for each parsed item.
+    ///
+    /// Data after the last element (e.g. DFU suffix) is not parsed,
+    /// and [`DfuSeError::TrailingData`] is returned if any data is pushed
+    /// after [`Event::End`].
+    pub fn push<'d>(
+        &mut self,
+        mut data: &'d [u8],
+        mut f: impl FnMut(Event<'d>),
+    ) -> Result<(), DfuSeError> {
+        while !data.is_empty() {
+            let header_size = match self.state {
+                ParserState::Prefix => PREFIX_LENGTH,
+                ParserState::Target => TARGET_PREFIX_LENGTH,
+                ParserState::Element => ELEMENT_HEADER_LENGTH,
+                ParserState::Data { address, remaining } => {
+                    let len = core::cmp::min(remaining as usize, data.len());
+                    let (chunk, rest) = data.split_at(len);
+                    data = rest;
+
+                    f(Event::Data {
+                        address,
+                        data: chunk,
+                    });
+                    self.state = ParserState::Data {
+                        address: address.wrapping_add(len as u32),
+                        remaining: remaining - len as u32,
+                    };
+                    self.next_item(&mut f);
+                    continue;
+                }
+                ParserState::Done => return Err(DfuSeError::TrailingData),
+            };
+
+            // collect header, it may be split between blocks
+            let len = core::cmp::min(header_size - self.header_len, data.len());
+            self.header[self.header_len..self.header_len + len].copy_from_slice(&data[..len]);
+            self.header_len += len;
+            data = &data[len..];
+
+            if self.header_len == header_size {
+                self.header_len = 0;
+                self.parse_header(&mut f)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_header<'d>(&mut self, f: &mut impl FnMut(Event<'d>)) -> Result<(), DfuSeError> {
+        match self.state {
+            ParserState::Prefix => {
+                let prefix = Prefix::try_from(&self.header[..])?;
+                self.targets_left = prefix.targets;
+                self.state = ParserState::Target;
+                f(Event::Prefix(prefix));
+            }
+            ParserState::Target => {
+                let target = TargetPrefix::try_from(&self.header[..])?;
+                self.targets_left -= 1;
+                self.elements_left = target.elements;
+                self.target_left = target.size;
+                self.state = ParserState::Element;
+                f(Event::Target(target));
+            }
+            ParserState::Element => {
+                let element = ElementHeader::try_from(&self.header[..])?;
+                self.target_left = self
+                    .target_left
+                    .checked_sub(ELEMENT_HEADER_LENGTH as u32)
+                    .and_then(|left| left.checked_sub(element.size))
+                    .ok_or(DfuSeError::InvalidSize)?;
+                self.elements_left -= 1;
+                self.state = ParserState::Data {
+                    address: element.address,
+                    remaining: element.size,
+                };
+                f(Event::Element(element));
+            }
+            ParserState::Data { .. } | ParserState::Done => {}
+        }
+        self.next_item(f);
+        Ok(())
+    }
+
+    /// Skip empty targets and elements.
+    fn next_item<'d>(&mut self, f: &mut impl FnMut(Event<'d>)) {
+        loop {
+            match self.state {
+                ParserState::Target if self.targets_left == 0 => self.state = ParserState::Done,
+                ParserState::Element if self.elements_left == 0 => {
+                    self.state = ParserState::Target;
+                    continue;
+                }
+                ParserState::Data { remaining: 0, .. } => {
+                    self.state = ParserState::Element;
+                    continue;
+                }
+                _ => {}
+            }
+            break;
+        }
+
+        if self.state == ParserState::Done {
+            f(Event::End);
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
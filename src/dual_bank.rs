@@ -0,0 +1,122 @@
+//! STM32 dual-bank swap
+//!
+//! On dual-bank STM32 parts, e.g. STM32F42x/F43x, STM32F7, or STM32L4, the *BFB2*
+//! option bit selects the bank the device boots from. A firmware update is
+//! downloaded to the inactive bank, then *BFB2* is toggled and option bytes are
+//! reloaded, which resets the device into the new firmware.
+//!
+//! [`DualBankSwap`] wraps [`DfuMemory`] and implements this flow: *BFB2* is toggled
+//! during manifestation, and option bytes are reloaded on the following USB reset.
+//! The option-byte sequence is abstracted by the [`OptionBytes`] trait.
+//! Downloads must target the inactive bank, this is up to the wrapped memory.
+//!
+//! Requires `stm32-dual-bank` feature.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::dual_bank::*;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # fn example<B: UsbBus>(
+//! #     usb_bus_alloc: UsbBusAllocator<B>,
+//! #     my_bank2_mem: impl DfuMemory,
+//! #     my_option_bytes: impl OptionBytes,
+//! # ) {
+//! let mem = DualBankSwap::new(my_bank2_mem, my_option_bytes);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory};
+
+/// Option-byte access of a dual-bank STM32 flash controller.
+pub trait OptionBytes {
+    /// Unlock the flash and option bytes, write the `FLASH_KEYR` and `FLASH_OPTKEYR` key sequences.
+    #[allow(clippy::result_unit_err)]
+    fn unlock(&mut self) -> Result<(), ()>;
+
+    /// Returns the current value of *BFB2*.
+    fn bfb2(&mut self) -> bool;
+
+    /// Set *BFB2* to `value`, start the option-byte programming and wait for completion.
+    #[allow(clippy::result_unit_err)]
+    fn program_bfb2(&mut self, value: bool) -> Result<(), ()>;
+
+    /// Lock the flash and option bytes.
+    fn lock(&mut self);
+
+    /// Reload option bytes, e.g. set `OBL_LAUNCH`. This resets the device and should not return.
+    fn launch(&mut self);
+}
+
+/// [`DfuMemory`] adapter that swaps flash banks after manifestation.
+///
+/// After the wrapped memory [`manifestation()`](DfuMemory::manifestation) succeeds,
+/// *BFB2* is toggled. The device is never
+/// [`MANIFESTATION_TOLERANT`](DfuMemory::MANIFESTATION_TOLERANT), so the host resets it,
+/// and option bytes are reloaded in [`usb_reset()`](DfuMemory::usb_reset).
+pub struct DualBankSwap<M: DfuMemory, O: OptionBytes> {
+    mem: M,
+    option_bytes: O,
+    /// *BFB2* was toggled, option bytes must be reloaded.
+    swap_pending: bool,
+}
+
+impl<M: DfuMemory, O: OptionBytes> DualBankSwap<M, O> {
+    /// Wrap `mem`, and swap banks with `option_bytes`.
+    pub fn new(mem: M, option_bytes: O) -> Self {
+        Self {
+            mem,
+            option_bytes,
+            swap_pending: false,
+        }
+    }
+
+    /// Returns a reference to the option bytes.
+    pub fn option_bytes(&mut self) -> &mut O {
+        &mut self.option_bytes
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory and the option bytes.
+    pub fn release(self) -> (M, O) {
+        (self.mem, self.option_bytes)
+    }
+
+    /// Toggle *BFB2*, the flash is locked again even if programming fails.
+    fn toggle_bfb2(&mut self) -> Result<(), ()> {
+        self.option_bytes.unlock()?;
+        let bfb2 = self.option_bytes.bfb2();
+        let result = self.option_bytes.program_bfb2(!bfb2);
+        self.option_bytes.lock();
+        result
+    }
+}
+
+impl<M: DfuMemory, O: OptionBytes> DfuMemory for DualBankSwap<M, O> {
+    forward_dfu_memory!(mem: M, except MANIFESTATION_TOLERANT, manifestation, usb_reset);
+
+    // option bytes are reloaded on reset
+    const MANIFESTATION_TOLERANT: bool = false;
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.mem.manifestation()?;
+        if !self.swap_pending {
+            self.toggle_bfb2()
+                .map_err(|_| DfuManifestationError::Unknown)?;
+            self.swap_pending = true;
+        }
+        Ok(())
+    }
+
+    fn usb_reset(&mut self) {
+        if self.swap_pending {
+            // may not return
+            self.option_bytes.launch();
+        }
+        self.mem.usb_reset()
+    }
+}
@@ -0,0 +1,336 @@
+//! C API
+//!
+//! `#[no_mangle]` functions around [`StreamLink`] for existing C bootloaders.
+//! Memory is accessed with callbacks in [`DfuMemoryOps`], and frames received
+//! from a serial or CAN link are passed to [`usbd_dfu_receive()`] or
+//! [`usbd_dfu_handle()`], see [`link`](crate::link) module documentation for
+//! the frame format. The C header is `include/usbd_dfu.h`.
+//!
+//! There is a single protocol instance, the functions must not be called
+//! concurrently, e.g. from an interrupt handler and the main loop.
+//! Memory callbacks are executed synchronously, from [`usbd_dfu_receive()`]
+//! or [`usbd_dfu_handle()`]. Transfer size is [`USBD_DFU_TRANSFER_SIZE`],
+//! and the address pointer starts at `0`, the host sets it with DfuSe
+//! *Set Address Pointer* command. The memory layout reported to the host is
+//! [`USBD_DFU_MEM_INFO_STRING`], a single readable, erasable and writable
+//! 4 GiB region of 256-byte pages, the callbacks reject addresses outside
+//! of the actual memory.
+//!
+//! Requires `ffi` feature. To link with C code, build a `no_std` `staticlib` crate
+//! that depends on this crate, re-exports the functions with
+//! `pub use usbd_dfu::ffi::*;`, and defines a `#[panic_handler]`.
+
+use core::cell::UnsafeCell;
+use core::ffi::c_void;
+use core::slice;
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError};
+use crate::link::{StreamLink, SETUP_LENGTH};
+
+/// Transfer size of the C API, *wTransferSize*.
+pub const USBD_DFU_TRANSFER_SIZE: usize = 256;
+
+/// Memory layout of the C API, DfuSe *iInterface* string.
+pub const USBD_DFU_MEM_INFO_STRING: &str = "@Memory/0x00000000/1*256 g";
+
+/// Callback status: operation succeeded, other values are DFU status codes, e.g. `0x03` *errWRITE*.
+pub const USBD_DFU_OK: u8 = 0;
+
+/// Memory callbacks, `struct usbd_dfu_memory_ops` in C.
+///
+/// Callbacks return [`USBD_DFU_OK`] or a DFU status code, an unknown code
+/// is reported to the host as *errUNKNOWN*. `context` is passed to every callback.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DfuMemoryOps {
+    /// Passed to callbacks.
+    pub context: *mut c_void,
+    /// Read at most `length` bytes at `address` to `buffer`, and store the
+    /// number of read bytes in `read`, fewer at the end of memory.
+    pub read: Option<
+        unsafe extern "C" fn(
+            context: *mut c_void,
+            address: u32,
+            buffer: *mut u8,
+            length: usize,
+            read: *mut usize,
+        ) -> u8,
+    >,
+    /// Program `length` bytes of `data` at `address`.
+    pub program: Option<
+        unsafe extern "C" fn(
+            context: *mut c_void,
+            address: u32,
+            data: *const u8,
+            length: usize,
+        ) -> u8,
+    >,
+    /// Erase the page that contains `address`.
+    pub erase: Option<unsafe extern "C" fn(context: *mut c_void, address: u32) -> u8>,
+    /// Erase all memory.
+    pub erase_all: Option<unsafe extern "C" fn(context: *mut c_void) -> u8>,
+    /// Finish the download, may not return.
+    pub manifestation: Option<unsafe extern "C" fn(context: *mut c_void) -> u8>,
+}
+
+fn memory_error(status: u8) -> DfuMemoryError {
+    match status {
+        0x01 => DfuMemoryError::Target,
+        0x02 => DfuMemoryError::File,
+        0x03 => DfuMemoryError::Write,
+        0x04 => DfuMemoryError::Erase,
+        0x05 => DfuMemoryError::CheckErased,
+        0x06 => DfuMemoryError::Prog,
+        0x07 => DfuMemoryError::Verify,
+        0x08 => DfuMemoryError::Address,
+        0x0b => DfuMemoryError::ErrVendor,
+        _ => DfuMemoryError::Unknown,
+    }
+}
+
+fn manifestation_error(status: u8) -> DfuManifestationError {
+    match status {
+        0x01 => DfuManifestationError::Target,
+        0x02 => DfuManifestationError::File,
+        0x09 => DfuManifestationError::NotDone,
+        0x0a => DfuManifestationError::Firmware,
+        0x0b => DfuManifestationError::ErrVendor,
+        _ => DfuManifestationError::Unknown,
+    }
+}
+
+/// [`DfuMemory`] implementation that calls [`DfuMemoryOps`].
+struct CallbackMemory {
+    ops: DfuMemoryOps,
+    buffer: [u8; USBD_DFU_TRANSFER_SIZE],
+}
+
+impl CallbackMemory {
+    /// Returns `Err` with `error` if the callback failed.
+    fn check<E>(status: u8, error: fn(u8) -> E) -> Result<(), E> {
+        match status {
+            USBD_DFU_OK => Ok(()),
+            status => Err(error(status)),
+        }
+    }
+}
+
+impl DfuMemory for CallbackMemory {
+    const INITIAL_ADDRESS_POINTER: u32 = 0;
+    const MEM_INFO_STRING: &'static str = USBD_DFU_MEM_INFO_STRING;
+    // callbacks complete before the next request is handled
+    const PROGRAM_TIME_MS: u32 = 1;
+    const ERASE_TIME_MS: u32 = 1;
+    const FULL_ERASE_TIME_MS: u32 = 1;
+    const TRANSFER_SIZE: u16 = USBD_DFU_TRANSFER_SIZE as u16;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let read = self.ops.read.ok_or(DfuMemoryError::Unknown)?;
+        let length = length.min(self.buffer.len());
+        let mut n = 0;
+        // SAFETY: callbacks are valid as required by `usbd_dfu_init()`
+        let status = unsafe {
+            read(
+                self.ops.context,
+                address,
+                self.buffer.as_mut_ptr(),
+                length,
+                &mut n,
+            )
+        };
+        Self::check(status, memory_error)?;
+        Ok(&self.buffer[..n.min(length)])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let program = self.ops.program.ok_or(DfuMemoryError::Prog)?;
+        let data = self.buffer.get(..length).ok_or(DfuMemoryError::Prog)?;
+        // SAFETY: callbacks are valid as required by `usbd_dfu_init()`
+        let status = unsafe { program(self.ops.context, address, data.as_ptr(), length) };
+        Self::check(status, memory_error)
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        let erase = self.ops.erase.ok_or(DfuMemoryError::Erase)?;
+        // SAFETY: callbacks are valid as required by `usbd_dfu_init()`
+        let status = unsafe { erase(self.ops.context, address) };
+        Self::check(status, memory_error)
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        let erase_all = self.ops.erase_all.ok_or(DfuMemoryError::Erase)?;
+        // SAFETY: callbacks are valid as required by `usbd_dfu_init()`
+        let status = unsafe { erase_all(self.ops.context) };
+        Self::check(status, memory_error)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        let manifestation = self
+            .ops
+            .manifestation
+            .ok_or(DfuManifestationError::Unknown)?;
+        // SAFETY: callbacks are valid as required by `usbd_dfu_init()`
+        let status = unsafe { manifestation(self.ops.context) };
+        Self::check(status, manifestation_error)
+    }
+}
+
+const FRAME_LENGTH: usize = SETUP_LENGTH + USBD_DFU_TRANSFER_SIZE;
+
+/// The protocol instance, access is not synchronized.
+struct Instance(UnsafeCell<Option<StreamLink<CallbackMemory, FRAME_LENGTH>>>);
+
+// SAFETY: the C API requires that functions are not called concurrently
+unsafe impl Sync for Instance {}
+
+static INSTANCE: Instance = Instance(UnsafeCell::new(None));
+
+/// Returns the protocol instance.
+///
+/// # Safety
+///
+/// No other reference to the instance may exist.
+unsafe fn instance() -> &'static mut Option<StreamLink<CallbackMemory, FRAME_LENGTH>> {
+    &mut *INSTANCE.0.get()
+}
+
+/// Returns `length` bytes at `data`, empty if `data` is null or `length` is `0`.
+///
+/// # Safety
+///
+/// `data` must be null or valid for reads of `length` bytes.
+unsafe fn bytes<'a>(data: *const u8, length: usize) -> &'a [u8] {
+    match data.is_null() || length == 0 {
+        true => &[],
+        false => slice::from_raw_parts(data, length),
+    }
+}
+
+/// Returns `length` bytes at `data`, empty if `data` is null or `length` is `0`.
+///
+/// # Safety
+///
+/// `data` must be null or valid for writes of `length` bytes.
+unsafe fn bytes_mut<'a>(data: *mut u8, length: usize) -> &'a mut [u8] {
+    match data.is_null() || length == 0 {
+        true => &mut [],
+        false => slice::from_raw_parts_mut(data, length),
+    }
+}
+
+/// Initialize the protocol with memory callbacks, resets the protocol
+/// state if it was initialized before.
+///
+/// Returns `false` if `ops` or any of its callbacks is null.
+///
+/// # Safety
+///
+/// `ops` must be null or point to a valid [`DfuMemoryOps`], callbacks must
+/// be safe to call with its `context` until the next `usbd_dfu_init()` call.
+/// Must not be called concurrently with other functions of the C API.
+#[no_mangle]
+pub unsafe extern "C" fn usbd_dfu_init(ops: *const DfuMemoryOps) -> bool {
+    let Some(ops) = ops.as_ref() else {
+        return false;
+    };
+    if ops.read.is_none()
+        || ops.program.is_none()
+        || ops.erase.is_none()
+        || ops.erase_all.is_none()
+        || ops.manifestation.is_none()
+    {
+        return false;
+    }
+
+    *instance() = Some(StreamLink::new(CallbackMemory {
+        ops: *ops,
+        buffer: [0; USBD_DFU_TRANSFER_SIZE],
+    }));
+    true
+}
+
+/// Handle a complete request frame of `request_length` bytes and write the
+/// reply frame to `reply`, see [`DfuLink::handle()`](crate::link::DfuLink::handle).
+///
+/// Returns the reply length, `0` if the protocol is not initialized.
+///
+/// # Safety
+///
+/// `request` must be valid for reads of `request_length` bytes, and `reply`
+/// for writes of `reply_length` bytes, which must be at least `3`. Null
+/// pointers are treated as empty buffers.
+/// Must not be called concurrently with other functions of the C API.
+#[no_mangle]
+pub unsafe extern "C" fn usbd_dfu_handle(
+    request: *const u8,
+    request_length: usize,
+    reply: *mut u8,
+    reply_length: usize,
+) -> usize {
+    let Some(link) = instance() else {
+        return 0;
+    };
+    let request = bytes(request, request_length);
+    let reply = bytes_mut(reply, reply_length);
+    link.link().handle(request, reply)
+}
+
+/// Process `length` bytes received from a byte stream, `send` is called
+/// with `context` and the reply to each complete frame, see
+/// [`StreamLink::receive()`].
+///
+/// # Safety
+///
+/// `data` must be null or valid for reads of `length` bytes, `send` must
+/// be safe to call with `context`.
+/// Must not be called concurrently with other functions of the C API.
+#[no_mangle]
+pub unsafe extern "C" fn usbd_dfu_receive(
+    data: *const u8,
+    length: usize,
+    send: unsafe extern "C" fn(context: *mut c_void, reply: *const u8, length: usize),
+    context: *mut c_void,
+) {
+    let Some(link) = instance() else {
+        return;
+    };
+    let data = bytes(data, length);
+    link.receive(data, |reply| send(context, reply.as_ptr(), reply.len()));
+}
+
+/// Drop a partially received frame of [`usbd_dfu_receive()`], and reset the
+/// protocol state if `link_lost` is `true`, like a USB reset.
+///
+/// # Safety
+///
+/// Must not be called concurrently with other functions of the C API.
+#[no_mangle]
+pub unsafe extern "C" fn usbd_dfu_reset(link_lost: bool) {
+    if let Some(link) = instance() {
+        link.reset();
+        if link_lost {
+            link.link().reset();
+        }
+    }
+}
+
+/// Returns the current address pointer, `0` if the protocol is not initialized.
+///
+/// # Safety
+///
+/// Must not be called concurrently with other functions of the C API.
+#[no_mangle]
+pub unsafe extern "C" fn usbd_dfu_address_pointer() -> u32 {
+    match instance() {
+        Some(link) => link.link().get_address_pointer(),
+        None => 0,
+    }
+}
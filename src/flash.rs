@@ -0,0 +1,258 @@
+//! NOR flash memory
+//!
+//! [`FlashMemory`] implements [`DfuMemory`] for a flash that implements
+//! [`NorFlash`] trait of the `embedded-storage` crate. Region addresses,
+//! page layout and timing are described by a [`FlashLayout`], which is usually
+//! provided by a chip-specific module, e.g. [`nrf`](crate::nrf).
+//!
+//! Blocks shorter than `WRITE_SIZE` are padded with `0xFF`. Uploads end at the
+//! region end. Blocks are programmed from an internal buffer aligned to
+//! [`BUFFER_ALIGN`] bytes, so word programming and DMA can read it directly.
+//!
+//! [`FlashLayout::KIND`] selects how the region is erased and manifested, so
+//! data EEPROM and option bytes can be exposed as well, see [`RegionKind`].
+//!
+//! Requires `embedded-storage` feature.
+
+use core::marker::PhantomData;
+
+use embedded_storage::nor_flash::{NorFlash, NorFlashError, NorFlashErrorKind};
+
+use crate::buffer::AlignedBuffer;
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError, ReadOutcome};
+
+/// Alignment of [`FlashMemory`] buffer, a 256-bit flash word or a Cortex-M7 cache line.
+pub const BUFFER_ALIGN: usize = 32;
+
+/// Kind of a [`FlashLayout`] region.
+#[derive(Clone, Copy)]
+pub enum RegionKind {
+    /// Flash, pages are erased before programming.
+    Flash,
+    /// Data EEPROM, written without erase.
+    ///
+    /// Erase requests succeed without changes, and a partial write unit is
+    /// padded with the current memory contents.
+    Eeprom,
+    /// Option bytes, written without erase like [`Eeprom`](RegionKind::Eeprom).
+    ///
+    /// The device loads new option bytes with a reset, so the memory is not
+    /// manifestation tolerant, and manifestation calls `reload`, e.g. to set
+    /// `OBL_LAUNCH` bit of STM32 `FLASH_CR`.
+    OptionBytes {
+        /// Load option bytes, usually doesn't return.
+        reload: fn(),
+    },
+}
+
+#[cfg(feature = "defmt-03")]
+impl defmt::Format for RegionKind {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            RegionKind::Flash => defmt::write!(fmt, "Flash"),
+            RegionKind::Eeprom => defmt::write!(fmt, "Eeprom"),
+            RegionKind::OptionBytes { .. } => defmt::write!(fmt, "OptionBytes"),
+        }
+    }
+}
+
+impl RegionKind {
+    /// Returns `true` if pages are erased before programming.
+    pub const fn is_erasable(&self) -> bool {
+        matches!(self, RegionKind::Flash)
+    }
+
+    /// Returns `true` if the device doesn't reset in manifestation.
+    pub const fn is_manifestation_tolerant(&self) -> bool {
+        !matches!(self, RegionKind::OptionBytes { .. })
+    }
+}
+
+/// Memory region, page layout and timing of a [`FlashMemory`].
+pub trait FlashLayout {
+    /// Kind of the region. Default is [`RegionKind::Flash`].
+    const KIND: RegionKind = RegionKind::Flash;
+
+    /// First address of the region, it's the initial address pointer.
+    const START: u32;
+
+    /// End address of the region, exclusive.
+    const END: u32;
+
+    /// See [`DfuMemory::MEM_INFO_STRING`].
+    const MEM_INFO_STRING: &'static str;
+
+    /// See [`DfuMemory::PROGRAM_TIME_MS`].
+    const PROGRAM_TIME_MS: u32;
+
+    /// See [`DfuMemory::ERASE_TIME_MS`].
+    const ERASE_TIME_MS: u32;
+
+    /// See [`DfuMemory::FULL_ERASE_TIME_MS`].
+    const FULL_ERASE_TIME_MS: u32;
+
+    /// Returns start address and size of the page that contains `address`,
+    /// or `None` if `address` is outside of the region.
+    fn page(address: u32) -> Option<(u32, u32)>;
+}
+
+fn map_error<E: NorFlashError>(error: E, other: DfuMemoryError) -> DfuMemoryError {
+    match error.kind() {
+        NorFlashErrorKind::NotAligned | NorFlashErrorKind::OutOfBounds => DfuMemoryError::Address,
+        _ => other,
+    }
+}
+
+/// [`DfuMemory`] implementation for a [`NorFlash`].
+///
+/// `N` is the size of the internal buffer and the
+/// [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE), it must be a multiple of `WRITE_SIZE`.
+pub struct FlashMemory<F: NorFlash, L: FlashLayout, const N: usize> {
+    flash: F,
+    base: u32,
+    buffer: AlignedBuffer<N, BUFFER_ALIGN>,
+    _layout: PhantomData<L>,
+}
+
+impl<F: NorFlash, L: FlashLayout, const N: usize> FlashMemory<F, L, N> {
+    /// Access the region with `flash`, `base` is the address of `flash` offset `0`.
+    ///
+    /// E.g. `base` is `0` if `flash` offsets are addresses, or [`FlashLayout::START`]
+    /// if `flash` covers only the region.
+    pub fn new(flash: F, base: u32) -> Self {
+        const {
+            assert!(
+                N > 0 && N.is_multiple_of(F::WRITE_SIZE),
+                "FlashMemory buffer is not a multiple of WRITE_SIZE"
+            )
+        };
+
+        Self {
+            flash,
+            base,
+            buffer: AlignedBuffer::new(),
+            _layout: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the flash.
+    pub fn flash(&mut self) -> &mut F {
+        &mut self.flash
+    }
+
+    /// Destroy the memory and return the flash.
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Returns `flash` offset of `length` bytes at `address`, they must be in the region.
+    fn offset(&self, address: u32, length: usize) -> Result<u32, DfuMemoryError> {
+        let end = address
+            .checked_add(length as u32)
+            .ok_or(DfuMemoryError::Address)?;
+        if address < L::START || end > L::END {
+            return Err(DfuMemoryError::Address);
+        }
+        address
+            .checked_sub(self.base)
+            .ok_or(DfuMemoryError::Address)
+    }
+
+    fn erase_page(&mut self, address: u32) -> Result<u32, DfuMemoryError> {
+        let (start, size) = L::page(address).ok_or(DfuMemoryError::Address)?;
+        let from = self.offset(start, size as usize)?;
+        if !L::KIND.is_erasable() {
+            return Ok(start + size);
+        }
+        self.flash
+            .erase(from, from + size)
+            .map_err(|e| map_error(e, DfuMemoryError::Erase))?;
+        Ok(start + size)
+    }
+}
+
+impl<F: NorFlash, L: FlashLayout, const N: usize> DfuMemory for FlashMemory<F, L, N> {
+    const INITIAL_ADDRESS_POINTER: u32 = L::START;
+    const MEM_INFO_STRING: &'static str = L::MEM_INFO_STRING;
+    const PROGRAM_TIME_MS: u32 = L::PROGRAM_TIME_MS;
+    const ERASE_TIME_MS: u32 = L::ERASE_TIME_MS;
+    const FULL_ERASE_TIME_MS: u32 = L::FULL_ERASE_TIME_MS;
+    const MANIFESTATION_TOLERANT: bool = L::KIND.is_manifestation_tolerant();
+    const TRANSFER_SIZE: u16 = N as u16;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        if address >= L::END {
+            return Ok(&[]);
+        }
+        let length = length.min(N).min((L::END - address) as usize);
+        let offset = self.offset(address, length)?;
+        let buffer = &mut self.buffer[..length];
+        self.flash
+            .read(offset, buffer)
+            .map_err(|e| map_error(e, DfuMemoryError::Unknown))?;
+        Ok(buffer)
+    }
+
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        let data = self.read(address, length)?;
+        if address.saturating_add(data.len() as u32) >= L::END {
+            Ok(ReadOutcome::LastChunk(data))
+        } else {
+            Ok(ReadOutcome::Full(data))
+        }
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        if length > N {
+            return Err(DfuMemoryError::Prog);
+        }
+        let offset = self.offset(address, length)?;
+        if !offset.is_multiple_of(F::WRITE_SIZE as u32) {
+            return Err(DfuMemoryError::Address);
+        }
+
+        // pad the last unit, flash is erased anyway, otherwise keep current data
+        let padded = length.next_multiple_of(F::WRITE_SIZE);
+        if L::KIND.is_erasable() {
+            self.buffer[length..padded].fill(0xff);
+        } else if padded > length {
+            self.flash
+                .read(offset + length as u32, &mut self.buffer[length..padded])
+                .map_err(|e| map_error(e, DfuMemoryError::Prog))?;
+        }
+        self.flash
+            .write(offset, &self.buffer[..padded])
+            .map_err(|e| map_error(e, DfuMemoryError::Prog))
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        self.erase_page(address).map(|_| ())
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        let mut address = L::START;
+        while address < L::END {
+            address = self.erase_page(address)?;
+        }
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        if let RegionKind::OptionBytes { reload } = L::KIND {
+            reload();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+//! Image digest calculation during download
+//!
+//! [`HashedMemory`] wraps [`DfuMemory`] and feeds every programmed block to an
+//! [`ImageHasher`], in the same order and with the same contents as passed to
+//! [`DfuMemory::program()`]. When the host completes the download, the digest
+//! is passed to [`DigestVerifier::verify_digest()`], for example to compare it
+//! with a digest from a signed manifest, before
+//! [`manifestation()`](DfuMemory::manifestation) is called.
+//!
+//! With `sha2` feature, [`ImageHasher`] is implemented for `sha2::Sha256`.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::hash::*;
+//! # use usbd_dfu::*;
+//! # struct MyMem {
+//! #     expected_digest: [u8; 32],
+//! # }
+//! # impl DfuMemory for MyMem {
+//! #     const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+//! #     const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*1Kg";
+//! #     const PROGRAM_TIME_MS: u32 = 10;
+//! #     const ERASE_TIME_MS: u32 = 20;
+//! #     const FULL_ERASE_TIME_MS: u32 = 320;
+//! # }
+//! impl DigestVerifier<[u8; 32]> for MyMem {
+//!     fn verify_digest(&mut self, digest: &[u8; 32]) -> Result<(), DfuManifestationError> {
+//!         if digest == &self.expected_digest {
+//!             Ok(())
+//!         } else {
+//!             Err(DfuManifestationError::File)
+//!         }
+//!     }
+//! }
+//!
+//! # #[cfg(feature = "sha2")]
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: MyMem) {
+//! # use sha2::Digest;
+//! let mem = HashedMemory::<_, _, 128>::new(my_mem, sha2::Sha256::new());
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError};
+
+/// Streaming hash function.
+pub trait ImageHasher {
+    /// Result of the hash function.
+    type Digest;
+
+    /// Start a new calculation.
+    fn reset(&mut self);
+
+    /// Process next block of data.
+    fn update(&mut self, data: &[u8]);
+
+    /// Return the digest of all data processed since the last reset.
+    fn finalize(&mut self) -> Self::Digest;
+}
+
+/// Check the digest of the downloaded image.
+pub trait DigestVerifier<D> {
+    /// Called before [`DfuMemory::manifestation()`] with the digest of all
+    /// data programmed during the download.
+    ///
+    /// On error, `manifestation()` is not called, and DFU switches to
+    /// ERROR state with the corresponding status.
+    fn verify_digest(&mut self, digest: &D) -> Result<(), DfuManifestationError>;
+}
+
+/// [`DfuMemory`] adapter that calculates the digest of programmed data.
+///
+/// The hasher is reset when a new download starts.
+///
+/// `N` is the size of the internal buffer, it must be at least `TRANSFER_SIZE` bytes.
+pub struct HashedMemory<M, H, const N: usize>
+where
+    M: DfuMemory + DigestVerifier<H::Digest>,
+    H: ImageHasher,
+{
+    mem: M,
+    hasher: H,
+    buffer: [u8; N],
+}
+
+impl<M, H, const N: usize> HashedMemory<M, H, N>
+where
+    M: DfuMemory + DigestVerifier<H::Digest>,
+    H: ImageHasher,
+{
+    /// Wrap `mem`, and use `hasher` to calculate the digest.
+    pub fn new(mem: M, mut hasher: H) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize,
+                "HashedMemory buffer is too small"
+            )
+        };
+
+        hasher.reset();
+        Self {
+            mem,
+            hasher,
+            buffer: [0; N],
+        }
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+}
+
+impl<M, H, const N: usize> DfuMemory for HashedMemory<M, H, N>
+where
+    M: DfuMemory + DigestVerifier<H::Digest>,
+    H: ImageHasher,
+{
+    forward_dfu_memory!(
+        mem: M,
+        except RESUME_COMMAND, SKIP_IDENTICAL, store_write_buffer, program, manifestation,
+        download_start, resume_point
+    );
+
+    // the hash context of an interrupted download is lost
+    const RESUME_COMMAND: bool = false;
+
+    // every block is hashed in program(), a skipped block would be missing from the digest
+    const SKIP_IDENTICAL: bool = false;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        // keep a copy, the block is hashed when it is programmed
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        self.mem.store_write_buffer(src)
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        self.hasher
+            .update(self.buffer.get(..length).ok_or(DfuMemoryError::Prog)?);
+        self.mem.program(address, length)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        let digest = self.hasher.finalize();
+        self.hasher.reset();
+        self.mem.verify_digest(&digest)?;
+        self.mem.manifestation()
+    }
+
+    fn download_start(&mut self) {
+        self.hasher.reset();
+        self.mem.download_start()
+    }
+}
+
+#[cfg(feature = "sha2")]
+impl ImageHasher for sha2::Sha256 {
+    type Digest = [u8; 32];
+
+    fn reset(&mut self) {
+        sha2::Digest::reset(self)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(self, data)
+    }
+
+    fn finalize(&mut self) -> Self::Digest {
+        sha2::Digest::finalize_reset(self).into()
+    }
+}
@@ -0,0 +1,302 @@
+//! Firmware header
+//!
+//! A small image header shared by the DFU bootloader and the code that decides
+//! whether the application can be started:
+//!
+//! | Offset | Size | Field                                        |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 4    | Magic, [`HEADER_MAGIC`]                      |
+//! | 4      | 4    | Image length in bytes                        |
+//! | 8      | 4    | Image version                                |
+//! | 12     | 4    | CRC-32 of the image, see [`Crc32`]           |
+//!
+//! All fields are little-endian `u32`.
+//!
+//! [`HeaderMemory`] wraps [`DfuMemory`] and writes the header of the downloaded
+//! image at a separate address during manifestation. The header is erased when
+//! a download starts, so an interrupted download never leaves a valid header.
+//!
+//! At boot, [`validate_image()`] checks the header and the image CRC:
+//!
+//! ```
+//! use usbd_dfu::header::*;
+//!
+//! let image = [0x10, 0x20, 0x30, 0x40];
+//! let header = FirmwareHeader::new(&image, 3).to_bytes();
+//!
+//! let valid = validate_image(&header, &image).unwrap();
+//! assert_eq!(valid.version, 3);
+//! assert_eq!(validate_image(&[0xff; 16], &image), Err(HeaderError::InvalidMagic));
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError};
+use crate::suffix::Crc32;
+
+/// Firmware header magic, `DFUH` in little-endian byte order.
+pub const HEADER_MAGIC: u32 = 0x4855_4644;
+
+/// Size of the firmware header in bytes.
+pub const HEADER_LENGTH: usize = 16;
+
+/// Errors of firmware header validation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum HeaderError {
+    /// Header is too short.
+    TooShort,
+    /// Magic value is not valid, e.g. the header is erased.
+    InvalidMagic,
+    /// Image is shorter than the header length.
+    InvalidLength,
+    /// Image CRC does not match the header.
+    InvalidCrc,
+}
+
+/// Firmware header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct FirmwareHeader {
+    /// Image length in bytes.
+    pub length: u32,
+    /// Image version.
+    pub version: u32,
+    /// CRC-32 of the image.
+    pub crc: u32,
+}
+
+impl FirmwareHeader {
+    /// Creates a header for `image`.
+    pub fn new(image: &[u8], version: u32) -> Self {
+        let mut crc = Crc32::new();
+        crc.update(image);
+        Self {
+            length: image.len() as u32,
+            version,
+            crc: crc.finalize(),
+        }
+    }
+
+    /// Serialize the header.
+    pub fn to_bytes(&self) -> [u8; HEADER_LENGTH] {
+        let mut bytes = [0; HEADER_LENGTH];
+        bytes[0..4].copy_from_slice(&HEADER_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.length.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.version.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    /// Check `image` against the header, `image` may be longer than the header length.
+    pub fn validate(&self, image: &[u8]) -> Result<(), HeaderError> {
+        let image = image
+            .get(..self.length as usize)
+            .ok_or(HeaderError::InvalidLength)?;
+        let mut crc = Crc32::new();
+        crc.update(image);
+        if crc.finalize() != self.crc {
+            return Err(HeaderError::InvalidCrc);
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&[u8]> for FirmwareHeader {
+    type Error = HeaderError;
+
+    /// Parse the header at the start of `value`.
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let value = value.get(..HEADER_LENGTH).ok_or(HeaderError::TooShort)?;
+        let u32_at = |i: usize| u32::from_le_bytes(value[i..i + 4].try_into().unwrap());
+
+        if u32_at(0) != HEADER_MAGIC {
+            return Err(HeaderError::InvalidMagic);
+        }
+
+        Ok(FirmwareHeader {
+            length: u32_at(4),
+            version: u32_at(8),
+            crc: u32_at(12),
+        })
+    }
+}
+
+/// Parse `header` and check `image` against it, for use at boot.
+///
+/// `image` starts at the first byte of the application and may extend to
+/// the end of its memory region.
+pub fn validate_image(header: &[u8], image: &[u8]) -> Result<FirmwareHeader, HeaderError> {
+    let header = FirmwareHeader::try_from(header)?;
+    header.validate(image)?;
+    Ok(header)
+}
+
+/// [`DfuMemory`] adapter that writes a [`FirmwareHeader`] of the downloaded image.
+///
+/// The image is expected to be downloaded sequentially, programming a block that
+/// does not follow the previous one fails with `errADDRESS`. The page at the header
+/// address is erased before the first block is programmed, it must not overlap the image.
+/// The header version is [`DfuMemory::image_version()`] of the wrapped memory, or `0`.
+///
+/// Blocks skipped with [`SKIP_IDENTICAL`](DfuMemory::SKIP_IDENTICAL) of the wrapped
+/// memory are a part of the image too, manifestation fails with `errFILE` if one of
+/// them does not follow the previous block. A download with the *Verify* command of
+/// the wrapped memory, see [`VERIFY_COMMAND`](DfuMemory::VERIFY_COMMAND), doesn't write
+/// the header.
+///
+/// `N` is the size of the internal buffer, it must be at least `TRANSFER_SIZE` bytes.
+pub struct HeaderMemory<M: DfuMemory, const N: usize> {
+    mem: M,
+    header_address: u32,
+    buffer: [u8; N],
+    crc: Crc32,
+    /// Number of bytes programmed since the download start.
+    length: u32,
+    /// Address of the next programmed block, set by the first downloaded block.
+    next_address: Option<u32>,
+    /// The old header is erased, it's done before the first programmed block.
+    header_erased: bool,
+    /// A skipped block did not follow the previous one.
+    gap: bool,
+}
+
+impl<M: DfuMemory, const N: usize> HeaderMemory<M, N> {
+    /// Wrap `mem`, and write headers at `header_address`.
+    pub fn new(mem: M, header_address: u32) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize,
+                "HeaderMemory buffer is too small"
+            )
+        };
+
+        Self {
+            mem,
+            header_address,
+            buffer: [0; N],
+            crc: Crc32::new(),
+            length: 0,
+            next_address: None,
+            header_erased: false,
+            gap: false,
+        }
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    fn restart(&mut self) {
+        self.crc = Crc32::new();
+        self.length = 0;
+        self.next_address = None;
+        self.header_erased = false;
+        self.gap = false;
+    }
+
+    /// Add the stored block at `address` to the image.
+    fn add_block(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let data = self.buffer.get(..length).ok_or(DfuMemoryError::Prog)?;
+        self.crc.update(data);
+        self.length = self
+            .length
+            .checked_add(length as u32)
+            .ok_or(DfuMemoryError::Address)?;
+        self.next_address = Some(
+            address
+                .checked_add(length as u32)
+                .ok_or(DfuMemoryError::Address)?,
+        );
+        Ok(())
+    }
+
+    fn erase_header(&mut self) -> Result<(), DfuMemoryError> {
+        if !self.header_erased {
+            self.mem.erase(self.header_address)?;
+            self.header_erased = true;
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<(), DfuMemoryError> {
+        // all blocks may have been skipped
+        self.erase_header()?;
+        let header = FirmwareHeader {
+            length: self.length,
+            version: self.mem.image_version().unwrap_or(0),
+            crc: self.crc.finalize(),
+        };
+        self.mem
+            .store_write_buffer(&header.to_bytes())
+            .map_err(|_| DfuMemoryError::Prog)?;
+        self.mem.program(self.header_address, HEADER_LENGTH)
+    }
+}
+
+impl<M: DfuMemory, const N: usize> DfuMemory for HeaderMemory<M, N> {
+    forward_dfu_memory!(
+        mem: M,
+        except RESUME_COMMAND, store_write_buffer, compare, program, manifestation, download_start,
+        resume_point
+    );
+
+    // the image CRC and length are counted from the start of the download
+    const RESUME_COMMAND: bool = false;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        // the block is kept for the image CRC, wrapped memory compares it
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        self.mem.store_write_buffer(src)
+    }
+
+    fn compare(&mut self, address: u32, length: usize) -> Result<bool, DfuMemoryError> {
+        if !self.mem.compare(address, length)? {
+            return Ok(false);
+        }
+
+        // the block is not programmed, but it's a part of the image
+        match self.next_address {
+            Some(next) if next != address => self.gap = true,
+            _ => self.add_block(address, length)?,
+        }
+        Ok(true)
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        if self.next_address.is_some_and(|next| next != address) {
+            return Err(DfuMemoryError::Address);
+        }
+        self.erase_header()?;
+
+        // wrapped memory has the block from store_write_buffer()
+        self.mem.program(address, length)?;
+        self.add_block(address, length)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        if self.next_address.is_none() {
+            return Err(DfuManifestationError::NotDone);
+        }
+        if self.gap {
+            self.restart();
+            return Err(DfuManifestationError::File);
+        }
+        let result = self.write_header();
+        self.restart();
+        result.map_err(|_| DfuManifestationError::Unknown)?;
+        self.mem.manifestation()
+    }
+
+    fn download_start(&mut self) {
+        self.restart();
+        self.mem.download_start()
+    }
+}
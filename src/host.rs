@@ -0,0 +1,547 @@
+//! Host side of the DFU protocol
+//!
+//! [`DfuHost`] implements the host side of the protocol over a [`ControlTransport`]:
+//! `DFU_DETACH`, download with the `DFU_DNLOAD`/`DFU_GETSTATUS` loop that waits
+//! for *bwPollTimeout*, upload, manifestation, and DfuSe *Set Address Pointer*
+//! and *Erase* commands.
+//!
+//! The module is `no_std` and doesn't allocate, so a gateway MCU can update
+//! a downstream device with an embedded USB host stack by implementing
+//! [`ControlTransport`] for it. [`download_file()`](DfuHost::download_file)
+//! checks the DFU file suffix with the same [`Suffix`] and [`Crc32`] types
+//! that the device side uses.
+//!
+//! On a development machine, [`RusbTransport`] is a transport for a device opened
+//! with `rusb`, to test device firmware end-to-end from `cargo test` without
+//! `dfu-util`, it requires `rusb` feature, which depends on `std`.
+//!
+//! Requires `host` feature.
+//!
+//! ```no_run
+//! # use usbd_dfu::host::*;
+//! # fn example(image: &[u8]) -> Result<(), HostError<rusb::Error>> {
+//! let transport = RusbTransport::open(0x1209, 0x2444, 0).map_err(HostError::Transport)?;
+//! let mut host = DfuHost::new(transport, 1024);
+//!
+//! host.mass_erase()?;
+//! host.download(0x0800_4000, &image)?;
+//! let mut data = vec![0; image.len()];
+//! host.upload(0x0800_4000, &mut data)?;
+//! assert_eq!(data, image);
+//! host.manifest()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::class::{DfuState, DownloadCommand, ReadoutProtection, MAX_UNLOCK_TOKEN_LENGTH};
+use crate::consts::{
+    DFU_ABORT, DFU_CLRSTATUS, DFU_DETACH, DFU_DNLOAD, DFU_GETSTATE, DFU_GETSTATUS, DFU_UPLOAD,
+};
+use crate::info::{DeviceInfo, MAX_DEVICE_INFO_LENGTH};
+use crate::suffix::{Crc32, Suffix, SuffixError, SuffixMismatch};
+use crate::tlv::{TlvReader, MAX_PAYLOAD_LENGTH};
+
+/// Class-specific control requests to the DFU interface.
+pub trait ControlTransport {
+    /// Transport error.
+    type Error: core::fmt::Debug;
+
+    /// Wait for `ms` milliseconds, e.g. for *bwPollTimeout*.
+    fn delay_ms(&mut self, ms: u32);
+
+    /// Send `request` with `data`.
+    fn control_out(&mut self, request: u8, value: u16, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Send `request` and receive at most `data` length bytes, returns the number
+    /// of bytes received.
+    fn control_in(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &mut [u8],
+    ) -> Result<usize, Self::Error>;
+}
+
+/// `DFU_GETSTATUS` reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Status {
+    /// *bStatus*, `0` if there's no error.
+    pub status: u8,
+    /// *bwPollTimeout* in milliseconds.
+    pub poll_timeout: u32,
+    /// *bState*.
+    pub state: u8,
+    /// *iString*.
+    pub string: u8,
+}
+
+impl Status {
+    /// Parse `DFU_GETSTATUS` reply.
+    pub fn from_bytes(bytes: &[u8; 6]) -> Self {
+        Self {
+            status: bytes[0],
+            poll_timeout: u32::from_le_bytes([bytes[1], bytes[2], bytes[3], 0]),
+            state: bytes[4],
+            string: bytes[5],
+        }
+    }
+
+    /// Returns `true` if the device reports an error.
+    pub fn is_error(&self) -> bool {
+        self.status != 0 || self.state == DfuState::DfuError as u8
+    }
+}
+
+/// Errors of [`DfuHost`].
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum HostError<E> {
+    /// Request failed or was stalled.
+    Transport(E),
+    /// Device reported an error, it's in `dfuERROR` state.
+    Status(Status),
+    /// Device is in an unexpected state.
+    State(u8),
+    /// Reply is too short.
+    Reply,
+    /// DFU file suffix is not valid.
+    Suffix(SuffixError),
+    /// DFU file is not intended for the device.
+    Mismatch(SuffixMismatch),
+    /// DFU file CRC doesn't match.
+    Crc,
+    /// *wTransferSize* is zero.
+    TransferSize,
+}
+
+/// Host side of the DFU protocol, see the [module documentation](self).
+pub struct DfuHost<T: ControlTransport> {
+    transport: T,
+    transfer_size: u16,
+    device: Option<(u16, u16, u16)>,
+}
+
+impl<T: ControlTransport> DfuHost<T> {
+    /// Talk to a device with `transport`, `transfer_size` is *wTransferSize*
+    /// of the DFU functional descriptor.
+    pub fn new(transport: T, transfer_size: u16) -> Self {
+        Self {
+            transport,
+            transfer_size,
+            device: None,
+        }
+    }
+
+    /// Check that DFU files match the device in [`download_file()`](Self::download_file),
+    /// see [`Suffix::matches_device()`].
+    pub fn with_device(mut self, usb_vendor: u16, usb_product: u16, device: u16) -> Self {
+        self.device = Some((usb_vendor, usb_product, device));
+        self
+    }
+
+    /// Returns a reference to the transport.
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Destroy the host and return the transport.
+    pub fn release(self) -> T {
+        self.transport
+    }
+
+    fn control_out(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &[u8],
+    ) -> Result<(), HostError<T::Error>> {
+        self.transport
+            .control_out(request, value, data)
+            .map_err(HostError::Transport)
+    }
+
+    fn control_in(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &mut [u8],
+    ) -> Result<usize, HostError<T::Error>> {
+        self.transport
+            .control_in(request, value, data)
+            .map_err(HostError::Transport)
+    }
+
+    /// Send `DFU_DETACH`, the device waits for a USB reset for at most `timeout_ms`.
+    pub fn detach(&mut self, timeout_ms: u16) -> Result<(), HostError<T::Error>> {
+        self.control_out(DFU_DETACH, timeout_ms, &[])
+    }
+
+    /// Send `DFU_GETSTATUS`.
+    pub fn get_status(&mut self) -> Result<Status, HostError<T::Error>> {
+        let mut reply = [0; 6];
+        if self.control_in(DFU_GETSTATUS, 0, &mut reply)? != reply.len() {
+            return Err(HostError::Reply);
+        }
+        Ok(Status::from_bytes(&reply))
+    }
+
+    /// Send `DFU_CLRSTATUS`.
+    pub fn clear_status(&mut self) -> Result<(), HostError<T::Error>> {
+        self.control_out(DFU_CLRSTATUS, 0, &[])
+    }
+
+    /// Send `DFU_GETSTATE`, returns *bState*.
+    pub fn get_state(&mut self) -> Result<u8, HostError<T::Error>> {
+        let mut reply = [0];
+        if self.control_in(DFU_GETSTATE, 0, &mut reply)? != reply.len() {
+            return Err(HostError::Reply);
+        }
+        Ok(reply[0])
+    }
+
+    /// Send `DFU_ABORT`.
+    pub fn abort(&mut self) -> Result<(), HostError<T::Error>> {
+        self.control_out(DFU_ABORT, 0, &[])
+    }
+
+    /// Bring the device to `dfuIDLE` state, clearing an error or ending
+    /// a download or upload.
+    pub fn idle(&mut self) -> Result<(), HostError<T::Error>> {
+        let state = self.get_state()?;
+        if state == DfuState::DfuError as u8 {
+            self.clear_status()?;
+        } else if state != DfuState::DfuIdle as u8 {
+            self.abort()?;
+        }
+        match self.get_state()? {
+            state if state == DfuState::DfuIdle as u8 => Ok(()),
+            state => Err(HostError::State(state)),
+        }
+    }
+
+    /// Poll status, waiting *bwPollTimeout* between requests, until the device
+    /// leaves busy states.
+    fn wait(&mut self) -> Result<Status, HostError<T::Error>> {
+        loop {
+            let status = self.get_status()?;
+            if status.is_error() {
+                return Err(HostError::Status(status));
+            }
+            let busy = [
+                DfuState::DfuDnloadSync,
+                DfuState::DfuDnBusy,
+                DfuState::DfuManifestSync,
+                DfuState::DfuManifest,
+            ];
+            if !busy.iter().any(|&s| s as u8 == status.state) {
+                return Ok(status);
+            }
+            self.transport.delay_ms(status.poll_timeout);
+        }
+    }
+
+    /// Send `DFU_DNLOAD` with `block` number and wait until the device processes it.
+    pub fn download_block(
+        &mut self,
+        block: u16,
+        data: &[u8],
+    ) -> Result<Status, HostError<T::Error>> {
+        self.control_out(DFU_DNLOAD, block, data)?;
+        let status = self.wait()?;
+        match status.state {
+            state if state == DfuState::DfuDnloadIdle as u8 => Ok(status),
+            state => Err(HostError::State(state)),
+        }
+    }
+
+    /// Execute a DfuSe command, e.g. `[0x21, address...]`.
+    pub fn command(&mut self, command: &[u8]) -> Result<(), HostError<T::Error>> {
+        self.download_block(0, command).map(|_| ())
+    }
+
+    /// Set Address Pointer with DfuSe command.
+    pub fn set_address(&mut self, address: u32) -> Result<(), HostError<T::Error>> {
+        let mut command = [DownloadCommand::SetAddressPointer as u8, 0, 0, 0, 0];
+        command[1..].copy_from_slice(&address.to_le_bytes());
+        self.command(&command)
+    }
+
+    /// Erase the page that contains `address` with DfuSe command.
+    pub fn erase(&mut self, address: u32) -> Result<(), HostError<T::Error>> {
+        let mut command = [DownloadCommand::Erase as u8, 0, 0, 0, 0];
+        command[1..].copy_from_slice(&address.to_le_bytes());
+        self.command(&command)
+    }
+
+    /// Erase all memory with DfuSe command.
+    pub fn mass_erase(&mut self) -> Result<(), HostError<T::Error>> {
+        self.command(&[DownloadCommand::Erase as u8])
+    }
+
+    /// Allow the next [`mass_erase()`](DfuHost::mass_erase) with vendor *Unlock Mass Erase*
+    /// command, see [`DfuMemory::MASS_ERASE_GUARD`](crate::class::DfuMemory::MASS_ERASE_GUARD).
+    pub fn unlock_mass_erase(&mut self) -> Result<(), HostError<T::Error>> {
+        self.command(&[DownloadCommand::UnlockMassErase as u8])
+    }
+
+    /// Allow downloads with vendor *Unlock* command and `token`, see
+    /// [`DfuMemory::UNLOCK_COMMAND`](crate::class::DfuMemory::UNLOCK_COMMAND).
+    pub fn unlock(&mut self, token: &[u8]) -> Result<(), HostError<T::Error>> {
+        // a longer token is sent cut to one byte over the limit, so the device rejects it
+        let len = token.len().min(MAX_UNLOCK_TOKEN_LENGTH + 1);
+        let mut command = [0; 2 + MAX_UNLOCK_TOKEN_LENGTH];
+        command[0] = DownloadCommand::Unlock as u8;
+        command[1..1 + len].copy_from_slice(&token[..len]);
+        self.command(&command[..1 + len])
+    }
+
+    /// Announce the length of the image with vendor *Set Image Size* command, see
+    /// [`DfuMemory::IMAGE_SIZE_COMMAND`](crate::class::DfuMemory::IMAGE_SIZE_COMMAND).
+    pub fn set_image_size(&mut self, length: u32) -> Result<(), HostError<T::Error>> {
+        let mut command = [DownloadCommand::SetImageSize as u8, 0, 0, 0, 0];
+        command[1..].copy_from_slice(&length.to_le_bytes());
+        self.command(&command)
+    }
+
+    /// Start a download that compares the blocks with the memory with vendor *Verify*
+    /// command, see [`DfuMemory::VERIFY_COMMAND`](crate::class::DfuMemory::VERIFY_COMMAND).
+    pub fn verify(&mut self) -> Result<(), HostError<T::Error>> {
+        self.command(&[DownloadCommand::Verify as u8])
+    }
+
+    /// Erase `length` bytes starting at `address` with vendor *Erase Range* command,
+    /// see [`DfuMemory::ERASE_RANGE_COMMAND`](crate::class::DfuMemory::ERASE_RANGE_COMMAND).
+    pub fn erase_range(&mut self, address: u32, length: u32) -> Result<(), HostError<T::Error>> {
+        let mut command = [DownloadCommand::EraseRange as u8, 0, 0, 0, 0, 0, 0, 0, 0];
+        command[1..5].copy_from_slice(&address.to_le_bytes());
+        command[5..].copy_from_slice(&length.to_le_bytes());
+        self.command(&command)
+    }
+
+    /// Returns the readout protection level with vendor *Readout Protection* command, see
+    /// [`DfuMemory::READOUT_PROTECTION_COMMANDS`](crate::class::DfuMemory::READOUT_PROTECTION_COMMANDS).
+    pub fn readout_protection(&mut self) -> Result<ReadoutProtection, HostError<T::Error>> {
+        self.command(&[DownloadCommand::ReadoutProtection as u8])?;
+        self.abort()?;
+        let mut reply = [0; 4];
+        match self.control_in(DFU_UPLOAD, 1, &mut reply)? {
+            1 => ReadoutProtection::try_from(reply[0]).map_err(|_| HostError::Reply),
+            _ => Err(HostError::Reply),
+        }
+    }
+
+    /// Change the readout protection level with vendor *Readout Protection* command, see
+    /// [`DfuMemory::READOUT_PROTECTION_COMMANDS`](crate::class::DfuMemory::READOUT_PROTECTION_COMMANDS).
+    pub fn set_readout_protection(
+        &mut self,
+        level: ReadoutProtection,
+    ) -> Result<(), HostError<T::Error>> {
+        self.command(&[DownloadCommand::ReadoutProtection as u8, level as u8])
+    }
+
+    /// Read the device metadata to `buf` with vendor *Get Device Info* command, see
+    /// [`DfuMemory::DEVICE_INFO_COMMAND`](crate::class::DfuMemory::DEVICE_INFO_COMMAND).
+    pub fn device_info<'b>(
+        &mut self,
+        buf: &'b mut [u8; MAX_DEVICE_INFO_LENGTH],
+    ) -> Result<DeviceInfo<'b>, HostError<T::Error>> {
+        self.command(&[DownloadCommand::GetDeviceInfo as u8])?;
+        self.abort()?;
+        let n = self.control_in(DFU_UPLOAD, 1, buf)?;
+        DeviceInfo::decode(&buf[..n]).ok_or(HostError::Reply)
+    }
+
+    /// Send TLV `parameters` with vendor *Set Parameters* command, see
+    /// [`DfuMemory::TLV_COMMANDS`](crate::class::DfuMemory::TLV_COMMANDS).
+    pub fn set_parameters(&mut self, parameters: &[u8]) -> Result<(), HostError<T::Error>> {
+        // a longer payload is sent cut to one byte over the limit, so the device rejects it
+        let len = parameters.len().min(MAX_PAYLOAD_LENGTH + 1);
+        let mut command = [0; 2 + MAX_PAYLOAD_LENGTH];
+        command[0] = DownloadCommand::SetParameters as u8;
+        command[1..1 + len].copy_from_slice(&parameters[..len]);
+        self.command(&command[..1 + len])
+    }
+
+    /// Read the capabilities of the device to `buf` with vendor *Get Capabilities* command,
+    /// see [`DfuMemory::TLV_COMMANDS`](crate::class::DfuMemory::TLV_COMMANDS).
+    pub fn capabilities<'b>(
+        &mut self,
+        buf: &'b mut [u8; MAX_PAYLOAD_LENGTH],
+    ) -> Result<TlvReader<'b>, HostError<T::Error>> {
+        self.command(&[DownloadCommand::GetCapabilities as u8])?;
+        self.abort()?;
+        let n = self.control_in(DFU_UPLOAD, 1, buf)?;
+        let reader = TlvReader::new(&buf[..n]);
+        match reader.is_valid() {
+            true => Ok(reader),
+            false => Err(HostError::Reply),
+        }
+    }
+
+    /// Remove the readout protection with DfuSe *Read Unprotect* command,
+    /// the device usually erases the memory.
+    pub fn read_unprotect(&mut self) -> Result<(), HostError<T::Error>> {
+        self.command(&[DownloadCommand::ReadUnprotect as u8])
+    }
+
+    /// Download `image` to `address` in blocks of *wTransferSize* bytes.
+    ///
+    /// The download is not finished, call [`manifest()`](Self::manifest) to finish it.
+    pub fn download(&mut self, address: u32, image: &[u8]) -> Result<(), HostError<T::Error>> {
+        if self.transfer_size == 0 {
+            return Err(HostError::TransferSize);
+        }
+        self.set_address(address)?;
+        for (i, block) in image.chunks(self.transfer_size as usize).enumerate() {
+            self.download_block((i as u16).wrapping_add(2), block)?;
+        }
+        Ok(())
+    }
+
+    /// Verify the suffix of DFU `file` and download the file without it to `address`.
+    ///
+    /// Returns the suffix. The download is not finished, call
+    /// [`manifest()`](Self::manifest) to finish it.
+    pub fn download_file(
+        &mut self,
+        address: u32,
+        file: &[u8],
+    ) -> Result<Suffix, HostError<T::Error>> {
+        let suffix = Suffix::try_from(file).map_err(HostError::Suffix)?;
+        let mut crc = Crc32::new();
+        crc.update(&file[..file.len() - 4]);
+        if crc.finalize() != suffix.crc {
+            return Err(HostError::Crc);
+        }
+        if let Some((usb_vendor, usb_product, device)) = self.device {
+            suffix
+                .matches_device(usb_vendor, usb_product, device)
+                .map_err(HostError::Mismatch)?;
+        }
+        self.download(address, &file[..file.len() - suffix.length as usize])?;
+        Ok(suffix)
+    }
+
+    /// Finish the download with a zero-length `DFU_DNLOAD`, and wait for manifestation.
+    ///
+    /// Returns the final status, the device is in `dfuIDLE` state if it's
+    /// manifestation tolerant, or in `dfuMANIFEST-WAIT-RESET` state.
+    pub fn manifest(&mut self) -> Result<Status, HostError<T::Error>> {
+        self.control_out(DFU_DNLOAD, 0, &[])?;
+        self.wait()
+    }
+
+    /// Upload to `data` from `address`, returns the number of uploaded bytes,
+    /// fewer than `data` length if the device ends the upload with a short block.
+    pub fn upload(&mut self, address: u32, data: &mut [u8]) -> Result<usize, HostError<T::Error>> {
+        if self.transfer_size == 0 {
+            return Err(HostError::TransferSize);
+        }
+        self.set_address(address)?;
+        self.abort()?;
+
+        let mut uploaded = 0;
+        for (i, block) in data.chunks_mut(self.transfer_size as usize).enumerate() {
+            let n = self
+                .transport
+                .control_in(DFU_UPLOAD, (i as u16).wrapping_add(2), block)
+                .map_err(HostError::Transport)?;
+            uploaded += n;
+            if n < self.transfer_size as usize {
+                break;
+            }
+        }
+        self.abort()?;
+        Ok(uploaded)
+    }
+}
+
+/// [`ControlTransport`] for a device opened with `rusb`, requires `rusb` feature.
+#[cfg(feature = "rusb")]
+pub struct RusbTransport<C: rusb::UsbContext> {
+    handle: rusb::DeviceHandle<C>,
+    interface: u8,
+    timeout: std::time::Duration,
+}
+
+#[cfg(feature = "rusb")]
+impl RusbTransport<rusb::GlobalContext> {
+    /// Open the first device with `vendor_id` and `product_id`, and claim DFU `interface`.
+    pub fn open(vendor_id: u16, product_id: u16, interface: u8) -> rusb::Result<Self> {
+        let handle =
+            rusb::open_device_with_vid_pid(vendor_id, product_id).ok_or(rusb::Error::NoDevice)?;
+        Self::new(handle, interface)
+    }
+}
+
+#[cfg(feature = "rusb")]
+impl<C: rusb::UsbContext> RusbTransport<C> {
+    /// Claim DFU `interface` of `handle`.
+    pub fn new(handle: rusb::DeviceHandle<C>, interface: u8) -> rusb::Result<Self> {
+        handle.claim_interface(interface)?;
+        Ok(Self {
+            handle,
+            interface,
+            timeout: std::time::Duration::from_secs(1),
+        })
+    }
+
+    /// Select alternate setting `alt` of the DFU interface.
+    pub fn set_alt_setting(&mut self, alt: u8) -> rusb::Result<()> {
+        self.handle.set_alternate_setting(self.interface, alt)
+    }
+
+    /// Returns a reference to the device handle, e.g. to reset the device after `DFU_DETACH`.
+    pub fn handle(&mut self) -> &mut rusb::DeviceHandle<C> {
+        &mut self.handle
+    }
+}
+
+#[cfg(feature = "rusb")]
+impl<C: rusb::UsbContext> ControlTransport for RusbTransport<C> {
+    type Error = rusb::Error;
+
+    fn delay_ms(&mut self, ms: u32) {
+        std::thread::sleep(std::time::Duration::from_millis(ms as u64));
+    }
+
+    fn control_out(&mut self, request: u8, value: u16, data: &[u8]) -> Result<(), Self::Error> {
+        let request_type = rusb::request_type(
+            rusb::Direction::Out,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        );
+        self.handle
+            .write_control(
+                request_type,
+                request,
+                value,
+                self.interface as u16,
+                data,
+                self.timeout,
+            )
+            .map(|_| ())
+    }
+
+    fn control_in(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        let request_type = rusb::request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Class,
+            rusb::Recipient::Interface,
+        );
+        self.handle.read_control(
+            request_type,
+            request,
+            value,
+            self.interface as u16,
+            data,
+            self.timeout,
+        )
+    }
+}
@@ -0,0 +1,225 @@
+//! I2C EEPROM
+//!
+//! [`I2cEeprom`] implements [`DfuMemory`] for a 24xx-style I2C EEPROM, e.g.
+//! AT24C02 or M24C64, connected to an `embedded-hal` [`I2c`] bus, for example
+//! to expose a configuration memory as an alternate setting.
+//!
+//! EEPROM is written without erase, so erase requests succeed without changes.
+//! Blocks are written in pages of [`EepromChip::PAGE_SIZE`] bytes, and the write
+//! cycle of each page is waited for with acknowledge polling, at most [`ACK_POLLS`]
+//! times. DFU addresses are EEPROM addresses, starting at `0`.
+//!
+//! Requires `i2c-eeprom` feature.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::i2c_eeprom::*;
+//! # use usbd_dfu::DfuClass;
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, i2c: impl embedded_hal::i2c::I2c) {
+//! let mem = I2cEeprom::<_, At24c256, 64>::new(i2c, 0x50);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use core::marker::PhantomData;
+
+use embedded_hal::i2c::{Error, ErrorKind, I2c, Operation};
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError};
+use crate::mem_info::MemInfo;
+
+/// Maximum write cycle time of common I2C EEPROMs.
+pub const WRITE_CYCLE_TIME_MS: u32 = 5;
+
+/// Maximum number of acknowledge polls after a page write.
+///
+/// A poll is at least 18 clocks, 18 µs at 1 MHz, so the polls last longer than
+/// a write cycle of [`WRITE_CYCLE_TIME_MS`].
+pub const ACK_POLLS: u32 = WRITE_CYCLE_TIME_MS * 100;
+
+/// Maximum length of generated memory info strings.
+const MEM_INFO_LENGTH: usize = 64;
+
+/// Size and organization of an I2C EEPROM.
+pub trait EepromChip {
+    /// Size in bytes.
+    const SIZE: u32;
+
+    /// Page size in bytes, a write can't cross a page boundary.
+    const PAGE_SIZE: u32;
+
+    /// Number of memory address bytes, `1` or `2`.
+    ///
+    /// With `1` address byte, higher address bits are sent in the device address,
+    /// as on AT24C04, AT24C08, and AT24C16.
+    const ADDRESS_BYTES: usize;
+}
+
+/// AT24C02, 256 bytes.
+pub struct At24c02;
+
+impl EepromChip for At24c02 {
+    const SIZE: u32 = 256;
+    const PAGE_SIZE: u32 = 8;
+    const ADDRESS_BYTES: usize = 1;
+}
+
+/// AT24C16, 2 KiB.
+pub struct At24c16;
+
+impl EepromChip for At24c16 {
+    const SIZE: u32 = 2048;
+    const PAGE_SIZE: u32 = 16;
+    const ADDRESS_BYTES: usize = 1;
+}
+
+/// AT24C32, 4 KiB.
+pub struct At24c32;
+
+impl EepromChip for At24c32 {
+    const SIZE: u32 = 4096;
+    const PAGE_SIZE: u32 = 32;
+    const ADDRESS_BYTES: usize = 2;
+}
+
+/// AT24C256, 32 KiB.
+pub struct At24c256;
+
+impl EepromChip for At24c256 {
+    const SIZE: u32 = 32768;
+    const PAGE_SIZE: u32 = 64;
+    const ADDRESS_BYTES: usize = 2;
+}
+
+/// Memory info string of EEPROM of `size` bytes with `page_size` byte pages.
+const fn mem_info(size: u32, page_size: u32) -> MemInfo<MEM_INFO_LENGTH> {
+    MemInfo::new("EEPROM", 0).area(size / page_size, page_size, 'e')
+}
+
+/// [`DfuMemory`] implementation for an I2C EEPROM.
+///
+/// `N` is the size of the internal buffer and the
+/// [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE).
+pub struct I2cEeprom<I: I2c, C: EepromChip, const N: usize> {
+    i2c: I,
+    device_address: u8,
+    buffer: [u8; N],
+    _chip: PhantomData<C>,
+}
+
+impl<I: I2c, C: EepromChip, const N: usize> I2cEeprom<I, C, N> {
+    /// Access EEPROM at 7-bit `device_address` with `i2c`, usually `0x50`.
+    pub fn new(i2c: I, device_address: u8) -> Self {
+        Self {
+            i2c,
+            device_address,
+            buffer: [0; N],
+            _chip: PhantomData,
+        }
+    }
+
+    /// Destroy the memory and return the I2C bus.
+    pub fn release(self) -> I {
+        self.i2c
+    }
+
+    /// Returns device address and memory address bytes of `address`.
+    fn address(&self, address: u32) -> (u8, [u8; 2], usize) {
+        let bytes = address.to_be_bytes();
+        let high = (address >> (8 * C::ADDRESS_BYTES)) as u8 & 0x7;
+        let memory_address = [bytes[2], bytes[3]];
+        (
+            self.device_address | high,
+            memory_address,
+            2 - C::ADDRESS_BYTES,
+        )
+    }
+
+    /// Wait for the write cycle, the device doesn't acknowledge its address until it's complete.
+    fn wait_ready(&mut self, device_address: u8) -> Result<(), DfuMemoryError> {
+        for _ in 0..ACK_POLLS {
+            match self.i2c.read(device_address, &mut [0]) {
+                Ok(_) => return Ok(()),
+                Err(e) if matches!(e.kind(), ErrorKind::NoAcknowledge(_)) => {}
+                Err(_) => return Err(DfuMemoryError::Write),
+            }
+        }
+        Err(DfuMemoryError::Write)
+    }
+}
+
+impl<I: I2c, C: EepromChip, const N: usize> DfuMemory for I2cEeprom<I, C, N> {
+    const INITIAL_ADDRESS_POINTER: u32 = 0;
+    const MEM_INFO_STRING: &'static str = mem_info(C::SIZE, C::PAGE_SIZE).as_str();
+    const PROGRAM_TIME_MS: u32 = WRITE_CYCLE_TIME_MS * (N as u32).div_ceil(C::PAGE_SIZE);
+    const ERASE_TIME_MS: u32 = 0;
+    const FULL_ERASE_TIME_MS: u32 = 0;
+    const TRANSFER_SIZE: u16 = N as u16;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        if address >= C::SIZE {
+            return Ok(&[]);
+        }
+        let length = length.min(N).min((C::SIZE - address) as usize);
+        let (device_address, memory_address, skip) = self.address(address);
+        let buffer = &mut self.buffer[..length];
+        self.i2c
+            .write_read(device_address, &memory_address[skip..], buffer)
+            .map_err(|_| DfuMemoryError::Unknown)?;
+        Ok(buffer)
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        if length > N {
+            return Err(DfuMemoryError::Prog);
+        }
+        match address.checked_add(length as u32) {
+            Some(end) if end <= C::SIZE => {}
+            _ => return Err(DfuMemoryError::Address),
+        }
+
+        let mut written = 0;
+        while written < length {
+            let page_address = address + written as u32;
+            let page_left = (C::PAGE_SIZE - page_address % C::PAGE_SIZE) as usize;
+            let chunk = page_left.min(length - written);
+
+            let (device_address, memory_address, skip) = self.address(page_address);
+            self.i2c
+                .transaction(
+                    device_address,
+                    &mut [
+                        Operation::Write(&memory_address[skip..]),
+                        Operation::Write(&self.buffer[written..written + chunk]),
+                    ],
+                )
+                .map_err(|_| DfuMemoryError::Write)?;
+            self.wait_ready(device_address)?;
+            written += chunk;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        if address >= C::SIZE {
+            return Err(DfuMemoryError::Address);
+        }
+        Ok(())
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+//! Device metadata
+//!
+//! With [`DfuMemory::DEVICE_INFO_COMMAND`](crate::DfuMemory::DEVICE_INFO_COMMAND),
+//! update tools can read the serial number, unique ID, hardware revision, and bootloader
+//! and application versions of the device before a download, e.g. to pick a compatible
+//! image, or to record which unit received which image:
+//!
+//! ```no_run
+//! # use usbd_dfu::info::DeviceInfo;
+//! # use usbd_dfu::DfuMemory;
+//! # struct AppHeader {
+//! #     version: u32,
+//! # }
+//! # struct MyMem {
+//! #     serial: &'static str,
+//! #     uid: [u8; 12],
+//! # }
+//! # impl MyMem {
+//! #     fn app_header(&self) -> Option<AppHeader> {
+//! #         None
+//! #     }
+//! # }
+//! # impl DfuMemory for MyMem {
+//! #     const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+//! #     const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*1Kg";
+//! #     const PROGRAM_TIME_MS: u32 = 10;
+//! #     const ERASE_TIME_MS: u32 = 20;
+//! #     const FULL_ERASE_TIME_MS: u32 = 320;
+//! #     const DEVICE_INFO_COMMAND: bool = true;
+//! #
+//! fn device_info(&mut self) -> DeviceInfo<'_> {
+//!     DeviceInfo {
+//!         serial_number: self.serial,
+//!         // e.g. 96-bit unique ID of STM32
+//!         unique_id: &self.uid,
+//!         hardware_revision: 3,
+//!         bootloader_version: 0x0001_0200,
+//!         application_version: self.app_header().map(|h| h.version),
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! The reply is a sequence of records framed as described in [`tlv`](crate::tlv) module.
+//! Records with unknown tags must be skipped by the host, so new fields can be added.
+
+use crate::tlv::{TlvReader, TlvWriter};
+
+/// Tag of the serial number record, UTF-8 string.
+pub const TAG_SERIAL_NUMBER: u8 = 0x01;
+/// Tag of the hardware revision record, 2 bytes.
+pub const TAG_HARDWARE_REVISION: u8 = 0x02;
+/// Tag of the bootloader version record, 4 bytes.
+pub const TAG_BOOTLOADER_VERSION: u8 = 0x03;
+/// Tag of the application version record, 4 bytes, omitted if there is no application.
+pub const TAG_APPLICATION_VERSION: u8 = 0x04;
+/// Tag of the unique ID record, raw bytes, omitted if empty.
+pub const TAG_UNIQUE_ID: u8 = 0x05;
+
+/// Maximum length of [`DeviceInfo::serial_number`].
+pub const MAX_SERIAL_NUMBER_LENGTH: usize = 32;
+
+/// Maximum length of [`DeviceInfo::unique_id`].
+pub const MAX_UNIQUE_ID_LENGTH: usize = 16;
+
+/// Maximum length of encoded [`DeviceInfo`].
+pub const MAX_DEVICE_INFO_LENGTH: usize =
+    2 + MAX_SERIAL_NUMBER_LENGTH + 2 + MAX_UNIQUE_ID_LENGTH + 2 + 2 + 2 + 4 + 2 + 4;
+
+/// Metadata of the device, see [`DfuMemory::device_info()`](crate::DfuMemory::device_info).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct DeviceInfo<'a> {
+    /// Serial number, at most [`MAX_SERIAL_NUMBER_LENGTH`] bytes, omitted if empty.
+    pub serial_number: &'a str,
+    /// Unique ID of the MCU, e.g. read from the factory-programmed registers,
+    /// at most [`MAX_UNIQUE_ID_LENGTH`] bytes, omitted if empty.
+    pub unique_id: &'a [u8],
+    /// Hardware revision.
+    pub hardware_revision: u16,
+    /// Bootloader version.
+    pub bootloader_version: u32,
+    /// Version of the installed application, `None` if there is no valid application.
+    pub application_version: Option<u32>,
+}
+
+impl<'a> DeviceInfo<'a> {
+    /// Encode to `buf`, returns the length,
+    /// or `None` if `buf` is too short, or the serial number or the unique ID is too long.
+    pub fn encode(&self, buf: &mut [u8]) -> Option<usize> {
+        if self.serial_number.len() > MAX_SERIAL_NUMBER_LENGTH
+            || self.unique_id.len() > MAX_UNIQUE_ID_LENGTH
+        {
+            return None;
+        }
+        let mut w = TlvWriter::new(buf);
+        if !self.serial_number.is_empty() {
+            w.str(TAG_SERIAL_NUMBER, self.serial_number).ok()?;
+        }
+        if !self.unique_id.is_empty() {
+            w.record(TAG_UNIQUE_ID, self.unique_id).ok()?;
+        }
+        w.u16(TAG_HARDWARE_REVISION, self.hardware_revision).ok()?;
+        w.u32(TAG_BOOTLOADER_VERSION, self.bootloader_version)
+            .ok()?;
+        if let Some(version) = self.application_version {
+            w.u32(TAG_APPLICATION_VERSION, version).ok()?;
+        }
+        Some(w.len())
+    }
+
+    /// Decode a reply, returns `None` if a record is truncated or not valid.
+    ///
+    /// Missing records are left at their default values.
+    pub fn decode(data: &'a [u8]) -> Option<Self> {
+        let mut info = DeviceInfo::default();
+        for record in TlvReader::new(data) {
+            let record = record.ok()?;
+            match record.tag {
+                TAG_SERIAL_NUMBER => info.serial_number = record.str()?,
+                TAG_HARDWARE_REVISION => info.hardware_revision = record.u16()?,
+                TAG_BOOTLOADER_VERSION => info.bootloader_version = record.u32()?,
+                TAG_APPLICATION_VERSION => info.application_version = Some(record.u32()?),
+                TAG_UNIQUE_ID => info.unique_id = record.value,
+                // added in a later version
+                _ => {}
+            }
+        }
+        Some(info)
+    }
+}
@@ -0,0 +1,247 @@
+//! Power-loss-safe download journal
+//!
+//! [`JournalMemory`] wraps [`DfuMemory`] and records the progress of each download
+//! in a [`DownloadJournal`], e.g. a flash page, FRAM, or backup registers. Before
+//! the first erase or program operation of a download, an
+//! [`InProgress`](JournalState::InProgress) entry is recorded, and it's updated
+//! after every programmed block. After a successful manifestation the entry
+//! becomes [`Complete`](JournalState::Complete).
+//!
+//! After an unexpected power loss the bootloader loads the journal, and if the
+//! last download is still in progress, the image is partial and must not be started:
+//!
+//! ```no_run
+//! # use usbd_dfu::journal::DownloadJournal;
+//! # fn enter_dfu_mode() {}
+//! # fn start_application() {}
+//! # fn example(mut journal: impl DownloadJournal) {
+//! match journal.load() {
+//!     Ok(Some(entry)) if entry.is_partial() => enter_dfu_mode(),
+//!     _ => start_application(),
+//! }
+//! # }
+//! ```
+//!
+//! With [`RESUME_COMMAND`](DfuMemory::RESUME_COMMAND) set on the wrapped memory,
+//! [`DfuMemory::resume_point()`] returns the end of the partial image, and the host
+//! can continue the download from there. The resumed download keeps the start
+//! address recorded in the journal.
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError};
+use crate::suffix::Crc32;
+
+/// Size of a serialized journal entry in bytes.
+pub const JOURNAL_ENTRY_LENGTH: usize = 16;
+
+/// State of the download recorded in the journal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum JournalState {
+    /// Download is started but not finished, the image is partial.
+    InProgress = 1,
+    /// Download and manifestation completed.
+    Complete = 2,
+}
+
+/// Journal entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct JournalEntry {
+    /// State of the download.
+    pub state: JournalState,
+    /// Address of the first programmed block.
+    pub start: u32,
+    /// End address of the last successfully programmed block, exclusive.
+    pub end: u32,
+}
+
+impl JournalEntry {
+    /// Returns `true` if the download is not complete.
+    pub fn is_partial(&self) -> bool {
+        self.state != JournalState::Complete
+    }
+
+    /// Serialize the entry, all fields and CRC-32 of them are little-endian `u32`.
+    pub fn to_bytes(&self) -> [u8; JOURNAL_ENTRY_LENGTH] {
+        let mut bytes = [0; JOURNAL_ENTRY_LENGTH];
+        bytes[0..4].copy_from_slice(&(self.state as u32).to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.start.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.end.to_le_bytes());
+        let mut crc = Crc32::new();
+        crc.update(&bytes[..12]);
+        bytes[12..16].copy_from_slice(&crc.finalize().to_le_bytes());
+        bytes
+    }
+
+    /// Parse the entry serialized by [`to_bytes()`](Self::to_bytes).
+    ///
+    /// Returns `None` if the entry is erased or damaged, e.g. by a power loss during write.
+    pub fn from_bytes(bytes: &[u8; JOURNAL_ENTRY_LENGTH]) -> Option<Self> {
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let mut crc = Crc32::new();
+        crc.update(&bytes[..12]);
+        if crc.finalize() != u32_at(12) {
+            return None;
+        }
+
+        let state = match u32_at(0) {
+            1 => JournalState::InProgress,
+            2 => JournalState::Complete,
+            _ => return None,
+        };
+        Some(Self {
+            state,
+            start: u32_at(4),
+            end: u32_at(8),
+        })
+    }
+}
+
+/// Persistent storage of the last [`JournalEntry`].
+pub trait DownloadJournal {
+    /// Load the last recorded entry, `None` if nothing was recorded.
+    #[allow(clippy::result_unit_err)]
+    fn load(&mut self) -> Result<Option<JournalEntry>, ()>;
+
+    /// Record `entry`, replacing the previous one.
+    #[allow(clippy::result_unit_err)]
+    fn record(&mut self, entry: &JournalEntry) -> Result<(), ()>;
+}
+
+/// [`DfuMemory`] adapter that records download progress in a [`DownloadJournal`].
+///
+/// If the journal can't be written, programming fails with `errWRITE`, and
+/// manifestation fails with `errUNKNOWN`.
+pub struct JournalMemory<M: DfuMemory, J: DownloadJournal> {
+    mem: M,
+    journal: J,
+    /// [`InProgress`](JournalState::InProgress) entry is recorded for the current download.
+    started: bool,
+    /// Address of the first programmed block of the current download.
+    start: Option<u32>,
+    /// End address of the last programmed block.
+    end: u32,
+    /// No download was started since the adapter was created, so the next one
+    /// may continue the download recorded in the journal.
+    resumable: bool,
+}
+
+impl<M: DfuMemory, J: DownloadJournal> JournalMemory<M, J> {
+    /// Wrap `mem`, and record downloads in `journal`.
+    pub fn new(mem: M, journal: J) -> Self {
+        Self {
+            mem,
+            journal,
+            started: false,
+            start: None,
+            end: 0,
+            resumable: true,
+        }
+    }
+
+    /// Returns a reference to the journal.
+    pub fn journal(&mut self) -> &mut J {
+        &mut self.journal
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory and the journal.
+    pub fn release(self) -> (M, J) {
+        (self.mem, self.journal)
+    }
+
+    fn record(&mut self, state: JournalState, start: u32, end: u32) -> Result<(), ()> {
+        self.journal.record(&JournalEntry { state, start, end })
+    }
+
+    /// Mark the image partial before it's modified.
+    fn begin(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        if !self.started && core::mem::take(&mut self.resumable) {
+            // a download without `download_start()` is resumed
+            if let Ok(Some(entry)) = self.journal.load() {
+                if entry.is_partial() {
+                    self.started = true;
+                    self.start = Some(entry.start);
+                    self.end = entry.end;
+                }
+            }
+        }
+        if !self.started {
+            self.record(JournalState::InProgress, address, address)
+                .map_err(|_| DfuMemoryError::Write)?;
+            self.started = true;
+            self.end = address;
+        }
+        Ok(())
+    }
+}
+
+impl<M: DfuMemory, J: DownloadJournal> DfuMemory for JournalMemory<M, J> {
+    forward_dfu_memory!(
+        mem: M,
+        except SKIP_IDENTICAL, program, erase, erase_all, erase_range, manifestation,
+        download_start, resume_point
+    );
+
+    // program() records the downloaded range in the journal
+    const SKIP_IDENTICAL: bool = false;
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        self.begin(address)?;
+        let start = *self.start.get_or_insert(address);
+
+        self.mem.program(address, length)?;
+        let end = address
+            .checked_add(length as u32)
+            .ok_or(DfuMemoryError::Address)?;
+        self.record(JournalState::InProgress, start, end)
+            .map_err(|_| DfuMemoryError::Write)?;
+        self.end = end;
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        self.begin(address)?;
+        self.mem.erase(address)
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        self.begin(M::INITIAL_ADDRESS_POINTER)?;
+        self.mem.erase_all()
+    }
+
+    fn erase_range(&mut self, address: u32, length: u32) -> Result<(), DfuMemoryError> {
+        self.begin(address)?;
+        self.mem.erase_range(address, length)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.mem.manifestation()?;
+        if self.started {
+            let start = self.start.unwrap_or(self.end);
+            self.record(JournalState::Complete, start, self.end)
+                .map_err(|_| DfuManifestationError::Unknown)?;
+            self.started = false;
+            self.start = None;
+        }
+        Ok(())
+    }
+
+    fn download_start(&mut self) {
+        self.started = false;
+        self.start = None;
+        self.resumable = false;
+        self.mem.download_start()
+    }
+
+    fn resume_point(&mut self) -> Option<u32> {
+        match self.journal.load() {
+            Ok(Some(entry)) if entry.is_partial() => Some(entry.end),
+            _ => None,
+        }
+    }
+}
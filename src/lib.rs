@@ -27,18 +27,16 @@
 //! * Write (host to device) - download command
 //! * Erase
 //! * Erase All
-//!
-//! ### Not supported operations
-//!
-//! * Read Unprotect - erase everything and remove read protection.
+//! * Read Unprotect - erase everything and remove read protection, see
+//!   [`DfuMemory::READOUT_PROTECTION_COMMANDS`].
 //!
 //! ### Limitations
 //!
 //! * Maximum USB transfer size is limited to what `usb-device` supports
-//! for control enpoint transfers, which is `128` bytes by default.
+//!   for control enpoint transfers, which is `128` bytes by default.
 //!
 //! * iString field in `DFU_GETSTATUS` is always `0`. Vendor-specific string
-//! error descriptions are not supported.
+//!   error descriptions are not supported.
 //!
 //! ## DFU utilities
 //!
@@ -176,9 +174,86 @@
 //! See [usbd-dfu-example](https://github.com/vitalyvb/usbd-dfu-example) for a functioning example.
 //!
 
+#[cfg(any(feature = "rusb", feature = "test-helpers"))]
+extern crate std;
+
+#[macro_use]
+mod macros;
+
+pub mod bos;
+pub mod buffer;
+pub mod cache;
 /// DFU protocol module
 pub mod class;
+#[cfg(feature = "test-helpers")]
+pub mod conformance;
+pub mod consts;
+pub mod decompress;
+pub mod delta;
+pub mod dfuse;
+#[cfg(feature = "stm32-dual-bank")]
+pub mod dual_bank;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "embedded-storage")]
+pub mod flash;
+pub mod hash;
+pub mod header;
+#[cfg(feature = "host")]
+pub mod host;
+#[cfg(feature = "i2c-eeprom")]
+pub mod i2c_eeprom;
+pub mod info;
+pub mod journal;
+pub mod link;
+pub mod manifest;
+#[cfg(feature = "embedded-storage")]
+pub mod mapped;
+pub mod mcuboot;
+pub mod mem_info;
+#[cfg(feature = "nrf52")]
+pub mod nrf;
+pub mod ram;
+#[cfg(feature = "test-helpers")]
+pub mod replay;
+pub mod rollback;
+#[cfg(feature = "rp2040")]
+pub mod rp2040;
+pub mod sd_staging;
+/// DFU class shared between interrupt handler and main loop
+#[cfg(feature = "critical-section")]
+pub mod shared;
+pub mod slots;
+#[cfg(feature = "spi-nor")]
+pub mod spi_nor;
+/// Split DFU class into USB and memory halves
+pub mod split;
+pub mod staging;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod status;
+#[cfg(feature = "stm32-flash")]
+pub mod stm32;
 pub mod suffix;
+#[cfg(feature = "test-helpers")]
+pub mod test_helpers;
+pub mod tlv;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod transform;
+pub mod verify;
+#[cfg(feature = "wcid")]
+pub mod wcid;
+pub mod webusb;
 
 #[doc(inline)]
-pub use crate::class::{DfuClass, DfuManifestationError, DfuMemory, DfuMemoryError};
+pub use crate::class::{
+    AltSetting, BlockSequence, BlockSizes, DfuClass, DfuClassBuilder, DfuManifestationError,
+    DfuMemory, DfuMemoryError, DfuState, DfuStatusCode, EmptyDownload, InitialState,
+    MemoryErrorDetail, ReadOutcome, ReadoutProtection, UploadEnd,
+};
+#[cfg(feature = "critical-section")]
+#[doc(inline)]
+pub use crate::shared::SharedDfuClass;
+#[doc(inline)]
+pub use crate::split::{DfuChannel, DfuControl, DfuWorker};
@@ -0,0 +1,398 @@
+//! DFU over a serial or CAN link
+//!
+//! [`DfuLink`] runs the same protocol state machine as [`DfuClass`](crate::DfuClass)
+//! over any message or byte transport, e.g. UART, RS-485, or CAN with ISO-TP,
+//! so one bootloader can be updated over USB in the lab and over CAN in the field.
+//!
+//! USB control transfers are replaced with request and reply frames:
+//!
+//! * Request is the 8-byte USB setup packet, *bmRequestType*, *bRequest*,
+//!   *wValue*, *wIndex*, and *wLength*, little-endian, followed by *wLength*
+//!   data bytes for host-to-device requests (`0x21`). For device-to-host
+//!   requests (`0xA1`) *wLength* is the maximum reply data length.
+//!   *wIndex* must be `0`.
+//! * Reply is the result byte, [`REPLY_ACK`] or [`REPLY_STALL`], and the
+//!   data length as a little-endian `u16`, followed by the data.
+//!
+//! With a message transport, e.g. ISO-TP, each message is one frame, pass it to
+//! [`DfuLink::handle()`] and send the reply back. With a byte stream, e.g. UART,
+//! [`StreamLink`] finds frame boundaries. A stream has no resynchronization,
+//! call [`StreamLink::reset()`] after a gap in received data, e.g. 100 ms.
+//!
+//! Memory operations are executed after each frame, the host polls the status
+//! with `DFU_GETSTATUS` requests as over USB.
+//!
+//! ```no_run
+//! # use usbd_dfu::link::*;
+//! # use usbd_dfu::DfuMemory;
+//! # trait Uart {
+//! #     fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()>;
+//! #     fn write_all(&mut self, buf: &[u8]) -> Result<(), ()>;
+//! # }
+//! # fn example(my_mem: impl DfuMemory, uart: &mut impl Uart) -> Result<(), ()> {
+//! # let mut rx = [0; 64];
+//! let mut link = StreamLink::<_, { SETUP_LENGTH + 128 }>::new(my_mem);
+//!
+//! loop {
+//!     let n = uart.read(&mut rx)?;
+//!     link.receive(&rx[..n], |reply| uart.write_all(reply).unwrap());
+//! }
+//! # }
+//! ```
+
+use usb_device::control::{Recipient, Request, RequestType};
+use usb_device::UsbDirection;
+
+#[cfg(feature = "upload")]
+use crate::class::query_reply;
+use crate::class::{
+    start_download, BlockSizes, DFUStatus, DfuMemory, DfuStatusCode, MemoryConfig,
+    MemoryErrorDetail,
+};
+#[cfg(feature = "download")]
+use crate::consts::DFU_DNLOAD;
+#[cfg(feature = "upload")]
+use crate::consts::DFU_UPLOAD;
+use crate::consts::{DFU_ABORT, DFU_CLRSTATUS, DFU_GETSTATE, DFU_GETSTATUS};
+use crate::status::ExtendedStatus;
+
+/// Length of the setup packet at the start of a request frame.
+pub const SETUP_LENGTH: usize = 8;
+
+/// Length of the reply frame header.
+pub const REPLY_HEADER_LENGTH: usize = 3;
+
+/// Reply result: request is accepted.
+pub const REPLY_ACK: u8 = 0;
+
+/// Reply result: request is rejected, like a stalled USB control transfer.
+pub const REPLY_STALL: u8 = 1;
+
+/// *bmRequestType* of host-to-device DFU requests.
+const REQUEST_TYPE_OUT: u8 = 0x21;
+
+/// *bmRequestType* of device-to-host DFU requests.
+const REQUEST_TYPE_IN: u8 = 0xa1;
+
+/// Parse the setup packet, returns `None` if it's not a DFU request.
+fn parse_setup(setup: &[u8; SETUP_LENGTH]) -> Option<Request> {
+    let direction = match setup[0] {
+        REQUEST_TYPE_OUT => UsbDirection::Out,
+        REQUEST_TYPE_IN => UsbDirection::In,
+        _ => return None,
+    };
+    let index = u16::from_le_bytes([setup[4], setup[5]]);
+    (index == 0).then_some(Request {
+        direction,
+        request_type: RequestType::Class,
+        recipient: Recipient::Interface,
+        request: setup[1],
+        value: u16::from_le_bytes([setup[2], setup[3]]),
+        index,
+        length: u16::from_le_bytes([setup[6], setup[7]]),
+    })
+}
+
+/// Returns the length of the request frame that starts with `setup`.
+fn frame_length(setup: &[u8; SETUP_LENGTH]) -> usize {
+    match setup[0] {
+        REQUEST_TYPE_OUT => SETUP_LENGTH + u16::from_le_bytes([setup[6], setup[7]]) as usize,
+        _ => SETUP_LENGTH,
+    }
+}
+
+/// Write the reply header and `data` to `reply`, returns the reply length,
+/// `0` if `reply` is shorter than the header.
+fn write_reply(reply: &mut [u8], result: u8, data: &[u8]) -> usize {
+    let Some((header, rest)) = reply.split_first_chunk_mut::<REPLY_HEADER_LENGTH>() else {
+        return 0;
+    };
+    let length = data.len().min(rest.len()).min(u16::MAX as usize);
+    let (data, rest) = (&data[..length], &mut rest[..length]);
+    let [len_lo, len_hi] = (length as u16).to_le_bytes();
+    *header = [result, len_lo, len_hi];
+    rest.copy_from_slice(data);
+    REPLY_HEADER_LENGTH + length
+}
+
+/// DFU protocol over request and reply frames, see the [module documentation](self).
+pub struct DfuLink<M: DfuMemory> {
+    status: DFUStatus,
+    mem: M,
+}
+
+impl<M: DfuMemory> DfuLink<M> {
+    /// Creates a new [`DfuLink`] with the provided [`DfuMemory`].
+    pub fn new(mem: M) -> Self {
+        Self {
+            status: DFUStatus::new(MemoryConfig::new::<M>(), M::INITIAL_ADDRESS_POINTER),
+            mem,
+        }
+    }
+
+    /// Destroy the link and return the memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    /// Returns a reference to the memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Return current Address Pointer value.
+    pub fn get_address_pointer(&self) -> u32 {
+        self.status.address_pointer()
+    }
+
+    /// Set Address Pointer, see [`DfuClass::set_address_pointer()`](crate::DfuClass::set_address_pointer).
+    pub fn set_address_pointer(&mut self, address: u32) -> Result<(), DfuStatusCode> {
+        self.status.set_address_pointer(address)
+    }
+
+    /// Returns the last failed [`DfuMemory`] call, if any.
+    pub fn last_memory_error(&self) -> Option<MemoryErrorDetail> {
+        self.status.last_memory_error()
+    }
+
+    /// Returns the smallest and largest data block of the current or the last download.
+    pub fn download_block_sizes(&self) -> Option<BlockSizes> {
+        self.status.download_block_sizes()
+    }
+
+    /// Returns the received part of the image in percent, if its length was announced.
+    pub fn download_progress(&self) -> Option<u8> {
+        self.status.download_progress()
+    }
+
+    /// Returns the state, bytes programmed, the expected image length, and the last
+    /// memory error, see [`status`](crate::status) module.
+    pub fn extended_status(&self) -> ExtendedStatus {
+        self.status.extended_status()
+    }
+
+    /// Returns `true` if the host polled the status since the last call.
+    pub fn take_host_poll(&mut self) -> bool {
+        self.status.take_host_poll()
+    }
+
+    /// Returns `true` if the host issued a DfuSe command since the last USB reset,
+    /// see [`DfuClass::dfuse_used()`](crate::DfuClass::dfuse_used).
+    pub fn dfuse_used(&self) -> bool {
+        self.status.dfuse_used()
+    }
+
+    /// Report `elapsed_ms` milliseconds since the last call, see [`DfuClass::tick()`](crate::DfuClass::tick).
+    pub fn tick(&mut self, elapsed_ms: u32) -> bool {
+        let timeout = self.status.tick(elapsed_ms);
+        if timeout {
+            self.mem.session_timeout();
+        }
+        timeout
+    }
+
+    /// Cancel the session and go to `dfuIDLE`, see [`DfuClass::force_idle()`](crate::DfuClass::force_idle).
+    pub fn force_idle(&mut self) {
+        if self.status.force_idle() {
+            self.mem.session_timeout();
+        }
+    }
+
+    /// Returns the last state transitions, see [`trace`](crate::trace).
+    #[cfg(feature = "trace")]
+    pub fn trace(&self) -> &crate::trace::Trace {
+        self.status.trace()
+    }
+
+    /// Forget recorded state transitions.
+    #[cfg(feature = "trace")]
+    pub fn clear_trace(&mut self) {
+        self.status.clear_trace();
+    }
+
+    /// Returns session counters, see [`stats`](crate::stats).
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &crate::stats::DfuStats {
+        self.status.stats()
+    }
+
+    /// Reset session counters to zero.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.status.reset_stats();
+    }
+
+    /// Reset the protocol state after the link was lost, like a USB reset.
+    pub fn reset(&mut self) {
+        // may not return
+        self.mem.usb_reset();
+
+        self.status.usb_reset();
+    }
+
+    /// Handle a complete `request` frame and write the reply frame to `reply`.
+    ///
+    /// Returns the reply length. `reply` must be at least [`REPLY_HEADER_LENGTH`]
+    /// bytes long, otherwise nothing is written and `0` is returned. Upload data is
+    /// limited to the rest of it.
+    pub fn handle(&mut self, request: &[u8], reply: &mut [u8]) -> usize {
+        self.update();
+
+        let setup = request.first_chunk::<SETUP_LENGTH>();
+        let req = match setup.and_then(parse_setup) {
+            Some(req) if setup.is_some_and(|s| request.len() == frame_length(s)) => req,
+            _ => {
+                self.status.stall();
+                return write_reply(reply, REPLY_STALL, &[]);
+            }
+        };
+        let max_length = reply.len().saturating_sub(REPLY_HEADER_LENGTH);
+
+        let length = match req.direction {
+            UsbDirection::In => {
+                let req = Request {
+                    length: req.length.min(max_length as u16),
+                    ..req
+                };
+                let result = match req.request {
+                    #[cfg(feature = "upload")]
+                    DFU_UPLOAD if req.value == 1 => {
+                        let mem = &mut self.mem;
+                        self.status
+                            .query(&req, |query| query_reply(mem, query))
+                            .map(|(data, len)| write_reply(reply, REPLY_ACK, &data[..len]))
+                    }
+                    #[cfg(feature = "upload")]
+                    DFU_UPLOAD => {
+                        let mem = &mut self.mem;
+                        self.status
+                            .upload(&req, |address, length| mem.read_block(address, length))
+                            .map(|data| write_reply(reply, REPLY_ACK, data))
+                    }
+                    DFU_GETSTATUS => self
+                        .status
+                        .get_status(&req)
+                        .map(|v| write_reply(reply, REPLY_ACK, &v)),
+                    DFU_GETSTATE => self
+                        .status
+                        .get_state(&req)
+                        .map(|v| write_reply(reply, REPLY_ACK, &[v])),
+                    _ => None,
+                };
+                result.unwrap_or_else(|| write_reply(reply, REPLY_STALL, &[]))
+            }
+            UsbDirection::Out => {
+                let accepted = match req.request {
+                    #[cfg(feature = "download")]
+                    DFU_DNLOAD => {
+                        let mem = &mut self.mem;
+                        let data = &request[SETUP_LENGTH..];
+                        self.status
+                            .download(&req, data, &mut |data| mem.store_write_buffer(data))
+                    }
+                    DFU_CLRSTATUS => self.status.clear_status(),
+                    DFU_ABORT => self.status.abort(),
+                    _ => false,
+                };
+                if accepted {
+                    write_reply(reply, REPLY_ACK, &[])
+                } else {
+                    write_reply(reply, REPLY_STALL, &[])
+                }
+            }
+        };
+
+        self.update();
+        length
+    }
+
+    /// Execute queued memory operations.
+    fn update(&mut self) {
+        while let Some(op) = self.status.next_operation() {
+            let start = match self.status.take_download_start(&op) {
+                true => start_download(&mut self.mem),
+                false => Ok(()),
+            };
+            if let Some((block_num, length)) = self.status.take_block_size_mismatch() {
+                self.mem.block_size_mismatch(block_num, length);
+            }
+            let result = start.and_then(|_| op.execute(&mut self.mem, self.status.payload()));
+            self.status.complete(result);
+        }
+    }
+}
+
+/// [`DfuLink`] over a byte stream, e.g. UART.
+///
+/// `N` is the size of the receive and reply buffers, it must be at least
+/// [`SETUP_LENGTH`] + [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE) bytes.
+/// Longer frames are rejected.
+pub struct StreamLink<M: DfuMemory, const N: usize> {
+    link: DfuLink<M>,
+    buffer: [u8; N],
+    reply: [u8; N],
+    /// Number of received bytes of the current frame.
+    received: usize,
+}
+
+impl<M: DfuMemory, const N: usize> StreamLink<M, N> {
+    /// Creates a new [`StreamLink`] with the provided [`DfuMemory`].
+    ///
+    /// Panics if `N` is less than `SETUP_LENGTH + TRANSFER_SIZE`.
+    pub fn new(mem: M) -> Self {
+        assert!(
+            N >= SETUP_LENGTH + M::TRANSFER_SIZE as usize,
+            "StreamLink buffer is too small"
+        );
+
+        Self {
+            link: DfuLink::new(mem),
+            buffer: [0; N],
+            reply: [0; N],
+            received: 0,
+        }
+    }
+
+    /// Returns a reference to the protocol handler.
+    pub fn link(&mut self) -> &mut DfuLink<M> {
+        &mut self.link
+    }
+
+    /// Destroy the link and return the memory.
+    pub fn release(self) -> M {
+        self.link.release()
+    }
+
+    /// Drop a partially received frame.
+    pub fn reset(&mut self) {
+        self.received = 0;
+    }
+
+    /// Process received `data`, `send` is called with the reply to each complete frame.
+    pub fn receive(&mut self, data: &[u8], mut send: impl FnMut(&[u8])) {
+        for &byte in data {
+            if let Some(b) = self.buffer.get_mut(self.received) {
+                *b = byte;
+            }
+            self.received += 1;
+
+            let Some(setup) = self.buffer.first_chunk::<SETUP_LENGTH>() else {
+                continue;
+            };
+            if self.received < SETUP_LENGTH || self.received < frame_length(setup) {
+                continue;
+            }
+
+            let length = if self.received <= N {
+                self.link
+                    .handle(&self.buffer[..self.received], &mut self.reply)
+            } else {
+                // frame is longer than the buffer
+                self.link.status.stall();
+                write_reply(&mut self.reply, REPLY_STALL, &[])
+            };
+            self.received = 0;
+            send(&self.reply[..length]);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Internal macros.
+//!
+//! Trace points are logged at debug level with `defmt-03` or `log` feature,
+//! and expand to nothing without them. Arguments are formatted with `{}`, which
+//! uses `defmt::Format` with defmt and `Display` with log, or with `{:?}`,
+//! which uses `Debug` with log.
+
+/// Log a protocol trace point at debug level.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "defmt-03")]
+        defmt::debug!($($arg)*);
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+    };
+}
+
+/// Forward the [`DfuMemory`](crate::DfuMemory) items of an adapter to the memory it wraps.
+///
+/// Used in `impl DfuMemory` of an adapter that keeps the wrapped memory of type `M` in
+/// field `mem`, as `forward_dfu_memory!(mem: M)`. Items listed after `except`, e.g.
+/// `forward_dfu_memory!(mem: M, except SKIP_IDENTICAL, program)`, are not forwarded,
+/// the impl defines them or keeps the trait default.
+macro_rules! forward_dfu_memory {
+    ($mem:ident: $M:ty $(, except $($skip:ident),+)? $(,)?) => {
+        forward_dfu_memory!(@all $mem, $M, [$($($skip)+)?]);
+    };
+    (@all $mem:ident, $M:ty, $skip:tt) => {
+        forward_dfu_memory!(@item $skip INITIAL_ADDRESS_POINTER
+            const INITIAL_ADDRESS_POINTER: u32 = <$M>::INITIAL_ADDRESS_POINTER;
+        );
+        forward_dfu_memory!(@item $skip MEM_INFO_STRING
+            const MEM_INFO_STRING: &'static str = <$M>::MEM_INFO_STRING;
+        );
+        forward_dfu_memory!(@item $skip HAS_INTERFACE_STRING
+            const HAS_INTERFACE_STRING: bool = <$M>::HAS_INTERFACE_STRING;
+        );
+        forward_dfu_memory!(@item $skip INTERFACE_NAME
+            const INTERFACE_NAME: Option<&'static str> = <$M>::INTERFACE_NAME;
+        );
+        forward_dfu_memory!(@item $skip ALT_SETTINGS
+            const ALT_SETTINGS: &'static [$crate::AltSetting] = <$M>::ALT_SETTINGS;
+        );
+        forward_dfu_memory!(@item $skip HAS_DOWNLOAD
+            const HAS_DOWNLOAD: bool = <$M>::HAS_DOWNLOAD;
+        );
+        forward_dfu_memory!(@item $skip HAS_UPLOAD const HAS_UPLOAD: bool = <$M>::HAS_UPLOAD;);
+        forward_dfu_memory!(@item $skip MANIFESTATION_TOLERANT
+            const MANIFESTATION_TOLERANT: bool = <$M>::MANIFESTATION_TOLERANT;
+        );
+        forward_dfu_memory!(@item $skip PROGRAM_TIME_MS
+            const PROGRAM_TIME_MS: u32 = <$M>::PROGRAM_TIME_MS;
+        );
+        forward_dfu_memory!(@item $skip ERASE_TIME_MS
+            const ERASE_TIME_MS: u32 = <$M>::ERASE_TIME_MS;
+        );
+        forward_dfu_memory!(@item $skip FULL_ERASE_TIME_MS
+            const FULL_ERASE_TIME_MS: u32 = <$M>::FULL_ERASE_TIME_MS;
+        );
+        forward_dfu_memory!(@item $skip MANIFESTATION_TIME_MS
+            const MANIFESTATION_TIME_MS: u32 = <$M>::MANIFESTATION_TIME_MS;
+        );
+        forward_dfu_memory!(@item $skip DETACH_TIMEOUT
+            const DETACH_TIMEOUT: u16 = <$M>::DETACH_TIMEOUT;
+        );
+        forward_dfu_memory!(@item $skip TRANSFER_SIZE
+            const TRANSFER_SIZE: u16 = <$M>::TRANSFER_SIZE;
+        );
+        forward_dfu_memory!(@item $skip COMMAND_QUEUE_DEPTH
+            const COMMAND_QUEUE_DEPTH: usize = <$M>::COMMAND_QUEUE_DEPTH;
+        );
+        forward_dfu_memory!(@item $skip LMDFU_PREFIX
+            const LMDFU_PREFIX: bool = <$M>::LMDFU_PREFIX;
+        );
+        forward_dfu_memory!(@item $skip IMAGE_CRC_COMMAND
+            const IMAGE_CRC_COMMAND: bool = <$M>::IMAGE_CRC_COMMAND;
+        );
+        forward_dfu_memory!(@item $skip RESUME_COMMAND
+            const RESUME_COMMAND: bool = <$M>::RESUME_COMMAND;
+        );
+        forward_dfu_memory!(@item $skip ERASE_RANGE_COMMAND
+            const ERASE_RANGE_COMMAND: bool = <$M>::ERASE_RANGE_COMMAND;
+        );
+        forward_dfu_memory!(@item $skip ERASE_PAGE_SIZE
+            const ERASE_PAGE_SIZE: u32 = <$M>::ERASE_PAGE_SIZE;
+        );
+        forward_dfu_memory!(@item $skip MASS_ERASE_GUARD
+            const MASS_ERASE_GUARD: bool = <$M>::MASS_ERASE_GUARD;
+        );
+        forward_dfu_memory!(@item $skip UNLOCK_COMMAND
+            const UNLOCK_COMMAND: bool = <$M>::UNLOCK_COMMAND;
+        );
+        forward_dfu_memory!(@item $skip READOUT_PROTECTION_COMMANDS
+            const READOUT_PROTECTION_COMMANDS: bool = <$M>::READOUT_PROTECTION_COMMANDS;
+        );
+        forward_dfu_memory!(@item $skip DEVICE_INFO_COMMAND
+            const DEVICE_INFO_COMMAND: bool = <$M>::DEVICE_INFO_COMMAND;
+        );
+        forward_dfu_memory!(@item $skip TLV_COMMANDS
+            const TLV_COMMANDS: bool = <$M>::TLV_COMMANDS;
+        );
+        forward_dfu_memory!(@item $skip EXTENDED_STATUS_REQUEST
+            const EXTENDED_STATUS_REQUEST: bool = <$M>::EXTENDED_STATUS_REQUEST;
+        );
+        forward_dfu_memory!(@item $skip ENFORCE_PERMISSIONS
+            const ENFORCE_PERMISSIONS: bool = <$M>::ENFORCE_PERMISSIONS;
+        );
+        forward_dfu_memory!(@item $skip VALIDATE_ADDRESS_POINTER
+            const VALIDATE_ADDRESS_POINTER: bool = <$M>::VALIDATE_ADDRESS_POINTER;
+        );
+        forward_dfu_memory!(@item $skip REBASE_ON_WRAP
+            const REBASE_ON_WRAP: bool = <$M>::REBASE_ON_WRAP;
+        );
+        forward_dfu_memory!(@item $skip CLAMP_UPLOAD
+            const CLAMP_UPLOAD: bool = <$M>::CLAMP_UPLOAD;
+        );
+        forward_dfu_memory!(@item $skip UPLOAD_END
+            const UPLOAD_END: $crate::UploadEnd = <$M>::UPLOAD_END;
+        );
+        forward_dfu_memory!(@item $skip IMAGE_SIZE_COMMAND
+            const IMAGE_SIZE_COMMAND: bool = <$M>::IMAGE_SIZE_COMMAND;
+        );
+        forward_dfu_memory!(@item $skip MIN_IMAGE_SIZE
+            const MIN_IMAGE_SIZE: u32 = <$M>::MIN_IMAGE_SIZE;
+        );
+        forward_dfu_memory!(@item $skip EMPTY_DOWNLOAD
+            const EMPTY_DOWNLOAD: $crate::EmptyDownload = <$M>::EMPTY_DOWNLOAD;
+        );
+        forward_dfu_memory!(@item $skip BLOCK_SEQUENCE
+            const BLOCK_SEQUENCE: $crate::BlockSequence = <$M>::BLOCK_SEQUENCE;
+        );
+        forward_dfu_memory!(@item $skip WRITE_ONCE const WRITE_ONCE: bool = <$M>::WRITE_ONCE;);
+        forward_dfu_memory!(@item $skip SKIP_IDENTICAL
+            const SKIP_IDENTICAL: bool = <$M>::SKIP_IDENTICAL;
+        );
+        forward_dfu_memory!(@item $skip VERIFY_COMMAND
+            const VERIFY_COMMAND: bool = <$M>::VERIFY_COMMAND;
+        );
+        forward_dfu_memory!(@item $skip STRICT const STRICT: bool = <$M>::STRICT;);
+        forward_dfu_memory!(@item $skip SESSION_TIMEOUT_MS
+            const SESSION_TIMEOUT_MS: u32 = <$M>::SESSION_TIMEOUT_MS;
+        );
+        forward_dfu_memory!(@item $skip store_write_buffer
+            fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+                self.$mem.store_write_buffer(src)
+            }
+        );
+        forward_dfu_memory!(@item $skip read
+            fn read(
+                &mut self,
+                address: u32,
+                length: usize,
+            ) -> Result<&[u8], $crate::DfuMemoryError> {
+                self.$mem.read(address, length)
+            }
+        );
+        forward_dfu_memory!(@item $skip read_block
+            fn read_block(
+                &mut self,
+                address: u32,
+                length: usize,
+            ) -> Result<$crate::ReadOutcome<'_>, $crate::DfuMemoryError> {
+                self.$mem.read_block(address, length)
+            }
+        );
+        forward_dfu_memory!(@item $skip compare
+            fn compare(
+                &mut self,
+                address: u32,
+                length: usize,
+            ) -> Result<bool, $crate::DfuMemoryError> {
+                self.$mem.compare(address, length)
+            }
+        );
+        forward_dfu_memory!(@item $skip program
+            fn program(
+                &mut self,
+                address: u32,
+                length: usize,
+            ) -> Result<(), $crate::DfuMemoryError> {
+                self.$mem.program(address, length)
+            }
+        );
+        forward_dfu_memory!(@item $skip erase
+            fn erase(&mut self, address: u32) -> Result<(), $crate::DfuMemoryError> {
+                self.$mem.erase(address)
+            }
+        );
+        forward_dfu_memory!(@item $skip erase_all
+            fn erase_all(&mut self) -> Result<(), $crate::DfuMemoryError> {
+                self.$mem.erase_all()
+            }
+        );
+        forward_dfu_memory!(@item $skip erase_range
+            fn erase_range(
+                &mut self,
+                address: u32,
+                length: u32,
+            ) -> Result<(), $crate::DfuMemoryError> {
+                self.$mem.erase_range(address, length)
+            }
+        );
+        forward_dfu_memory!(@item $skip mass_erase_allowed
+            fn mass_erase_allowed(&mut self) -> bool {
+                self.$mem.mass_erase_allowed()
+            }
+        );
+        forward_dfu_memory!(@item $skip may_start_download
+            fn may_start_download(&mut self) -> bool {
+                self.$mem.may_start_download()
+            }
+        );
+        forward_dfu_memory!(@item $skip may_erase
+            fn may_erase(&mut self) -> bool {
+                self.$mem.may_erase()
+            }
+        );
+        forward_dfu_memory!(@item $skip unlock
+            fn unlock(&mut self, token: &[u8]) -> bool {
+                self.$mem.unlock(token)
+            }
+        );
+        forward_dfu_memory!(@item $skip readout_protection
+            fn readout_protection(&mut self) -> $crate::ReadoutProtection {
+                self.$mem.readout_protection()
+            }
+        );
+        forward_dfu_memory!(@item $skip set_readout_protection
+            fn set_readout_protection(
+                &mut self,
+                level: $crate::ReadoutProtection,
+            ) -> Result<(), $crate::DfuMemoryError> {
+                self.$mem.set_readout_protection(level)
+            }
+        );
+        forward_dfu_memory!(@item $skip device_info
+            fn device_info(&mut self) -> $crate::info::DeviceInfo<'_> {
+                self.$mem.device_info()
+            }
+        );
+        forward_dfu_memory!(@item $skip set_parameters
+            fn set_parameters(
+                &mut self,
+                parameters: $crate::tlv::TlvReader<'_>,
+            ) -> Result<(), $crate::DfuMemoryError> {
+                self.$mem.set_parameters(parameters)
+            }
+        );
+        forward_dfu_memory!(@item $skip capabilities
+            fn capabilities(
+                &mut self,
+                w: &mut $crate::tlv::TlvWriter<'_>,
+            ) -> Result<(), $crate::tlv::TlvError> {
+                self.$mem.capabilities(w)
+            }
+        );
+        forward_dfu_memory!(@item $skip leave
+            fn leave(&mut self, address: u32) {
+                self.$mem.leave(address)
+            }
+        );
+        forward_dfu_memory!(@item $skip manifestation
+            fn manifestation(&mut self) -> Result<(), $crate::DfuManifestationError> {
+                self.$mem.manifestation()
+            }
+        );
+        forward_dfu_memory!(@item $skip image_version
+            fn image_version(&mut self) -> Option<u32> {
+                self.$mem.image_version()
+            }
+        );
+        forward_dfu_memory!(@item $skip minimum_image_version
+            fn minimum_image_version(&mut self) -> Option<u32> {
+                self.$mem.minimum_image_version()
+            }
+        );
+        forward_dfu_memory!(@item $skip download_start
+            fn download_start(&mut self) {
+                self.$mem.download_start()
+            }
+        );
+        forward_dfu_memory!(@item $skip block_size_mismatch
+            fn block_size_mismatch(&mut self, block_num: u16, length: u16) {
+                self.$mem.block_size_mismatch(block_num, length)
+            }
+        );
+        forward_dfu_memory!(@item $skip resume_point
+            fn resume_point(&mut self) -> Option<u32> {
+                self.$mem.resume_point()
+            }
+        );
+        forward_dfu_memory!(@item $skip usb_reset
+            fn usb_reset(&mut self) {
+                self.$mem.usb_reset()
+            }
+        );
+        forward_dfu_memory!(@item $skip select_alt_setting
+            fn select_alt_setting(&mut self, alt: u8) {
+                self.$mem.select_alt_setting(alt)
+            }
+        );
+        forward_dfu_memory!(@item $skip session_timeout
+            fn session_timeout(&mut self) {
+                self.$mem.session_timeout()
+            }
+        );
+    };
+    // items listed in `except` are left out
+    (@item [INITIAL_ADDRESS_POINTER $($rest:ident)*] INITIAL_ADDRESS_POINTER $($item:tt)*) => {};
+    (@item [MEM_INFO_STRING $($rest:ident)*] MEM_INFO_STRING $($item:tt)*) => {};
+    (@item [HAS_INTERFACE_STRING $($rest:ident)*] HAS_INTERFACE_STRING $($item:tt)*) => {};
+    (@item [INTERFACE_NAME $($rest:ident)*] INTERFACE_NAME $($item:tt)*) => {};
+    (@item [ALT_SETTINGS $($rest:ident)*] ALT_SETTINGS $($item:tt)*) => {};
+    (@item [HAS_DOWNLOAD $($rest:ident)*] HAS_DOWNLOAD $($item:tt)*) => {};
+    (@item [HAS_UPLOAD $($rest:ident)*] HAS_UPLOAD $($item:tt)*) => {};
+    (@item [MANIFESTATION_TOLERANT $($rest:ident)*] MANIFESTATION_TOLERANT $($item:tt)*) => {};
+    (@item [PROGRAM_TIME_MS $($rest:ident)*] PROGRAM_TIME_MS $($item:tt)*) => {};
+    (@item [ERASE_TIME_MS $($rest:ident)*] ERASE_TIME_MS $($item:tt)*) => {};
+    (@item [FULL_ERASE_TIME_MS $($rest:ident)*] FULL_ERASE_TIME_MS $($item:tt)*) => {};
+    (@item [MANIFESTATION_TIME_MS $($rest:ident)*] MANIFESTATION_TIME_MS $($item:tt)*) => {};
+    (@item [DETACH_TIMEOUT $($rest:ident)*] DETACH_TIMEOUT $($item:tt)*) => {};
+    (@item [TRANSFER_SIZE $($rest:ident)*] TRANSFER_SIZE $($item:tt)*) => {};
+    (@item [COMMAND_QUEUE_DEPTH $($rest:ident)*] COMMAND_QUEUE_DEPTH $($item:tt)*) => {};
+    (@item [LMDFU_PREFIX $($rest:ident)*] LMDFU_PREFIX $($item:tt)*) => {};
+    (@item [IMAGE_CRC_COMMAND $($rest:ident)*] IMAGE_CRC_COMMAND $($item:tt)*) => {};
+    (@item [RESUME_COMMAND $($rest:ident)*] RESUME_COMMAND $($item:tt)*) => {};
+    (@item [ERASE_RANGE_COMMAND $($rest:ident)*] ERASE_RANGE_COMMAND $($item:tt)*) => {};
+    (@item [ERASE_PAGE_SIZE $($rest:ident)*] ERASE_PAGE_SIZE $($item:tt)*) => {};
+    (@item [MASS_ERASE_GUARD $($rest:ident)*] MASS_ERASE_GUARD $($item:tt)*) => {};
+    (@item [UNLOCK_COMMAND $($rest:ident)*] UNLOCK_COMMAND $($item:tt)*) => {};
+    (
+        @item [READOUT_PROTECTION_COMMANDS $($rest:ident)*] READOUT_PROTECTION_COMMANDS $($item:tt)*
+    ) => {};
+    (@item [DEVICE_INFO_COMMAND $($rest:ident)*] DEVICE_INFO_COMMAND $($item:tt)*) => {};
+    (@item [TLV_COMMANDS $($rest:ident)*] TLV_COMMANDS $($item:tt)*) => {};
+    (@item [EXTENDED_STATUS_REQUEST $($rest:ident)*] EXTENDED_STATUS_REQUEST $($item:tt)*) => {};
+    (@item [ENFORCE_PERMISSIONS $($rest:ident)*] ENFORCE_PERMISSIONS $($item:tt)*) => {};
+    (@item [VALIDATE_ADDRESS_POINTER $($rest:ident)*] VALIDATE_ADDRESS_POINTER $($item:tt)*) => {};
+    (@item [REBASE_ON_WRAP $($rest:ident)*] REBASE_ON_WRAP $($item:tt)*) => {};
+    (@item [CLAMP_UPLOAD $($rest:ident)*] CLAMP_UPLOAD $($item:tt)*) => {};
+    (@item [UPLOAD_END $($rest:ident)*] UPLOAD_END $($item:tt)*) => {};
+    (@item [IMAGE_SIZE_COMMAND $($rest:ident)*] IMAGE_SIZE_COMMAND $($item:tt)*) => {};
+    (@item [MIN_IMAGE_SIZE $($rest:ident)*] MIN_IMAGE_SIZE $($item:tt)*) => {};
+    (@item [EMPTY_DOWNLOAD $($rest:ident)*] EMPTY_DOWNLOAD $($item:tt)*) => {};
+    (@item [BLOCK_SEQUENCE $($rest:ident)*] BLOCK_SEQUENCE $($item:tt)*) => {};
+    (@item [WRITE_ONCE $($rest:ident)*] WRITE_ONCE $($item:tt)*) => {};
+    (@item [SKIP_IDENTICAL $($rest:ident)*] SKIP_IDENTICAL $($item:tt)*) => {};
+    (@item [VERIFY_COMMAND $($rest:ident)*] VERIFY_COMMAND $($item:tt)*) => {};
+    (@item [STRICT $($rest:ident)*] STRICT $($item:tt)*) => {};
+    (@item [SESSION_TIMEOUT_MS $($rest:ident)*] SESSION_TIMEOUT_MS $($item:tt)*) => {};
+    (@item [store_write_buffer $($rest:ident)*] store_write_buffer $($item:tt)*) => {};
+    (@item [read $($rest:ident)*] read $($item:tt)*) => {};
+    (@item [read_block $($rest:ident)*] read_block $($item:tt)*) => {};
+    (@item [compare $($rest:ident)*] compare $($item:tt)*) => {};
+    (@item [program $($rest:ident)*] program $($item:tt)*) => {};
+    (@item [erase $($rest:ident)*] erase $($item:tt)*) => {};
+    (@item [erase_all $($rest:ident)*] erase_all $($item:tt)*) => {};
+    (@item [erase_range $($rest:ident)*] erase_range $($item:tt)*) => {};
+    (@item [mass_erase_allowed $($rest:ident)*] mass_erase_allowed $($item:tt)*) => {};
+    (@item [may_start_download $($rest:ident)*] may_start_download $($item:tt)*) => {};
+    (@item [may_erase $($rest:ident)*] may_erase $($item:tt)*) => {};
+    (@item [unlock $($rest:ident)*] unlock $($item:tt)*) => {};
+    (@item [readout_protection $($rest:ident)*] readout_protection $($item:tt)*) => {};
+    (@item [set_readout_protection $($rest:ident)*] set_readout_protection $($item:tt)*) => {};
+    (@item [device_info $($rest:ident)*] device_info $($item:tt)*) => {};
+    (@item [set_parameters $($rest:ident)*] set_parameters $($item:tt)*) => {};
+    (@item [capabilities $($rest:ident)*] capabilities $($item:tt)*) => {};
+    (@item [leave $($rest:ident)*] leave $($item:tt)*) => {};
+    (@item [manifestation $($rest:ident)*] manifestation $($item:tt)*) => {};
+    (@item [image_version $($rest:ident)*] image_version $($item:tt)*) => {};
+    (@item [minimum_image_version $($rest:ident)*] minimum_image_version $($item:tt)*) => {};
+    (@item [download_start $($rest:ident)*] download_start $($item:tt)*) => {};
+    (@item [block_size_mismatch $($rest:ident)*] block_size_mismatch $($item:tt)*) => {};
+    (@item [resume_point $($rest:ident)*] resume_point $($item:tt)*) => {};
+    (@item [usb_reset $($rest:ident)*] usb_reset $($item:tt)*) => {};
+    (@item [select_alt_setting $($rest:ident)*] select_alt_setting $($item:tt)*) => {};
+    (@item [session_timeout $($rest:ident)*] session_timeout $($item:tt)*) => {};
+    (@item [$other:ident $($rest:ident)*] $name:ident $($item:tt)*) => {
+        forward_dfu_memory!(@item [$($rest)*] $name $($item)*);
+    };
+    (@item [] $name:ident $($item:tt)*) => {
+        $($item)*
+    };
+}
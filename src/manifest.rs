@@ -0,0 +1,324 @@
+//! Update manifest in the first downloaded block
+//!
+//! [`ManifestMemory`] wraps [`DfuMemory`] and treats the first block of every
+//! download as a manifest that describes the payload: target address, size,
+//! digest, and version. The manifest is parsed by a [`ManifestParser`], for
+//! example a SUIT implementation, or [`CborManifestParser`] for a simple CBOR map.
+//!
+//! Following blocks are programmed at the manifest address, one after another,
+//! regardless of the block number and Address Pointer. Payload larger than the
+//! manifest size is rejected with `errFILE`. Before manifestation, the payload
+//! size and digest, calculated with an [`ImageHasher`], must match the manifest.
+//! [`DfuMemory::image_version()`] returns the manifest version, downgrades are
+//! rejected as soon as the manifest is received.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::manifest::*;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # #[cfg(feature = "sha2")]
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: impl DfuMemory) {
+//! # use sha2::Digest;
+//! let mem = ManifestMemory::<_, _, _, 128>::new(my_mem, CborManifestParser, sha2::Sha256::new());
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError};
+use crate::hash::ImageHasher;
+
+/// Payload description.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct ImageManifest<D> {
+    /// Address of the first payload byte.
+    pub address: u32,
+    /// Payload size in bytes.
+    pub size: u32,
+    /// Payload version.
+    pub version: u32,
+    /// Payload digest.
+    pub digest: D,
+}
+
+/// Manifest parser.
+pub trait ManifestParser {
+    /// Digest type used by the manifest.
+    type Digest;
+
+    /// Parse and validate the manifest at the start of `data`, the first block of a download.
+    ///
+    /// On error, the download fails with the corresponding status.
+    fn parse_manifest(
+        &mut self,
+        data: &[u8],
+    ) -> Result<ImageManifest<Self::Digest>, DfuMemoryError>;
+}
+
+/// [`DfuMemory`] adapter that checks the payload against a manifest in the first block.
+///
+/// `N` is the size of the internal buffer, it must be at least `TRANSFER_SIZE` bytes.
+pub struct ManifestMemory<M, P, H, const N: usize>
+where
+    M: DfuMemory,
+    P: ManifestParser,
+    H: ImageHasher<Digest = P::Digest>,
+{
+    mem: M,
+    parser: P,
+    hasher: H,
+    buffer: [u8; N],
+    manifest: Option<ImageManifest<P::Digest>>,
+    /// Number of payload bytes programmed.
+    received: u32,
+}
+
+impl<M, P, H, const N: usize> ManifestMemory<M, P, H, N>
+where
+    M: DfuMemory,
+    P: ManifestParser,
+    H: ImageHasher<Digest = P::Digest>,
+    P::Digest: PartialEq,
+{
+    /// Wrap `mem`, parse manifests with `parser`, and calculate payload digest with `hasher`.
+    pub fn new(mem: M, parser: P, mut hasher: H) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize,
+                "ManifestMemory buffer is too small"
+            )
+        };
+
+        hasher.reset();
+        Self {
+            mem,
+            parser,
+            hasher,
+            buffer: [0; N],
+            manifest: None,
+            received: 0,
+        }
+    }
+
+    /// Manifest of the current or the last download, if received.
+    pub fn manifest(&self) -> Option<&ImageManifest<P::Digest>> {
+        self.manifest.as_ref()
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    /// Program payload block in `buffer`.
+    fn program_payload(&mut self, length: usize) -> Result<(), DfuMemoryError> {
+        let Some(manifest) = &self.manifest else {
+            // the first block is the manifest
+            let manifest = self.parser.parse_manifest(&self.buffer[..length])?;
+            if let Some(minimum) = self.mem.minimum_image_version() {
+                if manifest.version < minimum {
+                    return Err(DfuMemoryError::File);
+                }
+            }
+            self.manifest = Some(manifest);
+            return Ok(());
+        };
+
+        let end = self
+            .received
+            .checked_add(length as u32)
+            .filter(|&end| end <= manifest.size)
+            .ok_or(DfuMemoryError::File)?;
+        let address = manifest
+            .address
+            .checked_add(self.received)
+            .ok_or(DfuMemoryError::Address)?;
+
+        let data = &self.buffer[..length];
+        self.hasher.update(data);
+        self.mem
+            .store_write_buffer(data)
+            .map_err(|_| DfuMemoryError::Prog)?;
+        self.mem.program(address, length)?;
+        self.received = end;
+        Ok(())
+    }
+}
+
+impl<M, P, H, const N: usize> DfuMemory for ManifestMemory<M, P, H, N>
+where
+    M: DfuMemory,
+    P: ManifestParser,
+    H: ImageHasher<Digest = P::Digest>,
+    P::Digest: PartialEq,
+{
+    forward_dfu_memory!(
+        mem: M,
+        except RESUME_COMMAND, WRITE_ONCE, SKIP_IDENTICAL, VERIFY_COMMAND, store_write_buffer,
+        compare, program, manifestation, image_version, download_start, resume_point
+    );
+
+    // the payload hash is computed from the start of the download
+    const RESUME_COMMAND: bool = false;
+
+    // the first block is the manifest, the payload is not at the download address
+    const VERIFY_COMMAND: bool = false;
+
+    // blocks are not programmed at the download address
+    const WRITE_ONCE: bool = false;
+    const SKIP_IDENTICAL: bool = false;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        // wrapped memory gets payload blocks when they are programmed
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    fn program(&mut self, _address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        // payload address is defined by the manifest
+        if length > N {
+            return Err(DfuMemoryError::Prog);
+        }
+        self.program_payload(length)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        let Some(manifest) = &self.manifest else {
+            return Err(DfuManifestationError::NotDone);
+        };
+        if self.received != manifest.size {
+            return Err(DfuManifestationError::NotDone);
+        }
+        if self.hasher.finalize() != manifest.digest {
+            return Err(DfuManifestationError::File);
+        }
+        self.mem.manifestation()
+    }
+
+    fn image_version(&mut self) -> Option<u32> {
+        match &self.manifest {
+            Some(manifest) => Some(manifest.version),
+            None => self.mem.image_version(),
+        }
+    }
+
+    fn download_start(&mut self) {
+        self.manifest = None;
+        self.received = 0;
+        self.hasher.reset();
+        self.mem.download_start()
+    }
+}
+
+/// Parser of a manifest encoded as a CBOR map with unsigned integer keys.
+///
+/// | Key | Value                          |
+/// |-----|--------------------------------|
+/// | 1   | address, unsigned integer      |
+/// | 2   | size, unsigned integer         |
+/// | 3   | version, unsigned integer      |
+/// | 4   | SHA-256 digest, 32-byte string |
+///
+/// All keys are required, entries with other keys and integer, byte string,
+/// or text string values are ignored. Bytes after the map are ignored, so
+/// the manifest block may be padded.
+pub struct CborManifestParser;
+
+// CBOR major types
+const CBOR_UINT: u8 = 0;
+const CBOR_NINT: u8 = 1;
+const CBOR_BYTES: u8 = 2;
+const CBOR_TEXT: u8 = 3;
+const CBOR_MAP: u8 = 5;
+
+struct CborReader<'d> {
+    data: &'d [u8],
+    pos: usize,
+}
+
+impl<'d> CborReader<'d> {
+    fn take(&mut self, len: usize) -> Option<&'d [u8]> {
+        let end = self.pos.checked_add(len)?;
+        let bytes = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+
+    /// Read the initial byte and the argument of a data item.
+    fn head(&mut self) -> Option<(u8, u64)> {
+        let initial = self.take(1)?[0];
+        let value = match initial & 0x1f {
+            n @ 0..=23 => n as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().ok()?) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().ok()?) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().ok()?),
+            // indefinite lengths are not supported
+            _ => return None,
+        };
+        Some((initial >> 5, value))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        match self.head()? {
+            (CBOR_UINT, value) => value.try_into().ok(),
+            _ => None,
+        }
+    }
+
+    fn bytes(&mut self) -> Option<&'d [u8]> {
+        match self.head()? {
+            (CBOR_BYTES, len) => self.take(len.try_into().ok()?),
+            _ => None,
+        }
+    }
+
+    fn skip(&mut self) -> Option<()> {
+        match self.head()? {
+            (CBOR_UINT | CBOR_NINT, _) => Some(()),
+            (CBOR_BYTES | CBOR_TEXT, len) => self.take(len.try_into().ok()?).map(|_| ()),
+            _ => None,
+        }
+    }
+}
+
+impl ManifestParser for CborManifestParser {
+    type Digest = [u8; 32];
+
+    fn parse_manifest(&mut self, data: &[u8]) -> Result<ImageManifest<[u8; 32]>, DfuMemoryError> {
+        parse_cbor_manifest(data).ok_or(DfuMemoryError::File)
+    }
+}
+
+fn parse_cbor_manifest(data: &[u8]) -> Option<ImageManifest<[u8; 32]>> {
+    let mut reader = CborReader { data, pos: 0 };
+    let (CBOR_MAP, entries) = reader.head()? else {
+        return None;
+    };
+
+    let (mut address, mut size, mut version, mut digest) = (None, None, None, None);
+    for _ in 0..entries {
+        match reader.u32()? {
+            1 => address = Some(reader.u32()?),
+            2 => size = Some(reader.u32()?),
+            3 => version = Some(reader.u32()?),
+            4 => digest = Some(reader.bytes()?.try_into().ok()?),
+            _ => reader.skip()?,
+        }
+    }
+
+    Some(ImageManifest {
+        address: address?,
+        size: size?,
+        version: version?,
+        digest: digest?,
+    })
+}
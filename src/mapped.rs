@@ -0,0 +1,171 @@
+//! Memory-mapped external flash
+//!
+//! QSPI and OSPI controllers, e.g. STM32 QUADSPI and OCTOSPI, map external flash
+//! into the address space for reading, but erase and program commands are sent
+//! in indirect (command) mode, which usually requires to leave memory-mapped mode.
+//!
+//! [`MappedFlash`] is a [`NorFlash`] wrapper that reads directly from the mapped
+//! address range, and erases and programs with a command-mode driver. It switches
+//! the controller with [`MemoryMap`] around each erase and program operation.
+//!
+//! [`MappedMemory`] is a [`FlashMemory`] of a [`MappedFlash`]. DFU addresses are
+//! addresses in the mapped range, so the memory is created with the mapped
+//! address of flash offset `0` as `base`, and the layout describes the mapped
+//! region, e.g. [`SpiNor`](crate::spi_nor::SpiNor) with `spi-nor` feature.
+//!
+//! Requires `embedded-storage` feature.
+//!
+//! ```no_run
+//! # use embedded_storage::nor_flash::NorFlash;
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::mapped::*;
+//! # use usbd_dfu::DfuClass;
+//! # struct QspiMode;
+//! # impl MemoryMap for QspiMode {
+//! #     fn unmap(&mut self) {}
+//! #     fn map(&mut self) {}
+//! # }
+//! # #[cfg(feature = "spi-nor")]
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, qspi: impl NorFlash) {
+//! # use usbd_dfu::spi_nor::{SpiNor, SpiNorRegion};
+//! const QSPI_BASE: u32 = 0x9000_0000;
+//!
+//! struct Staging;
+//!
+//! impl SpiNorRegion for Staging {
+//!     const START: u32 = 0x9010_0000;
+//!     const END: u32 = 0x9020_0000;
+//! }
+//!
+//! // `qspi` is an indirect-mode NorFlash driver, `QspiMode` implements MemoryMap
+//! let flash = unsafe { MappedFlash::new(qspi, QspiMode, QSPI_BASE as *const u8) };
+//! let mem = MappedMemory::<_, _, SpiNor<Staging>, 1024>::new(flash, QSPI_BASE);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::flash::FlashMemory;
+
+/// Switches the controller between memory-mapped and indirect mode.
+pub trait MemoryMap {
+    /// Leave memory-mapped mode before an erase or program operation.
+    fn unmap(&mut self);
+
+    /// Enter memory-mapped mode after an erase or program operation.
+    ///
+    /// Data in the mapped range is changed, so caches that hold it, e.g. Cortex-M7
+    /// data cache, must be invalidated.
+    fn map(&mut self);
+}
+
+/// [`MemoryMap`] for a driver that switches modes by itself.
+pub struct AlwaysMapped;
+
+impl MemoryMap for AlwaysMapped {
+    fn unmap(&mut self) {}
+
+    fn map(&mut self) {}
+}
+
+/// Errors of [`MappedFlash`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum MappedFlashError<E> {
+    /// Erase or program operation failed.
+    Flash(E),
+    /// Read is beyond the flash capacity.
+    OutOfBounds,
+}
+
+impl<E: NorFlashError> NorFlashError for MappedFlashError<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            MappedFlashError::Flash(e) => e.kind(),
+            MappedFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+        }
+    }
+}
+
+/// [`NorFlash`] that reads memory-mapped flash, and erases and programs it with `F`.
+///
+/// Offsets are `F` offsets, offset `0` is mapped at `base`.
+pub struct MappedFlash<F: NorFlash, M: MemoryMap> {
+    flash: F,
+    map: M,
+    base: *const u8,
+}
+
+// mapped memory is accessed only through `&mut self`
+unsafe impl<F: NorFlash + Send, M: MemoryMap + Send> Send for MappedFlash<F, M> {}
+
+impl<F: NorFlash, M: MemoryMap> MappedFlash<F, M> {
+    /// Wrap `flash` mapped at `base`, switch modes with `map`.
+    ///
+    /// # Safety
+    ///
+    /// `F::capacity()` bytes at `base` must be valid for reads while the controller
+    /// is in memory-mapped mode, and `map` must leave it in that mode.
+    pub unsafe fn new(flash: F, map: M, base: *const u8) -> Self {
+        Self { flash, map, base }
+    }
+
+    /// Destroy the wrapper and return the flash and the mode control.
+    pub fn release(self) -> (F, M) {
+        (self.flash, self.map)
+    }
+
+    fn unmapped<T>(
+        &mut self,
+        f: impl FnOnce(&mut F) -> Result<T, F::Error>,
+    ) -> Result<T, MappedFlashError<F::Error>> {
+        self.map.unmap();
+        let result = f(&mut self.flash);
+        self.map.map();
+        result.map_err(MappedFlashError::Flash)
+    }
+}
+
+impl<F: NorFlash, M: MemoryMap> ErrorType for MappedFlash<F, M> {
+    type Error = MappedFlashError<F::Error>;
+}
+
+impl<F: NorFlash, M: MemoryMap> ReadNorFlash for MappedFlash<F, M> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        match (offset as usize).checked_add(bytes.len()) {
+            Some(end) if end <= self.flash.capacity() => {}
+            _ => return Err(MappedFlashError::OutOfBounds),
+        }
+        unsafe {
+            let src = self.base.add(offset as usize);
+            core::ptr::copy_nonoverlapping(src, bytes.as_mut_ptr(), bytes.len());
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash, M: MemoryMap> NorFlash for MappedFlash<F, M> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.unmapped(|flash| flash.erase(from, to))
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.unmapped(|flash| flash.write(offset, bytes))
+    }
+}
+
+/// [`DfuMemory`](crate::DfuMemory) implementation for a region of memory-mapped
+/// flash with layout `L`, see [`FlashMemory`].
+pub type MappedMemory<F, M, L, const N: usize> = FlashMemory<MappedFlash<F, M>, L, N>;
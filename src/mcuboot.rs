@@ -0,0 +1,344 @@
+//! MCUboot image format
+//!
+//! Images for MCUboot-compatible bootloaders start with a 32-byte [`ImageHeader`],
+//! followed by the firmware and the TLV area: an optional protected TLV block and
+//! a TLV block with the image hash and signatures.
+//!
+//! [`McubootMemory`] wraps [`DfuMemory`] and checks the structure of the downloaded image:
+//!
+//! * the header is parsed from the first programmed bytes, an invalid magic or
+//!   header size fails the download with `errFILE`,
+//! * TLV info headers must follow the image,
+//! * data after the TLV area fails the download with `errFILE`,
+//! * if the download ends before the end of the TLV area, manifestation fails with
+//!   `errNOTDONE`.
+//!
+//! Hash and signatures are checked by the bootloader.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::mcuboot::McubootMemory;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: impl DfuMemory) {
+//! let mem = McubootMemory::<_, 128>::new(my_mem);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError};
+
+/// MCUboot image header magic.
+pub const IMAGE_MAGIC: u32 = 0x96f3_b83d;
+
+/// Magic of the TLV info header.
+pub const IMAGE_TLV_INFO_MAGIC: u16 = 0x6907;
+
+/// Magic of the protected TLV info header.
+pub const IMAGE_TLV_PROT_INFO_MAGIC: u16 = 0x6908;
+
+/// Size of the MCUboot image header in bytes.
+pub const IMAGE_HEADER_LENGTH: usize = 32;
+
+/// Size of TLV info header in bytes.
+pub const TLV_INFO_LENGTH: usize = 4;
+
+/// Errors of MCUboot image parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum McubootError {
+    /// Input is too short.
+    TooShort,
+    /// Magic value is not valid.
+    InvalidMagic,
+    /// Header size is less than the header length.
+    InvalidHeaderSize,
+    /// Size of TLV area is not valid.
+    InvalidTlvSize,
+}
+
+/// Image version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct ImageVersion {
+    /// Major version.
+    pub major: u8,
+    /// Minor version.
+    pub minor: u8,
+    /// Revision.
+    pub revision: u16,
+    /// Build number.
+    pub build_num: u32,
+}
+
+impl ImageVersion {
+    /// Version as a single number: `major`, `minor`, and `revision`, from
+    /// the most significant byte. Build number is ignored.
+    pub fn to_u32(&self) -> u32 {
+        (self.major as u32) << 24 | (self.minor as u32) << 16 | self.revision as u32
+    }
+}
+
+/// MCUboot image header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct ImageHeader {
+    /// Load address, for images that are copied to RAM.
+    pub load_addr: u32,
+    /// Size of the header, firmware starts at this offset.
+    pub hdr_size: u16,
+    /// Size of the protected TLV block, including its info header.
+    pub protect_tlv_size: u16,
+    /// Size of the firmware, without the header.
+    pub img_size: u32,
+    /// Image flags.
+    pub flags: u32,
+    /// Image version.
+    pub version: ImageVersion,
+}
+
+impl ImageHeader {
+    /// Offset of the TLV area from the start of the image.
+    pub fn tlv_offset(&self) -> u32 {
+        (self.hdr_size as u32).saturating_add(self.img_size)
+    }
+
+    /// Serialize the header.
+    pub fn to_bytes(&self) -> [u8; IMAGE_HEADER_LENGTH] {
+        let mut bytes = [0; IMAGE_HEADER_LENGTH];
+        bytes[0..4].copy_from_slice(&IMAGE_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.load_addr.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.hdr_size.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.protect_tlv_size.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.img_size.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[20] = self.version.major;
+        bytes[21] = self.version.minor;
+        bytes[22..24].copy_from_slice(&self.version.revision.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.version.build_num.to_le_bytes());
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for ImageHeader {
+    type Error = McubootError;
+
+    /// Parse the header at the start of `value`.
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let value = value
+            .get(..IMAGE_HEADER_LENGTH)
+            .ok_or(McubootError::TooShort)?;
+        let u16_at = |i: usize| u16::from_le_bytes([value[i], value[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(value[i..i + 4].try_into().unwrap());
+
+        if u32_at(0) != IMAGE_MAGIC {
+            return Err(McubootError::InvalidMagic);
+        }
+
+        let header = ImageHeader {
+            load_addr: u32_at(4),
+            hdr_size: u16_at(8),
+            protect_tlv_size: u16_at(10),
+            img_size: u32_at(12),
+            flags: u32_at(16),
+            version: ImageVersion {
+                major: value[20],
+                minor: value[21],
+                revision: u16_at(22),
+                build_num: u32_at(24),
+            },
+        };
+
+        if (header.hdr_size as usize) < IMAGE_HEADER_LENGTH {
+            return Err(McubootError::InvalidHeaderSize);
+        }
+        if header.protect_tlv_size != 0 && (header.protect_tlv_size as usize) < TLV_INFO_LENGTH {
+            return Err(McubootError::InvalidTlvSize);
+        }
+
+        Ok(header)
+    }
+}
+
+/// Parse TLV info header, return the size of the TLV block including the header.
+fn parse_tlv_info(bytes: &[u8; TLV_INFO_LENGTH], magic: u16) -> Result<u16, McubootError> {
+    if u16::from_le_bytes([bytes[0], bytes[1]]) != magic {
+        return Err(McubootError::InvalidMagic);
+    }
+    let size = u16::from_le_bytes([bytes[2], bytes[3]]);
+    if (size as usize) < TLV_INFO_LENGTH {
+        return Err(McubootError::InvalidTlvSize);
+    }
+    Ok(size)
+}
+
+/// Copy the part of `data` at image `offset` that overlaps `dst` at image `dst_offset`.
+fn capture(dst: &mut [u8], dst_offset: u32, data: &[u8], offset: u32) {
+    let start = dst_offset.max(offset);
+    let end = dst_offset
+        .saturating_add(dst.len() as u32)
+        .min(offset.saturating_add(data.len() as u32));
+    if start < end {
+        dst[(start - dst_offset) as usize..(end - dst_offset) as usize]
+            .copy_from_slice(&data[(start - offset) as usize..(end - offset) as usize]);
+    }
+}
+
+/// [`DfuMemory`] adapter that checks the structure of downloaded MCUboot images.
+///
+/// The image is expected to be downloaded sequentially from its first byte.
+/// [`DfuMemory::image_version()`] returns the header version, see [`ImageVersion::to_u32()`].
+///
+/// `N` is the size of the internal buffer, it must be at least `TRANSFER_SIZE` bytes.
+pub struct McubootMemory<M: DfuMemory, const N: usize> {
+    mem: M,
+    buffer: [u8; N],
+    /// Number of bytes programmed since the download start.
+    received: u32,
+    header_bytes: [u8; IMAGE_HEADER_LENGTH],
+    header: Option<ImageHeader>,
+    prot_tlv_info: [u8; TLV_INFO_LENGTH],
+    tlv_info: [u8; TLV_INFO_LENGTH],
+    /// Total size of the image, known when TLV info header is received.
+    total_size: Option<u32>,
+}
+
+impl<M: DfuMemory, const N: usize> McubootMemory<M, N> {
+    /// Wrap `mem`.
+    pub fn new(mem: M) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize,
+                "McubootMemory buffer is too small"
+            )
+        };
+
+        Self {
+            mem,
+            buffer: [0; N],
+            received: 0,
+            header_bytes: [0; IMAGE_HEADER_LENGTH],
+            header: None,
+            prot_tlv_info: [0; TLV_INFO_LENGTH],
+            tlv_info: [0; TLV_INFO_LENGTH],
+            total_size: None,
+        }
+    }
+
+    /// Header of the current or the last downloaded image, if received.
+    pub fn header(&self) -> Option<ImageHeader> {
+        self.header
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    fn restart(&mut self) {
+        self.received = 0;
+        self.header = None;
+        self.total_size = None;
+    }
+
+    /// Check the next `length` bytes of the image in `buffer`.
+    fn check(&mut self, length: usize) -> Result<(), McubootError> {
+        let data = &self.buffer[..length];
+        let offset = self.received;
+        let end = offset.saturating_add(length as u32);
+        self.received = end;
+
+        if self.header.is_none() {
+            capture(&mut self.header_bytes, 0, data, offset);
+            if end < IMAGE_HEADER_LENGTH as u32 {
+                return Ok(());
+            }
+            self.header = Some(ImageHeader::try_from(&self.header_bytes[..])?);
+        }
+
+        let Some(header) = self.header else {
+            return Ok(());
+        };
+
+        if self.total_size.is_none() {
+            let prot_offset = header.tlv_offset();
+            let tlv_offset = prot_offset.saturating_add(header.protect_tlv_size as u32);
+            if header.protect_tlv_size != 0 {
+                capture(&mut self.prot_tlv_info, prot_offset, data, offset);
+            }
+            capture(&mut self.tlv_info, tlv_offset, data, offset);
+
+            if end < tlv_offset.saturating_add(TLV_INFO_LENGTH as u32) {
+                return Ok(());
+            }
+
+            if header.protect_tlv_size != 0 {
+                let size = parse_tlv_info(&self.prot_tlv_info, IMAGE_TLV_PROT_INFO_MAGIC)?;
+                if size != header.protect_tlv_size {
+                    return Err(McubootError::InvalidTlvSize);
+                }
+            }
+            let size = parse_tlv_info(&self.tlv_info, IMAGE_TLV_INFO_MAGIC)?;
+            self.total_size = Some(tlv_offset.saturating_add(size as u32));
+        }
+
+        match self.total_size {
+            Some(total_size) if end > total_size => Err(McubootError::InvalidTlvSize),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<M: DfuMemory, const N: usize> DfuMemory for McubootMemory<M, N> {
+    forward_dfu_memory!(
+        mem: M,
+        except RESUME_COMMAND, SKIP_IDENTICAL, store_write_buffer, program, manifestation,
+        image_version, download_start, resume_point
+    );
+
+    // the image header and TLV info are parsed from the start of the download
+    const RESUME_COMMAND: bool = false;
+
+    // program() parses the image header and counts the received bytes
+    const SKIP_IDENTICAL: bool = false;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        // keep a copy, the block is checked when it is programmed
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        self.mem.store_write_buffer(src)
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        if length > N {
+            return Err(DfuMemoryError::Prog);
+        }
+        self.check(length).map_err(|_| DfuMemoryError::File)?;
+        self.mem.program(address, length)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        if self.total_size != Some(self.received) {
+            return Err(DfuManifestationError::NotDone);
+        }
+        self.mem.manifestation()
+    }
+
+    fn image_version(&mut self) -> Option<u32> {
+        match self.header {
+            Some(header) => Some(header.version.to_u32()),
+            None => self.mem.image_version(),
+        }
+    }
+
+    fn download_start(&mut self) {
+        self.restart();
+        self.mem.download_start()
+    }
+}
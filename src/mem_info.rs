@@ -0,0 +1,320 @@
+//! Memory info string builder
+//!
+//! [`MemInfo`] formats [`DfuMemory::MEM_INFO_STRING`](crate::DfuMemory::MEM_INFO_STRING)
+//! at compile time, so the string can be derived from memory layout constants:
+//!
+//! ```
+//! use usbd_dfu::mem_info::MemInfo;
+//!
+//! const START: u32 = 0x0800_4000;
+//! const INFO: &str = MemInfo::<64>::new("Flash", START)
+//!     .area(4, 1024, 'a')
+//!     .area(112, 1024, 'g')
+//!     .as_str();
+//!
+//! assert_eq!(INFO, "@Flash/0x08004000/4*1Ka,112*1Kg");
+//! ```
+//!
+//! [`areas()`] parses a memory info string back into [`Area`]s, the class uses it
+//! to enforce area [`Operations`], see
+//! [`DfuMemory::ENFORCE_PERMISSIONS`](crate::DfuMemory::ENFORCE_PERMISSIONS).
+
+/// Memory info string of at most `N` bytes.
+///
+/// Builder functions panic if the string does not fit, which is a compile-time
+/// error when the string is built in a constant.
+#[derive(Clone, Copy)]
+pub struct MemInfo<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+    areas: usize,
+}
+
+impl<const N: usize> MemInfo<N> {
+    /// Starts a region `name` at `address`, e.g. `@Flash/0x08000000/`.
+    pub const fn new(name: &str, address: u32) -> Self {
+        let s = Self {
+            bytes: [0; N],
+            len: 0,
+            areas: 0,
+        };
+        s.push(b'@')
+            .push_str(name)
+            .push_str("/0x")
+            .push_hex(address)
+            .push(b'/')
+    }
+
+    /// Adds an area of `count` pages of `size` bytes with `operations` letter,
+    /// e.g. `16*1Kg`. Sizes that are multiples of 1 KiB or 1 MiB use `K` or `M` suffix.
+    ///
+    /// See [`DfuMemory::MEM_INFO_STRING`](crate::DfuMemory::MEM_INFO_STRING) for operation letters.
+    pub const fn area(self, count: u32, size: u32, operations: char) -> Self {
+        let mut s = self;
+        if s.areas > 0 {
+            s = s.push(b',');
+        }
+        s.areas += 1;
+
+        let (size, suffix) = if size != 0 && size.is_multiple_of(1024 * 1024) {
+            (size / (1024 * 1024), b'M')
+        } else if size != 0 && size.is_multiple_of(1024) {
+            (size / 1024, b'K')
+        } else {
+            (size, b' ')
+        };
+        s.push_dec(count)
+            .push(b'*')
+            .push_dec(size)
+            .push(suffix)
+            .push(operations as u8)
+    }
+
+    /// Returns the string.
+    pub const fn as_str(&self) -> &str {
+        match core::str::from_utf8(self.bytes.split_at(self.len).0) {
+            Ok(s) => s,
+            Err(_) => panic!("MemInfo is not valid UTF-8"),
+        }
+    }
+
+    const fn push(mut self, b: u8) -> Self {
+        assert!(self.len < N, "MemInfo buffer is too small");
+        self.bytes[self.len] = b;
+        self.len += 1;
+        self
+    }
+
+    const fn push_str(mut self, s: &str) -> Self {
+        let s = s.as_bytes();
+        let mut i = 0;
+        while i < s.len() {
+            self = self.push(s[i]);
+            i += 1;
+        }
+        self
+    }
+
+    const fn push_hex(mut self, value: u32) -> Self {
+        let mut shift = 32;
+        while shift > 0 {
+            shift -= 4;
+            let digit = ((value >> shift) & 0xf) as u8;
+            self = self.push(if digit < 10 {
+                b'0' + digit
+            } else {
+                b'A' + digit - 10
+            });
+        }
+        self
+    }
+
+    const fn push_dec(mut self, value: u32) -> Self {
+        let mut div = 1_000_000_000;
+        let mut started = false;
+        while div > 0 {
+            let digit = (value / div % 10) as u8;
+            if digit != 0 || started || div == 1 {
+                self = self.push(b'0' + digit);
+                started = true;
+            }
+            div /= 10;
+        }
+        self
+    }
+}
+
+/// Operations supported by a memory area, the letter of an area in a memory info string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Operations(u8);
+
+impl Operations {
+    /// No operations.
+    pub const NONE: Operations = Operations(0);
+    /// Area can be read.
+    pub const READ: Operations = Operations(1);
+    /// Area can be erased.
+    pub const ERASE: Operations = Operations(2);
+    /// Area can be written.
+    pub const WRITE: Operations = Operations(4);
+    /// All operations, letter `g`.
+    pub const ALL: Operations = Operations(7);
+
+    /// Returns operations of a letter from `a` to `g`.
+    pub const fn from_letter(letter: u8) -> Option<Operations> {
+        match letter {
+            b'a'..=b'g' => Some(Operations(letter - b'a' + 1)),
+            _ => None,
+        }
+    }
+
+    /// Returns the letter, `None` for [`NONE`](Operations::NONE).
+    pub const fn letter(self) -> Option<char> {
+        match self.0 {
+            0 => None,
+            n => Some((b'a' + n - 1) as char),
+        }
+    }
+
+    /// Returns `true` if all `other` operations are supported.
+    pub const fn contains(self, other: Operations) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns operations supported by both.
+    pub const fn intersection(self, other: Operations) -> Operations {
+        Operations(self.0 & other.0)
+    }
+}
+
+/// Pages of the same size and operations in a memory info string, e.g. `16*1Kg`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Area {
+    /// Address of the first page.
+    pub address: u32,
+    /// Number of pages.
+    pub count: u32,
+    /// Page size in bytes.
+    pub page_size: u32,
+    /// Supported operations.
+    pub operations: Operations,
+}
+
+impl Area {
+    /// Returns the address after the last page.
+    ///
+    /// `u64`, as an area can end at the end of the address space.
+    pub const fn end(&self) -> u64 {
+        self.address as u64 + self.count as u64 * self.page_size as u64
+    }
+
+    /// Returns `true` if the area contains `address`.
+    pub const fn contains(&self, address: u32) -> bool {
+        address >= self.address && (address as u64) < self.end()
+    }
+
+    /// Returns `true` if any of `length` bytes at `address` are in the area.
+    pub const fn overlaps(&self, address: u32, length: u32) -> bool {
+        (address as u64 + length as u64) > self.address as u64 && (address as u64) < self.end()
+    }
+}
+
+/// Returns an iterator over areas of memory info string `info`,
+/// e.g. `@Flash/0x08000000/16*1Ka,48*1Kg`.
+///
+/// Parsing stops at the first malformed area.
+pub fn areas(info: &str) -> Areas<'_> {
+    let mut parts = info.strip_prefix('@').unwrap_or("").split('/');
+    // region name
+    parts.next();
+    Areas {
+        parts,
+        areas: None,
+        address: None,
+        done: false,
+    }
+}
+
+/// Returns operations supported by all `length` bytes at `address`,
+/// or `None` if some of them are not in an area of memory info string `info`.
+///
+/// `length` of `0` checks the byte at `address`.
+pub fn operations(info: &str, address: u32, length: u32) -> Option<Operations> {
+    let end = address as u64 + length.max(1) as u64;
+    let mut from = address;
+    let mut operations = Operations::ALL;
+    loop {
+        let area = areas(info).find(|a| a.contains(from))?;
+        operations = operations.intersection(area.operations);
+        if area.end() >= end {
+            return Some(operations);
+        }
+        // `end` fits in u32 here
+        from = area.end() as u32;
+    }
+}
+
+/// Returns the end of contiguous areas of memory info string `info`
+/// that contain `address`, or `None` if `address` is not in an area.
+pub fn region_end(info: &str, address: u32) -> Option<u64> {
+    let mut end = areas(info).find(|a| a.contains(address))?.end();
+    while let Some(next) = u32::try_from(end)
+        .ok()
+        .and_then(|e| areas(info).find(|a| a.contains(e)))
+    {
+        end = next.end();
+    }
+    Some(end)
+}
+
+/// Iterator over areas of a memory info string, see [`areas()`].
+#[derive(Clone)]
+pub struct Areas<'a> {
+    parts: core::str::Split<'a, char>,
+    /// Areas of the current region.
+    areas: Option<core::str::Split<'a, char>>,
+    /// Address of the next area, `None` after the end of the address space.
+    address: Option<u32>,
+    done: bool,
+}
+
+impl Iterator for Areas<'_> {
+    type Item = Area;
+
+    fn next(&mut self) -> Option<Area> {
+        while !self.done {
+            if let Some(area) = self.areas.as_mut().and_then(Iterator::next) {
+                match self.address.and_then(|address| parse_area(area, address)) {
+                    Some(area) => {
+                        self.address = u32::try_from(area.end()).ok();
+                        return Some(area);
+                    }
+                    None => self.done = true,
+                }
+            } else {
+                match (self.parts.next().map(parse_hex), self.parts.next()) {
+                    (Some(Some(address)), Some(areas)) => {
+                        self.address = Some(address);
+                        self.areas = Some(areas.split(','));
+                    }
+                    _ => self.done = true,
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Parses `count*size` with an optional size suffix and an operations letter.
+fn parse_area(area: &str, address: u32) -> Option<Area> {
+    let (count, size) = area.split_once('*')?;
+    let (&letter, size) = size.as_bytes().split_last()?;
+    let (multiplier, size) = match size.split_last() {
+        Some((b'K', size)) => (1 << 10, size),
+        Some((b'M', size)) => (1 << 20, size),
+        Some((b'G', size)) => (1 << 30, size),
+        Some((b' ' | b'B', size)) => (1, size),
+        _ => (1, size),
+    };
+    let size = core::str::from_utf8(size)
+        .ok()?
+        .trim()
+        .parse::<u32>()
+        .ok()?;
+    Some(Area {
+        address,
+        count: count.trim().parse().ok()?,
+        page_size: size.checked_mul(multiplier)?,
+        operations: Operations::from_letter(letter)?,
+    })
+}
+
+/// Parses a region address, e.g. `0x08000000`.
+fn parse_hex(address: &str) -> Option<u32> {
+    let digits = address
+        .strip_prefix("0x")
+        .or_else(|| address.strip_prefix("0X"))?;
+    u32::from_str_radix(digits, 16).ok()
+}
@@ -0,0 +1,83 @@
+//! nRF52 flash
+//!
+//! nRF52 internal flash is programmed by the NVMC in 32-bit words, and erased in
+//! 4 KiB pages. [`NrfFlash`] is a [`FlashMemory`] with nRF52 page layout and timing
+//! for a region of the internal flash described by [`NrfRegion`]. The flash is
+//! accessed with an `embedded-storage` [`NorFlash`](embedded_storage::nor_flash::NorFlash)
+//! implementation, e.g. `nrf_hal_common::nvmc::Nvmc` or `embassy_nrf::nvmc::Nvmc`.
+//!
+//! Requires `nrf52` feature.
+//!
+//! ```no_run
+//! # use embedded_storage::nor_flash::NorFlash;
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::nrf::*;
+//! # use usbd_dfu::DfuClass;
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, nvmc: impl NorFlash) {
+//! struct App;
+//!
+//! impl NrfRegion for App {
+//!     const START: u32 = 0x0001_0000;
+//!     const PAGES: u32 = 224;
+//!     const MEM_INFO_STRING: &'static str = "@Flash/0x00010000/224*4Kg";
+//! }
+//!
+//! // `nvmc` is e.g. embassy-nrf Nvmc, its offsets are addresses
+//! let mem = NrfFlash::<_, App, 1024>::new(nvmc, 0);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use core::marker::PhantomData;
+
+use crate::flash::{FlashLayout, FlashMemory};
+
+/// Page size of nRF52 internal flash.
+pub const PAGE_SIZE: u32 = 4096;
+
+/// Maximum page erase time of nRF52 series, rounded up.
+pub const PAGE_ERASE_TIME_MS: u32 = 90;
+
+/// Maximum time to program 1 KiB, 256 words of 41 µs, rounded up.
+pub const KIB_PROGRAM_TIME_MS: u32 = 11;
+
+/// Region of nRF52 internal flash available for downloads, e.g. the application.
+pub trait NrfRegion {
+    /// First address of the region, page aligned.
+    const START: u32;
+
+    /// Number of pages of the region.
+    const PAGES: u32;
+
+    /// See [`DfuMemory::MEM_INFO_STRING`](crate::DfuMemory::MEM_INFO_STRING),
+    /// e.g. `"@Flash/0x00010000/224*4Kg"` for [`START`](Self::START) `0x10000` and
+    /// [`PAGES`](Self::PAGES) `224`.
+    const MEM_INFO_STRING: &'static str;
+}
+
+/// [`FlashLayout`] of a nRF52 flash region.
+pub struct Nrf52<R: NrfRegion> {
+    _region: PhantomData<R>,
+}
+
+impl<R: NrfRegion> FlashLayout for Nrf52<R> {
+    const START: u32 = R::START;
+    const END: u32 = R::START + R::PAGES * PAGE_SIZE;
+    const MEM_INFO_STRING: &'static str = R::MEM_INFO_STRING;
+    const PROGRAM_TIME_MS: u32 = KIB_PROGRAM_TIME_MS;
+    const ERASE_TIME_MS: u32 = PAGE_ERASE_TIME_MS;
+    const FULL_ERASE_TIME_MS: u32 = PAGE_ERASE_TIME_MS * R::PAGES;
+
+    fn page(address: u32) -> Option<(u32, u32)> {
+        if !(Self::START..Self::END).contains(&address) {
+            return None;
+        }
+        Some((address - address % PAGE_SIZE, PAGE_SIZE))
+    }
+}
+
+/// [`DfuMemory`](crate::DfuMemory) implementation for a region `R` of nRF52 internal flash.
+///
+/// `N` is the transfer size, at most 1 KiB for the [`PROGRAM_TIME_MS`](crate::DfuMemory::PROGRAM_TIME_MS)
+/// to be accurate.
+pub type NrfFlash<F, R, const N: usize> = FlashMemory<F, Nrf52<R>, N>;
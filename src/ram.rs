@@ -0,0 +1,290 @@
+//! RAM loader region
+//!
+//! STM32 system bootloaders accept downloads to RAM, e.g. flashing algorithms of
+//! programming tools, and start the downloaded code with DfuSe *Leave DFU*.
+//! [`RamLoader`] wraps [`DfuMemory`] of the flash and adds a RAM region as
+//! [`ALT_SETTINGS`](DfuMemory::ALT_SETTINGS):
+//!
+//! * alternate setting `0` is the wrapped memory, with its memory map and permissions,
+//! * alternate setting `1` is the RAM region, described by [`RamRegion`].
+//!
+//! The memory map of the RAM region declares its addresses, so *Set Address Pointer*
+//! accepts them. After a download to RAM, manifestation calls [`RamRegion::execute()`]
+//! with the entry address instead of the wrapped memory
+//! [`manifestation()`](DfuMemory::manifestation).
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::ram::*;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # mod cortex_m {
+//! #     pub mod asm {
+//! #         pub unsafe fn bootload(_vector_table: *const u32) -> ! {
+//! #             loop {}
+//! #         }
+//! #     }
+//! # }
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_flash_mem: impl DfuMemory) {
+//! struct Sram2;
+//!
+//! impl RamRegion for Sram2 {
+//!     const MEM_INFO_STRING: &'static str = "@RAM/0x20010000/64*1Kg";
+//!     const START: u32 = 0x2001_0000;
+//!
+//!     fn execute(entry: u32) {
+//!         // vector table at the start of the region
+//!         unsafe { cortex_m::asm::bootload(Self::START as *const u32) }
+//!     }
+//! }
+//!
+//! let ram = unsafe { core::slice::from_raw_parts_mut(Sram2::START as *mut u8, 64 * 1024) };
+//! let mem = RamLoader::<_, Sram2, 1024>::new(my_flash_mem, ram);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use core::marker::PhantomData;
+
+use crate::class::{AltSetting, DfuManifestationError, DfuMemory, DfuMemoryError, ReadOutcome};
+
+/// Alternate setting of the RAM region.
+pub const ALT_RAM: u8 = 1;
+
+/// RAM region of a [`RamLoader`].
+pub trait RamRegion {
+    /// Memory map of the region, *iInterface* of alternate setting `1`,
+    /// e.g. `"@RAM/0x20010000/64*1Kg"`.
+    const MEM_INFO_STRING: &'static str;
+
+    /// Address of the first byte of the region.
+    const START: u32;
+
+    /// Start the downloaded code at `entry`, usually doesn't return.
+    ///
+    /// `entry` is the Address Pointer passed to [`DfuMemory::leave()`], e.g. set by
+    /// dfu-util with `-s 0x20010000:leave`, or [`START`](RamRegion::START) if it's
+    /// outside of the region.
+    ///
+    /// This function is called from `usb_dev.poll([])` (USB interrupt context).
+    fn execute(entry: u32);
+}
+
+/// [`DfuMemory`] adapter with a RAM loader region, see [`ram`](crate::ram) module.
+///
+/// `N` is the size of the write buffer of the region, it must be at least
+/// [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE). The wrapped memory must not declare
+/// [`ALT_SETTINGS`](DfuMemory::ALT_SETTINGS).
+///
+/// In the RAM region, erase requests succeed without changes, and addresses outside
+/// of the region fail with `errADDRESS`.
+pub struct RamLoader<'a, M: DfuMemory, R: RamRegion, const N: usize> {
+    mem: M,
+    ram: &'a mut [u8],
+    buffer: [u8; N],
+    /// Selected alternate setting.
+    alt: u8,
+    /// Data was programmed to RAM since the download start.
+    loaded: bool,
+    /// Entry address passed to [`RamRegion::execute()`].
+    entry: u32,
+    _region: PhantomData<R>,
+}
+
+impl<'a, M: DfuMemory, R: RamRegion, const N: usize> RamLoader<'a, M, R, N> {
+    /// Wrap `mem`, `ram` is the RAM region starting at [`RamRegion::START`].
+    pub fn new(mem: M, ram: &'a mut [u8]) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize,
+                "RamLoader buffer is smaller than TRANSFER_SIZE"
+            );
+            assert!(
+                M::ALT_SETTINGS.is_empty(),
+                "RamLoader memory has alternate settings"
+            );
+        };
+
+        Self {
+            mem,
+            ram,
+            buffer: [0; N],
+            alt: 0,
+            loaded: false,
+            entry: R::START,
+            _region: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Returns the RAM region.
+    pub fn ram(&mut self) -> &mut [u8] {
+        self.ram
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    fn is_ram(&self) -> bool {
+        self.alt == ALT_RAM
+    }
+
+    /// Returns the offset of `address` in the region, `length` bytes must be in the region.
+    fn offset(&self, address: u32, length: usize) -> Result<usize, DfuMemoryError> {
+        let offset = address
+            .checked_sub(R::START)
+            .ok_or(DfuMemoryError::Address)? as usize;
+        match offset.checked_add(length) {
+            Some(end) if end <= self.ram.len() => Ok(offset),
+            _ => Err(DfuMemoryError::Address),
+        }
+    }
+
+    /// Returns up to `length` bytes of the region at `address`.
+    fn read_ram(&self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let offset = self.offset(address, 0)?;
+        let to = offset.saturating_add(length).min(self.ram.len());
+        Ok(&self.ram[offset..to])
+    }
+}
+
+impl<M: DfuMemory, R: RamRegion, const N: usize> DfuMemory for RamLoader<'_, M, R, N> {
+    forward_dfu_memory!(
+        mem: M,
+        except HAS_INTERFACE_STRING, INTERFACE_NAME, ALT_SETTINGS, HAS_DOWNLOAD, HAS_UPLOAD,
+        SKIP_IDENTICAL, store_write_buffer, read, read_block, compare, program, erase, erase_all,
+        erase_range, leave, manifestation, download_start, usb_reset, select_alt_setting
+    );
+
+    const HAS_INTERFACE_STRING: bool = true;
+    const ALT_SETTINGS: &'static [AltSetting] = &[
+        AltSetting {
+            name: M::MEM_INFO_STRING,
+            upload: M::HAS_UPLOAD,
+            download: M::HAS_DOWNLOAD,
+        },
+        AltSetting {
+            name: R::MEM_INFO_STRING,
+            upload: true,
+            download: true,
+        },
+    ];
+
+    // a RAM download is executed only if program() loaded a block, and the
+    // constant can't differ between the alternate settings
+    const SKIP_IDENTICAL: bool = false;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        if !self.is_ram() {
+            return self.mem.store_write_buffer(src);
+        }
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        match self.is_ram() {
+            true => self.read_ram(address, length),
+            false => self.mem.read(address, length),
+        }
+    }
+
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        if !self.is_ram() {
+            return self.mem.read_block(address, length);
+        }
+        let end = R::START as usize + self.ram.len();
+        let data = self.read_ram(address, length)?;
+        match address as usize + data.len() >= end {
+            true => Ok(ReadOutcome::LastChunk(data)),
+            false => Ok(ReadOutcome::Full(data)),
+        }
+    }
+
+    fn compare(&mut self, address: u32, length: usize) -> Result<bool, DfuMemoryError> {
+        if !self.is_ram() {
+            return self.mem.compare(address, length);
+        }
+        let offset = self.offset(address, length)?;
+        let block = self.buffer.get(..length).ok_or(DfuMemoryError::Unknown)?;
+        Ok(self.ram[offset..offset + length] == *block)
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        if !self.is_ram() {
+            return self.mem.program(address, length);
+        }
+        if length > N {
+            return Err(DfuMemoryError::Prog);
+        }
+        let offset = self.offset(address, length)?;
+        self.ram[offset..offset + length].copy_from_slice(&self.buffer[..length]);
+        self.loaded = true;
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        match self.is_ram() {
+            true => self.offset(address, 0).map(|_| ()),
+            false => self.mem.erase(address),
+        }
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        match self.is_ram() {
+            true => Ok(()),
+            false => self.mem.erase_all(),
+        }
+    }
+
+    fn erase_range(&mut self, address: u32, length: u32) -> Result<(), DfuMemoryError> {
+        match self.is_ram() {
+            true => self.offset(address, length as usize).map(|_| ()),
+            false => self.mem.erase_range(address, length),
+        }
+    }
+
+    fn select_alt_setting(&mut self, alt: u8) {
+        self.alt = alt;
+    }
+
+    fn leave(&mut self, address: u32) {
+        self.entry = match self.offset(address, 1) {
+            Ok(_) => address,
+            Err(_) => R::START,
+        };
+        self.mem.leave(address)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        if self.loaded {
+            self.loaded = false;
+            // may not return
+            R::execute(self.entry);
+            return Ok(());
+        }
+        self.mem.manifestation()
+    }
+
+    fn download_start(&mut self) {
+        self.loaded = false;
+        self.mem.download_start()
+    }
+
+    fn usb_reset(&mut self) {
+        self.loaded = false;
+        self.mem.usb_reset()
+    }
+}
@@ -0,0 +1,273 @@
+//! Control transfer trace replay
+//!
+//! [`replay()`] sends the control transfers of a captured trace to [`DfuClass`](crate::DfuClass)
+//! on the emulated bus of `usbd-class-tester` and compares the replies, so the behavior
+//! seen by a host tool, e.g. dfu-util or STM32CubeProgrammer, can be kept as a test fixture.
+//!
+//! [`parse()`] reads a text trace, one event per line, e.g. converted from a usbmon
+//! or Wireshark export. `#` starts a comment.
+//!
+//! ```text
+//! # USB reset
+//! reset
+//! # bmRequestType bRequest wValue wIndex wLength, hexadecimal
+//! # OUT transfer with data
+//! 21 01 0000 0000 0005 > 21 00 00 00 08
+//! # IN transfer with the expected reply, without `<` the reply is not checked
+//! a1 03 0000 0000 0006 < 00 00 00 00 05 00
+//! # the request is expected to be stalled
+//! 21 01 0000 0000 0005 > 07 00 00 00 08 stall
+//! ```
+//!
+//! Data bytes can be separated with spaces or written as one hexadecimal string.
+//! With `serde` feature, [`Event`] can be deserialized, e.g. from JSON.
+//!
+//! Requires `test-helpers` feature, which depends on `std`.
+//!
+//! ```no_run
+//! # use usb_device::bus::UsbBusAllocator;
+//! # use usbd_class_tester::prelude::*;
+//! # use usbd_dfu::replay::*;
+//! # use usbd_dfu::DfuClass;
+//! # struct MyMem;
+//! # impl usbd_dfu::DfuMemory for MyMem {
+//! #     const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+//! #     const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*1Kg";
+//! #     const PROGRAM_TIME_MS: u32 = 10;
+//! #     const ERASE_TIME_MS: u32 = 20;
+//! #     const FULL_ERASE_TIME_MS: u32 = 320;
+//! # }
+//! # struct MkDfu;
+//! # impl UsbDeviceCtx for MkDfu {
+//! #     type C<'c> = DfuClass<EmulatedUsbBus, MyMem>;
+//! #     fn create_class(
+//! #         &mut self,
+//! #         alloc: &UsbBusAllocator<EmulatedUsbBus>,
+//! #     ) -> AnyResult<DfuClass<EmulatedUsbBus, MyMem>> {
+//! #         Ok(DfuClass::new(alloc, MyMem))
+//! #     }
+//! # }
+//! # const DFU_UTIL_TRACE: &str = "reset";
+//! MkDfu
+//!     .with_usb(|mut dfu, mut dev| {
+//!         let trace = parse(DFU_UTIL_TRACE).expect("trace");
+//!         replay(&mut dev, &mut dfu, &trace).expect("replay");
+//!     })
+//!     .expect("with_usb");
+//! ```
+
+use std::vec::Vec;
+
+use usb_device::class::UsbClass;
+use usbd_class_tester::prelude::*;
+
+/// Control transfer of a trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Transfer {
+    /// *bmRequestType*, bit 7 is set for IN transfers.
+    pub request_type: u8,
+    /// *bRequest*
+    pub request: u8,
+    /// *wValue*
+    pub value: u16,
+    /// *wIndex*
+    pub index: u16,
+    /// *wLength*
+    pub length: u16,
+    /// Data of OUT transfer, or the expected reply of IN transfer.
+    ///
+    /// `None` is no data, or the reply is not checked.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub data: Option<Vec<u8>>,
+    /// The device is expected to stall the request.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stall: bool,
+}
+
+impl Transfer {
+    /// Returns `true` for device to host transfer.
+    pub fn is_in(&self) -> bool {
+        self.request_type & 0x80 != 0
+    }
+
+    fn request_type(&self) -> CtrRequestType {
+        let reqt = match self.is_in() {
+            true => CtrRequestType::to_host(),
+            false => CtrRequestType::to_device(),
+        };
+        let reqt = match (self.request_type >> 5) & 3 {
+            0 => reqt.standard(),
+            1 => reqt.class(),
+            _ => reqt.vendor(),
+        };
+        match self.request_type & 0x1f {
+            0 => reqt.device(),
+            1 => reqt.interface(),
+            _ => reqt.endpoint(),
+        }
+    }
+}
+
+/// Trace event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Event {
+    /// USB reset.
+    Reset,
+    /// Control transfer.
+    Transfer(Transfer),
+}
+
+/// Invalid line of a text trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    /// Line number, starting from 1.
+    pub line: usize,
+}
+
+/// Difference between the device and the trace.
+#[derive(Debug)]
+pub enum Mismatch {
+    /// The device stalled the request.
+    Stalled,
+    /// The device did not stall the request.
+    NotStalled,
+    /// The device replied with different data.
+    Reply(Vec<u8>),
+    /// Emulated transfer failed.
+    Usb(AnyUsbError),
+}
+
+/// Replay stopped at an event.
+#[derive(Debug)]
+pub struct ReplayError {
+    /// Index of the event.
+    pub event: usize,
+    /// What was different.
+    pub mismatch: Mismatch,
+}
+
+fn parse_field(token: Option<&str>) -> Option<u16> {
+    let token = token?;
+    let token = token.strip_prefix("0x").unwrap_or(token);
+    u16::from_str_radix(token, 16).ok()
+}
+
+fn parse_data<'a>(tokens: impl Iterator<Item = &'a str>, data: &mut Vec<u8>) -> Option<()> {
+    for token in tokens {
+        if token.len() % 2 != 0 {
+            return None;
+        }
+        for i in (0..token.len()).step_by(2) {
+            data.push(u8::from_str_radix(token.get(i..i + 2)?, 16).ok()?);
+        }
+    }
+    Some(())
+}
+
+fn parse_transfer(line: &str) -> Option<Transfer> {
+    let mut tokens = line.split_whitespace();
+    let request_type = parse_field(tokens.next())?.try_into().ok()?;
+    let request = parse_field(tokens.next())?.try_into().ok()?;
+    let value = parse_field(tokens.next())?;
+    let index = parse_field(tokens.next())?;
+    let length = parse_field(tokens.next())?;
+
+    let mut rest: Vec<&str> = tokens.collect();
+    let stall = rest.last() == Some(&"stall");
+    if stall {
+        rest.pop();
+    }
+
+    let mut transfer = Transfer {
+        request_type,
+        request,
+        value,
+        index,
+        length,
+        data: None,
+        stall,
+    };
+    let Some((&marker, bytes)) = rest.split_first() else {
+        // OUT transfer without data
+        return (transfer.is_in() || length == 0).then_some(transfer);
+    };
+    if marker != if transfer.is_in() { "<" } else { ">" } {
+        return None;
+    }
+    let mut data = Vec::new();
+    parse_data(bytes.iter().copied(), &mut data)?;
+    let valid = match transfer.is_in() {
+        true => data.len() <= length as usize,
+        false => data.len() == length as usize,
+    };
+    transfer.data = Some(data);
+    valid.then_some(transfer)
+}
+
+/// Parse a text trace.
+pub fn parse(trace: &str) -> Result<Vec<Event>, ParseError> {
+    let mut events = Vec::new();
+    for (n, line) in trace.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let event = match line {
+            "" => continue,
+            "reset" => Event::Reset,
+            line => Event::Transfer(parse_transfer(line).ok_or(ParseError { line: n + 1 })?),
+        };
+        events.push(event);
+    }
+    Ok(events)
+}
+
+fn send<'a, C, M>(dev: &mut Device<'a, C, M>, cls: &mut C, t: &Transfer) -> Result<(), Mismatch>
+where
+    C: UsbClass<EmulatedUsbBus>,
+    M: UsbDeviceCtx<C<'a> = C>,
+{
+    let (reqt, data) = (t.request_type(), t.data.as_deref());
+    let result = match t.is_in() {
+        true => dev.control_read(cls, reqt, t.request, t.value, t.index, t.length),
+        false => dev.control_write(
+            cls,
+            reqt,
+            t.request,
+            t.value,
+            t.index,
+            t.length,
+            data.unwrap_or(&[]),
+        ),
+    };
+    match result {
+        Err(AnyUsbError::EP0Stalled) if t.stall => Ok(()),
+        Err(AnyUsbError::EP0Stalled) => Err(Mismatch::Stalled),
+        Err(e) => Err(Mismatch::Usb(e)),
+        Ok(_) if t.stall => Err(Mismatch::NotStalled),
+        Ok(reply) => match data {
+            Some(expected) if t.is_in() && reply != expected => Err(Mismatch::Reply(reply)),
+            _ => Ok(()),
+        },
+    }
+}
+
+/// Replay `events`, stops at the first event the device does not behave as recorded.
+pub fn replay<'a, C, M>(
+    dev: &mut Device<'a, C, M>,
+    cls: &mut C,
+    events: &[Event],
+) -> Result<(), ReplayError>
+where
+    C: UsbClass<EmulatedUsbBus>,
+    M: UsbDeviceCtx<C<'a> = C>,
+{
+    for (event, e) in events.iter().enumerate() {
+        match e {
+            Event::Reset => cls.reset(),
+            Event::Transfer(t) => {
+                send(dev, cls, t).map_err(|mismatch| ReplayError { event, mismatch })?
+            }
+        }
+    }
+    Ok(())
+}
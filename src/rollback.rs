@@ -0,0 +1,229 @@
+//! Rollback protection with a monotonic counter
+//!
+//! [`RollbackCounter`] wraps [`DfuMemory`] and rejects images with a version
+//! lower than the value of a [`MonotonicCounter`], see
+//! [`DfuMemory::minimum_image_version()`]. After a successful manifestation,
+//! the counter is incremented up to the version of the new image, so older
+//! images can't be downloaded again.
+//!
+//! The counter advances one step at a time, so image versions must be small
+//! sequential security epochs, incremented only by releases that fix a
+//! vulnerability, not packed release numbers like `0x0001_0203`.
+//!
+//! With `embedded-storage` feature, [`FlashCounter`] keeps the counter in a
+//! dedicated NOR flash page.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::rollback::*;
+//! # use usbd_dfu::suffix::StripSuffix;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # const COUNTER_PAGE_OFFSET: u32 = 0x0003_f800;
+//! # const COUNTER_PAGE_SIZE: u32 = 2048;
+//! # #[cfg(feature = "embedded-storage")]
+//! # fn example<B: UsbBus>(
+//! #     usb_bus_alloc: UsbBusAllocator<B>,
+//! #     my_mem: impl DfuMemory,
+//! #     flash: impl embedded_storage::nor_flash::NorFlash,
+//! # ) {
+//! let counter = FlashCounter::new(flash, COUNTER_PAGE_OFFSET, COUNTER_PAGE_SIZE);
+//! let mem = RollbackCounter::new(StripSuffix::<_, { 128 + 16 }>::new(my_mem), counter);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory};
+
+/// Counter that can only be incremented.
+pub trait MonotonicCounter {
+    /// Return the current value.
+    #[allow(clippy::result_unit_err)]
+    fn read(&mut self) -> Result<u32, ()>;
+
+    /// Increment the value by one.
+    #[allow(clippy::result_unit_err)]
+    fn increment(&mut self) -> Result<(), ()>;
+
+    /// Returns the maximum value. Default is `u32::MAX`.
+    fn max_value(&self) -> u32 {
+        u32::MAX
+    }
+}
+
+/// [`DfuMemory`] adapter that protects from downgrades with a [`MonotonicCounter`].
+///
+/// Minimum image version is the counter value, or the value returned by the wrapped
+/// memory, whichever is greater. If the counter can't be read, all images are rejected.
+/// Images with a version above [`max_value()`](MonotonicCounter::max_value) are
+/// rejected with `errFILE` before manifestation.
+///
+/// The counter is incremented only when the wrapped memory
+/// [`manifestation()`](DfuMemory::manifestation) returns `Ok`. The image is in place
+/// then, so a failed increment is not reported to the host, the counter is advanced
+/// by the next manifestation.
+/// If the device is not [`MANIFESTATION_TOLERANT`](DfuMemory::MANIFESTATION_TOLERANT),
+/// it's up to the new firmware to update the counter.
+pub struct RollbackCounter<M: DfuMemory, C: MonotonicCounter> {
+    mem: M,
+    counter: C,
+}
+
+impl<M: DfuMemory, C: MonotonicCounter> RollbackCounter<M, C> {
+    /// Wrap `mem`, and use `counter` as the minimum image version.
+    pub fn new(mem: M, counter: C) -> Self {
+        Self { mem, counter }
+    }
+
+    /// Returns a reference to the counter.
+    pub fn counter(&mut self) -> &mut C {
+        &mut self.counter
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory and the counter.
+    pub fn release(self) -> (M, C) {
+        (self.mem, self.counter)
+    }
+
+    /// Increment the counter until it reaches `version`.
+    fn advance(&mut self, version: u32) -> Result<(), ()> {
+        while self.counter.read()? < version {
+            self.counter.increment()?;
+        }
+        Ok(())
+    }
+}
+
+impl<M: DfuMemory, C: MonotonicCounter> DfuMemory for RollbackCounter<M, C> {
+    forward_dfu_memory!(mem: M, except manifestation, image_version, minimum_image_version);
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        // the version may not be available after manifestation
+        let version = self.mem.image_version();
+        self.mem.manifestation()?;
+        if let Some(version) = version {
+            if self.advance(version).is_err() {
+                debug!("rollback counter is not advanced to {}", version);
+            }
+        }
+        Ok(())
+    }
+
+    fn image_version(&mut self) -> Option<u32> {
+        // the counter can't hold a greater version
+        let max = self.counter.max_value();
+        self.mem.image_version().filter(|&version| version <= max)
+    }
+
+    fn minimum_image_version(&mut self) -> Option<u32> {
+        let counter = self.counter.read().unwrap_or(u32::MAX);
+        match self.mem.minimum_image_version() {
+            Some(minimum) => Some(minimum.max(counter)),
+            None => Some(counter),
+        }
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+const MAX_WRITE_SIZE: usize = 32;
+
+/// [`MonotonicCounter`] stored in a NOR flash page.
+///
+/// Each increment programs the next `WRITE_SIZE` unit of the page with zeroes,
+/// the value is the number of programmed units. The page is never erased by
+/// `FlashCounter`, it must be erased once when the device is provisioned.
+/// The maximum value is `size / WRITE_SIZE`.
+///
+/// `WRITE_SIZE` must not be greater than 32 bytes.
+///
+/// Requires `embedded-storage` feature.
+#[cfg(feature = "embedded-storage")]
+pub struct FlashCounter<F: embedded_storage::nor_flash::NorFlash> {
+    flash: F,
+    offset: u32,
+    size: u32,
+    value: Option<u32>,
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<F: embedded_storage::nor_flash::NorFlash> FlashCounter<F> {
+    /// Keep the counter in `size` bytes of `flash` starting at `offset`.
+    ///
+    /// Panics if `WRITE_SIZE` is greater than 32 bytes.
+    pub fn new(flash: F, offset: u32, size: u32) -> Self {
+        assert!(
+            F::WRITE_SIZE <= MAX_WRITE_SIZE,
+            "FlashCounter WRITE_SIZE is too large"
+        );
+
+        Self {
+            flash,
+            offset,
+            size,
+            value: None,
+        }
+    }
+
+    /// Destroy the counter and return the flash.
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    fn units(&self) -> u32 {
+        self.size / F::WRITE_SIZE as u32
+    }
+
+    /// Find the first erased unit.
+    fn scan(&mut self) -> Result<u32, ()> {
+        let mut unit = [0u8; MAX_WRITE_SIZE];
+        let unit = &mut unit[..F::WRITE_SIZE];
+
+        for n in 0..self.units() {
+            let offset = self.offset + n * F::WRITE_SIZE as u32;
+            self.flash.read(offset, unit).map_err(|_| ())?;
+            if unit.iter().all(|&b| b == 0xff) {
+                return Ok(n);
+            }
+        }
+        Ok(self.units())
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+impl<F: embedded_storage::nor_flash::NorFlash> MonotonicCounter for FlashCounter<F> {
+    fn read(&mut self) -> Result<u32, ()> {
+        match self.value {
+            Some(value) => Ok(value),
+            None => {
+                let value = self.scan()?;
+                self.value = Some(value);
+                Ok(value)
+            }
+        }
+    }
+
+    fn increment(&mut self) -> Result<(), ()> {
+        let value = self.read()?;
+        if value >= self.units() {
+            return Err(());
+        }
+
+        let zeroes = [0u8; MAX_WRITE_SIZE];
+        let offset = self.offset + value * F::WRITE_SIZE as u32;
+        // force a rescan if the write fails halfway
+        self.value = None;
+        self.flash
+            .write(offset, &zeroes[..F::WRITE_SIZE])
+            .map_err(|_| ())?;
+        self.value = Some(value + 1);
+        Ok(())
+    }
+
+    fn max_value(&self) -> u32 {
+        self.units()
+    }
+}
@@ -0,0 +1,295 @@
+//! RP2040 flash
+//!
+//! RP2040 executes code directly from the external QSPI flash (XIP). While the
+//! flash is erased or programmed, XIP is not available, so any code that runs at
+//! that time, including interrupt handlers and the other core, must not touch
+//! the flash. A naive [`DfuMemory`](crate::DfuMemory) implementation hard-faults
+//! as soon as it leaves XIP mode.
+//!
+//! [`Rp2040Flash`] is a [`NorFlash`] that:
+//!
+//! * looks up flash routines in the bootrom,
+//! * pauses the other core with [`CoreLock`] and disables interrupts,
+//! * calls the bootrom routines from a function placed in RAM
+//!   (`.data.ram_func` section, copied to RAM with `.data` by `cortex-m-rt`),
+//! * re-enables XIP, flushes the XIP cache, and resumes the other core.
+//!
+//! [`Rp2040Memory`] is a [`FlashMemory`] with [`Rp2040`] layout for a
+//! region of the flash described by [`Rp2040Region`].
+//!
+//! XIP is re-enabled with the bootrom `flash_enter_cmd_xip()`, which uses the
+//! slow `03h` read command. Call boot2 again after the download if performance
+//! matters before the device is reset.
+//!
+//! Requires `rp2040` feature.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::rp2040::*;
+//! # use usbd_dfu::DfuClass;
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>) {
+//! struct App;
+//!
+//! impl Rp2040Region for App {
+//!     const START: u32 = 0x1001_0000;
+//!     const END: u32 = 0x1020_0000;
+//! }
+//!
+//! // the other core is not started
+//! let flash = unsafe { Rp2040Flash::new(SingleCore) };
+//! let mem = Rp2040Memory::<_, App, 1024>::new(flash, XIP_BASE);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use core::marker::PhantomData;
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::flash::{FlashLayout, FlashMemory};
+use crate::mem_info::MemInfo;
+
+/// Address of flash offset `0` in the XIP address space.
+pub const XIP_BASE: u32 = 0x1000_0000;
+
+/// Erase sector size.
+pub const SECTOR_SIZE: u32 = 4096;
+
+/// Program page size.
+pub const PAGE_SIZE: u32 = 256;
+
+/// Maximum sector erase time of common QSPI flash chips, e.g. W25Q16JV.
+pub const SECTOR_ERASE_TIME_MS: u32 = 400;
+
+/// Maximum time to program 1 KiB, 4 pages of 3 ms.
+pub const KIB_PROGRAM_TIME_MS: u32 = 12;
+
+/// Maximum length of generated memory info strings.
+const MEM_INFO_LENGTH: usize = 64;
+
+/// Pauses the other core while the flash is not accessible.
+///
+/// The other core must not execute code from flash, e.g. it waits in a RAM
+/// function, as with `embassy_rp` multicore flash lockout.
+pub trait CoreLock {
+    /// Pause the other core, returns when it's paused.
+    fn pause(&mut self);
+
+    /// Resume the other core.
+    fn resume(&mut self);
+}
+
+/// [`CoreLock`] for a device that doesn't run the other core.
+pub struct SingleCore;
+
+impl CoreLock for SingleCore {
+    fn pause(&mut self) {}
+
+    fn resume(&mut self) {}
+}
+
+/// Errors of [`Rp2040Flash`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum Rp2040FlashError {
+    /// Offset or length is not aligned to sector or page size.
+    NotAligned,
+    /// Offset or length is beyond the flash capacity.
+    OutOfBounds,
+}
+
+impl NorFlashError for Rp2040FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Rp2040FlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            Rp2040FlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+        }
+    }
+}
+
+/// Bootrom flash routines.
+#[derive(Clone, Copy)]
+struct Rom {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+    flash_enter_cmd_xip: unsafe extern "C" fn(),
+}
+
+impl Rom {
+    /// Find a bootrom function by its two-letter code.
+    unsafe fn lookup(code: &[u8; 2]) -> usize {
+        type LookupFn = unsafe extern "C" fn(*const u16, u32) -> usize;
+        let table = core::ptr::read(0x14 as *const u16) as usize as *const u16;
+        let lookup_address = core::ptr::read(0x18 as *const u16) as usize;
+        let lookup: LookupFn = core::mem::transmute(lookup_address);
+        lookup(table, u16::from_le_bytes(*code) as u32)
+    }
+
+    unsafe fn new() -> Self {
+        // function pointers have the size of `usize`
+        Self {
+            connect_internal_flash: core::mem::transmute_copy(&Self::lookup(b"IF")),
+            flash_exit_xip: core::mem::transmute_copy(&Self::lookup(b"EX")),
+            flash_range_erase: core::mem::transmute_copy(&Self::lookup(b"RE")),
+            flash_range_program: core::mem::transmute_copy(&Self::lookup(b"RP")),
+            flash_flush_cache: core::mem::transmute_copy(&Self::lookup(b"FC")),
+            flash_enter_cmd_xip: core::mem::transmute_copy(&Self::lookup(b"CX")),
+        }
+    }
+}
+
+/// Erase `count` bytes at `offset` if `data` is null, or program them with `data`.
+///
+/// Runs from RAM, must not call any code in flash.
+#[inline(never)]
+#[cfg_attr(target_arch = "arm", link_section = ".data.ram_func")]
+unsafe extern "C" fn flash_operation(rom: &Rom, offset: u32, data: *const u8, count: usize) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    if data.is_null() {
+        // 64 KiB block erase command where possible
+        (rom.flash_range_erase)(offset, count, 1 << 16, 0xd8);
+    } else {
+        (rom.flash_range_program)(offset, data, count);
+    }
+    (rom.flash_flush_cache)();
+    (rom.flash_enter_cmd_xip)();
+}
+
+/// [`NorFlash`] for RP2040 external QSPI flash, offsets are relative to [`XIP_BASE`].
+pub struct Rp2040Flash<L: CoreLock> {
+    lock: L,
+    capacity: usize,
+    rom: Option<Rom>,
+}
+
+impl<L: CoreLock> Rp2040Flash<L> {
+    /// Access 2 MiB flash, pause the other core with `lock`.
+    ///
+    /// # Safety
+    ///
+    /// Must be called on RP2040, and nothing else may access the flash controller.
+    pub unsafe fn new(lock: L) -> Self {
+        Self::with_capacity(lock, 2 * 1024 * 1024)
+    }
+
+    /// Access flash of `capacity` bytes, pause the other core with `lock`.
+    ///
+    /// # Safety
+    ///
+    /// See [`new()`](Self::new).
+    pub unsafe fn with_capacity(lock: L, capacity: usize) -> Self {
+        Self {
+            lock,
+            capacity,
+            rom: None,
+        }
+    }
+
+    /// Destroy the flash and return the core lock.
+    pub fn release(self) -> L {
+        self.lock
+    }
+
+    fn check(&self, offset: u32, length: usize, align: u32) -> Result<(), Rp2040FlashError> {
+        if !offset.is_multiple_of(align) || !(length as u32).is_multiple_of(align) {
+            return Err(Rp2040FlashError::NotAligned);
+        }
+        match (offset as usize).checked_add(length) {
+            Some(end) if end <= self.capacity => Ok(()),
+            _ => Err(Rp2040FlashError::OutOfBounds),
+        }
+    }
+
+    fn execute(&mut self, offset: u32, data: *const u8, count: usize) {
+        let rom = *self.rom.get_or_insert_with(|| unsafe { Rom::new() });
+        self.lock.pause();
+        critical_section::with(|_| unsafe {
+            flash_operation(&rom, offset, data, count);
+        });
+        self.lock.resume();
+    }
+}
+
+impl<L: CoreLock> ErrorType for Rp2040Flash<L> {
+    type Error = Rp2040FlashError;
+}
+
+impl<L: CoreLock> ReadNorFlash for Rp2040Flash<L> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len(), 1)?;
+        let src = (XIP_BASE + offset) as *const u8;
+        unsafe {
+            core::ptr::copy_nonoverlapping(src, bytes.as_mut_ptr(), bytes.len());
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<L: CoreLock> NorFlash for Rp2040Flash<L> {
+    const WRITE_SIZE: usize = PAGE_SIZE as usize;
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        let length = to.checked_sub(from).ok_or(Rp2040FlashError::OutOfBounds)? as usize;
+        self.check(from, length, SECTOR_SIZE)?;
+        self.execute(from, core::ptr::null(), length);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len(), PAGE_SIZE)?;
+        // `bytes` must not be in flash, DFU buffers are in RAM
+        self.execute(offset, bytes.as_ptr(), bytes.len());
+        Ok(())
+    }
+}
+
+/// Region of RP2040 flash available for downloads, e.g. the application.
+///
+/// Addresses are in the XIP address space, and must be sector aligned.
+pub trait Rp2040Region {
+    /// First address of the region.
+    const START: u32;
+
+    /// End address of the region, exclusive.
+    const END: u32;
+}
+
+/// [`FlashLayout`] of RP2040 flash with 4 KiB sectors.
+pub struct Rp2040<R: Rp2040Region> {
+    _region: PhantomData<R>,
+}
+
+impl<R: Rp2040Region> FlashLayout for Rp2040<R> {
+    const START: u32 = R::START;
+    const END: u32 = R::END;
+    const MEM_INFO_STRING: &'static str = MemInfo::<MEM_INFO_LENGTH>::new("Flash", R::START)
+        .area((R::END - R::START) / SECTOR_SIZE, SECTOR_SIZE, 'g')
+        .as_str();
+    const PROGRAM_TIME_MS: u32 = KIB_PROGRAM_TIME_MS;
+    const ERASE_TIME_MS: u32 = SECTOR_ERASE_TIME_MS;
+    const FULL_ERASE_TIME_MS: u32 = SECTOR_ERASE_TIME_MS * ((R::END - R::START) / SECTOR_SIZE);
+
+    fn page(address: u32) -> Option<(u32, u32)> {
+        if !(R::START..R::END).contains(&address) {
+            return None;
+        }
+        Some((address - address % SECTOR_SIZE, SECTOR_SIZE))
+    }
+}
+
+/// [`DfuMemory`](crate::DfuMemory) implementation for a region `R` of RP2040 flash,
+/// `N` must be a multiple of 256. Create it with `base` [`XIP_BASE`].
+pub type Rp2040Memory<L, R, const N: usize> = FlashMemory<Rp2040Flash<L>, Rp2040<R>, N>;
@@ -0,0 +1,399 @@
+//! Image staging on an SD card
+//!
+//! [`SdStaging`] implements [`DfuMemory`] that writes the downloaded image to a
+//! [`StagingStorage`], e.g. a file or a raw region of an SD card, for devices
+//! whose firmware is too large to be staged in internal flash. Manifestation
+//! commits a [`StagedImage`] record with the image size and CRC-32, and the
+//! bootloader installs the image when it finds a valid record.
+//!
+//! The image is written sequentially, starting at address `0`. A block at any
+//! other address fails with `errADDRESS`. Erase requests succeed without changes.
+//!
+//! [`RawRegion`] stores the image in consecutive blocks of a [`BlockDevice`],
+//! the first block holds the record:
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::sd_staging::*;
+//! # use usbd_dfu::DfuClass;
+//! # fn install(region: &mut impl StagingStorage, image: StagedImage) {}
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, sd_blocks: impl BlockDevice) {
+//! // 4 MiB at block 2048
+//! let region = RawRegion::<_, 8193>::new(sd_blocks, 2048);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, SdStaging::<_, 1024>::new(region));
+//! # }
+//! # fn bootloader(sd_blocks: impl BlockDevice) {
+//!
+//! // bootloader, after reset
+//! let mut region = RawRegion::<_, 8193>::new(sd_blocks, 2048);
+//! if let Ok(Some(image)) = region.staged_image() {
+//!     install(&mut region, image);
+//! }
+//! # }
+//! ```
+//!
+//! With `embedded-sdmmc` feature, [`SdmmcRegion`] is a [`RawRegion`] of a card driven
+//! by `embedded-sdmmc`, e.g. its `SdCard`. Otherwise, [`BlockDevice`] is implemented by
+//! the application for its SD card driver.
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError};
+use crate::mem_info::MemInfo;
+use crate::suffix::Crc32;
+
+/// Size of a serialized [`StagedImage`] in bytes.
+pub const STAGED_IMAGE_LENGTH: usize = 16;
+
+/// SD card block size.
+pub const BLOCK_SIZE: usize = 512;
+
+/// `"DFUS"` marks a valid [`StagedImage`] record.
+const STAGED_IMAGE_MAGIC: u32 = 0x5355_4644;
+
+/// Maximum length of generated memory info strings.
+const MEM_INFO_LENGTH: usize = 64;
+
+/// Record of a completely downloaded image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct StagedImage {
+    /// Image size in bytes.
+    pub size: u32,
+    /// CRC-32 of the image (IEEE 802.3, as in zlib).
+    pub crc: u32,
+}
+
+impl StagedImage {
+    /// Serialize the record, magic, fields, and CRC-32 of them are little-endian `u32`.
+    pub fn to_bytes(&self) -> [u8; STAGED_IMAGE_LENGTH] {
+        let mut bytes = [0; STAGED_IMAGE_LENGTH];
+        bytes[0..4].copy_from_slice(&STAGED_IMAGE_MAGIC.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.size.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.crc.to_le_bytes());
+        let mut crc = Crc32::new();
+        crc.update(&bytes[..12]);
+        bytes[12..16].copy_from_slice(&crc.finalize().to_le_bytes());
+        bytes
+    }
+
+    /// Parse the record serialized by [`to_bytes()`](Self::to_bytes).
+    ///
+    /// Returns `None` if there's no valid record, e.g. the download was not completed.
+    pub fn from_bytes(bytes: &[u8; STAGED_IMAGE_LENGTH]) -> Option<Self> {
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let mut crc = Crc32::new();
+        crc.update(&bytes[..12]);
+        if u32_at(0) != STAGED_IMAGE_MAGIC || crc.finalize() != u32_at(12) {
+            return None;
+        }
+        Some(Self {
+            size: u32_at(4),
+            crc: u32_at(8),
+        })
+    }
+}
+
+/// Storage of the staged image.
+pub trait StagingStorage {
+    /// See [`DfuMemory::MEM_INFO_STRING`].
+    const MEM_INFO_STRING: &'static str;
+
+    /// Time in milliseconds to write a block of `TRANSFER_SIZE` bytes.
+    /// Default is `20`.
+    const WRITE_TIME_MS: u32 = 20;
+
+    /// Discard the staged image and its record before a new image is written.
+    #[allow(clippy::result_unit_err)]
+    fn begin(&mut self) -> Result<(), ()>;
+
+    /// Write `data` at `offset` of the image.
+    #[allow(clippy::result_unit_err)]
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()>;
+
+    /// Read image data at `offset`, returns the number of bytes read,
+    /// which is less than `data` length at the end of the storage.
+    #[allow(clippy::result_unit_err)]
+    fn read(&mut self, offset: u32, data: &mut [u8]) -> Result<usize, ()>;
+
+    /// Mark the image valid with `image` record.
+    #[allow(clippy::result_unit_err)]
+    fn commit(&mut self, image: &StagedImage) -> Result<(), ()>;
+}
+
+/// Block storage of an SD card driver.
+pub trait BlockDevice {
+    /// Read block `index`.
+    #[allow(clippy::result_unit_err)]
+    fn read_block(&mut self, index: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), ()>;
+
+    /// Write block `index`.
+    #[allow(clippy::result_unit_err)]
+    fn write_block(&mut self, index: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), ()>;
+}
+
+/// [`BlockDevice`] of an `embedded-sdmmc` block device.
+///
+/// Requires `embedded-sdmmc` feature.
+#[cfg(feature = "embedded-sdmmc")]
+pub struct SdmmcBlocks<D: embedded_sdmmc::BlockDevice> {
+    device: D,
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+impl<D: embedded_sdmmc::BlockDevice> SdmmcBlocks<D> {
+    /// Access blocks of `device`.
+    pub fn new(device: D) -> Self {
+        Self { device }
+    }
+
+    /// Destroy the adapter and return the block device.
+    pub fn release(self) -> D {
+        self.device
+    }
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+impl<D: embedded_sdmmc::BlockDevice> BlockDevice for SdmmcBlocks<D> {
+    fn read_block(&mut self, index: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), ()> {
+        let mut blocks = [embedded_sdmmc::Block::new()];
+        self.device
+            .read(&mut blocks, embedded_sdmmc::BlockIdx(index))
+            .map_err(|_| ())?;
+        *block = blocks[0].contents;
+        Ok(())
+    }
+
+    fn write_block(&mut self, index: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), ()> {
+        let blocks = [embedded_sdmmc::Block { contents: *block }];
+        self.device
+            .write(&blocks, embedded_sdmmc::BlockIdx(index))
+            .map_err(|_| ())
+    }
+}
+
+/// [`RawRegion`] of an `embedded-sdmmc` block device, requires `embedded-sdmmc` feature.
+#[cfg(feature = "embedded-sdmmc")]
+pub type SdmmcRegion<D, const BLOCKS: u32> = RawRegion<SdmmcBlocks<D>, BLOCKS>;
+
+/// [`StagingStorage`] in `BLOCKS` consecutive blocks of a [`BlockDevice`].
+///
+/// The first block holds the [`StagedImage`] record, the image starts at the second block.
+/// `BLOCKS` must be at least `2`, and the image size must fit in `u32`.
+pub struct RawRegion<D: BlockDevice, const BLOCKS: u32> {
+    device: D,
+    start: u32,
+    block: [u8; BLOCK_SIZE],
+}
+
+/// Memory info string of a region of `blocks` blocks.
+const fn raw_mem_info(blocks: u32) -> MemInfo<MEM_INFO_LENGTH> {
+    MemInfo::new("SD Card", 0).area(blocks - 1, BLOCK_SIZE as u32, 'e')
+}
+
+impl<D: BlockDevice, const BLOCKS: u32> RawRegion<D, BLOCKS> {
+    /// Store the image in `BLOCKS` blocks of `device`, starting at block `start`.
+    pub fn new(device: D, start: u32) -> Self {
+        const {
+            assert!(
+                BLOCKS >= 2 && BLOCKS - 1 <= u32::MAX / BLOCK_SIZE as u32,
+                "RawRegion size is out of range"
+            )
+        };
+
+        Self {
+            device,
+            start,
+            block: [0; BLOCK_SIZE],
+        }
+    }
+
+    /// Destroy the region and return the block device.
+    pub fn release(self) -> D {
+        self.device
+    }
+
+    /// Load the record of the staged image, `None` if there's no complete image.
+    #[allow(clippy::result_unit_err)]
+    pub fn staged_image(&mut self) -> Result<Option<StagedImage>, ()> {
+        self.device.read_block(self.start, &mut self.block)?;
+        let bytes = self.block[..STAGED_IMAGE_LENGTH].try_into().unwrap();
+        Ok(StagedImage::from_bytes(bytes))
+    }
+
+    fn write_record(&mut self, record: &[u8; STAGED_IMAGE_LENGTH]) -> Result<(), ()> {
+        self.block.fill(0);
+        self.block[..STAGED_IMAGE_LENGTH].copy_from_slice(record);
+        self.device.write_block(self.start, &self.block)
+    }
+
+    /// Returns image size in bytes.
+    fn capacity() -> u32 {
+        (BLOCKS - 1) * BLOCK_SIZE as u32
+    }
+}
+
+impl<D: BlockDevice, const BLOCKS: u32> StagingStorage for RawRegion<D, BLOCKS> {
+    const MEM_INFO_STRING: &'static str = raw_mem_info(BLOCKS).as_str();
+
+    fn begin(&mut self) -> Result<(), ()> {
+        self.write_record(&[0; STAGED_IMAGE_LENGTH])
+    }
+
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), ()> {
+        match offset.checked_add(data.len() as u32) {
+            Some(end) if end <= Self::capacity() => {}
+            _ => return Err(()),
+        }
+
+        let mut offset = offset as usize;
+        let mut data = data;
+        while !data.is_empty() {
+            let index = self.start + 1 + (offset / BLOCK_SIZE) as u32;
+            let from = offset % BLOCK_SIZE;
+            let len = data.len().min(BLOCK_SIZE - from);
+            if len < BLOCK_SIZE {
+                // keep the rest of a partial block
+                self.device.read_block(index, &mut self.block)?;
+            }
+            self.block[from..from + len].copy_from_slice(&data[..len]);
+            self.device.write_block(index, &self.block)?;
+            offset += len;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, offset: u32, data: &mut [u8]) -> Result<usize, ()> {
+        let available = Self::capacity().saturating_sub(offset) as usize;
+        let length = data.len().min(available);
+
+        let mut read = 0;
+        while read < length {
+            let position = offset as usize + read;
+            let index = self.start + 1 + (position / BLOCK_SIZE) as u32;
+            let from = position % BLOCK_SIZE;
+            let len = (length - read).min(BLOCK_SIZE - from);
+            self.device.read_block(index, &mut self.block)?;
+            data[read..read + len].copy_from_slice(&self.block[from..from + len]);
+            read += len;
+        }
+        Ok(length)
+    }
+
+    fn commit(&mut self, image: &StagedImage) -> Result<(), ()> {
+        self.write_record(&image.to_bytes())
+    }
+}
+
+/// [`DfuMemory`] implementation that stages the image in a [`StagingStorage`].
+///
+/// `N` is the size of the internal buffer and the
+/// [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE). If the storage can't be written,
+/// programming fails with `errWRITE`, and manifestation fails with `errUNKNOWN`.
+pub struct SdStaging<S: StagingStorage, const N: usize> {
+    storage: S,
+    buffer: [u8; N],
+    /// The previous image is discarded for the current download.
+    started: bool,
+    /// Size of the image written so far.
+    size: u32,
+    crc: Crc32,
+}
+
+impl<S: StagingStorage, const N: usize> SdStaging<S, N> {
+    /// Stage images in `storage`.
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage,
+            buffer: [0; N],
+            started: false,
+            size: 0,
+            crc: Crc32::new(),
+        }
+    }
+
+    /// Returns a reference to the storage.
+    pub fn storage(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    /// Destroy the memory and return the storage.
+    pub fn release(self) -> S {
+        self.storage
+    }
+}
+
+impl<S: StagingStorage, const N: usize> DfuMemory for SdStaging<S, N> {
+    const INITIAL_ADDRESS_POINTER: u32 = 0;
+    const MEM_INFO_STRING: &'static str = S::MEM_INFO_STRING;
+    const PROGRAM_TIME_MS: u32 = S::WRITE_TIME_MS;
+    const ERASE_TIME_MS: u32 = 0;
+    const FULL_ERASE_TIME_MS: u32 = 0;
+    const TRANSFER_SIZE: u16 = N as u16;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let buffer = &mut self.buffer[..length.min(N)];
+        let len = self
+            .storage
+            .read(address, buffer)
+            .map_err(|_| DfuMemoryError::Unknown)?;
+        Ok(&buffer[..len])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        if length > N {
+            return Err(DfuMemoryError::Prog);
+        }
+        if address != self.size {
+            return Err(DfuMemoryError::Address);
+        }
+        if !self.started {
+            self.storage.begin().map_err(|_| DfuMemoryError::Write)?;
+            self.started = true;
+        }
+
+        let data = &self.buffer[..length];
+        self.storage
+            .write(address, data)
+            .map_err(|_| DfuMemoryError::Write)?;
+        self.crc.update(data);
+        self.size += length as u32;
+        Ok(())
+    }
+
+    fn erase(&mut self, _address: u32) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        if !self.started {
+            return Err(DfuManifestationError::NotDone);
+        }
+        let image = StagedImage {
+            size: self.size,
+            crc: !self.crc.finalize(),
+        };
+        self.storage
+            .commit(&image)
+            .map_err(|_| DfuManifestationError::Unknown)?;
+        self.started = false;
+        Ok(())
+    }
+
+    fn download_start(&mut self) {
+        self.started = false;
+        self.size = 0;
+        self.crc = Crc32::new();
+    }
+}
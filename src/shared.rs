@@ -0,0 +1,164 @@
+//! [`DfuClass`] shared between USB interrupt handler and the main loop.
+//!
+//! A common pattern is to keep [`DfuClass`] and `UsbDevice` in `static` variables and
+//! call `usb_dev.poll()` from the USB interrupt handler, while the
+//! main loop sometimes needs to access the class or the memory.
+//! [`SharedDfuClass`] wraps the class into `critical_section::Mutex`, so
+//! every access happens inside a critical section.
+//!
+//! Requires `critical-section` feature.
+//!
+//! ```no_run
+//! # use usb_device::bus::UsbBusAllocator;
+//! # use usb_device::device::UsbDevice;
+//! # use usb_device::endpoint::{EndpointAddress, EndpointType};
+//! # use usb_device::UsbDirection;
+//! # struct MyUsbBus;
+//! # impl usb_device::bus::UsbBus for MyUsbBus {
+//! #     fn alloc_ep(&mut self, _: UsbDirection, _: Option<EndpointAddress>, _: EndpointType,
+//! #                 _: u16, _: u8) -> usb_device::Result<EndpointAddress> { todo!() }
+//! #     fn enable(&mut self) { todo!() }
+//! #     fn reset(&self) { todo!() }
+//! #     fn set_device_address(&self, _: u8) { todo!() }
+//! #     fn write(&self, _: EndpointAddress, _: &[u8]) -> usb_device::Result<usize> { todo!() }
+//! #     fn read(&self, _: EndpointAddress, _: &mut [u8]) -> usb_device::Result<usize> { todo!() }
+//! #     fn set_stalled(&self, _: EndpointAddress, _: bool) { todo!() }
+//! #     fn is_stalled(&self, _: EndpointAddress) -> bool { todo!() }
+//! #     fn suspend(&self) { todo!() }
+//! #     fn resume(&self) { todo!() }
+//! #     fn poll(&self) -> usb_device::bus::PollResult { todo!() }
+//! # }
+//! # use usbd_dfu::{DfuClass, DfuMemory, SharedDfuClass};
+//! # struct MyMem;
+//! # impl DfuMemory for MyMem {
+//! #     const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+//! #     const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*1Kg";
+//! #     const PROGRAM_TIME_MS: u32 = 10;
+//! #     const ERASE_TIME_MS: u32 = 20;
+//! #     const FULL_ERASE_TIME_MS: u32 = 320;
+//! # }
+//! # fn show_progress(percent: u8) {}
+//! static DFU: SharedDfuClass<MyUsbBus, MyMem> = SharedDfuClass::new();
+//!
+//! # fn example(usb_bus_alloc: UsbBusAllocator<MyUsbBus>, my_mem: MyMem, mut usb_dev: UsbDevice<MyUsbBus>) {
+//! // init
+//! DFU.init(DfuClass::new(&usb_bus_alloc, my_mem));
+//!
+//! // USB interrupt
+//! DFU.poll(&mut usb_dev);
+//!
+//! // timer interrupt
+//! DFU.tick(10);
+//!
+//! // main loop
+//! let addr = DFU.get_address_pointer();
+//! if let Some(percent) = DFU.download_progress() {
+//!     show_progress(percent);
+//! }
+//! # }
+//! ```
+
+use core::cell::RefCell;
+use critical_section::Mutex;
+use usb_device::{bus::UsbBus, device::UsbDevice};
+
+use crate::class::{DfuClass, DfuMemory, DfuStatusCode, MemoryErrorDetail};
+
+/// [`DfuClass`] protected by a `critical_section::Mutex`.
+///
+/// The class is empty until [`init()`](SharedDfuClass::init) is called,
+/// all forwarding methods return `None` or `false`, or do nothing in this case.
+pub struct SharedDfuClass<B: UsbBus, M: DfuMemory> {
+    inner: Mutex<RefCell<Option<DfuClass<B, M>>>>,
+}
+
+impl<B: UsbBus, M: DfuMemory> SharedDfuClass<B, M> {
+    /// Creates a new empty [`SharedDfuClass`], usable in `static` initializers.
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Store `class`, returns previously stored class, if any.
+    pub fn init(&self, class: DfuClass<B, M>) -> Option<DfuClass<B, M>> {
+        critical_section::with(|cs| self.inner.borrow_ref_mut(cs).replace(class))
+    }
+
+    /// Remove the class and return it.
+    pub fn take(&self) -> Option<DfuClass<B, M>> {
+        critical_section::with(|cs| self.inner.borrow_ref_mut(cs).take())
+    }
+
+    /// Returns `true` if the class was stored by [`init()`](SharedDfuClass::init).
+    pub fn is_initialized(&self) -> bool {
+        critical_section::with(|cs| self.inner.borrow_ref(cs).is_some())
+    }
+
+    /// Call `f` with a mutable reference to the class inside a critical section.
+    ///
+    /// Returns `None` if the class is not initialized.
+    ///
+    /// `f` must not call methods of the same [`SharedDfuClass`], they panic
+    /// because the class is already borrowed.
+    pub fn with<R>(&self, f: impl FnOnce(&mut DfuClass<B, M>) -> R) -> Option<R> {
+        critical_section::with(|cs| self.inner.borrow_ref_mut(cs).as_mut().map(f))
+    }
+
+    /// Call `usb_dev.poll()` with the class inside a critical section.
+    ///
+    /// Returns the value returned by `usb_dev.poll()`, or `false`
+    /// if the class is not initialized.
+    pub fn poll(&self, usb_dev: &mut UsbDevice<'_, B>) -> bool {
+        self.with(|dfu| usb_dev.poll(&mut [dfu])).unwrap_or(false)
+    }
+
+    /// Return current Address Pointer value.
+    pub fn get_address_pointer(&self) -> Option<u32> {
+        self.with(|dfu| dfu.get_address_pointer())
+    }
+
+    /// Set Address Pointer, see [`DfuClass::set_address_pointer()`].
+    ///
+    /// Returns `None` if the class is not initialized.
+    pub fn set_address_pointer(&self, address: u32) -> Option<Result<(), DfuStatusCode>> {
+        self.with(|dfu| dfu.set_address_pointer(address))
+    }
+
+    /// Returns the last failed [`DfuMemory`] call, if any.
+    pub fn last_memory_error(&self) -> Option<MemoryErrorDetail> {
+        self.with(|dfu| dfu.last_memory_error()).flatten()
+    }
+
+    /// Returns the received part of the image in percent,
+    /// see [`DfuClass::download_progress()`].
+    pub fn download_progress(&self) -> Option<u8> {
+        self.with(|dfu| dfu.download_progress()).flatten()
+    }
+
+    /// Returns `true` if the host polled the status since the last call,
+    /// see [`DfuClass::take_host_poll()`].
+    pub fn take_host_poll(&self) -> bool {
+        self.with(|dfu| dfu.take_host_poll()).unwrap_or(false)
+    }
+
+    /// Report `elapsed_ms` milliseconds since the last call, see [`DfuClass::tick()`].
+    ///
+    /// Unlike [`DfuClass::tick()`], it can be called from any context, e.g. a timer
+    /// interrupt of a different priority than the USB one. Returns `true` if the
+    /// session timed out.
+    pub fn tick(&self, elapsed_ms: u32) -> bool {
+        self.with(|dfu| dfu.tick(elapsed_ms)).unwrap_or(false)
+    }
+
+    /// Cancel the session and go to `dfuIDLE`, see [`DfuClass::force_idle()`].
+    pub fn force_idle(&self) {
+        self.with(|dfu| dfu.force_idle());
+    }
+}
+
+impl<B: UsbBus, M: DfuMemory> Default for SharedDfuClass<B, M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,377 @@
+//! A/B firmware slots
+//!
+//! Two firmware slots of the same size, one of them is active. [`SlotMemory`]
+//! wraps [`DfuMemory`] and redirects all downloads to the inactive slot: DFU
+//! addresses are offsets from [`INITIAL_ADDRESS_POINTER`](DfuMemory::INITIAL_ADDRESS_POINTER)
+//! and are translated to the inactive slot, so the host always downloads
+//! to the same addresses. After a successful manifestation, the inactive slot
+//! is marked pending.
+//!
+//! [`SlotManager`] keeps [`SlotStatus`] in a [`SlotStorage`] and implements
+//! the rest of the update flow:
+//!
+//! * the bootloader calls [`SlotManager::boot()`] to select the slot to start,
+//!   a pending slot becomes active for a trial boot,
+//! * the application calls [`SlotManager::confirm()`] when the new firmware works,
+//!   or [`SlotManager::rollback()`] to return to the previous one,
+//! * if the device boots again before the trial is confirmed,
+//!   [`SlotManager::boot()`] reverts to the previous slot.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::slots::*;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # fn example<B: UsbBus>(
+//! #     usb_bus_alloc: UsbBusAllocator<B>,
+//! #     my_mem: impl DfuMemory,
+//! #     my_storage: impl SlotStorage,
+//! # ) {
+//! let layout = SlotLayout { slot_a: 0x0800_8000, slot_b: 0x0804_4000, size: 240 * 1024, page_size: 2048 };
+//! let mem = SlotMemory::new(my_mem, SlotManager::new(my_storage), layout);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError, ReadOutcome};
+
+/// Firmware slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum Slot {
+    /// Slot A.
+    A,
+    /// Slot B.
+    B,
+}
+
+impl Slot {
+    /// Returns the other slot.
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+/// Persistent state of the slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct SlotStatus {
+    /// Slot of the running or last started firmware.
+    pub active: Slot,
+    /// The inactive slot contains a new image to be tried on the next boot.
+    pub pending: bool,
+    /// The active slot is started for a trial and is not confirmed yet.
+    pub trial: bool,
+}
+
+impl SlotStatus {
+    const ACTIVE_B: u8 = 0x01;
+    const PENDING: u8 = 0x02;
+    const TRIAL: u8 = 0x04;
+
+    /// Serialize the status as a single byte.
+    pub fn to_byte(&self) -> u8 {
+        let mut byte = 0;
+        if self.active == Slot::B {
+            byte |= Self::ACTIVE_B;
+        }
+        if self.pending {
+            byte |= Self::PENDING;
+        }
+        if self.trial {
+            byte |= Self::TRIAL;
+        }
+        byte
+    }
+
+    /// Parse the status serialized by [`to_byte()`](Self::to_byte).
+    ///
+    /// Unknown values, e.g. erased memory, are `None`.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        if byte & !(Self::ACTIVE_B | Self::PENDING | Self::TRIAL) != 0 {
+            return None;
+        }
+        Some(Self {
+            active: if byte & Self::ACTIVE_B != 0 {
+                Slot::B
+            } else {
+                Slot::A
+            },
+            pending: byte & Self::PENDING != 0,
+            trial: byte & Self::TRIAL != 0,
+        })
+    }
+}
+
+impl Default for SlotStatus {
+    fn default() -> Self {
+        Self {
+            active: Slot::A,
+            pending: false,
+            trial: false,
+        }
+    }
+}
+
+/// Persistent storage of [`SlotStatus`].
+pub trait SlotStorage {
+    /// Load the status.
+    #[allow(clippy::result_unit_err)]
+    fn load(&mut self) -> Result<SlotStatus, ()>;
+
+    /// Store the status.
+    #[allow(clippy::result_unit_err)]
+    fn store(&mut self, status: &SlotStatus) -> Result<(), ()>;
+}
+
+/// Slot state machine on top of a [`SlotStorage`].
+pub struct SlotManager<S: SlotStorage> {
+    storage: S,
+}
+
+impl<S: SlotStorage> SlotManager<S> {
+    /// Use `storage` for the slot status.
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    /// Returns a reference to the storage.
+    pub fn storage(&mut self) -> &mut S {
+        &mut self.storage
+    }
+
+    /// Destroy the manager and return the storage.
+    pub fn release(self) -> S {
+        self.storage
+    }
+
+    /// Current status.
+    #[allow(clippy::result_unit_err)]
+    pub fn status(&mut self) -> Result<SlotStatus, ()> {
+        self.storage.load()
+    }
+
+    fn update(&mut self, f: impl FnOnce(&mut SlotStatus)) -> Result<SlotStatus, ()> {
+        let mut status = self.storage.load()?;
+        let old = status;
+        f(&mut status);
+        if status != old {
+            self.storage.store(&status)?;
+        }
+        Ok(status)
+    }
+
+    /// Select the slot to start, called by the bootloader.
+    ///
+    /// A pending slot is started for a trial. If the previous trial was not
+    /// confirmed, the previous slot is started again.
+    #[allow(clippy::result_unit_err)]
+    pub fn boot(&mut self) -> Result<Slot, ()> {
+        self.update(|status| {
+            if status.pending {
+                status.active = status.active.other();
+                status.pending = false;
+                status.trial = true;
+            } else if status.trial {
+                status.active = status.active.other();
+                status.trial = false;
+            }
+        })
+        .map(|status| status.active)
+    }
+
+    /// Confirm the active slot after a trial boot, called by the application.
+    #[allow(clippy::result_unit_err)]
+    pub fn confirm(&mut self) -> Result<(), ()> {
+        self.update(|status| status.trial = false).map(|_| ())
+    }
+
+    /// Return to the previous slot after a trial boot, called by the application.
+    ///
+    /// The previous slot is started on the next boot. Fails if the active slot
+    /// is not in a trial.
+    #[allow(clippy::result_unit_err)]
+    pub fn rollback(&mut self) -> Result<(), ()> {
+        if !self.storage.load()?.trial {
+            return Err(());
+        }
+        self.update(|status| {
+            status.active = status.active.other();
+            status.trial = false;
+        })
+        .map(|_| ())
+    }
+}
+
+/// Addresses and size of the slots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct SlotLayout {
+    /// Address of slot A.
+    pub slot_a: u32,
+    /// Address of slot B.
+    pub slot_b: u32,
+    /// Size of each slot in bytes.
+    pub size: u32,
+    /// Size of the erase page in bytes, used by `erase_all`.
+    pub page_size: u32,
+}
+
+impl SlotLayout {
+    /// Address of `slot`.
+    pub fn address(&self, slot: Slot) -> u32 {
+        match slot {
+            Slot::A => self.slot_a,
+            Slot::B => self.slot_b,
+        }
+    }
+}
+
+/// [`DfuMemory`] adapter that redirects DFU operations to the inactive slot.
+///
+/// Reads, erases, and programs at `INITIAL_ADDRESS_POINTER + offset` access
+/// the inactive slot at `offset`, addresses outside the slot fail with `errADDRESS`.
+/// Mass erase erases only the inactive slot, page by page.
+///
+/// The first programmed block of a download clears the pending mark, and ends
+/// the trial of the active slot, as the previous image is overwritten.
+/// After the wrapped memory [`manifestation()`](DfuMemory::manifestation) succeeds,
+/// the inactive slot is marked pending.
+pub struct SlotMemory<M: DfuMemory, S: SlotStorage> {
+    mem: M,
+    manager: SlotManager<S>,
+    layout: SlotLayout,
+    /// A block was programmed since the download start.
+    programmed: bool,
+}
+
+impl<M: DfuMemory, S: SlotStorage> SlotMemory<M, S> {
+    /// Wrap `mem`, with slots defined by `layout` and status managed by `manager`.
+    ///
+    /// Panics if `layout.page_size` is `0`.
+    pub fn new(mem: M, manager: SlotManager<S>, layout: SlotLayout) -> Self {
+        assert!(layout.page_size > 0, "SlotLayout page size is 0");
+        Self {
+            mem,
+            manager,
+            layout,
+            programmed: false,
+        }
+    }
+
+    /// Returns a reference to the slot manager.
+    pub fn manager(&mut self) -> &mut SlotManager<S> {
+        &mut self.manager
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory and the slot manager.
+    pub fn release(self) -> (M, SlotManager<S>) {
+        (self.mem, self.manager)
+    }
+
+    /// Translate DFU address of `length` bytes to the inactive slot.
+    fn translate(&mut self, address: u32, length: usize) -> Result<u32, DfuMemoryError> {
+        let offset = address
+            .checked_sub(M::INITIAL_ADDRESS_POINTER)
+            .ok_or(DfuMemoryError::Address)?;
+        match offset.checked_add(length as u32) {
+            Some(end) if end <= self.layout.size => {}
+            _ => return Err(DfuMemoryError::Address),
+        }
+        let status = self.manager.status().map_err(|_| DfuMemoryError::Unknown)?;
+        Ok(self.layout.address(status.active.other()) + offset)
+    }
+}
+
+impl<M: DfuMemory, S: SlotStorage> DfuMemory for SlotMemory<M, S> {
+    forward_dfu_memory!(
+        mem: M,
+        except SKIP_IDENTICAL, read, read_block, compare, program, erase, erase_all, erase_range,
+        manifestation, download_start, resume_point
+    );
+
+    // the first program() clears the pending and trial flags of the slot
+    const SKIP_IDENTICAL: bool = false;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let address = self.translate(address, length)?;
+        self.mem.read(address, length)
+    }
+
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        let address = self.translate(address, length)?;
+        self.mem.read_block(address, length)
+    }
+
+    fn compare(&mut self, address: u32, length: usize) -> Result<bool, DfuMemoryError> {
+        let address = self.translate(address, length)?;
+        self.mem.compare(address, length)
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let address = self.translate(address, length)?;
+        if !self.programmed {
+            self.manager
+                .update(|status| {
+                    status.pending = false;
+                    status.trial = false;
+                })
+                .map_err(|_| DfuMemoryError::Unknown)?;
+            self.programmed = true;
+        }
+        self.mem.program(address, length)
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        let address = self.translate(address, 1)?;
+        self.mem.erase(address)
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        let mut offset = 0;
+        while offset < self.layout.size {
+            let address = self.translate(M::INITIAL_ADDRESS_POINTER + offset, 1)?;
+            self.mem.erase(address)?;
+            offset = offset.saturating_add(self.layout.page_size);
+        }
+        Ok(())
+    }
+
+    fn erase_range(&mut self, address: u32, length: u32) -> Result<(), DfuMemoryError> {
+        let address = self.translate(address, length as usize)?;
+        self.mem.erase_range(address, length)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.mem.manifestation()?;
+        self.programmed = false;
+        self.manager
+            .update(|status| status.pending = true)
+            .map(|_| ())
+            .map_err(|_| DfuManifestationError::Unknown)
+    }
+
+    fn download_start(&mut self) {
+        self.programmed = false;
+        self.mem.download_start()
+    }
+
+    fn resume_point(&mut self) -> Option<u32> {
+        // translate back from the inactive slot
+        let address = self.mem.resume_point()?;
+        let status = self.manager.status().ok()?;
+        let offset = address.checked_sub(self.layout.address(status.active.other()))?;
+        (offset <= self.layout.size).then(|| M::INITIAL_ADDRESS_POINTER + offset)
+    }
+}
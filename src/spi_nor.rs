@@ -0,0 +1,272 @@
+//! SPI NOR flash
+//!
+//! [`SpiNorFlash`] is a [`NorFlash`] for a JEDEC SPI NOR flash, e.g. Winbond W25Q,
+//! Macronix MX25, or GigaDevice GD25, connected to an `embedded-hal` [`SpiDevice`].
+//! It uses standard commands with 3-byte addresses: *Read Data* (`03h`),
+//! *Write Enable* (`06h`), *Page Program* (`02h`), *Sector Erase* (`20h`), and
+//! polls *Write In Progress* bit of *Status Register-1* (`05h`) until the chip is ready,
+//! at most [`STATUS_POLLS`] times. Flash of up to 16 MiB is supported.
+//!
+//! [`SpiNorMemory`] is a [`FlashMemory`] with [`SpiNor`] layout for a region
+//! of the flash described by [`SpiNorRegion`], e.g. to stage an update in external
+//! flash. DFU addresses are flash addresses.
+//!
+//! Requires `spi-nor` feature.
+//!
+//! ```no_run
+//! # use embedded_hal::spi::SpiDevice;
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::spi_nor::*;
+//! # use usbd_dfu::DfuClass;
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, spi_device: impl SpiDevice) {
+//! struct Staging;
+//!
+//! impl SpiNorRegion for Staging {
+//!     const START: u32 = 0x0010_0000;
+//!     const END: u32 = 0x0020_0000;
+//! }
+//!
+//! // 16 Mbit flash
+//! let flash = SpiNorFlash::new(spi_device, 2 * 1024 * 1024);
+//! let mem = SpiNorMemory::<_, Staging, 1024>::new(flash, 0);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use core::marker::PhantomData;
+
+use embedded_hal::spi::{Operation, SpiDevice};
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::flash::{FlashLayout, FlashMemory};
+use crate::mem_info::MemInfo;
+
+/// Erase sector size.
+pub const SECTOR_SIZE: u32 = 4096;
+
+/// Program page size.
+pub const PAGE_SIZE: u32 = 256;
+
+/// Maximum sector erase time of common SPI NOR flash chips.
+pub const SECTOR_ERASE_TIME_MS: u32 = 400;
+
+/// Maximum time to program 1 KiB, 4 pages of 3 ms.
+pub const KIB_PROGRAM_TIME_MS: u32 = 12;
+
+/// Maximum number of *Status Register-1* reads while waiting for the chip.
+///
+/// A read is at least 16 SPI clocks, 0.1 µs at 160 MHz, so a sector erase of
+/// [`SECTOR_ERASE_TIME_MS`] is complete before the polls are used up.
+pub const STATUS_POLLS: u32 = SECTOR_ERASE_TIME_MS * 10_000;
+
+/// *Read Data* command.
+pub const CMD_READ: u8 = 0x03;
+
+/// *Page Program* command.
+pub const CMD_PAGE_PROGRAM: u8 = 0x02;
+
+/// *Write Enable* command.
+pub const CMD_WRITE_ENABLE: u8 = 0x06;
+
+/// *Read Status Register-1* command.
+pub const CMD_READ_STATUS: u8 = 0x05;
+
+/// *Sector Erase* command.
+pub const CMD_SECTOR_ERASE: u8 = 0x20;
+
+/// *Write In Progress* bit of *Status Register-1*.
+pub const STATUS_WIP: u8 = 0x01;
+
+/// Flash size addressed by 3-byte addresses.
+const MAX_CAPACITY: usize = 16 * 1024 * 1024;
+
+/// Maximum length of generated memory info strings.
+const MEM_INFO_LENGTH: usize = 64;
+
+/// Errors of [`SpiNorFlash`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum SpiNorError<E> {
+    /// SPI transfer failed.
+    Spi(E),
+    /// Erase range is not aligned to sectors.
+    NotAligned,
+    /// Offset or length is beyond the flash capacity.
+    OutOfBounds,
+    /// Chip is still busy after [`STATUS_POLLS`] status reads.
+    Timeout,
+}
+
+impl<E: core::fmt::Debug> NorFlashError for SpiNorError<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            SpiNorError::Spi(_) | SpiNorError::Timeout => NorFlashErrorKind::Other,
+            SpiNorError::NotAligned => NorFlashErrorKind::NotAligned,
+            SpiNorError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+        }
+    }
+}
+
+/// [`NorFlash`] for a JEDEC SPI NOR flash.
+pub struct SpiNorFlash<S: SpiDevice> {
+    spi: S,
+    capacity: usize,
+}
+
+fn command(cmd: u8, address: u32) -> [u8; 4] {
+    let a = address.to_be_bytes();
+    [cmd, a[1], a[2], a[3]]
+}
+
+impl<S: SpiDevice> SpiNorFlash<S> {
+    /// Access flash of `capacity` bytes with `spi`.
+    ///
+    /// Panics if `capacity` is over 16 MiB, the limit of 3-byte addresses.
+    pub fn new(spi: S, capacity: usize) -> Self {
+        assert!(
+            capacity <= MAX_CAPACITY,
+            "SpiNorFlash capacity is over 16 MiB"
+        );
+        Self { spi, capacity }
+    }
+
+    /// Destroy the flash and return the SPI device.
+    pub fn release(self) -> S {
+        self.spi
+    }
+
+    /// Read *Status Register-1*.
+    pub fn read_status(&mut self) -> Result<u8, SpiNorError<S::Error>> {
+        let mut status = [0];
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[CMD_READ_STATUS]),
+                Operation::Read(&mut status),
+            ])
+            .map_err(SpiNorError::Spi)?;
+        Ok(status[0])
+    }
+
+    /// Poll *Write In Progress* bit until the erase or program operation is complete.
+    pub fn wait_ready(&mut self) -> Result<(), SpiNorError<S::Error>> {
+        for _ in 0..STATUS_POLLS {
+            if self.read_status()? & STATUS_WIP == 0 {
+                return Ok(());
+            }
+        }
+        Err(SpiNorError::Timeout)
+    }
+
+    fn check(&self, offset: u32, length: usize) -> Result<(), SpiNorError<S::Error>> {
+        match (offset as usize).checked_add(length) {
+            Some(end) if end <= self.capacity => Ok(()),
+            _ => Err(SpiNorError::OutOfBounds),
+        }
+    }
+
+    /// Send write enable, then `ops`, and wait until the chip is ready.
+    fn modify(&mut self, ops: &mut [Operation<'_, u8>]) -> Result<(), SpiNorError<S::Error>> {
+        self.spi
+            .write(&[CMD_WRITE_ENABLE])
+            .map_err(SpiNorError::Spi)?;
+        self.spi.transaction(ops).map_err(SpiNorError::Spi)?;
+        self.wait_ready()
+    }
+}
+
+impl<S: SpiDevice> ErrorType for SpiNorFlash<S> {
+    type Error = SpiNorError<S::Error>;
+}
+
+impl<S: SpiDevice> ReadNorFlash for SpiNorFlash<S> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len())?;
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&command(CMD_READ, offset)),
+                Operation::Read(bytes),
+            ])
+            .map_err(SpiNorError::Spi)
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<S: SpiDevice> NorFlash for SpiNorFlash<S> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = SECTOR_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from > to || !from.is_multiple_of(SECTOR_SIZE) || !to.is_multiple_of(SECTOR_SIZE) {
+            return Err(SpiNorError::NotAligned);
+        }
+        self.check(from, (to - from) as usize)?;
+
+        for sector in (from..to).step_by(SECTOR_SIZE as usize) {
+            self.modify(&mut [Operation::Write(&command(CMD_SECTOR_ERASE, sector))])?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len())?;
+
+        // page program wraps around at the page end
+        let mut offset = offset;
+        let mut bytes = bytes;
+        while !bytes.is_empty() {
+            let page_left = (PAGE_SIZE - offset % PAGE_SIZE) as usize;
+            let (chunk, rest) = bytes.split_at(page_left.min(bytes.len()));
+            self.modify(&mut [
+                Operation::Write(&command(CMD_PAGE_PROGRAM, offset)),
+                Operation::Write(chunk),
+            ])?;
+            offset += chunk.len() as u32;
+            bytes = rest;
+        }
+        Ok(())
+    }
+}
+
+/// Region of SPI NOR flash available for downloads.
+///
+/// Addresses are flash addresses, and must be sector aligned.
+pub trait SpiNorRegion {
+    /// First address of the region.
+    const START: u32;
+
+    /// End address of the region, exclusive.
+    const END: u32;
+}
+
+/// [`FlashLayout`] of SPI NOR flash with 4 KiB sectors.
+pub struct SpiNor<R: SpiNorRegion> {
+    _region: PhantomData<R>,
+}
+
+impl<R: SpiNorRegion> FlashLayout for SpiNor<R> {
+    const START: u32 = R::START;
+    const END: u32 = R::END;
+    const MEM_INFO_STRING: &'static str = MemInfo::<MEM_INFO_LENGTH>::new("SPI Flash", R::START)
+        .area((R::END - R::START) / SECTOR_SIZE, SECTOR_SIZE, 'g')
+        .as_str();
+    const PROGRAM_TIME_MS: u32 = KIB_PROGRAM_TIME_MS;
+    const ERASE_TIME_MS: u32 = SECTOR_ERASE_TIME_MS;
+    const FULL_ERASE_TIME_MS: u32 = SECTOR_ERASE_TIME_MS * ((R::END - R::START) / SECTOR_SIZE);
+
+    fn page(address: u32) -> Option<(u32, u32)> {
+        if !(R::START..R::END).contains(&address) {
+            return None;
+        }
+        Some((address - address % SECTOR_SIZE, SECTOR_SIZE))
+    }
+}
+
+/// [`DfuMemory`](crate::DfuMemory) implementation for a region `R` of SPI NOR flash.
+pub type SpiNorMemory<S, R, const N: usize> = FlashMemory<SpiNorFlash<S>, SpiNor<R>, N>;
@@ -0,0 +1,476 @@
+//! Split [`DfuClass`](crate::DfuClass) into a control half and a worker half.
+//!
+//! Erase, program, and manifestation operations may take a long time.
+//! [`DfuClass`](crate::DfuClass) executes them from `usb_dev.poll([])`, usually
+//! in USB interrupt context. With [`DfuClass::split()`](crate::DfuClass::split),
+//! USB requests are handled by [`DfuControl`], which implements `UsbClass`,
+//! while [`DfuMemory`] is owned by [`DfuWorker`], which may live in a lower
+//! priority task. Both halves are connected by a lock-free [`DfuChannel`].
+//!
+//! While the worker is busy, the host receives `dfuDNBUSY` or `dfuMANIFEST`
+//! state in `DFU_GETSTATUS` replies and waits for *bwPollTimeout* before asking again.
+//!
+//! Data blocks received from the host are copied by [`DfuControl`] to its own
+//! buffer of `N` bytes, and [`DfuMemory::store_write_buffer()`] is called by the worker
+//! just before [`DfuMemory::program()`]. `N` must not be less than
+//! [`DfuMemory::TRANSFER_SIZE`].
+//!
+//! ### Limitations
+//!
+//! * Upload (device to host) is not supported: `usb-device` requires data for
+//!   the control transfer to be ready before `usb_dev.poll([])` returns. *bitCanUpload*
+//!   is cleared in DFU Functional descriptor.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usb_device::device::UsbDevice;
+//! # use usbd_dfu::*;
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: impl DfuMemory, mut usb_dev: UsbDevice<B>) {
+//! static mut CHANNEL: DfuChannel<128> = DfuChannel::new();
+//!
+//! let dfu = DfuClass::new(&usb_bus_alloc, my_mem);
+//! let (mut control, mut worker) = dfu.split(unsafe { &mut CHANNEL });
+//!
+//! // USB interrupt
+//! usb_dev.poll(&mut [&mut control]);
+//!
+//! // low priority task
+//! worker.update();
+//! # }
+//! ```
+
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, Ordering};
+use heapless::spsc::{Consumer, Producer, Queue};
+use heapless::Vec;
+use usb_device::{class_prelude::*, control::Request};
+
+use crate::class::{
+    get_string, has_alt_setting, is_dfu_request, start_download, write_descriptors, AltStrings,
+    BlockSizes, DFUStatus, DfuMemory, DfuStatusCode, MemoryErrorDetail, Operation, OperationError,
+};
+
+#[cfg(feature = "download")]
+use crate::consts::DFU_DNLOAD;
+#[cfg(feature = "upload")]
+use crate::consts::DFU_UPLOAD;
+use crate::consts::{DFU_ABORT, DFU_CLRSTATUS, DFU_GETSTATE, DFU_GETSTATUS};
+use crate::status::ExtendedStatus;
+
+/// Operation sent from [`DfuControl`] to [`DfuWorker`].
+struct Job<const N: usize> {
+    op: Operation,
+    data: Vec<u8, N>,
+    download_start: bool,
+    /// Selected alternate setting.
+    alt_setting: u8,
+    /// See [`DFUStatus::take_block_size_mismatch()`].
+    block_size_mismatch: Option<(u16, u16)>,
+}
+
+type JobResult = Result<(), OperationError>;
+
+/// Lock-free channel between [`DfuControl`] and [`DfuWorker`].
+///
+/// `N` is the size of a data block buffer, it must not be less than
+/// [`DfuMemory::TRANSFER_SIZE`].
+pub struct DfuChannel<const N: usize> {
+    // single-producer single-consumer queue of size 2 holds one element
+    jobs: Queue<Job<N>, 2>,
+    results: Queue<JobResult, 2>,
+    usb_reset: AtomicBool,
+    session_timeout: AtomicBool,
+}
+
+impl<const N: usize> DfuChannel<N> {
+    /// Creates a new empty [`DfuChannel`].
+    pub const fn new() -> Self {
+        Self {
+            jobs: Queue::new(),
+            results: Queue::new(),
+            usb_reset: AtomicBool::new(false),
+            session_timeout: AtomicBool::new(false),
+        }
+    }
+}
+
+impl<const N: usize> Default for DfuChannel<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// USB half of a split [`DfuClass`](crate::DfuClass).
+///
+/// Handles DFU requests and sends memory operations to [`DfuWorker`].
+pub struct DfuControl<'a, B: UsbBus, M: DfuMemory, const N: usize> {
+    if_num: InterfaceNumber,
+    status: DFUStatus,
+    interface_string: Option<StringIndex>,
+    alt_strings: AltStrings,
+    buffer: Vec<u8, N>,
+    jobs: Producer<'a, Job<N>, 2>,
+    results: Consumer<'a, JobResult, 2>,
+    usb_reset: &'a AtomicBool,
+    session_timeout: &'a AtomicBool,
+    _bus: PhantomData<B>,
+    _mem: PhantomData<M>,
+}
+
+/// Memory half of a split [`DfuClass`](crate::DfuClass).
+///
+/// Executes memory operations requested by [`DfuControl`].
+pub struct DfuWorker<'a, M: DfuMemory, const N: usize> {
+    mem: M,
+    jobs: Consumer<'a, Job<N>, 2>,
+    results: Producer<'a, JobResult, 2>,
+    usb_reset: &'a AtomicBool,
+    session_timeout: &'a AtomicBool,
+    /// Alternate setting of the last job, see [`DfuMemory::select_alt_setting()`].
+    alt_setting: u8,
+}
+
+pub(crate) fn split<B: UsbBus, M: DfuMemory, const N: usize>(
+    if_num: InterfaceNumber,
+    interface_string: Option<StringIndex>,
+    alt_strings: AltStrings,
+    status: DFUStatus,
+    mem: M,
+    channel: &mut DfuChannel<N>,
+) -> (DfuControl<'_, B, M, N>, DfuWorker<'_, M, N>) {
+    assert!(
+        N >= M::TRANSFER_SIZE as usize,
+        "DfuChannel buffer is smaller than TRANSFER_SIZE"
+    );
+
+    let DfuChannel {
+        jobs,
+        results,
+        usb_reset,
+        session_timeout,
+    } = channel;
+    let usb_reset = &*usb_reset;
+    let session_timeout = &*session_timeout;
+    let (jobs_tx, jobs_rx) = jobs.split();
+    let (results_tx, results_rx) = results.split();
+    let alt_setting = status.alt_setting();
+
+    (
+        DfuControl {
+            if_num,
+            status,
+            interface_string,
+            alt_strings,
+            buffer: Vec::new(),
+            jobs: jobs_tx,
+            results: results_rx,
+            usb_reset,
+            session_timeout,
+            _bus: PhantomData,
+            _mem: PhantomData,
+        },
+        DfuWorker {
+            mem,
+            jobs: jobs_rx,
+            results: results_tx,
+            usb_reset,
+            session_timeout,
+            alt_setting,
+        },
+    )
+}
+
+impl<B: UsbBus, M: DfuMemory, const N: usize> DfuControl<'_, B, M, N> {
+    /// Return current Address Pointer value.
+    pub fn get_address_pointer(&self) -> u32 {
+        self.status.address_pointer()
+    }
+
+    /// Set Address Pointer, see [`DfuClass::set_address_pointer()`](crate::DfuClass::set_address_pointer).
+    pub fn set_address_pointer(&mut self, address: u32) -> Result<(), DfuStatusCode> {
+        self.status.set_address_pointer(address)
+    }
+
+    /// Returns the number of DFU interface.
+    pub fn get_interface_number(&self) -> InterfaceNumber {
+        self.if_num
+    }
+
+    /// Returns the last failed [`DfuMemory`] call, if any.
+    pub fn last_memory_error(&self) -> Option<MemoryErrorDetail> {
+        self.status.last_memory_error()
+    }
+
+    /// Returns the smallest and largest data block of the current or the last download.
+    pub fn download_block_sizes(&self) -> Option<BlockSizes> {
+        self.status.download_block_sizes()
+    }
+
+    /// Returns the received part of the image in percent, if its length was announced.
+    pub fn download_progress(&self) -> Option<u8> {
+        self.status.download_progress()
+    }
+
+    /// Returns the reply of *Get Extended Status* request, see
+    /// [`DfuMemory::EXTENDED_STATUS_REQUEST`].
+    pub fn extended_status(&self) -> ExtendedStatus {
+        self.status.extended_status()
+    }
+
+    /// Returns `true` if the host polled the status since the last call.
+    pub fn take_host_poll(&mut self) -> bool {
+        self.status.take_host_poll()
+    }
+
+    /// Returns `true` if the host issued a DfuSe command since the last USB reset,
+    /// see [`DfuClass::dfuse_used()`](crate::DfuClass::dfuse_used).
+    pub fn dfuse_used(&self) -> bool {
+        self.status.dfuse_used()
+    }
+
+    /// Report `elapsed_ms` milliseconds since the last call, see [`DfuClass::tick()`](crate::DfuClass::tick).
+    ///
+    /// [`DfuMemory::session_timeout()`] is called by [`DfuWorker::update()`].
+    pub fn tick(&mut self, elapsed_ms: u32) -> bool {
+        // collect the result of a completed operation first
+        self.update();
+        let timeout = self.status.tick(elapsed_ms);
+        if timeout {
+            self.session_timeout.store(true, Ordering::Release);
+        }
+        timeout
+    }
+
+    /// Cancel the session and go to `dfuIDLE`, see [`DfuClass::force_idle()`](crate::DfuClass::force_idle).
+    ///
+    /// An operation that is already running in [`DfuWorker`] is not interrupted, its result
+    /// is dropped. [`DfuMemory::session_timeout()`] is called by [`DfuWorker::update()`].
+    pub fn force_idle(&mut self) {
+        if self.status.force_idle() {
+            self.session_timeout.store(true, Ordering::Release);
+        }
+    }
+
+    /// Returns the last state transitions, see [`trace`](crate::trace).
+    #[cfg(feature = "trace")]
+    pub fn trace(&self) -> &crate::trace::Trace {
+        self.status.trace()
+    }
+
+    /// Forget recorded state transitions.
+    #[cfg(feature = "trace")]
+    pub fn clear_trace(&mut self) {
+        self.status.clear_trace();
+    }
+
+    /// Returns session counters, see [`stats`](crate::stats).
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &crate::stats::DfuStats {
+        self.status.stats()
+    }
+
+    /// Reset session counters to zero.
+    #[cfg(feature = "stats")]
+    pub fn reset_stats(&mut self) {
+        self.status.reset_stats();
+    }
+
+    /// Collect the result of a completed operation and send the next one to the worker.
+    fn update(&mut self) {
+        if let Some(result) = self.results.dequeue() {
+            self.status.complete(result);
+        }
+
+        if let Some(op) = self.status.next_operation() {
+            let data = match op {
+                Operation::Program { .. } => self.buffer.clone(),
+                // payload came in a data block, so it fits
+                Operation::Unlock | Operation::SetParameters => {
+                    Vec::from_slice(self.status.payload()).unwrap_or_default()
+                }
+                _ => Vec::new(),
+            };
+            let download_start = self.status.take_download_start(&op);
+            let block_size_mismatch = self.status.take_block_size_mismatch();
+            // only one operation is in progress, so there is always room in the queue
+            self.jobs
+                .enqueue(Job {
+                    op,
+                    data,
+                    download_start,
+                    alt_setting: self.status.alt_setting(),
+                    block_size_mismatch,
+                })
+                .ok();
+        }
+    }
+}
+
+impl<B: UsbBus, M: DfuMemory, const N: usize> UsbClass<B> for DfuControl<'_, B, M, N> {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        write_descriptors::<M>(
+            writer,
+            self.if_num,
+            self.interface_string,
+            &self.alt_strings,
+            false,
+        )
+    }
+
+    fn get_string(&self, index: StringIndex, _lang_id: LangID) -> Option<&str> {
+        get_string::<M>(index, self.interface_string, &self.alt_strings)
+    }
+
+    fn get_alt_setting(&mut self, interface: InterfaceNumber) -> Option<u8> {
+        (interface == self.if_num).then_some(self.status.alt_setting())
+    }
+
+    fn set_alt_setting(&mut self, interface: InterfaceNumber, alternative: u8) -> bool {
+        interface == self.if_num
+            && has_alt_setting::<M>(alternative)
+            && self.status.set_alt_setting(alternative)
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req: Request = *xfer.request();
+
+        if self.status.is_extended_status_request(&req, self.if_num) {
+            match self.status.get_extended_status(&req) {
+                Some(v) => xfer.accept_with(&v).ok(),
+                None => xfer.reject().ok(),
+            };
+            return;
+        }
+
+        if !is_dfu_request(&req, self.if_num) {
+            return;
+        }
+
+        match req.request {
+            #[cfg(feature = "upload")]
+            DFU_UPLOAD if req.value == 0 => {
+                match self
+                    .status
+                    .upload(&req, |_, _| Ok(crate::class::ReadOutcome::LastChunk(&[])))
+                {
+                    Some(data) => xfer.accept_with(data).ok(),
+                    None => xfer.reject().ok(),
+                };
+            }
+            DFU_GETSTATUS => {
+                self.update();
+                match self.status.get_status(&req) {
+                    Some(v) => xfer.accept_with(&v).ok(),
+                    None => xfer.reject().ok(),
+                };
+            }
+            DFU_GETSTATE => {
+                match self.status.get_state(&req) {
+                    Some(v) => xfer.accept_with(&[v]).ok(),
+                    None => xfer.reject().ok(),
+                };
+            }
+            _ => {
+                self.status.stall();
+                xfer.reject().ok();
+            }
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+
+        if !is_dfu_request(&req, self.if_num) {
+            return;
+        }
+
+        let accepted = match req.request {
+            #[cfg(feature = "download")]
+            DFU_DNLOAD => {
+                let buffer = &mut self.buffer;
+                self.status.download(&req, xfer.data(), &mut |data| {
+                    buffer.clear();
+                    buffer.extend_from_slice(data)
+                })
+            }
+            DFU_CLRSTATUS => self.status.clear_status(),
+            DFU_ABORT => self.status.abort(),
+            _ => false,
+        };
+
+        if accepted {
+            xfer.accept().ok();
+        } else {
+            xfer.reject().ok();
+        }
+    }
+
+    fn reset(&mut self) {
+        self.usb_reset.store(true, Ordering::Release);
+        self.status.usb_reset();
+    }
+
+    fn poll(&mut self) {
+        self.update();
+    }
+}
+
+impl<M: DfuMemory, const N: usize> DfuWorker<'_, M, N> {
+    /// Returns `true` if there is an operation waiting for [`update()`](DfuWorker::update).
+    pub fn is_pending(&self) -> bool {
+        self.jobs.ready()
+            || self.usb_reset.load(Ordering::Acquire)
+            || self.session_timeout.load(Ordering::Acquire)
+    }
+
+    /// Execute a pending memory operation, if any.
+    ///
+    /// Calls [`DfuMemory::usb_reset()`] if USB reset was detected by [`DfuControl`],
+    /// and [`DfuMemory::session_timeout()`] if the session timed out.
+    pub fn update(&mut self) {
+        if self.usb_reset.load(Ordering::Acquire) {
+            self.usb_reset.store(false, Ordering::Release);
+            // may not return
+            self.mem.usb_reset();
+            if self.alt_setting != 0 {
+                self.alt_setting = 0;
+                self.mem.select_alt_setting(0);
+            }
+        }
+        if self.session_timeout.load(Ordering::Acquire) {
+            self.session_timeout.store(false, Ordering::Release);
+            self.mem.session_timeout();
+        }
+
+        while let Some(job) = self.jobs.dequeue() {
+            if job.alt_setting != self.alt_setting {
+                self.alt_setting = job.alt_setting;
+                self.mem.select_alt_setting(job.alt_setting);
+            }
+            let start = match job.download_start {
+                true => start_download(&mut self.mem),
+                false => Ok(()),
+            };
+            if let Some((block_num, length)) = job.block_size_mismatch {
+                self.mem.block_size_mismatch(block_num, length);
+            }
+            let result = start.and_then(|_| match job.op {
+                Operation::Program { .. } => match self.mem.store_write_buffer(&job.data) {
+                    Ok(_) => job.op.execute(&mut self.mem, &[]),
+                    Err(_) => Err(DfuStatusCode::ErrUnknown.into()),
+                },
+                _ => job.op.execute(&mut self.mem, &job.data),
+            });
+            // DfuControl sends a new job only after the result is received
+            self.results.enqueue(result).ok();
+        }
+    }
+
+    /// Returns a reference to the memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+}
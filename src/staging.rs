@@ -0,0 +1,266 @@
+//! Active and staging firmware alternate settings
+//!
+//! A common layout keeps the running firmware in an *active* region, and a new image
+//! is downloaded to a *staging* region of the same flash, then copied over the active
+//! one. [`StagingMemory`] wraps [`DfuMemory`] of the whole flash and exposes both regions
+//! as [`ALT_SETTINGS`](DfuMemory::ALT_SETTINGS) with the addresses of the active region:
+//!
+//! * alternate setting `0`, *Active*, can only be read, e.g. to verify the running firmware,
+//! * alternate setting `1`, *Staging*, is where downloads land, it can be read back too.
+//!
+//! Manifestation promotes the staged image with [`StagingLayout::promote()`], by default
+//! the pages of the active region are erased and the image is copied to it.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::staging::*;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_flash_mem: impl DfuMemory) {
+//! struct Layout;
+//!
+//! impl StagingLayout for Layout {
+//!     const ACTIVE: &'static str = "@Active/0x08008000/28*2Ka";
+//!     const STAGING: &'static str = "@Staging/0x08008000/28*2Kg";
+//!     const ACTIVE_ADDRESS: u32 = 0x0800_8000;
+//!     const STAGING_ADDRESS: u32 = 0x0804_0000;
+//!     const SIZE: u32 = 56 * 1024;
+//!     const PAGE_SIZE: u32 = 2048;
+//! }
+//!
+//! let mem = StagingMemory::<_, Layout>::new(my_flash_mem);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use core::marker::PhantomData;
+
+use crate::class::{AltSetting, DfuManifestationError, DfuMemory, DfuMemoryError, ReadOutcome};
+
+/// Alternate setting of the active region.
+pub const ALT_ACTIVE: u8 = 0;
+/// Alternate setting of the staging region.
+pub const ALT_STAGING: u8 = 1;
+
+/// Maximum length of a chunk copied by [`StagingLayout::promote()`].
+const PROMOTE_CHUNK: usize = 256;
+
+/// Regions of a [`StagingMemory`] and promotion of a staged image.
+pub trait StagingLayout {
+    /// Memory map of the active region, *iInterface* of alternate setting `0`,
+    /// e.g. `"@Active/0x08008000/28*2Ka"`.
+    const ACTIVE: &'static str;
+
+    /// Memory map of the staging region, *iInterface* of alternate setting `1`,
+    /// with the addresses of the active region, e.g. `"@Staging/0x08008000/28*2Kg"`.
+    const STAGING: &'static str;
+
+    /// Address of the active region, it's the initial address pointer.
+    const ACTIVE_ADDRESS: u32;
+
+    /// Address of the staging region in the wrapped memory.
+    const STAGING_ADDRESS: u32;
+
+    /// Size of each region in bytes.
+    const SIZE: u32;
+
+    /// Size of the erase page in bytes.
+    const PAGE_SIZE: u32;
+
+    /// Time in milliseconds of [`promote()`](StagingLayout::promote) of a full region,
+    /// added to [`MANIFESTATION_TIME_MS`](DfuMemory::MANIFESTATION_TIME_MS). Default is `0`.
+    const PROMOTE_TIME_MS: u32 = 0;
+
+    /// Promote `length` bytes of the staged image to the active region.
+    ///
+    /// Default implementation erases the pages of the active region and copies the image
+    /// in chunks of at most [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE) bytes.
+    /// Override it to leave the copy to a bootloader, e.g. by writing a flag.
+    fn promote<M: DfuMemory>(mem: &mut M, length: u32) -> Result<(), DfuMemoryError> {
+        let mut offset = 0;
+        while offset < length {
+            mem.erase(Self::ACTIVE_ADDRESS + offset)?;
+            offset = offset.saturating_add(Self::PAGE_SIZE);
+        }
+
+        let chunk = PROMOTE_CHUNK.min(M::TRANSFER_SIZE as usize);
+        let mut buffer = [0; PROMOTE_CHUNK];
+        let mut offset = 0;
+        while offset < length {
+            let len = chunk.min((length - offset) as usize);
+            let data = mem.read(Self::STAGING_ADDRESS + offset, len)?;
+            if data.len() != len {
+                return Err(DfuMemoryError::Address);
+            }
+            buffer[..len].copy_from_slice(data);
+            mem.store_write_buffer(&buffer[..len])
+                .map_err(|_| DfuMemoryError::Unknown)?;
+            mem.program(Self::ACTIVE_ADDRESS + offset, len)?;
+            offset += len as u32;
+        }
+        Ok(())
+    }
+}
+
+/// [`DfuMemory`] adapter with active and staging alternate settings, see
+/// [`staging`](crate::staging) module.
+///
+/// DFU addresses are addresses of the active region. Reads in alternate setting
+/// [`ALT_ACTIVE`] access the active region, reads in [`ALT_STAGING`], erases and programs
+/// access the staging region at the same offset. Addresses outside of the region fail
+/// with `errADDRESS`. Mass erase erases only the staging region, page by page.
+///
+/// After a download, [`StagingLayout::promote()`] is called before the wrapped memory
+/// [`manifestation()`](DfuMemory::manifestation), a failure is reported as `errFIRMWARE`.
+pub struct StagingMemory<M: DfuMemory, L: StagingLayout> {
+    mem: M,
+    /// Selected alternate setting.
+    alt: u8,
+    /// End offset of the programmed data since the download start.
+    staged: u32,
+    _layout: PhantomData<L>,
+}
+
+impl<M: DfuMemory, L: StagingLayout> StagingMemory<M, L> {
+    /// Wrap `mem`, with regions defined by `L`.
+    pub fn new(mem: M) -> Self {
+        const { assert!(L::PAGE_SIZE > 0, "StagingLayout page size is 0") };
+
+        Self {
+            mem,
+            alt: ALT_ACTIVE,
+            staged: 0,
+            _layout: PhantomData,
+        }
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    /// Returns the offset of DFU address of `length` bytes in the region.
+    fn offset(address: u32, length: usize) -> Result<u32, DfuMemoryError> {
+        let offset = address
+            .checked_sub(L::ACTIVE_ADDRESS)
+            .ok_or(DfuMemoryError::Address)?;
+        match offset.checked_add(length as u32) {
+            Some(end) if end <= L::SIZE => Ok(offset),
+            _ => Err(DfuMemoryError::Address),
+        }
+    }
+
+    /// Translate DFU address of `length` bytes to the staging region.
+    fn staging(address: u32, length: usize) -> Result<u32, DfuMemoryError> {
+        Ok(L::STAGING_ADDRESS + Self::offset(address, length)?)
+    }
+
+    /// Translate DFU address of `length` bytes to the region of the selected alternate setting.
+    fn translate(&self, address: u32, length: usize) -> Result<u32, DfuMemoryError> {
+        match self.alt {
+            ALT_STAGING => Self::staging(address, length),
+            _ => Ok(L::ACTIVE_ADDRESS + Self::offset(address, length)?),
+        }
+    }
+}
+
+impl<M: DfuMemory, L: StagingLayout> DfuMemory for StagingMemory<M, L> {
+    forward_dfu_memory!(
+        mem: M,
+        except INITIAL_ADDRESS_POINTER, MEM_INFO_STRING, HAS_INTERFACE_STRING, INTERFACE_NAME,
+        ALT_SETTINGS, HAS_DOWNLOAD, HAS_UPLOAD, MANIFESTATION_TIME_MS, SKIP_IDENTICAL, read,
+        read_block, compare, program, erase, erase_all, erase_range, manifestation, download_start,
+        resume_point, select_alt_setting
+    );
+
+    const INITIAL_ADDRESS_POINTER: u32 = L::ACTIVE_ADDRESS;
+    const MEM_INFO_STRING: &'static str = L::ACTIVE;
+    const HAS_INTERFACE_STRING: bool = true;
+    const ALT_SETTINGS: &'static [AltSetting] = &[
+        AltSetting {
+            name: L::ACTIVE,
+            upload: true,
+            download: false,
+        },
+        AltSetting {
+            name: L::STAGING,
+            upload: true,
+            download: true,
+        },
+    ];
+    const MANIFESTATION_TIME_MS: u32 = M::MANIFESTATION_TIME_MS + L::PROMOTE_TIME_MS;
+
+    // program() extends the staged length that manifestation promotes
+    const SKIP_IDENTICAL: bool = false;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let address = self.translate(address, length)?;
+        self.mem.read(address, length)
+    }
+
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        let address = self.translate(address, length)?;
+        self.mem.read_block(address, length)
+    }
+
+    fn compare(&mut self, address: u32, length: usize) -> Result<bool, DfuMemoryError> {
+        let address = self.translate(address, length)?;
+        self.mem.compare(address, length)
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let end = Self::offset(address, length)? + length as u32;
+        self.mem.program(Self::staging(address, length)?, length)?;
+        self.staged = self.staged.max(end);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        self.mem.erase(Self::staging(address, 1)?)
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        let mut offset = 0;
+        while offset < L::SIZE {
+            self.mem.erase(L::STAGING_ADDRESS + offset)?;
+            offset = offset.saturating_add(L::PAGE_SIZE);
+        }
+        Ok(())
+    }
+
+    fn erase_range(&mut self, address: u32, length: u32) -> Result<(), DfuMemoryError> {
+        let address = Self::staging(address, length as usize)?;
+        self.mem.erase_range(address, length)
+    }
+
+    fn select_alt_setting(&mut self, alt: u8) {
+        self.alt = alt;
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        if self.staged > 0 {
+            L::promote(&mut self.mem, self.staged).map_err(|_| DfuManifestationError::Firmware)?;
+            self.staged = 0;
+        }
+        self.mem.manifestation()
+    }
+
+    fn download_start(&mut self) {
+        self.staged = 0;
+        self.mem.download_start()
+    }
+
+    fn resume_point(&mut self) -> Option<u32> {
+        // translate back from the staging region
+        let offset = self.mem.resume_point()?.checked_sub(L::STAGING_ADDRESS)?;
+        (offset <= L::SIZE).then(|| L::ACTIVE_ADDRESS + offset)
+    }
+}
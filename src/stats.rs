@@ -0,0 +1,83 @@
+//! Session statistics
+//!
+//! With `stats` feature, the class counts downloads, programmed blocks,
+//! uploaded bytes, and errors, so production tests and field diagnostics
+//! can report update health:
+//!
+//! ```no_run
+//! # use usb_device::bus::UsbBus;
+//! # use usbd_dfu::*;
+//! # fn example<B: UsbBus, M: DfuMemory>(dfu: &mut DfuClass<B, M>) {
+//! let stats = dfu.stats();
+//! log::info!(
+//!     "{} downloads, {} blocks, {} program errors",
+//!     stats.downloads,
+//!     stats.blocks_programmed,
+//!     stats.errors(DfuStatusCode::ErrProg),
+//! );
+//! dfu.reset_stats();
+//! # }
+//! ```
+//!
+//! Counters saturate at `u32::MAX`.
+
+use crate::class::DfuStatusCode;
+
+/// Number of DFU status codes.
+const STATUS_CODES: usize = DfuStatusCode::ErrStalledPkt as usize + 1;
+
+/// DFU session counters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct DfuStats {
+    /// Downloads started.
+    pub downloads: u32,
+    /// Uploads started.
+    pub uploads: u32,
+    /// Successfully programmed blocks.
+    pub blocks_programmed: u32,
+    /// Bytes of memory sent to the host.
+    pub bytes_uploaded: u32,
+    /// Data blocks received again with the same block number.
+    pub retries: u32,
+    /// Number of errors by status code, see [`errors()`](DfuStats::errors).
+    pub error_counts: [u32; STATUS_CODES],
+}
+
+impl DfuStats {
+    /// Creates zeroed counters.
+    pub const fn new() -> Self {
+        Self {
+            downloads: 0,
+            uploads: 0,
+            blocks_programmed: 0,
+            bytes_uploaded: 0,
+            retries: 0,
+            error_counts: [0; STATUS_CODES],
+        }
+    }
+
+    /// Returns the number of times the device reported `status`.
+    pub fn errors(&self, status: DfuStatusCode) -> u32 {
+        self.error_counts[status as usize]
+    }
+
+    /// Returns the total number of errors.
+    pub fn total_errors(&self) -> u32 {
+        self.error_counts
+            .iter()
+            .fold(0, |a, &c| a.saturating_add(c))
+    }
+
+    pub(crate) fn error(&mut self, status: DfuStatusCode) {
+        if status != DfuStatusCode::Ok {
+            let count = &mut self.error_counts[status as usize];
+            *count = count.saturating_add(1);
+        }
+    }
+}
+
+/// Saturating increment of a counter.
+pub(crate) fn add(counter: &mut u32, n: usize) {
+    *counter = counter.saturating_add(n.try_into().unwrap_or(u32::MAX));
+}
@@ -0,0 +1,155 @@
+//! Extended status
+//!
+//! `DFU_GETSTATUS` reports the state and the status code only, so a host GUI has to
+//! count blocks to show the progress of a download. With
+//! [`DfuMemory::EXTENDED_STATUS_REQUEST`](crate::DfuMemory::EXTENDED_STATUS_REQUEST),
+//! it can read [`ExtendedStatus`] with a vendor-specific request to the DFU interface:
+//!
+//! * *bmRequestType* `0xC1`, device to host, vendor, interface,
+//! * *bRequest* [`VENDOR_GET_EXTENDED_STATUS`](crate::consts::VENDOR_GET_EXTENDED_STATUS),
+//! * *wValue* `0`, *wIndex* is the interface number,
+//! * *wLength* at least [`EXTENDED_STATUS_LENGTH`].
+//!
+//! The request doesn't change the state, so it can be sent at any time, e.g. between
+//! `DFU_GETSTATUS` requests of a download.
+//!
+//! The reply is little-endian:
+//!
+//! | Offset | Size | Field                                                   |
+//! |--------|------|---------------------------------------------------------|
+//! | 0      | 1    | *bStatus*                                               |
+//! | 1      | 1    | *bState*                                                |
+//! | 2      | 1    | Alternate setting                                       |
+//! | 3      | 1    | Progress in percent, `0xFF` if the length is not known  |
+//! | 4      | 4    | Bytes programmed since the start of the download        |
+//! | 8      | 4    | Expected image length, `0` if not known                 |
+//! | 12     | 1    | Status code of the last memory error, `0` if none       |
+//! | 13     | 1    | Bit 0: the address is valid, bit 1: the block is valid  |
+//! | 14     | 2    | Block number of the last memory error                   |
+//! | 16     | 4    | Address of the last memory error                        |
+//!
+//! ```no_run
+//! # use usbd_dfu::status::ExtendedStatus;
+//! # enum Error {
+//! #     Reply,
+//! # }
+//! # struct ProgressBar;
+//! # impl ProgressBar {
+//! #     fn set(&mut self, percent: u8) {}
+//! # }
+//! # fn example(reply: Vec<u8>, progress_bar: &mut ProgressBar) -> Result<(), Error> {
+//! let status = ExtendedStatus::decode(&reply).ok_or(Error::Reply)?;
+//! if let Some(percent) = status.progress() {
+//!     progress_bar.set(percent);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::class::{DfuMemoryError, DfuState, DfuStatusCode, MemoryErrorDetail};
+
+/// Length of encoded [`ExtendedStatus`].
+pub const EXTENDED_STATUS_LENGTH: usize = 20;
+
+/// Progress byte if the image length is not known.
+const PROGRESS_UNKNOWN: u8 = 0xff;
+
+const FLAG_ADDRESS: u8 = 1 << 0;
+const FLAG_BLOCK: u8 = 1 << 1;
+
+/// Reply of the extended status request, see [`status`](crate::status) module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct ExtendedStatus {
+    /// Status code, like *bStatus* of `DFU_GETSTATUS`.
+    pub status: DfuStatusCode,
+    /// State, like *bState* of `DFU_GETSTATUS`.
+    pub state: DfuState,
+    /// Selected alternate setting.
+    pub alt_setting: u8,
+    /// Bytes programmed since the start of the current or the last download.
+    pub programmed: u32,
+    /// Image length announced with *Set Image Size* command or in LMDFU prefix,
+    /// `None` if not known, or after the download ends.
+    pub total: Option<u32>,
+    /// The last failed memory operation, see
+    /// [`DfuClass::last_memory_error()`](crate::DfuClass::last_memory_error).
+    pub last_error: Option<MemoryErrorDetail>,
+}
+
+impl ExtendedStatus {
+    /// Returns the programmed part of the image in percent, `None` if its length is not known.
+    pub fn progress(&self) -> Option<u8> {
+        self.total
+            .map(|total| (self.programmed.min(total) as u64 * 100 / total.max(1) as u64) as u8)
+    }
+
+    /// Encode the reply.
+    pub fn encode(&self) -> [u8; EXTENDED_STATUS_LENGTH] {
+        let mut buf = [0; EXTENDED_STATUS_LENGTH];
+        buf[0] = self.status as u8;
+        buf[1] = self.state as u8;
+        buf[2] = self.alt_setting;
+        buf[3] = self.progress().unwrap_or(PROGRESS_UNKNOWN);
+        buf[4..8].copy_from_slice(&self.programmed.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.total.unwrap_or(0).to_le_bytes());
+        if let Some(e) = self.last_error {
+            buf[12] = DfuStatusCode::from(e.error) as u8;
+            if let Some(block) = e.block {
+                buf[13] |= FLAG_BLOCK;
+                buf[14..16].copy_from_slice(&block.to_le_bytes());
+            }
+            if let Some(address) = e.address {
+                buf[13] |= FLAG_ADDRESS;
+                buf[16..20].copy_from_slice(&address.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    /// Decode a reply, returns `None` if it's too short or a field is not valid.
+    ///
+    /// Bytes after [`EXTENDED_STATUS_LENGTH`] are ignored, they may be added in a later version.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let data = data.first_chunk::<EXTENDED_STATUS_LENGTH>()?;
+        let u32_at =
+            |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let last_error = match data[12] {
+            0 => None,
+            code => Some(MemoryErrorDetail {
+                error: memory_error(DfuStatusCode::try_from(code).ok()?)?,
+                address: (data[13] & FLAG_ADDRESS != 0).then(|| u32_at(16)),
+                block: (data[13] & FLAG_BLOCK != 0)
+                    .then(|| u16::from_le_bytes([data[14], data[15]])),
+            }),
+        };
+        Some(Self {
+            status: DfuStatusCode::try_from(data[0]).ok()?,
+            state: DfuState::try_from(data[1]).ok()?,
+            alt_setting: data[2],
+            programmed: u32_at(4),
+            total: match u32_at(8) {
+                0 => None,
+                total => Some(total),
+            },
+            last_error,
+        })
+    }
+}
+
+/// Returns the memory error reported as `code`.
+fn memory_error(code: DfuStatusCode) -> Option<DfuMemoryError> {
+    Some(match code {
+        DfuStatusCode::ErrTarget => DfuMemoryError::Target,
+        DfuStatusCode::ErrFile => DfuMemoryError::File,
+        DfuStatusCode::ErrWrite => DfuMemoryError::Write,
+        DfuStatusCode::ErrErase => DfuMemoryError::Erase,
+        DfuStatusCode::ErrCheckErased => DfuMemoryError::CheckErased,
+        DfuStatusCode::ErrProg => DfuMemoryError::Prog,
+        DfuStatusCode::ErrVerify => DfuMemoryError::Verify,
+        DfuStatusCode::ErrUnknown => DfuMemoryError::Unknown,
+        DfuStatusCode::ErrAddress => DfuMemoryError::Address,
+        DfuStatusCode::ErrVendor => DfuMemoryError::ErrVendor,
+        _ => return None,
+    })
+}
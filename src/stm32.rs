@@ -0,0 +1,387 @@
+//! STM32 internal flash
+//!
+//! [`Stm32Flash`] is a [`FlashMemory`] for a region of STM32 internal flash. It
+//! unlocks the flash controller with [`FlashControl`] for each erase and program
+//! operation and locks it again, so the flash stays locked while it's not modified.
+//! [`FlashKeys`] implements the standard `FLASH_KEYR` unlock sequence.
+//!
+//! Page or sector layout, timing, and the memory info string are provided by
+//! [`Stm32F1`], [`Stm32F4`], and [`Stm32G0`] layouts for a [`Stm32Region`]:
+//!
+//! ```
+//! use usbd_dfu::flash::FlashLayout;
+//! use usbd_dfu::stm32::*;
+//!
+//! struct App;
+//!
+//! impl Stm32Region for App {
+//!     const START: u32 = 0x0800_8000;
+//!     const END: u32 = 0x0810_0000;
+//! }
+//!
+//! assert_eq!(
+//!     Stm32F4::<App>::MEM_INFO_STRING,
+//!     "@Flash/0x08008000/2*16Kg,1*64Kg,7*128Kg"
+//! );
+//! ```
+//!
+//! Data EEPROM of STM32L0 and STM32L1 is exposed with [`Stm32Eeprom`] layout, and
+//! option bytes with [`Stm32OptionBytes`] layout, which reloads them in manifestation.
+//!
+//! The flash is accessed with an `embedded-storage` [`NorFlash`] implementation
+//! that expects the flash to be unlocked. Requires `stm32-flash` feature.
+//!
+//! ```no_run
+//! # use embedded_storage::nor_flash::NorFlash;
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::stm32::*;
+//! # use usbd_dfu::DfuClass;
+//! # struct App;
+//! # impl Stm32Region for App {
+//! #     const START: u32 = 0x0800_8000;
+//! #     const END: u32 = 0x0810_0000;
+//! # }
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, flash: impl NorFlash) {
+//! // offsets of `flash` are relative to 0x08000000
+//! let flash = LockingFlash::new(flash, unsafe { FlashKeys::stm32f4() });
+//! let mem = Stm32Flash::<_, _, Stm32F4<App>, 1024>::new(flash, 0x0800_0000);
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use core::marker::PhantomData;
+
+use embedded_storage::nor_flash::{ErrorType, NorFlash, ReadNorFlash};
+
+use crate::flash::{FlashLayout, FlashMemory, RegionKind};
+use crate::mem_info::MemInfo;
+
+/// Start address of STM32 internal flash.
+pub const FLASH_BASE: u32 = 0x0800_0000;
+
+/// Maximum length of generated memory info strings.
+const MEM_INFO_LENGTH: usize = 128;
+
+/// Unlock and lock of a flash controller.
+pub trait FlashControl {
+    /// Unlock the flash for erase and program operations.
+    fn unlock(&mut self);
+
+    /// Lock the flash.
+    fn lock(&mut self);
+}
+
+/// [`FlashControl`] that uses `FLASH_KEYR` and the `LOCK` bit of `FLASH_CR`.
+pub struct FlashKeys {
+    keyr: *mut u32,
+    cr: *mut u32,
+    lock: u32,
+}
+
+// registers are accessed only through `&mut self`
+unsafe impl Send for FlashKeys {}
+
+impl FlashKeys {
+    /// First unlock key.
+    pub const KEY1: u32 = 0x4567_0123;
+
+    /// Second unlock key.
+    pub const KEY2: u32 = 0xCDEF_89AB;
+
+    /// Use `keyr` and `cr` registers, `lock` is the mask of `LOCK` bit.
+    ///
+    /// # Safety
+    ///
+    /// `keyr` and `cr` must be valid for volatile reads and writes, and nothing else
+    /// may unlock or lock the flash while `FlashKeys` exists.
+    pub const unsafe fn new(keyr: *mut u32, cr: *mut u32, lock: u32) -> Self {
+        Self { keyr, cr, lock }
+    }
+
+    /// STM32F1 registers.
+    ///
+    /// # Safety
+    ///
+    /// See [`new()`](Self::new).
+    pub const unsafe fn stm32f1() -> Self {
+        Self::new(0x4002_2004 as *mut u32, 0x4002_2010 as *mut u32, 1 << 7)
+    }
+
+    /// STM32F4 registers.
+    ///
+    /// # Safety
+    ///
+    /// See [`new()`](Self::new).
+    pub const unsafe fn stm32f4() -> Self {
+        Self::new(0x4002_3C04 as *mut u32, 0x4002_3C10 as *mut u32, 1 << 31)
+    }
+
+    /// STM32G0 registers.
+    ///
+    /// # Safety
+    ///
+    /// See [`new()`](Self::new).
+    pub const unsafe fn stm32g0() -> Self {
+        Self::new(0x4002_2008 as *mut u32, 0x4002_2014 as *mut u32, 1 << 31)
+    }
+}
+
+impl FlashControl for FlashKeys {
+    fn unlock(&mut self) {
+        // writing keys to an unlocked controller locks it until reset
+        unsafe {
+            if self.cr.read_volatile() & self.lock != 0 {
+                self.keyr.write_volatile(Self::KEY1);
+                self.keyr.write_volatile(Self::KEY2);
+            }
+        }
+    }
+
+    fn lock(&mut self) {
+        unsafe {
+            self.cr.write_volatile(self.cr.read_volatile() | self.lock);
+        }
+    }
+}
+
+/// [`NorFlash`] wrapper that unlocks the flash for each erase and write.
+pub struct LockingFlash<F: NorFlash, C: FlashControl> {
+    flash: F,
+    control: C,
+}
+
+impl<F: NorFlash, C: FlashControl> LockingFlash<F, C> {
+    /// Wrap `flash`, and unlock it with `control`.
+    pub fn new(flash: F, control: C) -> Self {
+        Self { flash, control }
+    }
+
+    /// Destroy the wrapper and return the flash and the control.
+    pub fn release(self) -> (F, C) {
+        (self.flash, self.control)
+    }
+}
+
+impl<F: NorFlash, C: FlashControl> ErrorType for LockingFlash<F, C> {
+    type Error = F::Error;
+}
+
+impl<F: NorFlash, C: FlashControl> ReadNorFlash for LockingFlash<F, C> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.flash.read(offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash.capacity()
+    }
+}
+
+impl<F: NorFlash, C: FlashControl> NorFlash for LockingFlash<F, C> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        self.control.unlock();
+        let result = self.flash.erase(from, to);
+        self.control.lock();
+        result
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.control.unlock();
+        let result = self.flash.write(offset, bytes);
+        self.control.lock();
+        result
+    }
+}
+
+/// Region of STM32 internal flash available for downloads, e.g. the application.
+///
+/// Both addresses must be page or sector aligned.
+pub trait Stm32Region {
+    /// First address of the region.
+    const START: u32;
+
+    /// End address of the region, exclusive.
+    const END: u32;
+}
+
+/// Memory info string of a region with `size` byte pages.
+const fn uniform_mem_info(start: u32, end: u32, size: u32) -> MemInfo<MEM_INFO_LENGTH> {
+    MemInfo::new("Flash", start).area((end - start) / size, size, 'g')
+}
+
+/// [`FlashLayout`] of STM32F1, `PAGE_SIZE` is 1 KiB for low- and medium-density,
+/// and 2 KiB for high-density and connectivity line devices.
+pub struct Stm32F1<R: Stm32Region, const PAGE_SIZE: u32 = 1024> {
+    _region: PhantomData<R>,
+}
+
+impl<R: Stm32Region, const PAGE_SIZE: u32> FlashLayout for Stm32F1<R, PAGE_SIZE> {
+    const START: u32 = R::START;
+    const END: u32 = R::END;
+    const MEM_INFO_STRING: &'static str = uniform_mem_info(R::START, R::END, PAGE_SIZE).as_str();
+    // 1 KiB, 512 half-words of 70 µs
+    const PROGRAM_TIME_MS: u32 = 36;
+    const ERASE_TIME_MS: u32 = 40;
+    const FULL_ERASE_TIME_MS: u32 = 40 * ((R::END - R::START) / PAGE_SIZE);
+
+    fn page(address: u32) -> Option<(u32, u32)> {
+        uniform_page(address, R::START, R::END, PAGE_SIZE)
+    }
+}
+
+/// [`FlashLayout`] of STM32G0, 2 KiB pages.
+pub struct Stm32G0<R: Stm32Region> {
+    _region: PhantomData<R>,
+}
+
+impl<R: Stm32Region> FlashLayout for Stm32G0<R> {
+    const START: u32 = R::START;
+    const END: u32 = R::END;
+    const MEM_INFO_STRING: &'static str = uniform_mem_info(R::START, R::END, 2048).as_str();
+    // 1 KiB, 128 double words of 125 µs
+    const PROGRAM_TIME_MS: u32 = 16;
+    const ERASE_TIME_MS: u32 = 40;
+    const FULL_ERASE_TIME_MS: u32 = 40 * ((R::END - R::START) / 2048);
+
+    fn page(address: u32) -> Option<(u32, u32)> {
+        uniform_page(address, R::START, R::END, 2048)
+    }
+}
+
+fn uniform_page(address: u32, start: u32, end: u32, size: u32) -> Option<(u32, u32)> {
+    if !(start..end).contains(&address) {
+        return None;
+    }
+    Some((address - (address - FLASH_BASE) % size, size))
+}
+
+/// Returns start address and size of STM32F4 sector that contains `address`.
+///
+/// Each 1 MiB bank has four 16 KiB sectors, one 64 KiB sector, and seven 128 KiB sectors.
+pub const fn f4_sector(address: u32) -> (u32, u32) {
+    let offset = (address - FLASH_BASE) % 0x10_0000;
+    let bank = address - offset;
+    let (start, size) = if offset < 0x1_0000 {
+        (offset - offset % 0x4000, 0x4000)
+    } else if offset < 0x2_0000 {
+        (0x1_0000, 0x1_0000)
+    } else {
+        (offset - offset % 0x2_0000, 0x2_0000)
+    };
+    (bank + start, size)
+}
+
+const fn f4_mem_info(start: u32, end: u32) -> MemInfo<MEM_INFO_LENGTH> {
+    let mut info = MemInfo::new("Flash", start);
+    let mut address = start;
+    while address < end {
+        let (_, size) = f4_sector(address);
+        let mut count = 0;
+        while address < end && f4_sector(address).1 == size {
+            address += size;
+            count += 1;
+        }
+        info = info.area(count, size, 'g');
+    }
+    info
+}
+
+const fn f4_sectors(start: u32, end: u32) -> u32 {
+    let mut address = start;
+    let mut count = 0;
+    while address < end {
+        address += f4_sector(address).1;
+        count += 1;
+    }
+    count
+}
+
+/// [`FlashLayout`] of STM32F4 with 16, 64, and 128 KiB sectors, single-bank mode.
+pub struct Stm32F4<R: Stm32Region> {
+    _region: PhantomData<R>,
+}
+
+impl<R: Stm32Region> FlashLayout for Stm32F4<R> {
+    const START: u32 = R::START;
+    const END: u32 = R::END;
+    const MEM_INFO_STRING: &'static str = f4_mem_info(R::START, R::END).as_str();
+    // 1 KiB, 256 words of 100 µs
+    const PROGRAM_TIME_MS: u32 = 26;
+    // 128 KiB sector, x32 parallelism
+    const ERASE_TIME_MS: u32 = 2000;
+    const FULL_ERASE_TIME_MS: u32 = 2000 * f4_sectors(R::START, R::END);
+
+    fn page(address: u32) -> Option<(u32, u32)> {
+        if !(R::START..R::END).contains(&address) {
+            return None;
+        }
+        Some(f4_sector(address))
+    }
+}
+
+/// [`FlashLayout`] of STM32L0 and STM32L1 data EEPROM, written in words without erase.
+///
+/// The NorFlash implementation must write 4-byte words, data EEPROM is unlocked
+/// with `PEKEYR` keys instead of [`FlashKeys`].
+pub struct Stm32Eeprom<R: Stm32Region> {
+    _region: PhantomData<R>,
+}
+
+impl<R: Stm32Region> FlashLayout for Stm32Eeprom<R> {
+    const KIND: RegionKind = RegionKind::Eeprom;
+    const START: u32 = R::START;
+    const END: u32 = R::END;
+    const MEM_INFO_STRING: &'static str = MemInfo::<MEM_INFO_LENGTH>::new("EEPROM", R::START)
+        .area(1, R::END - R::START, 'e')
+        .as_str();
+    // 1 KiB, 256 words of 3.2 ms
+    const PROGRAM_TIME_MS: u32 = 820;
+    const ERASE_TIME_MS: u32 = 0;
+    const FULL_ERASE_TIME_MS: u32 = 0;
+
+    fn page(address: u32) -> Option<(u32, u32)> {
+        if !(R::START..R::END).contains(&address) {
+            return None;
+        }
+        Some((R::START, R::END - R::START))
+    }
+}
+
+/// Option bytes region, loaded by [`reload()`](Self::reload).
+pub trait Stm32OptionRegion: Stm32Region {
+    /// Load option bytes, e.g. set `OBL_LAUNCH` bit of `FLASH_CR`, which resets the device.
+    fn reload();
+}
+
+/// [`FlashLayout`] of STM32 option bytes, written without erase and loaded in
+/// manifestation, so the memory is not manifestation tolerant.
+pub struct Stm32OptionBytes<R: Stm32OptionRegion> {
+    _region: PhantomData<R>,
+}
+
+impl<R: Stm32OptionRegion> FlashLayout for Stm32OptionBytes<R> {
+    const KIND: RegionKind = RegionKind::OptionBytes { reload: R::reload };
+    const START: u32 = R::START;
+    const END: u32 = R::END;
+    const MEM_INFO_STRING: &'static str = MemInfo::<MEM_INFO_LENGTH>::new("Option Bytes", R::START)
+        .area(1, R::END - R::START, 'e')
+        .as_str();
+    const PROGRAM_TIME_MS: u32 = 40;
+    const ERASE_TIME_MS: u32 = 0;
+    const FULL_ERASE_TIME_MS: u32 = 0;
+
+    fn page(address: u32) -> Option<(u32, u32)> {
+        if !(R::START..R::END).contains(&address) {
+            return None;
+        }
+        Some((R::START, R::END - R::START))
+    }
+}
+
+/// [`DfuMemory`](crate::DfuMemory) implementation for a region of STM32 internal flash
+/// with layout `L`, see [`FlashMemory`].
+pub type Stm32Flash<F, C, L, const N: usize> = FlashMemory<LockingFlash<F, C>, L, N>;
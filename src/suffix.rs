@@ -1,8 +1,11 @@
 //! DFU file suffix
 
+use crate::class::{DfuManifestationError, DfuMemory, DfuMemoryError, ReadOutcome};
+
 /// Firmware file suffix.
 #[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct Suffix {
     /// CRC checksum of the entire file, with the this CRC value set as zeroes.
@@ -21,16 +24,644 @@ pub struct Suffix {
     pub device: u16,
 }
 
-impl From<&[u8]> for Suffix {
-    fn from(bytes: &[u8]) -> Self {
+/// Size of the DFU file suffix in bytes.
+pub const SUFFIX_LENGTH: usize = 16;
+
+impl Suffix {
+    /// Serialize the suffix as it is stored at the end of a DFU file.
+    ///
+    /// Fields are little-endian, in the order defined by DFU specification:
+    /// *bcdDevice*, *idProduct*, *idVendor*, *bcdDFU*, *ucDfuSignature*,
+    /// *bLength*, *dwCRC*.
+    pub fn to_bytes(&self) -> [u8; SUFFIX_LENGTH] {
+        let mut bytes = [0; SUFFIX_LENGTH];
+        bytes[0..2].copy_from_slice(&self.device.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.usb_product.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.usb_vendor.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.dfu_specification.to_le_bytes());
+        for (b, c) in bytes[8..11].iter_mut().zip(self.dfu_signature) {
+            *b = c as u8;
+        }
+        bytes[11] = self.length;
+        bytes[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        bytes
+    }
+
+    /// Check that the file is intended for the device with the given
+    /// USB vendor ID, product ID, and release number.
+    ///
+    /// A field value of `0xFFFF` in the suffix matches any device.
+    /// Returns the first mismatched field, DFU class reports it
+    /// as `errTARGET`.
+    pub fn matches_device(
+        &self,
+        usb_vendor: u16,
+        usb_product: u16,
+        device: u16,
+    ) -> Result<(), SuffixMismatch> {
+        fn matches(expected: u16, actual: u16) -> bool {
+            expected == SUFFIX_WILDCARD || expected == actual
+        }
+
+        if !matches(self.usb_vendor, usb_vendor) {
+            Err(SuffixMismatch::Vendor)
+        } else if !matches(self.usb_product, usb_product) {
+            Err(SuffixMismatch::Product)
+        } else if !matches(self.device, device) {
+            Err(SuffixMismatch::Device)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Errors returned when parsing [`Suffix`] or [`LmdfuPrefix`] with `try_from()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum SuffixError {
+    /// Input is shorter than the structure.
+    TooShort,
+    /// *ucDfuSignature* is not "UFD", or LMDFU marker is not valid.
+    InvalidSignature,
+    /// *bLength* is less than 16 or longer than the input.
+    InvalidLength(u8),
+    /// *bcdDFU* is not 1.0 or 1.1a.
+    UnsupportedVersion(u16),
+}
+
+/// [`Suffix`] field that does not match the device, returned by [`Suffix::matches_device()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum SuffixMismatch {
+    /// *idVendor* does not match.
+    Vendor,
+    /// *idProduct* does not match.
+    Product,
+    /// *bcdDevice* does not match.
+    Device,
+}
+
+/// Value of *idVendor*, *idProduct*, or *bcdDevice* that matches any device.
+pub const SUFFIX_WILDCARD: u16 = 0xffff;
+
+/// *bcdDFU* value for DFU 1.0 and 1.1.
+pub const DFU_VERSION_1_0: u16 = 0x0100;
+/// *bcdDFU* value for DFU 1.1a, also used by ST DfuSe.
+pub const DFU_VERSION_1_1A: u16 = 0x011a;
+
+impl From<[u8; SUFFIX_LENGTH]> for Suffix {
+    /// Parse the suffix from the last 16 bytes of a DFU file without any checks.
+    fn from(bytes: [u8; SUFFIX_LENGTH]) -> Self {
+        Self {
+            device: u16::from_le_bytes([bytes[0], bytes[1]]),
+            usb_product: u16::from_le_bytes([bytes[2], bytes[3]]),
+            usb_vendor: u16::from_le_bytes([bytes[4], bytes[5]]),
+            dfu_specification: u16::from_le_bytes([bytes[6], bytes[7]]),
+            dfu_signature: [bytes[8] as char, bytes[9] as char, bytes[10] as char],
+            length: bytes[11],
+            crc: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Suffix {
+    type Error = SuffixError;
+
+    /// Parse and validate the suffix.
+    ///
+    /// `bytes` may be either the suffix itself, or a complete DFU file,
+    /// in which case the suffix is read from the last 16 bytes.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let start = bytes
+            .len()
+            .checked_sub(SUFFIX_LENGTH)
+            .ok_or(SuffixError::TooShort)?;
+
+        let mut raw = [0; SUFFIX_LENGTH];
+        raw.copy_from_slice(&bytes[start..]);
+        let suffix = Self::from(raw);
+
+        if suffix.dfu_signature != ['U', 'F', 'D'] {
+            return Err(SuffixError::InvalidSignature);
+        }
+
+        if (suffix.length as usize) < SUFFIX_LENGTH || suffix.length as usize > bytes.len() {
+            return Err(SuffixError::InvalidLength(suffix.length));
+        }
+
+        match suffix.dfu_specification {
+            DFU_VERSION_1_0 | DFU_VERSION_1_1A => Ok(suffix),
+            v => Err(SuffixError::UnsupportedVersion(v)),
+        }
+    }
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Streaming calculator of the DFU file CRC.
+///
+/// DFU uses CRC-32 (IEEE 802.3) polynomial without final inversion.
+/// The CRC covers the whole file, including the suffix, but excluding
+/// the *dwCRC* field itself.
+///
+/// ```
+/// use usbd_dfu::suffix::Crc32;
+///
+/// let mut crc = Crc32::new();
+/// crc.update(b"1234");
+/// crc.update(b"56789");
+/// assert_eq!(crc.finalize(), 0x340b_c6d9);
+/// ```
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    /// Creates a new calculator.
+    pub const fn new() -> Self {
+        Self { crc: 0xffff_ffff }
+    }
+
+    /// Process next block of data.
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.crc = CRC32_TABLE[((self.crc ^ b as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    /// Returns CRC of all data processed so far.
+    pub fn finalize(&self) -> u32 {
+        self.crc
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Size of the LMDFU prefix in bytes.
+pub const LMDFU_PREFIX_LENGTH: usize = 8;
+
+const LMDFU_MARKER: u8 = 0x01;
+
+/// LMDFU prefix, used by TI Tiva and Stellaris tools.
+///
+/// The prefix is placed at the start of the image and contains the
+/// memory address and the length of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LmdfuPrefix {
+    /// Memory address of the image, a multiple of 1024.
+    pub address: u32,
+    /// Length of the image, excluding the prefix.
+    pub length: u32,
+}
+
+impl LmdfuPrefix {
+    /// Serialize the prefix.
+    ///
+    /// The address is stored in 1KiB units and is rounded down.
+    pub fn to_bytes(&self) -> [u8; LMDFU_PREFIX_LENGTH] {
+        let mut bytes = [0; LMDFU_PREFIX_LENGTH];
+        bytes[0] = LMDFU_MARKER;
+        bytes[2..4].copy_from_slice(&((self.address / 1024) as u16).to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for LmdfuPrefix {
+    type Error = SuffixError;
+
+    /// Parse the prefix from the first 8 bytes of an image.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len() < LMDFU_PREFIX_LENGTH {
+            return Err(SuffixError::TooShort);
+        }
+        if bytes[0] != LMDFU_MARKER || bytes[1] != 0 {
+            return Err(SuffixError::InvalidSignature);
+        }
+
+        Ok(Self {
+            address: u16::from_le_bytes([bytes[2], bytes[3]]) as u32 * 1024,
+            length: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        })
+    }
+}
+
+/// [`DfuMemory`] adapter that removes DFU file suffix from downloaded data.
+///
+/// Hosts may send a complete DFU file, including the 16-byte suffix.
+/// `StripSuffix` keeps the last 16 received bytes in its own buffer,
+/// and programs the rest of the data with the wrapped memory. When the
+/// host completes the download, kept bytes are checked:
+///
+/// * if they form a valid suffix, the CRC of the downloaded data is verified
+///   and the suffix is not programmed,
+/// * otherwise, they are programmed as a part of the firmware.
+///
+/// Blocks passed to the wrapped memory are shifted by up to 16 bytes relative
+/// to the blocks sent by the host, and are never longer than
+/// [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE).
+/// The CRC is calculated in the order the data was received.
+///
+/// [`DfuMemory::image_version()`] returns *bcdDevice* of the suffix, unless it's `0xFFFF`.
+///
+/// `N` is the size of the internal buffer, it must be at least
+/// `TRANSFER_SIZE + 16` bytes.
+///
+/// ```no_run
+/// # use usb_device::bus::{UsbBus, UsbBusAllocator};
+/// # use usbd_dfu::suffix::StripSuffix;
+/// # use usbd_dfu::{DfuClass, DfuMemory};
+/// # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: impl DfuMemory) {
+/// let mem = StripSuffix::<_, { 128 + 16 }>::new(my_mem).with_device(0x1209, 0x2444, 0x0100);
+/// let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+/// # }
+/// ```
+pub struct StripSuffix<M: DfuMemory, const N: usize> {
+    mem: M,
+    buffer: [u8; N],
+    /// Number of kept bytes at the start of `buffer`.
+    tail_len: usize,
+    tail_address: u32,
+    /// Number of bytes stored after the kept bytes.
+    stored_len: usize,
+    crc: Crc32,
+    device: Option<(u16, u16, u16)>,
+    suffix: Option<Suffix>,
+}
+
+impl<M: DfuMemory, const N: usize> StripSuffix<M, N> {
+    /// Wrap `mem`.
+    pub fn new(mem: M) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize + SUFFIX_LENGTH,
+                "StripSuffix buffer is too small"
+            )
+        };
+
+        Self {
+            mem,
+            buffer: [0; N],
+            tail_len: 0,
+            tail_address: 0,
+            stored_len: 0,
+            crc: Crc32::new(),
+            device: None,
+            suffix: None,
+        }
+    }
+
+    /// Also check that the suffix matches the device, see [`Suffix::matches_device()`].
+    ///
+    /// A mismatch is reported to the host as `errTARGET`.
+    pub fn with_device(mut self, usb_vendor: u16, usb_product: u16, device: u16) -> Self {
+        self.device = Some((usb_vendor, usb_product, device));
+        self
+    }
+
+    /// Suffix found at the end of the last completed download, if any.
+    pub fn suffix(&self) -> Option<Suffix> {
+        self.suffix
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    fn restart(&mut self) {
+        self.tail_len = 0;
+        self.crc = Crc32::new();
+    }
+
+    /// Program `buffer[from..to]` at `address`.
+    fn program_range(
+        &mut self,
+        from: usize,
+        to: usize,
+        address: u32,
+    ) -> Result<(), DfuMemoryError> {
+        if from == to {
+            return Ok(());
+        }
+
+        let data = &self.buffer[from..to];
+        self.crc.update(data);
+        self.mem
+            .store_write_buffer(data)
+            .map_err(|_| DfuMemoryError::Prog)?;
+        self.mem.program(address, to - from)
+    }
+
+    /// Check the kept bytes, program them if they are not a suffix.
+    fn finish(&mut self) -> Result<(), DfuManifestationError> {
+        let tail = &self.buffer[..self.tail_len];
+
+        if let Ok(suffix) = Suffix::try_from(tail) {
+            let mut crc = self.crc;
+            crc.update(&tail[..SUFFIX_LENGTH - 4]);
+            if crc.finalize() != suffix.crc {
+                return Err(DfuManifestationError::File);
+            }
+
+            if let Some((usb_vendor, usb_product, device)) = self.device {
+                suffix
+                    .matches_device(usb_vendor, usb_product, device)
+                    .map_err(|_| DfuManifestationError::Target)?;
+            }
+
+            self.suffix = Some(suffix);
+            return Ok(());
+        }
+
+        self.suffix = None;
+        self.program_range(0, self.tail_len, self.tail_address)
+            .map_err(|_| DfuManifestationError::Unknown)
+    }
+}
+
+impl<M: DfuMemory, const N: usize> DfuMemory for StripSuffix<M, N> {
+    forward_dfu_memory!(
+        mem: M,
+        except RESUME_COMMAND, WRITE_ONCE, SKIP_IDENTICAL, VERIFY_COMMAND, store_write_buffer,
+        compare, program, manifestation, image_version, download_start, resume_point, usb_reset,
+        session_timeout
+    );
+
+    // the file CRC and the buffered tail are counted from the start of the download
+    const RESUME_COMMAND: bool = false;
+
+    // the suffix and the held back tail of the file are not in the memory
+    const VERIFY_COMMAND: bool = false;
+    const WRITE_ONCE: bool = false;
+    const SKIP_IDENTICAL: bool = false;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        // received data is placed after the kept bytes
+        let end = self.tail_len + src.len();
+        if end > N {
+            return Err(());
+        }
+        self.buffer[self.tail_len..end].copy_from_slice(src);
+        self.stored_len = src.len();
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let mut start = 0;
+        let mut start_address = self.tail_address;
+
+        if self.tail_len > 0 && self.tail_address.wrapping_add(self.tail_len as u32) != address {
+            // host has changed Address Pointer, kept bytes are not a suffix
+            self.program_range(0, self.tail_len, self.tail_address)?;
+            start = self.tail_len;
+            start_address = address;
+        } else if self.tail_len == 0 {
+            start_address = address;
+        }
+
+        let end = self.tail_len + length;
+        let keep_from = core::cmp::max(start, end.saturating_sub(SUFFIX_LENGTH));
+        let keep_address = start_address.wrapping_add((keep_from - start) as u32);
+
+        self.program_range(start, keep_from, start_address)?;
+
+        self.buffer.copy_within(keep_from..end, 0);
+        self.tail_len = end - keep_from;
+        self.tail_address = keep_address;
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        let result = self.finish();
+        self.restart();
+        result?;
+        self.mem.manifestation()
+    }
+
+    fn image_version(&mut self) -> Option<u32> {
+        // suffix is still kept, it's checked during manifestation
+        match Suffix::try_from(&self.buffer[..self.tail_len]) {
+            Ok(suffix) if suffix.device != SUFFIX_WILDCARD => Some(suffix.device.into()),
+            _ => self.mem.image_version(),
+        }
+    }
+
+    fn download_start(&mut self) {
+        // bytes kept from an aborted download are dropped,
+        // the first block may already be stored after them
+        self.buffer
+            .copy_within(self.tail_len..self.tail_len + self.stored_len, 0);
+        self.restart();
+        self.mem.download_start()
+    }
+
+    fn usb_reset(&mut self) {
+        self.restart();
+        self.mem.usb_reset()
+    }
+
+    fn session_timeout(&mut self) {
+        self.restart();
+        self.mem.session_timeout()
+    }
+}
+
+/// [`DfuMemory`] adapter that appends DFU file suffix to uploaded data.
+///
+/// When the wrapped memory returns the end of the firmware, a short block or
+/// [`ReadOutcome::LastChunk`], `AppendSuffix` adds a 16-byte suffix with the CRC of
+/// all uploaded data, so the file saved by the host can be downloaded again with
+/// strict tools. The suffix may continue in one more block.
+///
+/// An upload starts when the host reads a block that does not directly follow
+/// the previous one, usually block 0.
+///
+/// `N` is the size of the internal buffer, it must be at least `TRANSFER_SIZE` bytes.
+///
+/// ```no_run
+/// # use usb_device::bus::{UsbBus, UsbBusAllocator};
+/// # use usbd_dfu::suffix::AppendSuffix;
+/// # use usbd_dfu::{DfuClass, DfuMemory};
+/// # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: impl DfuMemory) {
+/// let mem = AppendSuffix::<_, 128>::new(my_mem, 0x1209, 0x2444, 0x0100);
+/// let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+/// # }
+/// ```
+pub struct AppendSuffix<M: DfuMemory, const N: usize> {
+    mem: M,
+    buffer: [u8; N],
+    next_address: Option<u32>,
+    crc: Crc32,
+    /// Suffix and the number of its bytes already sent.
+    suffix: Option<([u8; SUFFIX_LENGTH], usize)>,
+    usb_vendor: u16,
+    usb_product: u16,
+    device: u16,
+}
+
+impl<M: DfuMemory, const N: usize> AppendSuffix<M, N> {
+    /// Wrap `mem`, `usb_vendor`, `usb_product`, and `device` are stored in the suffix.
+    pub fn new(mem: M, usb_vendor: u16, usb_product: u16, device: u16) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize,
+                "AppendSuffix buffer is too small"
+            )
+        };
+
         Self {
-            crc: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
-            length: bytes[4],
-            dfu_signature: [bytes[5] as char, bytes[6] as char, bytes[7] as char],
-            dfu_specification: u16::from_le_bytes([bytes[8], bytes[9]]),
-            usb_vendor: u16::from_le_bytes([bytes[10], bytes[11]]),
-            usb_product: u16::from_le_bytes([bytes[12], bytes[13]]),
-            device: u16::from_le_bytes([bytes[14], bytes[15]]),
+            mem,
+            buffer: [0; N],
+            next_address: None,
+            crc: Crc32::new(),
+            suffix: None,
+            usb_vendor,
+            usb_product,
+            device,
+        }
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+
+    fn make_suffix(&self) -> [u8; SUFFIX_LENGTH] {
+        let mut suffix = Suffix {
+            crc: 0,
+            length: SUFFIX_LENGTH as u8,
+            dfu_signature: ['U', 'F', 'D'],
+            dfu_specification: DFU_VERSION_1_1A,
+            usb_vendor: self.usb_vendor,
+            usb_product: self.usb_product,
+            device: self.device,
         }
+        .to_bytes();
+
+        let mut crc = self.crc;
+        crc.update(&suffix[..SUFFIX_LENGTH - 4]);
+        suffix[SUFFIX_LENGTH - 4..].copy_from_slice(&crc.finalize().to_le_bytes());
+        suffix
+    }
+
+    /// Copy not yet sent suffix bytes to `buffer[from..]`, returns the number of copied bytes.
+    fn copy_suffix(&mut self, from: usize, length: usize) -> usize {
+        let Some((suffix, sent)) = &mut self.suffix else {
+            return 0;
+        };
+
+        let len = core::cmp::min(SUFFIX_LENGTH - *sent, length.saturating_sub(from));
+        self.buffer[from..from + len].copy_from_slice(&suffix[*sent..*sent + len]);
+        *sent += len;
+        len
+    }
+}
+
+impl<M: DfuMemory, const N: usize> DfuMemory for AppendSuffix<M, N> {
+    forward_dfu_memory!(
+        mem: M,
+        except CLAMP_UPLOAD, WRITE_ONCE, SKIP_IDENTICAL, VERIFY_COMMAND, read, read_block, compare,
+        usb_reset, session_timeout
+    );
+
+    // read() carries the upload and suffix state, verify reads would corrupt it
+    const VERIFY_COMMAND: bool = false;
+
+    // the suffix is uploaded after the end of the region
+    const CLAMP_UPLOAD: bool = false;
+
+    // reads are part of the upload stream
+    const WRITE_ONCE: bool = false;
+    const SKIP_IDENTICAL: bool = false;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(self.read_block(address, length)?.data())
+    }
+
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        let length = core::cmp::min(length, N);
+
+        if self.next_address != Some(address) {
+            // a new upload
+            self.crc = Crc32::new();
+            self.suffix = None;
+        }
+        self.next_address = Some(address.wrapping_add(length as u32));
+
+        let len = if self.suffix.is_some() {
+            // end of the firmware was reached, send the rest of the suffix
+            self.copy_suffix(0, length)
+        } else {
+            let block = self.mem.read_block(address, length)?;
+            let data = block.data();
+            let len = core::cmp::min(data.len(), length);
+            self.buffer[..len].copy_from_slice(&data[..len]);
+            self.crc.update(&self.buffer[..len]);
+
+            if !block.is_last() {
+                return Ok(ReadOutcome::Full(&self.buffer[..len]));
+            }
+
+            // the last chunk may be full-length, the suffix then follows in the next block
+            self.suffix = Some((self.make_suffix(), 0));
+            len + self.copy_suffix(len, length)
+        };
+
+        let data = &self.buffer[..len];
+        match self.suffix {
+            Some((_, sent)) if sent < SUFFIX_LENGTH => Ok(ReadOutcome::Full(data)),
+            _ => Ok(ReadOutcome::LastChunk(data)),
+        }
+    }
+
+    fn usb_reset(&mut self) {
+        self.next_address = None;
+        self.mem.usb_reset()
+    }
+
+    fn session_timeout(&mut self) {
+        self.next_address = None;
+        self.mem.session_timeout()
     }
 }
@@ -0,0 +1,193 @@
+//! Emulated host for tests
+//!
+//! [`DeviceExt`] extends `usbd-class-tester` [`Device`] with DFU requests, so a
+//! [`DfuMemory`](crate::DfuMemory) implementation can be tested against
+//! [`DfuClass`](crate::DfuClass) with `cargo test`, and [`status()`] assembles
+//! the expected `DFU_GETSTATUS` reply.
+//!
+//! Requires `test-helpers` feature, which depends on `std`.
+//!
+//! ```no_run
+//! use usbd_class_tester::prelude::*;
+//! use usbd_dfu::test_helpers::*;
+//! # use usb_device::bus::UsbBusAllocator;
+//! # use usbd_dfu::*;
+//! # struct MyMem;
+//! # impl MyMem {
+//! #     fn new() -> Self {
+//! #         MyMem
+//! #     }
+//! # }
+//! # impl DfuMemory for MyMem {
+//! #     const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+//! #     const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*1Kg";
+//! #     const PROGRAM_TIME_MS: u32 = 10;
+//! #     const ERASE_TIME_MS: u32 = 20;
+//! #     const FULL_ERASE_TIME_MS: u32 = 320;
+//! # }
+//!
+//! struct MkDfu;
+//!
+//! impl UsbDeviceCtx for MkDfu {
+//!     type C<'c> = DfuClass<EmulatedUsbBus, MyMem>;
+//!
+//!     fn create_class(
+//!         &mut self,
+//!         alloc: &UsbBusAllocator<EmulatedUsbBus>,
+//!     ) -> AnyResult<DfuClass<EmulatedUsbBus, MyMem>> {
+//!         Ok(DfuClass::new(alloc, MyMem::new()))
+//!     }
+//! }
+//!
+//! MkDfu
+//!     .with_usb(|mut dfu, mut dev| {
+//!         dev.download(&mut dfu, 2, &[0x55; 64]).expect("download");
+//!         let reply = dev.get_status(&mut dfu).expect("status");
+//!         assert_eq!(reply, status(DfuStatusCode::Ok, 10, DfuState::DfuDnBusy));
+//!     })
+//!     .expect("with_usb");
+//! ```
+
+use std::vec::Vec;
+
+use usb_device::class::UsbClass;
+use usbd_class_tester::prelude::*;
+
+use crate::class::{DfuState, DfuStatusCode};
+use crate::consts::{
+    DFU_ABORT, DFU_CLRSTATUS, DFU_DETACH, DFU_DNLOAD, DFU_GETSTATE, DFU_GETSTATUS, DFU_UPLOAD,
+};
+
+/// DFU requests of an emulated host.
+///
+/// Requests are sent to interface 0, [`read()`](DeviceExt::read) and
+/// [`write()`](DeviceExt::write) take the interface number in `index`.
+/// A stalled request returns an error.
+pub trait DeviceExt<C> {
+    /// Send a class request with a data stage to the device.
+    fn read(
+        &mut self,
+        cls: &mut C,
+        req: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> AnyResult<Vec<u8>>;
+
+    /// Send a class request with `data` to the device.
+    fn write(
+        &mut self,
+        cls: &mut C,
+        req: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+        data: &[u8],
+    ) -> AnyResult<Vec<u8>>;
+
+    /// Send `DFU_DETACH`.
+    fn detach(&mut self, cls: &mut C, timeout_ms: u16) -> AnyResult<Vec<u8>>;
+
+    /// Send `DFU_DNLOAD` of `block_num` with `data`.
+    fn download(&mut self, cls: &mut C, block_num: u16, data: &[u8]) -> AnyResult<Vec<u8>>;
+
+    /// Send `DFU_UPLOAD` of `block_num`, up to `length` bytes.
+    fn upload(&mut self, cls: &mut C, block_num: u16, length: usize) -> AnyResult<Vec<u8>>;
+
+    /// Send `DFU_GETSTATUS`, see [`status()`].
+    fn get_status(&mut self, cls: &mut C) -> AnyResult<Vec<u8>>;
+
+    /// Send `DFU_CLRSTATUS`.
+    fn clear_status(&mut self, cls: &mut C) -> AnyResult<Vec<u8>>;
+
+    /// Send `DFU_GETSTATE`.
+    fn get_state(&mut self, cls: &mut C) -> AnyResult<Vec<u8>>;
+
+    /// Send `DFU_ABORT`.
+    fn abort(&mut self, cls: &mut C) -> AnyResult<Vec<u8>>;
+}
+
+impl<'a, C, M> DeviceExt<C> for Device<'a, C, M>
+where
+    C: UsbClass<EmulatedUsbBus>,
+    M: UsbDeviceCtx<C<'a> = C>,
+{
+    fn read(
+        &mut self,
+        cls: &mut C,
+        req: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> AnyResult<Vec<u8>> {
+        self.control_read(
+            cls,
+            CtrRequestType::to_host().class().interface(),
+            req,
+            value,
+            index,
+            length,
+        )
+    }
+
+    fn write(
+        &mut self,
+        cls: &mut C,
+        req: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+        data: &[u8],
+    ) -> AnyResult<Vec<u8>> {
+        self.control_write(
+            cls,
+            CtrRequestType::to_device().class().interface(),
+            req,
+            value,
+            index,
+            length,
+            data,
+        )
+    }
+
+    fn detach(&mut self, cls: &mut C, timeout_ms: u16) -> AnyResult<Vec<u8>> {
+        self.write(cls, DFU_DETACH, timeout_ms, 0, 0, &[])
+    }
+
+    fn download(&mut self, cls: &mut C, block_num: u16, data: &[u8]) -> AnyResult<Vec<u8>> {
+        if data.len() > u16::MAX as usize {
+            return Err(AnyUsbError::DataConversion);
+        }
+        self.write(cls, DFU_DNLOAD, block_num, 0, data.len() as u16, data)
+    }
+
+    fn upload(&mut self, cls: &mut C, block_num: u16, length: usize) -> AnyResult<Vec<u8>> {
+        if length > u16::MAX as usize {
+            return Err(AnyUsbError::DataConversion);
+        }
+        self.read(cls, DFU_UPLOAD, block_num, 0, length as u16)
+    }
+
+    fn get_status(&mut self, cls: &mut C) -> AnyResult<Vec<u8>> {
+        self.read(cls, DFU_GETSTATUS, 0, 0, 6)
+    }
+
+    fn clear_status(&mut self, cls: &mut C) -> AnyResult<Vec<u8>> {
+        self.write(cls, DFU_CLRSTATUS, 0, 0, 0, &[])
+    }
+
+    fn get_state(&mut self, cls: &mut C) -> AnyResult<Vec<u8>> {
+        self.read(cls, DFU_GETSTATE, 0, 0, 1)
+    }
+
+    fn abort(&mut self, cls: &mut C) -> AnyResult<Vec<u8>> {
+        self.write(cls, DFU_ABORT, 0, 0, 0, &[])
+    }
+}
+
+/// Returns `DFU_GETSTATUS` reply with `status`, *bwPollTimeout* of `poll_timeout`
+/// milliseconds, and `state`.
+pub fn status(status: DfuStatusCode, poll_timeout: u32, state: DfuState) -> [u8; 6] {
+    let t = poll_timeout.to_le_bytes();
+    [status as u8, t[0], t[1], t[2], state as u8, 0]
+}
@@ -0,0 +1,186 @@
+//! TLV framing of vendor command payloads
+//!
+//! Vendor extensions exchange small structured payloads as a sequence of records:
+//! 1 byte of tag, 1 byte of value length, and the value. Numbers are little-endian,
+//! strings are UTF-8. A reader must skip records with unknown tags, so fields can be
+//! added without breaking older hosts or devices.
+//!
+//! The same framing is used by
+//! * [`DeviceInfo`](crate::info::DeviceInfo) reply of *Get Device Info* command,
+//! * *Set Parameters* command (`0xB9`), host to device, and *Get Capabilities* command
+//!   (`0xBA`), device to host, see [`DfuMemory::TLV_COMMANDS`](crate::DfuMemory::TLV_COMMANDS).
+//!
+//! Tags below [`TAG_VENDOR`] are reserved for this crate, applications use the rest.
+//!
+//! ```no_run
+//! # use usbd_dfu::tlv::*;
+//! # fn example() -> Result<(), TlvError> {
+//! # let mut buf = [0; MAX_PAYLOAD_LENGTH];
+//! # let mut speed = 0;
+//! let mut w = TlvWriter::new(&mut buf);
+//! w.u16(TAG_VENDOR, 0x0102)?;
+//! w.str(TAG_VENDOR + 1, "fast-erase")?;
+//! let payload = w.finish();
+//!
+//! for record in TlvReader::new(payload) {
+//!     let record = record?;
+//!     match record.tag {
+//!         TAG_VENDOR => speed = record.u16().ok_or(TlvError::Value)?,
+//!         _ => {}
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+/// First tag available to applications.
+pub const TAG_VENDOR: u8 = 0x80;
+
+/// Maximum length of *Set Parameters* and *Get Capabilities* payloads.
+pub const MAX_PAYLOAD_LENGTH: usize = 64;
+
+/// TLV encoding or decoding error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub enum TlvError {
+    /// The buffer is too short for the record, or the value is longer than 255 bytes.
+    Overflow,
+    /// The data ends inside a record.
+    Truncated,
+    /// The value has a wrong length or is not valid for its tag.
+    Value,
+}
+
+/// Record of a TLV payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Record<'a> {
+    /// Tag.
+    pub tag: u8,
+    /// Value.
+    pub value: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    /// Returns the value as `u8`, if it's 1 byte long.
+    pub fn u8(&self) -> Option<u8> {
+        match *self.value {
+            [value] => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as `u16`, if it's 2 bytes long.
+    pub fn u16(&self) -> Option<u16> {
+        self.value.try_into().ok().map(u16::from_le_bytes)
+    }
+
+    /// Returns the value as `u32`, if it's 4 bytes long.
+    pub fn u32(&self) -> Option<u32> {
+        self.value.try_into().ok().map(u32::from_le_bytes)
+    }
+
+    /// Returns the value as a string, if it's valid UTF-8.
+    pub fn str(&self) -> Option<&'a str> {
+        core::str::from_utf8(self.value).ok()
+    }
+}
+
+/// Iterator over the records of a TLV payload.
+///
+/// Returns [`TlvError::Truncated`] once if the payload ends inside a record.
+#[derive(Debug, Clone)]
+pub struct TlvReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TlvReader<'a> {
+    /// Read `data`.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    /// Returns `true` if all records are complete.
+    pub fn is_valid(&self) -> bool {
+        self.clone().all(|r| r.is_ok())
+    }
+}
+
+impl<'a> Iterator for TlvReader<'a> {
+    type Item = Result<Record<'a>, TlvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let [tag, len, rest @ ..] = self.data else {
+            let truncated = !self.data.is_empty();
+            self.data = &[];
+            return truncated.then_some(Err(TlvError::Truncated));
+        };
+        let Some((value, rest)) = rest.split_at_checked(*len as usize) else {
+            self.data = &[];
+            return Some(Err(TlvError::Truncated));
+        };
+        let record = Record { tag: *tag, value };
+        self.data = rest;
+        Some(Ok(record))
+    }
+}
+
+/// Writes the records of a TLV payload to a buffer.
+pub struct TlvWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> TlvWriter<'b> {
+    /// Write to `buf`.
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// Returns the length of written records.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no records were written.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Write a record.
+    pub fn record(&mut self, tag: u8, value: &[u8]) -> Result<(), TlvError> {
+        let len = u8::try_from(value.len()).map_err(|_| TlvError::Overflow)?;
+        let end = self.len + 2 + value.len();
+        let record = self.buf.get_mut(self.len..end).ok_or(TlvError::Overflow)?;
+        record[0] = tag;
+        record[1] = len;
+        record[2..].copy_from_slice(value);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Write a `u8` record.
+    pub fn u8(&mut self, tag: u8, value: u8) -> Result<(), TlvError> {
+        self.record(tag, &[value])
+    }
+
+    /// Write a `u16` record.
+    pub fn u16(&mut self, tag: u8, value: u16) -> Result<(), TlvError> {
+        self.record(tag, &value.to_le_bytes())
+    }
+
+    /// Write a `u32` record.
+    pub fn u32(&mut self, tag: u8, value: u32) -> Result<(), TlvError> {
+        self.record(tag, &value.to_le_bytes())
+    }
+
+    /// Write a string record.
+    pub fn str(&mut self, tag: u8, value: &str) -> Result<(), TlvError> {
+        self.record(tag, value.as_bytes())
+    }
+
+    /// Returns the written records.
+    pub fn finish(self) -> &'b [u8] {
+        &self.buf[..self.len]
+    }
+}
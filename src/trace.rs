@@ -0,0 +1,91 @@
+//! State transition trace
+//!
+//! With `trace` feature, the class records the last [`TRACE_LENGTH`] state or
+//! status changes, so a failed update can be reconstructed after the fact, e.g.
+//! from a crash dump, or sent to the host over a vendor channel:
+//!
+//! ```no_run
+//! # use usb_device::bus::UsbBus;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # fn example<B: UsbBus, M: DfuMemory>(dfu: &DfuClass<B, M>) {
+//! for t in dfu.trace().iter() {
+//!     log::info!("{:?}: {} -> {}, {}", t.request, t.old_state, t.new_state, t.status);
+//! }
+//! # }
+//! ```
+
+use heapless::HistoryBuffer;
+
+use crate::class::{DfuState, DfuStatusCode};
+
+/// Number of recorded transitions.
+pub const TRACE_LENGTH: usize = 16;
+
+/// State or status change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt-03", derive(defmt::Format))]
+pub struct Transition {
+    /// *bRequest* of the DFU request that caused the change, `None` for
+    /// a USB reset or a completed memory operation.
+    pub request: Option<u8>,
+    /// State before the change.
+    pub old_state: DfuState,
+    /// State after the change.
+    pub new_state: DfuState,
+    /// Status after the change.
+    pub status: DfuStatusCode,
+}
+
+impl Transition {
+    /// Serialize as *bRequest*, `0xFF` if there is no request, old state,
+    /// new state, and status.
+    pub fn to_bytes(&self) -> [u8; 4] {
+        [
+            self.request.unwrap_or(0xff),
+            self.old_state as u8,
+            self.new_state as u8,
+            self.status as u8,
+        ]
+    }
+}
+
+/// Last [`TRACE_LENGTH`] transitions.
+#[derive(Clone)]
+pub struct Trace(HistoryBuffer<Transition, TRACE_LENGTH>);
+
+impl Trace {
+    pub(crate) const fn new() -> Self {
+        Self(HistoryBuffer::new())
+    }
+
+    pub(crate) fn record(&mut self, transition: Transition) {
+        self.0.write(transition);
+    }
+
+    /// Returns the transitions, the oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Transition> {
+        self.0.oldest_ordered()
+    }
+
+    /// Returns the number of recorded transitions.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if no transitions were recorded.
+    pub fn is_empty(&self) -> bool {
+        self.0.len() == 0
+    }
+
+    /// Forget recorded transitions.
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+#[cfg(feature = "defmt-03")]
+impl defmt::Format for Trace {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "Trace({})", self.len())
+    }
+}
@@ -0,0 +1,225 @@
+//! Transformation of downloaded and uploaded data, e.g. decryption
+//!
+//! [`TransformMemory`] wraps [`DfuMemory`] and applies a [`DownloadTransform`]
+//! to every block before it is programmed, and to every block read from memory
+//! before it is uploaded. Each block comes with its offset from the start of the
+//! download or upload, so stream ciphers like AES-CTR or ChaCha20 can seek
+//! to the corresponding keystream position.
+//!
+//! With `cipher` feature, [`StreamCipherTransform`] implements [`DownloadTransform`]
+//! for any seekable stream cipher from RustCrypto.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::transform::*;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # const KEY: [u8; 32] = [0; 32];
+//! # const NONCE: [u8; 12] = [0; 12];
+//! # #[cfg(feature = "cipher")]
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: impl DfuMemory) {
+//! # use chacha20::cipher::KeyIvInit;
+//! let cipher = chacha20::ChaCha20::new(&KEY.into(), &NONCE.into());
+//! let mem = TransformMemory::<_, _, 128>::new(my_mem, StreamCipherTransform::new(cipher));
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuMemory, DfuMemoryError, ReadOutcome};
+
+/// In-place transformation of data blocks.
+pub trait DownloadTransform {
+    /// Blocks passed to the transformation, except the last one, are multiples of
+    /// this size. [`TRANSFER_SIZE`](DfuMemory::TRANSFER_SIZE) must be a multiple of it.
+    const BLOCK_SIZE: usize = 1;
+
+    /// Transform a downloaded block before it is programmed.
+    ///
+    /// `offset` is the position of `data` from the start of the download.
+    fn download(&mut self, offset: u32, data: &mut [u8]) -> Result<(), DfuMemoryError>;
+
+    /// Transform a block read from memory before it is uploaded.
+    ///
+    /// `offset` is the position of `data` from the start of the upload.
+    fn upload(&mut self, offset: u32, data: &mut [u8]) -> Result<(), DfuMemoryError>;
+}
+
+/// [`DfuMemory`] adapter that applies a [`DownloadTransform`] to downloaded and uploaded data.
+///
+/// Blocks are expected in order. An upload starts when the host reads a block
+/// that does not directly follow the previous one, usually block 0.
+///
+/// `N` is the size of the internal buffer, it must be at least `TRANSFER_SIZE` bytes.
+pub struct TransformMemory<M: DfuMemory, T: DownloadTransform, const N: usize> {
+    mem: M,
+    transform: T,
+    buffer: [u8; N],
+    /// Offset of the next downloaded block.
+    download_offset: u32,
+    /// Address and offset of the next uploaded block.
+    upload_next: Option<(u32, u32)>,
+}
+
+impl<M: DfuMemory, T: DownloadTransform, const N: usize> TransformMemory<M, T, N> {
+    /// Wrap `mem`, and transform data with `transform`.
+    pub fn new(mem: M, transform: T) -> Self {
+        const {
+            assert!(
+                N >= M::TRANSFER_SIZE as usize,
+                "TransformMemory buffer is too small"
+            );
+            assert!(
+                (M::TRANSFER_SIZE as usize).is_multiple_of(T::BLOCK_SIZE),
+                "TRANSFER_SIZE is not a multiple of BLOCK_SIZE"
+            );
+        };
+
+        Self {
+            mem,
+            transform,
+            buffer: [0; N],
+            download_offset: 0,
+            upload_next: None,
+        }
+    }
+
+    /// Returns a reference to the transformation.
+    pub fn transform(&mut self) -> &mut T {
+        &mut self.transform
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+}
+
+impl<M: DfuMemory, T: DownloadTransform, const N: usize> DfuMemory for TransformMemory<M, T, N> {
+    forward_dfu_memory!(
+        mem: M,
+        except RESUME_COMMAND, WRITE_ONCE, SKIP_IDENTICAL, VERIFY_COMMAND, store_write_buffer, read,
+        read_block, compare, program, download_start, resume_point, usb_reset, session_timeout
+    );
+
+    // the transform offset restarts at 0 with every download
+    const RESUME_COMMAND: bool = false;
+
+    // the memory holds transformed blocks, not the downloaded data
+    const VERIFY_COMMAND: bool = false;
+    const WRITE_ONCE: bool = false;
+    const SKIP_IDENTICAL: bool = false;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        // wrapped memory gets the block when it is programmed
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        self.read_block(address, length).map(|block| block.data())
+    }
+
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        let offset = match self.upload_next {
+            Some((next_address, offset)) if next_address == address => offset,
+            _ => 0,
+        };
+        self.upload_next = None;
+
+        let block = self.mem.read_block(address, length)?;
+        let len = block.data().len();
+        let buffer = self.buffer.get_mut(..len).ok_or(DfuMemoryError::Unknown)?;
+        buffer.copy_from_slice(block.data());
+        self.transform.upload(offset, buffer)?;
+
+        self.upload_next = address
+            .checked_add(len as u32)
+            .zip(offset.checked_add(len as u32));
+        let data = &self.buffer[..len];
+        Ok(match block {
+            ReadOutcome::Full(_) => ReadOutcome::Full(data),
+            ReadOutcome::LastChunk(_) => ReadOutcome::LastChunk(data),
+        })
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let data = self.buffer.get_mut(..length).ok_or(DfuMemoryError::Prog)?;
+        self.transform.download(self.download_offset, data)?;
+        self.mem
+            .store_write_buffer(data)
+            .map_err(|_| DfuMemoryError::Prog)?;
+        self.mem.program(address, length)?;
+        self.download_offset = self
+            .download_offset
+            .checked_add(length as u32)
+            .ok_or(DfuMemoryError::Address)?;
+        Ok(())
+    }
+
+    fn download_start(&mut self) {
+        self.download_offset = 0;
+        self.mem.download_start()
+    }
+
+    fn usb_reset(&mut self) {
+        self.upload_next = None;
+        self.mem.usb_reset()
+    }
+
+    fn session_timeout(&mut self) {
+        self.upload_next = None;
+        self.mem.session_timeout()
+    }
+}
+
+/// [`DownloadTransform`] that applies the keystream of a seekable stream cipher,
+/// e.g. `ctr::Ctr128BE<aes::Aes128>` or `chacha20::ChaCha20`.
+///
+/// Downloaded data is decrypted, and uploaded data is encrypted with the same keystream.
+///
+/// Requires `cipher` feature.
+#[cfg(feature = "cipher")]
+pub struct StreamCipherTransform<C> {
+    cipher: C,
+}
+
+#[cfg(feature = "cipher")]
+impl<C: cipher::StreamCipher + cipher::StreamCipherSeek> StreamCipherTransform<C> {
+    /// Use `cipher`, initialized with a key and a nonce.
+    pub fn new(cipher: C) -> Self {
+        Self { cipher }
+    }
+
+    fn apply(&mut self, offset: u32, data: &mut [u8]) -> Result<(), DfuMemoryError> {
+        self.cipher
+            .try_seek(offset)
+            .map_err(|_| DfuMemoryError::Address)?;
+        self.cipher
+            .try_apply_keystream(data)
+            .map_err(|_| DfuMemoryError::Address)
+    }
+}
+
+#[cfg(feature = "cipher")]
+impl<C: cipher::StreamCipher + cipher::StreamCipherSeek> DownloadTransform
+    for StreamCipherTransform<C>
+{
+    fn download(&mut self, offset: u32, data: &mut [u8]) -> Result<(), DfuMemoryError> {
+        self.apply(offset, data)
+    }
+
+    fn upload(&mut self, offset: u32, data: &mut [u8]) -> Result<(), DfuMemoryError> {
+        self.apply(offset, data)
+    }
+}
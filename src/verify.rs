@@ -0,0 +1,135 @@
+//! Firmware signature verification before manifestation
+//!
+//! [`SignedMemory`] wraps [`DfuMemory`] and implements [`DigestVerifier`], so it
+//! can be used together with [`HashedMemory`](crate::hash::HashedMemory):
+//! the digest of the downloaded image is checked by a [`FirmwareVerifier`]
+//! against the signature provided by [`ImageSignature::image_signature()`].
+//! [`DfuMemory::manifestation()`] is called only if the signature is valid.
+//!
+//! With `p256` feature, [`P256Verifier`] checks ECDSA P-256 signatures of SHA-256 digests.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usbd_dfu::hash::HashedMemory;
+//! # use usbd_dfu::verify::*;
+//! # use usbd_dfu::*;
+//! # const PUBLIC_KEY: [u8; 65] = [0; 65];
+//! # struct MyMem {
+//! #     signature: [u8; 64],
+//! # }
+//! # impl DfuMemory for MyMem {
+//! #     const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+//! #     const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*1Kg";
+//! #     const PROGRAM_TIME_MS: u32 = 10;
+//! #     const ERASE_TIME_MS: u32 = 20;
+//! #     const FULL_ERASE_TIME_MS: u32 = 320;
+//! # }
+//! impl ImageSignature for MyMem {
+//!     fn image_signature(&mut self) -> Result<&[u8], DfuManifestationError> {
+//!         // e.g. read the signature from a dedicated flash page
+//!         Ok(&self.signature)
+//!     }
+//! }
+//!
+//! # #[cfg(all(feature = "p256", feature = "sha2"))]
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: MyMem) {
+//! # use sha2::Digest;
+//! let verifier = P256Verifier::from_sec1_bytes(&PUBLIC_KEY).unwrap();
+//! let mem = SignedMemory::new(my_mem, verifier);
+//! let mem = HashedMemory::<_, _, 128>::new(mem, sha2::Sha256::new());
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, mem);
+//! # }
+//! ```
+
+use crate::class::{DfuManifestationError, DfuMemory};
+use crate::hash::DigestVerifier;
+
+/// Check a signature of the downloaded image.
+pub trait FirmwareVerifier<D> {
+    /// Returns `Ok(())` if `signature` of the image with `digest` is valid.
+    ///
+    /// Should return [`DfuManifestationError::File`] if the signature is malformed,
+    /// and [`DfuManifestationError::Firmware`] if the signature does not match.
+    fn verify_firmware(&self, digest: &D, signature: &[u8]) -> Result<(), DfuManifestationError>;
+}
+
+/// Provides the signature of the downloaded image.
+pub trait ImageSignature {
+    /// Called before manifestation to get the signature of the downloaded image.
+    fn image_signature(&mut self) -> Result<&[u8], DfuManifestationError>;
+}
+
+/// [`DfuMemory`] adapter that checks the image signature with a [`FirmwareVerifier`].
+pub struct SignedMemory<M: DfuMemory + ImageSignature, V> {
+    mem: M,
+    verifier: V,
+}
+
+impl<M: DfuMemory + ImageSignature, V> SignedMemory<M, V> {
+    /// Wrap `mem`, and use `verifier` to check image signatures.
+    pub fn new(mem: M, verifier: V) -> Self {
+        Self { mem, verifier }
+    }
+
+    /// Returns a reference to the wrapped memory.
+    pub fn memory(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Destroy the adapter and return the wrapped memory.
+    pub fn release(self) -> M {
+        self.mem
+    }
+}
+
+impl<M: DfuMemory + ImageSignature, V: FirmwareVerifier<D>, D> DigestVerifier<D>
+    for SignedMemory<M, V>
+{
+    fn verify_digest(&mut self, digest: &D) -> Result<(), DfuManifestationError> {
+        let signature = self.mem.image_signature()?;
+        self.verifier.verify_firmware(digest, signature)
+    }
+}
+
+impl<M: DfuMemory + ImageSignature, V> DfuMemory for SignedMemory<M, V> {
+    forward_dfu_memory!(mem: M);
+}
+
+/// ECDSA P-256 verifier of SHA-256 image digests.
+///
+/// Signatures are expected in fixed-size format: 32 bytes of *r* followed by 32 bytes of *s*.
+///
+/// Requires `p256` feature.
+#[cfg(feature = "p256")]
+pub struct P256Verifier {
+    key: p256::ecdsa::VerifyingKey,
+}
+
+#[cfg(feature = "p256")]
+impl P256Verifier {
+    /// Create a verifier from a SEC1-encoded public key, compressed or uncompressed.
+    ///
+    /// Returns `None` if the key is not valid.
+    pub fn from_sec1_bytes(key: &[u8]) -> Option<Self> {
+        p256::ecdsa::VerifyingKey::from_sec1_bytes(key)
+            .ok()
+            .map(|key| Self { key })
+    }
+}
+
+#[cfg(feature = "p256")]
+impl FirmwareVerifier<[u8; 32]> for P256Verifier {
+    fn verify_firmware(
+        &self,
+        digest: &[u8; 32],
+        signature: &[u8],
+    ) -> Result<(), DfuManifestationError> {
+        use p256::ecdsa::signature::hazmat::PrehashVerifier;
+
+        let signature = p256::ecdsa::Signature::from_slice(signature)
+            .map_err(|_| DfuManifestationError::File)?;
+        self.key
+            .verify_prehash(digest, &signature)
+            .map_err(|_| DfuManifestationError::Firmware)
+    }
+}
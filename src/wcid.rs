@@ -0,0 +1,122 @@
+//! Microsoft OS 1.0 descriptors (WCID)
+//!
+//! Windows 7 and 8 do not support Microsoft OS 2.0 descriptors, but they install
+//! the WinUSB driver automatically for a *WCID* device, i.e. a device that has the
+//! Microsoft OS string descriptor at index `0xEE`, and an *Extended Compat ID*
+//! OS feature descriptor with `WINUSB` compatible ID for the DFU interface.
+//!
+//! [`WcidClass`] is a companion [`UsbClass`] that provides both descriptors.
+//! Windows reads them only once per VID/PID, and caches the result in the registry.
+//!
+//! Requires `wcid` feature.
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usb_device::device::UsbDevice;
+//! # use usbd_dfu::wcid::WcidClass;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: impl DfuMemory, mut usb_dev: UsbDevice<B>) {
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, my_mem);
+//! // DFU interface is the first interface of the device
+//! let mut wcid = WcidClass::new(0x20, 0);
+//!
+//! loop {
+//!     usb_dev.poll(&mut [&mut dfu, &mut wcid]);
+//! }
+//! # }
+//! ```
+
+use usb_device::bus::UsbBus;
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+
+/// Index of the Microsoft OS string descriptor.
+pub const OS_STRING_INDEX: u8 = 0xEE;
+
+/// *Extended Compat ID* OS feature descriptor, sent in *wIndex* of the vendor request.
+pub const EXTENDED_COMPAT_ID_INDEX: u16 = 0x0004;
+
+/// Size of the *Extended Compat ID* OS feature descriptor with a single function.
+pub const EXTENDED_COMPAT_ID_LENGTH: usize = 16 + 24;
+
+/// [`UsbClass`] that implements Microsoft OS 1.0 descriptors for WinUSB.
+///
+/// The class has no interfaces.
+pub struct WcidClass {
+    vendor_code: u8,
+    interface: u8,
+    /// `MSFT100` signature followed by the vendor code character.
+    os_string: [u8; 9],
+    os_string_len: usize,
+}
+
+impl WcidClass {
+    /// Creates a class that uses `vendor_code` as *bRequest* of OS feature descriptor
+    /// requests, and sets the `WINUSB` compatible ID for `interface`, the DFU interface number.
+    pub fn new(vendor_code: u8, interface: u8) -> Self {
+        let mut os_string = [0; 9];
+        os_string[..7].copy_from_slice(b"MSFT100");
+        // bMS_VendorCode and bPad are the last UTF-16 character
+        let len = char::from(vendor_code)
+            .encode_utf8(&mut os_string[7..])
+            .len();
+
+        Self {
+            vendor_code,
+            interface,
+            os_string,
+            os_string_len: 7 + len,
+        }
+    }
+
+    /// Returns *bRequest* of OS feature descriptor requests.
+    pub fn vendor_code(&self) -> u8 {
+        self.vendor_code
+    }
+
+    /// Returns the interface number with the `WINUSB` compatible ID.
+    pub fn interface(&self) -> u8 {
+        self.interface
+    }
+
+    /// Returns the *Extended Compat ID* OS feature descriptor.
+    pub fn extended_compat_id(&self) -> [u8; EXTENDED_COMPAT_ID_LENGTH] {
+        let mut d = [0; EXTENDED_COMPAT_ID_LENGTH];
+        // header: dwLength, bcdVersion 1.0, wIndex, bCount
+        d[0..4].copy_from_slice(&(EXTENDED_COMPAT_ID_LENGTH as u32).to_le_bytes());
+        d[4..6].copy_from_slice(&0x0100u16.to_le_bytes());
+        d[6..8].copy_from_slice(&EXTENDED_COMPAT_ID_INDEX.to_le_bytes());
+        d[8] = 1;
+        // function: bFirstInterfaceNumber, reserved, compatibleID, subCompatibleID
+        d[16] = self.interface;
+        d[17] = 1;
+        d[18..24].copy_from_slice(b"WINUSB");
+        d
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for WcidClass {
+    fn get_string(&self, index: StringIndex, _lang_id: LangID) -> Option<&str> {
+        if u8::from(index) == OS_STRING_INDEX {
+            return core::str::from_utf8(&self.os_string[..self.os_string_len]).ok();
+        }
+        None
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if req.request_type != RequestType::Vendor
+            || req.recipient != Recipient::Device
+            || req.request != self.vendor_code
+        {
+            return;
+        }
+
+        if req.index == EXTENDED_COMPAT_ID_INDEX {
+            xfer.accept_with(&self.extended_compat_id()).ok();
+        } else {
+            xfer.reject().ok();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! WebUSB descriptors
+//!
+//! Browsers access DFU devices with WebUSB, e.g. with WebDFU, the JavaScript port
+//! of `dfu-util`. [`WebUsbClass`] is a companion [`UsbClass`] that adds the WebUSB
+//! platform capability to the BOS descriptor, and answers the WebUSB *GET_URL*
+//! request with the optional landing page URL. Browsers may show a notification
+//! with the landing page when the device is connected.
+//!
+//! As with [`BosClass`](crate::bos::BosClass), the device must be built with
+//! [`UsbRev::Usb210`](usb_device::device::UsbRev::Usb210):
+//!
+//! ```no_run
+//! # use usb_device::bus::{UsbBus, UsbBusAllocator};
+//! # use usb_device::device::UsbRev;
+//! # use usb_device::prelude::*;
+//! # use usbd_dfu::webusb::WebUsbClass;
+//! # use usbd_dfu::{DfuClass, DfuMemory};
+//! # fn example<B: UsbBus>(usb_bus_alloc: UsbBusAllocator<B>, my_mem: impl DfuMemory) {
+//! let mut dfu = DfuClass::new(&usb_bus_alloc, my_mem);
+//! let mut webusb = WebUsbClass::new(0x01, Some("https://example.com/update"));
+//!
+//! let mut usb_dev = UsbDeviceBuilder::new(&usb_bus_alloc, UsbVidPid(0x1209, 0x0001))
+//!     .usb_rev(UsbRev::Usb210)
+//!     .build();
+//!
+//! loop {
+//!     usb_dev.poll(&mut [&mut dfu, &mut webusb]);
+//! }
+//! # }
+//! ```
+
+use usb_device::bus::UsbBus;
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+use usb_device::descriptor::capability_type;
+
+use crate::bos::PlatformCapability;
+
+/// WebUSB platform capability UUID `{3408b638-09a9-47a0-8bfd-a0768815b665}`.
+pub const WEBUSB_UUID: [u8; 16] = [
+    0x38, 0xb6, 0x08, 0x34, 0xa9, 0x09, 0xa0, 0x47, 0x8b, 0xfd, 0xa0, 0x76, 0x88, 0x15, 0xb6, 0x65,
+];
+
+/// WebUSB *GET_URL* request, sent in *wIndex* of the vendor request.
+pub const WEBUSB_GET_URL: u16 = 2;
+
+/// WebUSB URL descriptor type.
+pub const WEBUSB_URL_DESCRIPTOR_TYPE: u8 = 3;
+
+/// Maximum length of a landing page URL without the scheme prefix.
+pub const MAX_URL_LENGTH: usize = 255 - 3;
+
+/// Landing page URL descriptor index.
+const LANDING_PAGE_INDEX: u8 = 1;
+
+/// Split `url` into the WebUSB *bScheme* and the rest of the URL.
+fn url_scheme(url: &str) -> (u8, &str) {
+    if let Some(rest) = url.strip_prefix("https://") {
+        (1, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (0, rest)
+    } else {
+        (255, url)
+    }
+}
+
+/// [`UsbClass`] that implements WebUSB descriptors.
+///
+/// The class has no interfaces, the DFU interface is accessed by the browser directly.
+pub struct WebUsbClass<'a> {
+    vendor_code: u8,
+    landing_page: Option<&'a str>,
+}
+
+impl<'a> WebUsbClass<'a> {
+    /// Creates a class that uses `vendor_code` as *bRequest* of WebUSB requests.
+    ///
+    /// `landing_page` is a complete URL, e.g. `https://example.com/`, `http://` and
+    /// `https://` prefixes are sent as the URL scheme.
+    ///
+    /// Panics if the landing page URL is longer than [`MAX_URL_LENGTH`] without the prefix.
+    pub fn new(vendor_code: u8, landing_page: Option<&'a str>) -> Self {
+        if let Some(url) = landing_page {
+            assert!(
+                url_scheme(url).1.len() <= MAX_URL_LENGTH,
+                "WebUSB landing page URL is too long"
+            );
+        }
+
+        Self {
+            vendor_code,
+            landing_page,
+        }
+    }
+
+    /// Returns *bRequest* of WebUSB requests.
+    pub fn vendor_code(&self) -> u8 {
+        self.vendor_code
+    }
+
+    /// Returns the landing page URL.
+    pub fn landing_page(&self) -> Option<&'a str> {
+        self.landing_page
+    }
+
+    /// Write the URL descriptor of `url` to `dst`, returns the descriptor length.
+    fn url_descriptor(url: &str, dst: &mut [u8; 255]) -> usize {
+        let (scheme, url) = url_scheme(url);
+        let len = 3 + url.len();
+        dst[0] = len as u8;
+        dst[1] = WEBUSB_URL_DESCRIPTOR_TYPE;
+        dst[2] = scheme;
+        dst[3..len].copy_from_slice(url.as_bytes());
+        len
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for WebUsbClass<'_> {
+    fn get_bos_descriptors(&self, writer: &mut BosWriter) -> usb_device::Result<()> {
+        let landing_page = match self.landing_page {
+            Some(_) => LANDING_PAGE_INDEX,
+            None => 0,
+        };
+        // bcdVersion 1.0, bVendorCode, iLandingPage
+        let data = [0x00, 0x01, self.vendor_code, landing_page];
+
+        let mut body = [0; 1 + 16 + 4];
+        let len = PlatformCapability::new(WEBUSB_UUID, &data)
+            .write(&mut body)
+            .map_err(|_| UsbError::BufferOverflow)?;
+        writer.capability(capability_type::PLATFORM, &body[..len])
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+
+        if req.request_type != RequestType::Vendor
+            || req.recipient != Recipient::Device
+            || req.request != self.vendor_code
+            || req.index != WEBUSB_GET_URL
+        {
+            return;
+        }
+
+        match self.landing_page {
+            Some(url) if req.value == LANDING_PAGE_INDEX as u16 => {
+                let mut descriptor = [0; 255];
+                let len = Self::url_descriptor(url, &mut descriptor);
+                xfer.accept_with(&descriptor[..len]).ok();
+            }
+            _ => {
+                xfer.reject().ok();
+            }
+        }
+    }
+}
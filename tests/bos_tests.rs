@@ -0,0 +1,41 @@
+use usbd_dfu::bos::*;
+
+// WebUSB platform capability UUID {3408b638-09a9-47a0-8bfd-a0768815b665}
+const UUID: [u8; 16] = [
+    0x38, 0xb6, 0x08, 0x34, 0xa9, 0x09, 0xa0, 0x47, 0x8b, 0xfd, 0xa0, 0x76, 0x88, 0x15, 0xb6, 0x65,
+];
+
+#[test]
+fn test_platform_capability_write() {
+    let cap = PlatformCapability::new(UUID, &[0x00, 0x01, 0x01, 0x01]);
+    let mut buf = [0xff; 32];
+
+    let len = cap.write(&mut buf).unwrap();
+
+    assert_eq!(len, 21);
+    assert_eq!(buf[0], 0);
+    assert_eq!(buf[1..17], UUID);
+    assert_eq!(buf[17..21], [0x00, 0x01, 0x01, 0x01]);
+    assert_eq!(buf[21], 0xff);
+    assert_eq!(len + 3, PLATFORM_CAPABILITY_HEADER_LENGTH + cap.data.len());
+}
+
+#[test]
+fn test_platform_capability_write_too_small() {
+    let cap = PlatformCapability::new(UUID, &[1, 2, 3]);
+    let mut buf = [0; 19];
+
+    assert_eq!(cap.write(&mut buf), Err(()));
+}
+
+#[test]
+fn test_platform_capability_data_too_long() {
+    let data = [0; MAX_PLATFORM_CAPABILITY_DATA_LENGTH + 1];
+    let mut buf = [0; 512];
+
+    let cap = PlatformCapability::new(UUID, &data[..MAX_PLATFORM_CAPABILITY_DATA_LENGTH]);
+    assert_eq!(cap.write(&mut buf), Ok(252));
+
+    let cap = PlatformCapability::new(UUID, &data);
+    assert_eq!(cap.write(&mut buf), Err(()));
+}
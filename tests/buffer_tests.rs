@@ -0,0 +1,60 @@
+use usbd_dfu::buffer::AlignedBuffer;
+
+fn check_alignment<const N: usize, const ALIGN: usize>(buffer: &AlignedBuffer<N, ALIGN>)
+where
+    usbd_dfu::buffer::Align<ALIGN>: usbd_dfu::buffer::Alignment,
+{
+    assert_eq!(buffer.as_ptr() as usize % ALIGN, 0);
+    assert_eq!(core::mem::align_of::<AlignedBuffer<N, ALIGN>>(), ALIGN);
+    assert_eq!(buffer.len(), N);
+}
+
+#[test]
+fn test_alignment() {
+    check_alignment(&AlignedBuffer::<1, 1>::new());
+    check_alignment(&AlignedBuffer::<7, 2>::new());
+    check_alignment(&AlignedBuffer::<128, 4>::new());
+    check_alignment(&AlignedBuffer::<100, 8>::new());
+    check_alignment(&AlignedBuffer::<256, 16>::new());
+    check_alignment(&AlignedBuffer::<33, 32>::new());
+    check_alignment(&AlignedBuffer::<64, 64>::new());
+    check_alignment(&AlignedBuffer::<1024, 128>::new());
+}
+
+#[test]
+fn test_aligned_in_struct() {
+    struct Memory {
+        _flag: u8,
+        buffer: AlignedBuffer<13, 8>,
+    }
+
+    let mem = [
+        Memory {
+            _flag: 0,
+            buffer: AlignedBuffer::new(),
+        },
+        Memory {
+            _flag: 1,
+            buffer: AlignedBuffer::new(),
+        },
+    ];
+    for m in &mem {
+        check_alignment(&m.buffer);
+    }
+
+    // size is padded to the alignment
+    assert_eq!(core::mem::size_of::<AlignedBuffer<13, 8>>(), 16);
+}
+
+#[test]
+fn test_access() {
+    let mut buffer = AlignedBuffer::<8, 4>::default();
+    assert_eq!(*buffer, [0; 8]);
+
+    buffer[2..6].copy_from_slice(&[1, 2, 3, 4]);
+    buffer[7] = 5;
+    assert_eq!(*buffer, [0, 0, 1, 2, 3, 4, 0, 5]);
+
+    let copy = buffer;
+    assert_eq!(&copy[..], &buffer[..]);
+}
@@ -0,0 +1,121 @@
+#![allow(unused_variables)]
+
+use std::cell::Cell;
+
+use usbd_dfu::cache::*;
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+const TESTMEMSIZE: usize = 200;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    reads: Cell<usize>,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/1*200 g";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        self.reads.set(self.reads.get() + 1);
+        let from = address
+            .checked_sub(TESTMEM_BASE)
+            .ok_or(DfuMemoryError::Address)? as usize;
+        if from > TESTMEMSIZE {
+            return Err(DfuMemoryError::Address);
+        }
+        Ok(&self.memory[from..(from + length).min(TESTMEMSIZE)])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+fn cached_mem() -> CachedMemory<TestMem, 128> {
+    CachedMemory::new(TestMem {
+        memory: core::array::from_fn(|i| i as u8),
+        reads: Cell::new(0),
+    })
+}
+
+/// Returns the number of reads from the wrapped memory, drops the cache.
+fn reads(mem: &mut CachedMemory<TestMem, 128>) -> usize {
+    mem.memory().reads.take()
+}
+
+fn bytes(from: usize, to: usize) -> Vec<u8> {
+    (from..to).map(|i| i as u8).collect()
+}
+
+#[test]
+fn test_cache_hits() {
+    let mut mem = cached_mem();
+
+    // two transfers are read
+    assert_eq!(mem.read(TESTMEM_BASE, 32).expect("read"), bytes(0, 32));
+    assert_eq!(
+        mem.read(TESTMEM_BASE + 32, 32).expect("read"),
+        bytes(32, 64)
+    );
+    assert_eq!(
+        mem.read(TESTMEM_BASE + 64, 64).expect("read"),
+        bytes(64, 128)
+    );
+    assert_eq!(mem.read(TESTMEM_BASE, 32).expect("read"), bytes(0, 32));
+    assert_eq!(reads(&mut mem), 2);
+}
+
+#[test]
+fn test_cache_end_of_memory() {
+    let mut mem = cached_mem();
+
+    // 64 bytes and 8 bytes at the end
+    let block = mem.read_block(TESTMEM_BASE + 128, 64).expect("read");
+    assert_eq!(block, ReadOutcome::Full(&bytes(128, 192)));
+
+    let block = mem.read_block(TESTMEM_BASE + 192, 64).expect("read");
+    assert_eq!(block, ReadOutcome::LastChunk(&bytes(192, 200)));
+
+    let block = mem.read_block(TESTMEM_BASE + 200, 64).expect("read");
+    assert_eq!(block, ReadOutcome::LastChunk(&[]));
+
+    assert_eq!(reads(&mut mem), 2);
+}
+
+#[test]
+fn test_cache_invalidate() {
+    let mut mem = cached_mem();
+
+    mem.read(TESTMEM_BASE, 64).expect("read");
+    mem.program(TESTMEM_BASE, 64).expect("program");
+    mem.read(TESTMEM_BASE, 64).expect("read");
+    mem.download_start();
+    mem.read(TESTMEM_BASE, 64).expect("read");
+    mem.usb_reset();
+    mem.read(TESTMEM_BASE, 64).expect("read");
+    mem.invalidate();
+    mem.read(TESTMEM_BASE, 64).expect("read");
+
+    assert_eq!(reads(&mut mem), 10);
+}
+
+#[test]
+fn test_cache_read_error() {
+    let mut mem = cached_mem();
+
+    assert!(matches!(
+        mem.read(TESTMEM_BASE - 64, 64),
+        Err(DfuMemoryError::Address)
+    ));
+    assert_eq!(mem.read(TESTMEM_BASE, 64).expect("read"), bytes(0, 64));
+}
@@ -0,0 +1,98 @@
+#![cfg(all(feature = "test-helpers", feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+use usbd_dfu::class::*;
+use usbd_dfu::conformance::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+const TESTMEMSIZE: usize = 1024;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 64],
+    /// Programmed data is corrupted.
+    broken: bool,
+}
+
+impl TestMem {
+    fn new(broken: bool) -> Self {
+        Self {
+            memory: [0xff; TESTMEMSIZE],
+            buffer: [0; 64],
+            broken,
+        }
+    }
+
+    fn offset(address: u32, length: usize) -> Result<usize, DfuMemoryError> {
+        let from = address
+            .checked_sub(TESTMEM_BASE)
+            .ok_or(DfuMemoryError::Address)? as usize;
+        if from + length > TESTMEMSIZE {
+            return Err(DfuMemoryError::Address);
+        }
+        Ok(from)
+    }
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*64 g";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = Self::offset(address, length)?;
+        Ok(&self.memory[from..from + length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = Self::offset(address, length)?;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        if self.broken {
+            self.memory[from] ^= 1;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        let from = Self::offset(address, 64)?;
+        self.memory[from..from + 64].fill(0xff);
+        Ok(())
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        self.memory.fill(0xff);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_conformance() {
+    let report = run(|| TestMem::new(false));
+    assert!(report.passed(), "{report}");
+    assert_eq!(report.checks.len(), 7);
+    assert!(report.checks.iter().all(|c| c.outcome == Outcome::Pass));
+}
+
+#[test]
+fn test_conformance_failure() {
+    let report = run(|| TestMem::new(true));
+    assert!(!report.passed());
+
+    let failures: Vec<_> = report.failures().map(|c| c.name).collect();
+    assert_eq!(failures, ["upload"]);
+    assert!(report
+        .to_string()
+        .contains("FAIL upload: block 2 data differs"));
+}
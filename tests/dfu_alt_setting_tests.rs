@@ -0,0 +1,165 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::Cell;
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::class::UsbClass;
+use usbd_dfu::class::*;
+
+thread_local! {
+    /// Number of program calls.
+    static WRITES: Cell<usize> = const { Cell::new(0) };
+    /// Number of erase calls.
+    static ERASES: Cell<usize> = const { Cell::new(0) };
+}
+
+test_mem! {
+    except MEM_INFO_STRING, read, program;
+
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/8*1Kg";
+    const ALT_SETTINGS: &'static [AltSetting] = &[
+        AltSetting {
+            name: "@Active/0x08000000/4*1Ka",
+            upload: true,
+            download: false,
+        },
+        AltSetting {
+            name: "@Staging/0x08001000/4*1Kg",
+            upload: false,
+            download: true,
+        },
+    ];
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&[0x5a; 64][..length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        WRITES.set(WRITES.get() + 1);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        ERASES.set(ERASES.get() + 1);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_descriptors() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev
+                .device_get_descriptor(&mut dfu, 2, 0, 0, 130)
+                .expect("vec");
+            assert_eq!(vec.len(), 36);
+
+            assert_eq!(vec[9..18], [9, 4, 0, 0, 0, 0xfe, 1, 2, 4]);
+            assert_eq!(vec[18..27], [9, 4, 0, 1, 0, 0xfe, 1, 2, 5]);
+            // bitCanUpload and bitCanDnload are set by one of the alternate settings
+            assert_eq!(vec[27..30], [9, 0x21, 0x0f]);
+
+            let istr = dev.device_get_string(&mut dfu, 4, 0x409).expect("str");
+            assert_eq!(istr, "@Active/0x08000000/4*1Ka");
+            let istr = dev.device_get_string(&mut dfu, 5, 0x409).expect("str");
+            assert_eq!(istr, "@Staging/0x08001000/4*1Kg");
+
+            dev.interface_set_interface(&mut dfu, 0, 2)
+                .expect_err("stall");
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_read_only() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // address pointer can be set for uploads
+            dev.download(&mut dfu, 0, &[0x21, 0x00, 0x04, 0x00, 0x08])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            dev.abort(&mut dfu).expect("vec");
+
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec, [0x5a; 64]);
+            dev.abort(&mut dfu).expect("vec");
+
+            assert!(dev.download(&mut dfu, 2, &[0x55; 64]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // erase
+            assert!(dev
+                .download(&mut dfu, 0, &[0x41, 0x00, 0x00, 0x00, 0x08])
+                .is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // leave
+            assert!(dev.download(&mut dfu, 0, &[]).is_err());
+            dev.clear_status(&mut dfu).expect("vec");
+
+            assert_eq!(WRITES.take(), 0);
+            assert_eq!(ERASES.take(), 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_write_only() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            dev.interface_set_interface(&mut dfu, 0, 1).expect("set");
+
+            dev.download(&mut dfu, 0, &[0x21, 0x00, 0x10, 0x00, 0x08])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(WRITES.take(), 1);
+            dev.abort(&mut dfu).expect("vec");
+
+            assert!(dev.upload(&mut dfu, 2, 64).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_set_interface_during_upload() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            dev.upload(&mut dfu, 2, 64).expect("vec");
+            let vec = dev.get_state(&mut dfu).expect("vec");
+            assert_eq!(vec, [DFU_UPLOAD_IDLE]);
+
+            // the transfer can't continue in another region
+            dev.interface_set_interface(&mut dfu, 0, 1)
+                .expect_err("stall");
+            let vec = dev.interface_get_interface(&mut dfu).expect("vec");
+            assert_eq!(vec, 0);
+
+            dev.abort(&mut dfu).expect("vec");
+            dev.interface_set_interface(&mut dfu, 0, 1).expect("set");
+            let vec = dev.interface_get_interface(&mut dfu).expect("vec");
+            assert_eq!(vec, 1);
+
+            dfu.reset();
+            let vec = dev.interface_get_interface(&mut dfu).expect("vec");
+            assert_eq!(vec, 0);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,176 @@
+#![cfg(feature = "download")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::RefCell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+const PASS_THROUGH: u8 = 0;
+const IGNORE_DUPLICATES: u8 = 1;
+const STRICT: u8 = 2;
+
+thread_local! {
+    /// Addresses and lengths of [`DfuMemory::program()`] calls.
+    static PROGRAMS: RefCell<Vec<(u32, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+pub struct TestMem<const SEQUENCE: u8> {}
+
+impl<const SEQUENCE: u8> DfuMemory for TestMem<SEQUENCE> {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/128*64Kg";
+    const TRANSFER_SIZE: u16 = 64;
+    const BLOCK_SEQUENCE: BlockSequence = match SEQUENCE {
+        PASS_THROUGH => BlockSequence::PassThrough,
+        IGNORE_DUPLICATES => BlockSequence::IgnoreDuplicates,
+        _ => BlockSequence::Strict,
+    };
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&[])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        PROGRAMS.with_borrow_mut(|p| p.push((address, length)));
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+type Dev<'a, const SEQUENCE: u8> =
+    Device<'a, DfuClass<EmulatedUsbBus, TestMem<SEQUENCE>>, MkDFU<TestMem<SEQUENCE>>>;
+
+/// Download a block and wait until it's programmed.
+fn block<const SEQUENCE: u8>(
+    dev: &mut Dev<SEQUENCE>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem<SEQUENCE>>,
+    block_num: u16,
+    length: usize,
+) {
+    dev.download(dfu, block_num, &vec![0x55; length])
+        .expect("vec");
+    dev.get_status(dfu).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+}
+
+#[test]
+fn test_pass_through() {
+    MkDFU::new(|| TestMem::<PASS_THROUGH> {})
+        .with_usb(|mut dfu, mut dev| {
+            for block_num in [2, 2, 4] {
+                block(&mut dev, &mut dfu, block_num, 64);
+            }
+
+            assert_eq!(
+                PROGRAMS.take(),
+                [
+                    (TESTMEM_BASE, 64),
+                    (TESTMEM_BASE, 64),
+                    (TESTMEM_BASE + 128, 64)
+                ]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_ignore_duplicates() {
+    MkDFU::new(|| TestMem::<IGNORE_DUPLICATES> {})
+        .with_usb(|mut dfu, mut dev| {
+            block(&mut dev, &mut dfu, 2, 64);
+            block(&mut dev, &mut dfu, 2, 64);
+            block(&mut dev, &mut dfu, 3, 64);
+            // not the same length
+            block(&mut dev, &mut dfu, 3, 32);
+            // gaps are not checked
+            block(&mut dev, &mut dfu, 5, 64);
+
+            assert_eq!(
+                PROGRAMS.take(),
+                [
+                    (TESTMEM_BASE, 64),
+                    (TESTMEM_BASE + 64, 64),
+                    (TESTMEM_BASE + 64, 32),
+                    (TESTMEM_BASE + 192, 64)
+                ]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_strict_gap() {
+    MkDFU::new(|| TestMem::<STRICT> {})
+        .with_usb(|mut dfu, mut dev| {
+            // the first block can have any number
+            block(&mut dev, &mut dfu, 3, 64);
+            block(&mut dev, &mut dfu, 3, 64);
+            block(&mut dev, &mut dfu, 4, 64);
+
+            dev.download(&mut dfu, 6, &[0x55; 64]).expect_err("stall");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+
+            assert_eq!(
+                PROGRAMS.take(),
+                [(TESTMEM_BASE + 64, 64), (TESTMEM_BASE + 128, 64)]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_strict_out_of_order() {
+    MkDFU::new(|| TestMem::<STRICT> {})
+        .with_usb(|mut dfu, mut dev| {
+            block(&mut dev, &mut dfu, 2, 64);
+            block(&mut dev, &mut dfu, 3, 64);
+
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect_err("stall");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_strict_set_address_pointer() {
+    MkDFU::new(|| TestMem::<STRICT> {})
+        .with_usb(|mut dfu, mut dev| {
+            block(&mut dev, &mut dfu, 2, 64);
+            block(&mut dev, &mut dfu, 3, 64);
+
+            // DfuSe hosts start again from block 2
+            dev.download(&mut dfu, 0, &[0x21, 0x00, 0x10, 0x00, 0x08])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            block(&mut dev, &mut dfu, 2, 64);
+
+            // a new download, block 2 follows block 65535
+            dev.abort(&mut dfu).expect("vec");
+            block(&mut dev, &mut dfu, 0xffff, 64);
+            block(&mut dev, &mut dfu, 2, 64);
+
+            assert_eq!(PROGRAMS.take().len(), 5);
+        })
+        .expect("with_usb");
+}
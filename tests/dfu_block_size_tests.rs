@@ -0,0 +1,89 @@
+#![cfg(feature = "download")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::RefCell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+thread_local! {
+    /// Arguments of [`DfuMemory::block_size_mismatch()`] calls.
+    static MISMATCHES: RefCell<Vec<(u16, u16)>> = const { RefCell::new(Vec::new()) };
+}
+
+test_mem! {
+    fn block_size_mismatch(&mut self, block_num: u16, length: u16) {
+        MISMATCHES.with_borrow_mut(|m| m.push((block_num, length)));
+    }
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem>,
+    blocks: &[(u16, usize)],
+) {
+    for &(block_num, length) in blocks {
+        let data = vec![0x55; length];
+        dev.download(dfu, block_num, &data).expect("vec");
+        dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+}
+
+#[test]
+fn test_block_sizes() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            assert_eq!(dfu.download_block_sizes(), None);
+
+            // only the last block is short
+            download(&mut dev, &mut dfu, &[(2, 64), (3, 64), (4, 10)]);
+            assert_eq!(
+                dfu.download_block_sizes(),
+                Some(BlockSizes { min: 10, max: 64 })
+            );
+            assert_eq!(MISMATCHES.take(), []);
+
+            dev.download(&mut dfu, 5, &[]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // kept after the download
+            assert_eq!(
+                dfu.download_block_sizes(),
+                Some(BlockSizes { min: 10, max: 64 })
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_block_size_mismatch() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // host uses 32-byte blocks, a repeated block is not a mismatch
+            download(&mut dev, &mut dfu, &[(2, 32), (2, 32), (3, 32), (4, 32)]);
+            assert_eq!(
+                dfu.download_block_sizes(),
+                Some(BlockSizes { min: 32, max: 32 })
+            );
+            assert_eq!(MISMATCHES.take(), [(0, 32), (1, 32)]);
+
+            // a new download resets sizes
+            dev.abort(&mut dfu).expect("vec");
+            download(&mut dev, &mut dfu, &[(2, 64)]);
+            assert_eq!(
+                dfu.download_block_sizes(),
+                Some(BlockSizes { min: 64, max: 64 })
+            );
+            assert_eq!(MISMATCHES.take(), []);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,75 @@
+#![cfg(feature = "download")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+test_mem! {}
+
+type Builder = DfuClassBuilder<EmulatedUsbBus, TestMem>;
+
+/// Class built with `options`.
+fn mk(options: fn(Builder) -> Builder) -> MkDFU<TestMem> {
+    MkDFU::builder(move |alloc| options(DfuClass::builder(TestMem {})).build(alloc))
+}
+
+#[test]
+fn test_builder_defaults() {
+    mk(|b| b)
+        .with_usb(|mut dfu, mut dev| {
+            assert_eq!(u8::from(dfu.get_interface_number()), 0);
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+            assert!(!dfu.tick(u32::MAX));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_builder_initial_state() {
+    mk(|b| b.initial_state(InitialState::FirmwareCorrupted))
+        .with_usb(|mut dfu, mut dev| {
+            // the status of the first error is kept
+            assert!(dev.download(&mut dfu, 2, &[0x55; 64]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_FIRMWARE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+
+    mk(|b| b.initial_state(InitialState::UnexpectedReset))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_POR, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_builder_strict() {
+    mk(|b| {
+        b.initial_state(InitialState::FirmwareCorrupted)
+            .strict(true)
+    })
+    .with_usb(|mut dfu, mut dev| {
+        assert!(dev.download(&mut dfu, 2, &[0x55; 64]).is_err());
+        let vec = dev.get_status(&mut dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+    })
+    .expect("with_usb");
+}
+
+#[test]
+fn test_builder_session_timeout() {
+    mk(|b| b.session_timeout_ms(1000))
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            assert!(dfu.tick(1000));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
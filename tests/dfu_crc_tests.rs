@@ -0,0 +1,109 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::suffix::Crc32;
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    manifested: bool,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+    const IMAGE_CRC_COMMAND: bool = true;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.manifested = true;
+        Ok(())
+    }
+}
+
+const IMAGE_LEN: usize = 300;
+
+/// CRC-32 as calculated by zlib
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    !crc.finalize()
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem>,
+    crc: u32,
+) {
+    let c = crc.to_le_bytes();
+    let vec = dev
+        .download(dfu, 0, &[0xb1, c[0], c[1], c[2], c[3]])
+        .expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+    for (i, block) in image(IMAGE_LEN).chunks(128).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+}
+
+#[test]
+fn test_get_commands() {
+    MkDFU::new(|| TestMem { manifested: false })
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41, 0xb1]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_image_crc() {
+    MkDFU::new(|| TestMem { manifested: false })
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, crc32(&image(IMAGE_LEN)));
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            assert!(dfu.release().manifested);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_image_crc_mismatch() {
+    MkDFU::new(|| TestMem { manifested: false })
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, crc32(&image(IMAGE_LEN)) ^ 1);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_VERIFY, 0, DFU_ERROR));
+
+            assert!(!dfu.release().manifested);
+        })
+        .expect("with_usb");
+}
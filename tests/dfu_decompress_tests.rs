@@ -0,0 +1,189 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::decompress::*;
+
+const TESTMEMSIZE: usize = 2048;
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 64],
+    programs: Vec<(u32, usize)>,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/2*1Kg";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        assert!(length <= Self::TRANSFER_SIZE as usize);
+        let from = (address - TESTMEM_BASE) as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        self.programs.push((address, length));
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+/// Run-length decoder of (count, byte) pairs, `count` is never 0
+struct RleDecoder {
+    count: Option<u8>,
+    pending: (u8, u8),
+}
+
+impl Decompressor for RleDecoder {
+    fn reset(&mut self) {
+        self.count = None;
+        self.pending = (0, 0);
+    }
+
+    fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<(usize, usize), DfuMemoryError> {
+        let mut consumed = 0;
+        let mut produced = 0;
+
+        loop {
+            while self.pending.0 > 0 && produced < output.len() {
+                output[produced] = self.pending.1;
+                produced += 1;
+                self.pending.0 -= 1;
+            }
+            if self.pending.0 > 0 || consumed == input.len() {
+                return Ok((consumed, produced));
+            }
+
+            let b = input[consumed];
+            consumed += 1;
+            match self.count.take() {
+                None if b == 0 => return Err(DfuMemoryError::File),
+                None => self.count = Some(b),
+                Some(count) => self.pending = (count, b),
+            }
+        }
+    }
+
+    fn finish(&mut self, output: &mut [u8]) -> Result<usize, DfuMemoryError> {
+        if self.count.is_some() {
+            return Err(DfuMemoryError::File);
+        }
+        self.decompress(&[], output).map(|(_, produced)| produced)
+    }
+}
+
+type Mem = DecompressMemory<TestMem, RleDecoder, 64>;
+
+fn mem() -> Mem {
+    let mem = TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; 64],
+        programs: Vec::new(),
+    };
+    let decoder = RleDecoder {
+        count: None,
+        pending: (0, 0),
+    };
+    DecompressMemory::new(mem, decoder)
+}
+
+/// 1975 bytes compressed to 2 * 16 bytes
+fn compressed() -> (Vec<u8>, Vec<u8>) {
+    let mut file = Vec::new();
+    let mut image = Vec::new();
+    for i in 0..16 {
+        let count = if i == 15 { 25 } else { 200 - i * 10 };
+        file.extend_from_slice(&[count, i]);
+        image.extend(std::iter::repeat_n(i, count as usize));
+    }
+    (file, image)
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    file: &[u8],
+    block_size: usize,
+) {
+    for (i, block) in file.chunks(block_size).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+}
+
+fn manifestation<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+) -> Vec<u8> {
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_decompress() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let (file, image) = compressed();
+            // a pair is split between blocks
+            download(&mut dev, &mut dfu, &file, 15);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let mem = dfu.release().release();
+            assert_eq!(image.len(), 1975);
+            assert_eq!(&mem.memory[..1975], &image[..]);
+            assert!(mem.memory[1975..].iter().all(|&b| b == 0xff));
+
+            // the last incomplete block is programmed before manifestation
+            assert_eq!(mem.programs.len(), 31);
+            assert_eq!(mem.programs[30], (TESTMEM_BASE + 30 * 64, 55));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_decompress_invalid() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.download(&mut dfu, 2, &[10, 1, 0, 1]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_decompress_truncated() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let (file, image) = compressed();
+            download(&mut dev, &mut dfu, &file[..file.len() - 1], 16);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
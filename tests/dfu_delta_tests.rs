@@ -0,0 +1,202 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::delta::*;
+
+const TESTMEMSIZE: usize = 4096;
+const TESTMEM_BASE: u32 = 0x0200_0000;
+const OLD_SIZE: u32 = 1000;
+const NEW_BASE: u32 = TESTMEM_BASE + 1024;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 64],
+    programs: Vec<(u32, usize)>,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = NEW_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/4*1Kg";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(&self.memory[from..from + length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        assert!(length <= Self::TRANSFER_SIZE as usize);
+        let from = (address - TESTMEM_BASE) as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        self.programs.push((address, length));
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+type Mem = DeltaMemory<TestMem, 64>;
+
+fn mem() -> Mem {
+    let mut mem = TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; 64],
+        programs: Vec::new(),
+    };
+    mem.memory[..OLD_SIZE as usize].copy_from_slice(&old_image());
+    DeltaMemory::new(mem, TESTMEM_BASE, OLD_SIZE)
+}
+
+fn old_image() -> Vec<u8> {
+    (0..OLD_SIZE).map(|i| (i * 7) as u8).collect()
+}
+
+/// New image and the patch from the old image
+fn patch() -> (Vec<u8>, Vec<u8>) {
+    let old = old_image();
+    let mut image = Vec::new();
+    let mut file = Vec::new();
+
+    // copy
+    image.extend_from_slice(&old[..500]);
+    file.push(PATCH_COPY);
+    file.extend_from_slice(&0u32.to_le_bytes());
+    file.extend_from_slice(&500u32.to_le_bytes());
+
+    // add
+    let diff: Vec<u8> = (0..100u32).map(|i| (i % 3) as u8).collect();
+    image.extend(
+        old[500..600]
+            .iter()
+            .zip(&diff)
+            .map(|(o, d)| o.wrapping_add(*d)),
+    );
+    file.push(PATCH_ADD);
+    file.extend_from_slice(&500u32.to_le_bytes());
+    file.extend_from_slice(&100u32.to_le_bytes());
+    file.extend_from_slice(&diff);
+
+    // insert
+    let data = [0x5a; 70];
+    image.extend_from_slice(&data);
+    file.push(PATCH_INSERT);
+    file.extend_from_slice(&70u32.to_le_bytes());
+    file.extend_from_slice(&data);
+
+    // copy the rest
+    image.extend_from_slice(&old[600..]);
+    file.push(PATCH_COPY);
+    file.extend_from_slice(&600u32.to_le_bytes());
+    file.extend_from_slice(&400u32.to_le_bytes());
+
+    let mut header = PATCH_MAGIC.to_vec();
+    header.extend_from_slice(&(image.len() as u32).to_le_bytes());
+    header.extend_from_slice(&file);
+    (header, image)
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    file: &[u8],
+    block_size: usize,
+) {
+    for (i, block) in file.chunks(block_size).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+}
+
+fn manifestation<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+) -> Vec<u8> {
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_delta() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let (file, image) = patch();
+            // commands are split between blocks
+            download(&mut dev, &mut dfu, &file, 7);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let mem = dfu.release().release();
+            let new = (NEW_BASE - TESTMEM_BASE) as usize;
+            assert_eq!(image.len(), 1070);
+            assert_eq!(&mem.memory[new..new + 1070], &image[..]);
+            assert_eq!(&mem.memory[..OLD_SIZE as usize], &old_image()[..]);
+
+            // the last incomplete block is programmed before manifestation
+            assert_eq!(mem.programs.len(), 17);
+            assert_eq!(mem.programs[16], (NEW_BASE + 16 * 64, 46));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_delta_invalid_magic() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev
+                .download(&mut dfu, 2, b"DIFF\x00\x01\x00\x00")
+                .expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_delta_out_of_old_image() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let mut file = PATCH_MAGIC.to_vec();
+            file.extend_from_slice(&100u32.to_le_bytes());
+            file.push(PATCH_COPY);
+            file.extend_from_slice(&950u32.to_le_bytes());
+            file.extend_from_slice(&100u32.to_le_bytes());
+
+            let vec = dev.download(&mut dfu, 2, &file).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_delta_truncated() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let (file, image) = patch();
+            download(&mut dev, &mut dfu, &file[..file.len() - 9], 32);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
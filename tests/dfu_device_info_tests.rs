@@ -0,0 +1,142 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::info::*;
+
+const INFO: DeviceInfo = DeviceInfo {
+    serial_number: "SN0042",
+    unique_id: &[0x10, 0x32, 0x54, 0x76, 0x98, 0xba, 0xdc, 0xfe],
+    hardware_revision: 3,
+    bootloader_version: 0x0001_0200,
+    application_version: Some(7),
+};
+
+test_mem! {
+    except store_write_buffer;
+
+    const DEVICE_INFO_COMMAND: bool = true;
+
+    fn device_info(&mut self) -> DeviceInfo<'_> {
+        INFO
+    }
+}
+
+#[test]
+fn test_encode_decode() {
+    let mut buf = [0; MAX_DEVICE_INFO_LENGTH];
+    let len = INFO.encode(&mut buf).expect("encode");
+    assert_eq!(
+        buf[..len],
+        [
+            0x01, 6, b'S', b'N', b'0', b'0', b'4', b'2', // serial number
+            0x05, 8, 0x10, 0x32, 0x54, 0x76, 0x98, 0xba, 0xdc, 0xfe, // unique ID
+            0x02, 2, 3, 0, // hardware revision
+            0x03, 4, 0x00, 0x02, 0x01, 0x00, // bootloader version
+            0x04, 4, 7, 0, 0, 0, // application version
+        ]
+    );
+    assert_eq!(DeviceInfo::decode(&buf[..len]), Some(INFO));
+
+    // unknown records are skipped
+    let data = [0x7f, 1, 0xaa, 0x02, 2, 5, 0];
+    let info = DeviceInfo::decode(&data).expect("decode");
+    assert_eq!(info.hardware_revision, 5);
+    assert_eq!(info.application_version, None);
+    assert_eq!(info.unique_id, []);
+
+    // truncated record
+    assert_eq!(DeviceInfo::decode(&buf[..len - 1]), None);
+
+    let long = "0123456789abcdef0123456789abcdef0";
+    let info = DeviceInfo {
+        serial_number: long,
+        ..INFO
+    };
+    assert_eq!(info.encode(&mut buf), None);
+
+    let info = DeviceInfo {
+        unique_id: &[0; MAX_UNIQUE_ID_LENGTH + 1],
+        ..INFO
+    };
+    assert_eq!(info.encode(&mut buf), None);
+
+    // all fields at their maximum length fit
+    let info = DeviceInfo {
+        serial_number: &long[..MAX_SERIAL_NUMBER_LENGTH],
+        unique_id: &[0xff; MAX_UNIQUE_ID_LENGTH],
+        ..INFO
+    };
+    let len = info.encode(&mut buf).expect("encode");
+    assert_eq!(len, MAX_DEVICE_INFO_LENGTH);
+    assert_eq!(DeviceInfo::decode(&buf[..len]), Some(info));
+}
+
+#[test]
+fn test_get_device_info() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41, 0xb8]);
+
+            dev.download(&mut dfu, 0, &[0xb8]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            dev.abort(&mut dfu).expect("vec");
+
+            let vec = dev.upload(&mut dfu, 1, 128).expect("vec");
+            assert_eq!(DeviceInfo::decode(&vec), Some(INFO));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_get_device_info_short_request() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 0, &[0xb8]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.abort(&mut dfu).expect("vec");
+
+            // the reply doesn't fit
+            assert!(dev.upload(&mut dfu, 1, 8).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_get_device_info_exact_request() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let mut buf = [0; MAX_DEVICE_INFO_LENGTH];
+            let len = INFO.encode(&mut buf).expect("encode");
+
+            dev.download(&mut dfu, 0, &[0xb8]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.abort(&mut dfu).expect("vec");
+
+            // wLength of the encoded reply is enough
+            let vec = dev.upload(&mut dfu, 1, len).expect("vec");
+            assert_eq!(vec, buf[..len]);
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            dev.download(&mut dfu, 0, &[0xb8]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.abort(&mut dfu).expect("vec");
+
+            // one byte short
+            assert!(dev.upload(&mut dfu, 1, len - 1).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "download")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::Cell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+thread_local! {
+    /// The application uses the memory.
+    static BUSY: Cell<bool> = const { Cell::new(false) };
+    /// Erase is refused.
+    static NO_ERASE: Cell<bool> = const { Cell::new(false) };
+    /// Number of program and erase calls.
+    static WRITES: Cell<usize> = const { Cell::new(0) };
+}
+
+test_mem! {
+    except program;
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        WRITES.set(WRITES.get() + 1);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        WRITES.set(WRITES.get() + 1);
+        Ok(())
+    }
+
+    fn may_start_download(&mut self) -> bool {
+        !BUSY.get()
+    }
+
+    fn may_erase(&mut self) -> bool {
+        !NO_ERASE.get()
+    }
+}
+
+#[test]
+fn test_may_start_download() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            BUSY.set(true);
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+            assert_eq!(WRITES.take(), 0);
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // retry when the memory is free
+            BUSY.set(false);
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(WRITES.take(), 1);
+
+            // only the start of a download is checked
+            BUSY.set(true);
+            dev.download(&mut dfu, 3, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(WRITES.take(), 1);
+            BUSY.set(false);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_may_erase() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            NO_ERASE.set(true);
+            dev.download(&mut dfu, 0, &[0x41, 0x00, 0x00, 0x00, 0x08])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // Erase All
+            dev.download(&mut dfu, 0, &[0x41]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+            assert_eq!(WRITES.take(), 0);
+
+            // programming is not affected
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(WRITES.take(), 1);
+            NO_ERASE.set(false);
+        })
+        .expect("with_usb");
+}
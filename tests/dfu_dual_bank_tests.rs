@@ -0,0 +1,131 @@
+#![cfg(feature = "stm32-dual-bank")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::dual_bank::*;
+
+const TESTMEM_BASE: u32 = 0x0808_0000;
+
+pub struct TestMem {
+    fail_manifestation: bool,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08080000/2*1Kg";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        if self.fail_manifestation {
+            return Err(DfuManifestationError::File);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct TestOptionBytes {
+    bfb2: bool,
+    unlocked: bool,
+    calls: Vec<&'static str>,
+}
+
+impl OptionBytes for TestOptionBytes {
+    fn unlock(&mut self) -> Result<(), ()> {
+        self.unlocked = true;
+        self.calls.push("unlock");
+        Ok(())
+    }
+
+    fn bfb2(&mut self) -> bool {
+        self.bfb2
+    }
+
+    fn program_bfb2(&mut self, value: bool) -> Result<(), ()> {
+        assert!(self.unlocked);
+        self.bfb2 = value;
+        self.calls.push("program");
+        Ok(())
+    }
+
+    fn lock(&mut self) {
+        self.unlocked = false;
+        self.calls.push("lock");
+    }
+
+    fn launch(&mut self) {
+        self.calls.push("launch");
+    }
+}
+
+type Mem = DualBankSwap<TestMem, TestOptionBytes>;
+
+fn mem(fail_manifestation: bool) -> Mem {
+    let mem = TestMem { fail_manifestation };
+    DualBankSwap::new(mem, TestOptionBytes::default())
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+) -> Vec<u8> {
+    let vec = dev.download(dfu, 2, &[0; 64]).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_bank_swap() {
+    MkDFU::new(|| mem(false))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = download(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_MANIFEST_WAIT_RESET));
+
+            let mut mem = dfu.release();
+            assert!(mem.option_bytes().bfb2);
+            assert_eq!(mem.option_bytes().calls, ["unlock", "program", "lock"]);
+
+            mem.usb_reset();
+            let (mem, option_bytes) = mem.release();
+            assert_eq!(option_bytes.calls.last(), Some(&"launch"));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_bank_swap_manifestation_failed() {
+    MkDFU::new(|| mem(true))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = download(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            let mut mem = dfu.release();
+            mem.usb_reset();
+            let (mem, option_bytes) = mem.release();
+            assert!(!option_bytes.bfb2);
+            assert!(option_bytes.calls.is_empty());
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "download")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::Cell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const MANIFEST: u8 = 0;
+const STALL: u8 = 1;
+const LEAVE: u8 = 2;
+
+thread_local! {
+    /// Number of [`DfuMemory::manifestation()`] calls.
+    static MANIFESTATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+pub struct TestMem<const E: u8> {}
+
+impl<const E: u8> DfuMemory for TestMem<E> {
+    const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 64;
+    const MIN_IMAGE_SIZE: u32 = 64;
+    const EMPTY_DOWNLOAD: EmptyDownload = match E {
+        MANIFEST => EmptyDownload::Manifest,
+        STALL => EmptyDownload::Stall,
+        _ => EmptyDownload::Leave,
+    };
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&[])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        MANIFESTATIONS.set(MANIFESTATIONS.get() + 1);
+        Ok(())
+    }
+}
+
+/// Download a block and a zero-length block, returns the status after it.
+fn download<'a, const E: u8>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, TestMem<E>>, MkDFU<TestMem<E>>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem<E>>,
+) -> Vec<u8> {
+    dev.download(dfu, 2, &[0x55; 64]).expect("vec");
+    dev.get_status(dfu).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    dev.download(dfu, 3, &[]).expect("vec");
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_empty_download_manifest() {
+    MkDFU::new(|| TestMem::<MANIFEST> {})
+        .with_usb(|mut dfu, mut dev| {
+            // a download without data is shorter than MIN_IMAGE_SIZE
+            dev.download(&mut dfu, 0, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            let vec = download(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            assert_eq!(MANIFESTATIONS.take(), 1);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_empty_download_stall() {
+    MkDFU::new(|| TestMem::<STALL> {})
+        .with_usb(|mut dfu, mut dev| {
+            assert!(dev.download(&mut dfu, 0, &[]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+            assert_eq!(MANIFESTATIONS.take(), 0);
+
+            // the end of a download
+            let vec = download(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            assert_eq!(MANIFESTATIONS.take(), 1);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_empty_download_leave() {
+    MkDFU::new(|| TestMem::<LEAVE> {})
+        .with_usb(|mut dfu, mut dev| {
+            // image checks are skipped
+            dev.download(&mut dfu, 0, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+            assert_eq!(MANIFESTATIONS.take(), 1);
+
+            let vec = download(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            assert_eq!(MANIFESTATIONS.take(), 1);
+        })
+        .expect("with_usb");
+}
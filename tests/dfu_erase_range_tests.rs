@@ -0,0 +1,128 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::RefCell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+const TESTMEMSIZE: u32 = 1024;
+
+thread_local! {
+    /// Arguments of [`DfuMemory::erase_range()`] calls.
+    static ERASES: RefCell<Vec<(u32, u32)>> = const { RefCell::new(Vec::new()) };
+}
+
+test_mem! {
+    except INITIAL_ADDRESS_POINTER, FULL_ERASE_TIME_MS, MEM_INFO_STRING, store_write_buffer;
+
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const FULL_ERASE_TIME_MS: u32 = 100;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*64 g";
+    const ERASE_RANGE_COMMAND: bool = true;
+    const ERASE_PAGE_SIZE: u32 = 64;
+
+    fn erase_range(&mut self, address: u32, length: u32) -> Result<(), DfuMemoryError> {
+        if address < TESTMEM_BASE || address - TESTMEM_BASE + length > TESTMEMSIZE {
+            return Err(DfuMemoryError::Address);
+        }
+        ERASES.with_borrow_mut(|e| e.push((address, length)));
+        Ok(())
+    }
+}
+
+fn erase_range_command(address: u32, length: u32) -> Vec<u8> {
+    let mut cmd = vec![0xb3];
+    cmd.extend_from_slice(&address.to_le_bytes());
+    cmd.extend_from_slice(&length.to_le_bytes());
+    cmd
+}
+
+#[test]
+fn test_get_commands() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41, 0xb3]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_erase_range() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // 3 pages
+            let cmd = erase_range_command(TESTMEM_BASE + 32, 130);
+            let vec = dev.download(&mut dfu, 0, &cmd).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 60, DFU_DN_BUSY));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            // limited by full erase time
+            let cmd = erase_range_command(TESTMEM_BASE, TESTMEMSIZE);
+            let vec = dev.download(&mut dfu, 0, &cmd).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(
+                vec,
+                status(STATUS_OK, TestMem::FULL_ERASE_TIME_MS, DFU_DN_BUSY)
+            );
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            assert_eq!(
+                ERASES.take(),
+                [(TESTMEM_BASE + 32, 130), (TESTMEM_BASE, TESTMEMSIZE)]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_erase_range_error() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let cmd = erase_range_command(TESTMEM_BASE + 512, 1024);
+            let vec = dev.download(&mut dfu, 0, &cmd).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+
+            let error = dfu.last_memory_error().expect("error");
+            assert_eq!(error.error, DfuMemoryError::Address);
+            assert_eq!(error.address, Some(TESTMEM_BASE + 512));
+            assert_eq!(ERASES.take(), []);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_erase_range_invalid() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // empty range
+            let cmd = erase_range_command(TESTMEM_BASE, 0);
+            assert!(dev.download(&mut dfu, 0, &cmd).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // past the end of the address space
+            let cmd = erase_range_command(u32::MAX, 2);
+            assert!(dev.download(&mut dfu, 0, &cmd).is_err());
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // missing length
+            let cmd = erase_range_command(TESTMEM_BASE, 64);
+            assert!(dev.download(&mut dfu, 0, &cmd[..5]).is_err());
+
+            assert_eq!(ERASES.take(), []);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,98 @@
+#![cfg(feature = "download")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::class::UsbClass;
+use usbd_dfu::class::*;
+
+/// Memory that fails to program every block.
+pub struct TestMem<const STRICT: bool> {}
+
+impl<const STRICT: bool> DfuMemory for TestMem<STRICT> {
+    const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 64;
+    const STRICT: bool = STRICT;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&[])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Err(DfuMemoryError::Prog)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+/// Fail a download with `errPROG` and send a request that's not allowed in `dfuERROR`.
+fn fail_download<const STRICT: bool>(
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem<STRICT>>,
+    dev: &mut Device<'_, DfuClass<EmulatedUsbBus, TestMem<STRICT>>, MkDFU<TestMem<STRICT>>>,
+) {
+    let vec = dev.download(dfu, 2, &[0x55; 64]).expect("vec");
+    assert_eq!(vec, []);
+
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 10, DFU_DN_BUSY));
+
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_ERR_PROG, 0, DFU_ERROR));
+
+    // extra block is rejected
+    assert!(dev.download(dfu, 3, &[0x55; 64]).is_err());
+}
+
+#[test]
+fn test_error_status_sticky() {
+    MkDFU::new(|| TestMem::<false> {})
+        .with_usb(|mut dfu, mut dev| {
+            fail_download(&mut dfu, &mut dev);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_PROG, 0, DFU_ERROR));
+
+            // USB reset doesn't hide the error either
+            dfu.reset();
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_PROG, 0, DFU_ERROR));
+
+            let vec = dev.clear_status(&mut dfu).expect("vec");
+            assert_eq!(vec, []);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_error_status_strict() {
+    MkDFU::new(|| TestMem::<true> {})
+        .with_usb(|mut dfu, mut dev| {
+            fail_download(&mut dfu, &mut dev);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+
+            let vec = dev.clear_status(&mut dfu).expect("vec");
+            assert_eq!(vec, []);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
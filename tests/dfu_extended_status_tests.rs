@@ -0,0 +1,180 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::consts::VENDOR_GET_EXTENDED_STATUS;
+use usbd_dfu::status::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+test_mem! {
+    except INITIAL_ADDRESS_POINTER, FULL_ERASE_TIME_MS, MEM_INFO_STRING, program;
+
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const FULL_ERASE_TIME_MS: u32 = 100;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*64 g";
+    const IMAGE_SIZE_COMMAND: bool = true;
+    const EXTENDED_STATUS_REQUEST: bool = true;
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        if address >= TESTMEM_BASE + 0x200 {
+            return Err(DfuMemoryError::Prog);
+        }
+        Ok(())
+    }
+}
+
+type Dev<'a> = Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>;
+
+fn extended_status(
+    dev: &mut Dev,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem>,
+    index: u16,
+    length: u16,
+) -> AnyResult<Vec<u8>> {
+    dev.control_read(
+        dfu,
+        CtrRequestType::to_host().vendor().interface(),
+        VENDOR_GET_EXTENDED_STATUS,
+        0,
+        index,
+        length,
+    )
+}
+
+#[test]
+fn test_idle() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = extended_status(&mut dev, &mut dfu, 0, 64).expect("vec");
+            assert_eq!(
+                vec,
+                [0, DFU_IDLE, 0, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+            );
+
+            // too short, or another interface
+            extended_status(&mut dev, &mut dfu, 0, 19).expect_err("stall");
+            extended_status(&mut dev, &mut dfu, 1, 64).expect_err("stall");
+
+            // the state is not changed
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_progress() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // Set Image Size
+            dev.download(&mut dfu, 0, &[0xb5, 200, 0, 0, 0])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+
+            for block in 2..4 {
+                dev.download(&mut dfu, block, &[0x55; 64]).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+            }
+
+            let vec = extended_status(&mut dev, &mut dfu, 0, 20).expect("vec");
+            let status = ExtendedStatus::decode(&vec).expect("decode");
+            assert_eq!(
+                status,
+                ExtendedStatus {
+                    status: DfuStatusCode::Ok,
+                    state: DfuState::DfuDnloadIdle,
+                    alt_setting: 0,
+                    programmed: 128,
+                    total: Some(200),
+                    last_error: None,
+                }
+            );
+            assert_eq!(vec[3], 64);
+            assert_eq!(status.progress(), Some(64));
+            assert_eq!(dfu.extended_status(), status);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_last_error() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // block 10 is at 0x08000200, the error reports its offset in blocks
+            dev.download(&mut dfu, 10, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_PROG, 0, DFU_ERROR));
+
+            let vec = extended_status(&mut dev, &mut dfu, 0, 64).expect("vec");
+            assert_eq!(
+                vec,
+                [
+                    STATUS_ERR_PROG,
+                    DFU_ERROR,
+                    0,
+                    0xff,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    STATUS_ERR_PROG,
+                    0x03,
+                    8,
+                    0,
+                    0x00,
+                    0x02,
+                    0x00,
+                    0x08
+                ]
+            );
+            let status = ExtendedStatus::decode(&vec).expect("decode");
+            assert_eq!(
+                status.last_error,
+                Some(MemoryErrorDetail {
+                    error: DfuMemoryError::Prog,
+                    address: Some(TESTMEM_BASE + 0x200),
+                    block: Some(8),
+                })
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_decode() {
+    let status = ExtendedStatus {
+        status: DfuStatusCode::ErrErase,
+        state: DfuState::DfuError,
+        alt_setting: 1,
+        programmed: 0x1234,
+        total: None,
+        last_error: Some(MemoryErrorDetail {
+            error: DfuMemoryError::Erase,
+            address: None,
+            block: None,
+        }),
+    };
+    let mut data = status.encode().to_vec();
+    assert_eq!(ExtendedStatus::decode(&data), Some(status));
+
+    // fields added in a later version
+    data.extend_from_slice(&[1, 2, 3]);
+    assert_eq!(ExtendedStatus::decode(&data), Some(status));
+
+    assert_eq!(ExtendedStatus::decode(&data[..19]), None);
+    data[1] = 11;
+    assert_eq!(ExtendedStatus::decode(&data), None);
+}
@@ -0,0 +1,247 @@
+#![cfg(feature = "fuzz")]
+#![allow(unused_variables)]
+
+//! Request fuzzer, checks that no request sequence panics.
+//!
+//! All sequences of [`DEPTH`] requests from [`ACTIONS`] are sent to a fresh
+//! device, followed by a long pseudo-random sequence. After each request
+//! the device must report a valid state.
+
+use std::cell::RefCell;
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::class::UsbClass;
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+const TESTMEMSIZE: usize = 256;
+const TRANSFER_SIZE: usize = 64;
+
+/// Number of requests in exhaustively tested sequences.
+const DEPTH: usize = 3;
+
+/// Number of requests in the pseudo-random sequence.
+const RANDOM_STEPS: usize = 100_000;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; TRANSFER_SIZE],
+}
+
+impl TestMem {
+    fn range(address: u32, length: usize) -> Result<core::ops::Range<usize>, DfuMemoryError> {
+        let from = address
+            .checked_sub(TESTMEM_BASE)
+            .ok_or(DfuMemoryError::Address)? as usize;
+        match from.checked_add(length) {
+            Some(to) if to <= TESTMEMSIZE => Ok(from..to),
+            _ => Err(DfuMemoryError::Address),
+        }
+    }
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 1;
+    const ERASE_TIME_MS: u32 = 1;
+    const FULL_ERASE_TIME_MS: u32 = 1;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/4*64 g";
+    const TRANSFER_SIZE: u16 = TRANSFER_SIZE as u16;
+    const COMMAND_QUEUE_DEPTH: usize = MAX_COMMAND_QUEUE_DEPTH;
+    const LMDFU_PREFIX: bool = true;
+    const IMAGE_CRC_COMMAND: bool = true;
+    const RESUME_COMMAND: bool = true;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer
+            .get_mut(..src.len())
+            .ok_or(())?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = address
+            .checked_sub(TESTMEM_BASE)
+            .ok_or(DfuMemoryError::Address)? as usize;
+        let to = from.saturating_add(length).min(TESTMEMSIZE);
+        Ok(self.memory.get(from..to).unwrap_or(&[]))
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let range = Self::range(address, length)?;
+        let data = self.buffer.get(..length).ok_or(DfuMemoryError::Prog)?;
+        self.memory[range].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        let range = Self::range(address, 1)?;
+        self.memory[range].fill(0xff);
+        Ok(())
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        self.memory.fill(0xff);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+
+    fn resume_point(&mut self) -> Option<u32> {
+        Some(TESTMEM_BASE + 64)
+    }
+}
+
+/// A request sent by the host.
+#[derive(Clone, Copy, Debug)]
+enum Action {
+    Out {
+        req: u8,
+        value: u16,
+        data: &'static [u8],
+    },
+    In {
+        req: u8,
+        value: u16,
+        length: u16,
+    },
+    Reset,
+}
+
+const fn out(req: u8, value: u16, data: &'static [u8]) -> Action {
+    Action::Out { req, value, data }
+}
+
+const fn input(req: u8, value: u16, length: u16) -> Action {
+    Action::In { req, value, length }
+}
+
+const BASE: [u8; 4] = TESTMEM_BASE.to_le_bytes();
+const END: [u8; 4] = (TESTMEM_BASE + TESTMEMSIZE as u32 - 16).to_le_bytes();
+
+const ACTIONS: &[Action] = &[
+    // DFU_DETACH
+    out(0, 0, &[]),
+    // DFU_DNLOAD commands
+    out(1, 0, &[0x21, BASE[0], BASE[1], BASE[2], BASE[3]]),
+    out(1, 0, &[0x21, END[0], END[1], END[2], END[3]]),
+    out(1, 0, &[0x21, 0xf0, 0xff, 0xff, 0xff]),
+    out(1, 0, &[0x21, 0, 0]),
+    out(1, 0, &[0x41, BASE[0], BASE[1], BASE[2], BASE[3]]),
+    out(1, 0, &[0x41]),
+    out(1, 0, &[0x92]),
+    out(1, 0, &[0xb1, 0, 0, 0, 0]),
+    out(1, 0, &[0xb2, BASE[0], BASE[1], BASE[2], BASE[3]]),
+    out(1, 0, &[0xff; 9]),
+    // DFU_DNLOAD data blocks
+    out(1, 0, &[]),
+    out(1, 1, &[0; 4]),
+    out(1, 2, &[0x55; TRANSFER_SIZE]),
+    out(1, 3, &[0xaa; 16]),
+    out(1, 0xffff, &[0x55; TRANSFER_SIZE]),
+    // LMDFU prefix
+    out(1, 2, &[1, 0, 0, 0x08, 16, 0, 0, 0, 1, 2, 3, 4]),
+    out(1, 2, &[1, 0, 0, 0x08, 0, 0, 0, 0]),
+    // DFU_UPLOAD
+    input(2, 0, 16),
+    input(2, 0, 1),
+    input(2, 1, 4),
+    input(2, 2, TRANSFER_SIZE as u16),
+    input(2, 5, TRANSFER_SIZE as u16),
+    input(2, 0xffff, TRANSFER_SIZE as u16),
+    input(2, 2, 0),
+    // DFU_GETSTATUS
+    input(3, 0, 6),
+    input(3, 0, 2),
+    // DFU_CLRSTATUS
+    out(4, 0, &[]),
+    // DFU_GETSTATE
+    input(5, 0, 1),
+    input(5, 0, 0),
+    // DFU_ABORT
+    out(6, 0, &[]),
+    // unknown requests
+    out(7, 0, &[1, 2, 3]),
+    input(0xff, 0xffff, 8),
+    Action::Reset,
+];
+
+std::thread_local! {
+    /// Actions of the running sequence, `with_usb()` takes no closures.
+    static SEQUENCE: RefCell<Vec<Action>> = const { RefCell::new(Vec::new()) };
+}
+
+fn mem() -> TestMem {
+    TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; TRANSFER_SIZE],
+    }
+}
+
+/// Send `actions`, the device must answer `DFU_GETSTATE` after each one.
+fn run(actions: Vec<Action>) {
+    SEQUENCE.set(actions);
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let actions = SEQUENCE.take();
+            for action in actions {
+                match action {
+                    Action::Out { req, value, data } => {
+                        dev.write(&mut dfu, req, value, 0, data.len() as u16, data)
+                            .ok();
+                    }
+                    Action::In { req, value, length } => {
+                        dev.read(&mut dfu, req, value, 0, length).ok();
+                    }
+                    Action::Reset => dfu.reset(),
+                }
+
+                let state = dev.get_state(&mut dfu).expect("state");
+                assert!(
+                    state.len() == 1 && state[0] <= DFU_ERROR,
+                    "{:?}: state {:?}",
+                    action,
+                    state
+                );
+            }
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_fuzz_exhaustive() {
+    let n = ACTIONS.len();
+    for i in 0..n.pow(DEPTH as u32) {
+        let mut k = i;
+        let actions = (0..DEPTH)
+            .map(|_| {
+                let action = ACTIONS[k % n];
+                k /= n;
+                action
+            })
+            .collect();
+        run(actions);
+    }
+}
+
+#[test]
+fn test_fuzz_random() {
+    // xorshift, fixed seed for reproducible failures
+    let mut x: u32 = 0x2545_f491;
+    let actions = (0..RANDOM_STEPS)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            ACTIONS[x as usize % ACTIONS.len()]
+        })
+        .collect();
+    run(actions);
+}
@@ -0,0 +1,145 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::hash::*;
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    expected: u64,
+    manifested: bool,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.manifested = true;
+        Ok(())
+    }
+}
+
+impl DigestVerifier<u64> for TestMem {
+    fn verify_digest(&mut self, digest: &u64) -> Result<(), DfuManifestationError> {
+        if *digest == self.expected {
+            Ok(())
+        } else {
+            Err(DfuManifestationError::File)
+        }
+    }
+}
+
+/// Position-dependent checksum, detects reordered blocks
+struct TestHasher {
+    pos: u64,
+    sum: u64,
+}
+
+impl ImageHasher for TestHasher {
+    type Digest = u64;
+
+    fn reset(&mut self) {
+        self.pos = 0;
+        self.sum = 0;
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.pos += 1;
+            self.sum += self.pos * *b as u64;
+        }
+    }
+
+    fn finalize(&mut self) -> u64 {
+        self.sum
+    }
+}
+
+fn checksum(data: &[u8]) -> u64 {
+    let mut h = TestHasher { pos: 0, sum: 0 };
+    h.update(data);
+    h.finalize()
+}
+
+type Mem = HashedMemory<TestMem, TestHasher, 128>;
+
+const IMAGE_LEN: usize = 300;
+
+fn mem() -> Mem {
+    let mem = TestMem {
+        expected: checksum(&image(IMAGE_LEN)),
+        manifested: false,
+    };
+    HashedMemory::new(mem, TestHasher { pos: 0, sum: 0 })
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    image: &[u8],
+) {
+    for (i, block) in image.chunks(128).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+}
+
+#[test]
+fn test_digest() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // aborted download is not included in the digest
+            download(&mut dev, &mut dfu, &[0x55; 200]);
+            let vec = dev.abort(&mut dfu).expect("vec");
+
+            download(&mut dev, &mut dfu, &image(IMAGE_LEN));
+            let vec = dev.download(&mut dfu, 0, &[]).expect("vec");
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            assert!(dfu.release().release().manifested);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_digest_mismatch() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let mut img = image(IMAGE_LEN);
+            img[150] ^= 1;
+            download(&mut dev, &mut dfu, &img);
+            let vec = dev.download(&mut dfu, 0, &[]).expect("vec");
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            assert!(!dfu.release().release().manifested);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,271 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::header::*;
+
+const TESTMEMSIZE: usize = 2048;
+const TESTMEM_BASE: u32 = 0x0200_0000;
+const HEADER_ADDRESS: u32 = TESTMEM_BASE + 1024;
+
+pub struct TestMem<
+    const WRITE_ONCE: bool = false,
+    const SKIP_IDENTICAL: bool = false,
+    const VERIFY_COMMAND: bool = false,
+> {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 64],
+    erases: Vec<u32>,
+}
+
+impl<const WRITE_ONCE: bool, const SKIP_IDENTICAL: bool, const VERIFY_COMMAND: bool> DfuMemory
+    for TestMem<WRITE_ONCE, SKIP_IDENTICAL, VERIFY_COMMAND>
+{
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/2*1Kg";
+    const TRANSFER_SIZE: u16 = 64;
+    const WRITE_ONCE: bool = WRITE_ONCE;
+    const SKIP_IDENTICAL: bool = SKIP_IDENTICAL;
+    const VERIFY_COMMAND: bool = VERIFY_COMMAND;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(&self.memory[from..from + length])
+    }
+
+    fn compare(&mut self, address: u32, length: usize) -> Result<bool, DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(self.memory[from..from + length] == self.buffer[..length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize & !1023;
+        self.memory[from..from + 1024].fill(0xff);
+        self.erases.push(address);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+
+    fn image_version(&mut self) -> Option<u32> {
+        Some(7)
+    }
+}
+
+type Mem = HeaderMemory<TestMem, 64>;
+
+fn mem() -> Mem {
+    mem_with()
+}
+
+fn mem_with<const WRITE_ONCE: bool, const SKIP_IDENTICAL: bool, const VERIFY_COMMAND: bool>(
+) -> HeaderMemory<TestMem<WRITE_ONCE, SKIP_IDENTICAL, VERIFY_COMMAND>, 64> {
+    let mem = TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; 64],
+        erases: Vec::new(),
+    };
+    HeaderMemory::new(mem, HEADER_ADDRESS)
+}
+
+fn download<'a, M: DfuMemory + 'static>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, M>, MkDFU<M>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, M>,
+    file: &[u8],
+) {
+    for (i, block) in file.chunks(64).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+}
+
+fn manifestation<'a, M: DfuMemory + 'static>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, M>, MkDFU<M>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, M>,
+) -> Vec<u8> {
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_header() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let image = image(200);
+            download(&mut dev, &mut dfu, &image);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let mem = dfu.release().release();
+            assert_eq!(mem.erases, [HEADER_ADDRESS]);
+
+            let header = validate_image(&mem.memory[1024..], &mem.memory[..1024]).unwrap();
+            assert_eq!(header, FirmwareHeader::new(&image, 7));
+            assert_eq!(header.length, 200);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_header_interrupted() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let image = image(200);
+            download(&mut dev, &mut dfu, &image);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // new download erases the header
+            download(&mut dev, &mut dfu, &image[..64]);
+            let mem = dfu.release().release();
+            assert_eq!(
+                validate_image(&mem.memory[1024..], &mem.memory[..1024]),
+                Err(HeaderError::InvalidMagic)
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_header_not_sequential() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let image = image(200);
+            download(&mut dev, &mut dfu, &image[..64]);
+
+            let vec = dev.download(&mut dfu, 4, &image[..64]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_header_write_once() {
+    MkDFU::new(mem_with::<true, false, false>)
+        .with_usb(|mut dfu, mut dev| {
+            let image = image(200);
+            download(&mut dev, &mut dfu, &image);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // programmed blocks are checked through the adapter
+            let vec = dev.download(&mut dfu, 2, &image[..64]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_WRITE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_header_skip_identical() {
+    MkDFU::new(mem_with::<false, true, false>)
+        .with_usb(|mut dfu, mut dev| {
+            let image = image(200);
+            download(&mut dev, &mut dfu, &image);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // only the last block is programmed, skipped blocks are in the header
+            let mut changed = image.clone();
+            changed[199] ^= 0xff;
+            download(&mut dev, &mut dfu, &changed);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // nothing is programmed, the header is written again
+            download(&mut dev, &mut dfu, &changed);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let mem = dfu.release().release();
+            assert_eq!(mem.erases, [HEADER_ADDRESS; 3]);
+
+            let header = validate_image(&mem.memory[1024..], &mem.memory[..1024]).unwrap();
+            assert_eq!(header, FirmwareHeader::new(&changed, 7));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_header_verify() {
+    MkDFU::new(mem_with::<false, false, true>)
+        .with_usb(|mut dfu, mut dev| {
+            let image = image(200);
+            download(&mut dev, &mut dfu, &image);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // verified blocks are compared through the adapter, the header is kept
+            dev.download(&mut dfu, 0, &[0xbc]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            download(&mut dev, &mut dfu, &image);
+            let vec = dev.download(&mut dfu, 0, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // the next download starts a new image
+            download(&mut dev, &mut dfu, &image);
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let mem = dfu.release().release();
+            assert_eq!(mem.erases, [HEADER_ADDRESS; 2]);
+
+            let header = validate_image(&mem.memory[1024..], &mem.memory[..1024]).unwrap();
+            assert_eq!(header, FirmwareHeader::new(&image, 7));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_validate_image() {
+    let image = image(200);
+    let header = FirmwareHeader::new(&image, 1);
+    let bytes = header.to_bytes();
+    assert_eq!(FirmwareHeader::try_from(&bytes[..]), Ok(header));
+    assert_eq!(
+        FirmwareHeader::try_from(&bytes[..15]),
+        Err(HeaderError::TooShort)
+    );
+
+    assert_eq!(validate_image(&bytes, &image), Ok(header));
+    assert_eq!(
+        validate_image(&bytes, &image[..199]),
+        Err(HeaderError::InvalidLength)
+    );
+
+    let mut corrupted = image.clone();
+    corrupted[100] ^= 1;
+    assert_eq!(
+        validate_image(&bytes, &corrupted),
+        Err(HeaderError::InvalidCrc)
+    );
+}
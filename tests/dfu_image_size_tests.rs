@@ -0,0 +1,134 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::RefCell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+thread_local! {
+    /// Arguments of [`DfuMemory::erase_range()`] calls.
+    static ERASES: RefCell<Vec<(u32, u32)>> = const { RefCell::new(Vec::new()) };
+}
+
+test_mem! {
+    except INITIAL_ADDRESS_POINTER, FULL_ERASE_TIME_MS, MEM_INFO_STRING;
+
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const FULL_ERASE_TIME_MS: u32 = 100;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*64 g";
+    const ERASE_RANGE_COMMAND: bool = true;
+    const ERASE_PAGE_SIZE: u32 = 64;
+    const IMAGE_SIZE_COMMAND: bool = true;
+
+    fn erase_range(&mut self, address: u32, length: u32) -> Result<(), DfuMemoryError> {
+        ERASES.with_borrow_mut(|e| e.push((address, length)));
+        Ok(())
+    }
+}
+
+type Dev<'a> = Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>;
+
+/// Set Address Pointer and announce `length` bytes.
+fn start(dev: &mut Dev, dfu: &mut DfuClass<EmulatedUsbBus, TestMem>, length: u32) {
+    let b = (TESTMEM_BASE + 64).to_le_bytes();
+    dev.download(dfu, 0, &[0x21, b[0], b[1], b[2], b[3]])
+        .expect("vec");
+    dev.get_status(dfu).expect("vec");
+    dev.get_status(dfu).expect("vec");
+
+    let b = length.to_le_bytes();
+    dev.download(dfu, 0, &[0xb5, b[0], b[1], b[2], b[3]])
+        .expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 40, DFU_DN_BUSY));
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+}
+
+fn block(dev: &mut Dev, dfu: &mut DfuClass<EmulatedUsbBus, TestMem>, block_num: u16, len: usize) {
+    dev.download(dfu, block_num, &vec![0x55; len]).expect("vec");
+    dev.get_status(dfu).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+}
+
+#[test]
+fn test_get_commands() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41, 0xb3, 0xb5]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_image_size() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            assert_eq!(dfu.download_progress(), None);
+
+            start(&mut dev, &mut dfu, 100);
+            assert_eq!(ERASES.take(), [(TESTMEM_BASE + 64, 100)]);
+            assert_eq!(dfu.download_progress(), Some(0));
+
+            block(&mut dev, &mut dfu, 2, 64);
+            assert_eq!(dfu.download_progress(), Some(64));
+            block(&mut dev, &mut dfu, 3, 36);
+            assert_eq!(dfu.download_progress(), Some(100));
+
+            dev.download(&mut dfu, 4, &[]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+            assert_eq!(dfu.download_progress(), None);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_image_size_short() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            start(&mut dev, &mut dfu, 100);
+            block(&mut dev, &mut dfu, 2, 64);
+
+            dev.download(&mut dfu, 3, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_image_size_long() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            start(&mut dev, &mut dfu, 100);
+            block(&mut dev, &mut dfu, 2, 64);
+
+            assert!(dev.download(&mut dfu, 3, &[0x55; 64]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_image_size_invalid() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            assert!(dev.download(&mut dfu, 0, &[0xb5, 0, 0, 0, 0]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
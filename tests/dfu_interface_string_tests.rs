@@ -0,0 +1,128 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+test_mem! {
+    except INITIAL_ADDRESS_POINTER, MEM_INFO_STRING, read;
+
+    const INITIAL_ADDRESS_POINTER: u32 = 0x0200_0000;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/16*1Kg";
+    const HAS_INTERFACE_STRING: bool = false;
+}
+
+#[test]
+fn test_no_interface_string() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev
+                .device_get_descriptor(&mut dfu, 2, 0, 0, 130)
+                .expect("vec");
+            assert_eq!(vec.len(), 27);
+
+            // iInterface
+            assert_eq!(vec[9..18], [9, 4, 0, 0, 0, 0xfe, 1, 2, 0]);
+
+            // string index is not allocated
+            dev.device_get_string(&mut dfu, 4, 0x409)
+                .expect_err("stall");
+        })
+        .expect("with_usb");
+}
+
+pub struct NamedMem {}
+
+impl DfuMemory for NamedMem {
+    const INITIAL_ADDRESS_POINTER: u32 = 0x0200_0000;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/16*1Kg";
+    const INTERFACE_NAME: Option<&'static str> = Some("Firmware Update");
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_interface_name() {
+    MkDFU::new(|| NamedMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev
+                .device_get_descriptor(&mut dfu, 2, 0, 0, 130)
+                .expect("vec");
+            assert_eq!(vec.len(), 36);
+
+            // alternate settings 0 and 1, followed by DFU functional descriptor
+            assert_eq!(vec[9..18], [9, 4, 0, 0, 0, 0xfe, 1, 2, 4]);
+            assert_eq!(vec[18..27], [9, 4, 0, 1, 0, 0xfe, 1, 2, 5]);
+            assert_eq!(vec[27..29], [9, 0x21]);
+
+            let istr = dev.device_get_string(&mut dfu, 4, 0x409).expect("str");
+            assert_eq!(istr, NamedMem::MEM_INFO_STRING);
+
+            let istr = dev.device_get_string(&mut dfu, 5, 0x409).expect("str");
+            assert_eq!(istr, "Firmware Update");
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_interface_name_alt_setting() {
+    MkDFU::new(|| NamedMem {})
+        .with_usb(|mut dfu, mut dev| {
+            dev.interface_set_interface(&mut dfu, 0, 1).expect("set");
+            let vec = dev.interface_get_interface(&mut dfu).expect("vec");
+            assert_eq!(vec, 1);
+
+            dev.interface_set_interface(&mut dfu, 0, 2)
+                .expect_err("stall");
+        })
+        .expect("with_usb");
+}
+
+/// Class with interface 0 and string 4 taken by another function.
+fn mk_resources() -> MkDFU<NamedMem> {
+    MkDFU::builder(|alloc| {
+        let other_if = alloc.interface();
+        let other_str = alloc.string();
+        let if_num = alloc.interface();
+        let str_idx = alloc.string();
+        DfuClass::new_with_resources(alloc, NamedMem {}, if_num, str_idx)
+    })
+}
+
+#[test]
+fn test_new_with_resources() {
+    mk_resources()
+        .with_usb(|mut dfu, mut dev| {
+            assert_eq!(u8::from(dfu.get_interface_number()), 1);
+
+            let vec = dev
+                .device_get_descriptor(&mut dfu, 2, 0, 0, 130)
+                .expect("vec");
+            assert_eq!(vec[9..18], [9, 4, 1, 0, 0, 0xfe, 1, 2, 5]);
+            assert_eq!(vec[18..27], [9, 4, 1, 1, 0, 0xfe, 1, 2, 6]);
+
+            let istr = dev.device_get_string(&mut dfu, 5, 0x409).expect("str");
+            assert_eq!(istr, NamedMem::MEM_INFO_STRING);
+
+            let istr = dev.device_get_string(&mut dfu, 6, 0x409).expect("str");
+            assert_eq!(istr, "Firmware Update");
+        })
+        .expect("with_usb");
+}
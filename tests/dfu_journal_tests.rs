@@ -0,0 +1,116 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::journal::*;
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+test_mem! {
+    except INITIAL_ADDRESS_POINTER, MEM_INFO_STRING, read;
+
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/2*1Kg";
+}
+
+/// Journal in a byte array, as it would be stored in flash
+#[derive(Default)]
+pub struct TestJournal {
+    bytes: Option<[u8; JOURNAL_ENTRY_LENGTH]>,
+    records: usize,
+}
+
+impl DownloadJournal for TestJournal {
+    fn load(&mut self) -> Result<Option<JournalEntry>, ()> {
+        match &self.bytes {
+            Some(bytes) => JournalEntry::from_bytes(bytes).map(Some).ok_or(()),
+            None => Ok(None),
+        }
+    }
+
+    fn record(&mut self, entry: &JournalEntry) -> Result<(), ()> {
+        self.bytes = Some(entry.to_bytes());
+        self.records += 1;
+        Ok(())
+    }
+}
+
+type Mem = JournalMemory<TestMem, TestJournal>;
+
+fn mem() -> Mem {
+    JournalMemory::new(TestMem {}, TestJournal::default())
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    blocks: u16,
+) {
+    for i in 0..blocks {
+        let vec = dev.download(dfu, 2 + i, &[0x5a; 64]).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+}
+
+#[test]
+fn test_journal_partial() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, 3);
+
+            // power loss before manifestation
+            let (mem, mut journal) = dfu.release().release();
+            let entry = journal.load().unwrap().unwrap();
+            assert!(entry.is_partial());
+            assert_eq!(
+                entry,
+                JournalEntry {
+                    state: JournalState::InProgress,
+                    start: TESTMEM_BASE,
+                    end: TESTMEM_BASE + 3 * 64,
+                }
+            );
+            assert_eq!(journal.records, 4);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_journal_complete() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, 2);
+            let vec = dev.download(&mut dfu, 0, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let (mem, mut journal) = dfu.release().release();
+            let entry = journal.load().unwrap().unwrap();
+            assert!(!entry.is_partial());
+            assert_eq!(entry.end, TESTMEM_BASE + 2 * 64);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_journal_entry_damaged() {
+    let entry = JournalEntry {
+        state: JournalState::Complete,
+        start: TESTMEM_BASE,
+        end: TESTMEM_BASE + 100,
+    };
+    let mut bytes = entry.to_bytes();
+    assert_eq!(JournalEntry::from_bytes(&bytes), Some(entry));
+
+    bytes[8] ^= 1;
+    assert_eq!(JournalEntry::from_bytes(&bytes), None);
+    assert_eq!(JournalEntry::from_bytes(&[0xff; 16]), None);
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "download")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::RefCell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+#[derive(Debug, PartialEq)]
+enum Call {
+    Leave(u32),
+    Manifestation,
+}
+
+thread_local! {
+    /// [`DfuMemory::leave()`] and [`DfuMemory::manifestation()`] calls.
+    static CALLS: RefCell<Vec<Call>> = const { RefCell::new(Vec::new()) };
+}
+
+test_mem! {
+    except INITIAL_ADDRESS_POINTER, MEM_INFO_STRING, manifestation;
+
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*1Kg";
+
+    fn leave(&mut self, address: u32) {
+        CALLS.with_borrow_mut(|c| c.push(Call::Leave(address)));
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        CALLS.with_borrow_mut(|c| c.push(Call::Manifestation));
+        Ok(())
+    }
+}
+
+fn manifest<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem>,
+) {
+    dev.download(dfu, 2, &[]).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+}
+
+#[test]
+fn test_leave_address() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+
+            // entry point selected by the host
+            let b = (TESTMEM_BASE + 0x400).to_le_bytes();
+            dev.download(&mut dfu, 0, &[0x21, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            manifest(&mut dev, &mut dfu);
+            assert_eq!(
+                CALLS.take(),
+                [Call::Leave(TESTMEM_BASE + 0x400), Call::Manifestation]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_leave_initial_address() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            manifest(&mut dev, &mut dfu);
+            assert_eq!(
+                CALLS.take(),
+                [Call::Leave(TESTMEM_BASE), Call::Manifestation]
+            );
+        })
+        .expect("with_usb");
+}
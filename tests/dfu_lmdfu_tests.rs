@@ -0,0 +1,129 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::suffix::*;
+
+const TESTMEMSIZE: usize = 4096;
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 128],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/4*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+    const LMDFU_PREFIX: bool = true;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+fn mem() -> TestMem {
+    TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; 128],
+    }
+}
+
+fn lmdfu_file(address: u32, length: u32, image: &[u8]) -> Vec<u8> {
+    let mut file = LmdfuPrefix { address, length }.to_bytes().to_vec();
+    file.extend_from_slice(image);
+    file
+}
+
+fn download_blocks<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem>,
+    file: &[u8],
+) {
+    for (i, block) in file.chunks(128).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+}
+
+#[test]
+fn test_lmdfu_prefix() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let image: Vec<u8> = (0..300).map(|i| i as u8).collect();
+            let address = TESTMEM_BASE + 1024;
+            download_blocks(
+                &mut dev,
+                &mut dfu,
+                &lmdfu_file(address, image.len() as u32, &image),
+            );
+            assert_eq!(dfu.get_address_pointer(), address);
+
+            let vec = dev.download(&mut dfu, 0, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let mem = dfu.release();
+            assert!(mem.memory[..1024].iter().all(|&b| b == 0xff));
+            assert_eq!(&mem.memory[1024..1324], &image[..]);
+            assert!(mem.memory[1324..].iter().all(|&b| b == 0xff));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_lmdfu_image_too_short() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let image = [0x55; 200];
+            download_blocks(&mut dev, &mut dfu, &lmdfu_file(TESTMEM_BASE, 300, &image));
+
+            let vec = dev.download(&mut dfu, 0, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_lmdfu_image_too_long() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let file = lmdfu_file(TESTMEM_BASE, 150, &[0x55; 200]);
+
+            let vec = dev.download(&mut dfu, 2, &file[..128]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            let e = dev.download(&mut dfu, 3, &file[128..]).expect_err("stall");
+            assert_eq!(e, AnyUsbError::EPStalled);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,221 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::hash::*;
+use usbd_dfu::manifest::*;
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    memory: [u8; 1024],
+    buffer: [u8; 128],
+    manifested: bool,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = address
+            .checked_sub(TESTMEM_BASE)
+            .ok_or(DfuMemoryError::Address)? as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.manifested = true;
+        Ok(())
+    }
+
+    fn minimum_image_version(&mut self) -> Option<u32> {
+        Some(3)
+    }
+}
+
+/// Sum of all bytes in the first four bytes of the digest
+struct TestHasher {
+    sum: u32,
+}
+
+impl ImageHasher for TestHasher {
+    type Digest = [u8; 32];
+
+    fn reset(&mut self) {
+        self.sum = 0;
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.sum += *b as u32;
+        }
+    }
+
+    fn finalize(&mut self) -> [u8; 32] {
+        let mut digest = [0; 32];
+        digest[..4].copy_from_slice(&self.sum.to_le_bytes());
+        digest
+    }
+}
+
+type Mem = ManifestMemory<TestMem, CborManifestParser, TestHasher, 128>;
+
+fn mem() -> Mem {
+    let mem = TestMem {
+        memory: [0xff; 1024],
+        buffer: [0; 128],
+        manifested: false,
+    };
+    ManifestMemory::new(mem, CborManifestParser, TestHasher { sum: 0 })
+}
+
+fn payload() -> Vec<u8> {
+    (0..300).map(|i| i as u8).collect()
+}
+
+fn digest(data: &[u8]) -> [u8; 32] {
+    let mut hasher = TestHasher { sum: 0 };
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// CBOR map with an extra text entry
+fn manifest(address: u32, size: u16, version: u8, digest: &[u8; 32]) -> Vec<u8> {
+    let mut cbor = vec![0xa5, 0x01, 0x1a];
+    cbor.extend_from_slice(&address.to_be_bytes());
+    cbor.extend_from_slice(&[0x02, 0x19]);
+    cbor.extend_from_slice(&size.to_be_bytes());
+    cbor.extend_from_slice(&[0x05, 0x62, b'h', b'i']);
+    cbor.extend_from_slice(&[0x03, version]);
+    cbor.extend_from_slice(&[0x04, 0x58, 0x20]);
+    cbor.extend_from_slice(digest);
+    cbor
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    manifest: &[u8],
+    payload: &[u8],
+) -> Vec<u8> {
+    let blocks = core::iter::once(manifest).chain(payload.chunks(128));
+    let mut vec = vec![];
+    for (i, block) in blocks.enumerate() {
+        let _ = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let _ = dev.get_status(dfu).expect("vec");
+        vec = dev.get_status(dfu).expect("vec");
+        if vec != status(STATUS_OK, 0, DFU_DNLOAD_IDLE) {
+            break;
+        }
+    }
+    vec
+}
+
+fn manifestation<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+) -> Vec<u8> {
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_manifest() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let data = payload();
+            let m = manifest(TESTMEM_BASE + 0x100, 300, 3, &digest(&data));
+            let vec = download(&mut dev, &mut dfu, &m, &data);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let mem = dfu.release();
+            let m = mem.manifest().expect("manifest");
+            assert_eq!(m.address, TESTMEM_BASE + 0x100);
+            assert_eq!(m.size, 300);
+            assert_eq!(m.version, 3);
+
+            let mem = mem.release();
+            assert!(mem.manifested);
+            assert!(mem.memory[..0x100].iter().all(|&b| b == 0xff));
+            assert_eq!(&mem.memory[0x100..0x100 + 300], &data[..]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_manifest_invalid() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let data = payload();
+            let m = manifest(TESTMEM_BASE, 300, 3, &digest(&data));
+            let vec = download(&mut dev, &mut dfu, &m[..m.len() - 1], &data);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_manifest_downgrade() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let data = payload();
+            let m = manifest(TESTMEM_BASE, 300, 2, &digest(&data));
+            let vec = download(&mut dev, &mut dfu, &m, &data);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_manifest_size_mismatch() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let data = payload();
+            let m = manifest(TESTMEM_BASE, 200, 3, &digest(&data));
+            let vec = download(&mut dev, &mut dfu, &m, &data);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            let vec = dev.clear_status(&mut dfu).expect("vec");
+            let m = manifest(TESTMEM_BASE, 400, 3, &digest(&data));
+            let vec = download(&mut dev, &mut dfu, &m, &data);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_manifest_digest_mismatch() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let data = payload();
+            let m = manifest(TESTMEM_BASE, 300, 3, &digest(&data[..299]));
+            let vec = download(&mut dev, &mut dfu, &m, &data);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            let vec = manifestation(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            assert!(!dfu.release().release().manifested);
+        })
+        .expect("with_usb");
+}
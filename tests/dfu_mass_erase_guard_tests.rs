@@ -0,0 +1,125 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::Cell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+thread_local! {
+    /// Number of [`DfuMemory::erase_all()`] calls.
+    static MASS_ERASES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// `ALLOWED` is returned by [`DfuMemory::mass_erase_allowed()`].
+pub struct TestMem<const ALLOWED: bool> {}
+
+impl<const ALLOWED: bool> DfuMemory for TestMem<ALLOWED> {
+    const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*64 g";
+    const TRANSFER_SIZE: u16 = 64;
+    const MASS_ERASE_GUARD: bool = true;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&[])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        MASS_ERASES.set(MASS_ERASES.get() + 1);
+        Ok(())
+    }
+
+    fn mass_erase_allowed(&mut self) -> bool {
+        ALLOWED
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+fn command<const ALLOWED: bool>(
+    dev: &mut Device<'_, DfuClass<EmulatedUsbBus, TestMem<ALLOWED>>, MkDFU<TestMem<ALLOWED>>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem<ALLOWED>>,
+    cmd: &[u8],
+) -> Vec<u8> {
+    dev.download(dfu, 0, cmd).expect("vec");
+    dev.get_status(dfu).expect("vec");
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_get_commands() {
+    MkDFU::new(|| TestMem::<false> {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41, 0xb4]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_mass_erase_locked() {
+    MkDFU::new(|| TestMem::<false> {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = command(&mut dev, &mut dfu, &[0x41]);
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+            assert_eq!(MASS_ERASES.get(), 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_mass_erase_unlocked() {
+    MkDFU::new(|| TestMem::<false> {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = command(&mut dev, &mut dfu, &[0xb4]);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            let vec = command(&mut dev, &mut dfu, &[0x41]);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(MASS_ERASES.get(), 1);
+
+            // the unlock is used
+            let vec = command(&mut dev, &mut dfu, &[0x41]);
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+            assert_eq!(MASS_ERASES.get(), 1);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_mass_erase_unlock_aborted() {
+    MkDFU::new(|| TestMem::<false> {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = command(&mut dev, &mut dfu, &[0xb4]);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            dev.abort(&mut dfu).expect("vec");
+
+            let vec = command(&mut dev, &mut dfu, &[0x41]);
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+            assert_eq!(MASS_ERASES.get(), 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_mass_erase_allowed() {
+    MkDFU::new(|| TestMem::<true> {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = command(&mut dev, &mut dfu, &[0x41]);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(MASS_ERASES.get(), 1);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,223 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::mcuboot::*;
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    programmed: usize,
+    manifested: bool,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        self.programmed += length;
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.manifested = true;
+        Ok(())
+    }
+}
+
+type Mem = McubootMemory<TestMem, 128>;
+
+fn mem() -> Mem {
+    let mem = TestMem {
+        programmed: 0,
+        manifested: false,
+    };
+    McubootMemory::new(mem)
+}
+
+fn header(img_size: u32, protect_tlv_size: u16) -> ImageHeader {
+    ImageHeader {
+        load_addr: 0,
+        hdr_size: 64,
+        protect_tlv_size,
+        img_size,
+        flags: 0,
+        version: ImageVersion {
+            major: 1,
+            minor: 2,
+            revision: 3,
+            build_num: 4,
+        },
+    }
+}
+
+/// Image with a SHA-256 TLV, and an optional protected TLV
+fn image(img_size: u32, protected: bool) -> Vec<u8> {
+    let protect_tlv_size = if protected { 4 + 8 } else { 0 };
+    let mut file = header(img_size, protect_tlv_size).to_bytes().to_vec();
+    file.resize(64, 0);
+    file.extend((0..img_size).map(|i| i as u8));
+    if protected {
+        file.extend_from_slice(&IMAGE_TLV_PROT_INFO_MAGIC.to_le_bytes());
+        file.extend_from_slice(&protect_tlv_size.to_le_bytes());
+        file.extend_from_slice(&[0x60, 0, 4, 0, 1, 2, 3, 4]);
+    }
+    file.extend_from_slice(&IMAGE_TLV_INFO_MAGIC.to_le_bytes());
+    file.extend_from_slice(&(4u16 + 4 + 32).to_le_bytes());
+    file.extend_from_slice(&[0x10, 0, 32, 0]);
+    file.extend_from_slice(&[0xaa; 32]);
+    file
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    file: &[u8],
+) -> Vec<u8> {
+    let mut vec = vec![];
+    for (i, block) in file.chunks(128).enumerate() {
+        let _ = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let _ = dev.get_status(dfu).expect("vec");
+        vec = dev.get_status(dfu).expect("vec");
+        if vec != status(STATUS_OK, 0, DFU_DNLOAD_IDLE) {
+            break;
+        }
+    }
+    vec
+}
+
+fn manifest<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+) -> Vec<u8> {
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_header() {
+    let hdr = header(1000, 0);
+    let bytes = hdr.to_bytes();
+    assert_eq!(&bytes[..4], &[0x3d, 0xb8, 0xf3, 0x96]);
+    assert_eq!(ImageHeader::try_from(&bytes[..]), Ok(hdr));
+    assert_eq!(hdr.tlv_offset(), 1064);
+    assert_eq!(hdr.version.to_u32(), 0x0102_0003);
+
+    assert_eq!(
+        ImageHeader::try_from(&bytes[..31]),
+        Err(McubootError::TooShort)
+    );
+
+    let mut bad = bytes;
+    bad[0] ^= 1;
+    assert_eq!(
+        ImageHeader::try_from(&bad[..]),
+        Err(McubootError::InvalidMagic)
+    );
+
+    let mut bad = bytes;
+    bad[8] = 16;
+    assert_eq!(
+        ImageHeader::try_from(&bad[..]),
+        Err(McubootError::InvalidHeaderSize)
+    );
+}
+
+#[test]
+fn test_mcuboot_image() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let file = image(300, false);
+            let vec = download(&mut dev, &mut dfu, &file);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            let vec = manifest(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let mut mem = dfu.release();
+            assert_eq!(mem.header(), Some(header(300, 0)));
+            assert_eq!(mem.memory().programmed, file.len());
+            assert!(mem.memory().manifested);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_mcuboot_protected_tlv() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // TLV info headers are split between blocks
+            let vec = download(&mut dev, &mut dfu, &image(190, true));
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            let vec = manifest(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_mcuboot_bad_magic() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let mut file = image(300, false);
+            file[3] = 0;
+            let vec = download(&mut dev, &mut dfu, &file);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            assert_eq!(dfu.release().memory().programmed, 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_mcuboot_bad_tlv() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let mut file = image(300, false);
+            file[364] = 0;
+            let vec = download(&mut dev, &mut dfu, &file);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_mcuboot_too_long() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let mut file = image(300, false);
+            file.push(0);
+            let vec = download(&mut dev, &mut dfu, &file);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_mcuboot_truncated() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let file = image(300, false);
+            let vec = download(&mut dev, &mut dfu, &file[..file.len() - 10]);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            let vec = manifest(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+
+            assert!(!dfu.release().memory().manifested);
+        })
+        .expect("with_usb");
+}
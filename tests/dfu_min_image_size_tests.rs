@@ -0,0 +1,92 @@
+#![cfg(feature = "download")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::Cell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+thread_local! {
+    /// Number of [`DfuMemory::manifestation()`] calls.
+    static MANIFESTATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+test_mem! {
+    except manifestation;
+
+    const MIN_IMAGE_SIZE: u32 = 100;
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        MANIFESTATIONS.set(MANIFESTATIONS.get() + 1);
+        Ok(())
+    }
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem>,
+    blocks: &[(u16, usize)],
+) {
+    for &(block_num, length) in blocks {
+        let data = vec![0x55; length];
+        dev.download(dfu, block_num, &data).expect("vec");
+        dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+}
+
+#[test]
+fn test_min_image_size() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, &[(2, 64), (3, 36)]);
+
+            dev.download(&mut dfu, 4, &[]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+            assert_eq!(MANIFESTATIONS.get(), 1);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_min_image_size_short() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, &[(2, 64)]);
+
+            dev.download(&mut dfu, 3, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // a new download starts from zero
+            download(&mut dev, &mut dfu, &[(2, 64)]);
+            dev.download(&mut dfu, 3, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+
+            assert_eq!(MANIFESTATIONS.get(), 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_min_image_size_empty() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // zero-length request right away
+            dev.download(&mut dfu, 0, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+            assert_eq!(MANIFESTATIONS.get(), 0);
+        })
+        .expect("with_usb");
+}
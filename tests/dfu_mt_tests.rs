@@ -64,6 +64,7 @@ impl UsbDeviceCtx for MkDFU {
     type C<'c> = DfuClass<EmulatedUsbBus, TestMem>;
     const EP0_SIZE: u8 = 32;
 
+    #[allow(clippy::needless_lifetimes, clippy::needless_borrow)]
     fn create_class<'a>(
         &mut self,
         alloc: &'a UsbBusAllocator<EmulatedUsbBus>,
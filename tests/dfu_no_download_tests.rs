@@ -0,0 +1,83 @@
+#![cfg(all(feature = "upload", not(feature = "download")))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+pub struct TestMem {
+    buffer: [u8; 64],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/1*256 g";
+    const HAS_DOWNLOAD: bool = true;
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&self.buffer[..length.min(64)])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        panic!("program without download feature");
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        panic!("erase without download feature");
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_can_dnload_cleared() {
+    MkDFU::new(|| TestMem { buffer: [0x5a; 64] })
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev
+                .device_get_descriptor(&mut dfu, 2, 0, 0, 130)
+                .expect("vec");
+            let config = &vec[18..];
+
+            // bitCanDnload is cleared even with HAS_DOWNLOAD, bitCanUpload is set
+            assert_eq!(config[2] & 0x3, 0x2);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_download_stalled() {
+    MkDFU::new(|| TestMem { buffer: [0x5a; 64] })
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // erase command
+            assert!(dev.download(&mut dfu, 0, &[0x41, 0, 0, 0, 8]).is_err());
+
+            // data block
+            assert!(dev.download(&mut dfu, 2, &[0; 64]).is_err());
+
+            // request is unknown, state is not changed
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // upload still works
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec, [0x5a; 64]);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,70 @@
+#![cfg(not(feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+pub struct TestMem {
+    buffer: [u8; 64],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/1*256 g";
+    const HAS_UPLOAD: bool = true;
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        panic!("read without upload feature");
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_can_upload_cleared() {
+    MkDFU::new(|| TestMem { buffer: [0; 64] })
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev
+                .device_get_descriptor(&mut dfu, 2, 0, 0, 130)
+                .expect("vec");
+            let config = &vec[18..];
+
+            // bitCanUpload is cleared even with HAS_UPLOAD
+            assert_eq!(config[2] & 0x2, 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_upload_stalled() {
+    MkDFU::new(|| TestMem { buffer: [0; 64] })
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            assert!(dev.upload(&mut dfu, 2, 64).is_err());
+
+            // resume query is not handled either
+            assert!(dev.upload(&mut dfu, 1, 64).is_err());
+        })
+        .expect("with_usb");
+}
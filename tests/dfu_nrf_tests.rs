@@ -0,0 +1,199 @@
+#![cfg(feature = "nrf52")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+use usbd_dfu::class::*;
+use usbd_dfu::nrf::*;
+
+const FLASH_SIZE: usize = 0x4000;
+
+/// NVMC emulation, offsets are addresses
+pub struct TestFlash {
+    memory: Vec<u8>,
+    erases: Vec<(u32, u32)>,
+}
+
+#[derive(Debug)]
+pub struct TestFlashError(NorFlashErrorKind);
+
+impl NorFlashError for TestFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.0
+    }
+}
+
+impl ErrorType for TestFlash {
+    type Error = TestFlashError;
+}
+
+impl ReadNorFlash for TestFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.memory[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
+
+impl NorFlash for TestFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = 4096;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !from.is_multiple_of(4096) || !to.is_multiple_of(4096) {
+            return Err(TestFlashError(NorFlashErrorKind::NotAligned));
+        }
+        self.memory[from as usize..to as usize].fill(0xff);
+        self.erases.push((from, to));
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        // NVMC programs words from an aligned source
+        if !offset.is_multiple_of(4)
+            || !bytes.len().is_multiple_of(4)
+            || !(bytes.as_ptr() as usize).is_multiple_of(4)
+        {
+            return Err(TestFlashError(NorFlashErrorKind::NotAligned));
+        }
+        let offset = offset as usize;
+        for (m, b) in self.memory[offset..offset + bytes.len()]
+            .iter_mut()
+            .zip(bytes)
+        {
+            *m &= b;
+        }
+        Ok(())
+    }
+}
+
+struct App;
+
+impl NrfRegion for App {
+    const START: u32 = 0x1000;
+    const PAGES: u32 = 2;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x00001000/2*4Kg";
+}
+
+type Mem = NrfFlash<TestFlash, App, 64>;
+
+fn mem() -> Mem {
+    let flash = TestFlash {
+        memory: vec![0xff; FLASH_SIZE],
+        erases: Vec::new(),
+    };
+    NrfFlash::new(flash, 0)
+}
+
+fn command<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    cmd: &[u8],
+) -> Vec<u8> {
+    let vec = dev.download(dfu, 0, cmd).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_consts() {
+    assert_eq!(Mem::INITIAL_ADDRESS_POINTER, 0x1000);
+    assert_eq!(Mem::TRANSFER_SIZE, 64);
+    assert_eq!(Mem::ERASE_TIME_MS, PAGE_ERASE_TIME_MS);
+    assert_eq!(Mem::FULL_ERASE_TIME_MS, 2 * PAGE_ERASE_TIME_MS);
+}
+
+#[test]
+fn test_download_upload() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let image: Vec<u8> = (0..98u32).map(|i| i as u8).collect();
+
+            for (i, block) in image.chunks(64).enumerate() {
+                let vec = dev.download(&mut dfu, 2 + i as u16, block).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, KIB_PROGRAM_TIME_MS, DFU_DN_BUSY));
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            }
+            let vec = dev.abort(&mut dfu).expect("vec");
+
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec, image[..64]);
+            let vec = dev.upload(&mut dfu, 3, 64).expect("vec");
+            assert_eq!(vec[..34], image[64..]);
+            assert!(vec[34..].iter().all(|&b| b == 0xff));
+
+            let flash = dfu.release().release();
+            assert_eq!(flash.memory[0x1000..0x1000 + 98], image);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_upload_region_end() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // last block of the region
+            let vec = command(&mut dev, &mut dfu, &[0x21, 0xc0, 0x2f, 0, 0]);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            let vec = dev.abort(&mut dfu).expect("vec");
+
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec.len(), 64);
+            let vec = dev.upload(&mut dfu, 3, 64).expect("vec");
+            assert!(vec.is_empty());
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_erase() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // page containing 0x2010
+            let vec = command(&mut dev, &mut dfu, &[0x41, 0x10, 0x20, 0, 0]);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            // erase all
+            let vec = command(&mut dev, &mut dfu, &[0x41]);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            let flash = dfu.release().release();
+            assert_eq!(
+                flash.erases,
+                [(0x2000, 0x3000), (0x1000, 0x2000), (0x2000, 0x3000)]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_outside_region() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // bootloader page
+            let vec = command(&mut dev, &mut dfu, &[0x41, 0x00, 0x00, 0, 0]);
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+            let vec = dev.clear_status(&mut dfu).expect("vec");
+
+            let vec = command(&mut dev, &mut dfu, &[0x21, 0xf0, 0x2f, 0, 0]);
+            let vec = dev.download(&mut dfu, 2, &[0; 32]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,121 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEMSIZE: usize = 256;
+const TESTMEM_BASE: u32 = 0x1fff_7000;
+
+/// OTP area, programming clears bits
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 32],
+    programs: Vec<u32>,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@OTP/0x1FFF7000/1*256 e";
+    const TRANSFER_SIZE: u16 = 32;
+    const WRITE_ONCE: bool = true;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        let to = (from + length.min(16)).min(TESTMEMSIZE);
+        Ok(&self.memory[from..to])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        for (m, b) in self.memory[from..from + length]
+            .iter_mut()
+            .zip(&self.buffer)
+        {
+            *m &= b;
+        }
+        self.programs.push(address);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+fn mem() -> TestMem {
+    let mut memory = [0xff; TESTMEMSIZE];
+    // provisioned word in the second block
+    memory[60] = 0x12;
+    TestMem {
+        memory,
+        buffer: [0; 32],
+        programs: Vec::new(),
+    }
+}
+
+#[test]
+fn test_blank() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.download(&mut dfu, 2, &[0x55; 32]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            let mem = dfu.release();
+            assert_eq!(mem.programs, [TESTMEM_BASE]);
+            assert_eq!(mem.memory[..32], [0x55; 32]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_programmed_twice() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.download(&mut dfu, 2, &[0x55; 32]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.abort(&mut dfu).expect("vec");
+
+            // the same block again
+            let vec = dev.download(&mut dfu, 2, &[0x55; 32]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 10, DFU_DN_BUSY));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_WRITE, 0, DFU_ERROR));
+
+            assert_eq!(dfu.release().programs, [TESTMEM_BASE]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_partly_programmed() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // second block, reads are 16 bytes, the second read finds programmed byte
+            let vec = dev.download(&mut dfu, 3, &[0x55; 32]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 10, DFU_DN_BUSY));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_WRITE, 0, DFU_ERROR));
+
+            let mem = dfu.release();
+            assert!(mem.programs.is_empty());
+            assert_eq!(mem.memory[60], 0x12);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,188 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+pub struct TestMem {
+    memory: [u8; 320],
+    buffer: [u8; 64],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    // read-only, all operations, write-only
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/2*64 a,2*64 g,1*64 d";
+    const TRANSFER_SIZE: u16 = 64;
+    const ENFORCE_PERMISSIONS: bool = true;
+    const VALIDATE_ADDRESS_POINTER: bool = true;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(&self.memory[from..(from + length).min(320)])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+fn mem() -> TestMem {
+    TestMem {
+        memory: core::array::from_fn(|i| i as u8),
+        buffer: [0; 64],
+    }
+}
+
+fn set_address<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem>,
+    address: u32,
+) {
+    let b = address.to_le_bytes();
+    dev.download(dfu, 0, &[0x21, b[0], b[1], b[2], b[3]])
+        .expect("vec");
+    dev.get_status(dfu).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+}
+
+#[test]
+fn test_program_permissions() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // writable area
+            set_address(&mut dev, &mut dfu, TESTMEM_BASE + 128);
+            for block in 2..5 {
+                dev.download(&mut dfu, block, &[0x55; 64]).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            }
+
+            // outside of the declared areas
+            dev.download(&mut dfu, 5, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // read-only area
+            set_address(&mut dev, &mut dfu, TESTMEM_BASE);
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_WRITE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_erase_permissions() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let b = (TESTMEM_BASE + 128).to_le_bytes();
+            dev.download(&mut dfu, 0, &[0x41, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            let b = (TESTMEM_BASE + 256).to_le_bytes();
+            dev.download(&mut dfu, 0, &[0x41, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ERASE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_upload_permissions() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            for block in 2..6 {
+                let vec = dev.upload(&mut dfu, block, 64).expect("vec");
+                assert_eq!(vec.len(), 64);
+            }
+
+            // write-only area
+            assert!(dev.upload(&mut dfu, 6, 64).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_address_pointer_validation() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            set_address(&mut dev, &mut dfu, TESTMEM_BASE + 64);
+
+            let b = (TESTMEM_BASE + 320).to_le_bytes();
+            dev.download(&mut dfu, 0, &[0x21, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // the pointer is not changed
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec[0], 64);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_set_address_pointer() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            assert_eq!(dfu.set_address_pointer(TESTMEM_BASE + 128), Ok(()));
+            assert_eq!(dfu.get_address_pointer(), TESTMEM_BASE + 128);
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec[0], 128);
+            dev.abort(&mut dfu).expect("vec");
+
+            // outside of the memory
+            assert_eq!(
+                dfu.set_address_pointer(TESTMEM_BASE + 320),
+                Err(DfuStatusCode::ErrAddress)
+            );
+            assert_eq!(dfu.get_address_pointer(), TESTMEM_BASE + 128);
+
+            // download in progress
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            assert_eq!(
+                dfu.set_address_pointer(TESTMEM_BASE + 192),
+                Err(DfuStatusCode::ErrNotdone)
+            );
+            assert_eq!(dfu.get_address_pointer(), TESTMEM_BASE + 128);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,137 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+#[derive(Debug, PartialEq)]
+enum Op {
+    Erase(u32),
+    Program(u32, usize),
+}
+
+pub struct TestMem {
+    ops: Vec<Op>,
+}
+
+impl TestMem {
+    fn new() -> Self {
+        Self { ops: Vec::new() }
+    }
+}
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/64*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+    const COMMAND_QUEUE_DEPTH: usize = 3;
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        self.ops.push(Op::Erase(address));
+        Ok(())
+    }
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        self.ops.push(Op::Program(address, length));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_queue_set_address_erase_write() {
+    MkDFU::new(TestMem::new)
+        .with_usb(|mut dfu, mut dev| {
+            let addr: u32 = TESTMEM_BASE + 0x400;
+            let b = addr.to_le_bytes();
+
+            /* Set Address Pointer, Erase, and Write without Get Status in between */
+            let vec = dev
+                .download(&mut dfu, 0, &[0x21, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            assert_eq!(vec, []);
+
+            let vec = dev
+                .download(&mut dfu, 0, &[0x41, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            assert_eq!(vec, []);
+
+            let vec = dev.download(&mut dfu, 2, &[0; 128]).expect("vec");
+            assert_eq!(vec, []);
+
+            /* Get State */
+            let vec = dev.get_state(&mut dfu).expect("vec");
+            assert_eq!(vec, [DFU_DNLOAD_SYNC]);
+
+            /* Get Status - timeout covers all queued commands */
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 20 + 10, DFU_DN_BUSY));
+            assert_eq!(dfu.get_address_pointer(), addr);
+
+            /* Get Status */
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            let mem = dfu.release();
+            assert_eq!(mem.ops, [Op::Erase(addr), Op::Program(addr, 128)]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_queue_write_must_be_last() {
+    MkDFU::new(TestMem::new)
+        .with_usb(|mut dfu, mut dev| {
+            /* Write block */
+            let vec = dev.download(&mut dfu, 2, &[0; 128]).expect("vec");
+            assert_eq!(vec, []);
+
+            /* Second write while the first one is queued */
+            let e = dev.download(&mut dfu, 3, &[0; 128]).expect_err("stall");
+            assert_eq!(e, AnyUsbError::EPStalled);
+
+            /* Get Status */
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_queue_full() {
+    MkDFU::new(TestMem::new)
+        .with_usb(|mut dfu, mut dev| {
+            let b = TESTMEM_BASE.to_le_bytes();
+
+            for _ in 0..TestMem::COMMAND_QUEUE_DEPTH {
+                let vec = dev
+                    .download(&mut dfu, 0, &[0x41, b[0], b[1], b[2], b[3]])
+                    .expect("vec");
+                assert_eq!(vec, []);
+            }
+
+            let e = dev
+                .download(&mut dfu, 0, &[0x41, b[0], b[1], b[2], b[3]])
+                .expect_err("stall");
+            assert_eq!(e, AnyUsbError::EPStalled);
+
+            /* Get Status */
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+
+            let mem = dfu.release();
+            assert_eq!(mem.ops, []);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,189 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::Cell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::ram::*;
+
+const RAMSIZE: usize = 256;
+const RAM_BASE: u32 = 0x2000_1000;
+
+thread_local! {
+    /// Entry address of the last execute call.
+    static ENTRY: Cell<Option<u32>> = const { Cell::new(None) };
+    /// Number of program calls of the flash.
+    static WRITES: Cell<usize> = const { Cell::new(0) };
+    /// Number of manifestation calls of the flash.
+    static MANIFESTATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+test_mem! {
+    except MEM_INFO_STRING, read, program, manifestation;
+
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/8*1Kg";
+    const VALIDATE_ADDRESS_POINTER: bool = true;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&[0x5a; 64][..length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        WRITES.set(WRITES.get() + 1);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        MANIFESTATIONS.set(MANIFESTATIONS.get() + 1);
+        Ok(())
+    }
+}
+
+struct Sram;
+
+impl RamRegion for Sram {
+    const MEM_INFO_STRING: &'static str = "@RAM/0x20001000/01*256 g";
+    const START: u32 = RAM_BASE;
+
+    fn execute(entry: u32) {
+        ENTRY.set(Some(entry));
+    }
+}
+
+type Mem = RamLoader<'static, TestMem, Sram, 64>;
+
+fn mem() -> Mem {
+    let ram = Box::leak(Box::new([0u8; RAMSIZE]));
+    RamLoader::new(TestMem {}, ram)
+}
+
+fn set_address<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    address: u32,
+) -> Vec<u8> {
+    let [a, b, c, d] = address.to_le_bytes();
+    dev.download(dfu, 0, &[0x21, a, b, c, d]).expect("vec");
+    dev.get_status(dfu).expect("vec");
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_descriptors() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev
+                .device_get_descriptor(&mut dfu, 2, 0, 0, 130)
+                .expect("vec");
+            assert_eq!(vec[9..18], [9, 4, 0, 0, 0, 0xfe, 1, 2, 4]);
+            assert_eq!(vec[18..27], [9, 4, 0, 1, 0, 0xfe, 1, 2, 5]);
+
+            let istr = dev.device_get_string(&mut dfu, 4, 0x409).expect("str");
+            assert_eq!(istr, TestMem::MEM_INFO_STRING);
+            let istr = dev.device_get_string(&mut dfu, 5, 0x409).expect("str");
+            assert_eq!(istr, Sram::MEM_INFO_STRING);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_address_pointer() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // RAM is not in the flash memory map
+            dev.download(&mut dfu, 0, &[0x21, 0x00, 0x10, 0x00, 0x20])
+                .expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            dev.interface_set_interface(&mut dfu, 0, 1).expect("set");
+            let vec = set_address(&mut dev, &mut dfu, RAM_BASE + 0x80);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            dev.abort(&mut dfu).expect("vec");
+            assert_eq!(dfu.get_address_pointer(), RAM_BASE + 0x80);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_download_and_execute() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.interface_set_interface(&mut dfu, 0, 1).expect("set");
+            set_address(&mut dev, &mut dfu, RAM_BASE);
+
+            let image: Vec<u8> = (0..100).collect();
+            for (i, block) in image.chunks(64).enumerate() {
+                dev.download(&mut dfu, 2 + i as u16, block).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            }
+
+            // entry point, like dfu-util with `:leave`
+            set_address(&mut dev, &mut dfu, RAM_BASE + 0x41);
+            dev.download(&mut dfu, 0, &[]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            assert_eq!(ENTRY.take(), Some(RAM_BASE + 0x41));
+            assert_eq!(MANIFESTATIONS.take(), 0);
+            assert_eq!(WRITES.take(), 0);
+
+            // read back
+            set_address(&mut dev, &mut dfu, RAM_BASE);
+            dev.abort(&mut dfu).expect("vec");
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec, image[..64]);
+            dev.abort(&mut dfu).expect("vec");
+
+            let mut mem = dfu.release();
+            assert_eq!(mem.ram()[..100], image[..]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_flash_download() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.download(&mut dfu, 0, &[]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            assert_eq!(ENTRY.take(), None);
+            assert_eq!(MANIFESTATIONS.take(), 1);
+            assert_eq!(WRITES.take(), 1);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_outside_of_ram() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.interface_set_interface(&mut dfu, 0, 1).expect("set");
+            set_address(&mut dev, &mut dfu, RAM_BASE + 0xc0);
+
+            dev.download(&mut dfu, 3, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
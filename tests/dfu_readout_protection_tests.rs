@@ -0,0 +1,112 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::Cell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+thread_local! {
+    /// Readout protection level of the device.
+    static LEVEL: Cell<ReadoutProtection> = const { Cell::new(ReadoutProtection::Level1) };
+}
+
+test_mem! {
+    except store_write_buffer;
+
+    const READOUT_PROTECTION_COMMANDS: bool = true;
+
+    fn readout_protection(&mut self) -> ReadoutProtection {
+        LEVEL.get()
+    }
+
+    fn set_readout_protection(&mut self, level: ReadoutProtection) -> Result<(), DfuMemoryError> {
+        if level == ReadoutProtection::Level2 {
+            return Err(DfuMemoryError::ErrVendor);
+        }
+        LEVEL.set(level);
+        Ok(())
+    }
+}
+
+/// Query the level with *Readout Protection* command.
+fn query<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem>,
+) -> Vec<u8> {
+    dev.download(dfu, 0, &[0xb7]).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    dev.abort(dfu).expect("vec");
+    // the reply is a single byte
+    dev.upload(dfu, 1, 1).expect("vec")
+}
+
+#[test]
+fn test_get_commands() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41, 0x92, 0xb7]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_query() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            LEVEL.set(ReadoutProtection::Level1);
+            assert_eq!(query(&mut dev, &mut dfu), [1]);
+
+            // the reply is selected for one request, resume point is not enabled
+            assert!(dev.upload(&mut dfu, 1, 4).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_set_readout_protection() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            LEVEL.set(ReadoutProtection::Level1);
+
+            // Read Unprotect
+            dev.download(&mut dfu, 0, &[0x92]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 30, DFU_DN_BUSY));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(LEVEL.get(), ReadoutProtection::Level0);
+            assert!(dfu.dfuse_used());
+
+            dev.download(&mut dfu, 0, &[0xb7, 1]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 30, DFU_DN_BUSY));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(LEVEL.get(), ReadoutProtection::Level1);
+            dev.abort(&mut dfu).expect("vec");
+            assert_eq!(query(&mut dev, &mut dfu), [1]);
+
+            // refused by the memory
+            dev.download(&mut dfu, 0, &[0xb7, 2]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // unknown level
+            assert!(dev.download(&mut dfu, 0, &[0xb7, 3]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+            assert_eq!(LEVEL.get(), ReadoutProtection::Level1);
+        })
+        .expect("with_usb");
+}
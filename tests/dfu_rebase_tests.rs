@@ -0,0 +1,142 @@
+#![cfg(feature = "download")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::RefCell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+thread_local! {
+    /// Addresses of [`DfuMemory::program()`] calls.
+    static PROGRAMS: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+}
+
+pub struct TestMem<const REBASE: bool> {}
+
+impl<const REBASE: bool> DfuMemory for TestMem<REBASE> {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/128*64Kg";
+    const TRANSFER_SIZE: u16 = 64;
+    const REBASE_ON_WRAP: bool = REBASE;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&[])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        PROGRAMS.with_borrow_mut(|p| p.push(address));
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_rebase() {
+    MkDFU::new(|| TestMem::<true> {})
+        .with_usb(|mut dfu, mut dev| {
+            for block in [0xfffe, 0xffff, 2, 3] {
+                dev.download(&mut dfu, block, &[0x55; 64]).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            }
+
+            assert_eq!(
+                PROGRAMS.take(),
+                [
+                    TESTMEM_BASE + 65532 * 64,
+                    TESTMEM_BASE + 65533 * 64,
+                    TESTMEM_BASE + 65534 * 64,
+                    TESTMEM_BASE + 65535 * 64,
+                ]
+            );
+            assert_eq!(dfu.get_address_pointer(), TESTMEM_BASE + 65534 * 64);
+
+            // block 2 again is a retry, not a wrap
+            dev.download(&mut dfu, 3, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(
+                PROGRAMS.take(),
+                [TESTMEM_BASE + 65535 * 64, TESTMEM_BASE + 65534 * 64]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_rebase_set_address_pointer() {
+    MkDFU::new(|| TestMem::<true> {})
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 0xffff, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+
+            // the host moves the Address Pointer itself
+            dev.download(&mut dfu, 0, &[0x21, 0x00, 0x00, 0x40, 0x08])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            assert_eq!(PROGRAMS.take(), [TESTMEM_BASE + 65533 * 64, 0x0840_0000]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_rebase_overflow() {
+    MkDFU::new(|| TestMem::<true> {})
+        .with_usb(|mut dfu, mut dev| {
+            dfu.set_address_pointer(0xffc0_0080).expect("address");
+            dev.download(&mut dfu, 0xffff, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 10, DFU_DN_BUSY));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+            assert_eq!(PROGRAMS.take(), [0xffc0_0080 + 65533 * 64]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_no_rebase() {
+    MkDFU::new(|| TestMem::<false> {})
+        .with_usb(|mut dfu, mut dev| {
+            for block in [0xffff, 2] {
+                dev.download(&mut dfu, block, &[0x55; 64]).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+            }
+
+            assert_eq!(PROGRAMS.take(), [TESTMEM_BASE + 65533 * 64, TESTMEM_BASE]);
+            assert_eq!(dfu.get_address_pointer(), TESTMEM_BASE);
+        })
+        .expect("with_usb");
+}
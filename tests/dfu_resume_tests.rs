@@ -0,0 +1,171 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::journal::*;
+
+const TESTMEMSIZE: usize = 1024;
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 64],
+    programs: Vec<u32>,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 64;
+    const RESUME_COMMAND: bool = true;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        self.programs.push(address);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct TestJournal {
+    entry: Option<JournalEntry>,
+}
+
+impl DownloadJournal for TestJournal {
+    fn load(&mut self) -> Result<Option<JournalEntry>, ()> {
+        Ok(self.entry)
+    }
+
+    fn record(&mut self, entry: &JournalEntry) -> Result<(), ()> {
+        self.entry = Some(*entry);
+        Ok(())
+    }
+}
+
+type Mem = JournalMemory<TestMem, TestJournal>;
+
+fn mem(entry: Option<JournalEntry>) -> Mem {
+    let mem = TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; 64],
+        programs: Vec::new(),
+    };
+    let journal = TestJournal { entry };
+    JournalMemory::new(mem, journal)
+}
+
+/// Power loss after 2 blocks
+const INTERRUPTED: JournalEntry = JournalEntry {
+    state: JournalState::InProgress,
+    start: TESTMEM_BASE,
+    end: TESTMEM_BASE + 128,
+};
+
+#[test]
+fn test_get_commands() {
+    MkDFU::new(|| mem(None))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41, 0xb2]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_resume_point() {
+    MkDFU::new(|| mem(Some(INTERRUPTED)))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 1, 4).expect("vec");
+            assert_eq!(vec, (TESTMEM_BASE + 128).to_le_bytes());
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_no_resume_point() {
+    MkDFU::new(|| mem(None))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 1, 4).expect("vec");
+            assert!(vec.is_empty());
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_resume() {
+    MkDFU::new(|| mem(Some(INTERRUPTED)))
+        .with_usb(|mut dfu, mut dev| {
+            let mut cmd = vec![0xb2];
+            cmd.extend_from_slice(&(TESTMEM_BASE + 128).to_le_bytes());
+            let vec = dev.download(&mut dfu, 0, &cmd).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            for i in 0..2 {
+                let vec = dev.download(&mut dfu, 2 + i, &[0x5a; 64]).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            }
+
+            let vec = dev.download(&mut dfu, 0, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let (mem, journal) = dfu.release().release();
+            assert_eq!(mem.programs, [TESTMEM_BASE + 128, TESTMEM_BASE + 192]);
+            assert_eq!(
+                journal.entry,
+                Some(JournalEntry {
+                    state: JournalState::Complete,
+                    start: TESTMEM_BASE,
+                    end: TESTMEM_BASE + 256,
+                })
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_new_download_after_interrupted() {
+    MkDFU::new(|| mem(Some(INTERRUPTED)))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.download(&mut dfu, 2, &[0x5a; 64]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            let (mem, journal) = dfu.release().release();
+            assert_eq!(
+                journal.entry,
+                Some(JournalEntry {
+                    state: JournalState::InProgress,
+                    start: TESTMEM_BASE,
+                    end: TESTMEM_BASE + 64,
+                })
+            );
+        })
+        .expect("with_usb");
+}
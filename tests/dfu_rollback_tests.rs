@@ -0,0 +1,233 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::Cell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::rollback::*;
+use usbd_dfu::suffix::*;
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+/// Maximum value of [`RamCounter`].
+const COUNTER_MAX: u32 = 8;
+
+thread_local! {
+    /// [`RamCounter`] fails to increment.
+    static BROKEN: Cell<bool> = const { Cell::new(false) };
+}
+
+pub struct TestMem {
+    manifested: usize,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+    const MANIFESTATION_TOLERANT: bool = true;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.manifested += 1;
+        Ok(())
+    }
+}
+
+pub struct RamCounter {
+    value: u32,
+}
+
+impl MonotonicCounter for RamCounter {
+    fn read(&mut self) -> Result<u32, ()> {
+        Ok(self.value)
+    }
+
+    fn increment(&mut self) -> Result<(), ()> {
+        if BROKEN.get() {
+            return Err(());
+        }
+        self.value += 1;
+        Ok(())
+    }
+
+    fn max_value(&self) -> u32 {
+        COUNTER_MAX
+    }
+}
+
+type Mem = RollbackCounter<StripSuffix<TestMem, { 128 + 16 }>, RamCounter>;
+
+fn mem() -> Mem {
+    let mem = StripSuffix::new(TestMem { manifested: 0 });
+    RollbackCounter::new(mem, RamCounter { value: 2 })
+}
+
+fn download_manifest<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    file: &[u8],
+) -> Vec<u8> {
+    for (i, block) in file.chunks(128).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_rollback_counter() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = download_manifest(&mut dev, &mut dfu, &with_suffix(&[0x11; 200], 0x1209, 2));
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // the counter is advanced to the new version
+            let vec = download_manifest(&mut dev, &mut dfu, &with_suffix(&[0x11; 200], 0x1209, 5));
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let vec = download_manifest(&mut dev, &mut dfu, &with_suffix(&[0x11; 200], 0x1209, 4));
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            let (mut mem, counter) = dfu.release().release();
+            assert_eq!(counter.value, 5);
+            assert_eq!(mem.memory().manifested, 2);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_rollback_counter_downgrade() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = download_manifest(&mut dev, &mut dfu, &with_suffix(&[0x11; 200], 0x1209, 1));
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            let (mut mem, counter) = dfu.release().release();
+            assert_eq!(counter.value, 2);
+            assert_eq!(mem.memory().manifested, 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_rollback_counter_max_value() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // rejected before the image is committed
+            let vec = download_manifest(&mut dev, &mut dfu, &with_suffix(&[0x11; 200], 0x1209, 9));
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            let vec = download_manifest(&mut dev, &mut dfu, &with_suffix(&[0x11; 200], 0x1209, 8));
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let (mut mem, counter) = dfu.release().release();
+            assert_eq!(counter.value, 8);
+            assert_eq!(mem.memory().manifested, 1);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_rollback_counter_increment_error() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // the image is committed, so manifestation succeeds
+            BROKEN.set(true);
+            let vec = download_manifest(&mut dev, &mut dfu, &with_suffix(&[0x11; 200], 0x1209, 5));
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // the next manifestation advances the counter
+            BROKEN.set(false);
+            let vec = download_manifest(&mut dev, &mut dfu, &with_suffix(&[0x11; 200], 0x1209, 3));
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let (mut mem, counter) = dfu.release().release();
+            assert_eq!(counter.value, 3);
+            assert_eq!(mem.memory().manifested, 2);
+        })
+        .expect("with_usb");
+}
+
+#[cfg(feature = "embedded-storage")]
+#[test]
+fn test_flash_counter() {
+    use embedded_storage::nor_flash::*;
+
+    struct TestFlash([u8; 64]);
+
+    impl ErrorType for TestFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for TestFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.0[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    impl NorFlash for TestFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 32;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            unreachable!()
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            for (dst, src) in self.0[offset..offset + bytes.len()].iter_mut().zip(bytes) {
+                *dst &= src;
+            }
+            Ok(())
+        }
+    }
+
+    let mut counter = FlashCounter::new(TestFlash([0xff; 64]), 32, 32);
+    assert_eq!(counter.read(), Ok(0));
+    counter.increment().unwrap();
+    counter.increment().unwrap();
+    assert_eq!(counter.read(), Ok(2));
+
+    // the value is restored from flash
+    let mut counter = FlashCounter::new(counter.release(), 32, 32);
+    assert_eq!(counter.read(), Ok(2));
+    for _ in 2..8 {
+        counter.increment().unwrap();
+    }
+    assert_eq!(counter.read(), Ok(8));
+    assert_eq!(counter.max_value(), 8);
+    assert_eq!(counter.increment(), Err(()));
+
+    let flash = counter.release();
+    assert!(flash.0[..32].iter().all(|&b| b == 0xff));
+    assert!(flash.0[32..].iter().all(|&b| b == 0));
+}
@@ -0,0 +1,219 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::sd_staging::*;
+use usbd_dfu::suffix::Crc32;
+
+/// SD card emulation
+pub struct TestCard {
+    blocks: Vec<[u8; BLOCK_SIZE]>,
+    writes: usize,
+}
+
+impl BlockDevice for TestCard {
+    fn read_block(&mut self, index: u32, block: &mut [u8; BLOCK_SIZE]) -> Result<(), ()> {
+        *block = *self.blocks.get(index as usize).ok_or(())?;
+        Ok(())
+    }
+
+    fn write_block(&mut self, index: u32, block: &[u8; BLOCK_SIZE]) -> Result<(), ()> {
+        *self.blocks.get_mut(index as usize).ok_or(())? = *block;
+        self.writes += 1;
+        Ok(())
+    }
+}
+
+/// Region at block 2, 1 record block and 2 image blocks
+type Region = RawRegion<TestCard, 3>;
+type Mem = SdStaging<Region, 128>;
+
+fn region() -> Region {
+    let card = TestCard {
+        blocks: vec![[0xaa; BLOCK_SIZE]; 8],
+        writes: 0,
+    };
+    let mut region = Region::new(card, 2);
+    // previous image
+    region.commit(&StagedImage { size: 1024, crc: 0 }).unwrap();
+    region
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    image: &[u8],
+) {
+    for (i, block) in image.chunks(128).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 20, DFU_DN_BUSY));
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+}
+
+#[test]
+fn test_consts() {
+    assert_eq!(Region::MEM_INFO_STRING, "@SD Card/0x00000000/2*512 e");
+    assert_eq!(Mem::PROGRAM_TIME_MS, 20);
+    assert_eq!(Mem::INITIAL_ADDRESS_POINTER, 0);
+}
+
+#[test]
+fn test_staged_image() {
+    MkDFU::new(|| Mem::new(region()))
+        .with_usb(|mut dfu, mut dev| {
+            let image: Vec<u8> = (0..600u32).map(|i| i as u8).collect();
+            download(&mut dev, &mut dfu, &image);
+
+            let vec = dev.download(&mut dfu, 0, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let vec = dev.upload(&mut dfu, 2, 128).expect("vec");
+            assert_eq!(vec, image[..128]);
+
+            let mut region = dfu.release().release();
+            let mut crc = Crc32::new();
+            crc.update(&image);
+            assert_eq!(
+                region.staged_image(),
+                Ok(Some(StagedImage {
+                    size: 600,
+                    crc: !crc.finalize(),
+                }))
+            );
+
+            let card = region.release();
+            assert_eq!(card.blocks[3][..], image[..512]);
+            assert_eq!(card.blocks[4][..88], image[512..]);
+            // blocks outside of the region are not modified
+            assert_eq!(card.blocks[1], [0xaa; BLOCK_SIZE]);
+            assert_eq!(card.blocks[5], [0xaa; BLOCK_SIZE]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_partial_download() {
+    MkDFU::new(|| Mem::new(region()))
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, &[0x55; 128]);
+
+            // previous image is discarded, the new one is not committed
+            let mut region = dfu.release().release();
+            assert_eq!(region.staged_image(), Ok(None));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_not_sequential() {
+    MkDFU::new(|| Mem::new(region()))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.download(&mut dfu, 3, &[0x55; 128]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+
+            let mut region = dfu.release().release();
+            assert!(region.staged_image().unwrap().is_some());
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_too_large() {
+    MkDFU::new(|| Mem::new(region()))
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, &[0x55; 1024]);
+
+            let vec = dev.download(&mut dfu, 10, &[0x55; 128]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_WRITE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_record_damaged() {
+    let image = StagedImage {
+        size: 600,
+        crc: 0x1234_5678,
+    };
+    let mut bytes = image.to_bytes();
+    assert_eq!(StagedImage::from_bytes(&bytes), Some(image));
+
+    bytes[4] ^= 1;
+    assert_eq!(StagedImage::from_bytes(&bytes), None);
+    assert_eq!(StagedImage::from_bytes(&[0; 16]), None);
+}
+
+/// `embedded-sdmmc` block device emulation
+#[cfg(feature = "embedded-sdmmc")]
+struct SdmmcCard {
+    blocks: std::cell::RefCell<Vec<embedded_sdmmc::Block>>,
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+impl embedded_sdmmc::BlockDevice for SdmmcCard {
+    type Error = core::convert::Infallible;
+
+    fn read(
+        &self,
+        blocks: &mut [embedded_sdmmc::Block],
+        start_block_idx: embedded_sdmmc::BlockIdx,
+    ) -> Result<(), Self::Error> {
+        let start = start_block_idx.0 as usize;
+        blocks.clone_from_slice(&self.blocks.borrow()[start..start + blocks.len()]);
+        Ok(())
+    }
+
+    fn write(
+        &self,
+        blocks: &[embedded_sdmmc::Block],
+        start_block_idx: embedded_sdmmc::BlockIdx,
+    ) -> Result<(), Self::Error> {
+        let start = start_block_idx.0 as usize;
+        self.blocks.borrow_mut()[start..start + blocks.len()].clone_from_slice(blocks);
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> Result<embedded_sdmmc::BlockCount, Self::Error> {
+        Ok(embedded_sdmmc::BlockCount(self.blocks.borrow().len() as u32))
+    }
+}
+
+#[cfg(feature = "embedded-sdmmc")]
+#[test]
+fn test_sdmmc_region() {
+    let card = SdmmcCard {
+        blocks: std::cell::RefCell::new(vec![embedded_sdmmc::Block::new(); 8]),
+    };
+    let mut region = SdmmcRegion::<_, 3>::new(SdmmcBlocks::new(card), 2);
+    let image = StagedImage { size: 600, crc: 1 };
+    let data: Vec<u8> = (0..600u32).map(|i| i as u8).collect();
+
+    assert_eq!(region.begin(), Ok(()));
+    assert_eq!(region.write(0, &data), Ok(()));
+    assert_eq!(region.commit(&image), Ok(()));
+    assert_eq!(region.staged_image(), Ok(Some(image)));
+
+    // crosses the block boundary
+    let mut read = [0; 64];
+    assert_eq!(region.read(500, &mut read), Ok(64));
+    assert_eq!(read, data[500..564]);
+
+    let blocks = region.release().release().blocks.into_inner();
+    assert_eq!(blocks[2][..16], image.to_bytes());
+    assert_eq!(blocks[3][..], data[..512]);
+    assert_eq!(blocks[4][..88], data[512..]);
+}
@@ -0,0 +1,224 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::Cell;
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::bus::UsbBusAllocator;
+use usbd_dfu::class::*;
+use usbd_dfu::suffix::*;
+
+thread_local! {
+    /// Number of [`DfuMemory::session_timeout()`] calls.
+    static TIMEOUTS: Cell<usize> = const { Cell::new(0) };
+}
+
+pub struct TestMem {
+    memory: [u8; 256],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/4*64 g";
+    const TRANSFER_SIZE: u16 = 64;
+    const SESSION_TIMEOUT_MS: u32 = 1000;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - Self::INITIAL_ADDRESS_POINTER) as usize;
+        Ok(&self.memory[from..(from + length).min(256)])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+
+    fn session_timeout(&mut self) {
+        TIMEOUTS.set(TIMEOUTS.get() + 1);
+    }
+}
+
+struct MkSuffixDFU {}
+
+impl UsbDeviceCtx for MkSuffixDFU {
+    type C<'c> = DfuClass<EmulatedUsbBus, StripSuffix<TestMem, { 64 + 16 }>>;
+    const EP0_SIZE: u8 = 32;
+
+    fn create_class(
+        &mut self,
+        alloc: &UsbBusAllocator<EmulatedUsbBus>,
+    ) -> AnyResult<DfuClass<EmulatedUsbBus, StripSuffix<TestMem, { 64 + 16 }>>> {
+        let mem = TestMem { memory: [0; 256] };
+        Ok(DfuClass::new(alloc, StripSuffix::new(mem)))
+    }
+}
+
+#[test]
+fn test_session_timeout_download() {
+    MkDFU::new(|| TestMem { memory: [0; 256] })
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            // requests restart the timer
+            assert!(!dfu.tick(900));
+            dev.get_state(&mut dfu).expect("vec");
+            assert!(!dfu.tick(900));
+            assert_eq!(TIMEOUTS.get(), 0);
+
+            assert!(dfu.tick(100));
+            assert_eq!(TIMEOUTS.get(), 1);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+
+            // no session in dfuERROR
+            assert!(!dfu.tick(5000));
+            dev.clear_status(&mut dfu).expect("vec");
+            let vec = dev.get_state(&mut dfu).expect("vec");
+            assert_eq!(vec, [DFU_IDLE]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_session_timeout_pending_command() {
+    MkDFU::new(|| TestMem { memory: [0; 256] })
+        .with_usb(|mut dfu, mut dev| {
+            // host is gone before it asks for the status
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            assert!(dfu.tick(1000));
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+            assert_eq!(TIMEOUTS.get(), 1);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_session_timeout_upload() {
+    MkDFU::new(|| TestMem { memory: [0; 256] })
+        .with_usb(|mut dfu, mut dev| {
+            dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert!(dfu.tick(1000));
+            assert_eq!(TIMEOUTS.get(), 1);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_session_timeout_idle() {
+    MkDFU::new(|| TestMem { memory: [0; 256] })
+        .with_usb(|mut dfu, mut dev| {
+            assert!(!dfu.tick(5000));
+            assert_eq!(TIMEOUTS.get(), 0);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_force_idle_download() {
+    MkDFU::new(|| TestMem { memory: [0; 256] })
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            dfu.force_idle();
+            assert_eq!(TIMEOUTS.get(), 1);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // the session is gone, no timeout later
+            assert!(!dfu.tick(5000));
+            assert_eq!(TIMEOUTS.get(), 1);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_force_idle_pending_command() {
+    MkDFU::new(|| TestMem { memory: [0; 256] })
+        .with_usb(|mut dfu, mut dev| {
+            // the block is never programmed
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dfu.force_idle();
+            assert_eq!(TIMEOUTS.get(), 1);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_force_idle_error() {
+    MkDFU::new(|| TestMem { memory: [0; 256] })
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            assert!(dfu.tick(1000));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+
+            dfu.force_idle();
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_force_idle_idle() {
+    MkDFU::new(|| TestMem { memory: [0; 256] })
+        .with_usb(|mut dfu, mut dev| {
+            dfu.force_idle();
+            assert_eq!(TIMEOUTS.get(), 0);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_session_timeout_adapter() {
+    MkSuffixDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            assert!(dfu.tick(1000));
+            assert_eq!(TIMEOUTS.get(), 1);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,214 @@
+#![cfg(feature = "critical-section")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::sync::Mutex;
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+use usb_device::device::{UsbDevice, UsbDeviceBuilder, UsbVidPid};
+use usb_device::endpoint::{EndpointAddress, EndpointType};
+use usb_device::{UsbDirection, UsbError};
+use usbd_dfu::class::*;
+use usbd_dfu::consts::{DFU_DNLOAD, DFU_GETSTATUS};
+use usbd_dfu::SharedDfuClass;
+
+pub struct TestMem {}
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/64*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_shared_class() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|dfu, mut dev| {
+            // emulated bus is not `Send`, so the class can't be in a `static`
+            let shared = SharedDfuClass::new();
+
+            assert!(!shared.is_initialized());
+            assert_eq!(shared.get_address_pointer(), None);
+            assert_eq!(shared.with(|dfu| ()), None);
+
+            assert!(shared.init(dfu).is_none());
+            assert!(shared.is_initialized());
+            assert_eq!(shared.get_address_pointer(), Some(TESTMEM_BASE));
+
+            let mut dfu = shared.take().expect("class");
+            assert!(!shared.is_initialized());
+
+            /* Set Address Pointer */
+            let addr = TESTMEM_BASE + 0x100;
+            let b = addr.to_le_bytes();
+            let vec = dev
+                .download(&mut dfu, 0, &[0x21, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            shared.init(dfu);
+            assert_eq!(shared.get_address_pointer(), Some(addr));
+            assert_eq!(shared.with(|dfu| dfu.get_address_pointer()), Some(addr));
+            assert!(shared.take().is_some());
+        })
+        .expect("with_usb");
+}
+
+struct BusState {
+    setup: Option<[u8; 8]>,
+    out: Option<Vec<u8>>,
+    in_data: Vec<u8>,
+    in_complete: bool,
+}
+
+static BUS: Mutex<BusState> = Mutex::new(BusState {
+    setup: None,
+    out: None,
+    in_data: Vec::new(),
+    in_complete: false,
+});
+
+/// Control endpoint only bus, so that `UsbDevice` is polled by [`SharedDfuClass::poll()`].
+struct PollBus;
+
+impl UsbBus for PollBus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        ep_addr: Option<EndpointAddress>,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval: u8,
+    ) -> usb_device::Result<EndpointAddress> {
+        ep_addr.ok_or(UsbError::EndpointOverflow)
+    }
+
+    fn enable(&mut self) {}
+
+    fn reset(&self) {}
+
+    fn set_device_address(&self, addr: u8) {}
+
+    fn write(&self, ep_addr: EndpointAddress, buf: &[u8]) -> usb_device::Result<usize> {
+        let mut bus = BUS.lock().unwrap();
+        bus.in_data.extend_from_slice(buf);
+        bus.in_complete = true;
+        Ok(buf.len())
+    }
+
+    fn read(&self, ep_addr: EndpointAddress, buf: &mut [u8]) -> usb_device::Result<usize> {
+        let mut bus = BUS.lock().unwrap();
+        if let Some(setup) = bus.setup.take() {
+            buf[..8].copy_from_slice(&setup);
+            return Ok(8);
+        }
+        let data = bus.out.take().ok_or(UsbError::WouldBlock)?;
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn set_stalled(&self, ep_addr: EndpointAddress, stalled: bool) {}
+
+    fn is_stalled(&self, ep_addr: EndpointAddress) -> bool {
+        false
+    }
+
+    fn suspend(&self) {}
+
+    fn resume(&self) {}
+
+    fn poll(&self) -> PollResult {
+        let mut bus = BUS.lock().unwrap();
+        let ep_setup = bus.setup.is_some() as u16;
+        let ep_out = (ep_setup == 0 && bus.out.is_some()) as u16;
+        let ep_in_complete = core::mem::take(&mut bus.in_complete) as u16;
+        if ep_setup | ep_out | ep_in_complete == 0 {
+            return PollResult::None;
+        }
+        PollResult::Data {
+            ep_out,
+            ep_in_complete,
+            ep_setup,
+        }
+    }
+}
+
+static POLLED: SharedDfuClass<PollBus, TestMem> = SharedDfuClass::new();
+
+/// Host side of a control transfer with a data stage of at most 8 bytes.
+fn control(usb_dev: &mut UsbDevice<'_, PollBus>, setup: [u8; 8], data: &[u8]) -> Vec<u8> {
+    let poll = |usb_dev: &mut UsbDevice<'_, PollBus>| {
+        while {
+            let bus = BUS.lock().unwrap();
+            bus.setup.is_some() || bus.out.is_some() || bus.in_complete
+        } {
+            POLLED.poll(usb_dev);
+        }
+    };
+
+    BUS.lock().unwrap().setup = Some(setup);
+    poll(usb_dev);
+    if !data.is_empty() {
+        BUS.lock().unwrap().out = Some(data.to_vec());
+        poll(usb_dev);
+    }
+    let vec = core::mem::take(&mut BUS.lock().unwrap().in_data);
+    if setup[0] & 0x80 != 0 {
+        // status stage
+        BUS.lock().unwrap().out = Some(Vec::new());
+        poll(usb_dev);
+    }
+    vec
+}
+
+fn get_status(usb_dev: &mut UsbDevice<'_, PollBus>) -> Vec<u8> {
+    control(usb_dev, [0xa1, DFU_GETSTATUS, 0, 0, 0, 0, 6, 0], &[])
+}
+
+#[test]
+fn test_shared_poll() {
+    let alloc = UsbBusAllocator::new(PollBus);
+    assert_eq!(POLLED.set_address_pointer(TESTMEM_BASE), None);
+    assert!(POLLED.init(DfuClass::new(&alloc, TestMem {})).is_none());
+    let mut usb_dev = UsbDeviceBuilder::new(&alloc, UsbVidPid(0x1209, 0x2444)).build();
+
+    /* Set Address Pointer */
+    let addr = TESTMEM_BASE + 0x100;
+    let b = addr.to_le_bytes();
+    let cmd = [0x21, b[0], b[1], b[2], b[3]];
+    control(&mut usb_dev, [0x21, DFU_DNLOAD, 0, 0, 0, 0, 5, 0], &cmd);
+    get_status(&mut usb_dev);
+    let vec = get_status(&mut usb_dev);
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    assert_eq!(POLLED.get_address_pointer(), Some(addr));
+    assert!(POLLED.take_host_poll());
+    assert!(!POLLED.take_host_poll());
+
+    // a download is in progress
+    assert_eq!(
+        POLLED.set_address_pointer(TESTMEM_BASE),
+        Some(Err(DfuStatusCode::ErrNotdone))
+    );
+
+    POLLED.force_idle();
+    let vec = get_status(&mut usb_dev);
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+    assert_eq!(POLLED.set_address_pointer(TESTMEM_BASE), Some(Ok(())));
+    assert_eq!(POLLED.get_address_pointer(), Some(TESTMEM_BASE));
+    assert!(POLLED.take().is_some());
+}
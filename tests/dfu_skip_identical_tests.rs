@@ -0,0 +1,160 @@
+#![cfg(feature = "download")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::RefCell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+thread_local! {
+    /// Addresses of [`DfuMemory::program()`] calls.
+    static PROGRAMS: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+}
+
+pub struct TestMem<const SKIP: bool> {
+    memory: [u8; 256],
+    buffer: [u8; 64],
+}
+
+impl<const SKIP: bool> TestMem<SKIP> {
+    fn new() -> Self {
+        Self {
+            memory: [0xff; 256],
+            buffer: [0; 64],
+        }
+    }
+}
+
+impl<const SKIP: bool> DfuMemory for TestMem<SKIP> {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/4*64 g";
+    const TRANSFER_SIZE: u16 = 64;
+    const SKIP_IDENTICAL: bool = SKIP;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = address
+            .checked_sub(TESTMEM_BASE)
+            .filter(|&o| o < 256)
+            .ok_or(DfuMemoryError::Address)? as usize;
+        // at most 16 bytes at once
+        Ok(&self.memory[from..(from + length.min(16)).min(256)])
+    }
+
+    fn compare(&mut self, address: u32, length: usize) -> Result<bool, DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(self.memory[from..from + length] == self.buffer[..length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        PROGRAMS.with_borrow_mut(|p| p.push(address));
+        if let Some(from) = address
+            .checked_sub(TESTMEM_BASE)
+            .map(|o| o as usize)
+            .filter(|o| o + length <= 256)
+        {
+            self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        }
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+type Dev<'a, const SKIP: bool> =
+    Device<'a, DfuClass<EmulatedUsbBus, TestMem<SKIP>>, MkDFU<TestMem<SKIP>>>;
+
+/// Download `blocks` from block 2, and abort.
+fn download<const SKIP: bool>(
+    dev: &mut Dev<SKIP>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem<SKIP>>,
+    blocks: &[[u8; 64]],
+) {
+    for (i, data) in blocks.iter().enumerate() {
+        dev.download(dfu, 2 + i as u16, data).expect("vec");
+        dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+    dev.abort(dfu).expect("vec");
+}
+
+#[test]
+fn test_skip_identical() {
+    MkDFU::new(TestMem::<true>::new)
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, &[[0x11; 64], [0x22; 64]]);
+            assert_eq!(PROGRAMS.take(), [TESTMEM_BASE, TESTMEM_BASE + 64]);
+
+            // the second block is changed
+            let mut changed = [0x22; 64];
+            changed[63] = 0x33;
+            download(&mut dev, &mut dfu, &[[0x11; 64], changed]);
+            assert_eq!(PROGRAMS.take(), [TESTMEM_BASE + 64]);
+
+            // erased memory is compared too
+            download(&mut dev, &mut dfu, &[[0x11; 64], changed, [0xff; 64]]);
+            assert!(PROGRAMS.take().is_empty());
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_skip_identical_crc_collision() {
+    MkDFU::new(TestMem::<true>::new)
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, &[[0x11; 64]]);
+            assert_eq!(PROGRAMS.take(), [TESTMEM_BASE]);
+
+            // the difference is the CRC-32 polynomial, CRC-32 of the block is not changed
+            let mut data = [0x11; 64];
+            for (b, d) in data[10..].iter_mut().zip([0x41, 0x06, 0x71, 0xdb, 0x01]) {
+                *b ^= d;
+            }
+            download(&mut dev, &mut dfu, &[data]);
+            assert_eq!(PROGRAMS.take(), [TESTMEM_BASE]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_skip_identical_read_error() {
+    MkDFU::new(TestMem::<true>::new)
+        .with_usb(|mut dfu, mut dev| {
+            // not readable, programmed
+            dfu.set_address_pointer(TESTMEM_BASE + 256)
+                .expect("address");
+            download(&mut dev, &mut dfu, &[[0xff; 64]]);
+            assert_eq!(PROGRAMS.take(), [TESTMEM_BASE + 256]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_no_skip() {
+    MkDFU::new(TestMem::<false>::new)
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, &[[0x11; 64]]);
+            download(&mut dev, &mut dfu, &[[0x11; 64]]);
+            assert_eq!(PROGRAMS.take(), [TESTMEM_BASE, TESTMEM_BASE]);
+        })
+        .expect("with_usb");
+}
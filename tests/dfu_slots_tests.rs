@@ -0,0 +1,200 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::slots::*;
+
+const TESTMEMSIZE: usize = 2048;
+const TESTMEM_BASE: u32 = 0x0200_0000;
+const SLOT_SIZE: u32 = 1024;
+
+const LAYOUT: SlotLayout = SlotLayout {
+    slot_a: TESTMEM_BASE,
+    slot_b: TESTMEM_BASE + SLOT_SIZE,
+    size: SLOT_SIZE,
+    page_size: 256,
+};
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 64],
+    erases: Vec<u32>,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/4*256Kg";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(&self.memory[from..from + length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        self.erases.push(address);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+pub struct RamStorage(SlotStatus);
+
+impl SlotStorage for RamStorage {
+    fn load(&mut self) -> Result<SlotStatus, ()> {
+        Ok(self.0)
+    }
+
+    fn store(&mut self, status: &SlotStatus) -> Result<(), ()> {
+        self.0 = *status;
+        Ok(())
+    }
+}
+
+type Mem = SlotMemory<TestMem, RamStorage>;
+
+fn mem(status: SlotStatus) -> Mem {
+    let mem = TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; 64],
+        erases: Vec::new(),
+    };
+    let manager = SlotManager::new(RamStorage(status));
+    SlotMemory::new(mem, manager, LAYOUT)
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    file: &[u8],
+) {
+    for (i, block) in file.chunks(64).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+}
+
+#[test]
+fn test_download_to_inactive_slot() {
+    MkDFU::new(|| mem(SlotStatus::default()))
+        .with_usb(|mut dfu, mut dev| {
+            let image = [0x5a; 100];
+            download(&mut dev, &mut dfu, &image);
+
+            let (mem, manager) = dfu.release().release();
+            assert!(mem.memory[..1024].iter().all(|&b| b == 0xff));
+            assert_eq!(&mem.memory[1024..1124], &image[..]);
+
+            let storage = manager.release();
+            assert_eq!(
+                storage.0,
+                SlotStatus {
+                    active: Slot::A,
+                    pending: true,
+                    trial: false,
+                }
+            );
+
+            // trial boot, confirmed by the application
+            let mut manager = SlotManager::new(storage);
+            assert_eq!(manager.boot(), Ok(Slot::B));
+            assert!(manager.status().unwrap().trial);
+            assert_eq!(manager.confirm(), Ok(()));
+            assert_eq!(manager.boot(), Ok(Slot::B));
+            assert_eq!(manager.rollback(), Err(()));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_active_slot_b() {
+    MkDFU::new(|| {
+        mem(SlotStatus {
+            active: Slot::B,
+            pending: false,
+            trial: false,
+        })
+    })
+    .with_usb(|mut dfu, mut dev| {
+        let image = [0x5a; 100];
+        download(&mut dev, &mut dfu, &image);
+
+        let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+        assert_eq!(vec, [0x5a; 64]);
+
+        let (mem, manager) = dfu.release().release();
+        assert_eq!(&mem.memory[..100], &image[..]);
+        assert!(mem.memory[1024..].iter().all(|&b| b == 0xff));
+    })
+    .expect("with_usb");
+}
+
+#[test]
+fn test_slot_out_of_range() {
+    MkDFU::new(|| mem(SlotStatus::default()))
+        .with_usb(|mut dfu, mut dev| {
+            // block 18 is at offset 1024
+            let vec = dev.download(&mut dfu, 18, &[0; 64]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_trial_not_confirmed() {
+    let mut manager = SlotManager::new(RamStorage(SlotStatus {
+        active: Slot::A,
+        pending: true,
+        trial: false,
+    }));
+    assert_eq!(manager.boot(), Ok(Slot::B));
+    // reset before confirmation
+    assert_eq!(manager.boot(), Ok(Slot::A));
+    assert_eq!(manager.status(), Ok(SlotStatus::default()));
+
+    manager.storage().0.pending = true;
+    assert_eq!(manager.boot(), Ok(Slot::B));
+    assert_eq!(manager.rollback(), Ok(()));
+    assert_eq!(manager.boot(), Ok(Slot::A));
+}
+
+#[test]
+fn test_slot_status_bytes() {
+    let status = SlotStatus {
+        active: Slot::B,
+        pending: false,
+        trial: true,
+    };
+    assert_eq!(SlotStatus::from_byte(status.to_byte()), Some(status));
+    assert_eq!(SlotStatus::from_byte(0xff), None);
+}
@@ -0,0 +1,286 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::bus::UsbBusAllocator;
+use usb_device::class_prelude::*;
+use usbd_dfu::class::*;
+use usbd_dfu::split::*;
+
+#[derive(Debug, PartialEq)]
+enum Op {
+    Erase(u32),
+    Program(u32, Vec<u8>),
+    Manifestation,
+    SessionTimeout,
+}
+
+pub struct TestMem {
+    ops: Vec<Op>,
+    buffer: Vec<u8>,
+}
+
+impl TestMem {
+    fn new() -> Self {
+        Self {
+            ops: Vec::new(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+const BUFFER_SIZE: usize = 128;
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MANIFESTATION_TIME_MS: u32 = 40;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/64*1Kg";
+    const TRANSFER_SIZE: u16 = BUFFER_SIZE as u16;
+    const MANIFESTATION_TOLERANT: bool = true;
+    const SESSION_TIMEOUT_MS: u32 = 1000;
+    const COMMAND_QUEUE_DEPTH: usize = 4;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Err(DfuMemoryError::Unknown)
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        self.ops.push(Op::Erase(address));
+        Ok(())
+    }
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer = src.to_vec();
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        self.ops
+            .push(Op::Program(address, self.buffer[..length].to_vec()));
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.ops.push(Op::Manifestation);
+        Ok(())
+    }
+
+    fn session_timeout(&mut self) {
+        self.ops.push(Op::SessionTimeout);
+    }
+}
+
+/// Both halves of a split class, only `control` is visible to USB.
+struct SplitDfu {
+    control: DfuControl<'static, EmulatedUsbBus, TestMem, BUFFER_SIZE>,
+    worker: DfuWorker<'static, TestMem, BUFFER_SIZE>,
+}
+
+impl UsbClass<EmulatedUsbBus> for SplitDfu {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        self.control.get_configuration_descriptors(writer)
+    }
+
+    fn get_string(&self, index: StringIndex, lang_id: LangID) -> Option<&str> {
+        self.control.get_string(index, lang_id)
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<EmulatedUsbBus>) {
+        self.control.control_in(xfer)
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<EmulatedUsbBus>) {
+        self.control.control_out(xfer)
+    }
+
+    fn reset(&mut self) {
+        UsbClass::<EmulatedUsbBus>::reset(&mut self.control)
+    }
+
+    fn poll(&mut self) {
+        UsbClass::<EmulatedUsbBus>::poll(&mut self.control)
+    }
+}
+
+struct MkDFU {}
+
+impl UsbDeviceCtx for MkDFU {
+    type C<'c> = SplitDfu;
+    const EP0_SIZE: u8 = 32;
+
+    fn create_class(&mut self, alloc: &UsbBusAllocator<EmulatedUsbBus>) -> AnyResult<SplitDfu> {
+        let channel = Box::leak(Box::new(DfuChannel::new()));
+        let (control, worker) = DfuClass::new(alloc, TestMem::new()).split(channel);
+        Ok(SplitDfu { control, worker })
+    }
+}
+
+#[test]
+fn test_split_descriptor_no_upload() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev
+                .device_get_descriptor(&mut dfu, 2, 0, 0, 255)
+                .expect("vec");
+
+            let dfu_desc = &vec[18..];
+            assert_eq!(dfu_desc[0], 9);
+            assert_eq!(dfu_desc[1], 0x21);
+            // bitCanDnload | bitManifestationTolerant, no bitCanUpload
+            assert_eq!(dfu_desc[2], 0b1101);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_split_erase_write() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            let addr: u32 = TESTMEM_BASE + 0x400;
+            let b = addr.to_le_bytes();
+
+            /* Erase */
+            let vec = dev
+                .download(&mut dfu, 0, &[0x41, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            assert_eq!(vec, []);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 20, DFU_DN_BUSY));
+
+            /* Worker did not run yet */
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 20, DFU_DN_BUSY));
+            assert!(dfu.worker.is_pending());
+
+            dfu.worker.update();
+            assert!(!dfu.worker.is_pending());
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(dfu.control.get_address_pointer(), TESTMEM_BASE);
+
+            /* Write block */
+            let block: Vec<u8> = (0..128).collect();
+            let vec = dev.download(&mut dfu, 2, &block).expect("vec");
+            assert_eq!(vec, []);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 10, DFU_DN_BUSY));
+
+            dfu.worker.update();
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            /* Manifestation */
+            let vec = dev.download(&mut dfu, 3, &[]).expect("vec");
+            assert_eq!(vec, []);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 40, DFU_MANIFEST));
+
+            dfu.worker.update();
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            assert_eq!(
+                dfu.worker.memory().ops,
+                [
+                    Op::Erase(addr),
+                    Op::Program(TESTMEM_BASE, block),
+                    Op::Manifestation
+                ]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_split_upload_stalls() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            /* Get Commands still works */
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41]);
+
+            let vec = dev.abort(&mut dfu).expect("vec");
+
+            /* Memory read is not supported */
+            let e = dev.upload(&mut dfu, 2, 128).expect_err("stall");
+            assert_eq!(e, AnyUsbError::EP0Stalled);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_split_session_timeout() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            let addr: u32 = TESTMEM_BASE + 0x400;
+            let b = addr.to_le_bytes();
+
+            dev.download(&mut dfu, 0, &[0x41, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 20, DFU_DN_BUSY));
+
+            /* Erase in progress is not cancelled */
+            assert!(!dfu.control.tick(2000));
+
+            dfu.worker.update();
+            assert!(dfu.control.tick(1000));
+            assert!(dfu.worker.is_pending());
+            dfu.worker.update();
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_NOTDONE, 0, DFU_ERROR));
+            assert_eq!(
+                dfu.worker.memory().ops,
+                [Op::Erase(addr), Op::SessionTimeout]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_split_usb_reset_queue() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            let addr: u32 = TESTMEM_BASE + 0x400;
+            let b = addr.to_le_bytes();
+            let c = (addr + 0x400).to_le_bytes();
+
+            dev.download(&mut dfu, 0, &[0x41, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            dev.download(&mut dfu, 0, &[0x41, c[0], c[1], c[2], c[3]])
+                .expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 40, DFU_DN_BUSY));
+
+            /* Queued commands are dropped, the erase in progress completes */
+            dfu.reset();
+            dfu.worker.update();
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_USBR, 0, DFU_ERROR));
+            assert!(!dfu.worker.is_pending());
+            dfu.worker.update();
+
+            assert_eq!(dfu.worker.memory().ops, [Op::Erase(addr)]);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,214 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::staging::*;
+
+const TESTMEMSIZE: usize = 2048;
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 64],
+    erases: Vec<u32>,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/8*256Bg";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(&self.memory[from..from + length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize & !255;
+        self.memory[from..from + 256].fill(0xff);
+        self.erases.push(address);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+struct Layout;
+
+impl StagingLayout for Layout {
+    const ACTIVE: &'static str = "@Active/0x08000000/4*256Ba";
+    const STAGING: &'static str = "@Staging/0x08000000/4*256Bg";
+    const ACTIVE_ADDRESS: u32 = TESTMEM_BASE;
+    const STAGING_ADDRESS: u32 = TESTMEM_BASE + 1024;
+    const SIZE: u32 = 1024;
+    const PAGE_SIZE: u32 = 256;
+}
+
+type Mem = StagingMemory<TestMem, Layout>;
+
+fn mem() -> Mem {
+    let mut mem = TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; 64],
+        erases: Vec::new(),
+    };
+    mem.memory[..1024].fill(0xaa);
+    StagingMemory::new(mem)
+}
+
+#[test]
+fn test_descriptors() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev
+                .device_get_descriptor(&mut dfu, 2, 0, 0, 130)
+                .expect("vec");
+            assert_eq!(vec[9..18], [9, 4, 0, 0, 0, 0xfe, 1, 2, 4]);
+            assert_eq!(vec[18..27], [9, 4, 0, 1, 0, 0xfe, 1, 2, 5]);
+
+            let istr = dev.device_get_string(&mut dfu, 4, 0x409).expect("str");
+            assert_eq!(istr, Layout::ACTIVE);
+            let istr = dev.device_get_string(&mut dfu, 5, 0x409).expect("str");
+            assert_eq!(istr, Layout::STAGING);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_active_is_read_only() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec, [0xaa; 64]);
+            dev.abort(&mut dfu).expect("vec");
+
+            assert!(dev.download(&mut dfu, 2, &[0x55; 64]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            let mem = dfu.release().release();
+            assert!(mem.memory[1024..].iter().all(|&b| b == 0xff));
+            assert!(mem.erases.is_empty());
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_download_to_staging() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.interface_set_interface(&mut dfu, 0, 1).expect("set");
+
+            // erase of the first page of the region
+            dev.download(&mut dfu, 0, &[0x41, 0x00, 0x00, 0x00, 0x08])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            let image: Vec<u8> = (0..100).collect();
+            for (i, block) in image.chunks(64).enumerate() {
+                dev.download(&mut dfu, 2 + i as u16, block).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            }
+            dev.abort(&mut dfu).expect("vec");
+
+            // read back from staging
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec, image[..64]);
+            dev.abort(&mut dfu).expect("vec");
+
+            // active is unchanged until manifestation
+            dev.interface_set_interface(&mut dfu, 0, 0).expect("set");
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec, [0xaa; 64]);
+            dev.abort(&mut dfu).expect("vec");
+
+            let mem = dfu.release().release();
+            assert_eq!(mem.erases, [TESTMEM_BASE + 1024]);
+            assert_eq!(mem.memory[1024..1124], image[..]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_manifestation_promotes_staging() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.interface_set_interface(&mut dfu, 0, 1).expect("set");
+
+            let image: Vec<u8> = (0..300).map(|i| i as u8).collect();
+            for (i, block) in image.chunks(64).enumerate() {
+                dev.download(&mut dfu, 2 + i as u16, block).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            }
+            dev.download(&mut dfu, 0, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            dev.interface_set_interface(&mut dfu, 0, 0).expect("set");
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec, image[..64]);
+            dev.abort(&mut dfu).expect("vec");
+
+            let mem = dfu.release().release();
+            // two active pages are erased before the copy
+            assert_eq!(mem.erases, [TESTMEM_BASE, TESTMEM_BASE + 256]);
+            assert_eq!(mem.memory[..300], image[..]);
+            assert!(mem.memory[300..512].iter().all(|&b| b == 0xff));
+            assert!(mem.memory[512..1024].iter().all(|&b| b == 0xaa));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_address_outside_of_region() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.interface_set_interface(&mut dfu, 0, 1).expect("set");
+
+            dev.download(&mut dfu, 0, &[0x21, 0x00, 0x04, 0x00, 0x08])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+
+            // 0x08000400 is the staging region in the wrapped memory, not a DFU address
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(
+                vec,
+                status(STATUS_OK, TestMem::PROGRAM_TIME_MS, DFU_DN_BUSY)
+            );
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,278 @@
+#![cfg(feature = "stm32-flash")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+use usbd_dfu::class::*;
+use usbd_dfu::flash::FlashLayout;
+use usbd_dfu::stm32::*;
+
+const FLASH_SIZE: usize = 0x1_0000;
+
+/// Flash that can be modified only when unlocked, offsets are relative to 0x08000000
+pub struct TestFlash {
+    memory: Vec<u8>,
+    unlocked: std::rc::Rc<std::cell::Cell<bool>>,
+}
+
+#[derive(Debug)]
+pub struct TestFlashError;
+
+impl NorFlashError for TestFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        NorFlashErrorKind::Other
+    }
+}
+
+impl ErrorType for TestFlash {
+    type Error = TestFlashError;
+}
+
+impl ReadNorFlash for TestFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.memory[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
+
+impl NorFlash for TestFlash {
+    const WRITE_SIZE: usize = 2;
+    const ERASE_SIZE: usize = 1024;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !self.unlocked.get() {
+            return Err(TestFlashError);
+        }
+        self.memory[from as usize..to as usize].fill(0xff);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        if !self.unlocked.get() {
+            return Err(TestFlashError);
+        }
+        let offset = offset as usize;
+        self.memory[offset..offset + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+pub struct TestControl {
+    unlocked: std::rc::Rc<std::cell::Cell<bool>>,
+    unlocks: usize,
+}
+
+impl FlashControl for TestControl {
+    fn unlock(&mut self) {
+        self.unlocked.set(true);
+        self.unlocks += 1;
+    }
+
+    fn lock(&mut self) {
+        self.unlocked.set(false);
+    }
+}
+
+struct App;
+
+impl Stm32Region for App {
+    const START: u32 = 0x0800_2000;
+    const END: u32 = 0x0801_0000;
+}
+
+struct F4App;
+
+impl Stm32Region for F4App {
+    const START: u32 = 0x0800_8000;
+    const END: u32 = 0x0810_0000;
+}
+
+type Mem = Stm32Flash<TestFlash, TestControl, Stm32F1<App>, 64>;
+
+fn mem() -> Mem {
+    let unlocked = std::rc::Rc::new(std::cell::Cell::new(false));
+    let flash = TestFlash {
+        memory: vec![0; FLASH_SIZE],
+        unlocked: unlocked.clone(),
+    };
+    let control = TestControl {
+        unlocked,
+        unlocks: 0,
+    };
+    Stm32Flash::new(LockingFlash::new(flash, control), FLASH_BASE)
+}
+
+#[test]
+fn test_mem_info_strings() {
+    assert_eq!(Stm32F1::<App>::MEM_INFO_STRING, "@Flash/0x08002000/56*1Kg");
+    assert_eq!(
+        Stm32F1::<App, 2048>::MEM_INFO_STRING,
+        "@Flash/0x08002000/28*2Kg"
+    );
+    assert_eq!(Stm32G0::<App>::MEM_INFO_STRING, "@Flash/0x08002000/28*2Kg");
+    assert_eq!(
+        Stm32F4::<F4App>::MEM_INFO_STRING,
+        "@Flash/0x08008000/2*16Kg,1*64Kg,7*128Kg"
+    );
+    assert_eq!(Stm32F4::<F4App>::FULL_ERASE_TIME_MS, 10 * 2000);
+}
+
+#[test]
+fn test_f4_sectors() {
+    assert_eq!(f4_sector(0x0800_0000), (0x0800_0000, 0x4000));
+    assert_eq!(f4_sector(0x0800_c123), (0x0800_c000, 0x4000));
+    assert_eq!(f4_sector(0x0801_8000), (0x0801_0000, 0x1_0000));
+    assert_eq!(f4_sector(0x080f_ffff), (0x080e_0000, 0x2_0000));
+    // second bank
+    assert_eq!(f4_sector(0x0810_4000), (0x0810_4000, 0x4000));
+
+    assert_eq!(Stm32F4::<F4App>::page(0x0800_0000), None);
+    assert_eq!(
+        Stm32F4::<F4App>::page(0x0802_0000),
+        Some((0x0802_0000, 0x2_0000))
+    );
+}
+
+#[test]
+fn test_flash_keys() {
+    // KEYR, CR
+    let mut regs = [0u32, 1 << 31];
+    let keyr = &mut regs[0] as *mut u32;
+    let cr = unsafe { keyr.add(1) };
+    let mut keys = unsafe { FlashKeys::new(keyr, cr, 1 << 31) };
+
+    keys.unlock();
+    assert_eq!(regs[0], FlashKeys::KEY2);
+
+    // already unlocked
+    regs = [0, 0];
+    keys.unlock();
+    assert_eq!(regs[0], 0);
+
+    regs[1] = 0x10;
+    keys.lock();
+    assert_eq!(regs[1], 0x8000_0010);
+}
+
+#[test]
+fn test_download() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // erase the first page
+            let vec = dev
+                .download(&mut dfu, 0, &[0x41, 0x00, 0x20, 0x00, 0x08])
+                .expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            let image: Vec<u8> = (0..100u32).map(|i| i as u8).collect();
+            for (i, block) in image.chunks(64).enumerate() {
+                let vec = dev.download(&mut dfu, 2 + i as u16, block).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            }
+
+            let (flash, control) = dfu.release().release().release();
+            assert_eq!(control.unlocks, 3);
+            assert!(!control.unlocked.get());
+            assert_eq!(flash.memory[0x2000..0x2000 + 100], image);
+            assert!(flash.memory[0x2000 + 100..0x2400]
+                .iter()
+                .all(|&b| b == 0xff));
+            assert_eq!(flash.memory[0x2400], 0);
+        })
+        .expect("with_usb");
+}
+
+struct Eeprom;
+
+impl Stm32Region for Eeprom {
+    const START: u32 = 0x0808_0000;
+    const END: u32 = 0x0808_1800;
+}
+
+static RELOADS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+struct Options;
+
+impl Stm32Region for Options {
+    const START: u32 = 0x1fff_7800;
+    const END: u32 = 0x1fff_7880;
+}
+
+impl Stm32OptionRegion for Options {
+    fn reload() {
+        RELOADS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+fn locking_flash(memory: Vec<u8>) -> LockingFlash<TestFlash, TestControl> {
+    let unlocked = std::rc::Rc::new(std::cell::Cell::new(false));
+    let flash = TestFlash {
+        memory,
+        unlocked: unlocked.clone(),
+    };
+    let control = TestControl {
+        unlocked,
+        unlocks: 0,
+    };
+    LockingFlash::new(flash, control)
+}
+
+#[test]
+fn test_eeprom() {
+    type EepromMem = Stm32Flash<TestFlash, TestControl, Stm32Eeprom<Eeprom>, 64>;
+    assert_eq!(
+        Stm32Eeprom::<Eeprom>::MEM_INFO_STRING,
+        "@EEPROM/0x08080000/1*6Ke"
+    );
+    const { assert!(EepromMem::MANIFESTATION_TOLERANT) };
+
+    let mut memory = vec![0; FLASH_SIZE];
+    memory[0x103] = 0x55;
+    let mut mem = EepromMem::new(locking_flash(memory), 0x0808_0000 - 0x100);
+
+    // erase doesn't modify the memory
+    assert!(mem.erase_all().is_ok());
+    assert!(mem.erase(0x0808_0000).is_ok());
+    assert!(mem.store_write_buffer(&[1, 2, 3]).is_ok());
+    // the last unit is padded with current data
+    assert!(mem.program(0x0808_0000, 3).is_ok());
+    assert!(mem.manifestation().is_ok());
+
+    let (flash, control) = mem.release().release();
+    assert_eq!(control.unlocks, 1);
+    assert_eq!(flash.memory[0x100..0x106], [1, 2, 3, 0x55, 0, 0]);
+}
+
+#[test]
+fn test_option_bytes() {
+    type OptionMem = Stm32Flash<TestFlash, TestControl, Stm32OptionBytes<Options>, 64>;
+    assert_eq!(
+        Stm32OptionBytes::<Options>::MEM_INFO_STRING,
+        "@Option Bytes/0x1FFF7800/1*128 e"
+    );
+    const { assert!(!OptionMem::MANIFESTATION_TOLERANT) };
+
+    let mut mem = OptionMem::new(locking_flash(vec![0; FLASH_SIZE]), 0x1fff_7800);
+    assert!(mem.store_write_buffer(&[0xaa; 16]).is_ok());
+    assert!(mem.program(0x1fff_7800, 16).is_ok());
+    assert_eq!(RELOADS.load(std::sync::atomic::Ordering::SeqCst), 0);
+    assert!(mem.manifestation().is_ok());
+    assert_eq!(RELOADS.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
@@ -0,0 +1,149 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::suffix::*;
+
+const TESTMEMSIZE: usize = 1024;
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 128],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        assert!(length <= Self::TRANSFER_SIZE as usize);
+        let from = (address - TESTMEM_BASE) as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+type Mem = StripSuffix<TestMem, { 128 + 16 }>;
+
+fn mem() -> Mem {
+    let mem = TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; 128],
+    };
+    StripSuffix::new(mem).with_device(0x1209, 0x2444, 0x0100)
+}
+
+fn download_file<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    file: &[u8],
+) {
+    for (i, block) in file.chunks(128).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 10, DFU_DN_BUSY));
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+}
+
+#[test]
+fn test_strip_suffix() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // the suffix is split between the last two blocks
+            let img = image(250);
+            download_file(
+                &mut dev,
+                &mut dfu,
+                &with_suffix(&img, 0x1209, SUFFIX_WILDCARD),
+            );
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let mut mem = dfu.release();
+            assert_eq!(mem.suffix().expect("suffix").usb_vendor, 0x1209);
+            let memory = &mem.memory().memory;
+            assert_eq!(&memory[..250], &img[..]);
+            assert!(memory[250..].iter().all(|&b| b == 0xff));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_strip_suffix_no_suffix() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let img = image(300);
+            download_file(&mut dev, &mut dfu, &img);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            let mut mem = dfu.release();
+            assert!(mem.suffix().is_none());
+            let memory = &mem.memory().memory;
+            assert_eq!(&memory[..300], &img[..]);
+            assert!(memory[300..].iter().all(|&b| b == 0xff));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_strip_suffix_bad_crc() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let mut file = with_suffix(&image(200), 0x1209, SUFFIX_WILDCARD);
+            file[10] ^= 1;
+            download_file(&mut dev, &mut dfu, &file);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_strip_suffix_wrong_target() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            download_file(
+                &mut dev,
+                &mut dfu,
+                &with_suffix(&image(200), 0x1234, SUFFIX_WILDCARD),
+            );
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_TARGET, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
@@ -8,6 +8,7 @@ use helpers::*;
 use usbd_class_tester::prelude::*;
 
 use usb_device::bus::UsbBusAllocator;
+use usb_device::class::UsbClass;
 
 use usbd_dfu::class::*;
 
@@ -18,6 +19,7 @@ pub struct TestMem {
     overrides: TestMemOverride,
 }
 
+#[allow(clippy::type_complexity)]
 struct TestMemOverride {
     read: Option<
         fn(
@@ -203,6 +205,7 @@ impl UsbDeviceCtx for MkDFU {
     type C<'c> = DfuClass<EmulatedUsbBus, TestMem>;
     const EP0_SIZE: u8 = 32;
 
+    #[allow(clippy::needless_lifetimes, clippy::needless_borrow)]
     fn create_class<'a>(
         &mut self,
         alloc: &'a UsbBusAllocator<EmulatedUsbBus>,
@@ -276,8 +279,9 @@ fn test_get_configuration() {
             let istr = dev.device_get_string(&mut dfu, 4, 0).expect("str");
             assert_eq!(istr, TestMem::MEM_INFO_STRING);
 
-            // get string descriptor unsupported lang_id (lang_id = 1)
-            dev.device_get_string(&mut dfu, 4, 1).expect_err("stall");
+            // get string descriptor other lang_id (lang_id = 0x407, German)
+            let istr = dev.device_get_string(&mut dfu, 4, 0x407).expect("str");
+            assert_eq!(istr, TestMem::MEM_INFO_STRING);
         })
         .expect("with_usb");
 }
@@ -415,6 +419,7 @@ fn test_erase() {
 }
 
 #[test]
+#[allow(clippy::len_zero)]
 fn test_erase_all() {
     MkDFU {}
         .with_usb(|mut dfu, mut dev| {
@@ -788,6 +793,7 @@ impl UsbDeviceCtx for MkDFUMTret {
     type C<'c> = DfuClass<EmulatedUsbBus, TestMem>;
     const EP0_SIZE: u8 = 32;
 
+    #[allow(clippy::needless_lifetimes, clippy::needless_borrow)]
     fn create_class<'a>(
         &mut self,
         alloc: &'a UsbBusAllocator<EmulatedUsbBus>,
@@ -858,6 +864,7 @@ impl UsbDeviceCtx for MkDFUMTerr {
     type C<'c> = DfuClass<EmulatedUsbBus, TestMem>;
     const EP0_SIZE: u8 = 32;
 
+    #[allow(clippy::needless_lifetimes, clippy::needless_borrow)]
     fn create_class<'a>(
         &mut self,
         alloc: &'a UsbBusAllocator<EmulatedUsbBus>,
@@ -919,6 +926,7 @@ impl UsbDeviceCtx for MkDFUEraseErr {
     type C<'c> = DfuClass<EmulatedUsbBus, TestMem>;
     const EP0_SIZE: u8 = 32;
 
+    #[allow(clippy::needless_lifetimes, clippy::needless_borrow)]
     fn create_class<'a>(
         &mut self,
         alloc: &'a UsbBusAllocator<EmulatedUsbBus>,
@@ -971,6 +979,7 @@ impl UsbDeviceCtx for MkDFUProgErr {
     type C<'c> = DfuClass<EmulatedUsbBus, TestMem>;
     const EP0_SIZE: u8 = 32;
 
+    #[allow(clippy::needless_lifetimes, clippy::needless_borrow)]
     fn create_class<'a>(
         &mut self,
         alloc: &'a UsbBusAllocator<EmulatedUsbBus>,
@@ -1016,10 +1025,22 @@ fn test_program_err_prog_write() {
             let vec = dev.get_status(&mut dfu).expect("vec");
             assert_eq!(vec, status(STATUS_ERR_PROG, 0, DFU_ERROR));
 
+            assert_eq!(
+                dfu.last_memory_error(),
+                Some(MemoryErrorDetail {
+                    error: DfuMemoryError::Prog,
+                    address: Some(TestMem::INITIAL_ADDRESS_POINTER),
+                    block: Some(0),
+                })
+            );
+
             /* Clear Status */
             let vec = dev.clear_status(&mut dfu).expect("vec");
             assert_eq!(vec, []);
 
+            // kept after clear status
+            assert!(dfu.last_memory_error().is_some());
+
             /* Download block 3 (offset 1) */
             let vec = dev.download(&mut dfu, 3, &[0x55; 128]).expect("vec");
             assert_eq!(vec, []);
@@ -1034,6 +1055,15 @@ fn test_program_err_prog_write() {
             /* Get Status */
             let vec = dev.get_status(&mut dfu).expect("vec");
             assert_eq!(vec, status(STATUS_ERR_WRITE, 0, DFU_ERROR));
+
+            assert_eq!(
+                dfu.last_memory_error(),
+                Some(MemoryErrorDetail {
+                    error: DfuMemoryError::Write,
+                    address: Some(TestMem::INITIAL_ADDRESS_POINTER + 128),
+                    block: Some(1),
+                })
+            );
         })
         .expect("with_usb");
 }
@@ -1044,6 +1074,7 @@ impl UsbDeviceCtx for MkDFUReadErr {
     type C<'c> = DfuClass<EmulatedUsbBus, TestMem>;
     const EP0_SIZE: u8 = 32;
 
+    #[allow(clippy::needless_lifetimes, clippy::needless_borrow)]
     fn create_class<'a>(
         &mut self,
         alloc: &'a UsbBusAllocator<EmulatedUsbBus>,
@@ -1097,11 +1128,21 @@ fn test_read_err_addr_vend() {
             /* Get Status */
             let vec = dev.get_status(&mut dfu).expect("vec");
             assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+
+            assert_eq!(
+                dfu.last_memory_error(),
+                Some(MemoryErrorDetail {
+                    error: DfuMemoryError::ErrVendor,
+                    address: Some(TestMem::INITIAL_ADDRESS_POINTER + 128),
+                    block: Some(1),
+                })
+            );
         })
         .expect("with_usb");
 }
 
 #[test]
+#[allow(clippy::assertions_on_constants)]
 fn test_download_program_short() {
     MkDFU {}
         .with_usb(|mut dfu, mut dev| {
@@ -1299,3 +1340,137 @@ fn test_err_addr_overflow() {
         })
         .expect("with_usb");
 }
+
+#[test]
+fn test_err_addr_overflow_last_block() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            let addr: u32 = 0xff90_0000;
+
+            /* Download block 0 (command), address pointer = addr */
+            let b = addr.to_le_bytes();
+            let vec = dev
+                .download(&mut dfu, 0, &[0x21, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            assert_eq!(vec, []);
+
+            /* Get Status */
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DN_BUSY));
+
+            /* Get Status */
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            /* Download the last block, offset 0xfffd * 128 is past the end of address space */
+            let vec = dev.download(&mut dfu, 0xffff, &[0x55; 128]).expect("vec");
+            assert_eq!(vec, []);
+
+            /* Get Status */
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(
+                vec,
+                status(STATUS_OK, TestMem::PROGRAM_TIME_MS, DFU_DN_BUSY)
+            );
+
+            /* Get Status */
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+
+            /* Clear Status */
+            let vec = dev.clear_status(&mut dfu).expect("vec");
+            assert_eq!(vec, []);
+
+            /* Upload the last block */
+            let e = dev.upload(&mut dfu, 0xffff, 128).expect_err("stall");
+            assert_eq!(e, AnyUsbError::EP0Stalled);
+
+            /* Get Status */
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_state_names() {
+    assert_eq!(DfuState::DfuDnloadSync.to_string(), "dfuDNLOAD-SYNC");
+    assert_eq!(
+        DfuState::try_from(DFU_MANIFEST_WAIT_RESET)
+            .unwrap()
+            .to_string(),
+        "dfuMANIFEST-WAIT-RESET"
+    );
+    assert_eq!(DfuState::try_from(11), Err(11));
+
+    assert_eq!(DfuStatusCode::ErrVerify.to_string(), "errVERIFY");
+    assert_eq!(
+        DfuStatusCode::try_from(STATUS_ERR_STALLED_PKT)
+            .unwrap()
+            .name(),
+        "errSTALLEDPKT"
+    );
+    assert_eq!(DfuStatusCode::try_from(STATUS_OK), Ok(DfuStatusCode::Ok));
+    assert_eq!(DfuStatusCode::try_from(0x10), Err(0x10));
+
+    // every value round-trips
+    for v in 0..=10 {
+        assert_eq!(DfuState::try_from(v).unwrap() as u8, v);
+    }
+    for v in 0..=0x0f {
+        assert_eq!(DfuStatusCode::try_from(v).unwrap() as u8, v);
+    }
+}
+
+#[test]
+fn test_host_poll() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            assert!(!dfu.take_host_poll());
+
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            assert!(!dfu.take_host_poll());
+
+            dev.get_status(&mut dfu).expect("vec");
+            assert!(dfu.take_host_poll());
+            assert!(!dfu.take_host_poll());
+
+            dev.get_state(&mut dfu).expect("vec");
+            assert!(dfu.take_host_poll());
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_dfuse_used() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            // plain DFU upload
+            dev.upload(&mut dfu, 2, 128).expect("vec");
+            dev.abort(&mut dfu).expect("vec");
+            assert!(!dfu.dfuse_used());
+
+            let b = TESTMEM_BASE.to_le_bytes();
+            dev.download(&mut dfu, 0, &[0x21, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            assert!(dfu.dfuse_used());
+
+            dfu.reset();
+            assert!(!dfu.dfuse_used());
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // Get Commands
+            dev.upload(&mut dfu, 0, 64).expect("vec");
+            assert!(dfu.dfuse_used());
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_interface_number() {
+    MkDFU {}
+        .with_usb(|dfu, dev| {
+            assert_eq!(u8::from(dfu.get_interface_number()), 0);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,182 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::{Cell, RefCell};
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::tlv::*;
+
+const TAG_SPEED: u8 = TAG_VENDOR;
+const TAG_NAME: u8 = TAG_VENDOR + 1;
+
+thread_local! {
+    /// Records of [`DfuMemory::set_parameters()`] calls.
+    static PARAMETERS: RefCell<Vec<(u8, Vec<u8>)>> = const { RefCell::new(Vec::new()) };
+    /// Speed set by the host.
+    static SPEED: Cell<u16> = const { Cell::new(0) };
+}
+
+test_mem! {
+    except store_write_buffer;
+
+    const TLV_COMMANDS: bool = true;
+
+    fn set_parameters(&mut self, parameters: TlvReader<'_>) -> Result<(), DfuMemoryError> {
+        for record in parameters {
+            let record = record.map_err(|_| DfuMemoryError::Unknown)?;
+            PARAMETERS.with_borrow_mut(|p| p.push((record.tag, record.value.to_vec())));
+            if record.tag == TAG_SPEED {
+                SPEED.set(record.u16().ok_or(DfuMemoryError::Unknown)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn capabilities(&mut self, w: &mut TlvWriter<'_>) -> Result<(), TlvError> {
+        w.u16(TAG_SPEED, SPEED.get())?;
+        w.str(TAG_NAME, "fast-erase")
+    }
+}
+
+/// Send *Set Parameters* command and return the final status.
+fn set_parameters<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem>,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut cmd = vec![0xb9];
+    cmd.extend_from_slice(payload);
+    dev.download(dfu, 0, &cmd).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DN_BUSY));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_write_read() {
+    let mut buf = [0; 16];
+    let mut w = TlvWriter::new(&mut buf);
+    assert!(w.is_empty());
+    w.u8(1, 0xaa).expect("u8");
+    w.u32(2, 0x0102_0304).expect("u32");
+    w.str(3, "ab").expect("str");
+    assert_eq!(w.len(), 3 + 6 + 4);
+    // doesn't fit
+    assert_eq!(w.u16(4, 0), Err(TlvError::Overflow));
+    let data = w.finish();
+    assert_eq!(
+        data,
+        [1, 1, 0xaa, 2, 4, 4, 3, 2, 1, 3, 2, b'a', b'b'].as_slice()
+    );
+
+    let records: Vec<_> = TlvReader::new(data).map(|r| r.expect("record")).collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].u8(), Some(0xaa));
+    assert_eq!(records[1].u32(), Some(0x0102_0304));
+    assert_eq!(records[1].u16(), None);
+    assert_eq!(records[2].str(), Some("ab"));
+    assert!(TlvReader::new(data).is_valid());
+    assert!(TlvReader::new(&[]).is_valid());
+
+    // truncated value and header
+    let mut reader = TlvReader::new(&data[..data.len() - 1]);
+    assert_eq!(reader.nth(2), Some(Err(TlvError::Truncated)));
+    assert_eq!(reader.next(), None);
+    assert!(!TlvReader::new(&[1]).is_valid());
+
+    let mut buf = [0; 300];
+    let mut w = TlvWriter::new(&mut buf);
+    assert_eq!(w.record(1, &[0; 256]), Err(TlvError::Overflow));
+}
+
+#[test]
+fn test_get_commands() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41, 0xb9, 0xba]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_set_parameters() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let payload = [TAG_SPEED, 2, 0x02, 0x01, 0x7f, 1, 0xaa];
+            let vec = set_parameters(&mut dev, &mut dfu, &payload);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(SPEED.get(), 0x0102);
+            assert_eq!(
+                PARAMETERS.take(),
+                [(TAG_SPEED, vec![0x02, 0x01]), (0x7f, vec![0xaa])]
+            );
+            dev.abort(&mut dfu).expect("vec");
+
+            // rejected by the memory
+            let vec = set_parameters(&mut dev, &mut dfu, &[TAG_SPEED, 1, 0]);
+            assert_eq!(vec, status(STATUS_ERR_UNKNOWN, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // truncated record
+            assert!(dev.download(&mut dfu, 0, &[0xb9, TAG_SPEED, 2, 0]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+            PARAMETERS.take();
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_set_parameters_abort() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // the queued command is dropped with its payload
+            dev.download(&mut dfu, 0, &[0xb9, TAG_SPEED, 2, 0x02, 0x01])
+                .expect("vec");
+            dev.abort(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+            assert!(PARAMETERS.take().is_empty());
+
+            let vec = set_parameters(&mut dev, &mut dfu, &[TAG_SPEED, 2, 0x04, 0x03]);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(PARAMETERS.take(), [(TAG_SPEED, vec![0x04, 0x03])]);
+            assert_eq!(SPEED.get(), 0x0304);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_get_capabilities() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            SPEED.set(5);
+            dev.download(&mut dfu, 0, &[0xba]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            dev.abort(&mut dfu).expect("vec");
+
+            let vec = dev.upload(&mut dfu, 1, 64).expect("vec");
+            assert_eq!(
+                vec,
+                [
+                    TAG_SPEED, 2, 5, 0, // speed
+                    TAG_NAME, 10, b'f', b'a', b's', b't', b'-', b'e', b'r', b'a', b's', b'e',
+                ]
+            );
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // arguments are not allowed
+            assert!(dev.download(&mut dfu, 0, &[0xba, 0]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,215 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cmp::min;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::transform::*;
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+const IMAGE_LEN: usize = 300;
+
+pub struct TestMem {
+    memory: [u8; IMAGE_LEN],
+    buffer: [u8; 128],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        if from >= self.memory.len() {
+            return Err(DfuMemoryError::Address);
+        }
+        let to = min(from + length, self.memory.len());
+        Ok(&self.memory[from..to])
+    }
+
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        let end = (address - TESTMEM_BASE) as usize + length;
+        let data = self.read(address, length)?;
+        match end >= IMAGE_LEN {
+            true => Ok(ReadOutcome::LastChunk(data)),
+            false => Ok(ReadOutcome::Full(data)),
+        }
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+/// Offset-dependent XOR "cipher"
+struct XorTransform {
+    key: u8,
+}
+
+impl XorTransform {
+    fn apply(&self, offset: u32, data: &mut [u8]) {
+        for (i, b) in data.iter_mut().enumerate() {
+            *b ^= self.key ^ (offset as usize + i) as u8;
+        }
+    }
+}
+
+impl DownloadTransform for XorTransform {
+    const BLOCK_SIZE: usize = 16;
+
+    fn download(&mut self, offset: u32, data: &mut [u8]) -> Result<(), DfuMemoryError> {
+        self.apply(offset, data);
+        Ok(())
+    }
+
+    fn upload(&mut self, offset: u32, data: &mut [u8]) -> Result<(), DfuMemoryError> {
+        self.apply(offset, data);
+        Ok(())
+    }
+}
+
+type Mem = TransformMemory<TestMem, XorTransform, 128>;
+
+fn mem() -> Mem {
+    let mem = TestMem {
+        memory: [0xff; IMAGE_LEN],
+        buffer: [0; 128],
+    };
+    TransformMemory::new(mem, XorTransform { key: 0x5a })
+}
+
+fn encrypt(data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    XorTransform { key: 0x5a }.apply(0, &mut data);
+    data
+}
+
+fn download<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    file: &[u8],
+) {
+    for (i, block) in file.chunks(128).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+}
+
+fn upload<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+) -> Vec<u8> {
+    let mut file = Vec::new();
+    for i in 0.. {
+        let vec = dev.upload(dfu, 2 + i as u16, 128).expect("vec");
+        file.extend_from_slice(&vec);
+        if vec.len() < 128 {
+            break;
+        }
+    }
+    file
+}
+
+#[test]
+fn test_transform_download() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            // an aborted download does not affect offsets of the next one
+            download(&mut dev, &mut dfu, &[0; 128]);
+            let vec = dev.abort(&mut dfu).expect("vec");
+
+            download(&mut dev, &mut dfu, &encrypt(&image(IMAGE_LEN)));
+            let vec = dev.abort(&mut dfu).expect("vec");
+
+            assert_eq!(&dfu.release().release().memory[..], &image(IMAGE_LEN)[..]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_transform_upload() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, &encrypt(&image(IMAGE_LEN)));
+            let vec = dev.abort(&mut dfu).expect("vec");
+
+            // every upload starts at offset 0
+            assert_eq!(upload(&mut dev, &mut dfu), encrypt(&image(IMAGE_LEN)));
+            assert_eq!(upload(&mut dev, &mut dfu), encrypt(&image(IMAGE_LEN)));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_transform_upload_last_chunk() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            download(&mut dev, &mut dfu, &encrypt(&image(IMAGE_LEN)));
+            let vec = dev.abort(&mut dfu).expect("vec");
+
+            /* Set Address Pointer, the upload ends with a full-length block */
+            let addr = TESTMEM_BASE + (IMAGE_LEN - 256) as u32;
+            let b = addr.to_le_bytes();
+            dev.download(&mut dfu, 0, &[0x21, b[0], b[1], b[2], b[3]])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.abort(&mut dfu).expect("vec");
+
+            // the end of the memory is kept, a zero-length block follows without a read
+            let file = upload(&mut dev, &mut dfu);
+            assert_eq!(file, encrypt(&image(IMAGE_LEN)[IMAGE_LEN - 256..]));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[cfg(feature = "cipher")]
+#[test]
+fn test_stream_cipher_transform() {
+    use chacha20::cipher::{KeyIvInit, StreamCipher};
+    use chacha20::ChaCha20;
+
+    let key = [0x42; 32];
+    let nonce = [0x24; 12];
+
+    let mut data = image(IMAGE_LEN);
+    ChaCha20::new(&key.into(), &nonce.into()).apply_keystream(&mut data);
+
+    let mut transform = StreamCipherTransform::new(ChaCha20::new(&key.into(), &nonce.into()));
+    // blocks in any order
+    let (first, second) = data.split_at_mut(128);
+    assert!(transform.download(128, second).is_ok());
+    assert!(transform.download(0, first).is_ok());
+    assert_eq!(data, image(IMAGE_LEN));
+
+    assert!(transform.upload(0, &mut data).is_ok());
+    assert!(transform.download(0, &mut data).is_ok());
+    assert_eq!(data, image(IMAGE_LEN));
+}
@@ -0,0 +1,210 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::{Cell, RefCell};
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::class::UsbClass;
+use usbd_dfu::class::*;
+
+const PIN: &[u8] = b"1234";
+
+thread_local! {
+    /// Arguments of [`DfuMemory::unlock()`] calls.
+    static TOKENS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+    /// Number of program calls.
+    static WRITES: Cell<usize> = const { Cell::new(0) };
+    /// Return value of [`DfuMemory::may_start_download()`].
+    static MAY_START: Cell<bool> = const { Cell::new(true) };
+    /// Number of [`DfuMemory::download_start()`] calls.
+    static STARTS: Cell<usize> = const { Cell::new(0) };
+}
+
+test_mem! {
+    except read, program;
+
+    const UNLOCK_COMMAND: bool = true;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&[0; 64][..length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        WRITES.set(WRITES.get() + 1);
+        Ok(())
+    }
+
+    fn unlock(&mut self, token: &[u8]) -> bool {
+        TOKENS.with_borrow_mut(|t| t.push(token.to_vec()));
+        token == PIN
+    }
+
+    fn may_start_download(&mut self) -> bool {
+        MAY_START.get()
+    }
+
+    fn download_start(&mut self) {
+        STARTS.set(STARTS.get() + 1);
+    }
+}
+
+fn unlock_command(token: &[u8]) -> Vec<u8> {
+    let mut cmd = vec![0xb6];
+    cmd.extend_from_slice(token);
+    cmd
+}
+
+/// Send *Unlock* command and return the final status.
+fn unlock<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, TestMem>,
+    token: &[u8],
+) -> Vec<u8> {
+    dev.download(dfu, 0, &unlock_command(token)).expect("vec");
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_DN_BUSY));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_get_commands() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // uploads are not locked
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41, 0xb6]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_locked() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            assert!(dev.download(&mut dfu, 2, &[0x55; 64]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // DfuSe commands are locked too
+            assert!(dev.download(&mut dfu, 0, &[0x41]).is_err());
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // wrong PIN
+            let vec = unlock(&mut dev, &mut dfu, b"0000");
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+            assert!(dev.download(&mut dfu, 2, &[0x55; 64]).is_err());
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // empty and too long tokens are rejected
+            assert!(dev.download(&mut dfu, 0, &[0xb6]).is_err());
+            dev.clear_status(&mut dfu).expect("vec");
+            assert!(dev
+                .download(&mut dfu, 0, &unlock_command(&[0; 33]))
+                .is_err());
+            dev.clear_status(&mut dfu).expect("vec");
+
+            assert_eq!(TOKENS.take(), [b"0000".to_vec()]);
+            assert_eq!(WRITES.take(), 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_unlock() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = unlock(&mut dev, &mut dfu, PIN);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            dev.abort(&mut dfu).expect("vec");
+
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(WRITES.take(), 1);
+            dev.abort(&mut dfu).expect("vec");
+
+            // locked again after USB reset
+            dfu.reset();
+            assert!(dev.download(&mut dfu, 2, &[0x55; 64]).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+
+            assert_eq!(TOKENS.take(), [PIN.to_vec()]);
+            assert_eq!(WRITES.take(), 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_unlock_abort() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // the queued command is dropped with its token
+            dev.download(&mut dfu, 0, &unlock_command(PIN))
+                .expect("vec");
+            dev.abort(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+            assert!(TOKENS.take().is_empty());
+
+            let vec = unlock(&mut dev, &mut dfu, PIN);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(TOKENS.take(), [PIN.to_vec()]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_unlock_download_guard() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // Unlock doesn't start a download, so the guard doesn't apply
+            MAY_START.set(false);
+            let vec = unlock(&mut dev, &mut dfu, PIN);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(STARTS.get(), 0);
+
+            // the first block of the same download does
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_VENDOR, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+            assert_eq!(STARTS.get(), 0);
+            assert_eq!(WRITES.get(), 0);
+
+            MAY_START.set(true);
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(STARTS.get(), 1);
+            assert_eq!(WRITES.get(), 1);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_unlock_then_download() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            let vec = unlock(&mut dev, &mut dfu, PIN);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(STARTS.get(), 0);
+
+            // blocks after Unlock start the download
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            assert_eq!(STARTS.get(), 1);
+        })
+        .expect("with_usb");
+}
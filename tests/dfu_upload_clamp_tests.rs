@@ -0,0 +1,92 @@
+#![cfg(feature = "upload")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::mem_info::MemInfo;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+const TESTMEMSIZE: usize = 512;
+
+/// Two pages of `PAGE` bytes are declared, reads are not limited.
+pub struct TestMem<const PAGE: u32> {
+    memory: [u8; TESTMEMSIZE],
+}
+
+impl<const PAGE: u32> DfuMemory for TestMem<PAGE> {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = MemInfo::<32>::new("Flash", TESTMEM_BASE)
+        .area(2, PAGE, 'g')
+        .as_str();
+    const TRANSFER_SIZE: u16 = 64;
+    const CLAMP_UPLOAD: bool = true;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(&self.memory[from..from + length])
+    }
+}
+
+fn mem<const PAGE: u32>() -> TestMem<PAGE> {
+    TestMem {
+        memory: core::array::from_fn(|i| i as u8),
+    }
+}
+
+#[test]
+fn test_upload_clamped() {
+    MkDFU::new(mem::<100>)
+        .with_usb(|mut dfu, mut dev| {
+            for block in 2..5 {
+                let vec = dev.upload(&mut dfu, block, 64).expect("vec");
+                assert_eq!(vec.len(), 64);
+            }
+
+            // 8 bytes up to the end of the region
+            let vec = dev.upload(&mut dfu, 5, 64).expect("vec");
+            assert_eq!(vec, (192..200).map(|i| i as u8).collect::<Vec<_>>());
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_upload_clamped_full_length() {
+    MkDFU::new(mem::<64>)
+        .with_usb(|mut dfu, mut dev| {
+            for block in 2..4 {
+                let vec = dev.upload(&mut dfu, block, 64).expect("vec");
+                assert_eq!(vec.len(), 64);
+            }
+
+            // terminating zero-length block
+            let vec = dev.upload(&mut dfu, 4, 64).expect("vec");
+            assert!(vec.is_empty());
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_upload_outside_of_region() {
+    MkDFU::new(mem::<64>)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 4, 64).expect("vec");
+            assert!(vec.is_empty());
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
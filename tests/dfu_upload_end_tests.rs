@@ -0,0 +1,85 @@
+#![cfg(feature = "upload")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+const TESTMEMSIZE: usize = 256;
+
+const LAST_BLOCK: u8 = 0;
+const ERROR: u8 = 1;
+
+/// `MODE` selects [`DfuMemory::UPLOAD_END`].
+pub struct TestMem<const MODE: u8> {
+    memory: [u8; TESTMEMSIZE],
+}
+
+impl<const MODE: u8> DfuMemory for TestMem<MODE> {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/2*64 g";
+    const TRANSFER_SIZE: u16 = 64;
+    const CLAMP_UPLOAD: bool = true;
+    const UPLOAD_END: UploadEnd = match MODE {
+        LAST_BLOCK => UploadEnd::LastBlock,
+        _ => UploadEnd::Error,
+    };
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(&self.memory[from..from + length])
+    }
+}
+
+fn mem<const MODE: u8>() -> TestMem<MODE> {
+    TestMem {
+        memory: core::array::from_fn(|i| i as u8),
+    }
+}
+
+#[test]
+fn test_upload_end_last_block() {
+    MkDFU::new(mem::<LAST_BLOCK>)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 2, 64).expect("vec");
+            assert_eq!(vec.len(), 64);
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_UPLOAD_IDLE));
+
+            // full-length block ends the upload
+            let vec = dev.upload(&mut dfu, 3, 64).expect("vec");
+            assert_eq!(vec.len(), 64);
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_upload_end_error() {
+    MkDFU::new(mem::<ERROR>)
+        .with_usb(|mut dfu, mut dev| {
+            for block in 2..4 {
+                let vec = dev.upload(&mut dfu, block, 64).expect("vec");
+                assert_eq!(vec.len(), 64);
+            }
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_UPLOAD_IDLE));
+
+            assert!(dev.upload(&mut dfu, 4, 64).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_ADDRESS, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // a new upload outside of the region
+            assert!(dev.upload(&mut dfu, 4, 64).is_err());
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "upload")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+const TESTMEMSIZE: usize = 256;
+
+/// Number of [`DfuMemory::read_block()`] calls.
+static READS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/2*128 g";
+    const TRANSFER_SIZE: u16 = 128;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        panic!("read instead of read_block");
+    }
+
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        READS.fetch_add(1, Ordering::Relaxed);
+        let from = (address - TESTMEM_BASE) as usize;
+        let to = from + length;
+        match to {
+            TESTMEMSIZE => Ok(ReadOutcome::LastChunk(&self.memory[from..to])),
+            to if to < TESTMEMSIZE => Ok(ReadOutcome::Full(&self.memory[from..to])),
+            _ => Err(DfuMemoryError::Address),
+        }
+    }
+}
+
+fn mem() -> TestMem {
+    TestMem {
+        memory: core::array::from_fn(|i| i as u8),
+    }
+}
+
+#[test]
+fn test_upload_last_chunk_full_length() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 2, 128).expect("vec");
+            assert_eq!(vec, (0..128).map(|i| i as u8).collect::<Vec<_>>());
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_UPLOAD_IDLE));
+
+            // the last block is full length, upload continues
+            let vec = dev.upload(&mut dfu, 3, 128).expect("vec");
+            assert_eq!(vec, (128..256).map(|i| i as u8).collect::<Vec<_>>());
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_UPLOAD_IDLE));
+
+            // zero-length block ends the upload without reading memory
+            let vec = dev.upload(&mut dfu, 4, 128).expect("vec");
+            assert_eq!(vec, []);
+            assert_eq!(READS.load(Ordering::Relaxed), 2);
+
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            // next upload starts over
+            let vec = dev.upload(&mut dfu, 3, 128).expect("vec");
+            assert_eq!(vec.len(), 128);
+            assert_eq!(READS.load(Ordering::Relaxed), 3);
+        })
+        .expect("with_usb");
+}
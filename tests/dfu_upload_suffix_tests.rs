@@ -0,0 +1,144 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cmp::min;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::suffix::*;
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    memory: Vec<u8>,
+    /// End of the memory is reported with `ReadOutcome::LastChunk` only.
+    last_chunk: bool,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(self.read_block(address, length)?.data())
+    }
+
+    fn read_block(
+        &mut self,
+        address: u32,
+        length: usize,
+    ) -> Result<ReadOutcome<'_>, DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        if self.last_chunk && from >= self.memory.len() {
+            // like the end of a flash region, nothing is read after the last chunk
+            return Err(DfuMemoryError::Address);
+        }
+        let from = min(from, self.memory.len());
+        let to = min(from + length, self.memory.len());
+        let data = &self.memory[from..to];
+        if data.len() < length || (self.last_chunk && to == self.memory.len()) {
+            Ok(ReadOutcome::LastChunk(data))
+        } else {
+            Ok(ReadOutcome::Full(data))
+        }
+    }
+}
+
+type Mem = AppendSuffix<TestMem, 128>;
+
+fn mem(size: usize) -> Mem {
+    mem_with_end(size, false)
+}
+
+fn mem_with_end(size: usize, last_chunk: bool) -> Mem {
+    let mem = TestMem {
+        memory: (0..size).map(|i| i as u8).collect(),
+        last_chunk,
+    };
+    AppendSuffix::new(mem, 0x1209, 0x2444, 0x0100)
+}
+
+fn check_upload<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    size: usize,
+    blocks: &[usize],
+) {
+    let mut file = Vec::new();
+    for (i, len) in blocks.iter().enumerate() {
+        let vec = dev.upload(dfu, 2 + i as u16, 128).expect("vec");
+        assert_eq!(vec.len(), *len);
+        file.extend_from_slice(&vec);
+    }
+
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+    assert_eq!(file.len(), size + 16);
+    assert!(file[..size].iter().enumerate().all(|(i, b)| *b == i as u8));
+
+    let suffix = Suffix::try_from(&file[..]).expect("suffix");
+    assert_eq!(suffix.matches_device(0x1209, 0x2444, 0x0100), Ok(()));
+
+    let mut crc = Crc32::new();
+    crc.update(&file[..file.len() - 4]);
+    assert_eq!(suffix.crc, crc.finalize());
+}
+
+#[test]
+fn test_upload_suffix() {
+    MkDFU::new(|| mem(300))
+        .with_usb(|mut dfu, mut dev| {
+            check_upload(&mut dev, &mut dfu, 300, &[128, 128, 44 + 16]);
+            // the second upload starts from block 0 again
+            check_upload(&mut dev, &mut dfu, 300, &[128, 128, 44 + 16]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_upload_suffix_split() {
+    // suffix does not fit into the last data block
+    MkDFU::new(|| mem(250))
+        .with_usb(|mut dfu, mut dev| {
+            check_upload(&mut dev, &mut dfu, 250, &[128, 122 + 6, 10]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_upload_suffix_aligned() {
+    // firmware ends at the block boundary
+    MkDFU::new(|| mem(256))
+        .with_usb(|mut dfu, mut dev| {
+            check_upload(&mut dev, &mut dfu, 256, &[128, 128, 16]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_upload_suffix_last_chunk() {
+    // memory reports a full-length last block, like FlashMemory at the region end
+    MkDFU::new(|| mem_with_end(256, true))
+        .with_usb(|mut dfu, mut dev| {
+            check_upload(&mut dev, &mut dfu, 256, &[128, 128, 16]);
+            check_upload(&mut dev, &mut dfu, 256, &[128, 128, 16]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_upload_suffix_last_chunk_split() {
+    MkDFU::new(|| mem_with_end(250, true))
+        .with_usb(|mut dfu, mut dev| {
+            check_upload(&mut dev, &mut dfu, 250, &[128, 122 + 6, 10]);
+        })
+        .expect("with_usb");
+}
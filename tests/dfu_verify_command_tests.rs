@@ -0,0 +1,207 @@
+#![cfg(all(feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::cell::Cell;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+thread_local! {
+    /// Number of calls that modify the memory or leave DFU mode.
+    static MODIFIED: Cell<usize> = const { Cell::new(0) };
+}
+
+pub struct TestMem {
+    memory: [u8; 256],
+    buffer: [u8; 64],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/4*64 g";
+    const TRANSFER_SIZE: u16 = 64;
+    const VERIFY_COMMAND: bool = true;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(&self.memory[from..(from + length).min(256)])
+    }
+
+    fn compare(&mut self, address: u32, length: usize) -> Result<bool, DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(self.memory[from..from + length] == self.buffer[..length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        MODIFIED.set(MODIFIED.get() + 1);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        MODIFIED.set(MODIFIED.get() + 1);
+        Ok(())
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        MODIFIED.set(MODIFIED.get() + 1);
+        Ok(())
+    }
+
+    fn download_start(&mut self) {
+        MODIFIED.set(MODIFIED.get() + 1);
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        MODIFIED.set(MODIFIED.get() + 1);
+        Ok(())
+    }
+}
+
+fn mem() -> TestMem {
+    let mut memory = [0xff; 256];
+    memory[..64].fill(0x11);
+    memory[64..128].fill(0x22);
+    TestMem {
+        memory,
+        buffer: [0; 64],
+    }
+}
+
+#[test]
+fn test_get_commands() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.upload(&mut dfu, 0, 128).expect("vec");
+            assert_eq!(vec, [0x00, 0x21, 0x41, 0xbc]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_verify() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 0, &[0xbc]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+            // like dfu-util, erase the page before its data
+            dev.download(&mut dfu, 0, &[0x41, 0x00, 0x00, 0x00, 0x08])
+                .expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+
+            for (block, data) in [(2, 0x11), (3, 0x22), (4, 0xff)] {
+                dev.download(&mut dfu, block, &[data; 64]).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            }
+
+            dev.download(&mut dfu, 2, &[]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            assert_eq!(MODIFIED.get(), 0);
+            assert_eq!(dfu.last_memory_error(), None);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_verify_mismatch() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 0, &[0xbc]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+
+            dev.download(&mut dfu, 2, &[0x11; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+
+            let mut data = [0x22; 64];
+            data[10] = 0;
+            dev.download(&mut dfu, 3, &data).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_DN_BUSY));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_VERIFY, 0, DFU_ERROR));
+
+            assert_eq!(
+                dfu.last_memory_error(),
+                Some(MemoryErrorDetail {
+                    error: DfuMemoryError::Verify,
+                    address: Some(TESTMEM_BASE + 64),
+                    block: Some(1),
+                })
+            );
+            assert_eq!(MODIFIED.get(), 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_verify_crc_collision() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 0, &[0xbc]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+
+            // the difference is the CRC-32 polynomial, CRC-32 of the block is not changed
+            let mut data = [0x11; 64];
+            for (b, d) in data[10..].iter_mut().zip([0x41, 0x06, 0x71, 0xdb, 0x01]) {
+                *b ^= d;
+            }
+            dev.download(&mut dfu, 2, &data).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_VERIFY, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_verify_not_first() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 2, &[0x11; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+
+            dev.download(&mut dfu, 0, &[0xbc]).expect_err("stall");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_download_after_verify() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 0, &[0xbc]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.abort(&mut dfu).expect("vec");
+
+            // a new download programs the memory
+            dev.download(&mut dfu, 2, &[0x11; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(MODIFIED.get(), 2);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,182 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::hash::*;
+use usbd_dfu::verify::*;
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    signature: Vec<u8>,
+    manifested: bool,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.manifested = true;
+        Ok(())
+    }
+}
+
+impl ImageSignature for TestMem {
+    fn image_signature(&mut self) -> Result<&[u8], DfuManifestationError> {
+        Ok(&self.signature)
+    }
+}
+
+/// "Signature" is the sum of all bytes, XORed with the key
+struct TestVerifier {
+    key: u32,
+}
+
+impl FirmwareVerifier<u32> for TestVerifier {
+    fn verify_firmware(&self, digest: &u32, signature: &[u8]) -> Result<(), DfuManifestationError> {
+        let signature: [u8; 4] = signature
+            .try_into()
+            .map_err(|_| DfuManifestationError::File)?;
+        if u32::from_le_bytes(signature) ^ self.key == *digest {
+            Ok(())
+        } else {
+            Err(DfuManifestationError::Firmware)
+        }
+    }
+}
+
+struct TestHasher {
+    sum: u32,
+}
+
+impl ImageHasher for TestHasher {
+    type Digest = u32;
+
+    fn reset(&mut self) {
+        self.sum = 0;
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.sum += *b as u32;
+        }
+    }
+
+    fn finalize(&mut self) -> u32 {
+        self.sum
+    }
+}
+
+const KEY: u32 = 0x5a5a_1234;
+
+type Mem = HashedMemory<SignedMemory<TestMem, TestVerifier>, TestHasher, 128>;
+
+fn sign(image: &[u8]) -> Vec<u8> {
+    let sum: u32 = image.iter().map(|b| *b as u32).sum();
+    (sum ^ KEY).to_le_bytes().to_vec()
+}
+
+fn mem(signature: Vec<u8>) -> Mem {
+    let mem = TestMem {
+        signature,
+        manifested: false,
+    };
+    let mem = SignedMemory::new(mem, TestVerifier { key: KEY });
+    HashedMemory::new(mem, TestHasher { sum: 0 })
+}
+
+fn download_manifest<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+) -> Vec<u8> {
+    for (i, block) in image(300).chunks(128).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_signature_valid() {
+    MkDFU::new(|| mem(sign(&image(300))))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = download_manifest(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            assert!(dfu.release().release().release().manifested);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_signature_mismatch() {
+    MkDFU::new(|| mem(sign(&[1, 2, 3])))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = download_manifest(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_ERR_FIRMWARE, 0, DFU_ERROR));
+
+            assert!(!dfu.release().release().release().manifested);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_signature_malformed() {
+    MkDFU::new(|| mem(vec![1, 2, 3]))
+        .with_usb(|mut dfu, mut dev| {
+            let vec = download_manifest(&mut dev, &mut dfu);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            assert!(!dfu.release().release().release().manifested);
+        })
+        .expect("with_usb");
+}
+
+#[cfg(feature = "p256")]
+#[test]
+fn test_p256_verifier() {
+    use p256::ecdsa::signature::hazmat::PrehashSigner;
+    use p256::ecdsa::{Signature, SigningKey};
+
+    let signing_key = SigningKey::from_bytes(&[0x42; 32].into()).unwrap();
+    let public_key = signing_key.verifying_key().to_encoded_point(true);
+    let verifier = P256Verifier::from_sec1_bytes(public_key.as_bytes()).unwrap();
+
+    let digest = [0x11; 32];
+    let signature: Signature = signing_key.sign_prehash(&digest).unwrap();
+    let signature = signature.to_bytes();
+
+    assert!(verifier.verify_firmware(&digest, &signature).is_ok());
+    assert!(matches!(
+        verifier.verify_firmware(&[0x12; 32], &signature),
+        Err(DfuManifestationError::Firmware)
+    ));
+    assert!(matches!(
+        verifier.verify_firmware(&digest, &signature[..63]),
+        Err(DfuManifestationError::File)
+    ));
+    assert!(P256Verifier::from_sec1_bytes(&[0x02; 33][..5]).is_none());
+}
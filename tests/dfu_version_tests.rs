@@ -0,0 +1,115 @@
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::suffix::*;
+
+const TESTMEM_BASE: u32 = 0x0200_0000;
+
+pub struct TestMem {
+    manifested: bool,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x02000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 128;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.manifested = true;
+        Ok(())
+    }
+
+    fn minimum_image_version(&mut self) -> Option<u32> {
+        Some(0x0200)
+    }
+}
+
+type Mem = StripSuffix<TestMem, { 128 + 16 }>;
+
+fn mem() -> Mem {
+    StripSuffix::new(TestMem { manifested: false })
+}
+
+fn download_manifest<'a>(
+    dev: &mut Device<'a, DfuClass<EmulatedUsbBus, Mem>, MkDFU<Mem>>,
+    dfu: &mut DfuClass<EmulatedUsbBus, Mem>,
+    file: &[u8],
+) -> Vec<u8> {
+    for (i, block) in file.chunks(128).enumerate() {
+        let vec = dev.download(dfu, 2 + i as u16, block).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        let vec = dev.get_status(dfu).expect("vec");
+        assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    }
+    let vec = dev.download(dfu, 0, &[]).expect("vec");
+
+    let vec = dev.get_status(dfu).expect("vec");
+    assert_eq!(vec, status(STATUS_OK, 1, DFU_MANIFEST));
+    dev.get_status(dfu).expect("vec")
+}
+
+#[test]
+fn test_version_accepted() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = download_manifest(
+                &mut dev,
+                &mut dfu,
+                &with_suffix(&[0x11; 200], 0x1209, 0x0200),
+            );
+            assert_eq!(vec, status(STATUS_OK, 0, DFU_IDLE));
+
+            assert!(dfu.release().release().manifested);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_version_downgrade() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let vec = download_manifest(
+                &mut dev,
+                &mut dfu,
+                &with_suffix(&[0x11; 200], 0x1209, 0x0199),
+            );
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            assert!(!dfu.release().release().manifested);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_version_unknown() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let file = with_suffix(&[0x11; 200], 0x1209, SUFFIX_WILDCARD);
+            let vec = download_manifest(&mut dev, &mut dfu, &file);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            // no suffix at all
+            let vec = dev.clear_status(&mut dfu).expect("vec");
+            let vec = download_manifest(&mut dev, &mut dfu, &[0x11; 200]);
+            assert_eq!(vec, status(STATUS_ERR_FILE, 0, DFU_ERROR));
+
+            assert!(!dfu.release().release().manifested);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,138 @@
+use usbd_dfu::dfuse::*;
+
+fn target(alt_setting: u8, name: &str, elements: &[(u32, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (address, data) in elements {
+        let header = ElementHeader {
+            address: *address,
+            size: data.len() as u32,
+        };
+        body.extend_from_slice(&header.to_bytes());
+        body.extend_from_slice(data);
+    }
+
+    let mut target_name = [0; TARGET_NAME_LENGTH];
+    target_name[..name.len()].copy_from_slice(name.as_bytes());
+    let prefix = TargetPrefix {
+        alt_setting,
+        name: target_name,
+        named: !name.is_empty(),
+        size: body.len() as u32,
+        elements: elements.len() as u32,
+    };
+
+    let mut bytes = prefix.to_bytes().to_vec();
+    bytes.extend_from_slice(&body);
+    bytes
+}
+
+fn file(targets: &[Vec<u8>]) -> Vec<u8> {
+    let size: usize = targets.iter().map(|t| t.len()).sum();
+    let prefix = Prefix {
+        image_size: (PREFIX_LENGTH + size) as u32,
+        targets: targets.len() as u8,
+    };
+    let mut bytes = prefix.to_bytes().to_vec();
+    for t in targets {
+        bytes.extend_from_slice(t);
+    }
+    bytes
+}
+
+#[derive(Debug, PartialEq)]
+enum Item {
+    Prefix(u8),
+    Target(u8, Option<String>),
+    Element(u32, u32),
+    Data(u32, Vec<u8>),
+    End,
+}
+
+fn parse(file: &[u8], block_size: usize) -> Result<Vec<Item>, DfuSeError> {
+    let mut items = Vec::new();
+    let mut parser = Parser::new();
+
+    for block in file.chunks(block_size) {
+        parser.push(block, |event| {
+            let item = match event {
+                Event::Prefix(p) => Item::Prefix(p.targets),
+                Event::Target(t) => Item::Target(t.alt_setting, t.name().map(String::from)),
+                Event::Element(e) => Item::Element(e.address, e.size),
+                Event::Data { address, data } => {
+                    // merge data split between blocks
+                    if let Some(Item::Data(a, d)) = items.last_mut() {
+                        if *a + d.len() as u32 == address {
+                            d.extend_from_slice(data);
+                            return;
+                        }
+                    }
+                    Item::Data(address, data.to_vec())
+                }
+                Event::End => Item::End,
+            };
+            items.push(item);
+        })?;
+    }
+
+    Ok(items)
+}
+
+#[test]
+fn test_dfuse_parse() {
+    let flash: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let file = file(&[
+        target(
+            0,
+            "Internal Flash",
+            &[(0x0800_0000, &flash[..]), (0x0800_4000, &[1, 2, 3])],
+        ),
+        target(1, "", &[(0x1fff_7800, &[0xaa; 16])]),
+    ]);
+
+    let expected = vec![
+        Item::Prefix(2),
+        Item::Target(0, Some("Internal Flash".into())),
+        Item::Element(0x0800_0000, 300),
+        Item::Data(0x0800_0000, flash.clone()),
+        Item::Element(0x0800_4000, 3),
+        Item::Data(0x0800_4000, vec![1, 2, 3]),
+        Item::Target(1, None),
+        Item::Element(0x1fff_7800, 16),
+        Item::Data(0x1fff_7800, vec![0xaa; 16]),
+        Item::End,
+    ];
+
+    // headers and data split at different offsets
+    for block_size in [1, 7, 128, 2048] {
+        assert_eq!(parse(&file, block_size).expect("parse"), expected);
+    }
+}
+
+#[test]
+fn test_dfuse_errors() {
+    let mut bad = file(&[target(0, "", &[(0, &[0])])]);
+    bad[0] = b'X';
+    assert_eq!(parse(&bad, 128), Err(DfuSeError::InvalidSignature));
+
+    let mut bad = file(&[target(0, "", &[(0, &[0])])]);
+    bad[5] = 2;
+    assert_eq!(parse(&bad, 128), Err(DfuSeError::UnsupportedVersion(2)));
+
+    let mut bad = file(&[target(0, "", &[(0, &[0])])]);
+    bad[PREFIX_LENGTH] = b'X';
+    assert_eq!(parse(&bad, 128), Err(DfuSeError::InvalidTargetSignature));
+
+    // element is larger than the target
+    let mut bad = file(&[target(0, "", &[(0, &[0])])]);
+    bad[PREFIX_LENGTH + 266] = 1;
+    assert_eq!(parse(&bad, 128), Err(DfuSeError::InvalidSize));
+
+    let mut bad = file(&[target(0, "", &[(0, &[0])])]);
+    bad.push(0);
+    assert_eq!(parse(&bad, 128), Err(DfuSeError::TrailingData));
+
+    assert_eq!(
+        Prefix::try_from(&[0u8; 4][..]).unwrap_err(),
+        DfuSeError::TooShort
+    );
+}
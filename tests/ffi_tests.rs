@@ -0,0 +1,207 @@
+#![cfg(feature = "ffi")]
+
+mod helpers;
+use helpers::*;
+
+use std::ffi::c_void;
+use std::sync::Mutex;
+
+use usbd_dfu::ffi::*;
+use usbd_dfu::link::*;
+
+/// The C API has a single instance
+static LOCK: Mutex<()> = Mutex::new(());
+
+struct TestMem {
+    memory: Vec<u8>,
+    erases: Vec<Option<u32>>,
+    fail_program: bool,
+}
+
+unsafe extern "C" fn read(
+    context: *mut c_void,
+    address: u32,
+    buffer: *mut u8,
+    length: usize,
+    read: *mut usize,
+) -> u8 {
+    let mem = &mut *(context as *mut TestMem);
+    let from = (address as usize).min(mem.memory.len());
+    let to = (from + length).min(mem.memory.len());
+    std::ptr::copy_nonoverlapping(mem.memory[from..to].as_ptr(), buffer, to - from);
+    *read = to - from;
+    USBD_DFU_OK
+}
+
+unsafe extern "C" fn program(
+    context: *mut c_void,
+    address: u32,
+    data: *const u8,
+    length: usize,
+) -> u8 {
+    let mem = &mut *(context as *mut TestMem);
+    if mem.fail_program {
+        return STATUS_ERR_PROG;
+    }
+    let data = std::slice::from_raw_parts(data, length);
+    let from = address as usize;
+    match mem.memory.get_mut(from..from + length) {
+        Some(m) => {
+            m.copy_from_slice(data);
+            USBD_DFU_OK
+        }
+        None => STATUS_ERR_ADDRESS,
+    }
+}
+
+unsafe extern "C" fn erase(context: *mut c_void, address: u32) -> u8 {
+    let mem = &mut *(context as *mut TestMem);
+    mem.erases.push(Some(address));
+    USBD_DFU_OK
+}
+
+unsafe extern "C" fn erase_all(context: *mut c_void) -> u8 {
+    let mem = &mut *(context as *mut TestMem);
+    mem.erases.push(None);
+    USBD_DFU_OK
+}
+
+unsafe extern "C" fn manifestation(_context: *mut c_void) -> u8 {
+    // unknown status code
+    0xff
+}
+
+unsafe extern "C" fn send(context: *mut c_void, reply: *const u8, length: usize) {
+    let replies = &mut *(context as *mut Vec<u8>);
+    replies.extend_from_slice(std::slice::from_raw_parts(reply, length));
+}
+
+fn ops(mem: &mut TestMem) -> DfuMemoryOps {
+    DfuMemoryOps {
+        context: mem as *mut TestMem as *mut c_void,
+        read: Some(read),
+        program: Some(program),
+        erase: Some(erase),
+        erase_all: Some(erase_all),
+        manifestation: Some(manifestation),
+    }
+}
+
+fn handle(frame: &[u8]) -> Vec<u8> {
+    let mut reply = [0; REPLY_HEADER_LENGTH + USBD_DFU_TRANSFER_SIZE];
+    let n =
+        unsafe { usbd_dfu_handle(frame.as_ptr(), frame.len(), reply.as_mut_ptr(), reply.len()) };
+    reply[..n].to_vec()
+}
+
+fn get_status() -> Vec<u8> {
+    handle(&get_status_request())[REPLY_HEADER_LENGTH..].to_vec()
+}
+
+#[test]
+fn test_ffi_download() {
+    let _lock = LOCK.lock().unwrap();
+    let mut mem = TestMem {
+        memory: vec![0xff; 1024],
+        erases: Vec::new(),
+        fail_program: false,
+    };
+    let ops = ops(&mut mem);
+    assert!(unsafe { usbd_dfu_init(&ops) });
+
+    // erase page, set address pointer
+    handle(&request(0x21, 1, 0, 5, &[0x41, 0, 2, 0, 0]));
+    get_status();
+    assert_eq!(get_status(), status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+    handle(&request(0x21, 1, 0, 5, &[0x21, 0, 1, 0, 0]));
+    get_status();
+    get_status();
+    assert_eq!(unsafe { usbd_dfu_address_pointer() }, 0x100);
+
+    // data block received in pieces from a stream
+    let frame = request(0x21, 1, 2, 256, &[0x55; 256]);
+    let mut replies: Vec<u8> = Vec::new();
+    for chunk in frame.chunks(100) {
+        unsafe {
+            usbd_dfu_receive(
+                chunk.as_ptr(),
+                chunk.len(),
+                send,
+                &mut replies as *mut Vec<u8> as *mut c_void,
+            )
+        };
+    }
+    assert_eq!(replies, [REPLY_ACK, 0, 0]);
+    get_status();
+    assert_eq!(get_status(), status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+
+    // manifestation callback error
+    handle(&request(0x21, 1, 0, 0, &[]));
+    get_status();
+    assert_eq!(get_status(), status(STATUS_ERR_UNKNOWN, 0, DFU_ERROR));
+
+    unsafe { usbd_dfu_reset(true) };
+    assert_eq!(mem.memory[0x100..0x200], [0x55; 256]);
+    assert_eq!(mem.memory[0x200], 0xff);
+    assert_eq!(mem.erases, [Some(0x200)]);
+}
+
+#[test]
+fn test_ffi_errors() {
+    let _lock = LOCK.lock().unwrap();
+    let mut mem = TestMem {
+        memory: vec![0xff; 1024],
+        erases: Vec::new(),
+        fail_program: true,
+    };
+
+    assert!(!unsafe { usbd_dfu_init(std::ptr::null()) });
+    let mut incomplete = ops(&mut mem);
+    incomplete.erase = None;
+    assert!(!unsafe { usbd_dfu_init(&incomplete) });
+
+    let ops = ops(&mut mem);
+    assert!(unsafe { usbd_dfu_init(&ops) });
+
+    handle(&request(0x21, 1, 2, 4, &[1, 2, 3, 4]));
+    get_status();
+    assert_eq!(get_status(), status(STATUS_ERR_PROG, 0, DFU_ERROR));
+
+    // upload
+    handle(&request(0x21, 4, 0, 0, &[]));
+    let reply = handle(&request(0xa1, 2, 5, 256, &[]));
+    assert_eq!(reply[..REPLY_HEADER_LENGTH], [REPLY_ACK, 0, 1]);
+    assert_eq!(reply[REPLY_HEADER_LENGTH..], [0xff; 256]);
+    let reply = handle(&request(0xa1, 2, 6, 256, &[]));
+    assert_eq!(reply, [REPLY_ACK, 0, 0]);
+}
+
+#[test]
+fn test_ffi_null_buffers() {
+    let _lock = LOCK.lock().unwrap();
+    let mut mem = TestMem {
+        memory: vec![0xff; 1024],
+        erases: Vec::new(),
+        fail_program: false,
+    };
+    let ops = ops(&mut mem);
+    assert!(unsafe { usbd_dfu_init(&ops) });
+
+    let mut reply = [0; REPLY_HEADER_LENGTH];
+    let n = unsafe { usbd_dfu_handle(std::ptr::null(), 0, reply.as_mut_ptr(), reply.len()) };
+    assert_eq!(reply[..n], [REPLY_STALL, 0, 0]);
+    let frame = get_status_request();
+    let n = unsafe { usbd_dfu_handle(frame.as_ptr(), frame.len(), std::ptr::null_mut(), 16) };
+    assert_eq!(n, 0);
+
+    let mut replies = Vec::<u8>::new();
+    unsafe {
+        usbd_dfu_receive(
+            std::ptr::null(),
+            16,
+            send,
+            &mut replies as *mut Vec<u8> as *mut c_void,
+        )
+    };
+    assert!(replies.is_empty());
+}
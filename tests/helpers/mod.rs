@@ -1,6 +1,12 @@
 #![allow(dead_code)]
+use usb_device::bus::UsbBusAllocator;
 use usb_device::class::UsbClass;
 use usbd_class_tester::prelude::*;
+use usbd_dfu::class::{DfuClass, DfuMemory};
+use usbd_dfu::consts::{
+    DFU_ABORT, DFU_CLRSTATUS, DFU_DNLOAD, DFU_GETSTATE, DFU_GETSTATUS, DFU_UPLOAD,
+};
+use usbd_dfu::suffix::{Crc32, Suffix, DFU_VERSION_1_0};
 
 // State
 pub const APP_IDLE: u8 = 0;
@@ -107,30 +113,30 @@ where
         if data.len() > u16::MAX as usize {
             return Err(AnyUsbError::DataConversion);
         }
-        self.write(cls, 0x1, block_num, 0, data.len() as u16, data)
+        self.write(cls, DFU_DNLOAD, block_num, 0, data.len() as u16, data)
     }
 
     fn upload(&mut self, cls: &mut C, block_num: u16, length: usize) -> AnyResult<Vec<u8>> {
         if length > u16::MAX as usize {
             return Err(AnyUsbError::DataConversion);
         }
-        self.read(cls, 0x2, block_num, 0, length as u16)
+        self.read(cls, DFU_UPLOAD, block_num, 0, length as u16)
     }
 
     fn get_status(&mut self, cls: &mut C) -> AnyResult<Vec<u8>> {
-        self.read(cls, 0x3, 0, 0, 6)
+        self.read(cls, DFU_GETSTATUS, 0, 0, 6)
     }
 
     fn clear_status(&mut self, cls: &mut C) -> AnyResult<Vec<u8>> {
-        self.write(cls, 0x4, 0, 0, 0, &[])
+        self.write(cls, DFU_CLRSTATUS, 0, 0, 0, &[])
     }
 
     fn get_state(&mut self, cls: &mut C) -> AnyResult<Vec<u8>> {
-        self.read(cls, 0x5, 0, 0, 1)
+        self.read(cls, DFU_GETSTATE, 0, 0, 1)
     }
 
     fn abort(&mut self, cls: &mut C) -> AnyResult<Vec<u8>> {
-        self.write(cls, 0x6, 0, 0, 0, &[])
+        self.write(cls, DFU_ABORT, 0, 0, 0, &[])
     }
 }
 
@@ -138,3 +144,155 @@ pub fn status(status: u8, poll_timeout: u32, state: u8) -> [u8; 6] {
     let t = poll_timeout.to_le_bytes();
     [status, t[0], t[1], t[2], state, 0]
 }
+
+type MakeClass<M> = dyn FnMut(&UsbBusAllocator<EmulatedUsbBus>) -> DfuClass<EmulatedUsbBus, M>;
+
+/// Class factory for [`UsbDeviceCtx::with_usb()`].
+pub struct MkDFU<M: DfuMemory> {
+    make: Box<MakeClass<M>>,
+}
+
+impl<M: DfuMemory + 'static> MkDFU<M> {
+    /// Class of the memory made by `mem`.
+    pub fn new(mut mem: impl FnMut() -> M + 'static) -> Self {
+        Self::builder(move |alloc| DfuClass::new(alloc, mem()))
+    }
+
+    /// Class made by `build`, e.g. with non-default [`DfuClass::builder()`] options.
+    pub fn builder(
+        build: impl FnMut(&UsbBusAllocator<EmulatedUsbBus>) -> DfuClass<EmulatedUsbBus, M> + 'static,
+    ) -> Self {
+        Self {
+            make: Box::new(build),
+        }
+    }
+}
+
+impl<M: DfuMemory + 'static> UsbDeviceCtx for MkDFU<M> {
+    type C<'c> = DfuClass<EmulatedUsbBus, M>;
+    const EP0_SIZE: u8 = 32;
+
+    fn create_class(
+        &mut self,
+        alloc: &UsbBusAllocator<EmulatedUsbBus>,
+    ) -> AnyResult<DfuClass<EmulatedUsbBus, M>> {
+        Ok((self.make)(alloc))
+    }
+}
+
+/// Test image of `len` bytes.
+pub fn image(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// `image` followed by a DFU suffix for product 0x2444.
+pub fn with_suffix(image: &[u8], usb_vendor: u16, device: u16) -> Vec<u8> {
+    let mut file = image.to_vec();
+    let mut suffix = Suffix {
+        crc: 0,
+        length: 16,
+        dfu_signature: ['U', 'F', 'D'],
+        dfu_specification: DFU_VERSION_1_0,
+        usb_vendor,
+        usb_product: 0x2444,
+        device,
+    };
+    file.extend_from_slice(&suffix.to_bytes()[..12]);
+    let mut crc = Crc32::new();
+    crc.update(&file);
+    suffix.crc = crc.finalize();
+    file.extend_from_slice(&suffix.crc.to_le_bytes());
+    file
+}
+
+/// Setup packet of a control request to interface 0, followed by `data`.
+pub fn request(request_type: u8, request: u8, value: u16, length: u16, data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![request_type, request];
+    frame.extend_from_slice(&value.to_le_bytes());
+    frame.extend_from_slice(&0u16.to_le_bytes());
+    frame.extend_from_slice(&length.to_le_bytes());
+    frame.extend_from_slice(data);
+    frame
+}
+
+/// Setup packet of a *DFU_GETSTATUS* request.
+pub fn get_status_request() -> Vec<u8> {
+    request(0xa1, DFU_GETSTATUS, 0, 6, &[])
+}
+
+/// Define `TestMem`, 1 KiB of flash at 0x0800_0000 that accepts every block and
+/// uploads nothing, with the given [`DfuMemory`] items.
+///
+/// Items listed after `except` are not defined by the macro, the invocation defines them.
+#[allow(unused_macros)]
+macro_rules! test_mem {
+    (except $($skip:ident),+; $($items:tt)*) => {
+        test_mem!(@mem [$($skip)+] $($items)*);
+    };
+    (@mem [$($skip:ident)*] $($items:tt)*) => {
+        pub struct TestMem {}
+
+        impl usbd_dfu::class::DfuMemory for TestMem {
+            test_mem!(@item [$($skip)*] INITIAL_ADDRESS_POINTER
+                const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;);
+            test_mem!(@item [$($skip)*] PROGRAM_TIME_MS const PROGRAM_TIME_MS: u32 = 10;);
+            test_mem!(@item [$($skip)*] ERASE_TIME_MS const ERASE_TIME_MS: u32 = 20;);
+            test_mem!(@item [$($skip)*] FULL_ERASE_TIME_MS const FULL_ERASE_TIME_MS: u32 = 30;);
+            test_mem!(@item [$($skip)*] MEM_INFO_STRING
+                const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/1*1Kg";);
+            test_mem!(@item [$($skip)*] TRANSFER_SIZE const TRANSFER_SIZE: u16 = 64;);
+            test_mem!(@item [$($skip)*] store_write_buffer
+                fn store_write_buffer(&mut self, _src: &[u8]) -> Result<(), ()> {
+                    Ok(())
+                }
+            );
+            test_mem!(@item [$($skip)*] read
+                fn read(
+                    &mut self,
+                    _address: u32,
+                    _length: usize,
+                ) -> Result<&[u8], usbd_dfu::class::DfuMemoryError> {
+                    Ok(&[])
+                }
+            );
+            test_mem!(@item [$($skip)*] program
+                fn program(
+                    &mut self,
+                    _address: u32,
+                    _length: usize,
+                ) -> Result<(), usbd_dfu::class::DfuMemoryError> {
+                    Ok(())
+                }
+            );
+            test_mem!(@item [$($skip)*] manifestation
+                fn manifestation(&mut self) -> Result<(), usbd_dfu::class::DfuManifestationError> {
+                    Ok(())
+                }
+            );
+
+            $($items)*
+        }
+    };
+    // items listed in `except` are left out
+    (@item [INITIAL_ADDRESS_POINTER $($rest:ident)*] INITIAL_ADDRESS_POINTER $($item:tt)*) => {};
+    (@item [PROGRAM_TIME_MS $($rest:ident)*] PROGRAM_TIME_MS $($item:tt)*) => {};
+    (@item [ERASE_TIME_MS $($rest:ident)*] ERASE_TIME_MS $($item:tt)*) => {};
+    (@item [FULL_ERASE_TIME_MS $($rest:ident)*] FULL_ERASE_TIME_MS $($item:tt)*) => {};
+    (@item [MEM_INFO_STRING $($rest:ident)*] MEM_INFO_STRING $($item:tt)*) => {};
+    (@item [TRANSFER_SIZE $($rest:ident)*] TRANSFER_SIZE $($item:tt)*) => {};
+    (@item [store_write_buffer $($rest:ident)*] store_write_buffer $($item:tt)*) => {};
+    (@item [read $($rest:ident)*] read $($item:tt)*) => {};
+    (@item [program $($rest:ident)*] program $($item:tt)*) => {};
+    (@item [manifestation $($rest:ident)*] manifestation $($item:tt)*) => {};
+    (@item [$other:ident $($rest:ident)*] $name:ident $($item:tt)*) => {
+        test_mem!(@item [$($rest)*] $name $($item)*);
+    };
+    (@item [] $name:ident $($item:tt)*) => {
+        $($item)*
+    };
+    ($($items:tt)*) => {
+        test_mem!(@mem [] $($items)*);
+    };
+}
+#[allow(unused_imports)]
+pub(crate) use test_mem;
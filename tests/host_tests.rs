@@ -0,0 +1,320 @@
+#![cfg(feature = "host")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::host::*;
+use usbd_dfu::suffix::*;
+
+const TESTMEMSIZE: usize = 1024;
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 64],
+    erases: Vec<Option<u32>>,
+    manifested: bool,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 1;
+    const ERASE_TIME_MS: u32 = 1;
+    const FULL_ERASE_TIME_MS: u32 = 1;
+    const MANIFESTATION_TIME_MS: u32 = 1;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/4*256Kg";
+    const TRANSFER_SIZE: u16 = 64;
+    const MANIFESTATION_TOLERANT: bool = true;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = address
+            .checked_sub(TESTMEM_BASE)
+            .ok_or(DfuMemoryError::Address)? as usize;
+        let to = (from + length).min(TESTMEMSIZE);
+        Ok(&self.memory[from.min(to)..to])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = address
+            .checked_sub(TESTMEM_BASE)
+            .ok_or(DfuMemoryError::Address)? as usize;
+        if from + length > TESTMEMSIZE {
+            return Err(DfuMemoryError::Address);
+        }
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        self.erases.push(Some(address));
+        Ok(())
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        self.erases.push(None);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        self.manifested = true;
+        Ok(())
+    }
+}
+
+fn mem() -> TestMem {
+    TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; 64],
+        erases: Vec::new(),
+        manifested: false,
+    }
+}
+
+/// Transport over the emulated device, stalled requests are errors
+struct TestTransport<'d, 'a> {
+    dev: &'d mut Device<'a, DfuClass<EmulatedUsbBus, TestMem>, MkDFU<TestMem>>,
+    dfu: &'d mut DfuClass<EmulatedUsbBus, TestMem>,
+    delays: Vec<u32>,
+}
+
+impl ControlTransport for TestTransport<'_, '_> {
+    type Error = AnyUsbError;
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.delays.push(ms);
+    }
+
+    fn control_out(&mut self, request: u8, value: u16, data: &[u8]) -> Result<(), Self::Error> {
+        self.dev
+            .write(self.dfu, request, value, 0, data.len() as u16, data)
+            .map(|_| ())
+    }
+
+    fn control_in(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        let vec = self
+            .dev
+            .read(self.dfu, request, value, 0, data.len() as u16)?;
+        data[..vec.len()].copy_from_slice(&vec);
+        Ok(vec.len())
+    }
+}
+
+#[test]
+fn test_download_upload() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let image: Vec<u8> = (0..300u32).map(|i| i as u8).collect();
+            let mut host = DfuHost::new(
+                TestTransport {
+                    dev: &mut dev,
+                    dfu: &mut dfu,
+                    delays: Vec::new(),
+                },
+                64,
+            );
+
+            host.mass_erase().expect("erase");
+            host.erase(TESTMEM_BASE + 256).expect("erase");
+            host.download(TESTMEM_BASE + 16, &image).expect("download");
+            let status = host.manifest().expect("manifest");
+            assert_eq!(status.state, DFU_IDLE);
+
+            let mut data = [0; 300];
+            let n = host.upload(TESTMEM_BASE + 16, &mut data).expect("upload");
+            assert_eq!(n, 300);
+            assert_eq!(data[..], image[..]);
+
+            // upload ends with a short block at the end of memory
+            let mut data = [0; 100];
+            let n = host.upload(TESTMEM_BASE + 1000, &mut data).expect("upload");
+            assert_eq!(n, 24);
+            assert_eq!(data[..24], [0xff; 24]);
+
+            assert_eq!(host.get_state().expect("state"), DFU_IDLE);
+            // bwPollTimeout is waited while the device is busy
+            assert!(!host.transport().delays.is_empty());
+
+            let mem = dfu.release();
+            assert_eq!(mem.erases, [None, Some(TESTMEM_BASE + 256)]);
+            assert!(mem.manifested);
+            assert_eq!(mem.memory[16..316], image[..]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_error_status() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let mut host = DfuHost::new(
+                TestTransport {
+                    dev: &mut dev,
+                    dfu: &mut dfu,
+                    delays: Vec::new(),
+                },
+                64,
+            );
+
+            match host.download(TESTMEM_BASE + 1000, &[0x55; 64]) {
+                Err(HostError::Status(status)) => {
+                    assert_eq!(status.status, STATUS_ERR_ADDRESS);
+                    assert_eq!(status.state, DFU_ERROR);
+                }
+                _ => panic!("download must fail"),
+            }
+            assert_eq!(host.get_state().expect("state"), DFU_ERROR);
+
+            host.idle().expect("idle");
+            assert_eq!(host.get_state().expect("state"), DFU_IDLE);
+
+            // upload in dfuDNLOAD-IDLE state is stalled
+            host.set_address(TESTMEM_BASE).expect("set address");
+            let mut buf = [0; 64];
+            assert!(host.transport().control_in(0x2, 2, &mut buf).is_err());
+        })
+        .expect("with_usb");
+}
+
+fn dfu_file(data: &[u8], usb_vendor: u16) -> Vec<u8> {
+    let mut file = data.to_vec();
+    let suffix = Suffix {
+        crc: 0,
+        length: SUFFIX_LENGTH as u8,
+        dfu_signature: ['U', 'F', 'D'],
+        dfu_specification: DFU_VERSION_1_1A,
+        usb_vendor,
+        usb_product: 0x2444,
+        device: 0x0100,
+    };
+    file.extend_from_slice(&suffix.to_bytes());
+    let mut crc = Crc32::new();
+    crc.update(&file[..file.len() - 4]);
+    let len = file.len();
+    file[len - 4..].copy_from_slice(&crc.finalize().to_le_bytes());
+    file
+}
+
+#[test]
+fn test_download_file() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            let mut host = DfuHost::new(
+                TestTransport {
+                    dev: &mut dev,
+                    dfu: &mut dfu,
+                    delays: Vec::new(),
+                },
+                64,
+            )
+            .with_device(0x1209, 0x2444, 0x0100);
+
+            let file = dfu_file(&[0x55; 100], 0x1209);
+            let suffix = host.download_file(TESTMEM_BASE, &file).expect("download");
+            assert_eq!(suffix.usb_vendor, 0x1209);
+            host.manifest().expect("manifest");
+
+            let mut bad = file.clone();
+            bad[0] = 0;
+            assert!(matches!(
+                host.download_file(TESTMEM_BASE, &bad),
+                Err(HostError::Crc)
+            ));
+
+            let other = dfu_file(&[0xaa; 100], 0x0483);
+            assert!(matches!(
+                host.download_file(TESTMEM_BASE, &other),
+                Err(HostError::Mismatch(SuffixMismatch::Vendor))
+            ));
+
+            assert!(matches!(
+                host.download_file(TESTMEM_BASE, &[0; 8]),
+                Err(HostError::Suffix(SuffixError::TooShort))
+            ));
+
+            let mem = dfu.release();
+            assert_eq!(mem.memory[..100], [0x55; 100]);
+            assert_eq!(mem.memory[100], 0xff);
+        })
+        .expect("with_usb");
+}
+
+/// Transport that accepts every request and records the block numbers
+#[derive(Default)]
+struct BlockTransport {
+    blocks: Vec<u16>,
+}
+
+impl ControlTransport for BlockTransport {
+    type Error = ();
+
+    fn delay_ms(&mut self, ms: u32) {}
+
+    fn control_out(&mut self, request: u8, value: u16, data: &[u8]) -> Result<(), Self::Error> {
+        if request == usbd_dfu::consts::DFU_DNLOAD {
+            self.blocks.push(value);
+        }
+        Ok(())
+    }
+
+    fn control_in(
+        &mut self,
+        request: u8,
+        value: u16,
+        data: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        if request == usbd_dfu::consts::DFU_GETSTATUS {
+            data.copy_from_slice(&[0, 0, 0, 0, DfuState::DfuDnloadIdle as u8, 0]);
+        } else {
+            self.blocks.push(value);
+        }
+        Ok(data.len())
+    }
+}
+
+#[test]
+fn test_block_number_wrap() {
+    let mut host = DfuHost::new(BlockTransport::default(), 1);
+    host.download(TESTMEM_BASE, &[0; 0x10001])
+        .expect("download");
+    let blocks = host.release().blocks;
+    assert_eq!(blocks[0], 0);
+    assert_eq!(blocks[0xfffe..], [0xffff, 0, 1, 2]);
+
+    let mut host = DfuHost::new(BlockTransport::default(), 1);
+    let mut data = [0; 0x10001];
+    assert_eq!(
+        host.upload(TESTMEM_BASE, &mut data).expect("upload"),
+        0x10001
+    );
+    let blocks = host.release().blocks;
+    assert_eq!(blocks[0], 0);
+    assert_eq!(blocks[0xfffe..], [0xffff, 0, 1, 2]);
+}
+
+#[test]
+fn test_zero_transfer_size() {
+    let mut host = DfuHost::new(BlockTransport::default(), 0);
+    assert!(matches!(
+        host.download(TESTMEM_BASE, &[0; 64]),
+        Err(HostError::TransferSize)
+    ));
+    assert!(matches!(
+        host.upload(TESTMEM_BASE, &mut [0; 64]),
+        Err(HostError::TransferSize)
+    ));
+    assert!(host.transport().blocks.is_empty());
+}
@@ -0,0 +1,166 @@
+#![cfg(feature = "i2c-eeprom")]
+
+use embedded_hal::i2c::{self, ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+use usbd_dfu::class::{DfuMemory, DfuMemoryError};
+use usbd_dfu::i2c_eeprom::*;
+
+#[derive(Debug)]
+pub struct TestError(ErrorKind);
+
+impl i2c::Error for TestError {
+    fn kind(&self) -> ErrorKind {
+        self.0
+    }
+}
+
+/// 24xx EEPROM emulation, busy for `busy_polls` address cycles after a write
+struct TestEeprom<C: EepromChip> {
+    memory: Vec<u8>,
+    pointer: usize,
+    busy: u32,
+    busy_polls: u32,
+    /// (device address, written bytes) of page writes
+    writes: Vec<(u8, usize)>,
+    naks: u32,
+    _chip: core::marker::PhantomData<C>,
+}
+
+impl<C: EepromChip> TestEeprom<C> {
+    fn new() -> Self {
+        Self {
+            memory: vec![0xff; C::SIZE as usize],
+            pointer: 0,
+            busy: 0,
+            busy_polls: 2,
+            writes: Vec::new(),
+            naks: 0,
+            _chip: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<C: EepromChip> ErrorType for TestEeprom<C> {
+    type Error = TestError;
+}
+
+impl<C: EepromChip> I2c for TestEeprom<C> {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), TestError> {
+        assert_eq!(address & 0xf8, 0x50);
+        if self.busy > 0 {
+            self.busy -= 1;
+            self.naks += 1;
+            return Err(TestError(ErrorKind::NoAcknowledge(
+                NoAcknowledgeSource::Address,
+            )));
+        }
+
+        let mut written = Vec::new();
+        for op in operations.iter_mut() {
+            match op {
+                Operation::Write(bytes) => written.extend_from_slice(bytes),
+                Operation::Read(bytes) => {
+                    if !written.is_empty() {
+                        self.pointer = memory_address::<C>(address, &written);
+                        written.clear();
+                    }
+                    for b in bytes.iter_mut() {
+                        *b = self.memory[self.pointer];
+                        self.pointer = (self.pointer + 1) % C::SIZE as usize;
+                    }
+                }
+            }
+        }
+
+        if !written.is_empty() {
+            let start = memory_address::<C>(address, &written);
+            let data = &written[C::ADDRESS_BYTES..];
+            let page = C::PAGE_SIZE as usize;
+            for (i, b) in data.iter().enumerate() {
+                // wraps around within the page
+                self.memory[start - start % page + (start + i) % page] = *b;
+            }
+            self.writes.push((address, data.len()));
+            self.busy = self.busy_polls;
+        }
+        Ok(())
+    }
+}
+
+fn memory_address<C: EepromChip>(device_address: u8, bytes: &[u8]) -> usize {
+    let mut address = (device_address & 0x7) as usize;
+    for b in &bytes[..C::ADDRESS_BYTES] {
+        address = address << 8 | *b as usize;
+    }
+    address
+}
+
+#[test]
+fn test_consts() {
+    type Mem = I2cEeprom<TestEeprom<At24c32>, At24c32, 64>;
+    assert_eq!(Mem::MEM_INFO_STRING, "@EEPROM/0x00000000/128*32 e");
+    assert_eq!(Mem::INITIAL_ADDRESS_POINTER, 0);
+    assert_eq!(Mem::PROGRAM_TIME_MS, 2 * WRITE_CYCLE_TIME_MS);
+    assert_eq!(Mem::TRANSFER_SIZE, 64);
+}
+
+#[test]
+fn test_program_read() {
+    let mut mem = I2cEeprom::<_, At24c32, 64>::new(TestEeprom::<At24c32>::new(), 0x50);
+
+    assert!(mem.erase_all().is_ok());
+    let data: Vec<u8> = (0..50).collect();
+    assert!(mem.store_write_buffer(&data).is_ok());
+    // crosses two page boundaries
+    assert!(mem.program(0x1f0, 50).is_ok());
+    assert_eq!(mem.read(0x1f0, 50).ok().unwrap(), &data[..]);
+
+    let eeprom = mem.release();
+    assert_eq!(eeprom.writes, [(0x50, 16), (0x50, 32), (0x50, 2)]);
+    assert_eq!(eeprom.naks, 3 * 2);
+    assert_eq!(eeprom.memory[0x1ef], 0xff);
+    assert_eq!(eeprom.memory[0x222], 0xff);
+}
+
+#[test]
+fn test_block_address() {
+    // 1 address byte, bits 8-10 are in the device address
+    let mut mem = I2cEeprom::<_, At24c16, 16>::new(TestEeprom::<At24c16>::new(), 0x50);
+
+    assert!(mem.store_write_buffer(&[0x12; 16]).is_ok());
+    assert!(mem.program(0x5f0, 16).is_ok());
+    assert_eq!(mem.read(0x5f8, 8).ok().unwrap(), [0x12; 8]);
+    assert_eq!(mem.read(0x7f8, 16).ok().unwrap().len(), 8);
+    assert!(mem.read(0x800, 16).ok().unwrap().is_empty());
+
+    let eeprom = mem.release();
+    assert_eq!(eeprom.writes, [(0x55, 16)]);
+    assert_eq!(eeprom.memory[0x5f0..0x600], [0x12; 16]);
+}
+
+#[test]
+fn test_write_timeout() {
+    let mut eeprom = TestEeprom::<At24c32>::new();
+    eeprom.busy_polls = u32::MAX;
+    let mut mem = I2cEeprom::<_, At24c32, 64>::new(eeprom, 0x50);
+
+    assert!(mem.store_write_buffer(&[0; 64]).is_ok());
+    assert_eq!(mem.program(0, 64), Err(DfuMemoryError::Write));
+
+    let eeprom = mem.release();
+    assert_eq!(eeprom.writes, [(0x50, 32)]);
+    assert_eq!(eeprom.naks, ACK_POLLS);
+}
+
+#[test]
+fn test_out_of_range() {
+    let mut mem = I2cEeprom::<_, At24c02, 16>::new(TestEeprom::<At24c02>::new(), 0x50);
+
+    assert!(mem.store_write_buffer(&[0; 16]).is_ok());
+    assert!(mem.program(0xf8, 16).is_err());
+    assert!(mem.erase(0x100).is_err());
+    assert!(mem.release().writes.is_empty());
+}
@@ -0,0 +1,197 @@
+mod helpers;
+use helpers::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::link::*;
+
+const TESTMEMSIZE: usize = 256;
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 64],
+    erased: bool,
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/1*256 g";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = ((address - TESTMEM_BASE) as usize).min(TESTMEMSIZE);
+        let to = (from + length).min(TESTMEMSIZE);
+        Ok(&self.memory[from..to])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        if from + length > TESTMEMSIZE {
+            return Err(DfuMemoryError::Address);
+        }
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn erase_all(&mut self) -> Result<(), DfuMemoryError> {
+        self.erased = true;
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+fn mem() -> TestMem {
+    TestMem {
+        memory: [0xff; TESTMEMSIZE],
+        buffer: [0; 64],
+        erased: false,
+    }
+}
+
+fn dnload(block: u16, data: &[u8]) -> Vec<u8> {
+    request(0x21, 1, block, data.len() as u16, data)
+}
+
+fn upload(block: u16, length: u16) -> Vec<u8> {
+    request(0xa1, 2, block, length, &[])
+}
+
+fn ack(data: &[u8]) -> Vec<u8> {
+    let mut reply = vec![REPLY_ACK];
+    reply.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    reply.extend_from_slice(data);
+    reply
+}
+
+fn stall() -> Vec<u8> {
+    vec![REPLY_STALL, 0, 0]
+}
+
+fn handle(link: &mut DfuLink<TestMem>, frame: &[u8]) -> Vec<u8> {
+    let mut reply = [0; 128];
+    let n = link.handle(frame, &mut reply);
+    reply[..n].to_vec()
+}
+
+#[test]
+fn test_link_download_upload() {
+    let mut link = DfuLink::new(mem());
+
+    assert_eq!(handle(&mut link, &dnload(0, &[0x41])), ack(&[]));
+    assert_eq!(
+        handle(&mut link, &get_status_request()),
+        ack(&status(STATUS_OK, 30, DFU_DN_BUSY))
+    );
+    assert_eq!(
+        handle(&mut link, &get_status_request()),
+        ack(&status(STATUS_OK, 0, DFU_DNLOAD_IDLE))
+    );
+
+    assert_eq!(handle(&mut link, &dnload(3, &[0x55; 64])), ack(&[]));
+    assert_eq!(
+        handle(&mut link, &get_status_request()),
+        ack(&status(STATUS_OK, 10, DFU_DN_BUSY))
+    );
+    assert_eq!(
+        handle(&mut link, &get_status_request()),
+        ack(&status(STATUS_OK, 0, DFU_DNLOAD_IDLE))
+    );
+
+    assert_eq!(handle(&mut link, &dnload(0, &[])), ack(&[]));
+    assert_eq!(
+        handle(&mut link, &get_status_request()),
+        ack(&status(STATUS_OK, 1, DFU_MANIFEST))
+    );
+    assert_eq!(
+        handle(&mut link, &get_status_request()),
+        ack(&status(STATUS_OK, 0, DFU_IDLE))
+    );
+
+    link.reset();
+    assert_eq!(handle(&mut link, &upload(3, 64)), ack(&[0x55; 64]));
+    // short block at the end of memory
+    assert_eq!(handle(&mut link, &upload(6, 64)), ack(&[]));
+
+    let mem = link.release();
+    assert!(mem.erased);
+    assert_eq!(mem.memory[64..128], [0x55; 64]);
+}
+
+#[test]
+fn test_link_errors() {
+    let mut link = DfuLink::new(mem());
+
+    // not a DFU request
+    assert_eq!(handle(&mut link, &request(0x40, 1, 0, 0, &[])), stall());
+    assert_eq!(
+        handle(&mut link, &get_status_request()),
+        ack(&status(STATUS_ERR_STALLED_PKT, 0, DFU_ERROR))
+    );
+    assert_eq!(handle(&mut link, &request(0x21, 4, 0, 0, &[])), ack(&[]));
+
+    // data length doesn't match wLength
+    let mut frame = dnload(2, &[0x55; 8]);
+    frame.pop();
+    assert_eq!(handle(&mut link, &frame), stall());
+    assert_eq!(handle(&mut link, &request(0x21, 4, 0, 0, &[])), ack(&[]));
+
+    // truncated setup packet
+    assert_eq!(handle(&mut link, &[0xa1, 3, 0]), stall());
+    assert_eq!(handle(&mut link, &request(0x21, 4, 0, 0, &[])), ack(&[]));
+
+    // upload data is limited by the reply buffer
+    let mut reply = [0; REPLY_HEADER_LENGTH + 10];
+    let n = link.handle(&upload(2, 64), &mut reply);
+    assert_eq!(reply[..n], ack(&[0xff; 10]));
+}
+
+#[test]
+fn test_stream_link() {
+    let mut link = StreamLink::<_, { SETUP_LENGTH + 64 }>::new(mem());
+
+    let mut stream = dnload(2, &[0xaa; 64]);
+    stream.extend_from_slice(&get_status_request());
+    stream.extend_from_slice(&get_status_request());
+
+    // frames split at arbitrary offsets
+    let mut replies = Vec::new();
+    for chunk in stream.chunks(5) {
+        link.receive(chunk, |reply| replies.extend_from_slice(reply));
+    }
+
+    let mut expected = ack(&[]);
+    expected.extend_from_slice(&ack(&status(STATUS_OK, 10, DFU_DN_BUSY)));
+    expected.extend_from_slice(&ack(&status(STATUS_OK, 0, DFU_DNLOAD_IDLE)));
+    assert_eq!(replies, expected);
+
+    // partial frame is dropped
+    let mut replies = Vec::new();
+    link.receive(&[0x21, 1], |reply| replies.extend_from_slice(reply));
+    link.reset();
+    link.receive(&get_status_request(), |reply| {
+        replies.extend_from_slice(reply)
+    });
+    assert_eq!(replies, ack(&status(STATUS_OK, 0, DFU_DNLOAD_IDLE)));
+
+    // frame is longer than the buffer
+    let mut replies = Vec::new();
+    link.receive(&dnload(3, &[0xaa; 100]), |reply| {
+        replies.extend_from_slice(reply)
+    });
+    assert_eq!(replies, stall());
+
+    let mem = link.release();
+    assert_eq!(mem.memory[..64], [0xaa; 64]);
+    assert_eq!(mem.memory[64..128], [0xff; 64]);
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "log")]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use std::sync::Mutex;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+static RECORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct TestLogger;
+
+impl log::Log for TestLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        RECORDS.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: TestLogger = TestLogger;
+
+pub struct TestMem {
+    buffer: [u8; 32],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/1*256 g";
+    const TRANSFER_SIZE: u16 = 32;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&self.buffer[..length.min(32)])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Err(DfuMemoryError::Prog)
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_trace_points() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Debug);
+
+    MkDFU::new(|| TestMem { buffer: [0; 32] })
+        .with_usb(|mut dfu, mut dev| {
+            let vec = dev.download(&mut dfu, 2, &[0x55; 32]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_PROG, 0, DFU_ERROR));
+        })
+        .expect("with_usb");
+
+    let records = RECORDS.lock().unwrap();
+    for expected in [
+        "DFU_DNLOAD block 2 length 32",
+        "DFU command WriteMemory { block_num: 0, len: 32, skip: 0, rebase: false, crc: None }",
+        "DFU state dfuIDLE -> dfuDNLOAD-SYNC, status OK",
+        "DFU operation Program { address: 134217728, len: 32, block_num: 0, crc: None }",
+        "DFU operation Program { address: 134217728, len: 32, block_num: 0, crc: None } failed: errPROG",
+        "DFU state dfuDNBUSY -> dfuERROR, status errPROG",
+    ] {
+        assert!(
+            records.iter().any(|r| r == expected),
+            "{expected} not logged"
+        );
+    }
+}
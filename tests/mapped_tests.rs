@@ -0,0 +1,165 @@
+#![cfg(feature = "embedded-storage")]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+use usbd_dfu::class::DfuMemory;
+use usbd_dfu::flash::FlashLayout;
+use usbd_dfu::mapped::*;
+
+const FLASH_SIZE: usize = 0x2000;
+const MAPPED_BASE: u32 = 0x9000_0000;
+
+type Log = Rc<RefCell<Vec<&'static str>>>;
+
+/// Indirect-mode driver emulation, memory is read through the mapping
+pub struct TestFlash {
+    memory: Box<[u8; FLASH_SIZE]>,
+    log: Log,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TestFlashError(NorFlashErrorKind);
+
+impl NorFlashError for TestFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.0
+    }
+}
+
+impl ErrorType for TestFlash {
+    type Error = TestFlashError;
+}
+
+impl ReadNorFlash for TestFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, _offset: u32, _bytes: &mut [u8]) -> Result<(), Self::Error> {
+        panic!("indirect read");
+    }
+
+    fn capacity(&self) -> usize {
+        FLASH_SIZE
+    }
+}
+
+impl NorFlash for TestFlash {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = 4096;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !from.is_multiple_of(4096) || !to.is_multiple_of(4096) {
+            return Err(TestFlashError(NorFlashErrorKind::NotAligned));
+        }
+        self.memory[from as usize..to as usize].fill(0xff);
+        self.log.borrow_mut().push("erase");
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let offset = offset as usize;
+        self.memory[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.log.borrow_mut().push("write");
+        Ok(())
+    }
+}
+
+struct TestMap(Log);
+
+impl MemoryMap for TestMap {
+    fn unmap(&mut self) {
+        self.0.borrow_mut().push("unmap");
+    }
+
+    fn map(&mut self) {
+        self.0.borrow_mut().push("map");
+    }
+}
+
+fn mapped_flash() -> (MappedFlash<TestFlash, TestMap>, Log) {
+    let log = Log::default();
+    let flash = TestFlash {
+        memory: Box::new([0xff; FLASH_SIZE]),
+        log: log.clone(),
+    };
+    let base = flash.memory.as_ptr();
+    let flash = unsafe { MappedFlash::new(flash, TestMap(log.clone()), base) };
+    (flash, log)
+}
+
+struct Staging;
+
+impl FlashLayout for Staging {
+    const START: u32 = MAPPED_BASE + 0x1000;
+    const END: u32 = MAPPED_BASE + 0x2000;
+    const MEM_INFO_STRING: &'static str = "@QSPI/0x90001000/1*4Kg";
+    const PROGRAM_TIME_MS: u32 = 12;
+    const ERASE_TIME_MS: u32 = 400;
+    const FULL_ERASE_TIME_MS: u32 = 400;
+
+    fn page(address: u32) -> Option<(u32, u32)> {
+        if !(Self::START..Self::END).contains(&address) {
+            return None;
+        }
+        Some((address - address % 4096, 4096))
+    }
+}
+
+type Mem = MappedMemory<TestFlash, TestMap, Staging, 64>;
+
+#[test]
+fn test_mode_switch() {
+    let (mut flash, log) = mapped_flash();
+    assert_eq!(flash.capacity(), FLASH_SIZE);
+
+    flash.erase(0x1000, 0x2000).unwrap();
+    flash.write(0x1010, &[1, 2, 3]).unwrap();
+    assert_eq!(
+        log.borrow()[..],
+        ["unmap", "erase", "map", "unmap", "write", "map"]
+    );
+
+    // reads don't switch modes
+    let mut buf = [0; 4];
+    flash.read(0x100f, &mut buf).unwrap();
+    assert_eq!(buf, [0xff, 1, 2, 3]);
+    assert_eq!(log.borrow().len(), 6);
+}
+
+#[test]
+fn test_errors() {
+    let (mut flash, log) = mapped_flash();
+
+    assert_eq!(
+        flash.erase(0x100, 0x1100),
+        Err(MappedFlashError::Flash(TestFlashError(
+            NorFlashErrorKind::NotAligned
+        )))
+    );
+    // mapped mode is restored after an error
+    assert_eq!(log.borrow()[..], ["unmap", "map"]);
+
+    assert_eq!(
+        flash.read(0x1ff0, &mut [0; 32]),
+        Err(MappedFlashError::OutOfBounds)
+    );
+}
+
+#[test]
+fn test_memory() {
+    let (flash, _) = mapped_flash();
+    let mut mem = Mem::new(flash, MAPPED_BASE);
+    assert_eq!(Mem::INITIAL_ADDRESS_POINTER, 0x9000_1000);
+
+    assert!(mem.erase(0x9000_1000).is_ok());
+    assert!(mem.store_write_buffer(&[0x12; 64]).is_ok());
+    assert!(mem.program(0x9000_1040, 64).is_ok());
+    assert!(mem.program(0x9000_0000, 64).is_err());
+
+    let data = mem.read(0x9000_1030, 32).ok().unwrap();
+    assert_eq!(data[..16], [0xff; 16]);
+    assert_eq!(data[16..], [0x12; 16]);
+}
@@ -0,0 +1,77 @@
+use usbd_dfu::mem_info::*;
+
+#[test]
+fn test_areas() {
+    let areas: Vec<Area> = areas("@Flash/0x08000000/16*1Ka,48*1Kg/0x1FFF0000/1*512 e").collect();
+    assert_eq!(
+        areas,
+        [
+            Area {
+                address: 0x0800_0000,
+                count: 16,
+                page_size: 1024,
+                operations: Operations::READ,
+            },
+            Area {
+                address: 0x0800_4000,
+                count: 48,
+                page_size: 1024,
+                operations: Operations::ALL,
+            },
+            Area {
+                address: 0x1FFF_0000,
+                count: 1,
+                page_size: 512,
+                operations: Operations::from_letter(b'e').expect("letter"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_areas_malformed() {
+    assert_eq!(areas("").count(), 0);
+    assert_eq!(areas("Flash/0x08000000/16*1Ka").count(), 0);
+    assert_eq!(areas("@Flash/08000000/16*1Ka").count(), 0);
+    // parsing stops at the malformed area
+    assert_eq!(areas("@Flash/0x08000000/16*1Ka,4*1Kz,4*1Kg").count(), 1);
+    // the end of the address space
+    assert_eq!(areas("@Flash/0xFFFF0000/1*64Kg,1*64Kg").count(), 1);
+}
+
+#[test]
+fn test_operations() {
+    const INFO: &str = "@Flash/0x08000000/2*64 a,2*64 g,1*64Mc";
+
+    assert_eq!(operations(INFO, 0x0800_0000, 64), Some(Operations::READ));
+    assert_eq!(operations(INFO, 0x0800_0080, 128), Some(Operations::ALL));
+    // across areas
+    let ops = operations(INFO, 0x0800_00C0, 128).expect("operations");
+    assert!(ops.contains(Operations::READ));
+    assert!(ops.contains(Operations::ERASE));
+    assert!(!ops.contains(Operations::WRITE));
+    assert_eq!(ops.letter(), Some('c'));
+    // outside
+    assert_eq!(operations(INFO, 0x07FF_FFFF, 2), None);
+    assert_eq!(operations(INFO, 0x0C00_0100, 1), None);
+}
+
+#[test]
+fn test_operations_letters() {
+    for letter in b'a'..=b'g' {
+        let ops = Operations::from_letter(letter).expect("letter");
+        assert_eq!(ops.letter(), Some(letter as char));
+    }
+    assert_eq!(Operations::from_letter(b'h'), None);
+    assert_eq!(Operations::NONE.letter(), None);
+}
+
+#[test]
+fn test_region_end() {
+    const INFO: &str = "@Flash/0x08000000/2*64 a,2*64 g/0x08001000/1*1Kg";
+
+    assert_eq!(region_end(INFO, 0x0800_0000), Some(0x0800_0100));
+    assert_eq!(region_end(INFO, 0x0800_00FF), Some(0x0800_0100));
+    assert_eq!(region_end(INFO, 0x0800_1000), Some(0x0800_1400));
+    assert_eq!(region_end(INFO, 0x0800_0100), None);
+}
@@ -0,0 +1,184 @@
+#![cfg(all(feature = "test-helpers", feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::bus::UsbBusAllocator;
+use usbd_dfu::class::*;
+use usbd_dfu::replay::*;
+use usbd_dfu::test_helpers::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+const TESTMEMSIZE: usize = 1024;
+
+pub struct TestMem {
+    memory: [u8; TESTMEMSIZE],
+    buffer: [u8; 64],
+}
+
+impl TestMem {
+    fn offset(address: u32, length: usize) -> Result<usize, DfuMemoryError> {
+        let from = address
+            .checked_sub(TESTMEM_BASE)
+            .ok_or(DfuMemoryError::Address)? as usize;
+        if from + length > TESTMEMSIZE {
+            return Err(DfuMemoryError::Address);
+        }
+        Ok(from)
+    }
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/16*64 g";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = Self::offset(address, length)?;
+        Ok(&self.memory[from..from + length])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = Self::offset(address, length)?;
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DfuMemoryError> {
+        let from = Self::offset(address, 64)?;
+        self.memory[from..from + 64].fill(0xff);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+struct MkDFU {}
+
+impl UsbDeviceCtx for MkDFU {
+    type C<'c> = DfuClass<EmulatedUsbBus, TestMem>;
+    const EP0_SIZE: u8 = 32;
+
+    fn create_class(
+        &mut self,
+        alloc: &UsbBusAllocator<EmulatedUsbBus>,
+    ) -> AnyResult<DfuClass<EmulatedUsbBus, TestMem>> {
+        let mem = TestMem {
+            memory: core::array::from_fn(|i| i as u8),
+            buffer: [0; 64],
+        };
+        Ok(DfuClass::new(alloc, mem))
+    }
+}
+
+#[test]
+fn test_replay_dfu_util_download() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            let trace = parse(include_str!("traces/dfu-util-download.txt")).expect("trace");
+            replay(&mut dev, &mut dfu, &trace).expect("replay");
+
+            let image: Vec<u8> = (0..64).map(|i| (i * 3) as u8).collect();
+            assert_eq!(dev.upload(&mut dfu, 2, 64).expect("vec"), image);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_replay_dfu_util_upload() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            let trace = parse(include_str!("traces/dfu-util-upload.txt")).expect("trace");
+            replay(&mut dev, &mut dfu, &trace).expect("replay");
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_replay_mismatch() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            let trace = parse(
+                "reset\n\
+                 a1 03 0000 0000 0006 < 00 00 00 00 02 00\n\
+                 # unknown request\n\
+                 21 07 0000 0000 0000 stall\n\
+                 a1 03 0000 0000 0006 < 00 00 00 00 05 00\n",
+            )
+            .expect("trace");
+            assert_eq!(trace.len(), 4);
+
+            let error = replay(&mut dev, &mut dfu, &trace).expect_err("replay");
+            assert_eq!(error.event, 3);
+            assert!(matches!(error.mismatch, Mismatch::Reply(r) if r == [0, 0, 0, 0, 2, 0]));
+
+            // DFU_ABORT is not stalled
+            let trace = parse("21 06 0000 0000 0000 stall").expect("trace");
+            let error = replay(&mut dev, &mut dfu, &trace).expect_err("replay");
+            assert!(matches!(error.mismatch, Mismatch::NotStalled));
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_parse_errors() {
+    assert_eq!(parse("reset\n21 01 0000"), Err(ParseError { line: 2 }));
+    // OUT data length differs from wLength
+    assert_eq!(
+        parse("\n21 01 0000 0000 0002 > 01"),
+        Err(ParseError { line: 2 })
+    );
+    // reply of OUT transfer
+    assert_eq!(
+        parse("21 01 0000 0000 0000 < 01"),
+        Err(ParseError { line: 1 })
+    );
+    assert_eq!(
+        parse("a1 03 0000 0000 0006 < 0"),
+        Err(ParseError { line: 1 })
+    );
+
+    let trace = parse("21 01 0x0002 0000 0004 > 0102 03 04 # block 2").expect("trace");
+    assert_eq!(
+        trace,
+        [Event::Transfer(Transfer {
+            request_type: 0x21,
+            request: 0x01,
+            value: 2,
+            index: 0,
+            length: 4,
+            data: Some(vec![1, 2, 3, 4]),
+            stall: false,
+        })]
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_replay_json() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            let json = r#"[
+                "Reset",
+                {"Transfer": {"request_type": 33, "request": 1, "value": 0, "index": 0,
+                    "length": 5, "data": [33, 0, 0, 0, 8]}},
+                {"Transfer": {"request_type": 161, "request": 3, "value": 0, "index": 0,
+                    "length": 6, "data": [0, 0, 0, 0, 4, 0]}},
+                {"Transfer": {"request_type": 161, "request": 3, "value": 0, "index": 0,
+                    "length": 6}}
+            ]"#;
+            let trace: Vec<Event> = serde_json::from_str(json).expect("json");
+            replay(&mut dev, &mut dfu, &trace).expect("replay");
+        })
+        .expect("with_usb");
+}
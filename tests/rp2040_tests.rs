@@ -0,0 +1,50 @@
+#![cfg(feature = "rp2040")]
+
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use usbd_dfu::class::DfuMemory;
+use usbd_dfu::flash::FlashLayout;
+use usbd_dfu::rp2040::*;
+
+struct App;
+
+impl Rp2040Region for App {
+    const START: u32 = 0x1001_0000;
+    const END: u32 = 0x1020_0000;
+}
+
+#[test]
+fn test_layout() {
+    assert_eq!(Rp2040::<App>::MEM_INFO_STRING, "@Flash/0x10010000/496*4Kg");
+    assert_eq!(Rp2040::<App>::page(0x1000_f000), None);
+    assert_eq!(Rp2040::<App>::page(0x1001_1234), Some((0x1001_1000, 4096)));
+    assert_eq!(Rp2040::<App>::page(0x1020_0000), None);
+
+    type Mem = Rp2040Memory<SingleCore, App, 1024>;
+    assert_eq!(Mem::INITIAL_ADDRESS_POINTER, 0x1001_0000);
+    assert_eq!(Mem::ERASE_TIME_MS, SECTOR_ERASE_TIME_MS);
+}
+
+#[test]
+fn test_alignment() {
+    // flash is not accessed before the arguments are checked
+    let mut flash = unsafe { Rp2040Flash::new(SingleCore) };
+    assert_eq!(flash.capacity(), 2 * 1024 * 1024);
+
+    assert_eq!(
+        flash.write(128, &[0; 256]),
+        Err(Rp2040FlashError::NotAligned)
+    );
+    assert_eq!(flash.write(0, &[0; 100]), Err(Rp2040FlashError::NotAligned));
+    assert_eq!(
+        flash.erase(0x100, 0x1100),
+        Err(Rp2040FlashError::NotAligned)
+    );
+    assert_eq!(
+        flash.erase(0x1f_f000, 0x20_1000),
+        Err(Rp2040FlashError::OutOfBounds)
+    );
+    assert_eq!(
+        flash.erase(0x2000, 0x1000),
+        Err(Rp2040FlashError::OutOfBounds)
+    );
+}
@@ -0,0 +1,83 @@
+#![cfg(feature = "serde")]
+
+use usbd_dfu::dfuse::*;
+use usbd_dfu::suffix::*;
+
+#[test]
+fn test_suffix_json() {
+    let suffix = Suffix::try_from(
+        &[
+            0x00, 0x01, 0x44, 0x24, 0x09, 0x12, 0x1a, 0x01, b'U', b'F', b'D', 16, 0x78, 0x56, 0x34,
+            0x12,
+        ][..],
+    )
+    .unwrap();
+
+    let json = serde_json::to_string(&suffix).unwrap();
+    assert_eq!(
+        json,
+        r#"{"crc":305419896,"length":16,"dfu_signature":["U","F","D"],"dfu_specification":282,"usb_vendor":4617,"usb_product":9284,"device":256}"#
+    );
+    let parsed: Suffix = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.to_bytes(), suffix.to_bytes());
+
+    let prefix = LmdfuPrefix {
+        address: 0x2000,
+        length: 1024,
+    };
+    let json = serde_json::to_string(&prefix).unwrap();
+    assert_eq!(serde_json::from_str::<LmdfuPrefix>(&json).unwrap(), prefix);
+}
+
+#[test]
+fn test_dfuse_json() {
+    let prefix = Prefix {
+        image_size: 1000,
+        targets: 2,
+    };
+    let json = serde_json::to_string(&prefix).unwrap();
+    assert_eq!(json, r#"{"image_size":1000,"targets":2}"#);
+    assert_eq!(serde_json::from_str::<Prefix>(&json).unwrap(), prefix);
+
+    let element = ElementHeader {
+        address: 0x0800_0000,
+        size: 64,
+    };
+    let json = serde_json::to_string(&element).unwrap();
+    assert_eq!(
+        serde_json::from_str::<ElementHeader>(&json).unwrap(),
+        element
+    );
+
+    let mut name = [0; TARGET_NAME_LENGTH];
+    name[..14].copy_from_slice(b"Internal Flash");
+    let target = TargetPrefix {
+        alt_setting: 0,
+        name,
+        named: true,
+        size: 72,
+        elements: 1,
+    };
+    let json = serde_json::to_string(&target).unwrap();
+    assert_eq!(
+        json,
+        r#"{"alt_setting":0,"name":"Internal Flash","named":true,"size":72,"elements":1}"#
+    );
+    let parsed: TargetPrefix = serde_json::from_str(&json).unwrap();
+    assert!(parsed == target);
+
+    // name that is not valid UTF-8 is serialized as bytes
+    let mut name = [0; TARGET_NAME_LENGTH];
+    name[..3].copy_from_slice(&[0xff, 0, 1]);
+    let target = TargetPrefix { name, ..target };
+    let json = serde_json::to_string(&target).unwrap();
+    assert!(json.contains(r#""name":[255,0,1]"#));
+    let parsed: TargetPrefix = serde_json::from_str(&json).unwrap();
+    assert!(parsed == target);
+
+    let long = format!(
+        r#"{{"alt_setting":0,"name":"{}","named":true,"size":0,"elements":0}}"#,
+        "x".repeat(256)
+    );
+    assert!(serde_json::from_str::<TargetPrefix>(&long).is_err());
+}
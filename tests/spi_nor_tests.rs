@@ -0,0 +1,213 @@
+#![cfg(feature = "spi-nor")]
+
+use core::convert::Infallible;
+
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use usbd_dfu::class::DfuMemory;
+use usbd_dfu::flash::FlashLayout;
+use usbd_dfu::spi_nor::*;
+
+const FLASH_SIZE: usize = 0x4000;
+
+/// SPI NOR chip emulation, busy for `busy_polls` status reads after each operation
+struct TestChip {
+    memory: Vec<u8>,
+    write_enabled: bool,
+    busy: u32,
+    busy_polls: u32,
+    commands: Vec<u8>,
+}
+
+impl TestChip {
+    fn new() -> Self {
+        Self {
+            memory: vec![0xff; FLASH_SIZE],
+            write_enabled: false,
+            busy: 0,
+            busy_polls: 2,
+            commands: Vec::new(),
+        }
+    }
+
+    fn address(cmd: &[u8]) -> usize {
+        u32::from_be_bytes([0, cmd[1], cmd[2], cmd[3]]) as usize
+    }
+}
+
+impl ErrorType for TestChip {
+    type Error = Infallible;
+}
+
+impl SpiDevice for TestChip {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        let mut written = Vec::new();
+        for op in operations.iter_mut() {
+            match op {
+                Operation::Write(bytes) => written.extend_from_slice(bytes),
+                Operation::Read(bytes) => match written[0] {
+                    CMD_READ_STATUS => {
+                        bytes[0] = if self.busy > 0 { STATUS_WIP } else { 0 };
+                        self.busy = self.busy.saturating_sub(1);
+                    }
+                    CMD_READ => {
+                        let a = Self::address(&written);
+                        bytes.copy_from_slice(&self.memory[a..a + bytes.len()]);
+                    }
+                    cmd => panic!("unexpected read command {cmd:#x}"),
+                },
+                _ => panic!("unexpected operation"),
+            }
+        }
+
+        let cmd = written[0];
+        self.commands.push(cmd);
+        assert!(
+            self.busy == 0 || cmd == CMD_READ_STATUS,
+            "command {cmd:#x} while busy"
+        );
+        match cmd {
+            CMD_WRITE_ENABLE => self.write_enabled = true,
+            CMD_SECTOR_ERASE | CMD_PAGE_PROGRAM => {
+                assert!(self.write_enabled, "write is not enabled");
+                self.write_enabled = false;
+                self.busy = self.busy_polls;
+                let a = Self::address(&written);
+                if cmd == CMD_SECTOR_ERASE {
+                    self.memory[a..a + 4096].fill(0xff);
+                } else {
+                    let data = &written[4..];
+                    assert!(data.len() <= 256);
+                    for (i, b) in data.iter().enumerate() {
+                        // wraps around within the page
+                        let p = a - a % 256 + (a + i) % 256;
+                        self.memory[p] &= b;
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+struct Staging;
+
+impl SpiNorRegion for Staging {
+    const START: u32 = 0x1000;
+    const END: u32 = 0x4000;
+}
+
+#[test]
+fn test_layout() {
+    assert_eq!(
+        SpiNor::<Staging>::MEM_INFO_STRING,
+        "@SPI Flash/0x00001000/3*4Kg"
+    );
+    assert_eq!(SpiNor::<Staging>::page(0x0fff), None);
+    assert_eq!(SpiNor::<Staging>::page(0x2345), Some((0x2000, 4096)));
+    assert_eq!(SpiNor::<Staging>::page(0x4000), None);
+
+    type Mem = SpiNorMemory<TestChip, Staging, 1024>;
+    assert_eq!(Mem::INITIAL_ADDRESS_POINTER, 0x1000);
+    assert_eq!(Mem::FULL_ERASE_TIME_MS, 3 * SECTOR_ERASE_TIME_MS);
+}
+
+#[test]
+fn test_erase_program_read() {
+    let mut flash = SpiNorFlash::new(TestChip::new(), FLASH_SIZE);
+    assert_eq!(flash.capacity(), FLASH_SIZE);
+
+    flash.write(0x1000, &[0; 16]).unwrap();
+    flash.erase(0x1000, 0x2000).unwrap();
+
+    // crosses a page boundary
+    let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    flash.write(0x10f0, &data).unwrap();
+
+    let mut buf = [0; 300];
+    flash.read(0x10f0, &mut buf).unwrap();
+    assert_eq!(&buf[..], &data[..]);
+
+    let chip = flash.release();
+    assert!(chip.memory[0x1000..0x10f0].iter().all(|&b| b == 0xff));
+    assert_eq!(
+        chip.commands,
+        [
+            // program, 2 busy polls, ready
+            CMD_WRITE_ENABLE,
+            CMD_PAGE_PROGRAM,
+            CMD_READ_STATUS,
+            CMD_READ_STATUS,
+            CMD_READ_STATUS,
+            CMD_WRITE_ENABLE,
+            CMD_SECTOR_ERASE,
+            CMD_READ_STATUS,
+            CMD_READ_STATUS,
+            CMD_READ_STATUS,
+            // 16 bytes to the page end, a full page, and the rest
+            CMD_WRITE_ENABLE,
+            CMD_PAGE_PROGRAM,
+            CMD_READ_STATUS,
+            CMD_READ_STATUS,
+            CMD_READ_STATUS,
+            CMD_WRITE_ENABLE,
+            CMD_PAGE_PROGRAM,
+            CMD_READ_STATUS,
+            CMD_READ_STATUS,
+            CMD_READ_STATUS,
+            CMD_WRITE_ENABLE,
+            CMD_PAGE_PROGRAM,
+            CMD_READ_STATUS,
+            CMD_READ_STATUS,
+            CMD_READ_STATUS,
+            CMD_READ,
+        ]
+    );
+}
+
+#[test]
+fn test_errors() {
+    let mut flash = SpiNorFlash::new(TestChip::new(), FLASH_SIZE);
+
+    assert_eq!(flash.erase(0x100, 0x1100), Err(SpiNorError::NotAligned));
+    assert_eq!(flash.erase(0x2000, 0x1000), Err(SpiNorError::NotAligned));
+    assert_eq!(flash.erase(0x3000, 0x5000), Err(SpiNorError::OutOfBounds));
+    assert_eq!(flash.write(0x3ff0, &[0; 32]), Err(SpiNorError::OutOfBounds));
+    assert_eq!(
+        flash.read(0x4000, &mut [0; 1]),
+        Err(SpiNorError::OutOfBounds)
+    );
+
+    assert!(flash.release().commands.is_empty());
+}
+
+#[test]
+fn test_timeout() {
+    let mut chip = TestChip::new();
+    chip.busy_polls = u32::MAX;
+    let mut flash = SpiNorFlash::new(chip, FLASH_SIZE);
+
+    assert_eq!(flash.erase(0x1000, 0x2000), Err(SpiNorError::Timeout));
+
+    let commands = flash.release().commands;
+    assert_eq!(commands.len(), 2 + STATUS_POLLS as usize);
+}
+
+#[test]
+#[should_panic(expected = "SpiNorFlash capacity is over 16 MiB")]
+fn test_capacity() {
+    SpiNorFlash::new(TestChip::new(), 32 * 1024 * 1024);
+}
+
+#[test]
+fn test_memory() {
+    let flash = SpiNorFlash::new(TestChip::new(), FLASH_SIZE);
+    let mut mem = SpiNorMemory::<_, Staging, 256>::new(flash, 0);
+
+    assert!(mem.erase(0x1000).is_ok());
+    mem.store_write_buffer(&[0x12; 256]).unwrap();
+    assert!(mem.program(0x1000, 256).is_ok());
+    assert_eq!(mem.read(0x10f0, 32).ok().unwrap()[..16], [0x12; 16]);
+    assert_eq!(mem.read(0x10f0, 32).ok().unwrap()[16..], [0xff; 16]);
+}
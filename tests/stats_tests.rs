@@ -0,0 +1,99 @@
+#![cfg(all(feature = "stats", feature = "download", feature = "upload"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+
+const TESTMEM_BASE: u32 = 0x0800_0000;
+
+pub struct TestMem {
+    memory: [u8; 256],
+    buffer: [u8; 64],
+}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = TESTMEM_BASE;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/4*64 g";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        self.buffer[..src.len()].copy_from_slice(src);
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        Ok(&self.memory[from..(from + length).min(100)])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        let from = (address - TESTMEM_BASE) as usize;
+        if from >= 128 {
+            return Err(DfuMemoryError::Prog);
+        }
+        self.memory[from..from + length].copy_from_slice(&self.buffer[..length]);
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+fn mem() -> TestMem {
+    TestMem {
+        memory: [0; 256],
+        buffer: [0; 64],
+    }
+}
+
+#[test]
+fn test_stats() {
+    MkDFU::new(mem)
+        .with_usb(|mut dfu, mut dev| {
+            assert_eq!(dfu.stats().downloads, 0);
+
+            // block 0, block 0 again, block 1
+            for block in [2, 2, 3] {
+                dev.download(&mut dfu, block, &[0x55; 64]).expect("vec");
+                dev.get_status(&mut dfu).expect("vec");
+                let vec = dev.get_status(&mut dfu).expect("vec");
+                assert_eq!(vec, status(STATUS_OK, 0, DFU_DNLOAD_IDLE));
+            }
+
+            // block 2 fails
+            dev.download(&mut dfu, 4, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(STATUS_ERR_PROG, 0, DFU_ERROR));
+            dev.clear_status(&mut dfu).expect("vec");
+
+            // 64 + 36 bytes
+            dev.upload(&mut dfu, 2, 64).expect("vec");
+            dev.upload(&mut dfu, 3, 64).expect("vec");
+
+            // stalled
+            assert!(dev.clear_status(&mut dfu).is_err());
+
+            let stats = dfu.stats();
+            assert_eq!(stats.downloads, 1);
+            assert_eq!(stats.uploads, 1);
+            assert_eq!(stats.blocks_programmed, 3);
+            assert_eq!(stats.bytes_uploaded, 100);
+            assert_eq!(stats.retries, 1);
+            assert_eq!(stats.errors(DfuStatusCode::ErrProg), 1);
+            assert_eq!(stats.errors(DfuStatusCode::ErrStalledPkt), 1);
+            assert_eq!(stats.total_errors(), 2);
+
+            dfu.reset_stats();
+            assert_eq!(*dfu.stats(), usbd_dfu::stats::DfuStats::new());
+        })
+        .expect("with_usb");
+}
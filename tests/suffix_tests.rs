@@ -0,0 +1,169 @@
+use usbd_dfu::suffix::*;
+
+// dfu-suffix -v 0x1209 -p 0x2444 -d 0x0102
+const SUFFIX: [u8; 16] = [
+    0x02, 0x01, // bcdDevice
+    0x44, 0x24, // idProduct
+    0x09, 0x12, // idVendor
+    0x00, 0x01, // bcdDFU
+    b'U', b'F', b'D', // ucDfuSignature
+    16,   // bLength
+    0x78, 0x56, 0x34, 0x12, // dwCRC
+];
+
+#[test]
+fn test_suffix_from_bytes() {
+    let s = Suffix::from(SUFFIX);
+
+    assert_eq!(s.device, 0x0102);
+    assert_eq!(s.usb_product, 0x2444);
+    assert_eq!(s.usb_vendor, 0x1209);
+    assert_eq!(s.dfu_specification, 0x0100);
+    assert_eq!(s.dfu_signature, ['U', 'F', 'D']);
+    assert_eq!(s.length, 16);
+    assert_eq!(s.crc, 0x1234_5678);
+}
+
+#[test]
+fn test_suffix_to_bytes() {
+    let s = Suffix {
+        crc: 0x1234_5678,
+        length: 16,
+        dfu_signature: ['U', 'F', 'D'],
+        dfu_specification: 0x0100,
+        usb_vendor: 0x1209,
+        usb_product: 0x2444,
+        device: 0x0102,
+    };
+
+    assert_eq!(s.to_bytes(), SUFFIX);
+    assert_eq!(Suffix::from(s.to_bytes()).to_bytes(), SUFFIX);
+}
+
+#[test]
+fn test_suffix_try_from() {
+    let s = Suffix::try_from(&SUFFIX[..]).expect("suffix");
+    assert_eq!(s.to_bytes(), SUFFIX);
+
+    /* Suffix at the end of a complete file */
+    let mut file = vec![0xaa; 100];
+    file.extend_from_slice(&SUFFIX);
+    let s = Suffix::try_from(&file[..]).expect("suffix");
+    assert_eq!(s.to_bytes(), SUFFIX);
+}
+
+#[test]
+fn test_suffix_try_from_errors() {
+    assert_eq!(
+        Suffix::try_from(&SUFFIX[1..]).unwrap_err(),
+        SuffixError::TooShort
+    );
+
+    let mut bad = SUFFIX;
+    bad[9] = b'X';
+    assert_eq!(
+        Suffix::try_from(&bad[..]).unwrap_err(),
+        SuffixError::InvalidSignature
+    );
+
+    let mut bad = SUFFIX;
+    bad[11] = 15;
+    assert_eq!(
+        Suffix::try_from(&bad[..]).unwrap_err(),
+        SuffixError::InvalidLength(15)
+    );
+
+    let mut bad = SUFFIX;
+    bad[11] = 17;
+    assert_eq!(
+        Suffix::try_from(&bad[..]).unwrap_err(),
+        SuffixError::InvalidLength(17)
+    );
+
+    let mut bad = SUFFIX;
+    bad[7] = 2;
+    assert_eq!(
+        Suffix::try_from(&bad[..]).unwrap_err(),
+        SuffixError::UnsupportedVersion(0x0200)
+    );
+
+    let mut dfuse = SUFFIX;
+    dfuse[6] = 0x1a;
+    let s = Suffix::try_from(&dfuse[..]).expect("suffix");
+    assert_eq!(s.dfu_specification, DFU_VERSION_1_1A);
+}
+
+#[test]
+fn test_crc32() {
+    let crc = Crc32::new();
+    assert_eq!(crc.finalize(), 0xffff_ffff);
+
+    let mut crc = Crc32::new();
+    crc.update(b"123456789");
+    assert_eq!(crc.finalize(), !0xcbf4_3926);
+
+    /* Build a file with a valid suffix, then verify it block by block */
+    let mut file: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    let mut s = Suffix::from(SUFFIX);
+    s.crc = 0;
+    file.extend_from_slice(&s.to_bytes()[..12]);
+
+    let mut crc = Crc32::new();
+    crc.update(&file);
+    s.crc = crc.finalize();
+    file.extend_from_slice(&s.crc.to_le_bytes());
+
+    let mut crc = Crc32::new();
+    for block in file[..file.len() - 4].chunks(64) {
+        crc.update(block);
+    }
+    let parsed = Suffix::try_from(&file[..]).expect("suffix");
+    assert_eq!(parsed.crc, crc.finalize());
+}
+
+#[test]
+fn test_suffix_matches_device() {
+    let s = Suffix::from(SUFFIX);
+
+    assert_eq!(s.matches_device(0x1209, 0x2444, 0x0102), Ok(()));
+    assert_eq!(
+        s.matches_device(0x1234, 0x2444, 0x0102),
+        Err(SuffixMismatch::Vendor)
+    );
+    assert_eq!(
+        s.matches_device(0x1209, 0x2445, 0x0102),
+        Err(SuffixMismatch::Product)
+    );
+    assert_eq!(
+        s.matches_device(0x1209, 0x2444, 0x0200),
+        Err(SuffixMismatch::Device)
+    );
+
+    let any = Suffix {
+        usb_vendor: SUFFIX_WILDCARD,
+        usb_product: SUFFIX_WILDCARD,
+        device: SUFFIX_WILDCARD,
+        ..s
+    };
+    assert_eq!(any.matches_device(0x1234, 0x5678, 0x9abc), Ok(()));
+}
+
+#[test]
+fn test_lmdfu_prefix() {
+    let prefix = LmdfuPrefix {
+        address: 0x2000,
+        length: 0x12345,
+    };
+    let bytes = prefix.to_bytes();
+    assert_eq!(bytes, [0x01, 0x00, 0x08, 0x00, 0x45, 0x23, 0x01, 0x00]);
+    assert_eq!(LmdfuPrefix::try_from(&bytes[..]), Ok(prefix));
+
+    assert_eq!(
+        LmdfuPrefix::try_from(&bytes[..7]),
+        Err(SuffixError::TooShort)
+    );
+    assert_eq!(
+        LmdfuPrefix::try_from(&[0x02, 0, 0, 0, 0, 0, 0, 0][..]),
+        Err(SuffixError::InvalidSignature)
+    );
+}
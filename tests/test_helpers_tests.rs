@@ -0,0 +1,71 @@
+#![cfg(all(feature = "test-helpers", feature = "download"))]
+#![allow(unused_variables)]
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::bus::UsbBusAllocator;
+use usbd_dfu::class::*;
+use usbd_dfu::test_helpers::*;
+
+pub struct TestMem {}
+
+impl DfuMemory for TestMem {
+    const INITIAL_ADDRESS_POINTER: u32 = 0x0800_0000;
+    const PROGRAM_TIME_MS: u32 = 10;
+    const ERASE_TIME_MS: u32 = 20;
+    const FULL_ERASE_TIME_MS: u32 = 30;
+    const MEM_INFO_STRING: &'static str = "@Flash/0x08000000/1*1Kg";
+    const TRANSFER_SIZE: u16 = 64;
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        Ok(())
+    }
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DfuMemoryError> {
+        Ok(&[])
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Ok(())
+    }
+
+    fn manifestation(&mut self) -> Result<(), DfuManifestationError> {
+        Ok(())
+    }
+}
+
+struct MkDFU {}
+
+impl UsbDeviceCtx for MkDFU {
+    type C<'c> = DfuClass<EmulatedUsbBus, TestMem>;
+    const EP0_SIZE: u8 = 32;
+
+    fn create_class(
+        &mut self,
+        alloc: &UsbBusAllocator<EmulatedUsbBus>,
+    ) -> AnyResult<DfuClass<EmulatedUsbBus, TestMem>> {
+        Ok(DfuClass::new(alloc, TestMem {}))
+    }
+}
+
+#[test]
+fn test_helpers() {
+    MkDFU {}
+        .with_usb(|mut dfu, mut dev| {
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(DfuStatusCode::Ok, 10, DfuState::DfuDnBusy));
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(DfuStatusCode::Ok, 0, DfuState::DfuDnloadIdle));
+
+            dev.abort(&mut dfu).expect("vec");
+            let vec = dev.get_state(&mut dfu).expect("vec");
+            assert_eq!(vec, [DfuState::DfuIdle as u8]);
+
+            // DFU_DETACH is not supported in DFU mode
+            assert!(dev.detach(&mut dfu, 1000).is_err());
+            let vec = dev.get_status(&mut dfu).expect("vec");
+            assert_eq!(vec, status(DfuStatusCode::Ok, 0, DfuState::DfuIdle));
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,101 @@
+#![cfg(all(feature = "trace", feature = "download"))]
+#![allow(unused_variables)]
+
+mod helpers;
+use helpers::*;
+
+use usbd_class_tester::prelude::*;
+
+use usbd_dfu::class::*;
+use usbd_dfu::trace::*;
+
+test_mem! {
+    except program;
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DfuMemoryError> {
+        Err(DfuMemoryError::Prog)
+    }
+}
+
+fn transition(
+    request: Option<u8>,
+    old_state: DfuState,
+    new_state: DfuState,
+    status: DfuStatusCode,
+) -> Transition {
+    Transition {
+        request,
+        old_state,
+        new_state,
+        status,
+    }
+}
+
+#[test]
+fn test_trace_failed_download() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            assert!(dfu.trace().is_empty());
+
+            dev.download(&mut dfu, 2, &[0x55; 64]).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.get_status(&mut dfu).expect("vec");
+            dev.clear_status(&mut dfu).expect("vec");
+
+            let trace: Vec<_> = dfu.trace().iter().copied().collect();
+            assert_eq!(
+                trace,
+                [
+                    transition(
+                        Some(0x01),
+                        DfuState::DfuIdle,
+                        DfuState::DfuDnloadSync,
+                        DfuStatusCode::Ok
+                    ),
+                    transition(
+                        Some(0x03),
+                        DfuState::DfuDnloadSync,
+                        DfuState::DfuDnBusy,
+                        DfuStatusCode::Ok
+                    ),
+                    transition(
+                        None,
+                        DfuState::DfuDnBusy,
+                        DfuState::DfuError,
+                        DfuStatusCode::ErrProg
+                    ),
+                    transition(
+                        Some(0x04),
+                        DfuState::DfuError,
+                        DfuState::DfuIdle,
+                        DfuStatusCode::Ok
+                    ),
+                ]
+            );
+            assert_eq!(trace[2].to_bytes(), [0xff, DFU_DN_BUSY, DFU_ERROR, 0x06]);
+
+            dfu.clear_trace();
+            assert_eq!(dfu.trace().len(), 0);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_trace_keeps_last() {
+    MkDFU::new(|| TestMem {})
+        .with_usb(|mut dfu, mut dev| {
+            // each stalled request is two transitions
+            for _ in 0..TRACE_LENGTH {
+                assert!(dev.clear_status(&mut dfu).is_err());
+                dev.clear_status(&mut dfu).expect("vec");
+            }
+            dev.abort(&mut dfu).expect("vec");
+
+            let trace = dfu.trace();
+            assert_eq!(trace.len(), TRACE_LENGTH);
+            let last = trace.iter().last().expect("last");
+            assert_eq!(last.request, Some(0x04));
+            assert_eq!(last.new_state, DfuState::DfuIdle);
+        })
+        .expect("with_usb");
+}
@@ -0,0 +1,100 @@
+#![cfg(feature = "wcid")]
+#![allow(unused_variables)]
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::bus::UsbBusAllocator;
+use usbd_dfu::wcid::*;
+
+struct MkWcid {
+    vendor_code: u8,
+}
+
+impl UsbDeviceCtx for MkWcid {
+    type C<'c> = WcidClass;
+
+    fn create_class(&mut self, alloc: &UsbBusAllocator<EmulatedUsbBus>) -> AnyResult<WcidClass> {
+        Ok(WcidClass::new(self.vendor_code, 2))
+    }
+}
+
+fn get_os_descriptor(
+    cls: &mut WcidClass,
+    dev: &mut Device<WcidClass, MkWcid>,
+    index: u16,
+) -> AnyResult<Vec<u8>> {
+    let vendor_code = cls.vendor_code();
+    dev.control_read(
+        cls,
+        CtrRequestType::to_host().vendor().device(),
+        vendor_code,
+        0,
+        index,
+        255,
+    )
+}
+
+#[test]
+fn test_os_string() {
+    MkWcid { vendor_code: 0x20 }
+        .with_usb(|mut cls, mut dev| {
+            let vec = dev
+                .device_get_descriptor(&mut cls, 3, OS_STRING_INDEX, 0, 255)
+                .expect("vec");
+            assert_eq!(
+                vec,
+                [
+                    0x12, 0x03, b'M', 0, b'S', 0, b'F', 0, b'T', 0, b'1', 0, b'0', 0, b'0', 0,
+                    0x20, 0
+                ]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_os_string_high_vendor_code() {
+    MkWcid { vendor_code: 0xA5 }
+        .with_usb(|mut cls, mut dev| {
+            let vec = dev
+                .device_get_descriptor(&mut cls, 3, OS_STRING_INDEX, 0, 255)
+                .expect("vec");
+            assert_eq!(vec.len(), 18);
+            assert_eq!(vec[16..], [0xA5, 0]);
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_extended_compat_id() {
+    MkWcid { vendor_code: 0x20 }
+        .with_usb(|mut cls, mut dev| {
+            let vec = get_os_descriptor(&mut cls, &mut dev, EXTENDED_COMPAT_ID_INDEX).expect("vec");
+            assert_eq!(
+                vec,
+                [
+                    40, 0, 0, 0, // dwLength
+                    0x00, 0x01, // bcdVersion
+                    0x04, 0x00, // wIndex
+                    1,    // bCount
+                    0, 0, 0, 0, 0, 0, 0, // reserved
+                    2, // bFirstInterfaceNumber
+                    1, // reserved
+                    b'W', b'I', b'N', b'U', b'S', b'B', 0, 0, // compatibleID
+                    0, 0, 0, 0, 0, 0, 0, 0, // subCompatibleID
+                    0, 0, 0, 0, 0, 0, // reserved
+                ]
+            );
+        })
+        .expect("with_usb");
+}
+
+#[test]
+fn test_unsupported_os_descriptor() {
+    MkWcid { vendor_code: 0x20 }
+        .with_usb(|mut cls, mut dev| {
+            let res = get_os_descriptor(&mut cls, &mut dev, 0x0005);
+            assert!(res.is_err());
+        })
+        .expect("with_usb");
+}
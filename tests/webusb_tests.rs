@@ -0,0 +1,93 @@
+#![allow(unused_variables)]
+
+use usbd_class_tester::prelude::*;
+
+use usb_device::bus::UsbBusAllocator;
+use usbd_dfu::webusb::*;
+
+const VENDOR_CODE: u8 = 0x21;
+
+struct MkWebUsb {
+    landing_page: Option<&'static str>,
+}
+
+impl UsbDeviceCtx for MkWebUsb {
+    type C<'c> = WebUsbClass<'static>;
+
+    fn create_class(
+        &mut self,
+        alloc: &UsbBusAllocator<EmulatedUsbBus>,
+    ) -> AnyResult<WebUsbClass<'static>> {
+        Ok(WebUsbClass::new(VENDOR_CODE, self.landing_page))
+    }
+}
+
+fn get_url(
+    cls: &mut WebUsbClass<'static>,
+    dev: &mut Device<WebUsbClass<'static>, MkWebUsb>,
+    index: u16,
+) -> AnyResult<Vec<u8>> {
+    dev.control_read(
+        cls,
+        CtrRequestType::to_host().vendor().device(),
+        VENDOR_CODE,
+        index,
+        WEBUSB_GET_URL,
+        255,
+    )
+}
+
+#[test]
+fn test_get_url() {
+    MkWebUsb {
+        landing_page: Some("https://example.com/dfu"),
+    }
+    .with_usb(|mut cls, mut dev| {
+        let vec = get_url(&mut cls, &mut dev, 1).expect("vec");
+        assert_eq!(vec[..3], [18, WEBUSB_URL_DESCRIPTOR_TYPE, 1]);
+        assert_eq!(&vec[3..], b"example.com/dfu");
+    })
+    .expect("with_usb");
+}
+
+#[test]
+fn test_get_url_http() {
+    MkWebUsb {
+        landing_page: Some("http://localhost:8000"),
+    }
+    .with_usb(|mut cls, mut dev| {
+        let vec = get_url(&mut cls, &mut dev, 1).expect("vec");
+        assert_eq!(vec[..3], [17, WEBUSB_URL_DESCRIPTOR_TYPE, 0]);
+        assert_eq!(&vec[3..], b"localhost:8000");
+    })
+    .expect("with_usb");
+}
+
+#[test]
+fn test_get_url_invalid_index() {
+    MkWebUsb {
+        landing_page: Some("https://example.com/dfu"),
+    }
+    .with_usb(|mut cls, mut dev| {
+        let res = get_url(&mut cls, &mut dev, 2);
+        assert!(res.is_err());
+    })
+    .expect("with_usb");
+}
+
+#[test]
+fn test_no_landing_page() {
+    MkWebUsb { landing_page: None }
+        .with_usb(|mut cls, mut dev| {
+            let res = get_url(&mut cls, &mut dev, 1);
+            assert!(res.is_err());
+        })
+        .expect("with_usb");
+}
+
+#[test]
+#[should_panic(expected = "WebUSB landing page URL is too long")]
+fn test_url_too_long() {
+    let url = std::str::from_utf8(&[b'a'; MAX_URL_LENGTH + 1]).unwrap();
+    WebUsbClass::new(VENDOR_CODE, Some(url));
+}